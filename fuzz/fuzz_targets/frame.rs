@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use oresat_c3_watchdog::{framing, protocol};
+
+// Framed packets go through the frame decoder and then the parser
+fuzz_target!(|packet: &[u8]| {
+    let Ok(frame) = framing::decode(packet) else {
+        return;
+    };
+    let encoded = framing::encode(frame.kind, frame.payload).expect("Decoded, it fits");
+    assert_eq!(encoded, packet);
+    let _ = protocol::parse(frame.payload);
+});
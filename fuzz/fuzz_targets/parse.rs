@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use oresat_c3_watchdog::protocol;
+
+// Whatever arrives on the socket mustn't panic the parser, and what it accepts has to mean the
+// same once written back out
+fuzz_target!(|packet: &[u8]| {
+    let Ok(packet) = protocol::parse(packet) else {
+        return;
+    };
+    let text = packet.to_string();
+    let reparsed = protocol::parse(text.as_bytes()).expect("Written back out, it doesn't parse");
+    assert_eq!(reparsed.command.keyword(), packet.command.keyword());
+    assert_eq!(reparsed.command.client(), packet.command.client());
+    assert_eq!(reparsed.auth.is_some(), packet.auth.is_some());
+});
@@ -0,0 +1,152 @@
+use crate::{
+    clock::{wall_now, Clock, Timer, WallEvent, WallTimer},
+    config::TimeAnomalyConfig,
+    history::{self, Event},
+    source::{EventSource, Ready},
+    stats,
+};
+use anyhow::{Context, Result};
+use log::warn;
+use mio::{unix::SourceFd, Interest, Registry, Token};
+use nix::time::{clock_gettime, ClockId};
+use std::time::Duration;
+
+// The wall clock timer never expires, it's only there to be cancelled when the time is set
+const FAR: i64 = 100 * 365 * 24 * 3600;
+// Less is just the two clocks being read a moment apart
+const MIN_SUSPEND: Duration = Duration::from_secs(1);
+
+/// Watches for the wall clock being stepped, through a timer that the kernel cancels when it's
+/// set, and for the card having been suspended, through how much CLOCK_BOOTTIME gained on
+/// CLOCK_MONOTONIC every interval. Either is warned about, counted in the status, and noted in
+/// a ping timeout that follows within the client's window, to save a post-mortem the guessing.
+pub struct TimeAnomalies {
+    interval: Duration,
+    timer: Box<dyn Timer>,
+    wall: WallTimer,
+    /// Since boot, as of the last check
+    suspended: Duration,
+}
+
+impl TimeAnomalies {
+    pub fn new(config: &TimeAnomalyConfig, clock: &dyn Clock) -> Result<Self> {
+        let mut wall = WallTimer::new()?;
+        wall.set(wall_now() + FAR)?;
+        let timer = clock.timer()?;
+        timer.set(config.interval)?;
+        Ok(Self {
+            interval: config.interval,
+            timer,
+            wall,
+            suspended: suspended()?,
+        })
+    }
+
+    pub fn on_wall(&mut self) -> Result<()> {
+        let event = self
+            .wall
+            .on_event()
+            .context("Failed to read the wall clock timer")?;
+        if let WallEvent::Stepped(step) = event {
+            note(format!("wall clock stepped by {}s", step));
+        }
+        // Setting the time left it unset
+        self.wall.set(wall_now() + FAR)
+    }
+
+    pub fn on_tick(&mut self) -> Result<()> {
+        self.timer.set(self.interval)?;
+        self.check(suspended()?);
+        Ok(())
+    }
+
+    /// Notes the suspension if `suspended` gained enough on the last check
+    fn check(&mut self, suspended: Duration) {
+        let gained = suspended.saturating_sub(self.suspended);
+        self.suspended = suspended;
+        if gained >= MIN_SUSPEND {
+            note(format!("suspended for {:.1}s", gained.as_secs_f64()));
+        }
+    }
+}
+
+impl EventSource for TimeAnomalies {
+    /// The wall clock timer, then the tick
+    fn tokens(&self) -> usize {
+        2
+    }
+
+    fn register(&mut self, registry: &Registry, token: Token) -> Result<()> {
+        let fd = self.wall.fd();
+        registry.register(&mut SourceFd(&fd), token, Interest::READABLE)?;
+        self.timer.register(registry, Token(token.0 + 1))
+    }
+
+    fn handle_ready(&mut self, index: usize, _ready: &mut Ready) -> Result<()> {
+        match index {
+            0 => self.on_wall(),
+            _ => self.on_tick(),
+        }
+    }
+}
+
+/// How long the card spent suspended since boot
+fn suspended() -> Result<Duration> {
+    let monotonic: Duration = clock_gettime(ClockId::CLOCK_MONOTONIC)?.into();
+    let boottime: Duration = clock_gettime(ClockId::CLOCK_BOOTTIME)?.into();
+    Ok(boottime.saturating_sub(monotonic))
+}
+
+fn note(what: String) {
+    warn!("Time anomaly: {}", what);
+    stats::anomaly(what.clone());
+    history::record(Event::Anomaly { what });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::Monotonic, dictionary::Counter};
+
+    #[test]
+    fn test_time_anomalies() -> Result<()> {
+        let config = TimeAnomalyConfig {
+            interval: Duration::from_secs(5),
+        };
+        let mut anomalies = TimeAnomalies::new(&config, &Monotonic)?;
+        // Nothing happened, so neither says anything
+        anomalies.on_wall()?;
+        anomalies.on_tick()?;
+        assert!(anomalies.wall.is_set()?);
+        assert!(anomalies.timer.remaining()? > Duration::ZERO);
+        Ok(())
+    }
+
+    #[test]
+    fn test_suspended() -> Result<()> {
+        let config = TimeAnomalyConfig {
+            interval: Duration::from_secs(5),
+        };
+        let mut anomalies = TimeAnomalies::new(&config, &Monotonic)?;
+        let before = stats::counter(Counter::TimeAnomalies);
+        // The clocks read a moment apart aren't a suspension
+        let baseline = anomalies.suspended;
+        anomalies.check(baseline + Duration::from_millis(200));
+        assert_eq!(stats::counter(Counter::TimeAnomalies), before);
+
+        anomalies.check(baseline + Duration::from_secs(5));
+        assert_eq!(anomalies.suspended, baseline + Duration::from_secs(5));
+        assert!(stats::counter(Counter::TimeAnomalies) > before);
+        let noted = history::dump();
+        assert!(noted
+            .iter()
+            .any(|l| l.ends_with(" time anomaly: suspended for 4.8s")));
+        // A timeout right after is put down to it
+        let reason = stats::annotate(String::from("c3 client stopped pinging"), config.interval);
+        assert_eq!(
+            reason,
+            "c3 client stopped pinging (a time anomaly happened in this window: suspended for 4.8s)"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+use crate::protocol::{Auth, Command};
+use log::warn;
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Every control command received and what came of it, appended to its own file apart from the
+/// operational log, so that who told the watchdog what can be reconstructed afterwards
+pub struct AuditLog {
+    file: Option<File>,
+}
+
+impl AuditLog {
+    /// Best effort, a log that can't be opened or written is warned about and otherwise skipped
+    pub fn open(path: Option<&Path>) -> Self {
+        let file = path.and_then(|path| {
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(path)
+                .inspect_err(|e| warn!("Failed to open audit log {:?}: {}", path, e))
+                .ok()
+        });
+        Self { file }
+    }
+
+    /// Records `command` if it's a control command, rejected or not
+    pub fn record(
+        &mut self,
+        source: impl Display,
+        command: &Command,
+        auth: Option<&Auth>,
+        outcome: &str,
+    ) {
+        if is_control(command) {
+            self.write(source, command, auth, outcome);
+        }
+    }
+
+    /// Records a command that wasn't permitted, control command or not
+    pub fn denied(
+        &mut self,
+        source: impl Display,
+        command: &Command,
+        auth: Option<&Auth>,
+        outcome: &str,
+    ) {
+        self.write(source, command, auth, outcome);
+    }
+
+    fn write(
+        &mut self,
+        source: impl Display,
+        command: &Command,
+        auth: Option<&Auth>,
+        outcome: &str,
+    ) {
+        let Some(file) = &mut self.file else {
+            return;
+        };
+        let (command, client) = describe(command);
+        let line = line(
+            timestamp(),
+            &source.to_string(),
+            &command,
+            client,
+            auth,
+            outcome,
+        );
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            warn!("Failed to write audit log: {}", e);
+        }
+    }
+}
+
+fn is_control(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::Arm
+            | Command::Disarm
+            | Command::Reset
+            | Command::Reexec
+            | Command::Inhibit { .. }
+            | Command::Uninhibit { .. }
+            | Command::Extend { .. }
+            | Command::Inject { .. }
+            | Command::Power { .. }
+            | Command::Profile { .. }
+            | Command::Criticality { .. }
+            | Command::Maintenance { .. }
+    )
+}
+
+/// The command with its duration, if any, and the client it's about
+fn describe<'a>(command: &Command<'a>) -> (String, Option<&'a str>) {
+    let description = match command {
+        Command::Extend { duration, .. } => {
+            format!("{} {}", command.keyword(), duration.as_secs_f64())
+        }
+        Command::Inhibit { .. }
+        | Command::Uninhibit { .. }
+        | Command::Inject { .. }
+        | Command::Power { .. }
+        | Command::Profile { .. }
+        | Command::Criticality { .. }
+        | Command::Maintenance { .. } => command.to_string(),
+        _ => command.keyword().to_string(),
+    };
+    (description, command.client())
+}
+
+/// Unix seconds
+fn timestamp() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// A line of key=value fields, with the outcome last as it can contain spaces
+fn line(
+    timestamp: f64,
+    source: &str,
+    command: &str,
+    client: Option<&str>,
+    auth: Option<&Auth>,
+    outcome: &str,
+) -> String {
+    let nonce = auth.map_or(String::from("-"), |auth| auth.nonce.to_string());
+    format!(
+        "{:.3} source={} command={:?} client={} nonce={} outcome={}\n",
+        timestamp,
+        source,
+        command,
+        client.unwrap_or("-"),
+        nonce,
+        outcome.replace('\n', " ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol;
+    use anyhow::Result;
+    use std::fs;
+
+    #[test]
+    fn test_audit_log() -> Result<()> {
+        let packet = protocol::parse(b"EXTEND c3-main 90 AUTH 7 c0ffee")?;
+        assert_eq!(
+            line(
+                1.5,
+                "127.0.0.1:4000",
+                "EXTEND 90",
+                Some("c3-main"),
+                packet.auth.as_ref(),
+                "ERR Bad MAC"
+            ),
+            "1.500 source=127.0.0.1:4000 command=\"EXTEND 90\" client=c3-main nonce=7 \
+             outcome=ERR Bad MAC\n"
+        );
+
+        let path = std::env::temp_dir().join(format!("watchdog-audit-{}", std::process::id()));
+        let mut audit = AuditLog::open(Some(&path));
+        audit.record("sdo", &Command::Disarm, None, "OK");
+        audit.record("127.0.0.1:4000", &Command::Status, None, "state=armed\n");
+        let inhibit = Command::Inhibit {
+            duration: std::time::Duration::from_secs(300),
+            name: None,
+        };
+        audit.record("127.0.0.1:4000", &inhibit, None, "ERR Inhibit is disabled");
+        let ping = Command::Ping {
+            client: "edl",
+            ack: None,
+            every: None,
+        };
+        audit.denied("127.0.0.1:4000", &ping, None, "ERR edl may not PING");
+        let written = fs::read_to_string(&path)?;
+        let lines: Vec<_> = written.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with(" source=sdo command=\"DISARM\" client=- nonce=- outcome=OK"));
+        assert!(lines[1].contains(" command=\"INHIBIT 300\" "));
+        assert!(lines[2].contains(" command=\"PING\" client=edl "));
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+use crate::{
+    config::AuthConfig,
+    protocol::{Auth, Command},
+};
+use anyhow::{bail, ensure, Context, Result};
+use hmac::{Hmac, Mac};
+use log::info;
+use sha2::Sha256;
+use std::{collections::BTreeMap, fs, path::Path};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The key file's key id in the permissions
+pub const DEFAULT_KEY: &str = "default";
+
+/// Checks the HMAC-SHA256 of authenticated commands against a shared key, the key file's or one
+/// picked by id from the key directory. Several keys being valid at once lets them be rotated
+/// without a window where none works, and `reload` picks up new ones without a restart.
+///
+/// Replays are rejected by requiring each nonce to be greater than the last accepted one, across
+/// all keys. This is only kept in memory, clients should use a timestamp so that old packets stay
+/// stale across restarts.
+pub struct Authenticator {
+    config: AuthConfig,
+    keys: Keys,
+    last_nonce: u64,
+}
+
+#[derive(Default)]
+struct Keys {
+    /// The key file's, used without a key id
+    default: Option<Vec<u8>>,
+    by_id: BTreeMap<String, Vec<u8>>,
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig) -> Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            keys: Keys::read(config)?,
+            last_nonce: 0,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn with_key(key: &[u8]) -> Self {
+        Self {
+            config: AuthConfig::default(),
+            keys: Keys {
+                default: Some(key.to_vec()),
+                by_id: BTreeMap::new(),
+            },
+            last_nonce: 0,
+        }
+    }
+
+    /// Rereads the key file and directory. If anything is wrong with them the keys so far stay.
+    pub fn reload(&mut self) -> Result<()> {
+        self.keys = Keys::read(&self.config)?;
+        let count = self.keys.by_id.len() + usize::from(self.keys.default.is_some());
+        info!("Reloaded {} keys", count);
+        Ok(())
+    }
+
+    /// Whether there's any key to verify against
+    pub fn keyed(&self) -> bool {
+        self.keys.default.is_some() || !self.keys.by_id.is_empty()
+    }
+
+    pub fn verify(&mut self, auth: Option<&Auth>) -> Result<()> {
+        if !self.keyed() {
+            bail!("No key configured")
+        }
+        let auth = auth.context("Not authenticated")?;
+        let key = match auth.key_id {
+            Some(id) => self.keys.by_id.get(id),
+            None => self.keys.default.as_ref(),
+        };
+        let key = key.with_context(|| format!("Unknown key {}", auth.key_id.unwrap_or("file")))?;
+        let mac = decode_hex(auth.mac).context("Invalid MAC")?;
+        let mut hmac = HmacSha256::new_from_slice(key)?;
+        hmac.update(auth.signed.as_bytes());
+        hmac.verify_slice(&mac).ok().context("Wrong MAC")?;
+        ensure!(auth.nonce > self.last_nonce, "Replayed nonce");
+        self.last_nonce = auth.nonce;
+        Ok(())
+    }
+
+    /// Whether the command's client, and the key it claims to be signed with, may send it.
+    /// A claimed key that then fails verification only ever loses permissions.
+    pub fn authorize(&self, command: &Command, auth: Option<&Auth>) -> Result<()> {
+        let key = auth.map(|auth| auth.key_id.unwrap_or(DEFAULT_KEY));
+        for name in command.client().into_iter().chain(key) {
+            if let Some(permitted) = self.config.permissions.get(name) {
+                ensure!(
+                    permitted.iter().any(|p| p == command.keyword()),
+                    "{} may not {}",
+                    name,
+                    command.keyword()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Keys {
+    fn read(config: &AuthConfig) -> Result<Self> {
+        let mut keys = Keys::default();
+        if let Some(path) = &config.key_file {
+            keys.default = Some(read_key(path)?);
+        }
+        if let Some(dir) = &config.key_dir {
+            let entries =
+                fs::read_dir(dir).with_context(|| format!("Failed to list keys in {:?}", dir))?;
+            for entry in entries {
+                let entry = entry?;
+                let id = entry.file_name().to_string_lossy().into_owned();
+                // Editor and in-progress copies
+                if id.starts_with('.') || !entry.file_type()?.is_file() {
+                    continue;
+                }
+                ensure!(!id.contains(char::is_whitespace), "Invalid key id {:?}", id);
+                keys.by_id.insert(id, read_key(&entry.path())?);
+            }
+        }
+        Ok(keys)
+    }
+}
+
+fn read_key(path: &Path) -> Result<Vec<u8>> {
+    let key = fs::read(path).with_context(|| format!("Failed to read key file {:?}", path))?;
+    // Tolerate the trailing newline of a key written with echo
+    let key = key.trim_ascii_end().to_vec();
+    ensure!(key.len() >= 16, "Key in {:?} is too short", path);
+    Ok(key)
+}
+
+/// Appends the AUTH trailer to a command, for the key file's key
+pub fn sign(key: &[u8], command: &str, nonce: u64) -> String {
+    mac(key, format!("{} AUTH {}", command, nonce))
+}
+
+/// Like sign, for the key with `key_id`
+pub fn sign_with_id(key: &[u8], key_id: &str, command: &str, nonce: u64) -> String {
+    mac(key, format!("{} AUTH {} {}", command, key_id, nonce))
+}
+
+fn mac(key: &[u8], signed: String) -> String {
+    let mut hmac = HmacSha256::new_from_slice(key).expect("HMAC takes any key length");
+    hmac.update(signed.as_bytes());
+    let mac: String = hmac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("{} {}", signed, mac)
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol;
+
+    #[test]
+    fn test_verify() {
+        let key = b"0123456789abcdef";
+        let mut auth = Authenticator::with_key(key);
+        let verify = |auth: &mut Authenticator, text: &str| {
+            auth.verify(protocol::parse(text.as_bytes()).unwrap().auth.as_ref())
+        };
+
+        assert!(verify(&mut auth, &sign(key, "ARM", 5)).is_ok());
+        // Replay and an older nonce
+        assert!(verify(&mut auth, &sign(key, "ARM", 5)).is_err());
+        assert!(verify(&mut auth, &sign(key, "ARM", 4)).is_err());
+        assert!(verify(&mut auth, &sign(key, "DISARM", 6)).is_ok());
+
+        assert!(verify(&mut auth, &sign(b"fedcba9876543210", "ARM", 7)).is_err());
+        let tampered = sign(key, "ARM", 8).replacen("ARM", "DISARM", 1);
+        assert!(verify(&mut auth, &tampered).is_err());
+        assert!(verify(&mut auth, "ARM").is_err());
+
+        let mut keyless = Authenticator::new(&AuthConfig::default()).unwrap();
+        assert!(verify(&mut keyless, &sign(key, "ARM", 9)).is_err());
+    }
+
+    #[test]
+    fn test_rotation() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("watchdog-keys-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let old = b"0123456789abcdef";
+        let new = b"fedcba9876543210";
+        fs::write(dir.join("old"), old)?;
+        fs::write(dir.join(".new.swp"), "not a key")?;
+        let config = AuthConfig {
+            key_dir: Some(dir.clone()),
+            ..AuthConfig::default()
+        };
+        let mut auth = Authenticator::new(&config)?;
+        let verify = |auth: &mut Authenticator, text: &str| {
+            auth.verify(protocol::parse(text.as_bytes()).unwrap().auth.as_ref())
+        };
+
+        assert!(verify(&mut auth, &sign_with_id(old, "old", "ARM", 1)).is_ok());
+        assert!(verify(&mut auth, &sign_with_id(new, "new", "ARM", 2)).is_err());
+        // Without an id there is no key file to use
+        assert!(verify(&mut auth, &sign(old, "ARM", 3)).is_err());
+
+        // Both are valid while the clients switch over
+        fs::write(dir.join("new"), [new.as_slice(), b"\n"].concat())?;
+        auth.reload()?;
+        assert!(verify(&mut auth, &sign_with_id(new, "new", "ARM", 4)).is_ok());
+        assert!(verify(&mut auth, &sign_with_id(old, "old", "ARM", 5)).is_ok());
+        // The wrong key for the id
+        assert!(verify(&mut auth, &sign_with_id(old, "new", "ARM", 6)).is_err());
+
+        fs::remove_file(dir.join("old"))?;
+        auth.reload()?;
+        assert!(verify(&mut auth, &sign_with_id(old, "old", "ARM", 7)).is_err());
+
+        // A broken key keeps the ones so far
+        fs::write(dir.join("short"), "short")?;
+        assert!(auth.reload().is_err());
+        assert!(verify(&mut auth, &sign_with_id(new, "new", "ARM", 8)).is_ok());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_authorize() -> Result<()> {
+        let mut config = AuthConfig::default();
+        let permit = |commands: &[&str]| commands.iter().map(|c| c.to_string()).collect();
+        config
+            .permissions
+            .insert("ops".into(), permit(&["INHIBIT", "ARM"]));
+        config.permissions.insert("edl".into(), permit(&["PING"]));
+        config
+            .permissions
+            .insert(DEFAULT_KEY.into(), permit(&["RESET"]));
+        let auth = Authenticator::new(&config)?;
+        let authorize = |text: &str| {
+            let packet = protocol::parse(text.as_bytes()).unwrap();
+            auth.authorize(&packet.command, packet.auth.as_ref())
+        };
+
+        assert!(authorize("INHIBIT 60 AUTH ops 1 00").is_ok());
+        let denied = authorize("RESET AUTH ops 2 00").unwrap_err();
+        assert_eq!(denied.to_string(), "ops may not RESET");
+        assert!(authorize("RESET AUTH 3 00").is_ok());
+        assert!(authorize("ARM AUTH 4 00").is_err());
+        // Unlisted keys and clients, and unauthenticated commands without a client
+        assert!(authorize("RESET AUTH ground 5 00").is_ok());
+        assert!(authorize("EXTEND c3-main 60").is_ok());
+        assert!(authorize("STATUS").is_ok());
+        assert!(authorize("PING edl").is_ok());
+        assert!(authorize("EXTEND edl 60").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+use crate::{
+    clock::{Clock, Timer},
+    config::BatteryConfig,
+    exit::Cause,
+};
+use anyhow::{Context, Result};
+use log::{info, warn};
+use mio::{Registry, Token};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Holds deliberate resets back while the battery is low, as resetting then can brown out the
+/// bus. Ping timeouts never come through here.
+pub struct Battery {
+    path: Option<PathBuf>,
+    scale: f64,
+    min_voltage: f64,
+    interval: Duration,
+    timer: Box<dyn Timer>,
+    /// A reset waiting for the voltage to recover
+    deferred: Option<(String, Cause)>,
+}
+
+impl Battery {
+    pub fn new(config: &BatteryConfig, clock: &dyn Clock) -> Result<Self> {
+        Ok(Self {
+            path: config.path.clone(),
+            scale: config.scale,
+            min_voltage: config.min_voltage,
+            interval: config.interval,
+            timer: clock.timer()?,
+            deferred: None,
+        })
+    }
+
+    pub fn register(&self, registry: &Registry, token: Token) -> Result<()> {
+        self.timer.register(registry, token)
+    }
+
+    /// A deliberate reset, returned if the battery allows it now. Otherwise it's returned by
+    /// `on_timer` once the voltage is back.
+    pub fn request(&mut self, reason: String, cause: Cause) -> Result<Option<(String, Cause)>> {
+        if self.low() {
+            info!("{}, deferred until the battery recovers", reason);
+            self.deferred = Some((reason, cause));
+            self.timer.set(self.interval)?;
+            return Ok(None);
+        }
+        Ok(Some((reason, cause)))
+    }
+
+    /// The reset due, if the voltage recovered
+    pub fn on_timer(&mut self) -> Result<Option<(String, Cause)>> {
+        if self.deferred.is_none() {
+            return Ok(None);
+        }
+        if self.low() {
+            self.timer.set(self.interval)?;
+            return Ok(None);
+        }
+        Ok(self.deferred.take())
+    }
+
+    /// Not being able to read the voltage doesn't hold anything back
+    fn low(&self) -> bool {
+        let Some(path) = &self.path else {
+            return false;
+        };
+        match voltage(path, self.scale) {
+            Ok(voltage) if voltage < self.min_voltage => {
+                warn!(
+                    "Battery at {:.2} V, below {:.2} V",
+                    voltage, self.min_voltage
+                );
+                true
+            }
+            Ok(_) => false,
+            Err(e) => {
+                warn!("{:#}", e);
+                false
+            }
+        }
+    }
+}
+
+/// In volts, from a sysfs attribute counting `scale` volts, like hwmon's millivolts
+fn voltage(path: &Path, scale: f64) -> Result<f64> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read the battery voltage from {:?}", path))?;
+    let raw: f64 = text
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid battery voltage {:?} in {:?}", text.trim(), path))?;
+    Ok(raw * scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::Monotonic, config::Config};
+
+    #[test]
+    fn test_battery() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("battery-test-{}", std::process::id()));
+        let config = Config::parse(&format!(
+            "[battery]\npath = {:?}\nmin_voltage = 7.0\ninterval = 0.01",
+            path
+        ))?;
+        let mut battery = Battery::new(&config.battery, &Monotonic)?;
+        let reset = (String::from("Up for too long"), Cause::Uptime);
+
+        fs::write(&path, "6500\n")?;
+        assert_eq!(battery.request(reset.0.clone(), reset.1)?, None);
+        assert_eq!(battery.on_timer()?, None);
+        fs::write(&path, "7400\n")?;
+        assert_eq!(battery.on_timer()?, Some(reset.clone()));
+        assert_eq!(battery.on_timer()?, None);
+
+        // Unreadable doesn't defer
+        fs::remove_file(&path)?;
+        assert_eq!(battery.request(reset.0.clone(), reset.1)?, Some(reset));
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{clock::Clock, config::BudgetConfig};
+use anyhow::{ensure, Result};
+use std::{sync::Arc, time::Duration, time::Instant};
+
+/// A token bucket of delay, so that EXTEND and INHIBIT can't be chained into putting the watchdog
+/// off indefinitely. Starts full and refills at `max` per `period`.
+pub struct Budget {
+    max: Duration,
+    period: Duration,
+    left: Duration,
+    /// When `left` was last updated
+    at: Instant,
+    clock: Arc<dyn Clock>,
+}
+
+impl Budget {
+    pub fn new(config: &BudgetConfig, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            max: config.max,
+            period: config.period,
+            left: config.max,
+            at: clock.now(),
+            clock,
+        }
+    }
+
+    /// A zero maximum doesn't limit anything
+    pub fn enabled(&self) -> bool {
+        !self.max.is_zero()
+    }
+
+    pub fn left(&self) -> Duration {
+        if !self.enabled() {
+            return Duration::MAX;
+        }
+        let elapsed = self.clock.now().saturating_duration_since(self.at);
+        let refilled = elapsed.mul_f64(self.max.as_secs_f64() / self.period.as_secs_f64());
+        self.left.saturating_add(refilled).min(self.max)
+    }
+
+    /// Spends `delay`, unless there isn't enough left
+    pub fn take(&mut self, delay: Duration) -> Result<()> {
+        if !self.enabled() {
+            return Ok(());
+        }
+        let left = self.left();
+        ensure!(
+            delay <= left,
+            "Delay budget exceeded, {:.1} s left",
+            left.as_secs_f64()
+        );
+        self.left = left - delay;
+        self.at = self.clock.now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::VirtualClock, config::Config};
+
+    #[test]
+    fn test_budget() -> Result<()> {
+        let config = Config::parse("[budget]\nmax = 600\nperiod = 3600")?;
+        let clock = VirtualClock::new();
+        let mut budget = Budget::new(&config.budget, Arc::new(clock.clone()));
+        let minutes = |minutes: u64| Duration::from_secs(minutes * 60);
+
+        budget.take(minutes(8))?;
+        assert!(budget.take(minutes(3)).is_err());
+        budget.take(minutes(2))?;
+        assert_eq!(budget.left(), Duration::ZERO);
+        // Refills at 10 minutes an hour, up to the maximum
+        clock.advance(minutes(30));
+        assert_eq!(budget.left(), minutes(5));
+        assert!(budget.take(minutes(6)).is_err());
+        clock.advance(minutes(120));
+        assert_eq!(budget.left(), minutes(10));
+
+        let mut unlimited = Budget::new(&Config::default().budget, Arc::new(clock));
+        unlimited.take(minutes(1000))?;
+        assert!(!unlimited.enabled());
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use crate::{history::Event, sinks::Queue};
+use anyhow::{Context, Result};
+use log::warn;
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+};
+use zbus::blocking::Connection;
+
+const PATH: &str = "/org/oresat/C3Watchdog";
+const INTERFACE: &str = "org.oresat.C3Watchdog";
+
+static SIGNALS: Mutex<Option<Arc<Queue<Signal>>>> = Mutex::new(None);
+
+#[derive(Debug, PartialEq)]
+enum Signal {
+    /// From, to, and the cause
+    StateChanged(String, String, String),
+    /// The client, its strikes, and the maximum
+    StrikeRecorded(String, u32, u32),
+    /// Seconds until the petting stops, and why
+    DeathImminent(f64, String),
+}
+
+/// Emits a signal on the system bus for every state transition, strike, and death row warning
+/// from now on, for flatsat tooling that wants to react right away rather than poll. Like the
+/// unit watchers the bus is only talked to from a thread of its own.
+pub fn start() -> Result<()> {
+    let conn = Connection::system().context("Failed to connect to the system D-Bus")?;
+    let queue = Queue::new("dbus");
+    let signals = queue.clone();
+    thread::Builder::new()
+        .name(String::from("dbus-signals"))
+        .spawn(move || loop {
+            let signal = signals.pop();
+            if let Err(e) = emit(&conn, &signal) {
+                warn!("Failed to emit {:?}: {}", signal, e);
+            }
+        })?;
+    *SIGNALS.lock().unwrap_or_else(|e| e.into_inner()) = Some(queue);
+    Ok(())
+}
+
+/// Asks logind for a clean reboot, which the user the watchdog runs as needs to be allowed by
+/// polkit
+pub fn reboot() -> Result<()> {
+    let conn = Connection::system().context("Failed to connect to the system D-Bus")?;
+    conn.call_method(
+        Some("org.freedesktop.login1"),
+        "/org/freedesktop/login1",
+        Some("org.freedesktop.login1.Manager"),
+        "Reboot",
+        &(false,),
+    )
+    .context("Failed to ask logind for a reboot")?;
+    Ok(())
+}
+
+/// Called for every recorded event, like the telemetry
+pub fn forward(event: &Event) {
+    let signals = SIGNALS.lock().unwrap_or_else(|e| e.into_inner());
+    if let (Some(queue), Some(signal)) = (&*signals, signal(event)) {
+        queue.push(signal);
+    }
+}
+
+fn signal(event: &Event) -> Option<Signal> {
+    let signal = match event {
+        Event::State { from, to, cause } => {
+            Signal::StateChanged(from.name().into(), to.name().into(), cause.clone())
+        }
+        Event::Strike {
+            client,
+            strikes,
+            max,
+        } => Signal::StrikeRecorded(client.clone(), *strikes, *max),
+        Event::DeathWarning { left, reason } => {
+            Signal::DeathImminent(left.as_secs_f64(), reason.clone())
+        }
+        _ => return None,
+    };
+    Some(signal)
+}
+
+fn emit(conn: &Connection, signal: &Signal) -> zbus::Result<()> {
+    let to = None::<&str>;
+    match signal {
+        Signal::StateChanged(from, state, cause) => {
+            conn.emit_signal(to, PATH, INTERFACE, "StateChanged", &(from, state, cause))
+        }
+        Signal::StrikeRecorded(client, strikes, max) => conn.emit_signal(
+            to,
+            PATH,
+            INTERFACE,
+            "StrikeRecorded",
+            &(client, strikes, max),
+        ),
+        Signal::DeathImminent(left, reason) => {
+            conn.emit_signal(to, PATH, INTERFACE, "DeathImminent", &(left, reason))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::State;
+    use std::time::Duration;
+
+    #[test]
+    fn test_signal() {
+        let state = Event::State {
+            from: State::Armed,
+            to: State::DeathRow,
+            cause: String::from("c3 client stopped pinging"),
+        };
+        assert_eq!(
+            signal(&state),
+            Some(Signal::StateChanged(
+                "armed".into(),
+                "death_row".into(),
+                "c3 client stopped pinging".into()
+            ))
+        );
+        let warning = Event::DeathWarning {
+            left: Duration::from_millis(1500),
+            reason: String::from("Maximum uptime"),
+        };
+        assert_eq!(
+            signal(&warning),
+            Some(Signal::DeathImminent(1.5, "Maximum uptime".into()))
+        );
+        assert_eq!(signal(&Event::Pet { value: true }), None);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{
+    clock::{Clock, Timer},
+    config::{CadenceConfig, Config},
+    pingee::Pingee,
+    source::{EventSource, Ready},
+    stats,
+};
+use anyhow::Result;
+use log::{info, warn};
+use mio::{Registry, Token};
+use std::time::Duration;
+
+/// Works out a timeout for each configured client from how it actually pings, every interval:
+/// the quantile of its recent intervals with the margin on top. One over the configured timeout
+/// is warned about, since a process that still pings in time, only ever more slowly, shows there
+/// first. With a minimum timeout the timeouts are also tightened to the recommendations.
+pub struct Cadence {
+    config: CadenceConfig,
+    timer: Box<dyn Timer>,
+    /// The configured clients in slot order, with their configured timeouts
+    clients: Vec<(String, Duration)>,
+    /// What each timeout was last tightened to, so that changes by anything else are left alone
+    tightened: Vec<Option<Duration>>,
+}
+
+impl Cadence {
+    pub fn new(config: &Config, cadence: &CadenceConfig, clock: &dyn Clock) -> Result<Self> {
+        let clients: Vec<_> = config
+            .clients
+            .iter()
+            .map(|c| (c.name.clone(), c.timeout))
+            .collect();
+        let timer = clock.timer()?;
+        timer.set(cadence.interval)?;
+        Ok(Self {
+            config: cadence.clone(),
+            timer,
+            tightened: vec![None; clients.len()],
+            clients,
+        })
+    }
+
+    pub fn on_tick(&mut self, pingee: &mut Pingee) -> Result<()> {
+        self.timer.set(self.config.interval)?;
+        for (slot, (name, timeout)) in self.clients.iter().enumerate() {
+            let intervals = stats::recent_intervals(name);
+            let Some(recommended) = recommend(&intervals, &self.config) else {
+                continue;
+            };
+            stats::recommend(name, recommended);
+            if recommended > *timeout {
+                warn!(
+                    "{} pings too slowly for its {:?} timeout, {:?} recommended",
+                    name, timeout, recommended
+                );
+            } else {
+                info!(
+                    "{} timeout {:?} recommended, {:?} configured",
+                    name, recommended, timeout
+                );
+            }
+            if self.config.min_timeout.is_zero() {
+                continue;
+            }
+            let tight = recommended.max(self.config.min_timeout).min(*timeout);
+            let current = pingee.timeout(slot);
+            let ours = current == Some(*timeout) || current == self.tightened[slot];
+            if current.is_some() && ours && current != Some(tight) {
+                pingee.set_timeout(slot, tight)?;
+                self.tightened[slot] = Some(tight);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl EventSource for Cadence {
+    fn register(&mut self, registry: &Registry, token: Token) -> Result<()> {
+        self.timer.register(registry, token)
+    }
+
+    fn handle_ready(&mut self, _index: usize, ready: &mut Ready) -> Result<()> {
+        ready.retry.run(|| self.on_tick(ready.pingee))
+    }
+}
+
+/// The quantile of the intervals with the margin on top, None with too few of them
+fn recommend(intervals: &[Duration], config: &CadenceConfig) -> Option<Duration> {
+    if intervals.len() < config.min_samples {
+        return None;
+    }
+    let mut sorted = intervals.to_vec();
+    sorted.sort_unstable();
+    let rank = (config.quantile * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1].mul_f64(config.margin))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Monotonic;
+
+    fn config(rest: &str) -> Result<Config> {
+        Config::parse(&format!(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "cadence-test"
+            timeout = 30
+            [cadence]
+            quantile = 0.9
+            margin = 2
+            min_samples = 10
+            {}
+            "#,
+            rest
+        ))
+    }
+
+    #[test]
+    fn test_recommend() -> Result<()> {
+        let config = config("")?;
+        let cadence = config.cadence.as_ref().unwrap();
+        let intervals: Vec<_> = (1..=20).map(Duration::from_secs).collect();
+        assert_eq!(
+            recommend(&intervals, cadence),
+            Some(Duration::from_secs(36))
+        );
+        assert_eq!(recommend(&intervals[..9], cadence), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tighten() -> Result<()> {
+        let config = config("min_timeout = 5")?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        let mut cadence = Cadence::new(&config, config.cadence.as_ref().unwrap(), &Monotonic)?;
+        for _ in 0..12 {
+            stats::ping("cadence-test");
+        }
+        // Pinging back to back, so only the minimum holds it up
+        cadence.on_tick(&mut pingee)?;
+        assert_eq!(pingee.timeout(0), Some(Duration::from_secs(5)));
+        assert!(pingee
+            .status()?
+            .get("client.cadence-test.recommended_timeout")
+            .is_some());
+
+        // Someone else's change stays
+        pingee.set_timeout(0, Duration::from_secs(20))?;
+        cadence.on_tick(&mut pingee)?;
+        assert_eq!(pingee.timeout(0), Some(Duration::from_secs(20)));
+        Ok(())
+    }
+}
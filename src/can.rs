@@ -0,0 +1,167 @@
+use crate::net::interface_index;
+use anyhow::{ensure, Context, Result};
+use std::{
+    io::{Error, ErrorKind},
+    mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+};
+
+/// A classic CAN frame, ids over 11 bits are sent as extended frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub id: u32,
+    len: u8,
+    data: [u8; 8],
+}
+
+impl Frame {
+    pub fn new(id: u32, payload: &[u8]) -> Result<Self> {
+        ensure!(id <= libc::CAN_EFF_MASK, "Invalid CAN id {:#x}", id);
+        ensure!(payload.len() <= 8, "CAN payload too long");
+        let mut data = [0; 8];
+        data[..payload.len()].copy_from_slice(payload);
+        Ok(Self {
+            id,
+            len: payload.len() as u8,
+            data,
+        })
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data[..self.len as usize]
+    }
+}
+
+/// A nonblocking raw SocketCAN socket
+pub struct CanSocket {
+    fd: OwnedFd,
+}
+
+impl CanSocket {
+    pub fn open(interface: &str) -> Result<Self> {
+        let index = interface_index(interface)?;
+        // SAFETY: no memory arguments, returns a new fd or -1
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_CAN,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::CAN_RAW,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::last_os_error()).context("Failed to open CAN socket");
+        }
+        // SAFETY: fd was just returned by the kernel and isn't owned by anything else
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // SAFETY: sockaddr_can is plain old data, all zeroes is valid
+        let mut addr: libc::sockaddr_can = unsafe { mem::zeroed() };
+        addr.can_family = libc::AF_CAN as libc::sa_family_t;
+        addr.can_ifindex = index as i32;
+        // SAFETY: addr outlives the call and the length matches its type
+        let ret = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const _ as *const libc::sockaddr,
+                mem::size_of_val(&addr) as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::last_os_error())
+                .with_context(|| format!("Failed to bind CAN interface {}", interface));
+        }
+        Ok(Self { fd })
+    }
+
+    /// Only frames with exactly one of `ids` are received from then on
+    pub fn filter(&self, ids: &[u32]) -> Result<()> {
+        let filters: Vec<_> = ids
+            .iter()
+            .map(|&id| libc::can_filter {
+                can_id: match id {
+                    id if id > libc::CAN_SFF_MASK => id | libc::CAN_EFF_FLAG,
+                    id => id,
+                },
+                can_mask: libc::CAN_EFF_MASK | libc::CAN_EFF_FLAG | libc::CAN_RTR_FLAG,
+            })
+            .collect();
+        // SAFETY: filters outlives the call and the length matches its contents
+        let ret = unsafe {
+            libc::setsockopt(
+                self.fd.as_raw_fd(),
+                libc::SOL_CAN_RAW,
+                libc::CAN_RAW_FILTER,
+                filters.as_ptr() as *const libc::c_void,
+                mem::size_of_val(filters.as_slice()) as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::last_os_error()).context("Failed to set the CAN filter");
+        }
+        Ok(())
+    }
+
+    /// None once there's nothing left to read
+    pub fn recv(&self) -> Result<Option<Frame>> {
+        Ok(self.recv_with_origin()?.map(|(frame, _)| frame))
+    }
+
+    /// Like recv, plus whether the frame was sent from this host rather than the bus
+    pub fn recv_with_origin(&self) -> Result<Option<(Frame, bool)>> {
+        // SAFETY: can_frame is plain old data, all zeroes is valid
+        let mut raw: libc::can_frame = unsafe { mem::zeroed() };
+        let mut iov = libc::iovec {
+            iov_base: &mut raw as *mut _ as *mut libc::c_void,
+            iov_len: mem::size_of_val(&raw),
+        };
+        // SAFETY: msghdr is plain old data, all zeroes is valid
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        // SAFETY: msg and the buffer it points to outlive the call
+        let ret = unsafe { libc::recvmsg(self.fd.as_raw_fd(), &mut msg, 0) };
+        if ret < 0 {
+            let error = Error::last_os_error();
+            if error.kind() == ErrorKind::WouldBlock {
+                return Ok(None);
+            }
+            return Err(error).context("Failed to receive CAN frame");
+        }
+        let id = match raw.can_id {
+            id if id & libc::CAN_EFF_FLAG != 0 => id & libc::CAN_EFF_MASK,
+            id => id & libc::CAN_SFF_MASK,
+        };
+        let frame = Frame::new(id, &raw.data[..usize::from(raw.can_dlc.min(8))])?;
+        // SocketCAN flags frames looped back from local sockets
+        Ok(Some((frame, msg.msg_flags & libc::MSG_DONTROUTE != 0)))
+    }
+
+    pub fn send(&self, frame: &Frame) -> Result<()> {
+        // SAFETY: can_frame is plain old data, all zeroes is valid
+        let mut raw: libc::can_frame = unsafe { mem::zeroed() };
+        raw.can_id = match frame.id {
+            id if id > libc::CAN_SFF_MASK => id | libc::CAN_EFF_FLAG,
+            id => id,
+        };
+        raw.can_dlc = frame.len;
+        raw.data = frame.data;
+        // SAFETY: raw outlives the call and the length matches its type
+        let ret = unsafe {
+            libc::write(
+                self.fd.as_raw_fd(),
+                &raw as *const _ as *const libc::c_void,
+                mem::size_of_val(&raw),
+            )
+        };
+        if ret < 0 {
+            return Err(Error::last_os_error()).context("Failed to send CAN frame");
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for CanSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
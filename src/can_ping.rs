@@ -0,0 +1,88 @@
+use crate::{
+    can::{CanSocket, Frame},
+    condition::Condition,
+    config::CanPingConfig,
+    pingee::Pingee,
+    stats,
+};
+use anyhow::{Context, Result};
+use mio::{unix::SourceFd, Interest, Registry, Token};
+use std::os::fd::AsRawFd;
+
+/// CAN frames that feed a client like its pings would, each on its own socket filtered to the id
+pub struct CanPings {
+    pings: Vec<(CanSocket, CanPingConfig)>,
+}
+
+impl CanPings {
+    /// Registered with tokens counting up from `first`
+    pub fn new(registry: &Registry, first: Token, configs: &[CanPingConfig]) -> Result<Self> {
+        let mut pings = Vec::new();
+        for (index, config) in configs.iter().enumerate() {
+            let socket = CanSocket::open(&config.interface)
+                .with_context(|| format!("Failed to set up CAN pings for {}", config.client))?;
+            socket.filter(&[config.id])?;
+            let fd = socket.as_raw_fd();
+            let token = Token(first.0 + index);
+            registry.register(&mut SourceFd(&fd), token, Interest::READABLE)?;
+            pings.push((socket, config.clone()));
+        }
+        Ok(Self { pings })
+    }
+}
+
+impl Condition for CanPings {
+    fn name(&self) -> &'static str {
+        "CAN pings"
+    }
+
+    fn tokens(&self) -> usize {
+        self.pings.len()
+    }
+
+    fn on_event(&mut self, index: usize, pingee: &mut Pingee) -> Result<Option<String>> {
+        let (socket, config) = &self.pings[index];
+        let mut pinged = false;
+        while let Some(frame) = socket.recv()? {
+            pinged |= matches(config, &frame);
+        }
+        if pinged {
+            pingee.feed(Some(&config.client))?;
+            stats::ping(&config.client);
+        }
+        Ok(None)
+    }
+}
+
+fn matches(config: &CanPingConfig, frame: &Frame) -> bool {
+    frame.id == config.id && frame.payload().starts_with(&config.payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_matches() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            [[client]]
+            name = "battery"
+            [[can_ping]]
+            client = "battery"
+            interface = "can0"
+            id = 0x1a5
+            payload = [0xbe, 0xef]
+            "#,
+        )?;
+        let ping = &config.can_pings[0];
+        assert!(matches(ping, &Frame::new(0x1a5, &[0xbe, 0xef, 0x01])?));
+        assert!(!matches(ping, &Frame::new(0x1a5, &[0xbe])?));
+        assert!(!matches(ping, &Frame::new(0x1a6, &[0xbe, 0xef])?));
+
+        let unknown = Config::parse("[[can_ping]]\nclient = \"c3\"\ninterface = \"can0\"\nid = 1");
+        assert!(unknown.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+use crate::{config::CaptureConfig, rotate};
+use anyhow::{ensure, Context, Result};
+use log::warn;
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// What every capture file starts with
+pub const MAGIC: &[u8; 8] = b"WDCAP\0\0\x01";
+
+// Microseconds, source address and port, verdict, length and captured length
+const HEADER: usize = 8 + 16 + 2 + 1 + 2 + 2;
+
+/// What came of a datagram
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Fed a client
+    Fed = 0,
+    /// A command other than a ping, replied to
+    Command = 1,
+    /// A ping from a client that isn't configured or registered
+    Unknown = 2,
+    /// Not permitted, or failed authentication
+    Denied = 3,
+    /// Neither a frame nor a command
+    Malformed = 4,
+    /// From a source that isn't allowed
+    Blocked = 5,
+    /// Dropped for any other reason, e.g. while draining
+    Ignored = 6,
+}
+
+impl Verdict {
+    const ALL: [Verdict; 7] = [
+        Verdict::Fed,
+        Verdict::Command,
+        Verdict::Unknown,
+        Verdict::Denied,
+        Verdict::Malformed,
+        Verdict::Blocked,
+        Verdict::Ignored,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Verdict::Fed => "fed",
+            Verdict::Command => "command",
+            Verdict::Unknown => "unknown",
+            Verdict::Denied => "denied",
+            Verdict::Malformed => "malformed",
+            Verdict::Blocked => "blocked",
+            Verdict::Ignored => "ignored",
+        }
+    }
+}
+
+/// A captured datagram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Unix microseconds
+    pub at: u64,
+    pub source: SocketAddr,
+    pub verdict: Verdict,
+    /// As received, of which only the first snaplen bytes are kept
+    pub len: usize,
+    pub data: Vec<u8>,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:06} {} {} {} \"{}\"",
+            self.at / 1_000_000,
+            self.at % 1_000_000,
+            self.source,
+            self.verdict.name(),
+            self.len,
+            self.data.escape_ascii()
+        )
+    }
+}
+
+/// Every datagram on the ping socket and its verdict, appended to a binary file that's rotated
+/// at a size, so that what arrived before a timeout can be looked at afterwards. Like the audit
+/// log it's best effort: a file that can't be opened or written is warned about and the capture
+/// stops.
+pub struct Capture {
+    config: Option<CaptureConfig>,
+    file: Option<File>,
+    size: u64,
+}
+
+impl Capture {
+    pub fn open(config: Option<&CaptureConfig>) -> Self {
+        let mut capture = Self {
+            config: config.cloned(),
+            file: None,
+            size: 0,
+        };
+        if let Some(config) = config {
+            if let Err(e) = capture.reopen() {
+                warn!("Failed to open capture {:?}: {:#}", config.path, e);
+            }
+        }
+        capture
+    }
+
+    pub fn record(&mut self, source: SocketAddr, packet: &[u8], verdict: Verdict) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        if self.file.is_none() {
+            return;
+        }
+        let data = &packet[..packet.len().min(config.snaplen)];
+        let record = encode(timestamp(), source, verdict, packet.len(), data);
+        let written = match self.size + record.len() as u64 > config.max_size {
+            true => self.rotate(),
+            false => Ok(()),
+        }
+        .and_then(|()| self.write(&record));
+        if let Err(e) = written {
+            warn!("Failed to write capture, stopping it: {:#}", e);
+            self.file = None;
+        }
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        let Some(config) = &self.config else {
+            return Ok(());
+        };
+        self.file = None;
+        rotate::shift(&config.path, config.files)?;
+        self.reopen()
+    }
+
+    /// Appends to the file, so that a restart doesn't lose the capture before it
+    fn reopen(&mut self) -> Result<()> {
+        let Some(config) = &self.config else {
+            return Ok(());
+        };
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&config.path)?;
+        self.size = file.metadata()?.len();
+        self.file = Some(file);
+        if self.size == 0 {
+            self.write(MAGIC)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        if let Some(file) = &mut self.file {
+            file.write_all(bytes)?;
+            self.size += bytes.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+/// Little endian, with IPv4 sources mapped into IPv6
+fn encode(at: u64, source: SocketAddr, verdict: Verdict, len: usize, data: &[u8]) -> Vec<u8> {
+    let ip = match source.ip() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    let mut record = Vec::with_capacity(HEADER + data.len());
+    record.extend_from_slice(&at.to_le_bytes());
+    record.extend_from_slice(&ip.octets());
+    record.extend_from_slice(&source.port().to_le_bytes());
+    record.push(verdict as u8);
+    record.extend_from_slice(&(len.min(u16::MAX.into()) as u16).to_le_bytes());
+    record.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    record.extend_from_slice(data);
+    record
+}
+
+/// The records in a capture file, oldest first
+pub fn read(path: &Path) -> Result<Vec<Record>> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+    decode(&bytes).with_context(|| format!("Bad capture {:?}", path))
+}
+
+fn decode(bytes: &[u8]) -> Result<Vec<Record>> {
+    let mut rest = bytes
+        .strip_prefix(MAGIC.as_slice())
+        .context("Not a capture file")?;
+    let mut records = Vec::new();
+    while !rest.is_empty() {
+        ensure!(rest.len() >= HEADER, "Truncated record");
+        let (header, data) = rest.split_at(HEADER);
+        let u16_at = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]);
+        let at = u64::from_le_bytes(header[..8].try_into()?);
+        let ip: [u8; 16] = header[8..24].try_into()?;
+        let ip = Ipv6Addr::from(ip);
+        let ip = ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4);
+        let verdict = *Verdict::ALL
+            .get(usize::from(header[26]))
+            .context("Unknown verdict")?;
+        let captured = usize::from(u16_at(29));
+        ensure!(data.len() >= captured, "Truncated record");
+        records.push(Record {
+            at,
+            source: SocketAddr::new(ip, u16_at(24)),
+            verdict,
+            len: usize::from(u16_at(27)),
+            data: data[..captured].to_vec(),
+        });
+        rest = &data[captured..];
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rotate::rotated;
+
+    #[test]
+    fn test_encode() -> Result<()> {
+        let v4: SocketAddr = "127.0.0.1:4000".parse()?;
+        let v6: SocketAddr = "[fe80::1]:20001".parse()?;
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(encode(1_500_000, v4, Verdict::Fed, 12, b"PING c3"));
+        bytes.extend(encode(2_000_001, v6, Verdict::Malformed, 3, b"\xff\x00\n"));
+        let records = decode(&bytes)?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0].to_string(),
+            "1.500000 127.0.0.1:4000 fed 12 \"PING c3\""
+        );
+        assert_eq!(records[1].source, v6);
+        assert_eq!(records[1].data, b"\xff\x00\n");
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode(b"PING").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotate() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("watchdog-capture-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("pings.cap");
+        let config = CaptureConfig {
+            path: path.clone(),
+            max_size: 200,
+            files: 2,
+            snaplen: 4,
+        };
+        let mut capture = Capture::open(Some(&config));
+        let source = "127.0.0.1:4000".parse()?;
+        // 35 bytes each after the magic, five to a file
+        for _ in 0..16 {
+            capture.record(source, b"PING c3-main", Verdict::Fed);
+        }
+        assert_eq!(read(&path)?.len(), 1);
+        assert_eq!(read(&rotated(&path, 1))?.len(), 5);
+        assert_eq!(read(&rotated(&path, 2))?[0].data, b"PING");
+        assert!(!rotated(&path, 3).exists());
+
+        // Picks up where it left off
+        let mut capture = Capture::open(Some(&config));
+        capture.record(source, b"STATUS", Verdict::Command);
+        assert_eq!(read(&path)?[1].verdict, Verdict::Command);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
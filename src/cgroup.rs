@@ -0,0 +1,144 @@
+use crate::{condition::Condition, config::CgroupConfig, pingee::Pingee};
+use anyhow::{bail, Context, Result};
+use log::info;
+use mio::{unix::SourceFd, Interest, Registry, Token};
+use nix::{
+    errno::Errno,
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify},
+};
+use std::{
+    fs,
+    os::fd::{AsFd, AsRawFd},
+    path::{Path, PathBuf},
+};
+
+struct Cgroup {
+    name: String,
+    /// Its cgroup.events
+    events: PathBuf,
+    populated: bool,
+}
+
+/// Watches cgroups through inotify on their cgroup.events, which the kernel modifies whenever
+/// `populated` changes. Every process of one dying, like the flight software's slice, is a loss of
+/// liveness even while a stray forked child keeps pinging. An empty cgroup is only waited for at
+/// first.
+pub struct CgroupMonitor {
+    inotify: Inotify,
+    cgroups: Vec<Cgroup>,
+}
+
+impl CgroupMonitor {
+    pub fn new(registry: &Registry, token: Token, configs: &[CgroupConfig]) -> Result<Self> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        let mut cgroups = Vec::new();
+        for config in configs {
+            let events = config.path.join("cgroup.events");
+            inotify
+                .add_watch(&events, AddWatchFlags::IN_MODIFY)
+                .with_context(|| format!("Failed to watch cgroup {:?}", config.path))?;
+            let populated = populated(&events)?;
+            if !populated {
+                info!("Cgroup {} is empty, waiting for processes", config.name);
+            }
+            cgroups.push(Cgroup {
+                name: config.name.clone(),
+                events,
+                populated,
+            });
+        }
+        let fd = inotify.as_fd().as_raw_fd();
+        registry.register(&mut SourceFd(&fd), token, Interest::READABLE)?;
+        Ok(Self { inotify, cgroups })
+    }
+}
+
+impl Condition for CgroupMonitor {
+    fn name(&self) -> &'static str {
+        "cgroups"
+    }
+
+    /// Why, if a cgroup ran empty
+    fn on_event(&mut self, _index: usize, _pingee: &mut Pingee) -> Result<Option<String>> {
+        loop {
+            match self.inotify.read_events() {
+                Ok(_) => (),
+                Err(Errno::EAGAIN) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        // Few enough to just reread them all
+        let mut emptied = None;
+        for cgroup in &mut self.cgroups {
+            let populated = populated(&cgroup.events)?;
+            match (cgroup.populated, populated) {
+                (true, false) => {
+                    let reason = || format!("Every process in cgroup {} exited", cgroup.name);
+                    emptied.get_or_insert_with(reason);
+                }
+                (false, true) => info!("Cgroup {} has processes", cgroup.name),
+                _ => (),
+            }
+            cgroup.populated = populated;
+        }
+        Ok(emptied)
+    }
+}
+
+fn populated(events: &Path) -> Result<bool> {
+    let text =
+        fs::read_to_string(events).with_context(|| format!("Failed to read {:?}", events))?;
+    match text
+        .lines()
+        .find_map(|line| line.strip_prefix("populated "))
+    {
+        Some("0") => Ok(false),
+        Some("1") => Ok(true),
+        _ => bail!("No populated field in {:?}", events),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use mio::{Events, Poll};
+    use std::time::Duration;
+
+    #[test]
+    fn test_cgroup_monitor() -> Result<()> {
+        // A regular file stands in for the kernel's
+        let dir = std::env::temp_dir().join(format!("watchdog-cgroup-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let events = dir.join("cgroup.events");
+        fs::write(&events, "populated 0\nfrozen 0\n")?;
+        let config = CgroupConfig {
+            name: String::from("flight"),
+            path: dir.clone(),
+        };
+        let mut poll = Poll::new()?;
+        let mut polled = Events::with_capacity(4);
+        let mut monitor = CgroupMonitor::new(poll.registry(), Token(0), &[config])?;
+        let mut pingee = Pingee::new(
+            &Config::parse("address = \"127.0.0.1:0\"")?,
+            Token(10),
+            Token(90),
+        )?;
+
+        fs::write(&events, "populated 1\nfrozen 0\n")?;
+        poll.poll(&mut polled, Some(Duration::from_secs(1)))?;
+        assert!(!polled.is_empty());
+        assert_eq!(monitor.on_event(0, &mut pingee)?, None);
+
+        fs::write(&events, "populated 0\nfrozen 0\n")?;
+        poll.poll(&mut polled, Some(Duration::from_secs(1)))?;
+        assert!(!polled.is_empty());
+        let reason = monitor.on_event(0, &mut pingee)?;
+        assert_eq!(
+            reason.as_deref(),
+            Some("Every process in cgroup flight exited")
+        );
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,417 @@
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use std::{
+    io::{ErrorKind, Read},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    os::unix::net::UnixStream,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+#[cfg(feature = "async")]
+use tokio::{io::AsyncReadExt, time};
+
+// Replies, unlike commands, can be longer than a packet
+const MAX_REPLY: usize = 65536;
+/// Per attempt, a command is sent up to `ATTEMPTS` times
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+const ATTEMPTS: usize = 3;
+
+pub use crate::net::parse_address;
+use crate::{auth, protocol, status::StatusReport};
+
+/// Talks to a running watchdog, for the subcommands, scripts on the flatsat, and the other OreSat
+/// daemons, so that none of them has to put the packets together itself. Everything it sends is
+/// signed once it has a key. Commands go with an id, and are resent as they were until the reply
+/// with that id arrives, which the watchdog answers without running them twice.
+pub struct WatchdogClient {
+    socket: UdpSocket,
+    signer: Option<Signer>,
+    ids: Ids,
+}
+
+/// The id of the next command, and what to send with it
+struct Ids(AtomicU64);
+
+impl Ids {
+    fn new() -> Self {
+        Self(AtomicU64::new(1))
+    }
+
+    fn next(&self, command: &str, signer: Option<&Signer>) -> (u64, String) {
+        let id = self.0.fetch_add(1, Ordering::Relaxed);
+        let command = format!("ID {} {}", id, command);
+        match signer {
+            Some(signer) => (id, signer.sign(&command)),
+            None => (id, command),
+        }
+    }
+}
+
+/// The reply without its id if it's the one to `id`, None for a late one to an earlier command
+fn matching(id: u64, reply: &str) -> Option<String> {
+    match protocol::reply_id(reply) {
+        (Some(got), rest) if got == id => Some(rest.to_string()),
+        _ => {
+            debug!("Skipped a reply to another command: {:?}", reply);
+            None
+        }
+    }
+}
+
+/// Signs with the Unix time in microseconds as the nonce, so that it stays fresh across restarts,
+/// and bumped past the last one for commands sent within the same microsecond
+struct Signer {
+    key: Vec<u8>,
+    key_id: Option<String>,
+    last_nonce: AtomicU64,
+}
+
+impl Signer {
+    fn new(key: &[u8], key_id: Option<&str>) -> Self {
+        Self {
+            key: key.to_vec(),
+            key_id: key_id.map(String::from),
+            last_nonce: AtomicU64::new(0),
+        }
+    }
+
+    fn sign(&self, command: &str) -> String {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH);
+        let now = now.map_or(0, |now| now.as_micros() as u64);
+        let next = |last: u64| Some(now.max(last + 1));
+        // Never None
+        let last = self
+            .last_nonce
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, next);
+        let nonce = now.max(last.unwrap_or_default() + 1);
+        match &self.key_id {
+            Some(key_id) => auth::sign_with_id(&self.key, key_id, command, nonce),
+            None => auth::sign(&self.key, command, nonce),
+        }
+    }
+}
+
+impl WatchdogClient {
+    /// A watchdog listening on any address is reached over loopback
+    pub fn new(watchdog: SocketAddr) -> Result<Self> {
+        Ok(Self {
+            socket: connect(watchdog)?,
+            signer: None,
+            ids: Ids::new(),
+        })
+    }
+
+    /// Signs everything with `key`, the one with `key_id` in the watchdog's key directory, or its
+    /// key file's without one
+    pub fn with_key(mut self, key: &[u8], key_id: Option<&str>) -> Self {
+        self.signer = Some(Signer::new(key, key_id));
+        self
+    }
+
+    fn send(&self, command: &str) -> Result<()> {
+        let packet = match &self.signer {
+            Some(signer) => signer.sign(command),
+            None => command.to_string(),
+        };
+        self.socket.send(packet.as_bytes())?;
+        Ok(())
+    }
+
+    /// Pings get no reply, unless the client is configured to be acknowledged
+    pub fn ping(&self, client: &str) -> Result<()> {
+        self.send(&format!("PING {}", client))
+    }
+
+    /// Sends any command but PING, returning what follows the OK, and failing with the reason
+    /// of an ERR
+    pub fn command(&self, command: &str) -> Result<String> {
+        reply(&self.request(command)?)
+    }
+
+    /// A one-off longer window for `client`'s next ping
+    pub fn extend(&self, client: &str, duration: Duration) -> Result<()> {
+        self.command(&format!("EXTEND {} {}", client, duration.as_secs_f64()))?;
+        Ok(())
+    }
+
+    pub fn inhibit(&self, duration: Duration, name: Option<&str>) -> Result<()> {
+        let command = format!("INHIBIT {}", duration.as_secs_f64());
+        match name {
+            Some(name) => self.command(&format!("{} {}", command, name))?,
+            None => self.command(&command)?,
+        };
+        Ok(())
+    }
+
+    pub fn status(&self) -> Result<StatusReport> {
+        StatusReport::parse(&self.request("STATUS")?)
+    }
+
+    /// The reply to `command`, without its id
+    fn request(&self, command: &str) -> Result<String> {
+        let (id, packet) = self.ids.next(command, self.signer.as_ref());
+        let mut buf = vec![0; MAX_REPLY];
+        for _ in 0..ATTEMPTS {
+            self.socket.send(packet.as_bytes())?;
+            let until = Instant::now() + REPLY_TIMEOUT;
+            while let Some(left) = until.checked_duration_since(Instant::now()) {
+                self.socket
+                    .set_read_timeout(Some(left.max(Duration::from_millis(1))))?;
+                let len = match self.socket.recv(&mut buf) {
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                        break
+                    }
+                    result => result.context("Failed to receive a reply")?,
+                };
+                if let Some(reply) = matching(id, &String::from_utf8_lossy(&buf[..len])) {
+                    return Ok(reply);
+                }
+            }
+        }
+        bail!("No reply from {}", self.socket.peer_addr()?)
+    }
+
+    /// Pings `count` times, or forever if None, waiting `interval` in between. A watchdog that
+    /// isn't up yet only warns, so this can be started first.
+    pub fn keep_pinging(&self, client: &str, count: Option<u64>, interval: Duration) -> Result<()> {
+        let mut sent = 0;
+        while count.is_none_or(|count| sent < count) {
+            if sent > 0 {
+                thread::sleep(interval);
+            }
+            match self.ping(client) {
+                Ok(()) => info!("Pinged {} as {}", self.socket.peer_addr()?, client),
+                Err(e) if is_refused(&e) => {
+                    warn!("Nothing is listening at {}", self.socket.peer_addr()?)
+                }
+                Err(e) => return Err(e),
+            }
+            sent += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Like `WatchdogClient`, but awaited on tokio, for a host like the EDL server. Made inside the
+/// runtime, whose reactor the socket is registered with.
+#[cfg(feature = "async")]
+pub struct AsyncWatchdogClient {
+    socket: tokio::net::UdpSocket,
+    signer: Option<Signer>,
+    ids: Ids,
+}
+
+#[cfg(feature = "async")]
+impl AsyncWatchdogClient {
+    pub fn new(watchdog: SocketAddr) -> Result<Self> {
+        let socket = connect(watchdog)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket: tokio::net::UdpSocket::from_std(socket)?,
+            signer: None,
+            ids: Ids::new(),
+        })
+    }
+
+    pub fn with_key(mut self, key: &[u8], key_id: Option<&str>) -> Self {
+        self.signer = Some(Signer::new(key, key_id));
+        self
+    }
+
+    async fn send(&self, command: &str) -> Result<()> {
+        let packet = match &self.signer {
+            Some(signer) => signer.sign(command),
+            None => command.to_string(),
+        };
+        self.socket.send(packet.as_bytes()).await?;
+        Ok(())
+    }
+
+    pub async fn ping(&self, client: &str) -> Result<()> {
+        self.send(&format!("PING {}", client)).await
+    }
+
+    pub async fn command(&self, command: &str) -> Result<String> {
+        reply(&self.request(command).await?)
+    }
+
+    pub async fn extend(&self, client: &str, duration: Duration) -> Result<()> {
+        let command = format!("EXTEND {} {}", client, duration.as_secs_f64());
+        self.command(&command).await?;
+        Ok(())
+    }
+
+    pub async fn status(&self) -> Result<StatusReport> {
+        StatusReport::parse(&self.request("STATUS").await?)
+    }
+
+    async fn request(&self, command: &str) -> Result<String> {
+        let (id, packet) = self.ids.next(command, self.signer.as_ref());
+        let mut buf = vec![0; MAX_REPLY];
+        for _ in 0..ATTEMPTS {
+            self.socket.send(packet.as_bytes()).await?;
+            let until = time::Instant::now() + REPLY_TIMEOUT;
+            loop {
+                let Ok(received) = time::timeout_at(until, self.socket.recv(&mut buf)).await else {
+                    break;
+                };
+                let len = received.context("Failed to receive a reply")?;
+                if let Some(reply) = matching(id, &String::from_utf8_lossy(&buf[..len])) {
+                    return Ok(reply);
+                }
+            }
+        }
+        bail!("No reply from {}", self.socket.peer_addr()?)
+    }
+}
+
+/// The status from the watchdog's `status_socket`, which needs no address or key
+pub fn status_socket(path: &Path) -> Result<StatusReport> {
+    let mut stream =
+        UnixStream::connect(path).with_context(|| format!("Failed to connect to {:?}", path))?;
+    stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+    let mut text = String::new();
+    stream.read_to_string(&mut text)?;
+    StatusReport::parse(&text)
+}
+
+/// Like `status_socket`, awaited
+#[cfg(feature = "async")]
+pub async fn status_socket_async(path: &Path) -> Result<StatusReport> {
+    let mut stream = tokio::net::UnixStream::connect(path)
+        .await
+        .with_context(|| format!("Failed to connect to {:?}", path))?;
+    let mut text = String::new();
+    stream.read_to_string(&mut text).await?;
+    StatusReport::parse(&text)
+}
+
+fn connect(mut watchdog: SocketAddr) -> Result<UdpSocket> {
+    let (any, loopback) = match watchdog.ip() {
+        IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED.into(), Ipv4Addr::LOCALHOST.into()),
+        IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED.into(), Ipv6Addr::LOCALHOST.into()),
+    };
+    if watchdog.ip().is_unspecified() {
+        watchdog.set_ip(loopback);
+    }
+    let socket = UdpSocket::bind(SocketAddr::new(any, 0))?;
+    socket
+        .connect(watchdog)
+        .with_context(|| format!("Failed to connect to {}", watchdog))?;
+    Ok(socket)
+}
+
+/// What follows the OK, or the ERR's reason as the error
+fn reply(reply: &str) -> Result<String> {
+    match reply.split_once(' ').unwrap_or((reply, "")) {
+        ("OK", rest) => Ok(rest.to_string()),
+        ("ERR", reason) => bail!("{}", reason),
+        _ => bail!("Unexpected reply {:?}", reply),
+    }
+}
+
+// Connected UDP sockets report the ICMP port unreachable from an earlier packet on the next send
+fn is_refused(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == ErrorKind::ConnectionRefused)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::Authenticator,
+        protocol::{self, Command},
+    };
+
+    #[test]
+    fn test_ping() -> Result<()> {
+        let watchdog = UdpSocket::bind("127.0.0.1:0")?;
+        watchdog.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let mut address = watchdog.local_addr()?;
+        address.set_ip(Ipv4Addr::UNSPECIFIED.into());
+
+        let client = WatchdogClient::new(address)?;
+        client.keep_pinging("edl", Some(2), Duration::from_millis(10))?;
+        let mut buf = [0; 64];
+        for _ in 0..2 {
+            let len = watchdog.recv(&mut buf)?;
+            let packet = protocol::parse(&buf[..len])?;
+            let ping = Command::Ping {
+                client: "edl",
+                ack: None,
+                every: None,
+            };
+            assert_eq!(packet.command, ping);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_signed_commands() -> Result<()> {
+        let key = b"0123456789abcdef";
+        let watchdog = UdpSocket::bind("127.0.0.1:0")?;
+        watchdog.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let client = WatchdogClient::new(watchdog.local_addr()?)?.with_key(key, None);
+        let mut authenticator = Authenticator::with_key(key);
+        let replies = ["OK 300", "ERR Denied"];
+        let answer = thread::spawn(move || -> Result<Vec<String>> {
+            let mut buf = [0; 512];
+            let mut received = Vec::new();
+            for reply in replies {
+                let (len, from) = watchdog.recv_from(&mut buf)?;
+                let id = protocol::parse(&buf[..len])?.id.unwrap();
+                received.push(String::from_utf8_lossy(&buf[..len]).into_owned());
+                // A late reply to something else first
+                watchdog.send_to(format!("ID {} OK 1", id + 100).as_bytes(), from)?;
+                watchdog.send_to(format!("ID {} {}", id, reply).as_bytes(), from)?;
+            }
+            Ok(received)
+        });
+        client.extend("edl", Duration::from_secs(300))?;
+        let denied = client.inhibit(Duration::from_secs(60), Some("update"));
+        assert_eq!(format!("{:#}", denied.unwrap_err()), "Denied");
+        let received = answer.join().unwrap()?;
+
+        // Both verify, the second with a greater nonce
+        for packet in &received {
+            let packet = protocol::parse(packet.as_bytes())?;
+            authenticator.verify(packet.auth.as_ref())?;
+        }
+        assert!(received[0].starts_with("ID 1 EXTEND edl 300 AUTH "));
+        assert!(received[1].starts_with("ID 2 INHIBIT 60 update AUTH "));
+        assert!(reply("PONG").is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_status() -> Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let _runtime = runtime.enter();
+        let watchdog = UdpSocket::bind("127.0.0.1:0")?;
+        let client = AsyncWatchdogClient::new(watchdog.local_addr()?)?;
+        let client_address = client.socket.local_addr()?;
+        let answer = thread::spawn(move || -> Result<()> {
+            let mut buf = [0; 64];
+            // The first one is lost, the resend answered
+            for _ in 0..2 {
+                let (len, _) = watchdog.recv_from(&mut buf)?;
+                assert_eq!(&buf[..len], b"ID 1 STATUS");
+            }
+            watchdog.send_to(b"ID 1 state=armed\n", client_address)?;
+            // Nothing back for the second
+            watchdog.recv_from(&mut buf)?;
+            Ok(())
+        });
+        let status = runtime.block_on(client.status())?;
+        assert_eq!(status.get("state"), Some("armed"));
+        assert!(runtime.block_on(client.status()).is_err());
+        answer.join().unwrap()
+    }
+}
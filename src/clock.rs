@@ -0,0 +1,761 @@
+use crate::config::TimerClock;
+#[cfg(target_os = "linux")]
+use crate::stats::{self, Syscall};
+use anyhow::{bail, Result};
+#[cfg(target_os = "linux")]
+use log::warn;
+use mio::{unix::SourceFd, Events, Interest, Registry, Token};
+#[cfg(target_os = "linux")]
+use nix::{
+    errno::Errno,
+    sys::{
+        time::TimeSpec,
+        timerfd::{ClockId, Expiration::OneShot, TimerFd, TimerFlags, TimerSetTimeFlags},
+    },
+    time::{self, clock_gettime},
+    unistd,
+};
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsFd, AsRawFd};
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    os::fd::RawFd,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Where the client deadlines come from. The watchdog runs on the monotonic or boottime clock,
+/// with timerfds that wake up its poll, while tests can step a virtual clock through timeouts
+/// without sleeping.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn timer(&self) -> Result<Box<dyn Timer>>;
+}
+
+/// A one-shot deadline. Whatever owns one registers it with the loop under its token and gets
+/// the event back, everything else about it goes through here rather than timerfd calls.
+pub trait Timer: Send {
+    /// Fires `after` from now, replacing the previous deadline
+    fn set(&self, after: Duration) -> Result<()>;
+    /// Fires at `deadline` on the clock it came from, right away if that's past. A schedule kept
+    /// as deadlines doesn't drift by however late each one gets set.
+    fn set_at(&self, deadline: Instant) -> Result<()>;
+    /// Cancels the deadline without firing
+    fn unset(&self) -> Result<()>;
+    /// Zero once it fired
+    fn remaining(&self) -> Result<Duration>;
+    /// Readable once it fired, for polling. Virtual timers have none.
+    fn fd(&self) -> Option<RawFd>;
+
+    /// Reads how often it fired since the last time, failing with EAGAIN if it hasn't
+    fn expirations(&self) -> Result<u64> {
+        match self.remaining()?.is_zero() {
+            true => Ok(1),
+            false => bail!("Timer hasn't fired"),
+        }
+    }
+
+    /// Moves the deadline out to `after` from now, unless it already is further out. Returns
+    /// whether it moved.
+    fn hold(&self, after: Duration) -> Result<bool> {
+        let moved = after > self.remaining()?;
+        if moved {
+            self.set(after)?;
+        }
+        Ok(moved)
+    }
+
+    /// Polled under `token`, virtual timers have nothing to register
+    fn register(&self, registry: &Registry, token: Token) -> Result<()> {
+        if let Some(fd) = self.fd() {
+            registry.register(&mut SourceFd(&fd), token, Interest::READABLE)?;
+        }
+        Ok(())
+    }
+
+    fn deregister(&self, registry: &Registry) -> Result<()> {
+        if let Some(fd) = self.fd() {
+            registry.deregister(&mut SourceFd(&fd))?;
+        }
+        Ok(())
+    }
+}
+
+/// The real time. Timers are timerfds on Linux, elsewhere there's nothing to poll and they only
+/// keep the deadline, which is enough for developing and testing the pings.
+#[derive(Clone)]
+pub struct Monotonic;
+
+impl Clock for Monotonic {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn timer(&self) -> Result<Box<dyn Timer>> {
+        let timer = ClockTimer::new(ClockId::CLOCK_MONOTONIC, self.clone())?;
+        Ok(Box::new(timer))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn timer(&self) -> Result<Box<dyn Timer>> {
+        Ok(Box::new(Deadline::new(self.clone())))
+    }
+}
+
+/// The configured clock for the watchdog's timers
+pub fn configured(clock: TimerClock) -> Result<Arc<dyn Clock>> {
+    Ok(match clock {
+        TimerClock::Monotonic => Arc::new(Monotonic),
+        #[cfg(target_os = "linux")]
+        TimerClock::Boottime => Arc::new(Boottime::new(false)?),
+        #[cfg(target_os = "linux")]
+        TimerClock::BoottimeAlarm => Arc::new(Boottime::new(true)?),
+        #[cfg(not(target_os = "linux"))]
+        TimerClock::Boottime | TimerClock::BoottimeAlarm => {
+            bail!("Boottime clocks are only available on Linux")
+        }
+    })
+}
+
+/// The monotonic clock plus the time spent suspended. Instants are only comparable with those
+/// of the same Boottime.
+#[cfg(target_os = "linux")]
+#[derive(Clone)]
+pub struct Boottime {
+    alarm: bool,
+    start: Instant,
+    since_boot: Duration,
+}
+
+#[cfg(target_os = "linux")]
+impl Boottime {
+    /// Alarm timers also wake the system up, if permitted
+    pub fn new(mut alarm: bool) -> Result<Self> {
+        if alarm {
+            if let Err(e) = TimerFd::new(ClockId::CLOCK_BOOTTIME_ALARM, TimerFlags::TFD_NONBLOCK) {
+                warn!("No alarm timers ({}), falling back to CLOCK_BOOTTIME", e);
+                alarm = false;
+            }
+        }
+        Ok(Self {
+            alarm,
+            start: Instant::now(),
+            since_boot: since_boot()?,
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn since_boot() -> Result<Duration> {
+    Ok(clock_gettime(time::ClockId::CLOCK_BOOTTIME)?.into())
+}
+
+#[cfg(target_os = "linux")]
+impl Clock for Boottime {
+    fn now(&self) -> Instant {
+        // Can only fail with a bad clock id
+        let now = since_boot().unwrap_or(self.since_boot);
+        self.start + now.saturating_sub(self.since_boot)
+    }
+
+    fn timer(&self) -> Result<Box<dyn Timer>> {
+        let id = match self.alarm {
+            true => ClockId::CLOCK_BOOTTIME_ALARM,
+            false => ClockId::CLOCK_BOOTTIME,
+        };
+        Ok(Box::new(ClockTimer::new(id, self.clone())?))
+    }
+}
+
+/// A timerfd on the clock underneath `clock`, whose Instants are converted to that clock's time
+/// for absolute deadlines. The deadline is kept alongside, so that reading what's left, on every
+/// ping, takes no timerfd_gettime.
+#[cfg(target_os = "linux")]
+struct ClockTimer<C> {
+    fd: TimerFd,
+    id: ClockId,
+    deadline: Deadline<C>,
+}
+
+#[cfg(target_os = "linux")]
+impl<C: Clock> ClockTimer<C> {
+    fn new(id: ClockId, clock: C) -> Result<Self> {
+        Ok(Self {
+            fd: TimerFd::new(id, TimerFlags::TFD_NONBLOCK)?,
+            id,
+            deadline: Deadline::new(clock),
+        })
+    }
+
+    fn settime(&self, expiration: Duration, flags: TimerSetTimeFlags) -> Result<()> {
+        let expiration = OneShot(TimeSpec::from_duration(expiration));
+        stats::timed(Syscall::TimerfdSettime, || self.fd.set(expiration, flags))?;
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl<C: Clock> Timer for ClockTimer<C> {
+    fn set(&self, after: Duration) -> Result<()> {
+        self.settime(after, TimerSetTimeFlags::empty())?;
+        // Zero disarms a timerfd
+        match after.is_zero() {
+            true => self.deadline.unset(),
+            false => self.deadline.set(after),
+        }
+    }
+
+    fn set_at(&self, deadline: Instant) -> Result<()> {
+        let id = match self.id {
+            ClockId::CLOCK_MONOTONIC => time::ClockId::CLOCK_MONOTONIC,
+            _ => time::ClockId::CLOCK_BOOTTIME,
+        };
+        let now: Duration = clock_gettime(id)?.into();
+        let at = now + deadline.saturating_duration_since(self.deadline.clock.now());
+        self.settime(at, TimerSetTimeFlags::TFD_TIMER_ABSTIME)?;
+        self.deadline.set_at(deadline)
+    }
+
+    fn unset(&self) -> Result<()> {
+        self.fd.unset()?;
+        self.deadline.unset()
+    }
+
+    fn remaining(&self) -> Result<Duration> {
+        self.deadline.remaining()
+    }
+
+    fn fd(&self) -> Option<RawFd> {
+        Some(self.fd.as_fd().as_raw_fd())
+    }
+
+    /// Times how long after the deadline it's read, which is the scheduler's latency on top of
+    /// the kernel's timer slack, however long the loop took to get to it
+    fn expirations(&self) -> Result<u64> {
+        let mut expirations = [0; 8];
+        unistd::read(self.fd.as_fd().as_raw_fd(), &mut expirations)?;
+        let deadline = *self
+            .deadline
+            .deadline
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(deadline) = deadline {
+            let late = self
+                .deadline
+                .clock
+                .now()
+                .saturating_duration_since(deadline);
+            if stats::wakeup(late) {
+                warn!("Timer woke up {:?} after its deadline", late);
+            }
+        }
+        Ok(u64::from_ne_bytes(expirations))
+    }
+}
+
+/// Timers due within this of the first one fire on the same wakeup
+const SLACK: Duration = Duration::from_millis(1);
+
+/// Many timers behind one timer of the clock underneath, always set for the earliest deadline.
+/// Every timer on a timerfd of its own meant an fd and a wakeup each, this keeps both to one, and
+/// re-arming a timer that isn't the next one due is no syscall at all. Its timers have no fd: the
+/// wheel is polled under its own token, and `tokens` hands back those of the timers that fired.
+#[derive(Clone)]
+pub struct Wheel(Arc<Inner>);
+
+struct Inner {
+    clock: Arc<dyn Clock>,
+    state: Mutex<WheelState>,
+}
+
+struct WheelState {
+    /// The one underneath
+    timer: Box<dyn Timer>,
+    /// Where it's set for, if at all
+    armed: Option<Instant>,
+    slots: Vec<Slot>,
+    /// Deadlines with the slot and generation they were set for. Entries for a slot that was
+    /// set again since are stale and skipped.
+    heap: BinaryHeap<Reverse<(Instant, usize, u64)>>,
+}
+
+#[derive(Default)]
+struct Slot {
+    deadline: Option<Instant>,
+    /// Handed out by `tokens`, until the expiration is read
+    fired: bool,
+    token: Option<Token>,
+    generation: u64,
+    used: bool,
+}
+
+impl Wheel {
+    pub fn new(clock: Arc<dyn Clock>) -> Result<Self> {
+        let timer = clock.timer()?;
+        Ok(Self(Arc::new(Inner {
+            clock,
+            state: Mutex::new(WheelState {
+                timer,
+                armed: None,
+                slots: Vec::new(),
+                heap: BinaryHeap::new(),
+            }),
+        })))
+    }
+
+    pub fn register(&self, registry: &Registry, token: Token) -> Result<()> {
+        self.0.state().timer.register(registry, token)
+    }
+
+    /// The tokens of `events`, with `token`, the wheel's own, replaced by those of the timers
+    /// that fired, earliest first
+    pub fn tokens(&self, events: &Events, token: Token) -> Result<Vec<Token>> {
+        let mut tokens = Vec::new();
+        for event in events.iter() {
+            match event.token() {
+                wheel if wheel == token => tokens.extend(self.fire()?),
+                other => tokens.push(other),
+            }
+        }
+        Ok(tokens)
+    }
+
+    /// Marks the due timers fired and returns their tokens
+    fn fire(&self) -> Result<Vec<Token>> {
+        let mut state = self.0.state();
+        // Whatever the count, every due deadline is checked below
+        let _ = state.timer.expirations();
+        state.armed = None;
+        let now = self.0.clock.now();
+        let mut fired = Vec::new();
+        while let Some(&Reverse((deadline, index, generation))) = state.heap.peek() {
+            if deadline > now + SLACK {
+                break;
+            }
+            state.heap.pop();
+            let slot = &mut state.slots[index];
+            if slot.generation == generation && !slot.fired {
+                slot.fired = true;
+                fired.extend(slot.token);
+            }
+        }
+        state.rearm()?;
+        Ok(fired)
+    }
+}
+
+impl Inner {
+    fn state(&self) -> MutexGuard<'_, WheelState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl WheelState {
+    /// Sets the timer underneath for the earliest deadline, if that changed
+    fn rearm(&mut self) -> Result<()> {
+        while let Some(&Reverse((_, index, generation))) = self.heap.peek() {
+            let slot = &self.slots[index];
+            if slot.generation == generation && !slot.fired {
+                break;
+            }
+            self.heap.pop();
+        }
+        let next = self.heap.peek().map(|&Reverse((deadline, ..))| deadline);
+        if next != self.armed {
+            match next {
+                Some(next) => self.timer.set_at(next)?,
+                None => self.timer.unset()?,
+            }
+            self.armed = next;
+        }
+        Ok(())
+    }
+
+    /// Bumps the generation, which makes its queued deadline stale
+    fn reset(&mut self, index: usize, deadline: Option<Instant>) {
+        let slot = &mut self.slots[index];
+        slot.generation += 1;
+        slot.deadline = deadline;
+        slot.fired = false;
+        if let Some(deadline) = deadline {
+            self.heap.push(Reverse((deadline, index, slot.generation)));
+        }
+    }
+}
+
+impl Clock for Wheel {
+    fn now(&self) -> Instant {
+        self.0.clock.now()
+    }
+
+    fn timer(&self) -> Result<Box<dyn Timer>> {
+        let mut state = self.0.state();
+        let index = match state.slots.iter().position(|slot| !slot.used) {
+            Some(index) => index,
+            None => {
+                state.slots.push(Slot::default());
+                state.slots.len() - 1
+            }
+        };
+        state.slots[index].used = true;
+        Ok(Box::new(WheelTimer {
+            wheel: self.0.clone(),
+            index,
+        }))
+    }
+}
+
+struct WheelTimer {
+    wheel: Arc<Inner>,
+    index: usize,
+}
+
+impl WheelTimer {
+    fn update(&self, deadline: Option<Instant>) -> Result<()> {
+        let mut state = self.wheel.state();
+        state.reset(self.index, deadline);
+        state.rearm()
+    }
+}
+
+impl Timer for WheelTimer {
+    /// Zero unsets it, like it does a timerfd
+    fn set(&self, after: Duration) -> Result<()> {
+        let deadline = (!after.is_zero()).then(|| self.wheel.clock.now() + after);
+        self.update(deadline)
+    }
+
+    fn set_at(&self, deadline: Instant) -> Result<()> {
+        self.update(Some(deadline))
+    }
+
+    fn unset(&self) -> Result<()> {
+        self.update(None)
+    }
+
+    fn remaining(&self) -> Result<Duration> {
+        let state = self.wheel.state();
+        let slot = &state.slots[self.index];
+        let now = self.wheel.clock.now();
+        Ok(match (slot.fired, slot.deadline) {
+            (false, Some(deadline)) => deadline.saturating_duration_since(now),
+            _ => Duration::ZERO,
+        })
+    }
+
+    fn fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// Once, like a one-shot timerfd, whether or not the wheel got to it yet
+    fn expirations(&self) -> Result<u64> {
+        let now = self.wheel.clock.now();
+        let mut state = self.wheel.state();
+        let slot = &state.slots[self.index];
+        let expired = slot.fired || slot.deadline.is_some_and(|deadline| deadline <= now);
+        if !expired {
+            bail!("Timer hasn't fired");
+        }
+        state.reset(self.index, None);
+        state.rearm()?;
+        Ok(1)
+    }
+
+    fn register(&self, _registry: &Registry, token: Token) -> Result<()> {
+        self.wheel.state().slots[self.index].token = Some(token);
+        Ok(())
+    }
+
+    fn deregister(&self, _registry: &Registry) -> Result<()> {
+        self.wheel.state().slots[self.index].token = None;
+        Ok(())
+    }
+}
+
+impl Drop for WheelTimer {
+    fn drop(&mut self) {
+        let mut state = self.wheel.state();
+        state.reset(self.index, None);
+        let slot = &mut state.slots[self.index];
+        slot.token = None;
+        slot.used = false;
+        // Failing that it only wakes up for nothing
+        let _ = state.rearm();
+    }
+}
+
+/// The wall clock, in Unix seconds
+pub fn wall_now() -> i64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH);
+    now.map_or(0, |now| now.as_secs() as i64)
+}
+
+/// What a `WallTimer` becoming readable meant
+#[cfg(target_os = "linux")]
+#[derive(Debug, PartialEq, Eq)]
+pub enum WallEvent {
+    /// Nothing, e.g. it was set again in between
+    Pending,
+    Expired,
+    /// The wall clock was set, by this many seconds against the monotonic clock, which leaves
+    /// the timer unset
+    Stepped(i64),
+}
+
+/// A CLOCK_REALTIME timer for a wall clock time, which reports the time being set, e.g. by NTP
+/// or GPS, instead of firing early or late
+#[cfg(target_os = "linux")]
+pub struct WallTimer {
+    timer: TimerFd,
+    /// When it was last set, on both clocks, to size the steps
+    reference: (SystemTime, Instant),
+}
+
+#[cfg(target_os = "linux")]
+impl WallTimer {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            timer: TimerFd::new(ClockId::CLOCK_REALTIME, TimerFlags::TFD_NONBLOCK)?,
+            reference: (SystemTime::now(), Instant::now()),
+        })
+    }
+
+    /// Fires at `at`, in Unix seconds
+    pub fn set(&mut self, at: i64) -> Result<()> {
+        self.reference = (SystemTime::now(), Instant::now());
+        let expiration = OneShot(TimeSpec::new(at, 0));
+        let flags =
+            TimerSetTimeFlags::TFD_TIMER_ABSTIME | TimerSetTimeFlags::TFD_TIMER_CANCEL_ON_SET;
+        self.timer.set(expiration, flags)?;
+        Ok(())
+    }
+
+    pub fn unset(&self) -> Result<()> {
+        self.timer.unset()?;
+        Ok(())
+    }
+
+    pub fn is_set(&self) -> Result<bool> {
+        Ok(self.timer.get()?.is_some())
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.timer.as_fd().as_raw_fd()
+    }
+
+    pub fn on_event(&mut self) -> Result<WallEvent> {
+        let mut expirations = [0; 8];
+        match unistd::read(self.fd(), &mut expirations) {
+            Ok(_) => Ok(WallEvent::Expired),
+            Err(Errno::ECANCELED) => {
+                let now = (SystemTime::now(), Instant::now());
+                Ok(WallEvent::Stepped(step(self.reference, now)))
+            }
+            Err(Errno::EAGAIN) => Ok(WallEvent::Pending),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// How far the wall clock jumped between `from` and `to`, in whole seconds
+#[cfg(target_os = "linux")]
+fn step(from: (SystemTime, Instant), to: (SystemTime, Instant)) -> i64 {
+    let wall = match to.0.duration_since(from.0) {
+        Ok(forward) => forward.as_millis() as i64,
+        Err(backward) => -(backward.duration().as_millis() as i64),
+    };
+    let monotonic = to.1.duration_since(from.1).as_millis() as i64;
+    (wall - monotonic) / 1000
+}
+
+/// A clock that stands still until advanced, so that a test can assert a timeout fires exactly
+/// when it should. Clones share the time.
+#[derive(Clone)]
+pub struct VirtualClock {
+    start: Instant,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Arc::default(),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed() += by;
+    }
+
+    fn elapsed(&self) -> MutexGuard<'_, Duration> {
+        self.elapsed.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed()
+    }
+
+    fn timer(&self) -> Result<Box<dyn Timer>> {
+        Ok(Box::new(Deadline::new(self.clone())))
+    }
+}
+
+/// A timer that only knows when it's due on its clock
+struct Deadline<C> {
+    clock: C,
+    deadline: Mutex<Option<Instant>>,
+}
+
+impl<C: Clock> Deadline<C> {
+    fn new(clock: C) -> Self {
+        Self {
+            clock,
+            deadline: Mutex::new(None),
+        }
+    }
+}
+
+impl<C: Clock> Timer for Deadline<C> {
+    fn set(&self, after: Duration) -> Result<()> {
+        self.set_at(self.clock.now() + after)
+    }
+
+    fn set_at(&self, deadline: Instant) -> Result<()> {
+        *self.deadline.lock().unwrap_or_else(|e| e.into_inner()) = Some(deadline);
+        Ok(())
+    }
+
+    fn unset(&self) -> Result<()> {
+        *self.deadline.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        Ok(())
+    }
+
+    fn remaining(&self) -> Result<Duration> {
+        let deadline = *self.deadline.lock().unwrap_or_else(|e| e.into_inner());
+        let now = self.clock.now();
+        Ok(deadline.map_or(Duration::ZERO, |d| d.saturating_duration_since(now)))
+    }
+
+    fn fd(&self) -> Option<RawFd> {
+        None
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use mio::{Events, Poll};
+
+    #[test]
+    fn test_wall_timer() -> Result<()> {
+        let (wall, monotonic) = (SystemTime::now(), Instant::now());
+        let later = monotonic + Duration::from_secs(10);
+        let forward = wall + Duration::from_secs(3610);
+        assert_eq!(step((wall, monotonic), (forward, later)), 3600);
+        let backward = wall - Duration::from_secs(3590);
+        assert_eq!(step((wall, monotonic), (backward, later)), -3600);
+
+        let mut poll = Poll::new()?;
+        let mut events = Events::with_capacity(1);
+        let mut timer = WallTimer::new()?;
+        assert_eq!(timer.on_event()?, WallEvent::Pending);
+        timer.set(wall_now() + 1)?;
+        assert!(timer.is_set()?);
+        let fd = timer.fd();
+        poll.registry()
+            .register(&mut SourceFd(&fd), Token(0), Interest::READABLE)?;
+        poll.poll(&mut events, Some(Duration::from_secs(3)))?;
+        assert_eq!(timer.on_event()?, WallEvent::Expired);
+        assert!(!timer.is_set()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_timer() -> Result<()> {
+        let mut poll = Poll::new()?;
+        let mut events = Events::with_capacity(1);
+        let timer = Monotonic.timer()?;
+        timer.register(poll.registry(), Token(0))?;
+        assert!(timer.expirations().is_err());
+
+        timer.set(Duration::from_millis(10))?;
+        assert!(timer.hold(Duration::from_secs(1))?);
+        assert!(!timer.hold(Duration::from_millis(10))?);
+        assert!(timer.remaining()? > Duration::from_millis(500));
+        timer.set(Duration::from_millis(1))?;
+        poll.poll(&mut events, Some(Duration::from_secs(1)))?;
+        assert_eq!(
+            events.iter().map(|e| e.token()).collect::<Vec<_>>(),
+            [Token(0)]
+        );
+        assert_eq!(timer.expirations()?, 1);
+        timer.deregister(poll.registry())?;
+
+        // Virtual ones aren't polled, but hold the same way
+        let clock = VirtualClock::new();
+        let timer = clock.timer()?;
+        timer.register(poll.registry(), Token(1))?;
+        timer.set(Duration::from_secs(5))?;
+        assert!(!timer.hold(Duration::from_secs(1))?);
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(timer.expirations()?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wheel() -> Result<()> {
+        let mut poll = Poll::new()?;
+        let mut events = Events::with_capacity(4);
+        let wheel = Wheel::new(Arc::new(Monotonic))?;
+        wheel.register(poll.registry(), Token(0))?;
+        let timers: Vec<_> = (1..=3).map(|_| wheel.timer()).collect::<Result<_>>()?;
+        for (i, timer) in timers.iter().enumerate() {
+            timer.register(poll.registry(), Token(i + 1))?;
+        }
+        timers[0].set(Duration::from_millis(20))?;
+        timers[1].set(Duration::from_millis(10))?;
+        timers[2].set(Duration::from_secs(10))?;
+        assert!(timers[0].expirations().is_err());
+        let mut fired = Vec::new();
+        while fired.len() < 2 {
+            poll.poll(&mut events, Some(Duration::from_secs(1)))?;
+            fired.extend(wheel.tokens(&events, Token(0))?);
+        }
+        assert_eq!(fired, [Token(2), Token(1)]);
+        assert_eq!(timers[1].remaining()?, Duration::ZERO);
+        assert_eq!(timers[1].expirations()?, 1);
+        assert!(timers[1].expirations().is_err());
+        assert!(timers[2].remaining()? > Duration::from_secs(9));
+
+        // Unsetting the earliest one moves the timer underneath out to the next
+        timers[2].set(Duration::from_millis(10))?;
+        timers[0].set(Duration::from_secs(10))?;
+        timers[2].unset()?;
+        poll.poll(&mut events, Some(Duration::from_millis(50)))?;
+        assert!(wheel.tokens(&events, Token(0))?.is_empty());
+        let armed = wheel.0.state().armed;
+        assert!(armed.is_some_and(|at| at > Instant::now() + Duration::from_secs(9)));
+
+        // A dropped one's slot is reused, without its deadline or token
+        drop(timers);
+        let timer = wheel.timer()?;
+        assert_eq!(wheel.0.state().slots.len(), 3);
+        assert_eq!(wheel.0.state().armed, None);
+        timer.set(Duration::from_millis(1))?;
+        poll.poll(&mut events, Some(Duration::from_secs(1)))?;
+        assert!(wheel.tokens(&events, Token(0))?.is_empty());
+        assert_eq!(timer.expirations()?, 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{
+    can_ping::CanPings, cgroup::CgroupMonitor, clock::Clock, config::Config,
+    eventfd::EventfdChannels, peer::Peers, pingee::Pingee, shm::SharedRegions, touch::TouchFiles,
+    units::UnitMonitor,
+};
+use anyhow::{ensure, Result};
+use log::info;
+use mio::{Registry, Token};
+
+/// The most tokens all conditions together can take
+pub const TOKENS: usize = 256;
+
+/// A source of liveness besides the ping sockets, like CAN frames, touched files, cgroups, or
+/// systemd units. Each one feeds clients the way their pings would, reports the liveness it
+/// watches being lost, or both, so that they all add up to the one verdict of whether to keep
+/// petting. A loss is taken like a ping timeout, not while disarmed or inhibited. The
+/// peers are one too, though they watch the liveness of other cards rather than this one's.
+///
+/// Processes watched through pidfds aren't one, as REGISTER and the lines hand those over too.
+pub trait Condition: Send {
+    /// For the logs
+    fn name(&self) -> &'static str;
+
+    /// How many consecutive tokens it was registered with, by its constructor
+    fn tokens(&self) -> usize {
+        1
+    }
+
+    /// One of its tokens became ready, `index` counting from its first. Returns why the liveness
+    /// it watches was lost, if it was, an error being the condition itself failing.
+    fn on_event(&mut self, index: usize, pingee: &mut Pingee) -> Result<Option<String>>;
+
+    /// Called after every pet, for feeding clients for as long as something holds
+    fn on_pet(&mut self, _pingee: &mut Pingee) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The configured conditions, each with its range of tokens
+pub struct Conditions {
+    conditions: Vec<(Token, Box<dyn Condition>)>,
+}
+
+impl Conditions {
+    /// Builds the ones the config asks for, registered with tokens counting up from `first`, with
+    /// their timers on `clock`
+    pub fn new(
+        config: &Config,
+        registry: &Registry,
+        first: Token,
+        clock: &dyn Clock,
+    ) -> Result<Self> {
+        let mut conditions = Self {
+            conditions: Vec::new(),
+        };
+        let end = first.0 + TOKENS;
+        if !config.can_pings.is_empty() {
+            let token = conditions.next(first, config.can_pings.len(), end)?;
+            conditions.add(token, CanPings::new(registry, token, &config.can_pings)?);
+        }
+        if !config.touches.is_empty() {
+            let token = conditions.next(first, 2, end)?;
+            let touches = TouchFiles::new(registry, token, &config.touches, clock)?;
+            conditions.add(token, touches);
+        }
+        if !config.shm.is_empty() {
+            let token = conditions.next(first, 0, end)?;
+            conditions.add(token, SharedRegions::new(&config.shm)?);
+        }
+        if !config.eventfds.is_empty() {
+            let token = conditions.next(first, 2 * config.eventfds.len(), end)?;
+            let channels = EventfdChannels::new(registry, token, &config.eventfds)?;
+            conditions.add(token, channels);
+        }
+        if !config.cgroups.is_empty() {
+            let token = conditions.next(first, 1, end)?;
+            conditions.add(token, CgroupMonitor::new(registry, token, &config.cgroups)?);
+        }
+        if !config.units.is_empty() {
+            let token = conditions.next(first, 1, end)?;
+            conditions.add(token, UnitMonitor::new(registry, token, &config.units)?);
+        }
+        if !config.peers.is_empty() {
+            let token = conditions.next(first, 2 * config.peers.len(), end)?;
+            let peers = Peers::new(registry, token, &config.peers, clock)?;
+            conditions.add(token, peers);
+        }
+        Ok(conditions)
+    }
+
+    /// The first free token, if `count` more fit before `end`
+    fn next(&self, first: Token, count: usize, end: usize) -> Result<Token> {
+        let next = self
+            .conditions
+            .last()
+            .map_or(first.0, |(token, c)| token.0 + c.tokens());
+        ensure!(next + count <= end, "Too many conditions");
+        Ok(Token(next))
+    }
+
+    fn add(&mut self, first: Token, condition: impl Condition + 'static) {
+        info!("Watching {}", condition.name());
+        self.conditions.push((first, Box::new(condition)));
+    }
+
+    pub fn handles(&self, token: Token) -> bool {
+        self.find(token).is_some()
+    }
+
+    /// Why the liveness the condition owning `token` watches was lost, if it was
+    pub fn on_event(&mut self, token: Token, pingee: &mut Pingee) -> Result<Option<String>> {
+        match self.find(token) {
+            Some(index) => {
+                let (first, condition) = &mut self.conditions[index];
+                condition.on_event(token.0 - first.0, pingee)
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn on_pet(&mut self, pingee: &mut Pingee) -> Result<()> {
+        for (_, condition) in &mut self.conditions {
+            condition.on_pet(pingee)?;
+        }
+        Ok(())
+    }
+
+    fn find(&self, token: Token) -> Option<usize> {
+        self.conditions
+            .iter()
+            .position(|(first, c)| (first.0..first.0 + c.tokens()).contains(&token.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Monotonic;
+    use mio::Poll;
+    use std::fs;
+
+    #[test]
+    fn test_tokens() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("watchdog-conditions-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("cgroup.events"), "populated 1\n")?;
+        let config = Config::parse(&format!(
+            r#"
+            [[client]]
+            name = "script"
+            [[touch]]
+            path = {:?}
+            client = "script"
+            [[cgroup]]
+            name = "flight"
+            path = {:?}
+            "#,
+            dir.join("alive"),
+            dir
+        ))?;
+        let poll = Poll::new()?;
+        let conditions = Conditions::new(&config, poll.registry(), Token(100), &Monotonic)?;
+        // The touch files' two, then the cgroups'
+        let handled: Vec<_> = (99..104).map(|t| conditions.handles(Token(t))).collect();
+        assert_eq!(handled, [false, true, true, true, false]);
+        assert_eq!(conditions.find(Token(102)), Some(1));
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
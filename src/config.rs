@@ -0,0 +1,2586 @@
+use crate::{
+    auth::Authenticator,
+    net::{self, Subnet},
+    policy::Policy,
+    protocol,
+    rule::{self, Rule},
+    schedule::Cron,
+};
+use anyhow::{ensure, Context, Result};
+use log::{error, info, warn};
+use serde::{Deserialize, Deserializer};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+pub const DEFAULT_PATH: &str = "/etc/oresat-c3-watchdog.toml";
+/// Every option, commented, as `init-config` writes it out. The ones that are set are the
+/// defaults, which the tests hold it to.
+pub const TEMPLATE: &str = include_str!("default.toml");
+pub const DEFAULT_ADDRESS: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 20001);
+
+/// Each client (including port channels) gets its own timer and poll token
+pub const MAX_CLIENTS: usize = 64;
+pub const MAX_PORTS: usize = 16;
+pub const MAX_LINES: usize = 8;
+pub const MAX_CAN_PINGS: usize = 16;
+/// Recent ping intervals kept per client
+pub const MAX_CADENCE_SAMPLES: usize = 4096;
+pub const MAX_PET_STEPS: usize = 16;
+
+// From sched.h and linux/can.h, which libc only has on Linux, so that configs validate the same
+// everywhere
+const CPU_SETSIZE: usize = 1024;
+const CAN_EFF_MASK: u32 = 0x1FFF_FFFF;
+
+/// Runtime configuration, read from a TOML file. Everything is optional, an empty or missing
+/// default config file gives the compiled-in behavior.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where pings and commands are received. IPv6 link-local addresses take a scope, either an
+    /// interface name or index: "[fe80::1%eth0]:20001"
+    #[serde(deserialize_with = "socket_address")]
+    pub address: SocketAddr,
+    /// Whether missing pings are fatal at startup, can be changed with ARM/DISARM
+    pub armed: bool,
+    /// Starting disarmed after a software update
+    pub interlock: Option<InterlockConfig>,
+    /// Conditions arming waits for at startup, against early boot races
+    #[serde(rename = "gate")]
+    pub gates: Vec<GateConfig>,
+    pub auth: AuthConfig,
+    /// If not empty, packets from sources outside of these subnets are dropped
+    pub allowed_sources: Vec<Subnet>,
+    /// Multicast groups joined on the main socket
+    #[serde(rename = "multicast")]
+    pub multicast_groups: Vec<MulticastConfig>,
+    /// SO_RCVBUF of the main socket and the ports in bytes, zero leaves the kernel's default
+    pub recv_buffer: usize,
+    /// Packets read off the ping sockets per wakeup, before the loop gets back to the pet timer
+    pub drain_budget: usize,
+    /// systemd units whose ActiveState is monitored over D-Bus
+    #[serde(rename = "unit")]
+    pub units: Vec<UnitConfig>,
+    /// Processes that must keep running, watched through pidfds
+    #[serde(rename = "process")]
+    pub processes: Vec<ProcessConfig>,
+    /// cgroups that must keep running processes, watched through their cgroup.events
+    #[serde(rename = "cgroup")]
+    pub cgroups: Vec<CgroupConfig>,
+    /// Named clients that all have to keep pinging. If there are none any packet is a ping.
+    #[serde(rename = "client")]
+    pub clients: Vec<ClientConfig>,
+    pub registration: RegistrationConfig,
+    pub legacy: LegacyConfig,
+    pub extension: ExtensionConfig,
+    pub inhibit: InhibitConfig,
+    pub budget: BudgetConfig,
+    pub strikes: StrikeConfig,
+    /// Which clients have to keep pinging, instead of all of them. Ones it doesn't name, like
+    /// registered ones, still all have to.
+    #[serde(deserialize_with = "policy")]
+    pub policy: Option<Policy>,
+    /// CAN frames that count as pings from a client
+    #[serde(rename = "can_ping")]
+    pub can_pings: Vec<CanPingConfig>,
+    /// Files whose being touched counts as a ping from a client
+    #[serde(rename = "touch")]
+    pub touches: Vec<TouchConfig>,
+    /// Shared memory files whose counter moving counts as a ping from a client
+    pub shm: Vec<ShmConfig>,
+    /// Eventfds handed out on Unix sockets, whose being written counts as a ping from a client
+    #[serde(rename = "eventfd")]
+    pub eventfds: Vec<EventfdConfig>,
+    /// Extra listening ports, each feeding its own named channel
+    #[serde(rename = "port")]
+    pub ports: Vec<PortConfig>,
+    pub death_row: DeathRowConfig,
+    /// Warns in steps as a client's timeout runs down, before it bites
+    pub ladder: Option<LadderConfig>,
+    /// Announces that the watchdog itself is alive
+    pub heartbeat: Option<HeartbeatConfig>,
+    /// Waits for another instance's heartbeats to stop before petting
+    pub standby: Option<StandbyConfig>,
+    /// Forwards the significant events, e.g. to a ground software simulator
+    pub telemetry: Option<TelemetryConfig>,
+    /// Publishes the status and events to a broker, with the mqtt feature
+    pub mqtt: Option<MqttConfig>,
+    /// Emits StateChanged, StrikeRecorded, and DeathImminent signals on the system D-Bus
+    pub dbus_signals: bool,
+    /// Times the GPIO writes, timerfd_settime calls, and ping socket reads, for the status
+    pub instrument: bool,
+    /// Reports how close the clients cut it, for tuning their ping cadence
+    pub margin: Option<MarginConfig>,
+    /// Seconds of missed windows and ping margins the health grade goes by
+    #[serde(deserialize_with = "seconds")]
+    pub health_window: Duration,
+    /// Recommends client timeouts from how often they actually ping
+    pub cadence: Option<CadenceConfig>,
+    /// Watches for wall clock steps and suspends, noting them in the timeouts that follow
+    pub time_anomalies: Option<TimeAnomalyConfig>,
+    pub hook: HookConfig,
+    /// On SIGTERM, how long to keep petting before exiting, so that a service restart doesn't
+    /// reset the card. Zero exits right away.
+    #[serde(deserialize_with = "seconds")]
+    pub drain: Duration,
+    /// Seconds since boot after which the card is deliberately reset, through the death row like
+    /// a ping timeout but not pardonable. Zero never resets.
+    #[serde(deserialize_with = "seconds")]
+    pub max_uptime: Duration,
+    pub schedule: ScheduleConfig,
+    pub battery: BatteryConfig,
+    pub rules: RulesConfig,
+    /// Treats a CAN bus going completely quiet like a ping timeout
+    pub can_silence: Option<CanSilenceConfig>,
+    /// Serves the parameters and counters over CANopen SDOs
+    pub canopen: Option<CanopenConfig>,
+    /// Where /healthz and /status are served over HTTP, for test orchestration
+    pub http: Option<SocketAddr>,
+    /// AF_VSOCK port pinged and commanded through too, for a host driving the watchdog in a VM
+    pub vsock: Option<u32>,
+    /// Unix socket where a restarting watchdog hands its GPIO line and sockets to its successor
+    pub handover: Option<PathBuf>,
+    /// Unix stream socket the C3 software pulls the status from
+    pub status_socket: Option<PathBuf>,
+    /// A few lines of the status rewritten every `status_file_interval`, best on a tmpfs
+    pub status_file: Option<PathBuf>,
+    #[serde(deserialize_with = "seconds")]
+    pub status_file_interval: Duration,
+    /// File every control command received is appended to, with its outcome
+    pub audit_log: Option<PathBuf>,
+    /// Every datagram on the ping socket, for looking at what arrived afterwards
+    pub capture: Option<CaptureConfig>,
+    /// Notable events kept across reboots, paged out with LOGBOOK
+    pub logbook: Option<LogbookConfig>,
+    /// Logs to a rotated file instead, for images with neither journald nor syslog
+    pub log: Option<LogConfig>,
+    /// Where the reason for going down is written before exiting, for the next boot to downlink
+    pub last_gasp: Option<PathBuf>,
+    /// What a panic does with the line before the watchdog exits
+    pub panic_line: PanicLine,
+    /// File counting starts and unclean shutdowns across power cycles
+    pub counters: Option<PathBuf>,
+    /// Seconds between saving the cumulative counters to `counters`, besides on exiting
+    #[serde(deserialize_with = "seconds")]
+    pub counters_interval: Duration,
+    pub reset_reason: ResetReasonConfig,
+    /// Line asking the supervisor for an orderly reboot on a commanded reset
+    pub reset_request: Option<ResetRequestConfig>,
+    /// What a ping timeout does
+    pub bite: BiteConfig,
+    /// The supervisor's reset output, watched for it biting
+    pub supervisor: Option<SupervisorConfig>,
+    /// The supervisor's datasheet limits, checked against the pet pattern and timeouts
+    pub hardware: Option<HardwareConfig>,
+    /// A kernel watchdog device fed along with the line, e.g. the softdog
+    pub kernel_watchdog: Option<KernelWatchdogConfig>,
+    pub late_pet: LatePetConfig,
+    pub pet: PetConfig,
+    /// Petting slower and allowing the clients longer while the EPS reports critical power
+    pub low_power: Option<LowPowerConfig>,
+    /// Entered with MAINTENANCE, for ground work on the card
+    pub maintenance: Option<MaintenanceConfig>,
+    /// Mission mode profiles, the first one active from the start
+    #[serde(rename = "profile")]
+    pub profiles: Vec<ProfileConfig>,
+    /// The mission state frames, whose first payload byte switches to the profile with that state
+    pub profile_can: Option<CanConfig>,
+    pub realtime: RealtimeConfig,
+    pub privileges: PrivilegesConfig,
+    /// Restricts the event loop to the syscalls it needs once set up
+    pub seccomp: bool,
+    /// Seconds the main loop may go without an iteration before exiting with loop_stall. Zero
+    /// doesn't check.
+    #[serde(deserialize_with = "seconds")]
+    pub stall: Duration,
+    /// What the ping timeouts and the death row window count
+    pub clock: TimerClock,
+    pub retry: RetryConfig,
+    /// Other cards' watchdog lines, petted by the same process
+    #[serde(rename = "line")]
+    pub lines: Vec<LineConfig>,
+    /// Companion cards watched by their heartbeats, and power cycled when those stop
+    #[serde(rename = "peer")]
+    pub peers: Vec<PeerConfig>,
+    /// Why the compiled-in safe defaults run instead of the config, if they do
+    #[serde(skip)]
+    pub degraded: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortConfig {
+    /// Name of the liveness channel, shares the namespace with clients
+    pub name: String,
+    pub port: u16,
+    #[serde(default = "default_ping_timeout", deserialize_with = "seconds")]
+    pub timeout: Duration,
+}
+
+/// For cards without an IP stack, which ping over the bus instead
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CanPingConfig {
+    /// The client or port channel fed
+    pub client: String,
+    pub interface: String,
+    /// Ids over 0x7ff are extended frames
+    pub id: u32,
+    /// If not empty, the payload has to start with these bytes
+    #[serde(default)]
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientConfig {
+    pub name: String,
+    /// Seconds allowed between pings
+    #[serde(default = "default_ping_timeout", deserialize_with = "seconds")]
+    pub timeout: Duration,
+    /// Acknowledges every ping, not just those asking with ACK
+    #[serde(default)]
+    pub ack: bool,
+    #[serde(default)]
+    pub criticality: Criticality,
+}
+
+/// What a client's silence does, changed at runtime with CRITICALITY
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Criticality {
+    /// Takes the ping timeout path, strikes and the policy included
+    #[default]
+    Critical,
+    /// Only logged, sent as telemetry, and counted against the health grade
+    Advisory,
+}
+
+impl Criticality {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Criticality::Critical => "critical",
+            Criticality::Advisory => "advisory",
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            address: DEFAULT_ADDRESS,
+            armed: true,
+            interlock: None,
+            gates: Vec::new(),
+            auth: AuthConfig::default(),
+            allowed_sources: Vec::new(),
+            multicast_groups: Vec::new(),
+            recv_buffer: 0,
+            drain_budget: 64,
+            units: Vec::new(),
+            processes: Vec::new(),
+            cgroups: Vec::new(),
+            clients: Vec::new(),
+            registration: RegistrationConfig::default(),
+            legacy: LegacyConfig::default(),
+            extension: ExtensionConfig::default(),
+            inhibit: InhibitConfig::default(),
+            budget: BudgetConfig::default(),
+            strikes: StrikeConfig::default(),
+            policy: None,
+            can_pings: Vec::new(),
+            touches: Vec::new(),
+            shm: Vec::new(),
+            eventfds: Vec::new(),
+            ports: Vec::new(),
+            death_row: DeathRowConfig::default(),
+            ladder: None,
+            heartbeat: None,
+            standby: None,
+            telemetry: None,
+            mqtt: None,
+            dbus_signals: false,
+            instrument: false,
+            margin: None,
+            health_window: Duration::from_secs(3600),
+            cadence: None,
+            time_anomalies: None,
+            hook: HookConfig::default(),
+            drain: Duration::ZERO,
+            max_uptime: Duration::ZERO,
+            schedule: ScheduleConfig::default(),
+            battery: BatteryConfig::default(),
+            rules: RulesConfig::default(),
+            canopen: None,
+            can_silence: None,
+            http: None,
+            vsock: None,
+            handover: None,
+            status_socket: None,
+            status_file: None,
+            status_file_interval: Duration::from_secs(5),
+            audit_log: None,
+            capture: None,
+            logbook: None,
+            log: None,
+            last_gasp: None,
+            panic_line: PanicLine::SafeLow,
+            counters: None,
+            counters_interval: Duration::from_secs(600),
+            reset_reason: ResetReasonConfig::default(),
+            reset_request: None,
+            bite: BiteConfig::default(),
+            supervisor: None,
+            hardware: None,
+            kernel_watchdog: None,
+            late_pet: LatePetConfig::default(),
+            pet: PetConfig::default(),
+            low_power: None,
+            maintenance: None,
+            profiles: Vec::new(),
+            profile_can: None,
+            realtime: RealtimeConfig::default(),
+            privileges: PrivilegesConfig::default(),
+            seccomp: false,
+            stall: Duration::ZERO,
+            clock: TimerClock::Monotonic,
+            retry: RetryConfig::default(),
+            lines: Vec::new(),
+            peers: Vec::new(),
+            degraded: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Shared HMAC key for authenticated commands, which are all rejected without any key
+    pub key_file: Option<PathBuf>,
+    /// More keys, each file's name being the key id commands pick it with. Reread on SIGHUP
+    /// along with the key file.
+    pub key_dir: Option<PathBuf>,
+    /// The commands each key id, or client name, may send. Ones not listed may send anything.
+    /// The key file's key is `default`.
+    pub permissions: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MulticastConfig {
+    pub group: IpAddr,
+    /// Interface name or index to join on, by default the kernel picks one
+    pub interface: Option<String>,
+}
+
+/// Clients enrolling themselves at runtime with REGISTER
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegistrationConfig {
+    pub enabled: bool,
+    /// Requested timeouts are capped to this
+    #[serde(deserialize_with = "seconds")]
+    pub max_timeout: Duration,
+}
+
+impl Default for RegistrationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_timeout: default_ping_timeout(),
+        }
+    }
+}
+
+/// Bare datagrams, as the old flight software sends, counted as pings while it's migrated
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LegacyConfig {
+    pub enabled: bool,
+    /// The client they feed, needed if there are clients
+    pub client: Option<String>,
+}
+
+/// One-off longer ping windows requested with EXTEND
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExtensionConfig {
+    pub enabled: bool,
+    /// Longer requests are denied
+    #[serde(deserialize_with = "seconds")]
+    pub max: Duration,
+}
+
+impl Default for ExtensionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max: Duration::from_secs(120),
+        }
+    }
+}
+
+/// Bounds the delay that EXTEND and INHIBIT can add up to together
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BudgetConfig {
+    /// Seconds of delay that can be requested at once, zero for no limit
+    #[serde(deserialize_with = "seconds")]
+    pub max: Duration,
+    /// Seconds over which all of it is refilled
+    #[serde(deserialize_with = "seconds")]
+    pub period: Duration,
+}
+
+impl Default for BudgetConfig {
+    fn default() -> Self {
+        Self {
+            max: Duration::ZERO,
+            period: Duration::from_secs(6 * 3600),
+        }
+    }
+}
+
+/// Missed ping windows tolerated before a client's silence is fatal
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StrikeConfig {
+    /// Missed windows that are fatal. One, the default, is the strict mode for flight.
+    pub max: u32,
+    /// Counts the misses over this sliding window rather than in a row, zero for in a row
+    #[serde(deserialize_with = "seconds")]
+    pub window: Duration,
+}
+
+impl Default for StrikeConfig {
+    fn default() -> Self {
+        Self {
+            max: 1,
+            window: Duration::ZERO,
+        }
+    }
+}
+
+/// Retrying the event loop on transient errors, like EINTR or EIO, instead of exiting
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /// Failures in a row before giving up, one exits on the first
+    pub threshold: u32,
+    /// Wait before the first retry, doubling up to 0.1 s
+    #[serde(deserialize_with = "seconds")]
+    pub backoff: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 5,
+            backoff: Duration::from_millis(10),
+        }
+    }
+}
+
+/// When deliberate resets may happen, e.g. outside of ground station passes
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Cron expressions in UTC for when each window opens, any time if there are none
+    pub windows: Vec<Cron>,
+    /// How long each window stays open
+    #[serde(deserialize_with = "seconds")]
+    pub duration: Duration,
+    /// Whether each window opening resets the card as well, rather than only allowing it
+    pub required: bool,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            windows: Vec::new(),
+            duration: Duration::from_secs(3600),
+            required: false,
+        }
+    }
+}
+
+/// Deferring deliberate resets while the battery is low
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatteryConfig {
+    /// sysfs attribute with the voltage, e.g. a hwmon in*_input, not checked if unset
+    pub path: Option<PathBuf>,
+    /// Volts per unit read, 0.001 for hwmon's millivolts
+    pub scale: f64,
+    /// Below this, in volts, resets wait
+    pub min_voltage: f64,
+    /// How often the voltage is checked again while waiting
+    #[serde(deserialize_with = "seconds")]
+    pub interval: Duration,
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            scale: 0.001,
+            min_voltage: 0.0,
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Mission policies for decisions the watchdog makes, see `Rule`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RulesConfig {
+    /// Whether a deliberate reset goes ahead now, otherwise it waits until it does
+    pub reset: Option<Rule>,
+    /// Whether an INHIBIT is granted, on top of the inhibit limits
+    pub inhibit: Option<Rule>,
+    /// How often a held back reset is checked again
+    #[serde(deserialize_with = "seconds")]
+    pub interval: Duration,
+}
+
+impl Default for RulesConfig {
+    fn default() -> Self {
+        Self {
+            reset: None,
+            inhibit: None,
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Suspending the ping requirement entirely with INHIBIT
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InhibitConfig {
+    /// The grace period after starting, before which nothing has to ping
+    #[serde(deserialize_with = "seconds")]
+    pub startup: Duration,
+    pub enabled: bool,
+    /// Longer requests are denied
+    #[serde(deserialize_with = "seconds")]
+    pub max: Duration,
+    /// Requests granted per run, unlimited if unset
+    pub max_count: Option<u32>,
+    /// Whether requests have to be authenticated, by default they do
+    pub auth: bool,
+}
+
+impl Default for InhibitConfig {
+    fn default() -> Self {
+        Self {
+            startup: crate::pingee::INHIBIT,
+            enabled: false,
+            max: Duration::from_secs(600),
+            max_count: None,
+            auth: true,
+        }
+    }
+}
+
+/// A flag file the update tooling drops, which keeps the watchdog starting disarmed until ARM or
+/// the end of the window
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InterlockConfig {
+    pub path: PathBuf,
+    /// Seconds after starting that it's armed anyway
+    #[serde(default = "default_interlock_window", deserialize_with = "seconds")]
+    pub window: Duration,
+}
+
+/// One thing to wait for before arming, exactly one of `can`, `unit`, and `time_sync`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GateConfig {
+    /// A network interface, e.g. can0, that has to be up
+    pub can: Option<String>,
+    /// A systemd unit that has to be active
+    pub unit: Option<String>,
+    /// Whether the system clock has to be synchronized, by NTP or the like
+    #[serde(default)]
+    pub time_sync: bool,
+    /// Seconds after starting to stop waiting
+    #[serde(deserialize_with = "seconds")]
+    pub timeout: Duration,
+    /// What to do then
+    #[serde(default)]
+    pub fallback: GateFallback,
+}
+
+impl GateConfig {
+    /// For the logs and the status
+    pub fn name(&self) -> String {
+        match (&self.can, &self.unit) {
+            (Some(interface), _) => interface.clone(),
+            (None, Some(unit)) => unit.clone(),
+            (None, None) => String::from("time-sync"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GateFallback {
+    /// Arm anyway, as if it had been met
+    #[default]
+    Arm,
+    /// Reset the card through the death row, for another try at booting
+    Bite,
+}
+
+/// Grace window after a ping timeout, during which warnings are broadcast before petting stops
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeathRowConfig {
+    /// Zero stops petting right away
+    #[serde(deserialize_with = "seconds")]
+    pub duration: Duration,
+    /// Time between warnings
+    #[serde(deserialize_with = "seconds")]
+    pub interval: Duration,
+    /// UDP addresses the WARNING packets are sent to, broadcast addresses are fine
+    pub targets: Vec<SocketAddr>,
+    pub can: Option<CanConfig>,
+}
+
+impl Default for DeathRowConfig {
+    fn default() -> Self {
+        Self {
+            duration: Duration::ZERO,
+            interval: Duration::from_secs(1),
+            targets: Vec::new(),
+            can: None,
+        }
+    }
+}
+
+/// Each rung is the share of a client's timeout gone by at which it's taken, zero for never
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LadderConfig {
+    /// Sends a NOTICE packet to the targets
+    pub notice: f64,
+    pub targets: Vec<SocketAddr>,
+    /// Sends a CANopen EMCY
+    pub emcy: f64,
+    pub can: Option<CanConfig>,
+    /// Runs the hook, rather than only once the watchdog dies
+    pub hook: f64,
+}
+
+impl Default for LadderConfig {
+    fn default() -> Self {
+        Self {
+            notice: 0.5,
+            targets: Vec::new(),
+            emcy: 0.8,
+            can: None,
+            hook: 0.95,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TouchConfig {
+    pub path: PathBuf,
+    /// The client or port channel fed
+    pub client: String,
+    /// How old its mtime can be to still count
+    #[serde(default = "default_touch_max_age", deserialize_with = "seconds")]
+    pub max_age: Duration,
+}
+
+fn default_touch_max_age() -> Duration {
+    Duration::from_secs(10)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShmConfig {
+    /// Created if it doesn't exist, e.g. in /dev/shm
+    pub path: PathBuf,
+    /// The client or port channel fed
+    pub client: String,
+    /// How old the timestamp written with the counter can be to still count
+    #[serde(default = "default_shm_max_age", deserialize_with = "seconds")]
+    pub max_age: Duration,
+}
+
+fn default_shm_max_age() -> Duration {
+    Duration::from_secs(1)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventfdConfig {
+    /// The Unix socket handing out the eventfd
+    pub path: PathBuf,
+    /// The client or port channel fed
+    pub client: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeartbeatConfig {
+    /// Time between beats
+    #[serde(default = "default_heartbeat_interval", deserialize_with = "seconds")]
+    pub interval: Duration,
+    /// UDP addresses the HEARTBEAT packets are sent to, broadcast addresses are fine
+    #[serde(default)]
+    pub targets: Vec<SocketAddr>,
+    pub can: Option<CanConfig>,
+}
+
+fn default_heartbeat_interval() -> Duration {
+    Duration::from_secs(1)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StandbyConfig {
+    /// Where the primary's HEARTBEAT packets arrive, one of its heartbeat targets
+    pub listen: SocketAddr,
+    /// Seconds without a heartbeat before taking over
+    #[serde(default = "default_standby_silence", deserialize_with = "seconds")]
+    pub silence: Duration,
+}
+
+fn default_standby_silence() -> Duration {
+    Duration::from_secs(5)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MarginConfig {
+    /// Time between reports
+    #[serde(default = "default_margin_interval", deserialize_with = "seconds")]
+    pub interval: Duration,
+    /// UDP addresses the MARGIN packets are sent to
+    #[serde(default)]
+    pub targets: Vec<SocketAddr>,
+    pub can: Option<CanConfig>,
+}
+
+fn default_margin_interval() -> Duration {
+    Duration::from_secs(1)
+}
+
+/// For the configured clients, from their recent ping intervals
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CadenceConfig {
+    /// Time between recommendations
+    #[serde(default = "default_cadence_interval", deserialize_with = "seconds")]
+    pub interval: Duration,
+    /// Of the recent intervals, recommended with the margin on top
+    #[serde(default = "default_cadence_quantile")]
+    pub quantile: f64,
+    #[serde(default = "default_cadence_margin")]
+    pub margin: f64,
+    /// Fewer recent intervals recommend nothing
+    #[serde(default = "default_cadence_samples")]
+    pub min_samples: usize,
+    /// If set, timeouts are tightened to the recommendation, though never below this or above
+    /// the configured one
+    #[serde(default, deserialize_with = "seconds")]
+    pub min_timeout: Duration,
+}
+
+fn default_cadence_interval() -> Duration {
+    Duration::from_secs(600)
+}
+
+fn default_cadence_quantile() -> f64 {
+    0.999
+}
+
+fn default_cadence_margin() -> f64 {
+    1.5
+}
+
+fn default_cadence_samples() -> usize {
+    100
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeAnomalyConfig {
+    /// How often to check whether the card was suspended
+    #[serde(default = "default_anomaly_interval", deserialize_with = "seconds")]
+    pub interval: Duration,
+}
+
+fn default_anomaly_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+/// A binary log of the received datagrams, rotated at a size
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureConfig {
+    pub path: PathBuf,
+    /// Bytes, after which the file is rotated to .1
+    #[serde(default = "default_rotate_size")]
+    pub max_size: u64,
+    /// Rotated files kept
+    #[serde(default = "default_rotate_files")]
+    pub files: usize,
+    /// Bytes kept of each datagram
+    #[serde(default = "default_capture_snaplen")]
+    pub snaplen: usize,
+}
+
+fn default_rotate_size() -> u64 {
+    1 << 20
+}
+
+fn default_rotate_files() -> usize {
+    4
+}
+
+fn default_capture_snaplen() -> usize {
+    64
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogbookConfig {
+    pub path: PathBuf,
+    /// Kept, the oldest are dropped past it
+    #[serde(default = "default_logbook_entries")]
+    pub entries: usize,
+}
+
+fn default_logbook_entries() -> usize {
+    1000
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogConfig {
+    pub path: PathBuf,
+    /// Bytes, after which the file is rotated to .1
+    #[serde(default = "default_rotate_size")]
+    pub max_size: u64,
+    /// Rotated files kept
+    #[serde(default = "default_rotate_files")]
+    pub files: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// UDP addresses the EVENT packets are sent to
+    pub targets: Vec<SocketAddr>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    pub broker: SocketAddr,
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    /// Prefix of the status, events, and availability topics
+    #[serde(default = "default_mqtt_topic")]
+    pub topic: String,
+    /// Time between status publications
+    #[serde(default = "default_mqtt_interval", deserialize_with = "seconds")]
+    pub interval: Duration,
+    #[serde(default = "default_mqtt_keep_alive", deserialize_with = "seconds")]
+    pub keep_alive: Duration,
+}
+
+fn default_mqtt_client_id() -> String {
+    String::from("oresat-c3-watchdog")
+}
+
+fn default_mqtt_topic() -> String {
+    String::from("oresat/c3/watchdog")
+}
+
+fn default_mqtt_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_mqtt_keep_alive() -> Duration {
+    Duration::from_secs(30)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CanConfig {
+    pub interface: String,
+    /// Ids over 0x7ff are sent as extended frames
+    pub id: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CanSilenceConfig {
+    pub interface: String,
+    /// Seconds without a frame from the bus
+    #[serde(default = "default_ping_timeout", deserialize_with = "seconds")]
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CanopenConfig {
+    pub interface: String,
+    pub node_id: u8,
+    /// Whether SDO downloads can change anything. They're not authenticated, so this takes
+    /// unauthenticated inhibits and no permissions.
+    #[serde(default)]
+    pub writable: bool,
+    /// The C3's node, whose NMT state is followed if set
+    pub c3_node_id: Option<u8>,
+    /// While the C3 is operational, client timeouts are at most this long. Zero leaves them.
+    #[serde(default, deserialize_with = "seconds")]
+    pub operational_timeout: Duration,
+}
+
+/// Command run before the petting stops for any reason other than a signal
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HookConfig {
+    /// Program and arguments, empty for none
+    pub command: Vec<String>,
+    /// The hook is killed after this, petting continues until then
+    #[serde(deserialize_with = "seconds")]
+    pub timeout: Duration,
+}
+
+impl Default for HookConfig {
+    fn default() -> Self {
+        Self {
+            command: Vec::new(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Where to find out why the card last reset, the first source that works is used
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResetReasonConfig {
+    /// Supervisor status line
+    pub gpio: Option<ReasonGpioConfig>,
+    /// sysfs node holding the reason as text
+    pub file: Option<PathBuf>,
+    /// A watchdog's bootstatus, e.g. "/sys/class/watchdog/watchdog0/bootstatus"
+    pub bootstatus: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReasonGpioConfig {
+    pub chip: String,
+    pub line: u32,
+    /// The reason when the line reads high, and when it reads low
+    pub high: String,
+    pub low: String,
+}
+
+/// The waveform the watchdog line is driven through, for supervisors that want more than a toggle
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PetConfig {
+    /// Cycled through for as long as the petting goes on, starting with the first step
+    pub pattern: Vec<PetStep>,
+    /// What to do when something else already holds the watchdog line
+    pub busy: BusyPolicy,
+    /// How long to keep trying for the line, unless failing outright
+    #[serde(deserialize_with = "seconds")]
+    pub busy_timeout: Duration,
+    /// Generates the pattern on a hardware PWM channel instead of toggling the GPIO line
+    pub pwm: Option<PwmConfig>,
+    /// An input wired to the watchdog line, watched for the edges the pets really made
+    pub mirror: Option<MirrorConfig>,
+    /// The line's global number in the deprecated sysfs interface, for kernels without the GPIO
+    /// character device
+    pub sysfs_gpio: Option<u32>,
+    /// Starts the cycles on wall clock second boundaries
+    pub align: Option<AlignConfig>,
+}
+
+/// The cycles start where the wall clock is `offset` past a multiple of the period, e.g. on the
+/// top of every second for a 1 s one
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlignConfig {
+    #[serde(deserialize_with = "seconds")]
+    pub offset: Duration,
+}
+
+impl Default for AlignConfig {
+    fn default() -> Self {
+        Self {
+            offset: Duration::ZERO,
+        }
+    }
+}
+
+/// A channel of a sysfs PWM chip, exported if it isn't yet
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PwmConfig {
+    /// e.g. /sys/class/pwm/pwmchip0
+    pub chip: PathBuf,
+    pub channel: u32,
+}
+
+/// Its edges are timestamped by the kernel, and compared to the writes that asked for them
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MirrorConfig {
+    pub chip: String,
+    /// Checked against the line's name, like the main line's
+    pub label: String,
+    pub line: u32,
+    /// Left as it is if unset
+    pub bias: Option<Bias>,
+}
+
+impl PetConfig {
+    /// One whole cycle
+    pub fn period(&self) -> Duration {
+        self.pattern.iter().map(|step| step.duration).sum()
+    }
+
+    /// From each rising edge to the next, wrapping around the cycle
+    pub fn feeds(&self) -> Vec<Duration> {
+        feeds(&self.pattern)
+    }
+}
+
+fn feeds(pattern: &[PetStep]) -> Vec<Duration> {
+    let rising: Vec<_> = (0..pattern.len()).filter(|&i| pattern[i].high).collect();
+    let since = |from: usize, to: usize| {
+        let steps = (to + pattern.len() - from - 1) % pattern.len() + 1;
+        let cycle = pattern.iter().cycle().skip(from).take(steps);
+        cycle.map(|step| step.duration).sum()
+    };
+    (0..rising.len())
+        .map(|i| since(rising[i], rising[(i + 1) % rising.len()]))
+        .collect()
+}
+
+impl Default for PetConfig {
+    fn default() -> Self {
+        Self {
+            pattern: vec![
+                PetStep {
+                    high: true,
+                    duration: Duration::from_millis(100),
+                },
+                PetStep {
+                    high: false,
+                    duration: Duration::from_millis(900),
+                },
+            ],
+            busy: BusyPolicy::Fail,
+            busy_timeout: Duration::from_secs(5),
+            pwm: None,
+            mirror: None,
+            sysfs_gpio: None,
+            align: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PetStep {
+    pub high: bool,
+    /// Until the next step
+    #[serde(deserialize_with = "seconds")]
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PanicLine {
+    /// Stop feeding the supervisor, so that it resets the card, without systemd restarting the
+    /// watchdog
+    SafeLow,
+    /// Leave the line where it is, for systemd to restart the watchdog before the supervisor bites
+    LeaveAsIs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BusyPolicy {
+    /// Exit, naming the holder
+    Fail,
+    /// Retry until whoever holds it lets go
+    Wait,
+    /// Only wait for a previous instance, retrying the handover until it listens
+    Takeover,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BiteAction {
+    /// Stop petting and exit
+    Stop,
+    /// Stop petting, holding the line at `level` until the supervisor resets the card
+    Hold,
+    /// Stop petting and pulse the reset request line
+    Pulse,
+    /// Ask systemd for a clean reboot, petting on, and stop petting if it doesn't come in time
+    Reboot,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BiteConfig {
+    pub action: BiteAction,
+    /// The level the line is held at, high if set
+    pub level: bool,
+    /// Seconds the reset request line is asserted for
+    #[serde(deserialize_with = "seconds")]
+    pub pulse: Duration,
+    /// Seconds the system has to go down in after asking for a reboot
+    #[serde(deserialize_with = "seconds")]
+    pub reboot_timeout: Duration,
+}
+
+impl Default for BiteConfig {
+    fn default() -> Self {
+        Self {
+            action: BiteAction::Stop,
+            level: false,
+            pulse: Duration::from_millis(100),
+            reboot_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Pets later than scheduled, e.g. from scheduler pressure or storage stalls
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LatePetConfig {
+    /// Later pets are counted and logged
+    #[serde(deserialize_with = "seconds")]
+    pub threshold: Duration,
+    /// An alarm is raised while the smoothed lateness is over this
+    #[serde(deserialize_with = "seconds")]
+    pub alarm: Duration,
+    /// Timers read later than this past their expiry are counted and logged
+    #[serde(deserialize_with = "seconds")]
+    pub wakeup: Duration,
+    /// Where to send a CANopen EMCY when the alarm is raised or cleared
+    pub can: Option<CanConfig>,
+}
+
+impl Default for LatePetConfig {
+    fn default() -> Self {
+        Self {
+            threshold: Duration::from_millis(50),
+            alarm: Duration::from_millis(500),
+            wakeup: Duration::from_millis(10),
+            can: None,
+        }
+    }
+}
+
+/// Scheduling options so that petting isn't starved by bulk work
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RealtimeConfig {
+    /// SCHED_FIFO priority, 1 to 99. Without it the default scheduler is used.
+    pub priority: Option<i32>,
+    /// Keeps all memory resident so that page faults can't delay a pet, on in release builds
+    pub lock_memory: bool,
+    /// CPUs to keep the event loop on, e.g. away from the radio DSP work. Empty allows all.
+    pub cpus: Vec<usize>,
+}
+
+impl Default for RealtimeConfig {
+    fn default() -> Self {
+        Self {
+            priority: None,
+            lock_memory: !cfg!(debug_assertions),
+            cpus: Vec::new(),
+        }
+    }
+}
+
+/// Who to run as once the GPIO line and sockets are open
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrivilegesConfig {
+    /// Unprivileged user to switch to. Without it the watchdog keeps running as it was started.
+    pub user: Option<String>,
+    /// Group to switch to, the user's primary group by default
+    pub group: Option<String>,
+}
+
+fn default_ping_timeout() -> Duration {
+    crate::pingee::PING_TIMEOUT
+}
+
+fn default_interlock_window() -> Duration {
+    Duration::from_secs(1800)
+}
+
+/// Durations are written as (possibly fractional) seconds
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let secs = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnitConfig {
+    /// Full unit name, e.g. "oresat-c3.service"
+    pub name: String,
+    pub policy: UnitPolicy,
+    /// The client fed by the ping policy, required if clients are configured
+    pub client: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimerClock {
+    /// Stops while suspended, so a client that was suspended along with the card isn't late
+    Monotonic,
+    /// Keeps counting while suspended
+    Boottime,
+    /// Like boottime, and also wakes the card from suspend to time out. Needs CAP_WAKE_ALARM,
+    /// falls back to boottime without it.
+    BoottimeAlarm,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnitPolicy {
+    /// The unit being active counts as a ping
+    Ping,
+    /// The unit entering the failed state is taken like a ping timeout
+    Fail,
+    /// Both of the above
+    PingAndFail,
+}
+
+impl UnitPolicy {
+    pub fn pings(self) -> bool {
+        matches!(self, UnitPolicy::Ping | UnitPolicy::PingAndFail)
+    }
+
+    pub fn fails(self) -> bool {
+        matches!(self, UnitPolicy::Fail | UnitPolicy::PingAndFail)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessConfig {
+    /// Only used in logs
+    pub name: String,
+    /// Exactly one of pid or pid_file must be given
+    pub pid: Option<i32>,
+    pub pid_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CgroupConfig {
+    /// Only used in logs
+    pub name: String,
+    /// Its directory, e.g. /sys/fs/cgroup/flight.slice
+    pub path: PathBuf,
+}
+
+/// A watchdog line with its own ping channel and clients. Missed pings only stop that line, the
+/// auth key, allowed sources, and arming are shared with the main one.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LineConfig {
+    pub name: String,
+    pub chip: String,
+    /// Checked against the line's name, like the main line's
+    pub label: String,
+    pub line: u32,
+    /// Where the line's pings are received
+    #[serde(deserialize_with = "socket_address")]
+    pub address: SocketAddr,
+    /// If there are none any packet to the address is a ping
+    #[serde(rename = "client", default)]
+    pub clients: Vec<ClientConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PeerConfig {
+    pub name: String,
+    /// Where its UDP heartbeats arrive, any datagram being one
+    pub address: Option<SocketAddr>,
+    /// Its CAN heartbeat, any frame with the id being one
+    pub can: Option<CanConfig>,
+    /// Seconds of silence before it's power cycled
+    #[serde(default = "default_ping_timeout", deserialize_with = "seconds")]
+    pub timeout: Duration,
+    /// Seconds it gets to boot, on starting and after each power cycle, before the timeout runs
+    #[serde(default = "default_peer_boot", deserialize_with = "seconds")]
+    pub boot: Duration,
+    /// The line that cuts its power while asserted
+    pub chip: String,
+    /// Checked against the line's name, like the main line's
+    pub label: String,
+    pub line: u32,
+    /// Asserted by pulling it low rather than high
+    #[serde(default)]
+    pub active_low: bool,
+    /// Seconds the line is held asserted for a power cycle
+    #[serde(default = "default_peer_pulse", deserialize_with = "seconds")]
+    pub pulse: Duration,
+}
+
+fn default_peer_boot() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_peer_pulse() -> Duration {
+    Duration::from_secs(1)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResetRequestConfig {
+    pub chip: String,
+    /// Checked against the line's name, like the main line's
+    pub label: String,
+    pub line: u32,
+    /// Asserted by pulling it low rather than high
+    #[serde(default)]
+    pub active_low: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SupervisorConfig {
+    pub chip: String,
+    /// Checked against the line's name, like the main line's
+    pub label: String,
+    pub line: u32,
+    /// Asserted when low rather than high
+    #[serde(default)]
+    pub active_low: bool,
+    /// How long after an assertion the status reports it as recent
+    #[serde(default = "default_supervisor_recent", deserialize_with = "seconds")]
+    pub recent: Duration,
+    /// Left as it is if unset
+    pub bias: Option<Bias>,
+    /// Edges closer together than this are filtered by the kernel, zero for none
+    #[serde(default, deserialize_with = "seconds")]
+    pub debounce: Duration,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KernelWatchdogConfig {
+    /// The device, e.g. /dev/watchdog1 for the softdog next to omap_wdt
+    pub path: PathBuf,
+    /// Whole seconds set on opening, zero leaves the driver's
+    #[serde(default, deserialize_with = "seconds")]
+    pub timeout: Duration,
+}
+
+/// Timing limits from the supervisor's datasheet, so that a config it can't take is refused at
+/// startup rather than found out by a reset
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HardwareConfig {
+    /// Feeding faster than this counts as a fault, for window watchdogs
+    #[serde(default, deserialize_with = "seconds")]
+    pub min_feed: Duration,
+    /// The supervisor bites once this passes without a feed
+    #[serde(deserialize_with = "seconds")]
+    pub max_feed: Duration,
+    /// Shorter high or low levels may not register
+    #[serde(default, deserialize_with = "seconds")]
+    pub min_pulse: Duration,
+}
+
+/// The mode the EPS falls back to when power is critical, entered and left over CAN or with POWER
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LowPowerConfig {
+    /// Petted through instead of the pet pattern, from the next cycle on
+    pub pattern: Vec<PetStep>,
+    /// Client timeouts are multiplied by this meanwhile
+    #[serde(default = "default_timeout_scale")]
+    pub timeout_scale: f64,
+    /// The EPS's power mode frames, whose first payload byte is nonzero while power is critical
+    pub can: Option<CanConfig>,
+}
+
+fn default_timeout_scale() -> f64 {
+    2.0
+}
+
+/// Pings aren't enforced and deliberate resets wait while in maintenance, which is always over
+/// after `max` at the latest
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaintenanceConfig {
+    /// The longest MAINTENANCE accepted, after which it's armed again
+    #[serde(deserialize_with = "seconds")]
+    pub max: Duration,
+    /// Petted through instead meanwhile, from the next cycle on. Empty keeps the pattern.
+    #[serde(default)]
+    pub pattern: Vec<PetStep>,
+}
+
+/// A mission mode, e.g. launch or safe-mode, switched to with PROFILE or by the mission state
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub name: String,
+    /// Petted through instead of the pet pattern, from the next cycle on. Empty keeps that one.
+    #[serde(default)]
+    pub pattern: Vec<PetStep>,
+    /// Every configured client's timeout meanwhile. Zero leaves them.
+    #[serde(default, deserialize_with = "seconds")]
+    pub timeout: Duration,
+    /// Whether the uptime and schedule resets go ahead, rather than waiting for a profile that
+    /// allows them
+    #[serde(default = "default_scheduled_resets")]
+    pub scheduled_resets: bool,
+    /// The first payload byte of the `profile_can` frames that switches to it
+    pub state: Option<u8>,
+}
+
+fn default_scheduled_resets() -> bool {
+    true
+}
+
+/// What an input line is pulled to while nothing drives it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Bias {
+    PullUp,
+    PullDown,
+    Disabled,
+}
+
+fn default_supervisor_recent() -> Duration {
+    Duration::from_secs(600)
+}
+
+fn socket_address<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SocketAddr, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    net::parse_address(&text).map_err(serde::de::Error::custom)
+}
+
+fn policy<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Policy>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    text.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+/// Cycled through as is, so every step has to be an edge
+fn validate_pattern(pattern: &[PetStep]) -> Result<()> {
+    ensure!(
+        (2..=MAX_PET_STEPS).contains(&pattern.len()),
+        "A pet pattern needs 2 to {} steps",
+        MAX_PET_STEPS
+    );
+    ensure!(
+        pattern.iter().all(|step| !step.duration.is_zero()),
+        "Pet pattern steps must be positive"
+    );
+    // Including from the last step back to the first, otherwise two steps are really one
+    ensure!(
+        (0..pattern.len()).all(|i| pattern[i].high != pattern[(i + 1) % pattern.len()].high),
+        "Pet pattern levels must alternate"
+    );
+    Ok(())
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self> {
+        let config: Self = toml::from_str(text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        ensure!(
+            self.clients.len() + self.ports.len() < MAX_CLIENTS,
+            "At most {} clients and ports are supported",
+            MAX_CLIENTS - 1
+        );
+        ensure!(
+            self.ports.len() <= MAX_PORTS,
+            "At most {} ports are supported",
+            MAX_PORTS
+        );
+        ensure!(
+            self.retry.threshold > 0,
+            "The retry threshold must be at least 1"
+        );
+        ensure!(self.strikes.max > 0, "At least one strike has to be fatal");
+        ensure!(
+            self.interlock.as_ref().is_none_or(|i| !i.window.is_zero()),
+            "The interlock window must be positive"
+        );
+        for gate in &self.gates {
+            let kinds = gate.can.is_some() as u8 + gate.unit.is_some() as u8 + gate.time_sync as u8;
+            ensure!(
+                kinds == 1,
+                "A gate waits for exactly one of can, unit, and time_sync"
+            );
+            ensure!(
+                !gate.timeout.is_zero(),
+                "The {} gate's timeout must be positive",
+                gate.name()
+            );
+        }
+        ensure!(self.drain_budget > 0, "The drain budget must be positive");
+        ensure!(
+            i32::try_from(self.recv_buffer).is_ok(),
+            "The receive buffer must fit in an int"
+        );
+        ensure!(
+            !self.health_window.is_zero(),
+            "The health window must be positive"
+        );
+        ensure!(
+            !self.counters_interval.is_zero(),
+            "The counters interval must be positive"
+        );
+        ensure!(
+            !self.status_file_interval.is_zero(),
+            "The status file interval must be positive"
+        );
+        ensure!(
+            !self.schedule.windows.is_empty() || !self.schedule.required,
+            "Required resets need windows"
+        );
+        ensure!(
+            self.battery.scale > 0.0,
+            "The battery scale has to be positive"
+        );
+        ensure!(
+            !self.battery.interval.is_zero(),
+            "The battery check interval can't be zero"
+        );
+        ensure!(
+            !self.schedule.duration.is_zero(),
+            "Reset windows have to stay open"
+        );
+        let rules = [
+            ("reset", &self.rules.reset, rule::RESET),
+            ("inhibit", &self.rules.inhibit, rule::INHIBIT),
+        ];
+        for (name, rule, known) in rules {
+            for variable in rule.iter().flat_map(Rule::variables) {
+                ensure!(
+                    known.contains(&variable),
+                    "The {} rule names unknown variable {:?}",
+                    name,
+                    variable
+                );
+            }
+        }
+        ensure!(
+            !self.rules.interval.is_zero(),
+            "The rule check interval can't be zero"
+        );
+        ensure!(
+            !self.registration.max_timeout.is_zero(),
+            "The registration max_timeout must be positive"
+        );
+        ensure!(
+            !self.budget.period.is_zero(),
+            "The budget period can't be zero"
+        );
+        ensure!(
+            !self.inhibit.startup.is_zero(),
+            "The startup inhibit must be positive"
+        );
+        let mut names = HashSet::new();
+        let channels = self.clients.iter().map(|c| &c.name);
+        for name in channels.chain(self.ports.iter().map(|p| &p.name)) {
+            ensure!(
+                !name.is_empty() && !name.contains(char::is_whitespace),
+                "Invalid client name {:?}",
+                name
+            );
+            ensure!(names.insert(name.as_str()), "Duplicate client {:?}", name);
+        }
+        // A zero timeout would disarm the timer rather than bite right away
+        let timeouts = self.clients.iter().map(|c| (&c.name, c.timeout));
+        for (name, timeout) in timeouts.chain(self.ports.iter().map(|p| (&p.name, p.timeout))) {
+            ensure!(
+                !timeout.is_zero(),
+                "Client {:?} needs a positive timeout",
+                name
+            );
+        }
+        for (name, commands) in &self.auth.permissions {
+            for command in commands {
+                ensure!(
+                    protocol::KEYWORDS.contains(&command.as_str()),
+                    "Unknown command {:?} permitted to {}",
+                    command,
+                    name
+                );
+            }
+        }
+        for multicast in &self.multicast_groups {
+            ensure!(
+                multicast.group.is_multicast(),
+                "{} is not a multicast group",
+                multicast.group
+            );
+            ensure!(
+                multicast.group.is_ipv4() == self.address.is_ipv4(),
+                "Multicast group {} doesn't match the address family of {}",
+                multicast.group,
+                self.address
+            );
+        }
+        let mut ports = HashSet::new();
+        for port in &self.ports {
+            ensure!(ports.insert(port.port), "Duplicate port {}", port.port);
+        }
+        for client in self.policy.iter().flat_map(Policy::clients) {
+            ensure!(
+                names.contains(client),
+                "The policy names unknown client {:?}",
+                client
+            );
+        }
+        for unit in self.units.iter().filter(|u| u.policy.pings()) {
+            match &unit.client {
+                Some(name) => ensure!(
+                    names.contains(name.as_str()),
+                    "Unit {} feeds unknown client {:?}",
+                    unit.name,
+                    name
+                ),
+                None => ensure!(
+                    names.is_empty(),
+                    "Unit {} needs a client to feed",
+                    unit.name
+                ),
+            }
+        }
+        if self.legacy.enabled {
+            match &self.legacy.client {
+                Some(name) => ensure!(
+                    names.contains(name.as_str()),
+                    "Legacy pings feed unknown client {:?}",
+                    name
+                ),
+                None => ensure!(names.is_empty(), "Legacy pings need a client to feed"),
+            }
+        }
+        ensure!(
+            self.can_pings.len() <= MAX_CAN_PINGS,
+            "At most {} CAN pings are supported",
+            MAX_CAN_PINGS
+        );
+        for ping in &self.can_pings {
+            ensure!(
+                names.contains(ping.client.as_str()),
+                "CAN id {:#x} feeds unknown client {:?}",
+                ping.id,
+                ping.client
+            );
+            ensure!(ping.id <= CAN_EFF_MASK, "Invalid CAN id {:#x}", ping.id);
+            ensure!(ping.payload.len() <= 8, "CAN payload too long");
+        }
+        for touch in &self.touches {
+            ensure!(
+                names.contains(touch.client.as_str()),
+                "Touch file {:?} feeds unknown client {:?}",
+                touch.path,
+                touch.client
+            );
+            ensure!(
+                touch.path.is_absolute() && touch.path.file_name().is_some(),
+                "Touch file {:?} needs an absolute path",
+                touch.path
+            );
+            ensure!(
+                !touch.max_age.is_zero(),
+                "Touch file {:?} needs a positive maximum age",
+                touch.path
+            );
+        }
+        for shm in &self.shm {
+            ensure!(
+                names.contains(shm.client.as_str()),
+                "Shared memory {:?} feeds unknown client {:?}",
+                shm.path,
+                shm.client
+            );
+            ensure!(
+                shm.path.is_absolute(),
+                "Shared memory {:?} needs an absolute path",
+                shm.path
+            );
+        }
+        for eventfd in &self.eventfds {
+            ensure!(
+                names.contains(eventfd.client.as_str()),
+                "Eventfd socket {:?} feeds unknown client {:?}",
+                eventfd.path,
+                eventfd.client
+            );
+            ensure!(
+                eventfd.path.is_absolute(),
+                "Eventfd socket {:?} needs an absolute path",
+                eventfd.path
+            );
+        }
+        ensure!(
+            !self.death_row.interval.is_zero(),
+            "Death row interval must be positive"
+        );
+        if let Some(ladder) = &self.ladder {
+            for rung in [ladder.notice, ladder.emcy, ladder.hook] {
+                ensure!(
+                    (0.0..1.0).contains(&rung),
+                    "Ladder rung {} must be from 0 to under 1",
+                    rung
+                );
+            }
+        }
+        validate_pattern(&self.pet.pattern)?;
+        let mut profiles = HashSet::new();
+        let mut states = HashSet::new();
+        for profile in &self.profiles {
+            let name = &profile.name;
+            ensure!(
+                !name.is_empty() && !name.contains(char::is_whitespace),
+                "Invalid profile name {:?}",
+                name
+            );
+            ensure!(profiles.insert(name), "Duplicate profile {:?}", name);
+            if !profile.pattern.is_empty() {
+                validate_pattern(&profile.pattern)
+                    .with_context(|| format!("Invalid pattern of profile {}", name))?;
+            }
+            if let Some(state) = profile.state {
+                ensure!(states.insert(state), "Duplicate profile state {}", state);
+            }
+        }
+        ensure!(
+            self.profile_can.is_none() || !states.is_empty(),
+            "Profile CAN frames need profiles with a state"
+        );
+        if let Some(low_power) = &self.low_power {
+            validate_pattern(&low_power.pattern).context("Invalid low power pattern")?;
+            ensure!(
+                low_power.timeout_scale >= 1.0,
+                "Low power timeouts can't be shorter than the usual ones"
+            );
+        }
+        if let Some(maintenance) = &self.maintenance {
+            ensure!(
+                !maintenance.max.is_zero(),
+                "The maintenance maximum must be positive"
+            );
+            if !maintenance.pattern.is_empty() {
+                validate_pattern(&maintenance.pattern).context("Invalid maintenance pattern")?;
+            }
+        }
+        ensure!(
+            self.pet.busy != BusyPolicy::Takeover || self.handover.is_some(),
+            "Taking over the watchdog line needs a handover socket"
+        );
+        ensure!(
+            self.pet.pwm.is_none() || self.pet.sysfs_gpio.is_none(),
+            "The pets go either to a PWM channel or to a sysfs GPIO"
+        );
+        ensure!(
+            self.pet.sysfs_gpio.is_none() || self.handover.is_none(),
+            "A sysfs GPIO can't be handed over"
+        );
+        if self.pet.pwm.is_some() {
+            ensure!(
+                self.pet.pattern.len() == 2 && self.pet.pattern[0].high,
+                "A PWM pet pattern is one high step and one low step"
+            );
+            ensure!(
+                self.low_power.is_none(),
+                "The low power pattern can't be switched to on a PWM channel"
+            );
+            ensure!(
+                self.profiles.iter().all(|p| p.pattern.is_empty()),
+                "Profile patterns can't be switched to on a PWM channel"
+            );
+            ensure!(
+                self.maintenance.iter().all(|m| m.pattern.is_empty()),
+                "The maintenance pattern can't be switched to on a PWM channel"
+            );
+            ensure!(
+                self.handover.is_none(),
+                "A PWM channel can't be handed over"
+            );
+        }
+        if let Some(align) = &self.pet.align {
+            ensure!(
+                self.pet.pwm.is_none(),
+                "A PWM channel's cycles can't be aligned"
+            );
+            for pattern in self.patterns() {
+                let period: Duration = pattern.iter().map(|step| step.duration).sum();
+                ensure!(
+                    period.subsec_nanos() == 0 || 1_000_000_000 % period.as_nanos() == 0,
+                    "Aligned cycles must divide a second or last whole seconds, not {:?}",
+                    period
+                );
+                ensure!(
+                    align.offset < period,
+                    "The alignment offset must be shorter than the {:?} cycles",
+                    period
+                );
+            }
+        }
+        if let Some(hardware) = &self.hardware {
+            self.validate_hardware(hardware)?;
+        }
+        let low_power = self.low_power.iter().flat_map(|l| &l.pattern);
+        let profiles = self.profiles.iter().flat_map(|p| &p.pattern);
+        let maintenance = self.maintenance.iter().flat_map(|m| &m.pattern);
+        let longest = self.pet.pattern.iter().chain(low_power).chain(profiles);
+        let longest = longest.chain(maintenance);
+        let longest = longest.map(|step| step.duration).max().unwrap_or_default();
+        if let Some(kernel) = &self.kernel_watchdog {
+            ensure!(
+                kernel.timeout.subsec_nanos() == 0,
+                "The kernel watchdog timeout is in whole seconds"
+            );
+            // Fed on every step of the pattern
+            ensure!(
+                kernel.timeout.is_zero() || kernel.timeout > longest,
+                "The kernel watchdog would bite between pets {:?} apart",
+                longest
+            );
+        }
+        // The loop goes round at least on every step of the pattern
+        ensure!(
+            self.stall.is_zero() || self.stall > longest,
+            "The stall bound would be hit between pets {:?} apart",
+            longest
+        );
+        ensure!(
+            self.heartbeat
+                .as_ref()
+                .is_none_or(|h| !h.interval.is_zero()),
+            "Heartbeat interval must be positive"
+        );
+        ensure!(
+            self.standby.as_ref().is_none_or(|s| !s.silence.is_zero()),
+            "Standby silence must be positive"
+        );
+        if let Some(cadence) = &self.cadence {
+            ensure!(
+                !cadence.interval.is_zero(),
+                "Cadence interval must be positive"
+            );
+            ensure!(
+                cadence.quantile > 0.0 && cadence.quantile <= 1.0,
+                "Invalid cadence quantile {}",
+                cadence.quantile
+            );
+            ensure!(
+                cadence.margin >= 1.0,
+                "A cadence margin under 1 would recommend timeouts that are already missed"
+            );
+            ensure!(
+                (1..=MAX_CADENCE_SAMPLES).contains(&cadence.min_samples),
+                "Cadence needs 1 to {} samples",
+                MAX_CADENCE_SAMPLES
+            );
+        }
+        ensure!(
+            self.time_anomalies
+                .as_ref()
+                .is_none_or(|t| !t.interval.is_zero()),
+            "Time anomaly interval must be positive"
+        );
+        if let Some(capture) = &self.capture {
+            ensure!(
+                capture.files >= 1,
+                "Capture needs at least one rotated file"
+            );
+            ensure!(
+                capture.snaplen <= u16::MAX.into(),
+                "Capture snaplen {} is too long",
+                capture.snaplen
+            );
+            ensure!(
+                capture.max_size >= 4096,
+                "Capture max_size has to be at least 4096 bytes"
+            );
+        }
+        ensure!(
+            self.logbook.as_ref().is_none_or(|l| l.entries >= 1),
+            "Logbook needs at least one entry"
+        );
+        ensure!(
+            self.telemetry
+                .as_ref()
+                .is_none_or(|t| !t.targets.is_empty()),
+            "Telemetry needs a target"
+        );
+        if let Some(mqtt) = &self.mqtt {
+            ensure!(cfg!(feature = "mqtt"), "Built without MQTT support");
+            ensure!(
+                !mqtt.topic.is_empty() && !mqtt.topic.contains(['+', '#']),
+                "Invalid MQTT topic {:?}",
+                mqtt.topic
+            );
+            ensure!(
+                !mqtt.interval.is_zero(),
+                "MQTT status interval must be positive"
+            );
+            ensure!(
+                (1..=u64::from(u16::MAX)).contains(&mqtt.keep_alive.as_secs()),
+                "MQTT keep alive has to be 1 to {} seconds",
+                u16::MAX
+            );
+        }
+        if let Some(log) = &self.log {
+            ensure!(log.files >= 1, "Log needs at least one rotated file");
+            ensure!(
+                log.max_size >= 4096,
+                "Log max_size has to be at least 4096 bytes"
+            );
+        }
+        ensure!(
+            self.margin.as_ref().is_none_or(|m| !m.interval.is_zero()),
+            "Margin report interval must be positive"
+        );
+        if let Some(priority) = self.realtime.priority {
+            ensure!(
+                (1..=99).contains(&priority),
+                "SCHED_FIFO priority {} is out of range",
+                priority
+            );
+        }
+        for &cpu in &self.realtime.cpus {
+            ensure!(cpu < CPU_SETSIZE, "Invalid CPU {}", cpu);
+        }
+        ensure!(
+            self.privileges.group.is_none() || self.privileges.user.is_some(),
+            "A privileges group needs a user"
+        );
+        // The hook would inherit the filter
+        ensure!(
+            !self.seccomp || self.hook.command.is_empty(),
+            "The seccomp filter can't be used with a hook"
+        );
+        ensure!(
+            self.lines.len() <= MAX_LINES,
+            "At most {} lines are supported",
+            MAX_LINES
+        );
+        let mut lines = HashSet::new();
+        for line in &self.lines {
+            ensure!(lines.insert(&line.name), "Duplicate line {:?}", line.name);
+            ensure!(
+                line.clients.len() < MAX_CLIENTS,
+                "At most {} clients per line are supported",
+                MAX_CLIENTS - 1
+            );
+            let mut names = HashSet::new();
+            for client in &line.clients {
+                let name = &client.name;
+                ensure!(
+                    !name.is_empty() && !name.contains(char::is_whitespace),
+                    "Invalid client name {:?}",
+                    name
+                );
+                ensure!(names.insert(name), "Duplicate client {:?}", name);
+                ensure!(
+                    !client.timeout.is_zero(),
+                    "Client {:?} needs a positive timeout",
+                    name
+                );
+            }
+        }
+        // Neither knows about the other lines
+        ensure!(
+            self.lines.is_empty() || self.handover.is_none() && !self.seccomp,
+            "Lines can't be handed over or used with the seccomp filter"
+        );
+        ensure!(
+            self.reset_request.is_none() || self.handover.is_none() && !self.seccomp,
+            "The reset request line can't be handed over or used with the seccomp filter"
+        );
+        let mut peers = HashSet::new();
+        for peer in &self.peers {
+            ensure!(peers.insert(&peer.name), "Duplicate peer {:?}", peer.name);
+            ensure!(
+                peer.address.is_some() || peer.can.is_some(),
+                "The {} peer needs an address or a CAN id to hear its heartbeats on",
+                peer.name
+            );
+            if let Some(can) = &peer.can {
+                ensure!(can.id <= CAN_EFF_MASK, "Invalid CAN id {:#x}", can.id);
+            }
+            ensure!(
+                !peer.timeout.is_zero() && !peer.pulse.is_zero(),
+                "The {} peer's timeout and pulse must be positive",
+                peer.name
+            );
+        }
+        ensure!(
+            self.peers.is_empty() || self.handover.is_none() && !self.seccomp,
+            "Peer lines can't be handed over or used with the seccomp filter"
+        );
+        match self.bite.action {
+            BiteAction::Pulse => ensure!(
+                self.reset_request.is_some(),
+                "Pulsing on a bite needs the reset request line"
+            ),
+            BiteAction::Reboot => ensure!(
+                !self.seccomp,
+                "Rebooting on a bite can't be used with the seccomp filter"
+            ),
+            BiteAction::Stop | BiteAction::Hold => (),
+        }
+        ensure!(
+            self.pet.mirror.is_none() || self.handover.is_none(),
+            "The pet mirror line can't be handed over"
+        );
+        if let Some(supervisor) = &self.supervisor {
+            ensure!(
+                self.handover.is_none(),
+                "The supervisor line can't be handed over"
+            );
+            ensure!(
+                u32::try_from(supervisor.debounce.as_micros()).is_ok(),
+                "Supervisor line debounce of {:?} is too long",
+                supervisor.debounce
+            );
+        }
+        let heartbeat = self.heartbeat.as_ref().and_then(|h| h.can.as_ref());
+        let margin = self.margin.as_ref().and_then(|m| m.can.as_ref());
+        for can in [
+            self.death_row.can.as_ref(),
+            self.ladder.as_ref().and_then(|l| l.can.as_ref()),
+            self.late_pet.can.as_ref(),
+            heartbeat,
+            margin,
+            self.low_power.as_ref().and_then(|l| l.can.as_ref()),
+            self.profile_can.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            ensure!(can.id <= CAN_EFF_MASK, "Invalid CAN id {:#x}", can.id);
+        }
+        if let Some(canopen) = &self.canopen {
+            ensure!(
+                (1..=127).contains(&canopen.node_id),
+                "Invalid CANopen node id {}",
+                canopen.node_id
+            );
+            if let Some(c3) = canopen.c3_node_id {
+                ensure!((1..=127).contains(&c3), "Invalid CANopen node id {}", c3);
+            }
+            // SDO downloads can disarm and inhibit without authentication
+            ensure!(
+                !canopen.writable || !self.inhibit.auth && self.auth.permissions.is_empty(),
+                "Writable SDOs aren't authenticated, so they need inhibit.auth = false and no \
+                 permissions"
+            );
+        }
+        Ok(())
+    }
+
+    /// Every pattern that can be petted through, the pet pattern first
+    fn patterns(&self) -> impl Iterator<Item = &[PetStep]> {
+        let low_power = self.low_power.iter().map(|l| l.pattern.as_slice());
+        let profiles = self.profiles.iter().map(|p| p.pattern.as_slice());
+        let maintenance = self.maintenance.iter().map(|m| m.pattern.as_slice());
+        let patterns = low_power.chain(profiles).chain(maintenance);
+        let patterns = patterns.filter(|p| !p.is_empty());
+        std::iter::once(self.pet.pattern.as_slice()).chain(patterns)
+    }
+
+    /// Against patterns that are already known to be valid
+    fn validate_hardware(&self, hardware: &HardwareConfig) -> Result<()> {
+        ensure!(
+            hardware.min_feed < hardware.max_feed,
+            "The supervisor's feed window is empty"
+        );
+        for pattern in self.patterns() {
+            // Aligning moves the last step by up to a tenth of it either way
+            let slack = match self.pet.align {
+                Some(_) => pattern[pattern.len() - 1].duration / 10,
+                None => Duration::ZERO,
+            };
+            for feed in feeds(pattern) {
+                ensure!(
+                    (hardware.min_feed + slack..=hardware.max_feed.saturating_sub(slack))
+                        .contains(&feed),
+                    "Feeding every {:?} is outside the supervisor's {:?} to {:?}",
+                    feed,
+                    hardware.min_feed,
+                    hardware.max_feed
+                );
+            }
+            for step in pattern {
+                ensure!(
+                    step.duration >= hardware.min_pulse + slack,
+                    "A {:?} pulse is shorter than the supervisor's minimum of {:?}",
+                    step.duration,
+                    hardware.min_pulse
+                );
+            }
+        }
+        // The supervisor only bites max_feed after the last pet, so anything shorter would
+        // promise a reset sooner than it can come
+        let timeouts = self.clients.iter().map(|c| (c.name.as_str(), c.timeout));
+        let timeouts = timeouts.chain(self.ports.iter().map(|p| (p.name.as_str(), p.timeout)));
+        let profiles = self.profiles.iter().filter(|p| !p.timeout.is_zero());
+        let timeouts = timeouts.chain(profiles.map(|p| (p.name.as_str(), p.timeout)));
+        for (name, timeout) in timeouts {
+            ensure!(
+                timeout >= hardware.max_feed,
+                "The ping timeout of {} is shorter than the supervisor's {:?}",
+                name,
+                hardware.max_feed
+            );
+        }
+        let min_timeout = self
+            .cadence
+            .as_ref()
+            .map_or(Duration::ZERO, |c| c.min_timeout);
+        ensure!(
+            min_timeout.is_zero() || min_timeout >= hardware.max_feed,
+            "The minimum cadence timeout is shorter than the supervisor's {:?}",
+            hardware.max_feed
+        );
+        ensure!(
+            self.inhibit.startup >= hardware.max_feed,
+            "The startup inhibit is shorter than the supervisor's {:?}",
+            hardware.max_feed
+        );
+        ensure!(
+            !self.inhibit.enabled || self.inhibit.max >= hardware.max_feed,
+            "The longest inhibit is shorter than the supervisor's {:?}",
+            hardware.max_feed
+        );
+        Ok(())
+    }
+
+    /// Loads the config at `path`. A missing file is only an error if the path was explicitly
+    /// requested, otherwise the defaults are used.
+    pub fn load(path: &Path, explicit: bool) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).with_context(|| format!("Invalid config {:?}", path)),
+            Err(e) if e.kind() == ErrorKind::NotFound && !explicit => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read config {:?}", path)),
+        }
+    }
+
+    /// Like `load`, but a config that can't be loaded, e.g. for a bitflip on the SD card, falls
+    /// back to the compiled-in defaults rather than leaving the card unprotected. Those start
+    /// disarmed, as which clients the config named and where they ping is anyone's guess, keeping
+    /// the keys so that it can still be armed.
+    pub fn load_or_safe(path: &Path, explicit: bool) -> Self {
+        match Self::load(path, explicit) {
+            Ok(config) => config,
+            Err(e) => {
+                error!("{:#}", e);
+                error!("DEGRADED CONFIG: running on the safe defaults, disarmed until an ARM");
+                Self {
+                    armed: false,
+                    auth: salvage_auth(path, Path::new(DEFAULT_KEY_FILE)),
+                    degraded: Some(format!("{:#}", e)),
+                    ..Self::default()
+                }
+            }
+        }
+    }
+}
+
+/// Where the key file usually is, for a degraded config to fall back on
+const DEFAULT_KEY_FILE: &str = "/etc/oresat-c3-watchdog.key";
+
+/// The config's `[auth]` if that much of it still reads and its keys do, otherwise `key_file` if
+/// that reads, and no keys at all otherwise
+fn salvage_auth(path: &Path, key_file: &Path) -> AuthConfig {
+    let table = fs::read_to_string(path)
+        .ok()
+        .and_then(|text| text.parse::<toml::Table>().ok());
+    let auth = table
+        .and_then(|mut table| table.remove("auth"))
+        .and_then(|auth| auth.try_into::<AuthConfig>().ok());
+    let fallback = AuthConfig {
+        key_file: Some(key_file.to_path_buf()),
+        ..AuthConfig::default()
+    };
+    for auth in auth.into_iter().chain([fallback]) {
+        if Authenticator::new(&auth).is_ok() {
+            info!("Keeping the keys of {:?}", auth.key_file);
+            return auth;
+        }
+    }
+    warn!("No keys to keep, a degraded config can't be armed");
+    AuthConfig::default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template() -> Result<()> {
+        let config = Config::parse(TEMPLATE)?;
+        assert_eq!(format!("{:?}", config), format!("{:?}", Config::default()));
+        // Everything commented out is a real option too, though not all of them go together
+        let uncommented: Vec<_> = TEMPLATE
+            .lines()
+            .map(|line| match line.strip_prefix('#') {
+                Some(option) if !option.starts_with(' ') => option,
+                _ => line,
+            })
+            .collect();
+        toml::from_str::<Config>(&uncommented.join("\n"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_canopen() -> Result<()> {
+        let canopen = "[canopen]\ninterface = \"can0\"\nnode_id = 0x3c\nwritable = true\n";
+        assert!(Config::parse(canopen).is_err());
+        let config = Config::parse(&format!("{}[inhibit]\nauth = false\n", canopen))?;
+        assert!(config.canopen.unwrap().writable);
+        let permitted = "[inhibit]\nauth = false\n[auth.permissions]\nops = [\"STATUS\"]\n";
+        assert!(Config::parse(&format!("{}{}", canopen, permitted)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_or_safe() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("watchdog-config-{}", std::process::id()));
+        fs::write(&path, "armed = tru\n")?;
+        let config = Config::load_or_safe(&path, true);
+        fs::remove_file(&path)?;
+        assert!(!config.armed);
+        assert!(config.degraded.unwrap().starts_with("Invalid config"));
+        let config = Config::load_or_safe(&path, false);
+        assert!(config.armed && config.degraded.is_none());
+        assert!(Config::load_or_safe(&path, true).degraded.is_some());
+
+        // The keys are kept, so that it can be armed, see pingee's test_degraded
+        let key = path.with_extension("key");
+        fs::write(&key, "0123456789abcdef\n")?;
+        let text = format!(
+            "address = \"127.0.0.1:0\"\narmed = \"yes\"\n[auth]\nkey_file = {:?}\n",
+            key
+        );
+        fs::write(&path, text)?;
+        let config = Config::load_or_safe(&path, true);
+        assert!(config.degraded.is_some());
+        assert_eq!(config.auth.key_file.as_ref(), Some(&key));
+        // Past a config too broken to read the keys from, the usual key file
+        fs::write(&path, "[auth\n")?;
+        assert_eq!(salvage_auth(&path, &key).key_file, Some(key.clone()));
+        assert_eq!(
+            salvage_auth(&path, &path.with_extension("none")).key_file,
+            None
+        );
+        fs::remove_file(&path)?;
+        fs::remove_file(&key)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_units() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            [[unit]]
+            name = "oresat-c3.service"
+            policy = "ping-and-fail"
+
+            [[unit]]
+            name = "oresat-edl.service"
+            policy = "fail"
+            "#,
+        )?;
+        assert_eq!(config.units.len(), 2);
+        assert!(config.units[0].policy.pings() && config.units[0].policy.fails());
+        assert!(!config.units[1].policy.pings() && config.units[1].policy.fails());
+
+        assert!(Config::parse("[[unit]]\nname = \"a\"\npolicy = \"sometimes\"").is_err());
+        assert!(Config::parse("").is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_address() -> Result<()> {
+        assert_eq!(Config::default().address, DEFAULT_ADDRESS);
+        let config = Config::parse("address = \"[::1]:20001\"")?;
+        assert_eq!(config.address, "[::1]:20001".parse()?);
+
+        let config = Config::parse(
+            r#"
+            address = "0.0.0.0:20001"
+            allowed_sources = ["10.0.0.0/8", "127.0.0.1"]
+            [[multicast]]
+            group = "239.0.0.1"
+            interface = "lo"
+            "#,
+        )?;
+        assert_eq!(config.allowed_sources.len(), 2);
+        assert_eq!(
+            config.multicast_groups[0].group,
+            "239.0.0.1".parse::<IpAddr>()?
+        );
+
+        assert!(Config::parse("[[multicast]]\ngroup = \"10.0.0.1\"").is_err());
+        assert!(Config::parse("[[multicast]]\ngroup = \"ff02::1\"").is_err());
+        assert!(Config::parse("allowed_sources = [\"10.0.0.0/33\"]").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_clients() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            [[client]]
+            name = "c3-main"
+
+            [[client]]
+            name = "radio-bridge"
+            timeout = 2.5
+            criticality = "advisory"
+
+            [[unit]]
+            name = "oresat-c3.service"
+            policy = "ping"
+            client = "c3-main"
+            "#,
+        )?;
+        assert_eq!(config.clients[0].timeout, Duration::from_secs(30));
+        assert_eq!(config.clients[1].timeout, Duration::from_millis(2500));
+        assert_eq!(config.clients[0].criticality, Criticality::Critical);
+        assert_eq!(config.clients[1].criticality, Criticality::Advisory);
+
+        let dup = "[[client]]\nname = \"a\"\n[[client]]\nname = \"a\"";
+        assert!(Config::parse(dup).is_err());
+        let orphan = "[[client]]\nname = \"a\"\n[[unit]]\nname = \"u\"\npolicy = \"ping\"";
+        assert!(Config::parse(orphan).is_err());
+        assert!(Config::parse("[[client]]\nname = \"a\"\ntimeout = -1").is_err());
+        assert!(Config::parse("[[client]]\nname = \"a\"\ntimeout = 0").is_err());
+        assert!(Config::parse("[registration]\nenabled = true\nmax_timeout = 0").is_err());
+        let port = "[[port]]\nname = \"a\"\nport = 20002\ntimeout = 0";
+        assert!(Config::parse(port).is_err());
+
+        let port = "[[client]]\nname = \"a\"\n[[port]]\nname = \"a\"\nport = 20002";
+        assert!(Config::parse(port).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_lines() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            [[line]]
+            name = "battery"
+            chip = "gpiochip1"
+            label = "PET_BAT"
+            line = 12
+            address = "127.0.0.1:20010"
+            [[line.client]]
+            name = "battery"
+            timeout = 10
+            "#,
+        )?;
+        let line = &config.lines[0];
+        assert_eq!((line.chip.as_str(), line.line), ("gpiochip1", 12));
+        assert_eq!(line.clients[0].timeout, Duration::from_secs(10));
+
+        let line = "[[line]]\nname = \"a\"\nchip = \"c\"\nlabel = \"l\"\nline = 1\naddress = \"127.0.0.1:1\"\n";
+        assert!(Config::parse(&format!("{line}{line}")).is_err());
+        assert!(Config::parse(&format!("seccomp = true\n{line}")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_hardware() -> Result<()> {
+        let hardware = |limits: &str, rest: &str| {
+            Config::parse(&format!("{}\n[hardware]\nmax_feed = 1.6\n{}", rest, limits))
+        };
+        let pulses = "[pet]\npattern = [{ high = true, duration = 0.05 }, \
+            { high = false, duration = 0.05 }, { high = true, duration = 0.05 }, \
+            { high = false, duration = 1.35 }]";
+        assert_eq!(
+            Config::parse(pulses)?.pet.feeds(),
+            [Duration::from_millis(100), Duration::from_millis(1400)]
+        );
+        assert!(hardware("min_feed = 0.2", "").is_ok());
+        assert!(hardware("min_feed = 0.2", pulses).is_err());
+        assert!(hardware("min_pulse = 0.01", pulses).is_ok());
+        assert!(hardware("min_pulse = 0.1", pulses).is_err());
+        assert!(Config::parse("[hardware]\nmax_feed = 0.8").is_err());
+        assert!(hardware("", "[[client]]\nname = \"c3\"\ntimeout = 1").is_err());
+        assert!(hardware("", "[inhibit]\nstartup = 1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_supervisor() -> Result<()> {
+        let line = "[supervisor]\nchip = \"gpiochip2\"\nlabel = \"WDT_STATUS\"\nline = 27\n";
+        let config = Config::parse(&format!("{line}bias = \"pull-up\"\ndebounce = 0.005"))?;
+        let supervisor = config.supervisor.unwrap();
+        assert_eq!(supervisor.bias, Some(Bias::PullUp));
+        assert_eq!(supervisor.debounce, Duration::from_millis(5));
+        assert!(Config::parse(&format!("{line}bias = \"pull-sideways\"")).is_err());
+        assert!(Config::parse(&format!("{line}debounce = 5000")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_kernel_watchdog() -> Result<()> {
+        let kernel = |timeout: &str| {
+            Config::parse(&format!(
+                "[kernel_watchdog]\npath = \"/dev/watchdog1\"\n{}",
+                timeout
+            ))
+        };
+        assert_eq!(kernel("")?.kernel_watchdog.unwrap().timeout, Duration::ZERO);
+        assert!(kernel("timeout = 5").is_ok());
+        assert!(kernel("timeout = 5.5").is_err());
+        let slow =
+            "[pet]\npattern = [{ high = true, duration = 0.1 }, { high = false, duration = 2 }]";
+        assert!(Config::parse(&format!(
+            "{}\n[kernel_watchdog]\npath = \"/dev/watchdog1\"\ntimeout = 2",
+            slow
+        ))
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_pet() -> Result<()> {
+        assert_eq!(Config::default().pet.period(), Duration::from_secs(1));
+        let step = |high, duration| format!("{{ high = {}, duration = {} }}", high, duration);
+        let pattern =
+            |steps: &[String]| Config::parse(&format!("[pet]\npattern = [{}]", steps.join(", ")));
+        let pulses = pattern(&[
+            step(true, 0.05),
+            step(false, 0.05),
+            step(true, 0.05),
+            step(false, 0.7),
+        ])?;
+        assert_eq!(pulses.pet.period(), Duration::from_millis(850));
+
+        assert!(pattern(&[step(true, 0.1)]).is_err());
+        assert!(pattern(&[step(true, 0.1), step(false, 0.0)]).is_err());
+        assert!(pattern(&[step(true, 0.1), step(false, 0.1), step(true, 0.1)]).is_err());
+
+        let config = Config::parse("[pet]\nbusy = \"wait\"\nbusy_timeout = 30")?;
+        assert_eq!(config.pet.busy, BusyPolicy::Wait);
+        assert_eq!(config.pet.busy_timeout, Duration::from_secs(30));
+        assert!(Config::parse("[pet]\nbusy = \"takeover\"").is_err());
+        let takeover = "handover = \"/run/watchdog.sock\"\n[pet]\nbusy = \"takeover\"";
+        assert_eq!(Config::parse(takeover)?.pet.busy, BusyPolicy::Takeover);
+
+        let pwm = "[pet.pwm]\nchip = \"/sys/class/pwm/pwmchip0\"\nchannel = 1";
+        assert_eq!(Config::parse(pwm)?.pet.pwm.unwrap().channel, 1);
+        let pulses = "[pet]\npattern = [{ high = true, duration = 0.05 }, \
+                      { high = false, duration = 0.05 }, { high = true, duration = 0.05 }, \
+                      { high = false, duration = 0.85 }]\n";
+        assert!(Config::parse(&format!("{}{}", pulses, pwm)).is_err());
+        assert!(Config::parse(&format!("handover = \"/run/watchdog.sock\"\n{}", pwm)).is_err());
+        let sysfs = "[pet]\nsysfs_gpio = 505\n";
+        assert_eq!(Config::parse(sysfs)?.pet.sysfs_gpio, Some(505));
+        assert!(Config::parse(&format!("{}{}", sysfs, pwm)).is_err());
+
+        let align = "[pet.align]\noffset = 0.25\n";
+        let offset = Config::parse(align)?.pet.align.unwrap().offset;
+        assert_eq!(offset, Duration::from_millis(250));
+        assert!(Config::parse(&format!("{}{}", align, pwm)).is_err());
+        let uneven = "[pet]\npattern = [{ high = true, duration = 0.1 }, \
+                      { high = false, duration = 0.75 }]\n";
+        assert!(Config::parse(&format!("{}{}", uneven, align)).is_err());
+        assert!(Config::parse("[pet.align]\noffset = 1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_profiles() -> Result<()> {
+        let profiles = |rest: &str| {
+            Config::parse(&format!(
+                "[[profile]]\nname = \"launch\"\ntimeout = 120\nstate = 1\n{}",
+                rest
+            ))
+        };
+        let config = profiles("[[profile]]\nname = \"nominal\"")?;
+        assert!(config.profiles[1].scheduled_resets);
+        assert!(config.profiles[1].pattern.is_empty());
+        let can = "profile_can = { interface = \"can0\", id = 0x1b0 }";
+        assert!(Config::parse(&format!("{}\n[[profile]]\nname = \"launch\"", can)).is_err());
+        assert!(profiles("[[profile]]\nname = \"launch\"").is_err());
+        assert!(profiles("[[profile]]\nname = \"safe\"\nstate = 1").is_err());
+        assert!(profiles("[hardware]\nmax_feed = 150").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_rules() -> Result<()> {
+        let rules = |rest: &str| Config::parse(&format!("[rules]\n{}", rest));
+        let config = rules("reset = 'hour < 6'\ninhibit = 'duration <= 300'")?;
+        assert_eq!(config.rules.reset.unwrap().to_string(), "(hour < 6)");
+        // Each sees only its own
+        assert!(rules("reset = 'duration <= 300'").is_err());
+        assert!(rules("inhibit = 'cause == \"max_uptime\"'").is_err());
+        assert!(rules("reset = 'hour <'").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_low_power() -> Result<()> {
+        let low_power = |rest: &str| {
+            Config::parse(&format!(
+                "[low_power]\npattern = [{{ high = true, duration = 0.1 }}, \
+                 {{ high = false, duration = 2.9 }}]\n{}",
+                rest
+            ))
+        };
+        let config = low_power("can = { interface = \"can0\", id = 0x1a0 }")?;
+        let low_power_config = config.low_power.unwrap();
+        assert_eq!(low_power_config.timeout_scale, 2.0);
+        assert_eq!(low_power_config.can.unwrap().id, 0x1a0);
+        assert!(low_power("timeout_scale = 0.5").is_err());
+        assert!(Config::parse("[low_power]\npattern = [{ high = true, duration = 3 }]").is_err());
+        // Held to the supervisor's limits like the pet pattern
+        assert!(low_power("[hardware]\nmax_feed = 5").is_ok());
+        assert!(low_power("[hardware]\nmax_feed = 1.6").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_mqtt() {
+        let mqtt =
+            |rest: &str| Config::parse(&format!("[mqtt]\nbroker = \"[::1]:1883\"\n{}", rest));
+        assert_eq!(mqtt("").is_ok(), cfg!(feature = "mqtt"));
+        assert!(mqtt("topic = \"lab/+/watchdog\"").is_err());
+        assert!(mqtt("keep_alive = 0").is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use crate::stats::{self, Totals};
+use anyhow::{Context, Result};
+use log::warn;
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// Counts starts across power cycles. A start is marked as running until a clean shutdown, so
+/// finding it still marked on the next start means the watchdog went down some other way, most
+/// likely taking the card with it. The pets, pings, strikes and resets of all runs are added up
+/// too, for the long-term trends.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub boots: u64,
+    pub unclean_shutdowns: u64,
+    running: bool,
+    /// As of the last save
+    pub totals: Totals,
+}
+
+impl Counters {
+    fn parse(text: &str) -> Result<Self> {
+        let values = text
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| Ok((key.trim(), value.trim().parse::<u64>()?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+        let value = |key| values.get(key).copied().unwrap_or(0);
+        Ok(Self {
+            boots: value("boots"),
+            unclean_shutdowns: value("unclean_shutdowns"),
+            running: value("running") != 0,
+            totals: Totals {
+                pets: value("pets"),
+                pings: value("pings"),
+                strikes: value("strikes"),
+                resets: value("resets"),
+            },
+        })
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!(
+            "boots={}\nunclean_shutdowns={}\nrunning={}\n",
+            self.boots, self.unclean_shutdowns, self.running as u8
+        );
+        for (name, count) in self.totals.named() {
+            text += &format!("{}={}\n", name, count);
+        }
+        text
+    }
+}
+
+/// The counter file
+pub struct BootCounter {
+    path: PathBuf,
+    pub counters: Counters,
+    /// The totals of the runs before this one
+    before: Totals,
+    interval: Duration,
+    saved: Instant,
+}
+
+impl BootCounter {
+    /// Counts this start, and the last shutdown if it wasn't clean. A corrupt file starts over.
+    /// Taking over from a running instance counts as neither. The totals are saved every
+    /// `interval`.
+    pub fn start(path: &Path, handed_over: bool, interval: Duration) -> Result<Self> {
+        let mut counters = match fs::read_to_string(path) {
+            Ok(text) => Counters::parse(&text).unwrap_or_else(|e| {
+                warn!("Resetting corrupt counters {:?}: {:#}", path, e);
+                Counters::default()
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Counters::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        if !handed_over {
+            counters.unclean_shutdowns += counters.running as u64;
+            counters.boots += 1;
+        }
+        counters.running = true;
+        let before = counters.totals;
+        stats::set_before(before);
+        let counter = Self {
+            path: path.to_path_buf(),
+            counters,
+            before,
+            interval,
+            saved: Instant::now(),
+        };
+        counter.save()?;
+        Ok(counter)
+    }
+
+    /// Saves the totals if it's been `interval`
+    pub fn on_pet(&mut self) -> Result<()> {
+        if self.saved.elapsed() < self.interval {
+            return Ok(());
+        }
+        // A file that can't be written is tried again next interval, rather than every pet
+        self.saved = Instant::now();
+        self.save_totals()
+    }
+
+    /// Saves the totals so far
+    pub fn save_totals(&mut self) -> Result<()> {
+        self.counters.totals = self.before + stats::totals();
+        self.save()
+    }
+
+    /// Saves the totals, marking the shutdown as clean or not
+    pub fn stop(mut self, clean: bool) -> Result<()> {
+        self.counters.running = !clean;
+        self.save_totals()
+    }
+
+    /// Replaces the file atomically, synced so that it survives an imminent reset
+    fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp = self.path.with_extension("new");
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .custom_flags(libc::O_SYNC)
+            .open(&temp)
+            .with_context(|| format!("Failed to open {:?}", temp))?;
+        file.write_all(self.counters.to_text().as_bytes())?;
+        fs::rename(&temp, &self.path).with_context(|| format!("Failed to replace {:?}", self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(600);
+
+    #[test]
+    fn test_counters() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("watchdog-counters-{}", std::process::id()));
+        let path = dir.join("counters");
+
+        let counter = BootCounter::start(&path, false, INTERVAL)?;
+        assert_eq!(
+            (counter.counters.boots, counter.counters.unclean_shutdowns),
+            (1, 0)
+        );
+        counter.stop(true)?;
+        let counter = BootCounter::start(&path, false, INTERVAL)?;
+        assert_eq!(
+            (counter.counters.boots, counter.counters.unclean_shutdowns),
+            (2, 0)
+        );
+        // Went down without stopping
+        drop(counter);
+        let counter = BootCounter::start(&path, false, INTERVAL)?;
+        assert_eq!(
+            (counter.counters.boots, counter.counters.unclean_shutdowns),
+            (3, 1)
+        );
+        let counter = BootCounter::start(&path, true, INTERVAL)?;
+        assert_eq!(
+            (counter.counters.boots, counter.counters.unclean_shutdowns),
+            (3, 1)
+        );
+
+        // The totals of the runs before carry on
+        fs::write(&path, "boots=3\npets=5\npings=7\n")?;
+        let counter = BootCounter::start(&path, false, INTERVAL)?;
+        assert_eq!(counter.before.pets, 5);
+        counter.stop(false)?;
+        let counters = Counters::parse(&fs::read_to_string(&path)?)?;
+        assert!(counters.running);
+        assert!(counters.totals.pets >= 5 && counters.totals.pings >= 7);
+
+        fs::write(&path, "boots=lots\n")?;
+        assert_eq!(
+            BootCounter::start(&path, false, INTERVAL)?.counters.boots,
+            1
+        );
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use anyhow::{bail, Context, Result};
+use log::warn;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Error,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// Where a daemonized watchdog logs, instead of stderr
+pub enum Log {
+    Syslog,
+    File(PathBuf),
+}
+
+/// Detaches from the terminal like a classic daemon, for systems without systemd, e.g. the
+/// recovery initramfs: forks twice around a setsid, points the standard streams away from the
+/// terminal, and logs to `log`. Returns in the daemon, the original process exits.
+///
+/// Has to be called before any other thread is started, those don't survive a fork.
+pub fn daemonize(log: &Log) -> Result<()> {
+    // Open before forking, so that a bad path fails in the foreground
+    let log_file = match log {
+        Log::File(path) => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open log file {:?}", path))?,
+        ),
+        Log::Syslog => None,
+    };
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+
+    // Re-executed in the daemon already, see reexec
+    if crate::reexec::resuming() {
+        if let Log::Syslog = log {
+            crate::logger::syslog();
+        }
+        return Ok(());
+    }
+    fork()?;
+    // SAFETY: no arguments, this process isn't a group leader after the fork
+    if unsafe { libc::setsid() } < 0 {
+        bail!("Failed to start a session: {}", Error::last_os_error());
+    }
+    // No longer a session leader, so opening a tty can't make it a controlling terminal
+    fork()?;
+
+    let stderr = log_file.as_ref().unwrap_or(&null);
+    redirect(&null, libc::STDIN_FILENO)?;
+    redirect(&null, libc::STDOUT_FILENO)?;
+    redirect(stderr, libc::STDERR_FILENO)?;
+    if let Log::Syslog = log {
+        crate::logger::syslog();
+    }
+    Ok(())
+}
+
+/// Exits in the parent
+fn fork() -> Result<()> {
+    // SAFETY: only called before other threads exist, so the child can do anything
+    match unsafe { libc::fork() } {
+        -1 => bail!("Failed to fork: {}", Error::last_os_error()),
+        0 => Ok(()),
+        _ => process::exit(0),
+    }
+}
+
+fn redirect(file: &File, fd: i32) -> Result<()> {
+    // SAFETY: both are open fds, dup2 closes the standard stream it replaces
+    if unsafe { libc::dup2(file.as_raw_fd(), fd) } < 0 {
+        bail!("Failed to redirect fd {}: {}", fd, Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Has this process sent SIGTERM as soon as its parent dies, e.g. the supervisor it runs under
+/// in the recovery environment, so that it doesn't go on petting with nothing watching it. A
+/// parent that's gone already is fatal. The kernel ties it to the thread that started this
+/// process, so the parent has to run it from its main thread.
+pub fn exit_with_parent() -> Result<()> {
+    // SAFETY: neither takes pointers
+    let parent = unsafe { libc::getppid() };
+    if unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) } < 0 {
+        bail!(
+            "Failed to set the parent death signal: {}",
+            Error::last_os_error()
+        );
+    }
+    // Reparented before the signal was set up
+    if unsafe { libc::getppid() } != parent {
+        bail!("The parent has already exited");
+    }
+    Ok(())
+}
+
+/// The pid of this process, written to `path` until dropped
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// An existing file is replaced, e.g. the one of a predecessor handing over
+    pub fn create(path: &Path) -> Result<Self> {
+        fs::write(path, format!("{}\n", process::id()))
+            .with_context(|| format!("Failed to write pid file {:?}", path))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // A successor may have replaced it already
+        let ours =
+            fs::read_to_string(&self.path).is_ok_and(|pid| pid.trim() == process::id().to_string());
+        if ours {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn!("Failed to remove pid file {:?}: {}", self.path, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_file() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("watchdog-pid-{}", process::id()));
+        let pid_file = PidFile::create(&path)?;
+        assert_eq!(fs::read_to_string(&path)?, format!("{}\n", process::id()));
+        drop(pid_file);
+        assert!(!path.exists());
+
+        // Left alone once a successor took it over
+        let pid_file = PidFile::create(&path)?;
+        fs::write(&path, "1\n")?;
+        drop(pid_file);
+        assert_eq!(fs::read_to_string(&path)?, "1\n");
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_exit_with_parent() -> Result<()> {
+        exit_with_parent()?;
+        let mut signal = 0;
+        // SAFETY: signal outlives the call
+        unsafe { libc::prctl(libc::PR_GET_PDEATHSIG, &mut signal as *mut libc::c_int) };
+        assert_eq!(signal, libc::SIGTERM);
+        // Not for the test runner
+        unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, 0) };
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{
+    can::{CanSocket, Frame},
+    clock::{Clock, Timer},
+    config::DeathRowConfig,
+    exit::Cause,
+    history::{self, Event},
+    net,
+};
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use mio::{Registry, Token};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// First byte of a CAN warning frame, followed by the seconds left as a little endian u16
+const CAN_WARNING: u8 = 0x01;
+
+struct Sentence {
+    reason: String,
+    /// Only ping timeouts can be pardoned
+    cause: Cause,
+    until: Instant,
+}
+
+/// The grace window between a fatal ping timeout and the petting stopping, during which WARNING
+/// packets are broadcast so that the flight software can sync or still ping its way out of it.
+/// Deliberate resets, like the one for the maximum uptime, go through it too.
+pub struct DeathRow {
+    duration: Duration,
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+    timer: Box<dyn Timer>,
+    /// Each UDP target with a socket of the matching address family
+    targets: Vec<(UdpSocket, SocketAddr)>,
+    can: Option<(CanSocket, u32)>,
+    sentence: Option<Sentence>,
+}
+
+impl DeathRow {
+    /// The window and its ticks are timed on `clock`, which needs polled timers
+    pub fn new(config: &DeathRowConfig, clock: Arc<dyn Clock>) -> Result<Self> {
+        let targets = net::senders(&config.targets)?;
+        let can = match &config.can {
+            Some(can) => Some((CanSocket::open(&can.interface)?, can.id)),
+            None => None,
+        };
+        Ok(Self {
+            duration: config.duration,
+            interval: config.interval,
+            timer: clock.timer()?,
+            clock,
+            targets,
+            can,
+            sentence: None,
+        })
+    }
+
+    pub fn register(&self, registry: &Registry, token: Token) -> Result<()> {
+        self.timer.register(registry, token)
+    }
+
+    pub fn pending(&self) -> bool {
+        self.sentence.is_some()
+    }
+
+    /// What the sentence is for and how long is left of it, if pending
+    pub fn left(&self) -> Option<(&str, Duration)> {
+        let sentence = self.sentence.as_ref()?;
+        let left = sentence.until.saturating_duration_since(self.clock.now());
+        Some((&sentence.reason, left))
+    }
+
+    /// Starts the grace window, or fails right away with `cause` if there is none. Further
+    /// reasons while already pending don't move the deadline, but one that can't be pardoned
+    /// takes over from one that could.
+    pub fn sentence(&mut self, reason: String, cause: Cause) -> Result<()> {
+        if self.duration.is_zero() {
+            return Err(anyhow!(reason)).context(cause);
+        }
+        match &mut self.sentence {
+            None => {
+                warn!("{}, stopping petting in {:?}", reason, self.duration);
+                self.sentence = Some(Sentence {
+                    reason,
+                    cause,
+                    until: self.clock.now() + self.duration,
+                });
+                self.on_tick()?;
+            }
+            Some(sentence) if sentence.cause == Cause::PingTimeout && cause != sentence.cause => {
+                warn!("{}, no longer pardonable", reason);
+                sentence.reason = reason;
+                sentence.cause = cause;
+            }
+            Some(_) => (),
+        }
+        Ok(())
+    }
+
+    /// Called once whatever was fatal has been fixed, e.g. the late client pinged after all
+    pub fn pardon(&mut self) -> Result<()> {
+        if self
+            .sentence
+            .as_ref()
+            .is_some_and(|s| s.cause == Cause::PingTimeout)
+        {
+            let sentence = self.sentence.take().unwrap();
+            self.timer.unset()?;
+            info!("Reprieved from {}", sentence.reason);
+        }
+        Ok(())
+    }
+
+    /// Broadcasts the next warning, or fails once the window is over
+    pub fn on_tick(&mut self) -> Result<()> {
+        let Some(sentence) = &self.sentence else {
+            return Ok(());
+        };
+        let left = sentence.until.saturating_duration_since(self.clock.now());
+        if left.is_zero() {
+            return Err(anyhow!("{}", sentence.reason)).context(sentence.cause);
+        }
+        self.broadcast(&sentence.reason, left);
+        history::record(Event::DeathWarning {
+            left,
+            reason: sentence.reason.clone(),
+        });
+        // The last tick lands on the deadline rather than up to an interval past it
+        self.timer.set(self.interval.min(left))?;
+        Ok(())
+    }
+
+    /// Best effort, a warning that can't be sent shouldn't stop the others
+    fn broadcast(&self, reason: &str, left: Duration) {
+        let packet = format!("WARNING {:.1} {}", left.as_secs_f64(), reason);
+        for (socket, target) in &self.targets {
+            if let Err(e) = socket.send_to(packet.as_bytes(), target) {
+                warn!("Failed to send warning to {}: {}", target, e);
+            }
+        }
+        if let Some((socket, id)) = &self.can {
+            let seconds = left.as_secs().min(u16::MAX.into()) as u16;
+            let [low, high] = seconds.to_le_bytes();
+            let sent = Frame::new(*id, &[CAN_WARNING, low, high]).and_then(|f| socket.send(&f));
+            if let Err(e) = sent.context("Failed to send CAN warning") {
+                warn!("{:#}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::{Boottime, Monotonic},
+        config::Config,
+    };
+
+    #[test]
+    fn test_death_row() -> Result<()> {
+        let listener = UdpSocket::bind("127.0.0.1:0")?;
+        listener.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let config = Config::parse(&format!(
+            "[death_row]\nduration = 0.2\ninterval = 0.1\ntargets = [\"{}\"]",
+            listener.local_addr()?
+        ))?;
+        let mut death_row = DeathRow::new(&config.death_row, Arc::new(Boottime::new(false)?))?;
+
+        death_row.sentence(
+            String::from("Ping timeout for c3 client"),
+            Cause::PingTimeout,
+        )?;
+        assert!(death_row.pending());
+        let mut buf = [0; 64];
+        let len = listener.recv(&mut buf)?;
+        assert!(buf[..len].starts_with(b"WARNING 0."));
+        assert!(buf[..len].ends_with(b" Ping timeout for c3 client"));
+
+        death_row.pardon()?;
+        assert!(!death_row.pending());
+        assert!(death_row.on_tick().is_ok());
+
+        death_row.sentence(
+            String::from("Ping timeout for c3 client"),
+            Cause::PingTimeout,
+        )?;
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(death_row.on_tick().is_err());
+
+        let mut immediate = DeathRow::new(&Config::default().death_row, Arc::new(Monotonic))?;
+        assert!(immediate
+            .sentence(String::from("Ping timeout"), Cause::PingTimeout)
+            .is_err());
+
+        // A deliberate reset takes over a pending ping timeout and isn't pardoned with it
+        let mut death_row = DeathRow::new(&config.death_row, Arc::new(Monotonic))?;
+        death_row.sentence(String::from("Ping timeout"), Cause::PingTimeout)?;
+        death_row.sentence(String::from("Up for too long"), Cause::Uptime)?;
+        death_row.pardon()?;
+        assert!(death_row.pending());
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(Cause::of(&death_row.on_tick().unwrap_err()), Cause::Uptime);
+        Ok(())
+    }
+}
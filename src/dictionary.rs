@@ -0,0 +1,100 @@
+/// The object the counters are subindices of over SDO, see `sdo::Sdo`
+pub const INDEX: u16 = 0x2010;
+
+/// The counters of this run, each registered only here: `stats::count` counts them, the status
+/// shows them under their keys, and SDO reads them as their subindex of `INDEX`, numbered from 1
+/// in the order of `ALL`. So that the ground's numbering stays put, new ones go at the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    Pets,
+    /// Over the late threshold
+    LatePets,
+    /// A whole cycle or more late
+    Overruns,
+    /// Times the ping socket was bound again after breaking
+    Rebinds,
+    /// Wakeups that left packets for the next one, for the drain budget
+    DrainCuts,
+    /// EXTEND and INHIBIT requests denied for exceeding the delay budget
+    BudgetDenials,
+    /// Bare datagrams taken as pings
+    LegacyPings,
+    /// Framed packets of a protocol version this watchdog doesn't speak
+    UnknownVersions,
+    /// Timer wakeups over the threshold
+    LateWakeups,
+    /// Wall clock steps and suspends
+    TimeAnomalies,
+    Pings,
+    /// Missed windows that weren't fatal yet
+    Strikes,
+    /// Times this went down for the card to be reset
+    Resets,
+}
+
+impl Counter {
+    pub const ALL: [Counter; 13] = [
+        Counter::Pets,
+        Counter::LatePets,
+        Counter::Overruns,
+        Counter::Rebinds,
+        Counter::DrainCuts,
+        Counter::BudgetDenials,
+        Counter::LegacyPings,
+        Counter::UnknownVersions,
+        Counter::LateWakeups,
+        Counter::TimeAnomalies,
+        Counter::Pings,
+        Counter::Strikes,
+        Counter::Resets,
+    ];
+
+    /// In the status
+    pub fn key(self) -> &'static str {
+        match self {
+            Counter::Pets => "count.pets",
+            Counter::LatePets => "pet.late_pets",
+            Counter::Overruns => "pet.overruns",
+            Counter::Rebinds => "socket.rebinds",
+            Counter::DrainCuts => "socket.drain_cuts",
+            Counter::BudgetDenials => "budget.denials",
+            Counter::LegacyPings => "protocol.legacy_pings",
+            Counter::UnknownVersions => "protocol.unknown_versions",
+            Counter::LateWakeups => "wakeup.late",
+            Counter::TimeAnomalies => "time.anomalies",
+            Counter::Pings => "count.pings",
+            Counter::Strikes => "count.strikes",
+            Counter::Resets => "count.resets",
+        }
+    }
+
+    /// Of `INDEX`
+    pub fn sub(self) -> u8 {
+        self as u8 + 1
+    }
+
+    /// The one at subindex `sub` of `INDEX`
+    pub fn at(sub: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|counter| counter.sub() == sub)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_counters() {
+        for (index, counter) in Counter::ALL.into_iter().enumerate() {
+            assert_eq!(counter as usize, index);
+            assert_eq!(Counter::at(counter.sub()), Some(counter));
+        }
+        let keys: HashSet<_> = Counter::ALL.iter().map(|c| c.key()).collect();
+        assert_eq!(keys.len(), Counter::ALL.len());
+        assert_eq!(Counter::at(0), None);
+        assert_eq!(Counter::at(Counter::ALL.len() as u8 + 1), None);
+        // Kept where the ground expects them
+        assert_eq!(Counter::Rebinds.sub(), 4);
+    }
+}
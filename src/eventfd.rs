@@ -0,0 +1,202 @@
+use crate::{condition::Condition, config::EventfdConfig, pingee::Pingee, stats};
+use anyhow::{Context, Result};
+use log::{debug, info};
+use mio::{unix::SourceFd, Interest, Registry, Token};
+use nix::sys::socket::{sendmsg, ControlMessage, MsgFlags};
+use std::{
+    fs::{self, File, Permissions},
+    io::{Error, ErrorKind, IoSlice, Read},
+    os::{
+        fd::{AsRawFd, FromRawFd},
+        unix::{fs::PermissionsExt, net::UnixListener},
+    },
+};
+
+/// Sent along with the eventfd
+const HELLO: &[u8] = b"EVENTFD";
+
+struct Channel {
+    config: EventfdConfig,
+    eventfd: File,
+    /// Where the eventfd is handed out
+    listener: UnixListener,
+}
+
+impl Channel {
+    fn new(config: &EventfdConfig) -> Result<Self> {
+        // SAFETY: a new fd, checked for failure below and owned by the File from then on
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(Error::last_os_error()).context("Failed to create an eventfd");
+        }
+        let eventfd = unsafe { File::from_raw_fd(fd) };
+        // Whatever is left there belongs to a previous instance that's gone or on its way out
+        match fs::remove_file(&config.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("Failed to remove {:?}", config.path))
+            }
+            _ => (),
+        }
+        let listener = UnixListener::bind(&config.path)
+            .with_context(|| format!("Failed to bind eventfd socket {:?}", config.path))?;
+        fs::set_permissions(&config.path, Permissions::from_mode(0o666))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            config: config.clone(),
+            eventfd,
+            listener,
+        })
+    }
+
+    /// Hands the eventfd to everyone waiting
+    fn on_accept(&self) -> Result<()> {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e).context("Eventfd socket accept failed"),
+            };
+            let iov = [IoSlice::new(HELLO)];
+            let fds = [self.eventfd.as_raw_fd()];
+            let cmsg = [ControlMessage::ScmRights(&fds)];
+            match sendmsg::<()>(stream.as_raw_fd(), &iov, &cmsg, MsgFlags::empty(), None) {
+                Ok(_) => info!("Handed out the eventfd of {}", self.config.client),
+                Err(e) => debug!("Failed to hand out an eventfd: {}", e),
+            }
+        }
+    }
+
+    /// Reading resets the count, which only has to have gone up
+    fn on_written(&mut self, pingee: &mut Pingee) -> Result<()> {
+        let mut count = [0; 8];
+        match self.eventfd.read(&mut count) {
+            Ok(_) => {
+                pingee.feed(Some(&self.config.client))?;
+                stats::ping(&self.config.client);
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+            Err(e) => return Err(e).context("Failed to read an eventfd"),
+        }
+        Ok(())
+    }
+}
+
+/// Eventfds that feed a client whenever they're written, for processes in the recovery environment
+/// without a network stack. A wrapper connects to the channel's Unix socket, receives the eventfd
+/// with SCM_RIGHTS and leaves it open for the client it launches, which then writes any 8 byte
+/// count to it as its ping.
+pub struct EventfdChannels {
+    channels: Vec<Channel>,
+}
+
+impl EventfdChannels {
+    /// Registered with two tokens per channel from `first`, its socket's and its eventfd's
+    pub fn new(registry: &Registry, first: Token, configs: &[EventfdConfig]) -> Result<Self> {
+        let mut channels = Vec::new();
+        for (i, config) in configs.iter().enumerate() {
+            let channel = Channel::new(config)?;
+            let listener = channel.listener.as_raw_fd();
+            let eventfd = channel.eventfd.as_raw_fd();
+            let token = first.0 + 2 * i;
+            registry.register(&mut SourceFd(&listener), Token(token), Interest::READABLE)?;
+            registry.register(
+                &mut SourceFd(&eventfd),
+                Token(token + 1),
+                Interest::READABLE,
+            )?;
+            channels.push(channel);
+        }
+        Ok(Self { channels })
+    }
+}
+
+impl Condition for EventfdChannels {
+    fn name(&self) -> &'static str {
+        "eventfd channels"
+    }
+
+    fn tokens(&self) -> usize {
+        2 * self.channels.len()
+    }
+
+    fn on_event(&mut self, index: usize, pingee: &mut Pingee) -> Result<Option<String>> {
+        let channel = &mut self.channels[index / 2];
+        match index % 2 {
+            0 => channel.on_accept()?,
+            _ => channel.on_written(pingee)?,
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use mio::Poll;
+    use nix::{
+        cmsg_space,
+        sys::socket::{recvmsg, ControlMessageOwned},
+    };
+    use std::{
+        io::{IoSliceMut, Write},
+        os::{fd::RawFd, unix::net::UnixStream},
+    };
+
+    fn receive(stream: &UnixStream) -> Result<File> {
+        let mut buf = [0; HELLO.len()];
+        let mut iov = [IoSliceMut::new(&mut buf)];
+        let mut space = cmsg_space!(RawFd);
+        let msg = recvmsg::<()>(
+            stream.as_raw_fd(),
+            &mut iov,
+            Some(&mut space),
+            MsgFlags::empty(),
+        )?;
+        let fd = msg
+            .cmsgs()
+            .find_map(|cmsg| match cmsg {
+                ControlMessageOwned::ScmRights(fds) => fds.first().copied(),
+                _ => None,
+            })
+            .context("No eventfd")?;
+        // SAFETY: just received, nothing else owns it
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    #[test]
+    fn test_eventfd_channels() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("watchdog-eventfd-{}", std::process::id()));
+        let config = Config::parse(&format!(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "recovery"
+            [[eventfd]]
+            path = {:?}
+            client = "recovery"
+            "#,
+            path
+        ))?;
+        let poll = Poll::new()?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        let mut channels = EventfdChannels::new(poll.registry(), Token(100), &config.eventfds)?;
+        assert_eq!(channels.tokens(), 2);
+
+        let stream = UnixStream::connect(&path)?;
+        channels.on_event(0, &mut pingee)?;
+        let mut eventfd = receive(&stream)?;
+        // Nothing written yet
+        channels.on_event(1, &mut pingee)?;
+        assert!(!stats::last_pings()
+            .iter()
+            .any(|(client, _)| client == "recovery"));
+        eventfd.write_all(&1u64.to_ne_bytes())?;
+        channels.on_event(1, &mut pingee)?;
+        assert!(stats::last_pings()
+            .iter()
+            .any(|(client, _)| client == "recovery"));
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use std::{fmt, process::ExitCode};
+
+/// Why the watchdog stopped, as its exit code, so that the systemd unit and post-mortem tooling
+/// can tell them apart. Errors are tagged by attaching one as context, untagged errors are
+/// `Other`. The same cause goes into the logs, the last gasp, the hook's environment, and the
+/// EMCY announcing it, so that they all agree on why the card went down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    /// Stopped by a signal, including after draining or handing over
+    Signal = 0,
+    Other = 1,
+    /// Liveness lost: a client stopped pinging, the CAN bus went quiet, or a watched process,
+    /// cgroup, or unit was lost
+    PingTimeout = 2,
+    Gpio = 3,
+    Socket = 4,
+    /// A commanded reset, or SIGQUIT
+    Reset = 5,
+    /// Up for longer than the configured maximum
+    Uptime = 6,
+    /// A reset window opening, if those are required
+    Scheduled = 7,
+    /// The main loop stopped going round
+    Stall = 8,
+    /// A startup gate that bites wasn't met in time
+    StartupGate = 9,
+    /// A panic on any thread, 10 being the simulated supervisor's bite
+    Panic = 11,
+}
+
+impl Cause {
+    pub fn of(error: &anyhow::Error) -> Self {
+        error.downcast_ref().copied().unwrap_or(Cause::Other)
+    }
+
+    /// For machines to match on, unlike the Display text
+    pub fn name(self) -> &'static str {
+        match self {
+            Cause::Signal => "signal_shutdown",
+            Cause::Other => "internal_error",
+            Cause::PingTimeout => "ping_timeout",
+            Cause::Gpio => "gpio_failure",
+            Cause::Socket => "socket_failure",
+            Cause::Reset => "commanded_reset",
+            Cause::Uptime => "max_uptime",
+            Cause::Scheduled => "scheduled_cycle",
+            Cause::Stall => "loop_stall",
+            Cause::StartupGate => "startup_gate",
+            Cause::Panic => "panic",
+        }
+    }
+
+    /// The CANopen EMCY error code class, the exit code goes with it to tell apart the ones
+    /// sharing one
+    pub fn emcy(self) -> u16 {
+        match self {
+            Cause::Signal => 0x0000,
+            Cause::Other | Cause::Stall | Cause::Panic => 0x6100,
+            // Life guard or heartbeat error
+            Cause::PingTimeout => 0x8130,
+            Cause::Gpio => 0x5000,
+            Cause::Socket => 0x8100,
+            Cause::Reset | Cause::Uptime | Cause::Scheduled | Cause::StartupGate => 0x6200,
+        }
+    }
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Cause::Signal => "Stopped by a signal",
+            Cause::Other => "Failure",
+            Cause::PingTimeout => "Ping timeout",
+            Cause::Gpio => "GPIO failure",
+            Cause::Socket => "Socket failure",
+            Cause::Reset => "Commanded reset",
+            Cause::Uptime => "Maximum uptime",
+            Cause::Scheduled => "Scheduled reset",
+            Cause::Stall => "Main loop stall",
+            Cause::StartupGate => "Startup gate timed out",
+            Cause::Panic => "Panic",
+        })
+    }
+}
+
+impl std::error::Error for Cause {}
+
+impl From<Cause> for ExitCode {
+    fn from(cause: Cause) -> Self {
+        ExitCode::from(cause as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context, Result};
+
+    #[test]
+    fn test_cause() {
+        let timeout: Result<()> = Err(anyhow!("c3 client stopped pinging"));
+        let timeout = timeout.context(Cause::PingTimeout).context("Outer");
+        assert_eq!(Cause::of(&timeout.unwrap_err()), Cause::PingTimeout);
+        assert_eq!(Cause::of(&anyhow!("Untagged")), Cause::Other);
+        let gpio = anyhow!(Cause::Gpio);
+        assert_eq!(format!("{:#}", gpio), "GPIO failure");
+        assert_eq!(Cause::of(&gpio), Cause::Gpio);
+        assert_eq!(Cause::Uptime.name(), "max_uptime");
+        assert_eq!(Cause::PingTimeout.emcy(), 0x8130);
+    }
+}
@@ -0,0 +1,242 @@
+use anyhow::{ensure, Result};
+use std::fmt;
+
+// Framed packets wrap a command or its reply, so that the protocol can change over the mission
+// without breaking clients that speak an older version. Big endian:
+//
+//     version: u8, type: u8, length: u16, payload: [u8; length], crc: u16
+//
+// The CRC is CRC-16/CCITT-FALSE over everything before it. In version 1 the payload is a text
+// command or reply as described in protocol.rs, AUTH trailer included. Text commands start with a
+// letter, so a first byte below 0x20 marks a framed packet, and plain text keeps working.
+
+pub const VERSION: u8 = 1;
+
+/// Version, type, and length
+const HEADER: usize = 4;
+const CRC: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Command = 1,
+    Reply = 2,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub kind: Kind,
+    pub payload: &'a [u8],
+}
+
+/// Why a framed packet was dropped
+#[derive(Debug, PartialEq, Eq)]
+pub enum Rejected {
+    /// From a newer client, or garbage
+    Version(u8),
+    Kind(u8),
+    /// Shorter than its header and CRC, or than the length says
+    Truncated,
+    /// Longer than the length says
+    Trailing,
+    Crc {
+        expected: u16,
+        actual: u16,
+    },
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejected::Version(version) => write!(f, "Unknown protocol version {}", version),
+            Rejected::Kind(kind) => write!(f, "Unknown frame type {}", kind),
+            Rejected::Truncated => write!(f, "Truncated frame"),
+            Rejected::Trailing => write!(f, "Trailing bytes after frame"),
+            Rejected::Crc { expected, actual } => {
+                write!(f, "Bad CRC {:#06x}, expected {:#06x}", actual, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// Whether `packet` is framed rather than plain text
+pub fn is_framed(packet: &[u8]) -> bool {
+    packet.first().is_some_and(|&b| b < 0x20)
+}
+
+pub fn decode(packet: &[u8]) -> Result<Frame<'_>, Rejected> {
+    let &[version, kind, high, low, ..] = packet else {
+        return Err(match packet.first() {
+            Some(&version) if version != VERSION => Rejected::Version(version),
+            _ => Rejected::Truncated,
+        });
+    };
+    // Checked first, a newer version may lay out the rest differently
+    if version != VERSION {
+        return Err(Rejected::Version(version));
+    }
+    let kind = match kind {
+        1 => Kind::Command,
+        2 => Kind::Reply,
+        other => return Err(Rejected::Kind(other)),
+    };
+    let len = HEADER + usize::from(u16::from_be_bytes([high, low]));
+    if packet.len() < len + CRC {
+        return Err(Rejected::Truncated);
+    }
+    if packet.len() > len + CRC {
+        return Err(Rejected::Trailing);
+    }
+    let expected = crc16(&packet[..len]);
+    let actual = u16::from_be_bytes([packet[len], packet[len + 1]]);
+    if actual != expected {
+        return Err(Rejected::Crc { expected, actual });
+    }
+    Ok(Frame {
+        kind,
+        payload: &packet[HEADER..len],
+    })
+}
+
+pub fn encode(kind: Kind, payload: &[u8]) -> Result<Vec<u8>> {
+    let len = u16::try_from(payload.len());
+    ensure!(
+        len.is_ok(),
+        "Payload of {} bytes is too long",
+        payload.len()
+    );
+    let mut packet = vec![VERSION, kind as u8];
+    packet.extend_from_slice(&len.unwrap().to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet.extend_from_slice(&crc16(&packet).to_be_bytes());
+    Ok(packet)
+}
+
+/// CRC-16/CCITT-FALSE: polynomial 0x1021, initial 0xffff, not reflected
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffff_u16;
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::{collection::vec, prelude::*, sample::Index};
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(10_000))]
+
+        #[test]
+        fn test_arbitrary_frames(
+            reply in any::<bool>(),
+            payload in vec(any::<u8>(), ..512),
+            bit in any::<Index>(),
+            bytes in vec(0..3u8, ..16),
+        ) {
+            let kind = if reply { Kind::Reply } else { Kind::Command };
+            let mut packet = encode(kind, &payload).unwrap();
+            prop_assert_eq!(
+                decode(&packet),
+                Ok(Frame {
+                    kind,
+                    payload: &payload
+                })
+            );
+            // Any single flipped bit is caught, if only by the CRC
+            let bit = bit.index(packet.len() * 8);
+            packet[bit / 8] ^= 1 << (bit % 8);
+            prop_assert!(decode(&packet).is_err());
+            // Whatever does decode encodes back to the same bytes
+            if let Ok(frame) = decode(&bytes) {
+                prop_assert_eq!(&encode(frame.kind, frame.payload).unwrap(), &bytes);
+            }
+        }
+    }
+
+    #[test]
+    fn test_crc16() {
+        // The catalogued check value
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+        assert_eq!(crc16(b""), 0xffff);
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let packet = encode(Kind::Command, b"PING c3")?;
+        assert_eq!(&packet[..4], [1, 1, 0, 7]);
+        assert_eq!(
+            decode(&packet),
+            Ok(Frame {
+                kind: Kind::Command,
+                payload: b"PING c3",
+            })
+        );
+        let empty = encode(Kind::Reply, b"")?;
+        assert_eq!(empty.len(), 6);
+        assert_eq!(decode(&empty)?.payload, b"");
+        assert_eq!(decode(&empty)?.kind, Kind::Reply);
+        assert!(encode(Kind::Reply, &vec![0; 65536]).is_err());
+        assert!(encode(Kind::Reply, &vec![0; 65535]).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_framed() -> Result<()> {
+        assert!(is_framed(&encode(Kind::Command, b"STATUS")?));
+        assert!(is_framed(&[0x02]));
+        assert!(!is_framed(b"PING c3"));
+        assert!(!is_framed(b""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejected() -> Result<()> {
+        let packet = encode(Kind::Command, b"ARM")?;
+
+        let mut newer = packet.clone();
+        newer[0] = 2;
+        assert_eq!(decode(&newer), Err(Rejected::Version(2)));
+        assert_eq!(decode(&[0]), Err(Rejected::Version(0)));
+        assert_eq!(decode(&[9, 1]), Err(Rejected::Version(9)));
+
+        let mut kind = packet.clone();
+        kind[1] = 3;
+        assert_eq!(decode(&kind), Err(Rejected::Kind(3)));
+
+        assert_eq!(decode(&[]), Err(Rejected::Truncated));
+        assert_eq!(decode(&[1, 1, 0]), Err(Rejected::Truncated));
+        assert_eq!(
+            decode(&packet[..packet.len() - 1]),
+            Err(Rejected::Truncated)
+        );
+        let mut long = packet.clone();
+        long[3] = 0xff;
+        assert_eq!(decode(&long), Err(Rejected::Truncated));
+
+        let mut trailing = packet.clone();
+        trailing.push(0);
+        assert_eq!(decode(&trailing), Err(Rejected::Trailing));
+
+        let mut flipped = packet.clone();
+        flipped[4] ^= 0x20;
+        assert!(matches!(decode(&flipped), Err(Rejected::Crc { .. })));
+        let mut crc = packet.clone();
+        let last = crc.len() - 1;
+        crc[last] ^= 1;
+        let Err(Rejected::Crc { expected, actual }) = decode(&crc) else {
+            panic!("CRC not checked");
+        };
+        assert_eq!(expected ^ actual, 1);
+        Ok(())
+    }
+}
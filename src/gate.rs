@@ -0,0 +1,197 @@
+use crate::{
+    config::{GateConfig, GateFallback},
+    exit::Cause,
+    pingee::Pingee,
+    units,
+};
+use anyhow::Result;
+use log::{info, warn};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// IFF_UP in /sys/class/net/<interface>/flags
+const IFF_UP: u32 = 0x1;
+
+enum Check {
+    /// The interface's flags file
+    Interface(PathBuf),
+    /// Kept up to date by a unit watcher
+    Unit(Arc<AtomicBool>),
+    TimeSync,
+}
+
+impl Check {
+    fn met(&self) -> bool {
+        match self {
+            Check::Interface(flags) => interface_up(flags),
+            Check::Unit(active) => active.load(Ordering::Relaxed),
+            Check::TimeSync => time_synchronized(),
+        }
+    }
+}
+
+struct Gate {
+    name: String,
+    check: Check,
+    timeout: Duration,
+    fallback: GateFallback,
+}
+
+/// Keeps the watchdog disarmed at startup until each of its gates is met, e.g. the CAN interface
+/// being up or the clock being synchronized, so that a slow SD card doesn't reset the card for
+/// clients that couldn't have started yet. A gate past its timeout arms anyway or bites, as
+/// configured. An ARM or DISARM ends the wait.
+pub struct StartupGate {
+    /// The ones still waited for
+    gates: Vec<Gate>,
+    started: Instant,
+}
+
+impl StartupGate {
+    pub fn new(configs: &[GateConfig]) -> Result<Self> {
+        let mut gates = Vec::new();
+        for config in configs {
+            let check = match (&config.can, &config.unit) {
+                (Some(interface), _) => Check::Interface(
+                    PathBuf::from("/sys/class/net")
+                        .join(interface)
+                        .join("flags"),
+                ),
+                (None, Some(unit)) => {
+                    let active = Arc::new(AtomicBool::new(false));
+                    units::watch_active(unit, active.clone())?;
+                    Check::Unit(active)
+                }
+                (None, None) => Check::TimeSync,
+            };
+            gates.push(Gate {
+                name: config.name(),
+                check,
+                timeout: config.timeout,
+                fallback: config.fallback,
+            });
+        }
+        Ok(Self {
+            gates,
+            started: Instant::now(),
+        })
+    }
+
+    /// Checks the gates still waited for, on every pet. Returns the reset a gate that bites asks
+    /// for once its timeout is up.
+    pub fn on_pet(&mut self, pingee: &mut Pingee) -> Result<Option<(String, Cause)>> {
+        if self.gates.is_empty() || !pingee.gated() {
+            self.gates.clear();
+            return Ok(None);
+        }
+        let waited = self.started.elapsed();
+        let mut reset = None;
+        self.gates.retain(|gate| {
+            if gate.check.met() {
+                info!("Startup gate {} met after {:?}", gate.name, waited);
+                return false;
+            }
+            if waited < gate.timeout {
+                return true;
+            }
+            match gate.fallback {
+                GateFallback::Arm => warn!("Startup gate {} timed out, arming anyway", gate.name),
+                GateFallback::Bite => {
+                    let reason = format!("Startup gate {} timed out", gate.name);
+                    reset.get_or_insert((reason, Cause::StartupGate));
+                }
+            }
+            false
+        });
+        if reset.is_some() {
+            self.gates.clear();
+            return Ok(reset);
+        }
+        match self.gates.is_empty() {
+            true => pingee.ungate()?,
+            false => pingee.gating(self.waiting()),
+        }
+        Ok(None)
+    }
+
+    fn waiting(&self) -> String {
+        let names: Vec<_> = self.gates.iter().map(|gate| gate.name.as_str()).collect();
+        names.join(",")
+    }
+}
+
+fn interface_up(flags: &Path) -> bool {
+    let flags = fs::read_to_string(flags).unwrap_or_default();
+    let flags = u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16);
+    flags.is_ok_and(|flags| flags & IFF_UP != 0)
+}
+
+/// Whether the kernel considers the clock synchronized, which NTP daemons and timesyncd tell it
+fn time_synchronized() -> bool {
+    // SAFETY: all integers, for which zero is valid
+    let mut timex: libc::timex = unsafe { std::mem::zeroed() };
+    // SAFETY: timex outlives the call, and no modes only reads the state
+    let state = unsafe { libc::adjtimex(&mut timex) };
+    state >= 0 && state != libc::TIME_ERROR
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use mio::Token;
+
+    #[test]
+    fn test_gates() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[gate]]
+            can = "can0"
+            timeout = 30
+            [[gate]]
+            time_sync = true
+            timeout = 60
+            fallback = "bite"
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        assert!(!pingee.armed());
+        assert_eq!(
+            pingee.status()?.get("gates_waiting"),
+            Some("can0,time-sync")
+        );
+
+        let flags = std::env::temp_dir().join(format!("watchdog-gate-{}", std::process::id()));
+        fs::write(&flags, "0x1002\n")?;
+        let mut gate = StartupGate::new(&config.gates)?;
+        gate.gates[0].check = Check::Interface(flags.clone());
+        gate.gates[1].check = Check::Unit(Arc::new(AtomicBool::new(false)));
+        assert_eq!(gate.on_pet(&mut pingee)?, None);
+        fs::write(&flags, "0x1003\n")?;
+        assert_eq!(gate.on_pet(&mut pingee)?, None);
+        assert_eq!(pingee.status()?.get("gates_waiting"), Some("time-sync"));
+
+        gate.started -= Duration::from_secs(60);
+        let reset = gate.on_pet(&mut pingee)?;
+        assert_eq!(reset.map(|(_, cause)| cause), Some(Cause::StartupGate));
+        assert!(!pingee.armed());
+        fs::remove_file(&flags)?;
+
+        // Arming anyway
+        let mut gate = StartupGate::new(&config.gates[..1])?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        gate.started -= Duration::from_secs(30);
+        assert_eq!(gate.on_pet(&mut pingee)?, None);
+        assert!(pingee.armed());
+        assert_eq!(pingee.status()?.get("gates_waiting"), None);
+        Ok(())
+    }
+}
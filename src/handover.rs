@@ -0,0 +1,200 @@
+use anyhow::{ensure, Context, Result};
+use log::{info, warn};
+use mio::{unix::SourceFd, Interest, Registry, Token};
+use nix::{
+    cmsg_space,
+    sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags},
+};
+use std::{
+    fs,
+    io::{ErrorKind, IoSlice, IoSliceMut, Read, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    time::Duration,
+};
+
+// A restarting watchdog hands its requested GPIO line and bound sockets to its successor, so that
+// an upgrade doesn't have to stop the petting:
+//
+//  1. The successor connects to the handover socket
+//  2. The old instance sends "HANDOVER" with the fds attached and stops petting
+//  3. The successor starts petting and replies "OK"
+//  4. The old instance exits without touching the line. Without the reply it resumes petting.
+
+const HELLO: &[u8] = b"HANDOVER";
+const CONFIRMATION: &[u8] = b"OK";
+// Well within the hardware watchdog's window, since nobody pets in the meantime
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(2);
+// The line, the main socket, and every extra port
+const MAX_FDS: usize = 2 + crate::config::MAX_PORTS;
+
+/// Listens for a successor to hand over to
+pub struct Handover {
+    listener: UnixListener,
+}
+
+/// What was handed over by the previous instance
+pub struct Inherited {
+    pub line: OwnedFd,
+    /// The main socket followed by the extra ports, in config order
+    pub sockets: Vec<OwnedFd>,
+    stream: UnixStream,
+}
+
+impl Handover {
+    pub fn listen(path: &Path) -> Result<Self> {
+        // Whatever is left there belongs to a previous instance that's gone or on its way out
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("Failed to remove {:?}", path))
+            }
+            _ => (),
+        }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to bind handover socket {:?}", path))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener })
+    }
+
+    pub fn register(&mut self, registry: &Registry, token: Token) -> Result<()> {
+        registry.register(
+            &mut SourceFd(&self.listener.as_raw_fd()),
+            token,
+            Interest::READABLE,
+        )?;
+        Ok(())
+    }
+
+    /// Sends the fds to the next waiting successor, if any. Returns the connection to wait on
+    /// for the confirmation.
+    pub fn accept(&self, fds: &[RawFd]) -> Result<Option<UnixStream>> {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e).context("Handover accept failed"),
+            };
+            match send_fds(&stream, fds) {
+                Ok(()) => {
+                    info!("Handing over to a successor");
+                    return Ok(Some(stream));
+                }
+                Err(e) => warn!("Failed to hand over: {:#}", e),
+            }
+        }
+    }
+}
+
+fn send_fds(stream: &UnixStream, fds: &[RawFd]) -> Result<()> {
+    let iov = [IoSlice::new(HELLO)];
+    let cmsg = [ControlMessage::ScmRights(fds)];
+    sendmsg::<()>(stream.as_raw_fd(), &iov, &cmsg, MsgFlags::empty(), None)?;
+    Ok(())
+}
+
+/// Waits briefly for the successor to confirm that it's petting
+pub fn confirmed(mut successor: UnixStream) -> bool {
+    let mut buf = [0; 2];
+    let confirmed = successor
+        .set_read_timeout(Some(CONFIRM_TIMEOUT))
+        .and_then(|()| successor.read_exact(&mut buf))
+        .is_ok_and(|()| buf == CONFIRMATION);
+    if !confirmed {
+        warn!("Successor didn't confirm the handover, resuming");
+    }
+    confirmed
+}
+
+/// Takes over from a running instance at `path`, None if there isn't one
+pub fn receive(path: &Path) -> Result<Option<Inherited>> {
+    let stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+            return Ok(None)
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to connect to {:?}", path)),
+    };
+    stream.set_read_timeout(Some(CONFIRM_TIMEOUT))?;
+
+    let mut buf = [0; HELLO.len()];
+    let mut iov = [IoSliceMut::new(&mut buf)];
+    let mut space = cmsg_space!([RawFd; MAX_FDS]);
+    let msg = recvmsg::<()>(
+        stream.as_raw_fd(),
+        &mut iov,
+        Some(&mut space),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )
+    .context("Failed to receive handover")?;
+    let mut fds = Vec::new();
+    for cmsg in msg.cmsgs() {
+        if let ControlMessageOwned::ScmRights(received) = cmsg {
+            // SAFETY: the fds were just received and nothing else owns them
+            fds.extend(
+                received
+                    .into_iter()
+                    .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+            );
+        }
+    }
+    ensure!(msg.bytes == HELLO.len() && buf == HELLO, "Invalid handover");
+    ensure!(fds.len() >= 2, "Handover is missing fds");
+    let line = fds.remove(0);
+    info!("Took over {} sockets and the GPIO line", fds.len());
+    Ok(Some(Inherited {
+        line,
+        sockets: fds,
+        stream,
+    }))
+}
+
+impl Inherited {
+    /// Lets the previous instance exit, once the petting is going
+    pub fn confirm(mut self) -> Result<()> {
+        self.stream
+            .write_all(CONFIRMATION)
+            .context("Failed to confirm handover")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::File, thread};
+
+    #[test]
+    fn test_handover() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("watchdog-handover-{}", std::process::id()));
+        assert!(receive(&path)?.is_none());
+        let mut handover = Handover::listen(&path)?;
+        let poll = mio::Poll::new()?;
+        handover.register(poll.registry(), Token(0))?;
+        assert!(handover.accept(&[])?.is_none());
+
+        let line = File::open("/dev/null")?;
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+        let address = socket.local_addr()?;
+        let successor = thread::spawn({
+            let path = path.clone();
+            move || {
+                let inherited = receive(&path).unwrap().unwrap();
+                let socket = std::net::UdpSocket::from(inherited.sockets[0].try_clone().unwrap());
+                assert_eq!(socket.local_addr().unwrap(), address);
+                inherited.confirm().unwrap();
+            }
+        });
+        let stream = loop {
+            if let Some(stream) = handover.accept(&[line.as_raw_fd(), socket.as_raw_fd()])? {
+                break stream;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert!(confirmed(stream));
+        successor.join().unwrap();
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
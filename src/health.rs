@@ -0,0 +1,112 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// How well the clients kept up over the window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grade {
+    /// The share of the expected ping windows that got a ping, from 0 to 1
+    pub grade: f64,
+    /// The least time a client had left when it pinged, None without pings
+    pub worst_margin: Option<Duration>,
+}
+
+/// The missed ping windows and the margins of the pings over the last `window`, so that a flight
+/// software slowly falling behind shows as a trend long before it's a reset
+pub struct Health {
+    window: Duration,
+    misses: VecDeque<Instant>,
+    /// Oldest first, each smaller than everything after it, so that the first one in the window is
+    /// the smallest
+    margins: VecDeque<(Instant, Duration)>,
+}
+
+impl Health {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            misses: VecDeque::new(),
+            margins: VecDeque::new(),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// A window that ran out without a ping
+    pub fn missed(&mut self, now: Instant) {
+        self.expire(now);
+        self.misses.push_back(now);
+    }
+
+    /// A ping with `margin` left of its window
+    pub fn pinged(&mut self, now: Instant, margin: Duration) {
+        self.expire(now);
+        while self.margins.back().is_some_and(|&(_, m)| m >= margin) {
+            self.margins.pop_back();
+        }
+        self.margins.push_back((now, margin));
+    }
+
+    /// Out of `expected` windows in the last window's worth of time
+    pub fn grade(&self, now: Instant, expected: f64) -> Grade {
+        let recent = |at: &Instant| now.saturating_duration_since(*at) < self.window;
+        let missed = self.misses.iter().filter(|at| recent(at)).count() as f64;
+        let grade = match expected > 0.0 {
+            true => (1.0 - missed / expected).clamp(0.0, 1.0),
+            false => 1.0,
+        };
+        let worst_margin = self.margins.iter().find(|(at, _)| recent(at));
+        Grade {
+            grade,
+            worst_margin: worst_margin.map(|&(_, margin)| margin),
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let old = |at: &Instant| now.saturating_duration_since(*at) >= self.window;
+        while self.misses.front().is_some_and(old) {
+            self.misses.pop_front();
+        }
+        while self.margins.front().is_some_and(|(at, _)| old(at)) {
+            self.margins.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grade() {
+        let mut health = Health::new(Duration::from_secs(3600));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert_eq!(health.grade(at(0), 0.0).grade, 1.0);
+        assert_eq!(health.grade(at(0), 10.0).worst_margin, None);
+
+        health.pinged(at(10), Duration::from_secs(5));
+        health.pinged(at(20), Duration::from_secs(2));
+        health.pinged(at(30), Duration::from_secs(8));
+        health.missed(at(40));
+        let grade = health.grade(at(50), 100.0);
+        assert_eq!(grade.grade, 0.99);
+        assert_eq!(grade.worst_margin, Some(Duration::from_secs(2)));
+        // More misses than windows
+        assert_eq!(health.grade(at(50), 0.5).grade, 0.0);
+
+        // The 2 s margin has left the window, then the miss
+        let grade = health.grade(at(3625), 100.0);
+        assert_eq!(grade.grade, 0.99);
+        assert_eq!(grade.worst_margin, Some(Duration::from_secs(8)));
+        assert_eq!(health.grade(at(3645), 100.0).grade, 1.0);
+        health.pinged(at(3700), Duration::from_secs(9));
+        assert_eq!(
+            health.grade(at(3700), 1.0).worst_margin,
+            Some(Duration::from_secs(9))
+        );
+    }
+}
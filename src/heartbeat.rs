@@ -0,0 +1,144 @@
+use crate::{
+    can::{CanSocket, Frame},
+    clock::{Clock, Timer},
+    config::HeartbeatConfig,
+    net,
+    source::{EventSource, Ready},
+    state::State,
+};
+use anyhow::{Context, Result};
+use log::warn;
+use mio::{Registry, Token};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+/// First byte of a CAN heartbeat frame, after the death row's warnings
+const CAN_HEARTBEAT: u8 = 0x02;
+
+/// Announces that the watchdog itself is still running, so that the rest of the system can tell
+/// the daemon died before the hardware watchdog bites. Each beat carries the state and the time
+/// left until petting stops, if that's counting down.
+pub struct Heartbeat {
+    interval: Duration,
+    timer: Box<dyn Timer>,
+    targets: Vec<(UdpSocket, SocketAddr)>,
+    can: Option<(CanSocket, u32)>,
+}
+
+impl Heartbeat {
+    pub fn new(config: &HeartbeatConfig, clock: &dyn Clock) -> Result<Self> {
+        let can = match &config.can {
+            Some(can) => Some((CanSocket::open(&can.interface)?, can.id)),
+            None => None,
+        };
+        let timer = clock.timer()?;
+        timer.set(config.interval)?;
+        Ok(Self {
+            interval: config.interval,
+            timer,
+            targets: net::senders(&config.targets)?,
+            can,
+        })
+    }
+
+    /// Sends a beat and waits for the next one. `left` is None if nothing is counting down.
+    pub fn on_tick(&mut self, state: &str, left: Option<Duration>) -> Result<()> {
+        self.timer.set(self.interval)?;
+        self.beat(state, left);
+        Ok(())
+    }
+
+    /// Best effort, like the death row's warnings
+    fn beat(&self, state: &str, left: Option<Duration>) {
+        let packet = packet(state, left);
+        for (socket, target) in &self.targets {
+            if let Err(e) = socket.send_to(packet.as_bytes(), target) {
+                warn!("Failed to send heartbeat to {}: {}", target, e);
+            }
+        }
+        if let Some((socket, id)) = &self.can {
+            let sent = Frame::new(*id, &payload(state, left)).and_then(|f| socket.send(&f));
+            if let Err(e) = sent.context("Failed to send CAN heartbeat") {
+                warn!("{:#}", e);
+            }
+        }
+    }
+}
+
+impl EventSource for Heartbeat {
+    fn register(&mut self, registry: &Registry, token: Token) -> Result<()> {
+        self.timer.register(registry, token)
+    }
+
+    fn handle_ready(&mut self, _index: usize, ready: &mut Ready) -> Result<()> {
+        let (state, left) = match ready.death_row.left() {
+            Some((_, left)) => (State::DeathRow, Some(left)),
+            None => (
+                ready.pingee.state(),
+                ready.retry.run(|| ready.pingee.deadline())?,
+            ),
+        };
+        self.on_tick(state.name(), left)
+    }
+}
+
+/// "HEARTBEAT <state> <seconds left>", with "-" for nothing counting down
+fn packet(state: &str, left: Option<Duration>) -> String {
+    match left {
+        Some(left) => format!("HEARTBEAT {} {:.1}", state, left.as_secs_f64()),
+        None => format!("HEARTBEAT {} -", state),
+    }
+}
+
+/// The state as a code, then the seconds left as a little endian u16, 0xffff for none
+fn payload(state: &str, left: Option<Duration>) -> [u8; 4] {
+    let code = match state {
+        "armed" => 0,
+        "inhibited" => 1,
+        "disarmed" => 2,
+        "death_row" => 3,
+        "startup_inhibit" => 4,
+        "dying" => 5,
+        "maintenance" => 6,
+        _ => 0xff,
+    };
+    let seconds = left.map_or(u16::MAX, |left| {
+        left.as_secs().min(u64::from(u16::MAX - 1)) as u16
+    });
+    let [low, high] = seconds.to_le_bytes();
+    [CAN_HEARTBEAT, code, low, high]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::Monotonic, config::Config};
+
+    #[test]
+    fn test_heartbeat() -> Result<()> {
+        let listener = UdpSocket::bind("127.0.0.1:0")?;
+        listener.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let config = Config::parse(&format!(
+            "[heartbeat]\ninterval = 0.1\ntargets = [\"{}\"]",
+            listener.local_addr()?
+        ))?;
+        let mut heartbeat = Heartbeat::new(config.heartbeat.as_ref().unwrap(), &Monotonic)?;
+
+        heartbeat.on_tick("armed", Some(Duration::from_millis(2500)))?;
+        let mut buf = [0; 64];
+        let len = listener.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"HEARTBEAT armed 2.5");
+        heartbeat.on_tick("disarmed", None)?;
+        let len = listener.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"HEARTBEAT disarmed -");
+
+        assert_eq!(
+            payload("death_row", Some(Duration::from_secs(300))),
+            [0x02, 3, 0x2c, 0x01]
+        );
+        assert_eq!(payload("disarmed", None), [0x02, 2, 0xff, 0xff]);
+        Ok(())
+    }
+}
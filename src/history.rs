@@ -0,0 +1,180 @@
+use crate::{config::Criticality, state::State};
+use std::{
+    collections::VecDeque,
+    fmt,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Enough for a few minutes of petting and pinging
+const LEN: usize = 256;
+
+static HISTORY: Mutex<VecDeque<(Instant, Event)>> = Mutex::new(VecDeque::new());
+
+/// Something worth knowing about after an unexplained reset
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Pet {
+        value: bool,
+    },
+    /// The line really went to `value`, as a mirror input saw it, this long after the write
+    Edge {
+        value: bool,
+        lag: Option<Duration>,
+    },
+    /// The pet timer fired this much later than it was due
+    LateWakeup {
+        late: Duration,
+    },
+    Ping {
+        client: String,
+        source: SocketAddr,
+    },
+    /// A client's deadline was moved out by this much
+    TimerReset {
+        client: String,
+        timeout: Duration,
+    },
+    State {
+        from: State,
+        to: State,
+        cause: String,
+    },
+    /// A client missed a window but has strikes left
+    Strike {
+        client: String,
+        strikes: u32,
+        max: u32,
+    },
+    /// A commanded inhibit was granted
+    Inhibit {
+        duration: Duration,
+        name: String,
+    },
+    /// A named inhibit was revoked before it ran out
+    Uninhibit {
+        name: String,
+    },
+    /// Sent during the death row, this long before the petting stops
+    DeathWarning {
+        left: Duration,
+        reason: String,
+    },
+    /// The wall clock was stepped or the card suspended
+    Anomaly {
+        what: String,
+    },
+    /// A rung of the notification ladder was taken, this long before the client times out
+    Rung {
+        rung: &'static str,
+        client: String,
+        left: Duration,
+    },
+    /// An advisory client missed its first window since pinging, which isn't fatal
+    Missed {
+        client: String,
+    },
+    /// CRITICALITY changed what a client's silence does
+    Criticality {
+        client: String,
+        criticality: Criticality,
+    },
+    /// A companion card went silent and was power cycled
+    PeerCycle {
+        peer: String,
+    },
+    /// The config couldn't be loaded, the safe defaults run instead
+    DegradedConfig {
+        error: String,
+    },
+    /// Right before the panic hook exits
+    Panic {
+        message: String,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Pet { value } => write!(f, "pet {}", if *value { "high" } else { "low" }),
+            Event::Edge { value, lag } => {
+                write!(f, "edge {}", if *value { "high" } else { "low" })?;
+                match lag {
+                    Some(lag) => write!(f, " {:?} after the write", lag),
+                    None => f.write_str(" without a write"),
+                }
+            }
+            Event::LateWakeup { late } => write!(f, "late wakeup by {:?}", late),
+            Event::Ping { client, source } => write!(f, "ping from {} client ({})", client, source),
+            Event::TimerReset { client, timeout } => {
+                write!(f, "{} client timer reset to {:?}", client, timeout)
+            }
+            Event::State { from, to, cause } => write!(f, "state {} -> {}: {}", from, to, cause),
+            Event::Strike {
+                client,
+                strikes,
+                max,
+            } => write!(f, "{} client strike {} of {}", client, strikes, max),
+            Event::Inhibit { duration, name } => {
+                write!(f, "inhibited for {:?} ({})", duration, name)
+            }
+            Event::Uninhibit { name } => write!(f, "inhibit {} revoked", name),
+            Event::DeathWarning { left, reason } => {
+                write!(f, "death row warning, {:?} left: {}", left, reason)
+            }
+            Event::Anomaly { what } => write!(f, "time anomaly: {}", what),
+            Event::Rung { rung, client, left } => {
+                write!(f, "{} client ladder {}, {:?} left", client, rung, left)
+            }
+            Event::Missed { client } => write!(f, "{} advisory client stopped pinging", client),
+            Event::Criticality {
+                client,
+                criticality,
+            } => write!(f, "{} client made {}", client, criticality.name()),
+            Event::PeerCycle { peer } => write!(f, "{} peer power cycled", peer),
+            Event::DegradedConfig { error } => write!(f, "degraded config: {}", error),
+            Event::Panic { message } => write!(f, "panic: {}", message),
+        }
+    }
+}
+
+/// Keeps the event, dropping the oldest one if full, and queues it for the sinks
+pub fn record(event: Event) {
+    #[cfg(target_os = "linux")]
+    crate::sinks::forward(&event);
+    let mut history = HISTORY.lock().unwrap_or_else(|e| e.into_inner());
+    if history.len() == LEN {
+        history.pop_front();
+    }
+    history.push_back((Instant::now(), event));
+}
+
+/// The recorded events, oldest first, each with how many seconds ago it happened
+pub fn dump() -> Vec<String> {
+    let now = Instant::now();
+    let history = HISTORY.lock().unwrap_or_else(|e| e.into_inner());
+    history
+        .iter()
+        .map(|(at, event)| format!("-{:.3} {}", (now - *at).as_secs_f64(), event))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        for _ in 0..LEN {
+            record(Event::Pet { value: true });
+        }
+        let late = Duration::from_millis(120);
+        record(Event::LateWakeup { late });
+        let dump = dump();
+        assert_eq!(dump.len(), LEN);
+        // Other tests record events too
+        let line = dump.iter().find(|l| l.ends_with(" late wakeup by 120ms"));
+        assert!(line.unwrap().starts_with("-0.0"));
+    }
+}
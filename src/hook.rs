@@ -0,0 +1,171 @@
+use crate::{
+    clock::{Clock, Monotonic, Timer},
+    config::HookConfig,
+    exit::Cause,
+    process::pidfd_open,
+};
+use anyhow::Result;
+use log::{info, warn};
+use mio::{unix::SourceFd, Interest, Registry, Token};
+use std::{
+    io::{BufRead, BufReader, Read},
+    os::fd::{AsRawFd, OwnedFd},
+    process::{Child, Command, Stdio},
+    thread,
+};
+
+/// A command run once the watchdog has decided to die, before the petting stops, e.g. to sync
+/// filesystems and flush the telemetry cache
+pub struct Hook {
+    config: HookConfig,
+}
+
+/// A started hook. Both its pidfd and timeout timer are registered with the same token.
+pub struct Running {
+    child: Child,
+    pidfd: OwnedFd,
+    timer: Box<dyn Timer>,
+}
+
+impl Hook {
+    pub fn new(config: &HookConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    /// Starts the hook with the reason for dying as `WATCHDOG_REASON`. None if there is no hook,
+    /// or if it failed to start, since that mustn't keep the watchdog alive.
+    pub fn start(
+        &self,
+        registry: &Registry,
+        token: Token,
+        cause: Cause,
+        reason: &str,
+    ) -> Option<Running> {
+        let (program, args) = self.config.command.split_first()?;
+        match self.spawn(program, args, cause, reason, registry, token) {
+            Ok(running) => Some(running),
+            Err(e) => {
+                warn!("Failed to run hook {}: {:#}", program, e);
+                None
+            }
+        }
+    }
+
+    fn spawn(
+        &self,
+        program: &str,
+        args: &[String],
+        cause: Cause,
+        reason: &str,
+        registry: &Registry,
+        token: Token,
+    ) -> Result<Running> {
+        let mut child = Command::new(program)
+            .args(args)
+            .env("WATCHDOG_CAUSE", cause.name())
+            .env("WATCHDOG_REASON", reason)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        info!("Running hook {} ({})", program, child.id());
+        if let Some(stdout) = child.stdout.take() {
+            log_lines(stdout, "stdout");
+        }
+        if let Some(stderr) = child.stderr.take() {
+            log_lines(stderr, "stderr");
+        }
+        let running = Running {
+            pidfd: pidfd_open(child.id() as i32)?,
+            child,
+            timer: Monotonic.timer()?,
+        };
+        running.timer.set(self.config.timeout)?;
+        registry.register(
+            &mut SourceFd(&running.pidfd.as_raw_fd()),
+            token,
+            Interest::READABLE,
+        )?;
+        running.timer.register(registry, token)?;
+        Ok(running)
+    }
+}
+
+/// Logs each line of the hook's output from a thread, until it closes the pipe
+fn log_lines(pipe: impl Read + Send + 'static, name: &'static str) {
+    thread::spawn(move || {
+        for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+            info!("hook {}: {}", name, line);
+        }
+    });
+}
+
+impl Running {
+    /// Returns whether the hook is done, either having exited or run out of time
+    pub fn on_event(&mut self) -> Result<bool> {
+        if let Some(status) = self.child.try_wait()? {
+            info!("Hook finished with {}", status);
+            return Ok(true);
+        }
+        Ok(self.timer.remaining()?.is_zero())
+    }
+
+    /// Kills the hook if it's still running
+    pub fn finish(mut self, registry: &Registry) -> Result<()> {
+        registry.deregister(&mut SourceFd(&self.pidfd.as_raw_fd()))?;
+        self.timer.deregister(registry)?;
+        if self.child.try_wait()?.is_none() {
+            warn!("Hook timed out, killing it");
+            self.child.kill()?;
+            self.child.wait()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mio::{Events, Poll};
+    use std::time::{Duration, Instant};
+
+    fn run(command: &[&str], timeout: f64) -> Result<Duration> {
+        let mut poll = Poll::new()?;
+        let mut events = Events::with_capacity(8);
+        let hook = Hook::new(&HookConfig {
+            command: command.iter().map(|s| s.to_string()).collect(),
+            timeout: Duration::from_secs_f64(timeout),
+        });
+        let start = Instant::now();
+        let mut running = hook
+            .start(poll.registry(), Token(0), Cause::Other, "test")
+            .unwrap();
+        while !running.on_event()? {
+            poll.poll(&mut events, Some(Duration::from_secs(2)))?;
+        }
+        running.finish(poll.registry())?;
+        Ok(start.elapsed())
+    }
+
+    #[test]
+    fn test_hook() -> Result<()> {
+        let echo = "echo $WATCHDOG_CAUSE $WATCHDOG_REASON";
+        assert!(run(&["sh", "-c", echo], 1.0)? < Duration::from_secs(1));
+        assert!(run(&["sleep", "5"], 0.2)? < Duration::from_secs(1));
+
+        let poll = Poll::new()?;
+        let missing = Hook::new(&HookConfig {
+            command: vec![String::from("/nonexistent")],
+            timeout: Duration::from_secs(1),
+        });
+        assert!(missing
+            .start(poll.registry(), Token(0), Cause::Other, "test")
+            .is_none());
+        assert!(Hook::new(&HookConfig::default())
+            .start(poll.registry(), Token(0), Cause::Other, "test")
+            .is_none());
+        Ok(())
+    }
+}
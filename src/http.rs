@@ -0,0 +1,279 @@
+use crate::{
+    death_row::DeathRow,
+    pingee::Pingee,
+    source::{EventSource, Ready},
+    state::State,
+    status::quote,
+};
+use anyhow::Result;
+use log::debug;
+use mio::{
+    net::{TcpListener, TcpStream},
+    Interest, Registry, Token,
+};
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::SocketAddr,
+    time::Instant,
+};
+
+/// Connections open at once, each with its own token. Past that the oldest is dropped.
+const CONNECTIONS: usize = 16;
+/// Longer requests are dropped, a probe's are a few hundred bytes
+const MAX_REQUEST: usize = 4096;
+
+struct Connection {
+    stream: TcpStream,
+    request: Vec<u8>,
+    accepted: Instant,
+}
+
+/// A tiny HTTP/1.0 server for test orchestration that probes health endpoints. /healthz is 200
+/// while armed and fed and 503 with the reason otherwise, /status has the STATUS reply as JSON.
+/// Each connection gets one response, then is closed.
+pub struct Http {
+    listener: TcpListener,
+    /// The connections', right after the listener's once registered
+    first: Token,
+    connections: Vec<Option<Connection>>,
+}
+
+impl Http {
+    pub fn new(address: SocketAddr) -> Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(address)?,
+            first: Token(0),
+            connections: (0..CONNECTIONS).map(|_| None).collect(),
+        })
+    }
+
+    pub fn on_accept(&mut self, registry: &Registry) -> Result<()> {
+        loop {
+            let mut stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            let slot = match self.connections.iter().position(Option::is_none) {
+                Some(slot) => slot,
+                None => {
+                    let oldest = (0..CONNECTIONS)
+                        .min_by_key(|&slot| self.connections[slot].as_ref().map(|c| c.accepted))
+                        .unwrap();
+                    self.close(oldest, registry)?;
+                    oldest
+                }
+            };
+            let token = Token(self.first.0 + slot);
+            registry.register(&mut stream, token, Interest::READABLE)?;
+            self.connections[slot] = Some(Connection {
+                stream,
+                request: Vec::new(),
+                accepted: Instant::now(),
+            });
+        }
+    }
+
+    /// Reads the request, and responds once it's all there
+    pub fn on_readable(
+        &mut self,
+        token: Token,
+        registry: &Registry,
+        pingee: &Pingee,
+        death_row: &DeathRow,
+    ) -> Result<()> {
+        let slot = token.0 - self.first.0;
+        let Some(connection) = &mut self.connections[slot] else {
+            return Ok(());
+        };
+        let mut buf = [0; 1024];
+        let done = loop {
+            match connection.stream.read(&mut buf) {
+                Ok(0) => break true,
+                Ok(len) => connection.request.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break false,
+                Err(e) => {
+                    debug!("HTTP connection failed: {}", e);
+                    break true;
+                }
+            }
+            if connection.request.len() > MAX_REQUEST {
+                break true;
+            }
+        };
+        if let Some(end) = find(&connection.request, b"\r\n\r\n") {
+            let (status, body) = respond(&connection.request[..end], pingee, death_row)?;
+            let response = format!(
+                "HTTP/1.0 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            // Small enough for the socket buffer, a client that can't take it is dropped
+            if let Err(e) = connection.stream.write_all(response.as_bytes()) {
+                debug!("Failed to send HTTP response: {}", e);
+            }
+        } else if !done {
+            return Ok(());
+        }
+        self.close(slot, registry)
+    }
+
+    fn close(&mut self, slot: usize, registry: &Registry) -> Result<()> {
+        if let Some(mut connection) = self.connections[slot].take() {
+            registry.deregister(&mut connection.stream)?;
+        }
+        Ok(())
+    }
+}
+
+impl EventSource for Http {
+    /// The listener, then each connection
+    fn tokens(&self) -> usize {
+        1 + CONNECTIONS
+    }
+
+    fn register(&mut self, registry: &Registry, token: Token) -> Result<()> {
+        registry.register(&mut self.listener, token, Interest::READABLE)?;
+        self.first = Token(token.0 + 1);
+        Ok(())
+    }
+
+    fn handle_ready(&mut self, index: usize, ready: &mut Ready) -> Result<()> {
+        match index {
+            0 => ready.retry.run(|| self.on_accept(ready.registry)),
+            _ => {
+                let token = Token(self.first.0 + index - 1);
+                self.on_readable(token, ready.registry, ready.pingee, ready.death_row)
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// The status line and JSON body for a request's head
+fn respond(head: &[u8], pingee: &Pingee, death_row: &DeathRow) -> Result<(&'static str, String)> {
+    let line = head.split(|&b| b == b'\r').next().unwrap_or_default();
+    let line = String::from_utf8_lossy(line);
+    let mut words = line.split(' ');
+    let (Some(method), Some(target)) = (words.next(), words.next()) else {
+        return Ok(("400 Bad Request", error("Bad request")));
+    };
+    if method != "GET" {
+        return Ok(("405 Method Not Allowed", error("Only GET is supported")));
+    }
+    let path = target.split('?').next().unwrap_or_default();
+    Ok(match path {
+        "/healthz" => match unhealthy(pingee, death_row)? {
+            None => ("200 OK", String::from("{\"healthy\":true}")),
+            Some(reason) => (
+                "503 Service Unavailable",
+                format!("{{\"healthy\":false,\"reason\":{}}}", quote(&reason)),
+            ),
+        },
+        "/status" => ("200 OK", pingee.status()?.to_json()),
+        _ => ("404 Not Found", error("Not found")),
+    })
+}
+
+fn error(message: &str) -> String {
+    format!("{{\"error\":{}}}", quote(message))
+}
+
+/// Why the watchdog isn't armed and fed, if it isn't
+fn unhealthy(pingee: &Pingee, death_row: &DeathRow) -> Result<Option<String>> {
+    if let Some((reason, _)) = death_row.left() {
+        return Ok(Some(format!("Death row: {}", reason)));
+    }
+    match pingee.state() {
+        State::Armed => Ok(pingee
+            .starved()?
+            .map(|client| format!("{} client starving", client))),
+        state => Ok(Some(state.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::Monotonic, config::Config, exit::Cause};
+    use mio::{Events, Poll};
+    use std::{net::TcpStream, sync::Arc, time::Duration};
+
+    #[test]
+    fn test_http() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            [inhibit]
+            startup = 0.001
+            [death_row]
+            duration = 10
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        let mut death_row = DeathRow::new(&config.death_row, Arc::new(Monotonic))?;
+        let mut poll = Poll::new()?;
+        let mut events = Events::with_capacity(4);
+        let mut http = Http::new("127.0.0.1:0".parse()?)?;
+        http.register(poll.registry(), Token(0))?;
+        let address = http.listener.local_addr()?;
+        // Past the startup inhibit, which is as long as the client has to ping at first
+        std::thread::sleep(Duration::from_millis(10));
+        // As the main loop would after the next event
+        pingee.sync_state(None);
+
+        let mut get = |request: &str, pingee: &Pingee, death_row: &DeathRow| -> Result<String> {
+            let mut client = TcpStream::connect(address)?;
+            client.set_read_timeout(Some(Duration::from_secs(1)))?;
+            client.write_all(request.as_bytes())?;
+            let mut response = String::new();
+            while response.is_empty() {
+                poll.poll(&mut events, Some(Duration::from_secs(1)))?;
+                for event in &events {
+                    match event.token() {
+                        Token(0) => http.on_accept(poll.registry())?,
+                        token => {
+                            http.on_readable(token, poll.registry(), pingee, death_row)?;
+                            client.read_to_string(&mut response)?;
+                        }
+                    }
+                }
+            }
+            Ok(response)
+        };
+
+        let starving = get(
+            "GET /healthz HTTP/1.1\r\nHost: c3\r\n\r\n",
+            &pingee,
+            &death_row,
+        )?;
+        assert!(starving.ends_with("{\"healthy\":false,\"reason\":\"c3 client starving\"}"));
+        pingee.feed(Some("c3"))?;
+        let healthy = get("GET /healthz HTTP/1.1\r\n\r\n", &pingee, &death_row)?;
+        assert!(healthy.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(healthy.ends_with("\r\n\r\n{\"healthy\":true}"));
+        let status = get("GET /status HTTP/1.0\r\n\r\n", &pingee, &death_row)?;
+        assert!(status.contains("{\"state\":\"armed\","));
+
+        pingee.arm(false, "test")?;
+        let disarmed = get("GET /healthz?verbose HTTP/1.0\r\n\r\n", &pingee, &death_row)?;
+        assert!(disarmed.starts_with("HTTP/1.0 503 Service Unavailable\r\n"));
+        assert!(disarmed.ends_with("{\"healthy\":false,\"reason\":\"disarmed\"}"));
+        death_row.sentence(String::from("Up for too long"), Cause::Uptime)?;
+        let sentenced = get("GET /healthz HTTP/1.0\r\n\r\n", &pingee, &death_row)?;
+        assert!(sentenced.ends_with("\"reason\":\"Death row: Up for too long\"}"));
+
+        let missing = get("GET /metrics HTTP/1.0\r\n\r\n", &pingee, &death_row)?;
+        assert!(missing.starts_with("HTTP/1.0 404 Not Found\r\n"));
+        let post = get("POST /healthz HTTP/1.0\r\n\r\n", &pingee, &death_row)?;
+        assert!(post.starts_with("HTTP/1.0 405 "));
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{protocol::Fault, status::StatusReport};
+use log::warn;
+use nix::errno::Errno;
+use std::{
+    io,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+/// The faults INJECT turned on, checked where pings are handled, pets are timed, and the line is
+/// written. Only built with the inject feature, flight builds leave it out.
+struct Faults {
+    drop_pings: u8,
+    /// Percent carried over towards the next drop, so that drops are spread evenly
+    owed: u32,
+    delay_pets: Duration,
+    fail_gpio: u32,
+}
+
+const NONE: Faults = Faults {
+    drop_pings: 0,
+    owed: 0,
+    delay_pets: Duration::ZERO,
+    fail_gpio: 0,
+};
+
+static FAULTS: Mutex<Faults> = Mutex::new(NONE);
+
+fn faults() -> MutexGuard<'static, Faults> {
+    FAULTS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn set(fault: Fault) {
+    warn!("Injecting {}", fault);
+    let mut faults = faults();
+    match fault {
+        Fault::DropPings(percent) => {
+            faults.drop_pings = percent;
+            faults.owed = 0;
+        }
+        Fault::DelayPets(delay) => faults.delay_pets = delay,
+        Fault::FailGpio(writes) => faults.fail_gpio = writes,
+        Fault::Clear => *faults = NONE,
+    }
+}
+
+/// Whether to act as if the ping never arrived
+pub fn drop_ping() -> bool {
+    let mut faults = faults();
+    faults.owed += u32::from(faults.drop_pings);
+    let drop = faults.owed >= 100;
+    if drop {
+        faults.owed -= 100;
+    }
+    drop
+}
+
+/// How long to stall before petting
+pub fn pet_delay() -> Duration {
+    faults().delay_pets
+}
+
+/// Fails with EIO while writes are to fail, which the retries count as transient
+pub fn gpio_write() -> io::Result<()> {
+    let mut faults = faults();
+    if faults.fail_gpio == 0 {
+        return Ok(());
+    }
+    faults.fail_gpio -= 1;
+    Err(io::Error::from_raw_os_error(Errno::EIO as i32))
+}
+
+pub fn report(status: &mut StatusReport) {
+    let faults = faults();
+    status.push("inject.drop_pings", faults.drop_pings);
+    status.push("inject.delay_pets", faults.delay_pets.as_secs_f64());
+    status.push("inject.fail_gpio", faults.fail_gpio);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_faults() {
+        set(Fault::DropPings(25));
+        let dropped: Vec<_> = (0..8).map(|_| drop_ping()).collect();
+        assert_eq!(dropped.iter().filter(|&&d| d).count(), 2);
+        assert!(dropped[3] && dropped[7]);
+
+        set(Fault::FailGpio(2));
+        assert!(gpio_write().is_err());
+        assert_eq!(gpio_write().unwrap_err().raw_os_error(), Some(libc::EIO));
+        assert!(gpio_write().is_ok());
+
+        set(Fault::DelayPets(Duration::from_millis(300)));
+        let mut status = StatusReport::new();
+        report(&mut status);
+        assert_eq!(status.get("inject.delay_pets"), Some("0.3"));
+        set(Fault::Clear);
+        assert_eq!(pet_delay(), Duration::ZERO);
+        assert!(!drop_ping());
+    }
+}
@@ -0,0 +1,258 @@
+use crate::{
+    config::{Config, PanicLine},
+    exit::Cause,
+};
+use anyhow::{ensure, Context, Result};
+use std::{
+    collections::BTreeSet,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Where `write` puts the units by default
+pub const UNIT_DIR: &str = "/etc/systemd/system";
+/// The units' name, without the suffix
+pub const NAME: &str = "oresat-c3-watchdog";
+
+// WatchdogSec is never shorter than this, a loaded card can stall the loop for a few seconds
+const MIN_WATCHDOG: Duration = Duration::from_secs(10);
+
+/// How the service is started
+pub struct Exec {
+    pub program: PathBuf,
+    pub config: PathBuf,
+    pub gpio_chip: Option<String>,
+}
+
+/// The service unit, matching `config`: socket activated on the ping sockets, ordered after
+/// what the config talks to, and with a systemd watchdog that the event loop keeps happy
+pub fn service(config: &Config, exec: &Exec) -> String {
+    let mut unit = String::from("[Unit]\nDescription=Pets the hardware watchdog on the C3\n");
+    let socket = format!("{}.socket", NAME);
+    let _ = writeln!(unit, "Requires={}\nAfter={}", socket, socket);
+    for dependency in dependencies(config) {
+        let _ = writeln!(unit, "Wants={}\nAfter={}", dependency, dependency);
+    }
+    let mut exec_start = vec![exec.program.display().to_string()];
+    exec_start.extend([String::from("--config"), exec.config.display().to_string()]);
+    if let Some(chip) = &exec.gpio_chip {
+        exec_start.extend([String::from("--gpio-chip"), chip.clone()]);
+    }
+    let exec_start: Vec<_> = exec_start.iter().map(|arg| quote(arg)).collect();
+    let _ = write!(
+        unit,
+        "\n[Service]
+Type=simple
+# For the self-test result in the unit's status, and the watchdog keepalives
+NotifyAccess=main
+ExecStart={}
+# Rereads the command authentication keys
+ExecReload=/bin/kill -HUP $MAINPID
+WatchdogSec={}
+# A restart after a ping timeout, commanded reset, deliberate reset, a startup gate biting, or a
+# safe-low panic would get the petting going again. Other failures, like the GPIO, a socket, or the
+# loop stalling, are restarted on purpose, the supervisor still bites if the restart doesn't pet in
+# time.
+Restart=on-failure
+RestartPreventExitStatus={}
+CPUQuota=100%
+CPUWeight=1000
+
+[Install]
+WantedBy=multi-user.target
+",
+        exec_start.join(" "),
+        watchdog_sec(config).as_secs(),
+        restart_prevented(config)
+    );
+    unit
+}
+
+/// The socket unit, a datagram socket for the address and for each extra port, in the order
+/// the daemon expects them
+pub fn socket(config: &Config) -> Result<String> {
+    ensure!(
+        config.address.port() != 0,
+        "Socket activation needs a fixed port, not {}",
+        config.address
+    );
+    let mut unit = String::from("[Unit]\nDescription=Ping sockets of the C3 watchdog\n");
+    unit.push_str("\n[Socket]\n");
+    let _ = writeln!(unit, "ListenDatagram={}", config.address);
+    for port in &config.ports {
+        let mut address = config.address;
+        address.set_port(port.port);
+        let _ = writeln!(unit, "ListenDatagram={}", address);
+    }
+    // Bound before the network is up
+    let ip = config.address.ip();
+    if !ip.is_loopback() && !ip.is_unspecified() {
+        unit.push_str("FreeBind=yes\n");
+    }
+    unit.push_str("\n[Install]\nWantedBy=sockets.target\n");
+    Ok(unit)
+}
+
+/// Writes both units into `dir`, replacing any earlier ones, and returns their paths
+pub fn write(config: &Config, exec: &Exec, dir: &Path) -> Result<Vec<PathBuf>> {
+    let units = [
+        (format!("{}.service", NAME), service(config, exec)),
+        (format!("{}.socket", NAME), socket(config)?),
+    ];
+    let mut paths = Vec::new();
+    for (name, unit) in units {
+        let path = dir.join(name);
+        fs::write(&path, unit).with_context(|| format!("Failed to write {:?}", path))?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// The exit codes that mean to get the card reset, which a restart would pet away
+fn restart_prevented(config: &Config) -> String {
+    let mut causes = vec![
+        Cause::PingTimeout,
+        Cause::Reset,
+        Cause::Uptime,
+        Cause::Scheduled,
+        Cause::StartupGate,
+    ];
+    // Leaving the line as it is on a panic is for the restart to pet before the supervisor bites
+    if config.panic_line == PanicLine::SafeLow {
+        causes.push(Cause::Panic);
+    }
+    let codes: Vec<_> = causes.iter().map(|&c| (c as u8).to_string()).collect();
+    codes.join(" ")
+}
+
+/// Ten of the slowest pet cycles, so that a late pet or two never gets the loop killed, and long
+/// enough to wait out a busy line at startup
+pub fn watchdog_sec(config: &Config) -> Duration {
+    let low_power = config.low_power.iter().map(|l| &l.pattern);
+    let profiles = config.profiles.iter().map(|p| &p.pattern);
+    let slowest = low_power
+        .chain(profiles)
+        .map(|pattern| pattern.iter().map(|step| step.duration).sum())
+        .fold(config.pet.period(), Duration::max);
+    let watchdog = (slowest * 10)
+        .max(config.pet.busy_timeout)
+        .max(MIN_WATCHDOG);
+    // Whole seconds, rounded up
+    Duration::from_secs(watchdog.as_secs() + u64::from(watchdog.subsec_nanos() > 0))
+}
+
+/// The units the daemon needs up first: the network unless everything is on loopback, the system
+/// bus for the unit watchers and signals, and each CAN interface
+fn dependencies(config: &Config) -> BTreeSet<String> {
+    let mut dependencies = BTreeSet::new();
+    let mut addresses = std::iter::once(config.address)
+        .chain(config.lines.iter().map(|l| l.address))
+        .chain(config.peers.iter().filter_map(|p| p.address));
+    if addresses.any(|a| !a.ip().is_loopback()) {
+        dependencies.insert(String::from("network-online.target"));
+    }
+    if !config.units.is_empty() || config.dbus_signals {
+        dependencies.insert(String::from("dbus.service"));
+    }
+    let mut interfaces: Vec<&str> = Vec::new();
+    let can = [
+        config.death_row.can.as_ref(),
+        config.heartbeat.as_ref().and_then(|h| h.can.as_ref()),
+        config.margin.as_ref().and_then(|m| m.can.as_ref()),
+        config.late_pet.can.as_ref(),
+        config.low_power.as_ref().and_then(|l| l.can.as_ref()),
+        config.profile_can.as_ref(),
+    ];
+    interfaces.extend(can.into_iter().flatten().map(|c| c.interface.as_str()));
+    interfaces.extend(config.can_silence.iter().map(|c| c.interface.as_str()));
+    interfaces.extend(config.canopen.iter().map(|c| c.interface.as_str()));
+    interfaces.extend(config.can_pings.iter().map(|c| c.interface.as_str()));
+    let peers = config.peers.iter().filter_map(|p| p.can.as_ref());
+    interfaces.extend(peers.map(|c| c.interface.as_str()));
+    for interface in interfaces {
+        dependencies.insert(format!("sys-subsystem-net-devices-{}.device", interface));
+    }
+    dependencies
+}
+
+/// A command line argument, quoted with systemd's specifiers and variables escaped
+fn quote(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '"' | '\\' => quoted.push('\\'),
+            '%' | '$' => quoted.push(c),
+            _ => (),
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_units() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            address = "192.168.6.2:20001"
+            dbus_signals = true
+            [[port]]
+            name = "camera"
+            port = 20002
+            [can_silence]
+            interface = "can0"
+            [death_row]
+            can = { interface = "can0", id = 0x7c3 }
+            "#,
+        )?;
+        let exec = Exec {
+            program: PathBuf::from("/usr/bin/oresat-c3-watchdog"),
+            config: PathBuf::from("/etc/oresat 100%.toml"),
+            gpio_chip: Some(String::from("gpiochip2")),
+        };
+        let service = service(&config, &exec);
+        assert!(service.contains("Requires=oresat-c3-watchdog.socket\n"));
+        assert!(service.contains("Wants=network-online.target\nAfter=network-online.target\n"));
+        assert!(service.contains("After=dbus.service\n"));
+        assert_eq!(service.matches("can0.device").count(), 2);
+        assert!(service.contains(
+            "ExecStart=\"/usr/bin/oresat-c3-watchdog\" \"--config\" \"/etc/oresat 100%%.toml\" \
+             \"--gpio-chip\" \"gpiochip2\"\n"
+        ));
+        assert!(service.contains("WatchdogSec=10\n"));
+        assert!(service.contains("RestartPreventExitStatus=2 5 6 7 9 11\n"));
+        let left = Config::parse("address = \"127.0.0.1:0\"\npanic_line = \"leave-as-is\"")?;
+        let left = super::service(&left, &exec);
+        assert!(left.contains("RestartPreventExitStatus=2 5 6 7 9\n"));
+
+        let socket = socket(&config)?;
+        assert!(socket.contains(
+            "ListenDatagram=192.168.6.2:20001\nListenDatagram=192.168.6.2:20002\nFreeBind=yes\n"
+        ));
+
+        let local = Config::parse("address = \"127.0.0.1:0\"")?;
+        assert!(!super::service(&local, &exec).contains("Wants="));
+        assert!(super::socket(&local).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_watchdog_sec() -> Result<()> {
+        let slow = Config::parse(
+            r#"
+            [pet]
+            pattern = [{ high = true, duration = 0.1 }, { high = false, duration = 1.45 }]
+            "#,
+        )?;
+        assert_eq!(watchdog_sec(&slow), Duration::from_secs(16));
+        let busy = Config::parse("[pet]\nbusy = \"wait\"\nbusy_timeout = 60")?;
+        assert_eq!(watchdog_sec(&busy), Duration::from_secs(60));
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::{Context, Result};
+use log::info;
+use nix::{
+    errno::Errno,
+    sys::socket::{bind, socket, AddressFamily, SockFlag, SockType, UnixAddr},
+};
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+
+/// Held for as long as this instance pets the watchdog line, so that a second one started by
+/// accident fails right away instead of fighting over the chip and the ports. It's a name in the
+/// abstract socket namespace, which the kernel gives up however the process goes down. An
+/// instance that was handed over to takes it once its predecessor has exited.
+pub struct InstanceLock {
+    name: String,
+    socket: OwnedFd,
+    held: bool,
+}
+
+impl InstanceLock {
+    /// Tries to take the lock on the line `name`, see `held`
+    pub fn acquire(name: &str) -> Result<Self> {
+        let socket = socket(
+            AddressFamily::Unix,
+            SockType::Datagram,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        let mut lock = Self {
+            name: name.to_string(),
+            socket,
+            held: false,
+        };
+        lock.take()?;
+        Ok(lock)
+    }
+
+    /// The lock a re-executed image kept
+    pub fn inherit(name: &str, socket: OwnedFd) -> Self {
+        Self {
+            name: name.to_string(),
+            socket,
+            held: true,
+        }
+    }
+
+    /// Whether this instance has it, rather than another one
+    pub fn held(&self) -> bool {
+        self.held
+    }
+
+    /// For keeping it across a re-exec, None unless held
+    pub fn fd(&self) -> Option<RawFd> {
+        self.held.then(|| self.socket.as_raw_fd())
+    }
+
+    /// The socket already exists, so that this works under the syscall filter
+    fn take(&mut self) -> Result<()> {
+        let address =
+            UnixAddr::new_abstract(format!("oresat-c3-watchdog/{}", self.name).as_bytes())?;
+        match bind(self.socket.as_raw_fd(), &address) {
+            Ok(()) => self.held = true,
+            Err(Errno::EADDRINUSE) => (),
+            Err(e) => return Err(e).with_context(|| format!("Failed to lock {}", self.name)),
+        }
+        Ok(())
+    }
+
+    /// Tries again until it's held, for once the line has been handed over
+    pub fn on_pet(&mut self) -> Result<()> {
+        if !self.held {
+            self.take()?;
+            if self.held {
+                info!(
+                    "Took the instance lock on {} from the previous instance",
+                    self.name
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instance_lock() -> Result<()> {
+        let name = format!("test-{}", std::process::id());
+        let first = InstanceLock::acquire(&name)?;
+        assert!(first.held());
+        let mut second = InstanceLock::acquire(&name)?;
+        assert!(!second.held());
+        second.on_pet()?;
+        assert!(!second.held());
+        drop(first);
+        second.on_pet()?;
+        assert!(second.held());
+        Ok(())
+    }
+}
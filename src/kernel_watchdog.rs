@@ -0,0 +1,109 @@
+use crate::config::KernelWatchdogConfig;
+use anyhow::{bail, Context, Result};
+use log::info;
+use std::{
+    fs::{File, OpenOptions},
+    io::{Error, Write},
+    os::fd::AsRawFd,
+    path::PathBuf,
+    time::Duration,
+};
+
+// WDIOC_SETTIMEOUT, _IOWR('W', 6, int)
+const WDIOC_SETTIMEOUT: u64 = 0xC004_5706;
+
+/// A kernel watchdog device, like the softdog or omap_wdt, fed on every pet of the line. It's a
+/// second way back from a lockup that keeps the supervisor fed, e.g. a stuck kernel still running
+/// the pet timer. It's never closed with the magic character, so it keeps counting after an exit
+/// and whoever pets next has to feed it in time too.
+pub struct KernelWatchdog {
+    path: PathBuf,
+    timeout: Duration,
+    /// None while a predecessor handing over still has it open
+    file: Option<File>,
+}
+
+impl KernelWatchdog {
+    /// Opens the device, or leaves that to a later pet if `waiting` and it's still open
+    /// elsewhere. The timeout is set on opening, unless it's zero.
+    pub fn open(config: &KernelWatchdogConfig, waiting: bool) -> Result<Self> {
+        let mut watchdog = Self {
+            path: config.path.clone(),
+            timeout: config.timeout,
+            file: None,
+        };
+        if !watchdog.try_open()? && !waiting {
+            bail!("{:?} is already open elsewhere", watchdog.path);
+        }
+        Ok(watchdog)
+    }
+
+    /// Whether it's open now
+    fn try_open(&mut self) -> Result<bool> {
+        let file = match OpenOptions::new().write(true).open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.raw_os_error() == Some(libc::EBUSY) => return Ok(false),
+            Err(e) => return Err(e).with_context(|| format!("Failed to open {:?}", self.path)),
+        };
+        if !self.timeout.is_zero() {
+            let mut seconds = self.timeout.as_secs() as libc::c_int;
+            // SAFETY: the ioctl reads and writes back the one int
+            let ret = unsafe {
+                libc::ioctl(
+                    file.as_raw_fd(),
+                    WDIOC_SETTIMEOUT as libc::Ioctl,
+                    &mut seconds,
+                )
+            };
+            if ret < 0 {
+                return Err(Error::last_os_error())
+                    .with_context(|| format!("Failed to set the timeout of {:?}", self.path));
+            }
+        }
+        self.file = Some(file);
+        Ok(true)
+    }
+
+    /// Feeds it, opening it first once the predecessor has let go
+    pub fn on_pet(&mut self) -> Result<()> {
+        if self.file.is_none() {
+            if !self.try_open()? {
+                return Ok(());
+            }
+            info!("Feeding {:?} from the previous instance", self.path);
+        }
+        if let Some(file) = &mut self.file {
+            file.write_all(b"\0")
+                .with_context(|| format!("Failed to feed {:?}", self.path))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_on_pet() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("watchdog-kernel-{}", std::process::id()));
+        fs::write(&path, "")?;
+        let config = KernelWatchdogConfig {
+            path: path.clone(),
+            timeout: Duration::ZERO,
+        };
+        let mut watchdog = KernelWatchdog::open(&config, false)?;
+        watchdog.on_pet()?;
+        watchdog.on_pet()?;
+        assert_eq!(fs::read(&path)?, [0, 0]);
+        // A regular file has no timeout to set
+        let timed = KernelWatchdogConfig {
+            timeout: Duration::from_secs(10),
+            ..config
+        };
+        assert!(KernelWatchdog::open(&timed, false).is_err());
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
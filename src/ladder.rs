@@ -0,0 +1,248 @@
+use crate::{
+    can::{CanSocket, Frame},
+    config::{HookConfig, LadderConfig},
+    exit::Cause,
+    history::{self, Event},
+    hook::{Hook, Running},
+    net,
+    pingee::Pingee,
+};
+use anyhow::{Context, Result};
+use log::{info, warn};
+use mio::{Registry, Token};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+// CANopen EMCY: the ping timeout's error code, error register bit 0 (generic), then the share of
+// the timeout gone by in percent and the milliseconds left as a little endian u16. Code 0 clears
+// it once the client pinged.
+const EMCY_RESET: u16 = 0x0000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Rung {
+    Notice,
+    Emcy,
+    Hook,
+}
+
+impl Rung {
+    fn name(self) -> &'static str {
+        match self {
+            Rung::Notice => "notice",
+            Rung::Emcy => "emcy",
+            Rung::Hook => "hook",
+        }
+    }
+}
+
+/// Escalates as the client closest to timing out runs down its timeout, a rung at a time: a
+/// NOTICE packet, a CANopen EMCY, then the hook, so that the flight software hears about it well
+/// before the bite. Each rung is taken once per countdown, until a ping moves the client back
+/// under it.
+pub struct Ladder {
+    /// The share of the timeout each rung is taken at, with whether it was
+    rungs: Vec<(Rung, f64, bool)>,
+    targets: Vec<(UdpSocket, SocketAddr)>,
+    emcy: Option<(CanSocket, u32)>,
+    hook: Hook,
+    token: Token,
+    /// The hook started by the last rung, while it runs
+    running: Option<Running>,
+}
+
+impl Ladder {
+    /// The hook is registered with `token` while it runs. Rungs without anything to do are left
+    /// out, like a notice without targets.
+    pub fn new(config: &LadderConfig, hook: &HookConfig, token: Token) -> Result<Self> {
+        let emcy = match &config.can {
+            Some(can) => Some((CanSocket::open(&can.interface)?, can.id)),
+            None => None,
+        };
+        let rungs = [
+            (Rung::Notice, config.notice, !config.targets.is_empty()),
+            (Rung::Emcy, config.emcy, emcy.is_some()),
+            (Rung::Hook, config.hook, !hook.command.is_empty()),
+        ]
+        .into_iter()
+        .filter(|&(_, at, useful)| at > 0.0 && useful)
+        .map(|(rung, at, _)| (rung, at, false))
+        .collect();
+        Ok(Self {
+            rungs,
+            targets: net::senders(&config.targets)?,
+            emcy,
+            hook: Hook::new(hook),
+            token,
+            running: None,
+        })
+    }
+
+    /// Takes the rungs the countdown reached, and lets go of the ones it's back under
+    pub fn on_pet(&mut self, pingee: &Pingee, registry: &Registry) -> Result<()> {
+        let Some((client, left, gone)) = pingee.countdown()? else {
+            return Ok(());
+        };
+        for i in 0..self.rungs.len() {
+            let (rung, at, taken) = self.rungs[i];
+            if gone < at && taken {
+                self.rungs[i].2 = false;
+                if rung == Rung::Emcy {
+                    self.send_emcy(EMCY_RESET, gone, left);
+                }
+            } else if gone >= at && !taken {
+                self.rungs[i].2 = true;
+                warn!(
+                    "{} client is {:.0}% into its timeout, {:?} left: {}",
+                    client,
+                    gone * 100.0,
+                    left,
+                    rung.name()
+                );
+                history::record(Event::Rung {
+                    rung: rung.name(),
+                    client: client.to_string(),
+                    left,
+                });
+                self.take(rung, client, gone, left, registry);
+            }
+        }
+        Ok(())
+    }
+
+    fn take(&mut self, rung: Rung, client: &str, gone: f64, left: Duration, registry: &Registry) {
+        match rung {
+            Rung::Notice => {
+                let packet = format!("NOTICE {} {:.1}", client, left.as_secs_f64());
+                for (socket, target) in &self.targets {
+                    if let Err(e) = socket.send_to(packet.as_bytes(), target) {
+                        warn!("Failed to send notice to {}: {}", target, e);
+                    }
+                }
+            }
+            Rung::Emcy => self.send_emcy(Cause::PingTimeout.emcy(), gone, left),
+            // Still running from an earlier countdown, it's not started twice
+            Rung::Hook if self.running.is_some() => (),
+            Rung::Hook => {
+                let reason = format!("{} client is running out of time", client);
+                let cause = Cause::PingTimeout;
+                self.running = self.hook.start(registry, self.token, cause, &reason);
+            }
+        }
+    }
+
+    /// Best effort, like the heartbeats
+    fn send_emcy(&self, code: u16, gone: f64, left: Duration) {
+        let Some((socket, id)) = &self.emcy else {
+            return;
+        };
+        let millis = left.as_millis().min(u16::MAX.into()) as u16;
+        let mut payload = [0; 8];
+        payload[..2].copy_from_slice(&code.to_le_bytes());
+        payload[2] = (code != EMCY_RESET) as u8;
+        payload[3] = (gone * 100.0).min(100.0) as u8;
+        payload[4..6].copy_from_slice(&millis.to_le_bytes());
+        let sent = Frame::new(*id, &payload).and_then(|f| socket.send(&f));
+        if let Err(e) = sent.context("Failed to send EMCY") {
+            warn!("{:#}", e);
+        }
+    }
+
+    /// Called when the hook's token is ready
+    pub fn on_hook(&mut self, registry: &Registry) -> Result<()> {
+        if let Some(running) = &mut self.running {
+            if running.on_event()? {
+                self.running.take().unwrap().finish(registry)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the hook rung was taken in this countdown, so that dying doesn't run the hook
+    /// again, and the still running hook to wait for if so
+    pub fn take_hook(&mut self) -> Option<Option<Running>> {
+        let taken = self
+            .rungs
+            .iter()
+            .any(|&(rung, _, taken)| rung == Rung::Hook && taken);
+        if taken {
+            info!("Hook already run as the ping timeout neared");
+        }
+        taken.then(|| self.running.take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::VirtualClock, config::Config};
+    use mio::Poll;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_ladder() -> Result<()> {
+        let listener = UdpSocket::bind("127.0.0.1:0")?;
+        listener.set_read_timeout(Some(Duration::from_millis(100)))?;
+        let config = Config::parse(&format!(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            timeout = 10
+            [inhibit]
+            startup = 10
+            [ladder]
+            targets = ["{}"]
+            hook = 0.9
+            [hook]
+            command = ["true"]
+            "#,
+            listener.local_addr()?
+        ))?;
+        let poll = Poll::new()?;
+        let clock = VirtualClock::new();
+        let transport = Box::new(mio::net::UdpSocket::bind(config.address)?);
+        let mut pingee = Pingee::with_clock(
+            &config,
+            Token(10),
+            Token(90),
+            transport,
+            Arc::new(clock.clone()),
+        )?;
+        let ladder_config = config.ladder.as_ref().unwrap();
+        let mut ladder = Ladder::new(ladder_config, &config.hook, Token(5))?;
+        // Without CAN there's no EMCY rung
+        assert_eq!(ladder.rungs.len(), 2);
+
+        clock.advance(Duration::from_secs(10));
+        pingee.feed(Some("c3"))?;
+        clock.advance(Duration::from_secs(4));
+        ladder.on_pet(&pingee, poll.registry())?;
+        let mut buf = [0; 64];
+        assert!(listener.recv(&mut buf).is_err());
+
+        clock.advance(Duration::from_secs(2));
+        ladder.on_pet(&pingee, poll.registry())?;
+        let len = listener.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"NOTICE c3 4.0");
+        // Once per countdown
+        ladder.on_pet(&pingee, poll.registry())?;
+        assert!(listener.recv(&mut buf).is_err());
+        assert_eq!(ladder.take_hook().map(|r| r.is_some()), None);
+
+        clock.advance(Duration::from_secs(3));
+        ladder.on_pet(&pingee, poll.registry())?;
+        assert!(ladder.running.is_some());
+        assert_eq!(ladder.take_hook().map(|r| r.is_some()), Some(true));
+
+        // A ping starts the countdown over
+        pingee.feed(Some("c3"))?;
+        ladder.on_pet(&pingee, poll.registry())?;
+        assert!(ladder.rungs.iter().all(|&(_, _, taken)| !taken));
+        clock.advance(Duration::from_secs(6));
+        ladder.on_pet(&pingee, poll.registry())?;
+        assert!(listener.recv(&mut buf).is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,369 @@
+use crate::{
+    exit::Cause,
+    status::{quote, StatusReport},
+};
+use anyhow::{bail, ensure, Context, Result};
+use std::{
+    ffi::OsString,
+    fmt::{self, Write as _},
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    iter::Peekable,
+    net::SocketAddr,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    str::Chars,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Why the watchdog went down, written just before so that the next boot can downlink it
+pub struct LastGasp<'a> {
+    pub cause: Cause,
+    pub reason: &'a str,
+    /// Time since the last ping packet and where it came from
+    pub last_ping: Option<(Duration, SocketAddr)>,
+    pub pets: u64,
+    /// Recent events, oldest first
+    pub history: &'a [String],
+    /// None if it couldn't be had
+    pub status: Option<&'a StatusReport>,
+    /// Where a panic happened
+    pub backtrace: Option<&'a str>,
+}
+
+impl LastGasp<'_> {
+    fn to_json(&self) -> String {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut json = format!(
+            "{{\"timestamp\":{:.3},\"cause\":{},\"cause_name\":{},\"exit_code\":{},\
+             \"emcy_code\":{},\"reason\":{},",
+            timestamp.as_secs_f64(),
+            quote(&self.cause.to_string()),
+            quote(self.cause.name()),
+            self.cause as u8,
+            self.cause.emcy(),
+            quote(self.reason)
+        );
+        match self.last_ping {
+            Some((since, source)) => write!(
+                json,
+                "\"since_last_ping\":{:.3},\"last_ping_source\":{},",
+                since.as_secs_f64(),
+                quote(&source.to_string())
+            ),
+            None => write!(json, "\"since_last_ping\":null,\"last_ping_source\":null,"),
+        }
+        .unwrap();
+        let history: Vec<_> = self.history.iter().map(|line| quote(line)).collect();
+        let status = self
+            .status
+            .map_or(String::from("null"), StatusReport::to_json);
+        let backtrace = self.backtrace.map_or(String::from("null"), quote);
+        write!(
+            json,
+            "\"pets\":{},\"history\":[{}],\"status\":{},\"backtrace\":{}}}",
+            self.pets,
+            history.join(","),
+            status,
+            backtrace
+        )
+        .unwrap();
+        json
+    }
+
+    /// Written with O_SYNC so that it survives the reset that's about to happen
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .custom_flags(libc::O_SYNC)
+            .open(path)
+            .with_context(|| format!("Failed to open {:?}", path))?;
+        file.write_all(self.to_json().as_bytes())?;
+        file.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// The gist of the previous run's last gasp, for the log and the status of this one
+#[derive(Debug, PartialEq)]
+pub struct Previous {
+    /// Unix seconds
+    pub timestamp: f64,
+    pub cause: String,
+    /// On one line
+    pub reason: String,
+    /// The least time any client had left, in seconds
+    pub margin: Option<f64>,
+    pub last_ping_source: Option<String>,
+}
+
+impl Previous {
+    fn parse(text: &str) -> Result<Self> {
+        let mut chars = text.chars().peekable();
+        let Json::Object(gasp) = Json::parse(&mut chars)? else {
+            bail!("Not an object");
+        };
+        let get = |key| gasp.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+        let string = |key| match get(key) {
+            Some(Json::String(value)) => Some(value.clone()),
+            _ => None,
+        };
+        let Some(Json::Number(timestamp)) = get("timestamp") else {
+            bail!("No timestamp");
+        };
+        let margin = match get("status") {
+            Some(Json::Object(status)) => status
+                .iter()
+                .filter(|(key, _)| key.starts_with("client.") && key.ends_with(".remaining"))
+                .filter_map(|(_, value)| match value {
+                    Json::Number(left) => Some(*left),
+                    _ => None,
+                })
+                .reduce(f64::min),
+            _ => None,
+        };
+        Ok(Self {
+            timestamp: *timestamp,
+            cause: string("cause_name").context("No cause")?,
+            reason: string("reason")
+                .context("No reason")?
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+            margin,
+            last_ping_source: string("last_ping_source"),
+        })
+    }
+
+    /// As `previous.<key>` status keys, "-" for what it doesn't have
+    pub fn to_status(&self) -> StatusReport {
+        let mut status = StatusReport::new();
+        status.push("previous.cause", &self.cause);
+        status.push("previous.at", format!("{:.3}", self.timestamp));
+        status.push("previous.reason", &self.reason);
+        match self.margin {
+            Some(margin) => status.push("previous.margin", format!("{:.1}", margin)),
+            None => status.push("previous.margin", "-"),
+        }
+        let source = self.last_ping_source.as_deref();
+        status.push("previous.last_ping_source", source.unwrap_or("-"));
+        status
+    }
+}
+
+impl fmt::Display for Previous {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.cause, self.reason)?;
+        if let Some(margin) = self.margin {
+            write!(f, ", {:.1}s margin", margin)?;
+        }
+        match &self.last_ping_source {
+            Some(source) => write!(f, ", last ping from {}", source),
+            None => f.write_str(", no ping"),
+        }
+    }
+}
+
+/// Reads back what the previous run left at `path`, if anything, and moves it to `<path>.1` so
+/// that it's only replayed once. A file that doesn't parse is moved all the same.
+pub fn replay(path: &Path) -> Result<Option<Previous>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+    };
+    let archived = archived(path);
+    fs::rename(path, &archived).with_context(|| format!("Failed to archive {:?}", path))?;
+    Previous::parse(&text)
+        .map(Some)
+        .with_context(|| format!("Invalid last gasp, kept as {:?}", archived))
+}
+
+fn archived(path: &Path) -> PathBuf {
+    let mut archived = OsString::from(path);
+    archived.push(".1");
+    archived.into()
+}
+
+/// Just enough JSON to read a last gasp back with
+#[derive(Debug)]
+enum Json {
+    Null,
+    Bool,
+    Number(f64),
+    String(String),
+    Array,
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(chars: &mut Peekable<Chars>) -> Result<Self> {
+        skip_whitespace(chars);
+        let json = match chars.peek().context("Unexpected end")? {
+            '{' => {
+                chars.next();
+                let mut members = Vec::new();
+                skip_whitespace(chars);
+                if chars.next_if_eq(&'}').is_none() {
+                    loop {
+                        skip_whitespace(chars);
+                        let Json::String(key) = Json::parse(chars)? else {
+                            bail!("Expected a key");
+                        };
+                        skip_whitespace(chars);
+                        ensure!(chars.next() == Some(':'), "Expected ':'");
+                        members.push((key, Json::parse(chars)?));
+                        skip_whitespace(chars);
+                        match chars.next() {
+                            Some(',') => (),
+                            Some('}') => break,
+                            _ => bail!("Expected ',' or '}}'"),
+                        }
+                    }
+                }
+                Json::Object(members)
+            }
+            '[' => {
+                chars.next();
+                skip_whitespace(chars);
+                if chars.next_if_eq(&']').is_none() {
+                    loop {
+                        Json::parse(chars)?;
+                        skip_whitespace(chars);
+                        match chars.next() {
+                            Some(',') => (),
+                            Some(']') => break,
+                            _ => bail!("Expected ',' or ']'"),
+                        }
+                    }
+                }
+                Json::Array
+            }
+            '"' => {
+                chars.next();
+                let mut string = String::new();
+                loop {
+                    match chars.next().context("Unterminated string")? {
+                        '"' => break,
+                        '\\' => match chars.next().context("Unterminated string")? {
+                            'n' => string.push('\n'),
+                            't' => string.push('\t'),
+                            'r' => string.push('\r'),
+                            'u' => {
+                                let hex: String = chars.by_ref().take(4).collect();
+                                let code = u32::from_str_radix(&hex, 16)?;
+                                string.push(char::from_u32(code).unwrap_or('?'));
+                            }
+                            c => string.push(c),
+                        },
+                        c => string.push(c),
+                    }
+                }
+                Json::String(string)
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|c| !",]} \t\r\n".contains(*c)) {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "null" => Json::Null,
+                    "true" | "false" => Json::Bool,
+                    _ => Json::Number(
+                        word.parse()
+                            .with_context(|| format!("Invalid {:?}", word))?,
+                    ),
+                }
+            }
+        };
+        Ok(json)
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_last_gasp() -> Result<()> {
+        let status = StatusReport::parse("state=armed\npets=42\n")?;
+        let gasp = LastGasp {
+            cause: Cause::PingTimeout,
+            reason: "Ping timeout: \"c3\" client stopped pinging\n",
+            last_ping: Some((Duration::from_millis(30500), "127.0.0.1:4000".parse()?)),
+            pets: 42,
+            history: &[
+                String::from("-0.500 pet high"),
+                String::from("-0.100 pet low"),
+            ],
+            status: Some(&status),
+            backtrace: None,
+        };
+        let json = gasp.to_json();
+        assert!(json.starts_with("{\"timestamp\":"));
+        assert!(json.ends_with(
+            "\"cause\":\"Ping timeout\",\"cause_name\":\"ping_timeout\",\"exit_code\":2,\
+             \"emcy_code\":33072,\
+             \"reason\":\"Ping timeout: \\\"c3\\\" client stopped pinging\\u000a\",\
+             \"since_last_ping\":30.500,\"last_ping_source\":\"127.0.0.1:4000\",\"pets\":42,\
+             \"history\":[\"-0.500 pet high\",\"-0.100 pet low\"],\
+             \"status\":{\"state\":\"armed\",\"pets\":42},\"backtrace\":null}"
+        ));
+
+        let path = std::env::temp_dir().join(format!("watchdog-gasp-{}/last", std::process::id()));
+        gasp.write(&path)?;
+        // Everything but the timestamp
+        let written = fs::read_to_string(&path)?;
+        assert_eq!(
+            written.split_once(',').unwrap().1,
+            json.split_once(',').unwrap().1.to_owned() + "\n"
+        );
+
+        // Read back once on the next start
+        let previous = replay(&path)?.unwrap();
+        assert_eq!(previous.cause, "ping_timeout");
+        assert_eq!(
+            previous.reason,
+            "Ping timeout: \"c3\" client stopped pinging"
+        );
+        assert_eq!(previous.margin, None);
+        assert_eq!(
+            previous.to_string(),
+            "ping_timeout: Ping timeout: \"c3\" client stopped pinging, last ping from 127.0.0.1:4000"
+        );
+        assert!(replay(&path)?.is_none());
+        assert!(archived(&path).exists());
+
+        let status =
+            StatusReport::parse("state=armed\nclient.a.remaining=4.5\nclient.b.remaining=0.5\n")?;
+        let gasp = LastGasp {
+            last_ping: None,
+            status: Some(&status),
+            backtrace: Some("0: main\n"),
+            ..gasp
+        };
+        gasp.write(&path)?;
+        let previous = replay(&path)?.unwrap();
+        assert_eq!(previous.margin, Some(0.5));
+        let status = previous.to_status();
+        assert_eq!(status.get("previous.margin"), Some("0.5"));
+        assert_eq!(status.get("previous.last_ping_source"), Some("-"));
+
+        fs::write(&path, "{\"timestamp\":")?;
+        assert!(replay(&path).is_err());
+        assert!(!path.exists());
+        fs::remove_dir_all(path.parent().unwrap())?;
+        Ok(())
+    }
+}
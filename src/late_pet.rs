@@ -0,0 +1,101 @@
+use crate::{
+    can::{CanSocket, Frame},
+    config::LatePetConfig,
+    dictionary::Counter,
+    history::{self, Event},
+    stats,
+};
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::time::Duration;
+
+// CANopen EMCY: error code 0x6100 (internal software), error register bit 0 (generic), then the
+// lateness trend in milliseconds as the manufacturer specific part. Code 0 clears it.
+const EMCY_SOFTWARE: u16 = 0x6100;
+const EMCY_RESET: u16 = 0x0000;
+
+// Weight of the newest pet in the trend
+const SMOOTHING: f64 = 0.2;
+
+/// Watches how late the pets are. Single late pets are counted and logged, and an alarm is raised
+/// while the smoothed lateness trends past the alarm level, until it's back under half of it.
+pub struct LatePets {
+    threshold: Duration,
+    alarm: Duration,
+    /// Exponentially smoothed lateness, in seconds
+    trend: f64,
+    alarmed: bool,
+    emcy: Option<(CanSocket, u32)>,
+}
+
+impl LatePets {
+    pub fn new(config: &LatePetConfig) -> Result<Self> {
+        let emcy = match &config.can {
+            Some(can) => Some((CanSocket::open(&can.interface)?, can.id)),
+            None => None,
+        };
+        Ok(Self {
+            threshold: config.threshold,
+            alarm: config.alarm,
+            trend: 0.0,
+            alarmed: false,
+            emcy,
+        })
+    }
+
+    pub fn on_pet(&mut self, late: Duration) {
+        if late > self.threshold {
+            warn!("Pet was {:?} late", late);
+            stats::count(Counter::LatePets);
+            history::record(Event::LateWakeup { late });
+        }
+        self.trend += SMOOTHING * (late.as_secs_f64() - self.trend);
+        let alarm = self.alarm.as_secs_f64();
+        if !self.alarmed && self.trend > alarm {
+            warn!("Pets are trending late, by {:.3} s", self.trend);
+            self.set_alarm(true);
+        } else if self.alarmed && self.trend < alarm / 2.0 {
+            info!("Pets are back on schedule");
+            self.set_alarm(false);
+        }
+    }
+
+    fn set_alarm(&mut self, alarmed: bool) {
+        self.alarmed = alarmed;
+        stats::set_late_alarm(alarmed);
+        if let Some((socket, id)) = &self.emcy {
+            let code = if alarmed { EMCY_SOFTWARE } else { EMCY_RESET };
+            let millis = (self.trend * 1000.0).min(u16::MAX.into()) as u16;
+            let mut payload = [0; 8];
+            payload[..2].copy_from_slice(&code.to_le_bytes());
+            payload[2] = alarmed as u8;
+            payload[3..5].copy_from_slice(&millis.to_le_bytes());
+            let sent = Frame::new(*id, &payload).and_then(|f| socket.send(&f));
+            if let Err(e) = sent.context("Failed to send EMCY") {
+                warn!("{:#}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_late_pets() -> Result<()> {
+        let mut late_pets = LatePets::new(&LatePetConfig::default())?;
+        late_pets.on_pet(Duration::from_millis(2));
+        late_pets.on_pet(Duration::from_millis(900));
+        assert!(!late_pets.alarmed);
+        for _ in 0..5 {
+            late_pets.on_pet(Duration::from_millis(900));
+        }
+        assert!(late_pets.alarmed);
+        for _ in 0..5 {
+            late_pets.on_pet(Duration::ZERO);
+        }
+        assert!(!late_pets.alarmed);
+        Ok(())
+    }
+}
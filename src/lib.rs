@@ -0,0 +1,181 @@
+//! Pets the C3's hardware watchdog for as long as the rest of the card keeps pinging. The binary
+//! is a thin wrapper, other tools can embed a `Watchdog` directly.
+//!
+//! The watchdog itself needs Linux. Elsewhere only the protocol, config, and ping handling build,
+//! with a mock backend, for developing and testing those.
+
+#[cfg(all(feature = "flight", any(feature = "sim", feature = "inject")))]
+compile_error!("Flight builds can't have the sim or inject features");
+
+#[cfg(target_os = "linux")]
+mod anomaly;
+mod audit;
+pub mod auth;
+#[cfg(target_os = "linux")]
+mod battery;
+mod budget;
+#[cfg(target_os = "linux")]
+mod bus;
+#[cfg(target_os = "linux")]
+mod cadence;
+#[cfg(target_os = "linux")]
+mod can;
+#[cfg(target_os = "linux")]
+mod can_ping;
+pub mod capture;
+#[cfg(target_os = "linux")]
+mod cgroup;
+pub mod client;
+pub mod clock;
+#[cfg(target_os = "linux")]
+mod condition;
+pub mod config;
+#[cfg(target_os = "linux")]
+mod counters;
+#[cfg(target_os = "linux")]
+pub mod daemon;
+#[cfg(target_os = "linux")]
+mod death_row;
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+mod dictionary;
+#[cfg(target_os = "linux")]
+mod eventfd;
+pub mod exit;
+pub mod framing;
+#[cfg(target_os = "linux")]
+mod gate;
+#[cfg(target_os = "linux")]
+mod handover;
+mod health;
+#[cfg(target_os = "linux")]
+mod heartbeat;
+// Partly only used by the watchdog
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+mod history;
+#[cfg(target_os = "linux")]
+mod hook;
+#[cfg(target_os = "linux")]
+mod http;
+#[cfg(feature = "inject")]
+mod inject;
+pub mod install;
+#[cfg(target_os = "linux")]
+mod instance;
+#[cfg(target_os = "linux")]
+mod kernel_watchdog;
+#[cfg(target_os = "linux")]
+mod ladder;
+#[cfg(target_os = "linux")]
+mod last_gasp;
+#[cfg(target_os = "linux")]
+mod late_pet;
+#[cfg(target_os = "linux")]
+mod line;
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+mod logbook;
+pub mod logger;
+#[cfg(target_os = "linux")]
+mod maintenance;
+#[cfg(target_os = "linux")]
+mod margin;
+#[cfg(target_os = "linux")]
+mod mirror;
+#[cfg(all(target_os = "linux", feature = "mqtt"))]
+mod mqtt;
+mod net;
+#[cfg(target_os = "linux")]
+mod nmt;
+#[cfg(target_os = "linux")]
+mod notify;
+#[cfg(target_os = "linux")]
+mod panic;
+#[cfg(target_os = "linux")]
+mod peer;
+pub mod petter;
+pub mod pingee;
+pub mod policy;
+#[cfg(target_os = "linux")]
+mod power;
+#[cfg(target_os = "linux")]
+mod privileges;
+mod process;
+#[cfg(target_os = "linux")]
+mod profile;
+pub mod protocol;
+#[cfg(target_os = "linux")]
+mod realtime;
+#[cfg(target_os = "linux")]
+mod reexec;
+#[cfg(target_os = "linux")]
+mod reset_reason;
+#[cfg(target_os = "linux")]
+mod reset_request;
+#[cfg(target_os = "linux")]
+mod retry;
+mod rotate;
+pub mod rule;
+#[cfg(target_os = "linux")]
+mod sdo;
+// Only cron parsing is used elsewhere, by the config
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+mod schedule;
+#[cfg(target_os = "linux")]
+mod seccomp;
+#[cfg(target_os = "linux")]
+mod self_test;
+#[cfg(target_os = "linux")]
+mod shm;
+#[cfg(target_os = "linux")]
+mod silence;
+#[cfg(feature = "sim")]
+pub mod sim_supervisor;
+#[cfg(target_os = "linux")]
+mod sinks;
+#[cfg(target_os = "linux")]
+mod source;
+#[cfg(target_os = "linux")]
+mod stall;
+#[cfg(target_os = "linux")]
+mod standby;
+pub mod state;
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+mod stats;
+pub mod status;
+#[cfg(target_os = "linux")]
+mod status_file;
+#[cfg(target_os = "linux")]
+mod status_socket;
+#[cfg(target_os = "linux")]
+mod supervisor;
+#[cfg(target_os = "linux")]
+mod telemetry;
+#[cfg(target_os = "linux")]
+mod touch;
+mod traffic;
+#[cfg(target_os = "linux")]
+mod units;
+#[cfg(target_os = "linux")]
+mod uptime;
+#[cfg(target_os = "linux")]
+mod vsock;
+#[cfg(target_os = "linux")]
+mod watchdog;
+
+pub use client::WatchdogClient;
+pub use config::Config;
+pub use exit::Cause;
+pub use petter::Backend;
+#[cfg(target_os = "linux")]
+pub use petter::Petter;
+pub use pingee::{Pingee, Transport};
+#[cfg(target_os = "linux")]
+pub use watchdog::Watchdog;
+
+#[cfg(debug_assertions)]
+pub(crate) fn timestamp_millis() -> u128 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_micros()
+}
@@ -0,0 +1,192 @@
+use crate::{
+    clock::{self, Clock},
+    config::{Config, LineConfig, MAX_CLIENTS},
+    exit::Cause,
+    petter::Petter,
+    pingee::{Action, Pingee, Transport},
+    process::ProcessMonitor,
+};
+use anyhow::{Context, Result};
+use log::{info, warn};
+use mio::{net::UdpSocket, Registry, Token};
+use std::sync::Arc;
+
+/// Tokens each line takes up: its socket, its pet timer, and its clients' timers
+pub const TOKENS: usize = 2 + MAX_CLIENTS;
+
+/// Another card's watchdog line, petted alongside the main one for as long as its own clients
+/// keep pinging. A timeout only stops this line, until they're back. A commanded reset stops it
+/// for good.
+pub struct Line {
+    name: String,
+    pingee: Pingee,
+    petter: Petter,
+    first: Token,
+    stopped: Option<Stopped>,
+}
+
+#[derive(PartialEq)]
+enum Stopped {
+    Starving,
+    Reset,
+}
+
+impl Line {
+    /// Takes up `TOKENS` tokens from `first`
+    pub fn new(config: &LineConfig, main: &Config, first: Token) -> Result<Self> {
+        let context = || format!("Failed to set up the {} line", config.name);
+        let socket = UdpSocket::bind(config.address)
+            .with_context(|| format!("Failed to bind {}", config.address))
+            .with_context(context)
+            .context(Cause::Socket)?;
+        let petter = Petter::new(&config.chip, &config.label, config.line)
+            .with_context(context)
+            .context(Cause::Gpio)?;
+        Self::with_parts(
+            config,
+            main,
+            first,
+            Box::new(socket),
+            petter,
+            clock::configured(main.clock)?,
+        )
+    }
+
+    fn with_parts(
+        config: &LineConfig,
+        main: &Config,
+        first: Token,
+        transport: Box<dyn Transport>,
+        petter: Petter,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self> {
+        let channel = Config {
+            address: config.address,
+            armed: main.armed,
+            auth: main.auth.clone(),
+            allowed_sources: main.allowed_sources.clone(),
+            clients: config.clients.clone(),
+            strikes: main.strikes.clone(),
+            inhibit: main.inhibit.clone(),
+            ..Config::default()
+        };
+        let timers = Token(first.0 + 2);
+        let pingee =
+            Pingee::with_clock(&channel, timers, Token(first.0 + TOKENS), transport, clock)?;
+        Ok(Self {
+            name: config.name.clone(),
+            pingee,
+            petter,
+            first,
+            stopped: None,
+        })
+    }
+
+    pub fn register(&mut self, registry: &Registry) -> Result<()> {
+        self.pingee.register(registry, self.first)?;
+        self.petter.register(registry, Token(self.first.0 + 1))
+    }
+
+    pub fn reload_keys(&mut self) -> Result<()> {
+        self.pingee.reload_keys()
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        self.petter.pet()
+    }
+
+    pub fn on_event(
+        &mut self,
+        token: Token,
+        registry: &Registry,
+        processes: &mut ProcessMonitor,
+    ) -> Result<()> {
+        match token.0 - self.first.0 {
+            0 => {
+                if let Some(Action::Reset) = self.pingee.on_ping(registry, processes)? {
+                    self.stop(Stopped::Reset, "Commanded reset")?;
+                }
+            }
+            1 => {
+                self.petter.on_pet()?;
+            }
+            _ => {
+                if let Some(reason) = self.pingee.on_timeout(token)? {
+                    self.stop(Stopped::Starving, &reason)?;
+                }
+            }
+        }
+        if self.stopped == Some(Stopped::Starving) && !self.pingee.starving()? {
+            info!("Petting the {} line again", self.name);
+            self.stopped = None;
+            self.petter.pet()?;
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self, stopped: Stopped, reason: &str) -> Result<()> {
+        if self.stopped.is_none() {
+            warn!("Stopped petting the {} line: {}", self.name, reason);
+            self.petter.stop()?;
+        }
+        // A reset isn't undone by pings
+        if self.stopped != Some(Stopped::Reset) {
+            self.stopped = Some(stopped);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::VirtualClock, petter::Mock, pingee::INHIBIT};
+    use mio::Poll;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_line() -> Result<()> {
+        let main = Config::parse(
+            r#"
+            [[line]]
+            name = "battery"
+            chip = "gpiochip1"
+            label = "PET_BAT"
+            line = 12
+            address = "127.0.0.1:0"
+            [[line.client]]
+            name = "battery"
+            "#,
+        )?;
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(10), &[])?;
+        let mock = Mock::default();
+        let petter = Petter::with_backend(Box::new(mock.clone()))?;
+        let first = Token(1000);
+        let socket = UdpSocket::bind(main.lines[0].address)?;
+        let address = socket.local_addr()?;
+        let clock = VirtualClock::new();
+        let mut line = Line::with_parts(
+            &main.lines[0],
+            &main,
+            first,
+            Box::new(socket),
+            petter,
+            Arc::new(clock.clone()),
+        )?;
+        line.register(poll.registry())?;
+        line.start()?;
+
+        // The client's timer firing stops only this line
+        clock.advance(INHIBIT);
+        line.on_event(Token(1002), poll.registry(), &mut processes)?;
+        assert_eq!(mock.values(), [true, false]);
+
+        let client = std::net::UdpSocket::bind("127.0.0.1:0")?;
+        client.send_to(b"PING battery", address)?;
+        thread::sleep(Duration::from_millis(10));
+        line.on_event(first, poll.registry(), &mut processes)?;
+        assert_eq!(mock.values(), [true, false, true]);
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+use crate::{clock, config::LogbookConfig, history::Event};
+use anyhow::{ensure, Context, Result};
+use log::warn;
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+/// Entries a LOGBOOK reply carries, which keeps it well within a packet
+pub const PAGE: usize = 16;
+
+static ENTRIES: Mutex<Option<Entries>> = Mutex::new(None);
+
+struct Entries {
+    capacity: usize,
+    /// Oldest first
+    kept: VecDeque<Entry>,
+    next: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    seq: u64,
+    /// Unix seconds
+    at: i64,
+    text: String,
+}
+
+impl Entry {
+    fn parse(line: &str) -> Option<Self> {
+        let (seq, rest) = line.split_once(' ')?;
+        let (at, text) = rest.split_once(' ')?;
+        Some(Self {
+            seq: seq.parse().ok()?,
+            at: at.parse().ok()?,
+            text: text.to_string(),
+        })
+    }
+
+    fn line(&self) -> String {
+        format!("{} {} {}\n", self.seq, self.at, self.text)
+    }
+}
+
+/// The notable events, kept in a file across reboots for the ground to page through with LOGBOOK
+/// weeks later, without having had the telemetry all along: strikes, late wakeups, inhibits,
+/// advisory clients going silent, criticality changes, time anomalies, peer power cycles, and
+/// degraded configs. Entries are numbered on across restarts, so a page is asked for from a number
+/// and a gap shows what was dropped for being older than the last `entries`. This is the writing
+/// end, on the sinks thread, the entries are paged from memory.
+pub struct Logbook {
+    path: PathBuf,
+    file: File,
+    /// In the file, which is rewritten with only the kept ones once it has twice as many
+    lines: usize,
+}
+
+impl Logbook {
+    /// Lines that don't parse, e.g. the last one cut short by a reset, are dropped, and the file
+    /// rewritten without them so that the next line doesn't run on from a cut off one
+    pub fn open(config: &LogbookConfig) -> Result<Self> {
+        let text = match fs::read_to_string(&config.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", config.path)),
+        };
+        let mut kept: VecDeque<_> = text.lines().filter_map(Entry::parse).collect();
+        let lines = text.lines().count();
+        if lines > kept.len() {
+            warn!(
+                "Dropped {} corrupt lines of the logbook {:?}",
+                lines - kept.len(),
+                config.path
+            );
+        }
+        let next = kept.back().map_or(1, |entry| entry.seq + 1);
+        while kept.len() > config.entries {
+            kept.pop_front();
+        }
+        if let Some(dir) = config.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)
+            .with_context(|| format!("Failed to open {:?}", config.path))?;
+        let corrupt = lines > kept.len() || !text.is_empty() && !text.ends_with('\n');
+        let mut logbook = Self {
+            path: config.path.clone(),
+            file,
+            lines,
+        };
+        if corrupt {
+            logbook.compact(&kept.iter().map(Entry::line).collect::<String>())?;
+        }
+        *entries() = Some(Entries {
+            capacity: config.entries,
+            kept,
+            next,
+        });
+        Ok(logbook)
+    }
+
+    /// Called for every recorded event, only the notable ones are kept. It's synced, as it's
+    /// meant to survive the reset that's likely to follow.
+    pub fn forward(&mut self, event: &Event) {
+        if !notable(event) {
+            return;
+        }
+        if let Err(e) = self.append(event) {
+            warn!("Failed to write the logbook {:?}: {:#}", self.path, e);
+        }
+    }
+
+    fn append(&mut self, event: &Event) -> Result<()> {
+        let (entry, compacted) = {
+            let mut entries = entries();
+            let entries = entries.as_mut().context("No logbook")?;
+            let entry = Entry {
+                seq: entries.next,
+                at: clock::wall_now(),
+                text: event.to_string().replace('\n', " "),
+            };
+            entries.next += 1;
+            entries.kept.push_back(entry.clone());
+            if entries.kept.len() > entries.capacity {
+                entries.kept.pop_front();
+            }
+            let compacted = (self.lines + 1 >= 2 * entries.capacity)
+                .then(|| entries.kept.iter().map(Entry::line).collect::<String>());
+            (entry, compacted)
+        };
+        match compacted {
+            Some(text) => self.compact(&text),
+            None => {
+                self.file.write_all(entry.line().as_bytes())?;
+                self.file.sync_data()?;
+                self.lines += 1;
+                Ok(())
+            }
+        }
+    }
+
+    fn compact(&mut self, text: &str) -> Result<()> {
+        let temp = self.path.with_extension("new");
+        let mut file =
+            File::create(&temp).with_context(|| format!("Failed to write {:?}", temp))?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, &self.path)
+            .with_context(|| format!("Failed to replace {:?}", self.path))?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.lines = text.lines().count();
+        Ok(())
+    }
+}
+
+fn notable(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::Strike { .. }
+            | Event::LateWakeup { .. }
+            | Event::Inhibit { .. }
+            | Event::Missed { .. }
+            | Event::Criticality { .. }
+            | Event::Anomaly { .. }
+            | Event::PeerCycle { .. }
+            | Event::DegradedConfig { .. }
+    )
+}
+
+fn entries() -> std::sync::MutexGuard<'static, Option<Entries>> {
+    ENTRIES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The LOGBOOK reply: "OK <count> <next>", where next is what to ask for to page on, then up to
+/// a page of "<seq> <unix seconds> <event>" lines from the oldest kept at or after `from`
+pub fn page(from: u64) -> Result<String> {
+    let entries = entries();
+    let entries = entries.as_ref();
+    ensure!(entries.is_some(), "No logbook");
+    let entries = entries.unwrap();
+    let page: Vec<_> = entries
+        .kept
+        .iter()
+        .filter(|entry| entry.seq >= from)
+        .take(PAGE)
+        .collect();
+    let next = page
+        .last()
+        .map_or(from.max(entries.next), |entry| entry.seq + 1);
+    let mut reply = format!("OK {} {}", page.len(), next);
+    for entry in page {
+        write!(reply, "\n{}", entry.line().trim_end()).unwrap();
+    }
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_logbook() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("watchdog-logbook-{}", std::process::id()));
+        let config = LogbookConfig {
+            path: dir.join("logbook"),
+            entries: 20,
+        };
+        let strike = |strikes| Event::Strike {
+            client: String::from("c3"),
+            strikes,
+            max: 50,
+        };
+        let mut logbook = Logbook::open(&config)?;
+        logbook.forward(&Event::Pet { value: true });
+        for strikes in 1..=45 {
+            logbook.forward(&strike(strikes));
+        }
+        // Compacted on the 40th, so the last 20 of those and 5 more since
+        assert_eq!(fs::read_to_string(&config.path)?.lines().count(), 25);
+
+        // Numbered on after a restart, with the cut off last line dropped
+        let mut file = OpenOptions::new().append(true).open(&config.path)?;
+        file.write_all(b"46 17604")?;
+        drop(logbook);
+        let mut logbook = Logbook::open(&config)?;
+        logbook.forward(&Event::Inhibit {
+            duration: Duration::from_secs(60),
+            name: String::from("update"),
+        });
+        let reply = page(0)?;
+        let mut lines = reply.lines();
+        assert_eq!(lines.next(), Some("OK 16 43"));
+        assert!(lines.next().unwrap().starts_with("27 "));
+        assert!(lines
+            .next()
+            .unwrap()
+            .ends_with(" c3 client strike 28 of 50"));
+        let reply = page(43)?;
+        let last = reply.lines().last().unwrap();
+        assert!(reply.starts_with("OK 4 47\n"));
+        assert!(last.starts_with("46 ") && last.ends_with(" inhibited for 60s (update)"));
+        assert_eq!(page(47)?, "OK 0 47");
+        assert_eq!(fs::read_to_string(&config.path)?.lines().count(), 21);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+use crate::{config::LogConfig, rotate};
+use anyhow::{Context, Result};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Everything goes to stderr, which systemd forwards to the journal, or to syslog when daemonized
+// without a log file, unless a rotated log file is configured. Debug builds also get the debug
+// level messages (pet and ping times).
+
+struct Logger;
+
+static SYSLOG: AtomicBool = AtomicBool::new(false);
+
+static FILE: Mutex<Option<FileLog>> = Mutex::new(None);
+
+static LOGGER: Logger = Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut file = FILE.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(log) = &mut *file {
+            let line = format!(
+                "{:.3} {:<5} {}\n",
+                timestamp(),
+                record.level(),
+                record.args()
+            );
+            match log.write(line.as_bytes()) {
+                Ok(()) => return,
+                // Nowhere else to say so, and nothing left to rotate into
+                Err(e) => {
+                    eprintln!("Failed to write log file, logging to stderr: {}", e);
+                    *file = None;
+                }
+            }
+        }
+        drop(file);
+        if SYSLOG.load(Ordering::Relaxed) {
+            let priority = match record.level() {
+                Level::Error => libc::LOG_ERR,
+                Level::Warn => libc::LOG_WARNING,
+                Level::Info => libc::LOG_INFO,
+                Level::Debug | Level::Trace => libc::LOG_DEBUG,
+            };
+            // Interior nuls would cut the message short rather than fail it
+            let message = record.args().to_string().replace('\0', "");
+            if let Ok(message) = std::ffi::CString::new(message) {
+                // SAFETY: both strings are nul terminated, the format takes exactly one
+                unsafe { libc::syslog(priority, c"%s".as_ptr(), message.as_ptr()) };
+            }
+        } else {
+            eprintln!("{:<5} {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn init() {
+    let level = if cfg!(debug_assertions) {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    };
+    // Only fails if a logger was already set, e.g. by another test
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Logs to the configured file from now on, rotating it at its size
+pub fn file(config: &LogConfig) -> Result<()> {
+    let log = FileLog::open(config)
+        .with_context(|| format!("Failed to open log file {:?}", config.path))?;
+    *FILE.lock().unwrap_or_else(|e| e.into_inner()) = Some(log);
+    Ok(())
+}
+
+/// Each line goes out in one appending write, so a reset mid-line loses at most that line. The
+/// full file is synced before it's renamed away, so that it's never the one left half written.
+struct FileLog {
+    config: LogConfig,
+    file: File,
+    size: u64,
+}
+
+impl FileLog {
+    fn open(config: &LogConfig) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&config.path)?;
+        Ok(Self {
+            config: config.clone(),
+            size: file.metadata()?.len(),
+            file,
+        })
+    }
+
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.config.max_size {
+            self.file.sync_data()?;
+            rotate::shift(&self.config.path, self.config.files)?;
+            *self = Self::open(&self.config)?;
+        }
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Unix seconds
+fn timestamp() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Logs to syslog from now on
+pub fn syslog() {
+    // SAFETY: the ident is a static nul terminated string, as openlog requires
+    unsafe {
+        libc::openlog(
+            c"oresat-c3-watchdog".as_ptr(),
+            libc::LOG_PID,
+            libc::LOG_DAEMON,
+        )
+    };
+    SYSLOG.store(true, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rotate::rotated;
+    use std::fs;
+
+    #[test]
+    fn test_file_log() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("watchdog-log-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let config = LogConfig {
+            path: dir.join("watchdog.log"),
+            max_size: 100,
+            files: 2,
+        };
+        let mut log = FileLog::open(&config)?;
+        let line = format!("{:<39}\n", "INFO  Petting");
+        for _ in 0..7 {
+            log.write(line.as_bytes())?;
+        }
+        assert_eq!(fs::read_to_string(&config.path)?, line);
+        assert_eq!(
+            fs::read_to_string(rotated(&config.path, 1))?,
+            line.repeat(2)
+        );
+        assert!(rotated(&config.path, 2).exists());
+        assert!(!rotated(&config.path, 3).exists());
+
+        // Longer than the whole file, which still gets it
+        log.write("x".repeat(200).as_bytes())?;
+        assert_eq!(fs::metadata(&config.path)?.len(), 200);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
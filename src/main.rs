@@ -1,35 +1,31 @@
-use anyhow::{anyhow, bail, Context, Result};
-use gpiod::{Chip, Lines, Options, Output};
-use gpiosim::{Bank, Sim};
-use mio::{net::UdpSocket, unix::SourceFd, Events, Interest, Poll, Token};
-use nix::sys::{
-    signal::{SIGHUP, SIGINT, SIGTERM},
-    signalfd::{SfdFlags, SigSet, SignalFd},
-    time::TimeSpec,
-    timerfd::{
-        ClockId,
-        Expiration::{self, OneShot},
-        TimerFd, TimerFlags, TimerSetTimeFlags,
-    },
+use anyhow::{bail, Context, Result};
+use log::error;
+use oresat_c3_watchdog::{
+    capture,
+    client::{self, WatchdogClient},
+    config,
+    install::{self, Exec},
+    logger,
+    petter::{self, GPIO_CHIP},
+    protocol, Cause, Config,
 };
 use std::{
-    array::IntoIter,
-    io::ErrorKind,
-    iter::Cycle,
-    net::{IpAddr, Ipv4Addr, SocketAddr},
-    os::fd::{AsFd, AsRawFd},
+    env,
+    fs::OpenOptions,
+    io::Write,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+#[cfg(target_os = "linux")]
+use {
+    log::debug,
+    oresat_c3_watchdog::{
+        daemon::{self, Log, PidFile},
+        Watchdog,
+    },
 };
-
-const ADDRESS: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 20001);
-const INHIBIT: Expiration = OneShot(TimeSpec::new(120, 0));
-const PING_TIMEOUT: Expiration = OneShot(TimeSpec::new(30, 0));
-const PET_ON: Expiration = OneShot(TimeSpec::new(0, 100_000_000));
-const PET_OFF: Expiration = OneShot(TimeSpec::new(0, 900_000_000));
-
-const GPIO_LABEL: &str = "PET_WDT";
-const GPIO_LINE: u32 = 25;
-const GPIO_CHIP: &str = "gpiochip2";
-const GPIO_CONSUMER: &str = "C3_Watchdog";
 
 // pet every 1s (0.1s high, 0.9s low)
 // wait 120s
@@ -38,200 +34,310 @@ const GPIO_CONSUMER: &str = "C3_Watchdog";
 // Specifically die to sigterm/sighup/sigint
 // set line low on death
 
-#[cfg(debug_assertions)]
-fn timestamp_millis() -> u128 {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_micros()
+fn main() -> ExitCode {
+    logger::init();
+    let mut args = std::env::args().skip(1).peekable();
+    let result = match args.peek().map(String::as_str) {
+        Some("ping") => ping(args.skip(1)).map(|()| ExitCode::SUCCESS),
+        Some("status") => status(args.skip(1)).map(|()| ExitCode::SUCCESS),
+        Some("capture") => dump(args.skip(1)).map(|()| ExitCode::SUCCESS),
+        Some("check") => check(args.skip(1)),
+        Some("init-config") => init_config(args.skip(1)).map(|()| ExitCode::SUCCESS),
+        Some("install") => install(args.skip(1)).map(|()| ExitCode::SUCCESS),
+        _ => run(args),
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            let cause = Cause::of(&e);
+            error!("{:#}, exiting with {}", e, cause.name());
+            cause.into()
+        }
+    }
 }
 
-struct Petter {
-    hand: Lines<Output>,
-    timer: TimerFd,
-    values: Cycle<IntoIter<(bool, Expiration), 2>>,
+/// `ping [--addr ADDRESS] [--client NAME] [--count N] [--interval SECONDS]`, by default to the
+/// configured address as the anonymous client, every 5 s until stopped
+fn ping(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut address = None;
+    let mut name = String::from("anonymous");
+    let mut count = None;
+    let mut interval = Duration::from_secs(5);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("{} requires a value", arg))
+        };
+        match arg.as_str() {
+            "--addr" => address = Some(client::parse_address(&value()?)?),
+            "--client" => name = value()?,
+            "--count" => count = Some(value()?.parse().context("Invalid count")?),
+            "--interval" => interval = protocol::seconds(&value()?)?,
+            _ => bail!("Unknown argument {:?}", arg),
+        }
+    }
+    WatchdogClient::new(or_configured(address)?)?.keep_pinging(&name, count, interval)
 }
 
-impl Petter {
-    fn new(gpio_chip: &str, gpio_label: &str, gpio_line: u32) -> Result<Self> {
-        let chip = Chip::new(gpio_chip).context("Failed to get GPIO chip")?;
-
-        let read_label = chip.line_info(gpio_line)?.name;
-        anyhow::ensure!(
-            read_label == gpio_label,
-            "Invalid GPIO LINE label: expected {:?}, found {:?}",
-            gpio_label,
-            read_label
-        );
-
-        let opts = Options::output([GPIO_LINE])
-            .values([false])
-            .consumer(GPIO_CONSUMER);
-        let line = chip.request_lines(opts).context("Failed to get GPIO pin")?;
-
-        Ok(Petter {
-            hand: line,
-            timer: TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::TFD_NONBLOCK)?,
-            values: [(true, PET_ON), (false, PET_OFF)].into_iter().cycle(),
-        })
-    }
-
-    fn pet(&mut self) -> Result<()> {
-        // functions as a toggle
-        if let Some((value, duration)) = self.values.next() {
-            self.hand.set_values([value])?;
-            self.timer.set(duration, TimerSetTimeFlags::empty())?;
-            #[cfg(debug_assertions)]
-            println!("PETTED at {} ms with value {}", timestamp_millis(), value);
-        } else {
-            bail!("Unexpected iterator in Petter")
+/// `status [--addr ADDRESS] [--json]`, pretty printed unless --json
+fn status(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut address = None;
+    let mut json = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" => {
+                let text = args.next().context("--addr requires a value")?;
+                address = Some(client::parse_address(&text)?);
+            }
+            "--json" => json = true,
+            _ => bail!("Unknown argument {:?}", arg),
         }
-        Ok(())
     }
+    let status = WatchdogClient::new(or_configured(address)?)?.status()?;
+    if json {
+        println!("{}", status.to_json());
+    } else {
+        print!("{}", status);
+    }
+    Ok(())
+}
 
-    fn on_pet(&mut self) -> Result<()> {
-        self.timer.wait()?; // TODO: read and assert 1?
-        self.pet()
+/// `capture FILE...`, each captured datagram on a line, oldest file first if given in the order
+/// they were rotated
+fn dump(args: impl Iterator<Item = String>) -> Result<()> {
+    let mut paths = args.peekable();
+    if paths.peek().is_none() {
+        bail!("capture requires a file");
+    }
+    for path in paths {
+        for record in capture::read(Path::new(&path))? {
+            println!("{}", record);
+        }
     }
+    Ok(())
 }
 
-impl Drop for Petter {
-    fn drop(&mut self) {
-        let _ = self.hand.set_values([false]);
+/// `check [--config FILE] [--gpio-chip NAME] [--no-gpio]`, for catching a bad config before it's
+/// deployed. Loading it validates it, the hardware limits included, and the GPIO lines are
+/// resolved without requesting them, unless --no-gpio, e.g. on a build machine. Every problem
+/// with the lines is reported, the config's own stop at the first.
+fn check(mut args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    let mut config_path = String::from(config::DEFAULT_PATH);
+    let mut gpio_chip = GPIO_CHIP.to_string();
+    let mut gpio = true;
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("{} requires a value", arg))
+        };
+        match arg.as_str() {
+            "--config" => config_path = value()?,
+            "--gpio-chip" => gpio_chip = value()?,
+            "--no-gpio" => gpio = false,
+            _ => bail!("Unknown argument {:?}", arg),
+        }
+    }
+    let config = Config::load(config_path.as_ref(), true)?;
+    let problems = match gpio {
+        true => petter::check_lines(&config, &gpio_chip),
+        false => Vec::new(),
+    };
+    for problem in &problems {
+        error!("{:#}", problem);
     }
+    if !problems.is_empty() {
+        bail!("{:?} failed the check", config_path);
+    }
+    println!("{:?} is valid", config_path);
+    Ok(ExitCode::SUCCESS)
 }
 
-struct Pingee {
-    socket: UdpSocket,
-    timer: TimerFd,
+/// `init-config [FILE]`, the default config with every option commented, to stdout unless a
+/// file is given. An existing file is left alone.
+fn init_config(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let Some(path) = args.next() else {
+        print!("{}", config::TEMPLATE);
+        return Ok(());
+    };
+    if let Some(arg) = args.next() {
+        bail!("Unknown argument {:?}", arg);
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .with_context(|| format!("Failed to create {:?}", path))?;
+    file.write_all(config::TEMPLATE.as_bytes())?;
+    Ok(())
 }
 
-impl Pingee {
-    fn new() -> Result<Self> {
-        let timer = TimerFd::new(ClockId::CLOCK_MONOTONIC, TimerFlags::TFD_NONBLOCK)?;
-        timer.set(INHIBIT, TimerSetTimeFlags::empty())?;
-        Ok(Self {
-            socket: UdpSocket::bind(ADDRESS)?,
-            timer,
-        })
-    }
-
-    fn on_ping(&self) -> Result<()> {
-        // We don't care about the contents - bytes longer than buf are discarded
-        let mut buf = [0; 1];
-        // Read until there's no more packets, otherwise mio won't see the socket as readable again
-        while match self.socket.recv_from(&mut buf) {
-            Ok(_) => true,
-            Err(e) if e.kind() == ErrorKind::WouldBlock => false,
-            Err(e) => return Err(e).context("Ping socket read failed"),
-        } {}
-        if let (Some(OneShot(remaining)), OneShot(ping)) = (self.timer.get()?, PING_TIMEOUT) {
-            if remaining < ping {
-                self.timer.set(PING_TIMEOUT, TimerSetTimeFlags::empty())?;
+/// `install [--config FILE] [--gpio-chip NAME] [--exe PATH] [--write [DIR]]`, the systemd
+/// service and socket units for the config, running this executable unless given. Printed
+/// unless --write, which replaces the ones in /etc/systemd/system or DIR.
+fn install(args: impl Iterator<Item = String>) -> Result<()> {
+    let mut config_path = PathBuf::from(config::DEFAULT_PATH);
+    let mut gpio_chip = None;
+    let mut program = None;
+    let mut write = None;
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("{} requires a value", arg))
+        };
+        match arg.as_str() {
+            "--config" => config_path = PathBuf::from(value()?),
+            "--gpio-chip" => gpio_chip = Some(value()?),
+            "--exe" => program = Some(PathBuf::from(value()?)),
+            "--write" => {
+                let dir = args.next_if(|arg| !arg.starts_with("--"));
+                write = Some(PathBuf::from(dir.as_deref().unwrap_or(install::UNIT_DIR)));
             }
-        } else {
-            bail!("Unexpected ping timeout timer")
+            _ => bail!("Unknown argument {:?}", arg),
         }
-        #[cfg(debug_assertions)]
-        println!("PINGED at {} ms", timestamp_millis());
-        Ok(())
     }
+    let config = Config::load(&config_path, true)?;
+    let program = match program {
+        Some(program) => program,
+        None => env::current_exe().context("Failed to find this executable")?,
+    };
+    // The unit won't run from the directory this ran in
+    let config_path = config_path
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve {:?}", config_path))?;
+    let exec = Exec {
+        program,
+        config: config_path,
+        gpio_chip,
+    };
+    match write {
+        Some(dir) => {
+            for path in install::write(&config, &exec, &dir)? {
+                println!("Wrote {:?}", path);
+            }
+            println!(
+                "Run systemctl daemon-reload and enable {}.socket",
+                install::NAME
+            );
+        }
+        None => {
+            println!("# {}.service", install::NAME);
+            print!("{}", install::service(&config, &exec));
+            println!("\n# {}.socket", install::NAME);
+            print!("{}", install::socket(&config)?);
+        }
+    }
+    Ok(())
 }
 
-fn simulate_gpio(line_offset: u32, line_label: &str) -> Sim {
-    #[cfg(debug_assertions)]
-    println!("Simulating the GPIO.");
-
-    gpiosim::builder()
-        .with_name("watchdog_sim")
-        .with_bank(Bank::new(32, "sim_bank").name(line_offset, line_label))
-        .live()
-        .expect("Failed to simulate GPIO")
+/// The address of the watchdog running with the default config, unless given
+fn or_configured(address: Option<SocketAddr>) -> Result<SocketAddr> {
+    match address {
+        Some(address) => Ok(address),
+        None => Ok(Config::load(config::DEFAULT_PATH.as_ref(), false)?.address),
+    }
 }
 
-fn main() -> Result<()> {
-    #[cfg(debug_assertions)]
-    println!("This is a Debug build.");
+/// The watchdog itself. With --self-test-only, exits after the startup self-test, successfully if
+/// it passed.
+#[cfg(target_os = "linux")]
+fn run(mut args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    debug!("This is a Debug build.");
+
+    let mut simulate = false;
+    let mut dry_run = false;
+    let mut gpio_chip = None;
+    let mut config_path = None;
+    let mut daemonize = false;
+    let mut pid_file = None;
+    let mut log = Log::Syslog;
+    let mut self_test_only = false;
+    let mut exit_with_parent = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "sim" => simulate = true,
+            "--dry-run" => dry_run = true,
+            "--daemonize" => daemonize = true,
+            "--exit-with-parent" => exit_with_parent = true,
+            "--self-test-only" => self_test_only = true,
+            "--pid-file" => pid_file = Some(PathBuf::from(path(&arg, args.next())?)),
+            "--log-file" => log = Log::File(PathBuf::from(path(&arg, args.next())?)),
+            "--gpio-chip" => gpio_chip = Some(args.next().context("--gpio-chip requires a name")?),
+            "--config" => config_path = Some(PathBuf::from(path(&arg, args.next())?)),
+            _ => bail!("Unknown argument {:?}", arg),
+        }
+    }
+    let config = match config_path {
+        Some(path) => Config::load_or_safe(&path, true),
+        None => Config::load_or_safe(config::DEFAULT_PATH.as_ref(), false),
+    };
+    if let Some(log) = &config.log {
+        logger::file(log)?;
+    }
+    if exit_with_parent {
+        // Whose parent exits right away
+        if daemonize {
+            bail!("--exit-with-parent can't be used with --daemonize");
+        }
+        daemon::exit_with_parent()?;
+    }
+    if daemonize {
+        daemon::daemonize(&log)?;
+    }
+    let _pid_file = pid_file.as_deref().map(PidFile::create).transpose()?;
 
-    let sim: Sim;
-    let gpio_chip = if std::env::args().any(|arg| arg == "sim") {
-        sim = simulate_gpio(GPIO_LINE, GPIO_LABEL);
-        sim.chips()[0].chip_name.clone()
+    let gpio_chip = gpio_chip.unwrap_or_else(|| GPIO_CHIP.to_string());
+    let mut watchdog = if dry_run {
+        dry_run_of(config)?
+    } else if simulate {
+        simulated(config)?
     } else {
-        GPIO_CHIP.to_string()
+        Watchdog::new(config, &gpio_chip)?
     };
-
-    let mut poll = Poll::new()?;
-    let registry = poll.registry();
-    let mut events = Events::with_capacity(128);
-
-    let mut pingee = Pingee::new()?;
-    let mut petter = Petter::new(&gpio_chip, GPIO_LABEL, GPIO_LINE)?;
-    let mask = SigSet::from_iter([SIGTERM, SIGHUP, SIGINT]);
-    mask.thread_block()?;
-    let sfd = SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK)?;
-
-    const PING: Token = Token(0);
-    const PET: Token = Token(1);
-    const TIMEOUT: Token = Token(2);
-    const SIGNAL: Token = Token(3);
-
-    registry.register(&mut pingee.socket, PING, Interest::READABLE)?;
-    registry.register(
-        &mut SourceFd(&petter.timer.as_fd().as_raw_fd()),
-        PET,
-        Interest::READABLE,
-    )?;
-    registry.register(
-        &mut SourceFd(&pingee.timer.as_fd().as_raw_fd()),
-        TIMEOUT,
-        Interest::READABLE,
-    )?;
-    registry.register(&mut SourceFd(&sfd.as_raw_fd()), SIGNAL, Interest::READABLE)?;
-
-    petter.pet()?;
-    'outer: loop {
-        poll.poll(&mut events, None)?;
-        for event in events.iter() {
-            match event.token() {
-                PING => pingee.on_ping()?,
-                PET => petter.on_pet()?,
-                TIMEOUT => break 'outer Err(anyhow!("Ping timeout")),
-                SIGNAL => break 'outer Ok(()),
-                _ => unreachable!(),
-            }
-        }
+    watchdog.install_panic_hook();
+    if self_test_only {
+        watchdog.self_test()?;
+        return Ok(ExitCode::SUCCESS);
     }
+    watchdog.run().map(ExitCode::from)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_simulated_pet() -> Result<()> {
-        // Test whether the simulated watchdog GPIO output changes on each pet
-
-        use gpiosim::Level;
+#[cfg(target_os = "linux")]
+fn path(arg: &str, value: Option<String>) -> Result<String> {
+    value.with_context(|| format!("{} requires a path", arg))
+}
 
-        let sim = simulate_gpio(GPIO_LINE, GPIO_LABEL);
-        let chip = &sim.chips()[0];
-        let gpio_chip = chip.chip_name.clone();
+#[cfg(not(target_os = "linux"))]
+fn run(_args: impl Iterator<Item = String>) -> Result<ExitCode> {
+    bail!("The watchdog only runs on Linux")
+}
 
-        let mut petter = Petter::new(&gpio_chip, GPIO_LABEL, GPIO_LINE)?;
+#[cfg(all(target_os = "linux", feature = "sim"))]
+fn simulated(config: Config) -> Result<Watchdog> {
+    use oresat_c3_watchdog::{petter::Simulated, sim_supervisor::SimulatedSupervisor, Backend};
 
-        let line_level = chip.get_level(GPIO_LINE).unwrap();
-        assert_eq!(line_level, Level::Low);
+    let mut backend: Box<dyn Backend> = Box::new(Simulated::new()?);
+    // Without limits to hold the petting to the simulated line never bites
+    if let Some(hardware) = &config.hardware {
+        let reset = Box::new(SimulatedSupervisor::reset);
+        backend = Box::new(SimulatedSupervisor::new(backend, hardware, reset));
+    }
+    Watchdog::with_backend(config, backend)
+}
 
-        petter.pet()?;
-        let line_level = chip.get_level(GPIO_LINE).unwrap();
-        assert_eq!(line_level, Level::High);
+// Flight builds leave gpiosim out, a stray sim argument mustn't leave the real line unpetted
+#[cfg(all(target_os = "linux", not(feature = "sim")))]
+fn simulated(_config: Config) -> Result<Watchdog> {
+    bail!("Built without simulation support")
+}
 
-        petter.pet()?;
-        let line_level = chip.get_level(GPIO_LINE).unwrap();
-        assert_eq!(line_level, Level::Low);
+#[cfg(all(target_os = "linux", not(feature = "flight")))]
+fn dry_run_of(config: Config) -> Result<Watchdog> {
+    Watchdog::dry_run(config)
+}
 
-        Ok(())
-    }
+// Neither may a stray --dry-run
+#[cfg(all(target_os = "linux", feature = "flight"))]
+fn dry_run_of(_config: Config) -> Result<Watchdog> {
+    bail!("Flight builds can't dry run")
 }
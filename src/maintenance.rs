@@ -0,0 +1,149 @@
+use crate::{
+    clock::{Clock, Timer},
+    config::{MaintenanceConfig, PetStep},
+    exit::Cause,
+    petter::Petter,
+    pingee::Pingee,
+};
+use anyhow::Result;
+use log::info;
+use mio::{Registry, Token};
+use std::time::Duration;
+
+/// The watchdog's side of MAINTENANCE, the pings being the pingee's: ends it once its time runs
+/// out, so that a forgotten one can't leave the card unprotected, pets through the maintenance
+/// pattern meanwhile, and holds deliberate resets back until it's over.
+pub struct Maintenance {
+    pattern: Vec<PetStep>,
+    timer: Box<dyn Timer>,
+    /// The pattern petted through before, to go back to
+    saved: Option<Vec<PetStep>>,
+    /// A deliberate reset held back, released once it's over
+    held: Option<(String, Cause)>,
+    active: bool,
+}
+
+impl Maintenance {
+    /// Without a maintenance mode, MAINTENANCE is refused before it gets here
+    pub fn new(config: Option<&MaintenanceConfig>, clock: &dyn Clock) -> Result<Self> {
+        Ok(Self {
+            pattern: config.map(|m| m.pattern.clone()).unwrap_or_default(),
+            timer: clock.timer()?,
+            saved: None,
+            held: None,
+            active: false,
+        })
+    }
+
+    pub fn register(&self, registry: &Registry, token: Token) -> Result<()> {
+        self.timer.register(registry, token)
+    }
+
+    /// After the pingee accepted MAINTENANCE, starts or restarts the countdown, or ends it for
+    /// zero. Returns the reset held back meanwhile once it's over.
+    pub fn on_command(
+        &mut self,
+        duration: Duration,
+        petter: &mut Petter,
+    ) -> Result<Option<(String, Cause)>> {
+        if duration.is_zero() {
+            return self.end(petter);
+        }
+        self.timer.set(duration)?;
+        if !self.active && !self.pattern.is_empty() {
+            self.saved = Some(petter.pattern().to_vec());
+            petter.switch_pattern(&self.pattern);
+        }
+        self.active = true;
+        Ok(None)
+    }
+
+    /// The time ran out, arms again
+    pub fn on_timer(
+        &mut self,
+        petter: &mut Petter,
+        pingee: &mut Pingee,
+    ) -> Result<Option<(String, Cause)>> {
+        if !self.active {
+            return Ok(None);
+        }
+        pingee.end_maintenance("Maintenance ran out")?;
+        self.end(petter)
+    }
+
+    /// A deliberate reset, passed on unless it's in maintenance
+    pub fn allow(&mut self, reset: Option<(String, Cause)>) -> Option<(String, Cause)> {
+        match reset {
+            Some((reason, cause)) if self.active => {
+                info!("{}, held back until maintenance is over", reason);
+                self.held = Some((reason, cause));
+                None
+            }
+            reset => reset,
+        }
+    }
+
+    fn end(&mut self, petter: &mut Petter) -> Result<Option<(String, Cause)>> {
+        self.timer.unset()?;
+        self.active = false;
+        // Unless something else, e.g. a profile, switched the pattern since
+        if let Some(saved) = self.saved.take() {
+            if petter.pattern() == self.pattern {
+                petter.switch_pattern(&saved);
+            }
+        }
+        Ok(self.held.take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::Monotonic, config::Config, petter::Mock};
+    use mio::{Events, Poll};
+
+    #[test]
+    fn test_maintenance() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            timeout = 30
+            [maintenance]
+            max = 600
+            pattern = [{ high = true, duration = 0.1 }, { high = false, duration = 1.9 }]
+            "#,
+        )?;
+        let mut poll = Poll::new()?;
+        let mut events = Events::with_capacity(4);
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        let mut petter = Petter::with_backend(Box::new(Mock::default()))?;
+        let mut maintenance = Maintenance::new(config.maintenance.as_ref(), &Monotonic)?;
+        maintenance.register(poll.registry(), Token(0))?;
+        let reset = || Some((String::from("Scheduled"), Cause::Scheduled));
+
+        assert_eq!(maintenance.allow(reset()), reset());
+        maintenance.on_command(Duration::from_secs(300), &mut petter)?;
+        assert_eq!(
+            petter.pattern(),
+            config.maintenance.as_ref().unwrap().pattern
+        );
+        assert_eq!(maintenance.allow(reset()), None);
+        // Ended early, the held reset goes ahead
+        assert_eq!(
+            maintenance.on_command(Duration::ZERO, &mut petter)?,
+            reset()
+        );
+        assert_eq!(petter.pattern(), config.pet.pattern);
+
+        maintenance.on_command(Duration::from_millis(20), &mut petter)?;
+        poll.poll(&mut events, Some(Duration::from_secs(1)))?;
+        assert_eq!(events.iter().next().map(|e| e.token()), Some(Token(0)));
+        assert_eq!(maintenance.on_timer(&mut petter, &mut pingee)?, None);
+        assert!(pingee.armed());
+        assert_eq!(petter.pattern(), config.pet.pattern);
+        assert_eq!(maintenance.allow(reset()), reset());
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{
+    can::{CanSocket, Frame},
+    clock::{Clock, Timer},
+    config::MarginConfig,
+    health::Grade,
+    net,
+    source::{EventSource, Ready},
+};
+use anyhow::{Context, Result};
+use log::warn;
+use mio::{Registry, Token};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+/// First byte of a CAN margin frame, after the heartbeat's
+const CAN_MARGIN: u8 = 0x03;
+/// First byte of a CAN health frame
+const CAN_HEALTH: u8 = 0x04;
+
+/// Tells the flight software how much of its timeout the closest client had left, every
+/// interval, so that it can see how near the edge it routinely gets and tune its ping cadence.
+/// The health grade goes along, for the trend over the health window.
+pub struct MarginReport {
+    interval: Duration,
+    timer: Box<dyn Timer>,
+    targets: Vec<(UdpSocket, SocketAddr)>,
+    can: Option<(CanSocket, u32)>,
+}
+
+impl MarginReport {
+    pub fn new(config: &MarginConfig, clock: &dyn Clock) -> Result<Self> {
+        let can = match &config.can {
+            Some(can) => Some((CanSocket::open(&can.interface)?, can.id)),
+            None => None,
+        };
+        let timer = clock.timer()?;
+        timer.set(config.interval)?;
+        Ok(Self {
+            interval: config.interval,
+            timer,
+            targets: net::senders(&config.targets)?,
+            can,
+        })
+    }
+
+    /// Sends a report and waits for the next one. `margin` is None if missing pings aren't fatal
+    /// right now.
+    pub fn on_tick(&mut self, margin: Option<Duration>, health: Grade) -> Result<()> {
+        self.timer.set(self.interval)?;
+        self.report(margin, health);
+        Ok(())
+    }
+
+    /// Best effort, like the heartbeats
+    fn report(&self, margin: Option<Duration>, health: Grade) {
+        for packet in [packet(margin), health_packet(health)] {
+            for (socket, target) in &self.targets {
+                if let Err(e) = socket.send_to(packet.as_bytes(), target) {
+                    warn!("Failed to send margin to {}: {}", target, e);
+                }
+            }
+        }
+        if let Some((socket, id)) = &self.can {
+            let sent = Frame::new(*id, &payload(margin))
+                .and_then(|f| socket.send(&f))
+                .and_then(|_| Frame::new(*id, &health_payload(health)))
+                .and_then(|f| socket.send(&f));
+            if let Err(e) = sent.context("Failed to send CAN margin") {
+                warn!("{:#}", e);
+            }
+        }
+    }
+}
+
+impl EventSource for MarginReport {
+    fn register(&mut self, registry: &Registry, token: Token) -> Result<()> {
+        self.timer.register(registry, token)
+    }
+
+    fn handle_ready(&mut self, _index: usize, ready: &mut Ready) -> Result<()> {
+        let margin = ready.retry.run(|| ready.pingee.deadline())?;
+        self.on_tick(margin, ready.pingee.health())
+    }
+}
+
+/// "MARGIN <ms>", with "-" for none
+fn packet(margin: Option<Duration>) -> String {
+    match margin {
+        Some(margin) => format!("MARGIN {}", margin.as_millis()),
+        None => String::from("MARGIN -"),
+    }
+}
+
+/// The milliseconds as a little endian u32, 0xffffffff for none
+fn payload(margin: Option<Duration>) -> [u8; 5] {
+    let [a, b, c, d] = millis(margin).to_le_bytes();
+    [CAN_MARGIN, a, b, c, d]
+}
+
+fn millis(margin: Option<Duration>) -> u32 {
+    margin.map_or(u32::MAX, |margin| {
+        margin.as_millis().min(u128::from(u32::MAX - 1)) as u32
+    })
+}
+
+/// "HEALTH <grade> <worst ms>", with "-" for no pings in the window
+fn health_packet(health: Grade) -> String {
+    let worst = health
+        .worst_margin
+        .map(|margin| margin.as_millis().to_string());
+    format!(
+        "HEALTH {:.3} {}",
+        health.grade,
+        worst.as_deref().unwrap_or("-")
+    )
+}
+
+/// The grade in ten-thousandths as a little endian u16, then the worst margin like a margin frame
+fn health_payload(health: Grade) -> [u8; 7] {
+    let [a, b] = ((health.grade * 10_000.0).round() as u16).to_le_bytes();
+    let [c, d, e, f] = millis(health.worst_margin).to_le_bytes();
+    [CAN_HEALTH, a, b, c, d, e, f]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::Monotonic, config::Config};
+
+    #[test]
+    fn test_margin() -> Result<()> {
+        let listener = UdpSocket::bind("127.0.0.1:0")?;
+        listener.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let config = Config::parse(&format!(
+            "[margin]\ntargets = [\"{}\"]",
+            listener.local_addr()?
+        ))?;
+        let mut margin = MarginReport::new(config.margin.as_ref().unwrap(), &Monotonic)?;
+
+        let health = Grade {
+            grade: 0.975,
+            worst_margin: Some(Duration::from_millis(120)),
+        };
+        margin.on_tick(Some(Duration::from_millis(2500)), health)?;
+        let mut buf = [0; 64];
+        let len = listener.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"MARGIN 2500");
+        let len = listener.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"HEALTH 0.975 120");
+        let fresh = Grade {
+            grade: 1.0,
+            worst_margin: None,
+        };
+        margin.on_tick(None, fresh)?;
+        let len = listener.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"MARGIN -");
+        let len = listener.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"HEALTH 1.000 -");
+
+        assert_eq!(
+            payload(Some(Duration::from_millis(300))),
+            [0x03, 0x2c, 0x01, 0, 0]
+        );
+        assert_eq!(payload(None), [0x03, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(health_payload(health), [0x04, 0x16, 0x26, 0x78, 0, 0, 0]);
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{
+    config::MirrorConfig,
+    history::{self, Event},
+    source::{EventSource, Ready},
+    stats,
+    supervisor::{self, EVENT_SIZE},
+};
+use anyhow::{Context, Result};
+use mio::{unix::SourceFd, Interest, Registry, Token};
+use nix::{
+    errno::Errno,
+    time::{clock_gettime, ClockId},
+    unistd,
+};
+use std::{
+    os::fd::{AsRawFd, OwnedFd},
+    time::{Duration, Instant},
+};
+
+/// An input wired to the watchdog line, for when the pets really reached it. The kernel
+/// timestamps each edge as it happens, so the last feed in the status is the supervisor's rather
+/// than when the event loop got round to the write, and how long each write took to show up is
+/// kept as the edge lag.
+pub struct PetMirror {
+    fd: OwnedFd,
+}
+
+impl PetMirror {
+    pub fn new(config: &MirrorConfig) -> Result<Self> {
+        let fd = supervisor::request_edges(
+            "pet mirror",
+            &config.chip,
+            &config.label,
+            config.line,
+            false,
+            config.bias,
+            Duration::ZERO,
+        )?;
+        stats::mirror_watched();
+        Ok(Self { fd })
+    }
+
+    pub fn on_readable(&self) -> Result<()> {
+        let mut buf = [0; EVENT_SIZE * 16];
+        loop {
+            match unistd::read(self.fd.as_raw_fd(), &mut buf) {
+                Ok(len) => {
+                    let now = Instant::now();
+                    let monotonic = clock_gettime(ClockId::CLOCK_MONOTONIC)?.into();
+                    for (timestamp, value) in supervisor::edges(&buf[..len]) {
+                        on_edge(value, instant(now, monotonic, timestamp));
+                    }
+                }
+                Err(Errno::EAGAIN) => return Ok(()),
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(e).context("Pet mirror line read failed"),
+            }
+        }
+    }
+}
+
+impl EventSource for PetMirror {
+    fn register(&mut self, registry: &Registry, token: Token) -> Result<()> {
+        let fd = self.fd.as_raw_fd();
+        registry.register(&mut SourceFd(&fd), token, Interest::READABLE)?;
+        Ok(())
+    }
+
+    fn handle_ready(&mut self, _index: usize, ready: &mut Ready) -> Result<()> {
+        ready.retry.run(|| self.on_readable())
+    }
+}
+
+/// The CLOCK_MONOTONIC `timestamp` as an Instant, given the two clocks read together. One from the
+/// future is taken as now.
+fn instant(now: Instant, monotonic: Duration, timestamp: Duration) -> Instant {
+    now.checked_sub(monotonic.saturating_sub(timestamp))
+        .unwrap_or(now)
+}
+
+fn on_edge(value: bool, at: Instant) {
+    let lag = stats::mirror_edge(value, at);
+    history::record(Event::Edge { value, lag });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instant() {
+        let now = Instant::now();
+        let monotonic = Duration::from_secs(100);
+        let at = instant(now, monotonic, Duration::from_millis(99_750));
+        assert_eq!(now - at, Duration::from_millis(250));
+        assert_eq!(instant(now, monotonic, Duration::from_secs(101)), now);
+
+        let edge = Event::Edge {
+            value: true,
+            lag: Some(Duration::from_micros(120)),
+        };
+        assert_eq!(edge.to_string(), "edge high 120µs after the write");
+    }
+}
@@ -0,0 +1,338 @@
+use crate::{
+    clock::{Clock, Timer},
+    config::MqttConfig,
+    history::Event,
+    sinks::Queue,
+    source::{EventSource, Ready},
+    status::{self, StatusReport},
+    telemetry,
+};
+use anyhow::{ensure, Context, Result};
+use log::{info, warn};
+use mio::{Registry, Token};
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+// For connecting, the CONNACK, and each write
+const TIMEOUT: Duration = Duration::from_secs(5);
+// Between tries to reach the broker
+const RETRY: Duration = Duration::from_secs(5);
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const RETAIN: u8 = 0x01;
+const PINGREQ: u8 = 0xc0;
+const DISCONNECT: u8 = 0xe0;
+
+/// Where the events go, while connected
+static EVENTS: Mutex<Option<(Arc<Queue<Message>>, String)>> = Mutex::new(None);
+
+enum Message {
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+        retain: bool,
+    },
+    Stop,
+}
+
+/// Publishes the status and the significant events to an MQTT broker, for the bench and
+/// thermal-vac setups that aggregate everything there. The broker's connection is kept on its
+/// own thread, so that a slow or missing broker never holds up the petting. The availability
+/// topic is "online" while connected, and the broker sets it "offline" through the will if the
+/// daemon dies without saying so.
+pub struct Mqtt {
+    interval: Duration,
+    timer: Box<dyn Timer>,
+    status_topic: String,
+    queue: Arc<Queue<Message>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Mqtt {
+    pub fn new(config: &MqttConfig, clock: &dyn Clock) -> Result<Self> {
+        let queue = Queue::new("mqtt");
+        let serving = config.clone();
+        let receiver = queue.clone();
+        let thread = thread::Builder::new()
+            .name(String::from("mqtt"))
+            .spawn(move || serve(&serving, &receiver))?;
+        let events = format!("{}/events", config.topic);
+        *EVENTS.lock().unwrap_or_else(|e| e.into_inner()) = Some((queue.clone(), events));
+        let timer = clock.timer()?;
+        timer.set(config.interval)?;
+        Ok(Self {
+            interval: config.interval,
+            timer,
+            status_topic: format!("{}/status", config.topic),
+            queue,
+            thread: Some(thread),
+        })
+    }
+
+    /// Publishes `status`, retained so that a dashboard that subscribes later gets it right away
+    pub fn on_tick(&mut self, status: &StatusReport) -> Result<()> {
+        self.timer.set(self.interval)?;
+        self.queue.push(Message::Publish {
+            topic: self.status_topic.clone(),
+            payload: status.to_json().into_bytes(),
+            retain: true,
+        });
+        Ok(())
+    }
+}
+
+impl EventSource for Mqtt {
+    fn register(&mut self, registry: &Registry, token: Token) -> Result<()> {
+        self.timer.register(registry, token)
+    }
+
+    fn handle_ready(&mut self, _index: usize, ready: &mut Ready) -> Result<()> {
+        self.on_tick(&ready.pingee.status()?)
+    }
+}
+
+impl Drop for Mqtt {
+    /// Says it's going offline and disconnects, rather than leaving it to the will
+    fn drop(&mut self) {
+        *EVENTS.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        self.queue.push(Message::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Called for every recorded event, like the telemetry
+pub fn forward(event: &Event) {
+    let events = EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+    let (Some((queue, topic)), Some(fields)) = (&*events, telemetry::fields(event)) else {
+        return;
+    };
+    let payload = format!(
+        "{{\"time\":{:.3},\"event\":{}}}",
+        telemetry::timestamp(),
+        status::quote(&fields)
+    );
+    queue.push(Message::Publish {
+        topic: topic.clone(),
+        payload: payload.into_bytes(),
+        retain: false,
+    });
+}
+
+/// Stays connected until stopped, reconnecting whenever the connection is lost. Whatever is
+/// published while it's down is dropped, the next status replaces it anyway.
+fn serve(config: &MqttConfig, receiver: &Queue<Message>) {
+    let mut connected = false;
+    loop {
+        match session(config, receiver, &mut connected) {
+            Ok(()) => return,
+            Err(e) if connected => warn!("Lost MQTT broker {}: {:#}", config.broker, e),
+            Err(e) => warn!("Failed to reach MQTT broker {}: {:#}", config.broker, e),
+        }
+        connected = false;
+        let until = Instant::now() + RETRY;
+        while let Some(left) = until.checked_duration_since(Instant::now()) {
+            match receiver.pop_timeout(left) {
+                Some(Message::Stop) => return,
+                Some(Message::Publish { .. }) => (),
+                None => break,
+            }
+        }
+    }
+}
+
+fn session(config: &MqttConfig, receiver: &Queue<Message>, connected: &mut bool) -> Result<()> {
+    let mut stream = TcpStream::connect_timeout(&config.broker, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    stream.write_all(&connect(config))?;
+    let mut connack = [0; 4];
+    stream
+        .read_exact(&mut connack)
+        .context("No CONNACK from the broker")?;
+    ensure!(connack[0] == CONNACK, "Expected a CONNACK");
+    ensure!(
+        connack[3] == 0,
+        "Broker refused the connection with {}",
+        connack[3]
+    );
+    if !*connected {
+        info!("Connected to MQTT broker {}", config.broker);
+    }
+    *connected = true;
+    let availability = format!("{}/availability", config.topic);
+    stream.write_all(&publish(&availability, b"online", true))?;
+    // Well within the keep alive, so that the broker never gives up on it
+    let quiet = config.keep_alive / 2;
+    let mut last_sent = Instant::now();
+    loop {
+        let wait = quiet.saturating_sub(last_sent.elapsed());
+        match receiver.pop_timeout(wait) {
+            Some(Message::Publish {
+                topic,
+                payload,
+                retain,
+            }) => stream.write_all(&publish(&topic, &payload, retain))?,
+            Some(Message::Stop) => {
+                stream.write_all(&publish(&availability, b"offline", true))?;
+                stream.write_all(&[DISCONNECT, 0])?;
+                return Ok(());
+            }
+            None => stream.write_all(&[PINGREQ, 0])?,
+        }
+        last_sent = Instant::now();
+        drain(&mut stream)?;
+    }
+}
+
+/// Reads away the PINGRESPs, the only thing a broker sends a client that doesn't subscribe
+fn drain(stream: &mut TcpStream) -> Result<()> {
+    stream.set_nonblocking(true)?;
+    let mut buf = [0; 64];
+    let drained = loop {
+        match stream.read(&mut buf) {
+            Ok(0) => break Err(anyhow::anyhow!("Broker closed the connection")),
+            Ok(_) => continue,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break Ok(()),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => break Err(e.into()),
+        }
+    };
+    stream.set_nonblocking(false)?;
+    drained
+}
+
+/// MQTT 3.1.1, with a clean session and the will setting the availability offline
+fn connect(config: &MqttConfig) -> Vec<u8> {
+    let mut body = Vec::new();
+    string(&mut body, b"MQTT");
+    // Protocol level, then the flags: will retained, will, clean session
+    body.extend([4, 0x20 | 0x04 | 0x02]);
+    let keep_alive = config.keep_alive.as_secs().min(u16::MAX.into()) as u16;
+    body.extend(keep_alive.to_be_bytes());
+    string(&mut body, config.client_id.as_bytes());
+    string(
+        &mut body,
+        format!("{}/availability", config.topic).as_bytes(),
+    );
+    string(&mut body, b"offline");
+    packet(CONNECT, &body)
+}
+
+/// At QoS 0, which needs no packet id or acknowledgement
+fn publish(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    string(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload);
+    packet(PUBLISH | if retain { RETAIN } else { 0 }, &body)
+}
+
+fn string(out: &mut Vec<u8>, text: &[u8]) {
+    out.extend((text.len() as u16).to_be_bytes());
+    out.extend_from_slice(text);
+}
+
+/// The fixed header, with the length in 7 bit groups, least significant first
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![kind];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        packet.push(if len > 0 { byte | 0x80 } else { byte });
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::Monotonic, config::Config};
+    use anyhow::bail;
+    use std::net::TcpListener;
+
+    /// The inverse of `packet`, for a stream positioned at one
+    fn read_packet(stream: &mut impl Read) -> Result<(u8, Vec<u8>)> {
+        let mut byte = [0];
+        stream.read_exact(&mut byte)?;
+        let kind = byte[0];
+        let mut len = 0;
+        for shift in (0..28).step_by(7) {
+            stream.read_exact(&mut byte)?;
+            len |= usize::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                let mut body = vec![0; len];
+                stream.read_exact(&mut body)?;
+                return Ok((kind, body));
+            }
+        }
+        bail!("Bad remaining length")
+    }
+
+    /// The kind of the next packet ending in `suffix`, past any events other tests record
+    fn until(client: &mut TcpStream, suffix: &[u8]) -> Result<u8> {
+        loop {
+            let (kind, body) = read_packet(client)?;
+            if body.ends_with(suffix) {
+                return Ok(kind);
+            }
+        }
+    }
+
+    #[test]
+    fn test_packet() -> Result<()> {
+        let body = vec![0x5a; 321];
+        let packet = packet(PUBLISH, &body);
+        // 321 = 0x41 + 2 * 128
+        assert_eq!(packet[..3], [PUBLISH, 0xc1, 0x02]);
+        assert_eq!(read_packet(&mut &packet[..])?, (PUBLISH, body));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mqtt() -> Result<()> {
+        let broker = TcpListener::bind("127.0.0.1:0")?;
+        let config = Config::parse(&format!(
+            "[mqtt]\nbroker = \"{}\"\ntopic = \"lab/c3\"",
+            broker.local_addr()?
+        ))?;
+        let mut mqtt = Mqtt::new(config.mqtt.as_ref().unwrap(), &Monotonic)?;
+        let (mut client, _) = broker.accept()?;
+        client.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let (kind, connect) = read_packet(&mut client)?;
+        assert_eq!(kind, CONNECT);
+        assert!(connect.ends_with(b"\0\x13lab/c3/availability\0\x07offline"));
+        client.write_all(&[CONNACK, 2, 0, 0])?;
+        let online = until(&mut client, b"lab/c3/availabilityonline")?;
+        assert_eq!(online, PUBLISH | RETAIN);
+
+        let mut status = StatusReport::new();
+        status.push("state", "armed");
+        mqtt.on_tick(&status)?;
+        let kind = until(&mut client, b"lab/c3/status{\"state\":\"armed\"}")?;
+        assert_eq!(kind, PUBLISH | RETAIN);
+        forward(&Event::Inhibit {
+            duration: Duration::from_secs(300),
+            name: String::from("deploy"),
+        });
+        let event = b",\"event\":\"inhibit 300 deploy\"}";
+        assert_eq!(until(&mut client, event)?, PUBLISH);
+
+        drop(mqtt);
+        until(&mut client, b"lab/c3/availabilityoffline")?;
+        assert_eq!(read_packet(&mut client)?, (DISCONNECT, Vec::new()));
+        Ok(())
+    }
+}
@@ -0,0 +1,254 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use mio::net::UdpSocket;
+use serde::{Deserialize, Deserializer};
+use std::{
+    ffi::CString,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV6},
+    str::FromStr,
+};
+#[cfg(target_os = "linux")]
+use std::{io::Error, mem, os::fd::AsRawFd};
+
+/// Like SocketAddr's FromStr, but also resolves interface names as IPv6 scopes
+pub fn parse_address(text: &str) -> Result<SocketAddr> {
+    if let Ok(address) = text.parse() {
+        return Ok(address);
+    }
+    let invalid = || anyhow!("Invalid address {:?}", text);
+    let (host, port) = text.rsplit_once("]:").ok_or_else(invalid)?;
+    let (ip, scope) = host
+        .strip_prefix('[')
+        .and_then(|host| host.split_once('%'))
+        .ok_or_else(invalid)?;
+    Ok(SocketAddr::V6(SocketAddrV6::new(
+        ip.parse().map_err(|_| invalid())?,
+        port.parse().map_err(|_| invalid())?,
+        0,
+        interface_index(scope)?,
+    )))
+}
+
+/// Resolves an interface name, or passes through a numeric index
+pub fn interface_index(interface: &str) -> Result<u32> {
+    if let Ok(index) = interface.parse() {
+        return Ok(index);
+    }
+    let name = CString::new(interface).context("Invalid interface name")?;
+    // SAFETY: name is a valid nul terminated string
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    ensure!(index != 0, "Unknown interface {:?}", interface);
+    Ok(index)
+}
+
+/// Joins a multicast group, on a specific interface or any if None
+pub fn join_multicast(socket: &UdpSocket, group: IpAddr, interface: Option<&str>) -> Result<()> {
+    let index = interface.map(interface_index).transpose()?.unwrap_or(0);
+    match group {
+        IpAddr::V4(group) => join_multicast_v4(socket, group, index)?,
+        IpAddr::V6(group) => socket.join_multicast_v6(&group, index)?,
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn join_multicast_v4(socket: &UdpSocket, group: Ipv4Addr, index: u32) -> Result<()> {
+    // ip_mreqn rather than ip_mreq so that the interface can be given by index
+    let mreq = libc::ip_mreqn {
+        imr_multiaddr: libc::in_addr {
+            s_addr: u32::from(group).to_be(),
+        },
+        imr_address: libc::in_addr { s_addr: 0 },
+        imr_ifindex: index as i32,
+    };
+    // SAFETY: mreq outlives the call and the length matches its type
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_ADD_MEMBERSHIP,
+            &mreq as *const _ as *const libc::c_void,
+            mem::size_of_val(&mreq) as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// Without ip_mreqn the interface would have to be given by address, only any is supported
+#[cfg(not(target_os = "linux"))]
+fn join_multicast_v4(socket: &UdpSocket, group: Ipv4Addr, index: u32) -> Result<()> {
+    ensure!(
+        index == 0,
+        "Multicast interfaces are only supported on Linux"
+    );
+    socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED)?;
+    Ok(())
+}
+
+/// Asks for a receive buffer of `bytes`, which the kernel caps at net.core.rmem_max. Returns what
+/// it got, for the request, not counting the half the kernel adds for its bookkeeping.
+pub fn set_recv_buffer(socket: &UdpSocket, bytes: usize) -> Result<usize> {
+    use nix::sys::socket::{getsockopt, setsockopt, sockopt::RcvBuf};
+    use std::os::fd::{AsRawFd, BorrowedFd};
+    // SAFETY: the socket outlives the borrow
+    let fd = unsafe { BorrowedFd::borrow_raw(socket.as_raw_fd()) };
+    setsockopt(&fd, RcvBuf, &bytes).context("Failed to set SO_RCVBUF")?;
+    let got = getsockopt(&fd, RcvBuf)?;
+    Ok(match cfg!(target_os = "linux") {
+        true => got / 2,
+        false => got,
+    })
+}
+
+// asm-generic/socket.h, which ARM uses, and the sk_meminfo array's length and drops offset
+#[cfg(target_os = "linux")]
+const SO_MEMINFO: libc::c_int = 55;
+#[cfg(target_os = "linux")]
+const SK_MEMINFO_VARS: usize = 9;
+#[cfg(target_os = "linux")]
+const SK_MEMINFO_DROPS: usize = 8;
+
+/// Datagrams the kernel dropped on the socket for lack of buffer space, from SO_MEMINFO
+#[cfg(target_os = "linux")]
+pub fn drops(socket: &impl AsRawFd) -> Option<u32> {
+    let mut meminfo = [0u32; SK_MEMINFO_VARS];
+    let mut len = mem::size_of_val(&meminfo) as libc::socklen_t;
+    // SAFETY: meminfo outlives the call and len is its size
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            SO_MEMINFO,
+            meminfo.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    (ret == 0).then(|| meminfo[SK_MEMINFO_DROPS])
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn drops<T>(_socket: &T) -> Option<u32> {
+    None
+}
+
+/// A nonblocking socket for sending to each of `targets`, of the matching address family, with
+/// broadcasts allowed over IPv4
+#[cfg(target_os = "linux")]
+pub fn senders(targets: &[SocketAddr]) -> Result<Vec<(std::net::UdpSocket, SocketAddr)>> {
+    let mut senders = Vec::new();
+    for &target in targets {
+        let socket = if target.is_ipv4() {
+            let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+            socket.set_broadcast(true)?;
+            socket
+        } else {
+            std::net::UdpSocket::bind((std::net::Ipv6Addr::UNSPECIFIED, 0))?
+        };
+        socket.set_nonblocking(true)?;
+        senders.push((socket, target));
+    }
+    Ok(senders)
+}
+
+/// An IP subnet in CIDR notation, a bare address is a single host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subnet {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl Subnet {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // Treat IPv4-mapped IPv6 sources (from dual stack sockets) as IPv4
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        match (self.address, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let (address, prefix) = match text.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (text, None),
+        };
+        let address: IpAddr = address
+            .parse()
+            .with_context(|| format!("Invalid subnet {:?}", text))?;
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .with_context(|| format!("Invalid subnet {:?}", text))?,
+            None => max,
+        };
+        if prefix > max {
+            bail!("Invalid prefix length in {:?}", text);
+        }
+        Ok(Self { address, prefix })
+    }
+}
+
+impl<'de> Deserialize<'de> for Subnet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_address() -> Result<()> {
+        let SocketAddr::V6(scoped) = parse_address("[fe80::1%3]:20001")? else {
+            panic!("Not IPv6")
+        };
+        assert_eq!(scoped.scope_id(), 3);
+        let SocketAddr::V6(named) = parse_address("[fe80::1%lo]:20001")? else {
+            panic!("Not IPv6")
+        };
+        assert_ne!(named.scope_id(), 0);
+
+        assert!(parse_address("[fe80::1%nonexistent0]:20001").is_err());
+        assert!(parse_address("localhost").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_subnet() -> Result<()> {
+        let net: Subnet = "192.168.1.0/24".parse()?;
+        assert!(net.contains("192.168.1.77".parse()?));
+        assert!(net.contains("::ffff:192.168.1.77".parse()?));
+        assert!(!net.contains("192.168.2.1".parse()?));
+        assert!(!net.contains("fe80::1".parse()?));
+
+        let host: Subnet = "fe80::1".parse()?;
+        assert!(host.contains("fe80::1".parse()?));
+        assert!(!host.contains("fe80::2".parse()?));
+
+        let all: Subnet = "0.0.0.0/0".parse()?;
+        assert!(all.contains("8.8.8.8".parse()?));
+
+        assert!("10.0.0.0/33".parse::<Subnet>().is_err());
+        assert!("10.0.0/8".parse::<Subnet>().is_err());
+        Ok(())
+    }
+}
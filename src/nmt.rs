@@ -0,0 +1,229 @@
+use crate::{
+    can::{CanSocket, Frame},
+    config::CanopenConfig,
+    exit::Cause,
+    pingee::Pingee,
+};
+use anyhow::Result;
+use log::info;
+use mio::{unix::SourceFd, Interest, Registry, Token};
+use std::{os::fd::AsRawFd, time::Duration};
+
+/// Function code of heartbeats, added to the node id. NMT commands are sent with id 0.
+const HEARTBEAT: u32 = 0x700;
+const NMT: u32 = 0;
+
+/// A CANopen node's NMT state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    BootUp,
+    Stopped,
+    Operational,
+    PreOperational,
+}
+
+impl State {
+    fn from_heartbeat(state: u8) -> Option<Self> {
+        match state & 0x7f {
+            0x00 => Some(State::BootUp),
+            0x04 => Some(State::Stopped),
+            0x05 => Some(State::Operational),
+            0x7f => Some(State::PreOperational),
+            _ => None,
+        }
+    }
+
+    fn from_command(command: u8) -> Option<Self> {
+        match command {
+            0x01 => Some(State::Operational),
+            0x02 => Some(State::Stopped),
+            0x80 => Some(State::PreOperational),
+            // Resets boot up again
+            0x81 | 0x82 => Some(State::BootUp),
+            _ => None,
+        }
+    }
+}
+
+/// Follows the C3's NMT state, from its heartbeats and the NMT commands addressed to it. While
+/// it's pre-operational, likely updating, deliberate resets wait. While it's operational the
+/// client timeouts can be tightened.
+pub struct Nmt {
+    socket: CanSocket,
+    follower: Follower,
+}
+
+impl Nmt {
+    /// None without a C3 node id to follow
+    pub fn new(config: &CanopenConfig) -> Result<Option<Self>> {
+        let Some(node_id) = config.c3_node_id else {
+            return Ok(None);
+        };
+        let socket = CanSocket::open(&config.interface)?;
+        socket.filter(&[HEARTBEAT + u32::from(node_id), NMT])?;
+        Ok(Some(Self {
+            socket,
+            follower: Follower::new(node_id, config.operational_timeout),
+        }))
+    }
+
+    pub fn register(&self, registry: &Registry, token: Token) -> Result<()> {
+        let fd = self.socket.as_raw_fd();
+        registry.register(&mut SourceFd(&fd), token, Interest::READABLE)?;
+        Ok(())
+    }
+
+    /// None until the first heartbeat or command
+    pub fn state(&self) -> Option<State> {
+        self.follower.state
+    }
+
+    /// A deliberate reset, returned unless the C3 is pre-operational. Otherwise it's returned by
+    /// `on_readable` once it isn't anymore.
+    pub fn request(&mut self, reason: String, cause: Cause) -> Option<(String, Cause)> {
+        self.follower.request(reason, cause)
+    }
+
+    /// The reset due, if the C3 left pre-operational
+    pub fn on_readable(&mut self, pingee: &mut Pingee) -> Result<Option<(String, Cause)>> {
+        while let Some(frame) = self.socket.recv()? {
+            self.follower.on_frame(&frame, pingee)?;
+        }
+        Ok(self.follower.release())
+    }
+}
+
+/// The state machine, apart from the socket
+struct Follower {
+    node_id: u8,
+    operational_timeout: Duration,
+    state: Option<State>,
+    /// Each client slot's timeout from before tightening
+    saved: Vec<Option<Duration>>,
+    /// A reset waiting for the C3 to leave pre-operational
+    deferred: Option<(String, Cause)>,
+}
+
+impl Follower {
+    fn new(node_id: u8, operational_timeout: Duration) -> Self {
+        Self {
+            node_id,
+            operational_timeout,
+            state: None,
+            saved: Vec::new(),
+            deferred: None,
+        }
+    }
+
+    fn request(&mut self, reason: String, cause: Cause) -> Option<(String, Cause)> {
+        if self.state == Some(State::PreOperational) {
+            info!(
+                "{}, deferred until the C3 is no longer pre-operational",
+                reason
+            );
+            self.deferred = Some((reason, cause));
+            return None;
+        }
+        Some((reason, cause))
+    }
+
+    fn release(&mut self) -> Option<(String, Cause)> {
+        if self.state == Some(State::PreOperational) {
+            return None;
+        }
+        self.deferred.take()
+    }
+
+    fn on_frame(&mut self, frame: &Frame, pingee: &mut Pingee) -> Result<()> {
+        let state = match (frame.id, frame.payload()) {
+            (id, &[state, ..]) if id == HEARTBEAT + u32::from(self.node_id) => {
+                State::from_heartbeat(state)
+            }
+            // Node 0 addresses every node
+            (NMT, &[command, node]) if node == self.node_id || node == 0 => {
+                State::from_command(command)
+            }
+            _ => None,
+        };
+        match state {
+            Some(state) if self.state != Some(state) => self.transition(state, pingee),
+            _ => Ok(()),
+        }
+    }
+
+    fn transition(&mut self, state: State, pingee: &mut Pingee) -> Result<()> {
+        info!("C3 is {:?}", state);
+        let tight = self.operational_timeout;
+        if !tight.is_zero() {
+            if state == State::Operational {
+                self.saved = (0..pingee.slots())
+                    .map(|slot| pingee.timeout(slot))
+                    .collect();
+                for (slot, timeout) in self.saved.iter().enumerate() {
+                    if timeout.is_some_and(|timeout| timeout > tight) {
+                        pingee.set_timeout(slot, tight)?;
+                    }
+                }
+            } else if self.state == Some(State::Operational) {
+                // Unless it was changed since, or the slot is someone else's now
+                for (slot, saved) in self.saved.drain(..).enumerate() {
+                    if let Some(saved) = saved {
+                        if pingee.timeout(slot) == Some(saved.min(tight)) {
+                            pingee.set_timeout(slot, saved)?;
+                        }
+                    }
+                }
+            }
+        }
+        self.state = Some(state);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use mio::Token;
+
+    #[test]
+    fn test_states() -> Result<()> {
+        assert_eq!(State::from_heartbeat(0x05), Some(State::Operational));
+        assert_eq!(State::from_heartbeat(0xff), Some(State::PreOperational));
+        assert_eq!(State::from_heartbeat(0x42), None);
+        assert_eq!(State::from_command(0x80), Some(State::PreOperational));
+        assert_eq!(State::from_command(0x81), Some(State::BootUp));
+        Ok(())
+    }
+
+    #[test]
+    fn test_follower() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            timeout = 30
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        let mut follower = Follower::new(0x01, Duration::from_secs(10));
+
+        follower.on_frame(&Frame::new(0x701, &[0x05])?, &mut pingee)?;
+        assert_eq!(follower.state, Some(State::Operational));
+        assert_eq!(pingee.timeout(0), Some(Duration::from_secs(10)));
+        // Addressed to another node
+        follower.on_frame(&Frame::new(0, &[0x80, 0x02])?, &mut pingee)?;
+        assert_eq!(follower.state, Some(State::Operational));
+        follower.on_frame(&Frame::new(0, &[0x80, 0x01])?, &mut pingee)?;
+        assert_eq!(follower.state, Some(State::PreOperational));
+        assert_eq!(pingee.timeout(0), Some(Duration::from_secs(30)));
+
+        let reset = (String::from("Up for too long"), Cause::Uptime);
+        assert_eq!(follower.request(reset.0.clone(), reset.1), None);
+        assert_eq!(follower.release(), None);
+        follower.on_frame(&Frame::new(0x701, &[0x05])?, &mut pingee)?;
+        assert_eq!(follower.release(), Some(reset));
+        Ok(())
+    }
+}
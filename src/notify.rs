@@ -0,0 +1,153 @@
+use anyhow::{Context, Result};
+use log::warn;
+use std::{
+    env,
+    ffi::OsStr,
+    os::{
+        fd::{FromRawFd, OwnedFd, RawFd},
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixDatagram},
+        },
+    },
+    process,
+    time::{Duration, Instant},
+};
+
+// The first fd passed by socket activation, see sd_listen_fds(3)
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Sends `state` to systemd, e.g. "STATUS=...", see sd_notify(3). Nothing happens when not
+/// started by systemd.
+pub fn notify(state: &str) -> Result<()> {
+    match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => notify_to(&path, state),
+        None => Ok(()),
+    }
+}
+
+fn notify_to(path: &OsStr, state: &str) -> Result<()> {
+    UnixDatagram::unbound()?
+        .send_to_addr(state.as_bytes(), &address(path)?)
+        .with_context(|| format!("Failed to notify {:?}", path))?;
+    Ok(())
+}
+
+fn address(path: &OsStr) -> Result<SocketAddr> {
+    let address = match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    Ok(address)
+}
+
+/// Whether a variable systemd sets names this process, for the ones only meant for the main one
+fn ours(pid: &str) -> bool {
+    env::var(pid).ok().and_then(|pid| pid.parse().ok()) == Some(process::id())
+}
+
+/// The sockets systemd passed in the socket unit's order, none when not socket activated. Taken
+/// only once, and kept from any children, like the hook.
+pub fn listen_fds() -> Result<Vec<OwnedFd>> {
+    if !ours("LISTEN_PID") {
+        return Ok(Vec::new());
+    }
+    let count: RawFd = env::var("LISTEN_FDS")?.parse().context("Bad LISTEN_FDS")?;
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            // SAFETY: systemd passed these and nothing else owns them, LISTEN_PID says
+            // they're for this process
+            if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+                return Err(std::io::Error::last_os_error()).context("Bad LISTEN_FDS");
+            }
+            Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+        })
+        .collect()
+}
+
+/// Tells systemd that the loop is still petting, every half of the unit's WatchdogSec, so that
+/// a hung loop is killed and restarted rather than left holding the line. The socket is set up
+/// front, seccomp doesn't allow creating one later.
+pub struct Keepalive {
+    socket: UnixDatagram,
+    address: SocketAddr,
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl Keepalive {
+    /// None without a WatchdogSec for this process
+    pub fn from_env() -> Result<Option<Self>> {
+        let (Some(path), Some(usec)) =
+            (env::var_os("NOTIFY_SOCKET"), env::var("WATCHDOG_USEC").ok())
+        else {
+            return Ok(None);
+        };
+        if env::var_os("WATCHDOG_PID").is_some() && !ours("WATCHDOG_PID") {
+            return Ok(None);
+        }
+        let usec: u64 = usec.parse().context("Bad WATCHDOG_USEC")?;
+        Ok(Some(Self {
+            socket: UnixDatagram::unbound()?,
+            address: address(&path)?,
+            interval: Duration::from_micros(usec) / 2,
+            last: None,
+        }))
+    }
+
+    /// Called on every pet, only notifies once the interval is up. A failure is only warned
+    /// about, systemd killing the loop for it is no worse than it not petting.
+    pub fn on_pet(&mut self) {
+        let now = Instant::now();
+        if self.last.is_some_and(|last| now < last + self.interval) {
+            return;
+        }
+        self.last = Some(now);
+        if let Err(e) = self.socket.send_to_addr(b"WATCHDOG=1", &self.address) {
+            warn!("Failed to notify the systemd watchdog: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify() -> Result<()> {
+        let path = env::temp_dir().join(format!("watchdog-notify-{}", std::process::id()));
+        let systemd = UnixDatagram::bind(&path)?;
+        notify_to(path.as_os_str(), "STATUS=Self-test: PASS")?;
+        let mut buf = [0; 64];
+        let len = systemd.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"STATUS=Self-test: PASS");
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_keepalive() -> Result<()> {
+        let path = env::temp_dir().join(format!("watchdog-keepalive-{}", std::process::id()));
+        let systemd = UnixDatagram::bind(&path)?;
+        systemd.set_nonblocking(true)?;
+        let mut keepalive = Keepalive {
+            socket: UnixDatagram::unbound()?,
+            address: address(path.as_os_str())?,
+            interval: Duration::from_secs(60),
+            last: None,
+        };
+        keepalive.on_pet();
+        keepalive.on_pet();
+        let mut buf = [0; 64];
+        let len = systemd.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"WATCHDOG=1");
+        // The second one was within the interval
+        assert!(systemd.recv(&mut buf).is_err());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
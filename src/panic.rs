@@ -0,0 +1,81 @@
+use crate::{
+    config::PanicLine,
+    exit::Cause,
+    history::{self, Event},
+    last_gasp::LastGasp,
+    petter::Stopper,
+    stats,
+};
+use log::{error, info};
+use std::{any::Any, backtrace::Backtrace, panic, path::PathBuf, process, thread};
+
+/// Ends the watchdog from the hook on a panic on any thread, rather than leaving it to unwind: a
+/// helper thread panicking would leave the loop running without it, and only the main thread's
+/// unwinding reaches the petter's drop. It's recorded like any other way of going down, the line
+/// is set as configured, and the process exits with `Cause::Panic`. The hook is process-wide, so
+/// only the binary installs it.
+pub fn install(line: PanicLine, stopper: Option<Stopper>, last_gasp: Option<PathBuf>) {
+    if line == PanicLine::SafeLow && stopper.is_none() {
+        info!("Nothing to set the line low with on a panic, it's left to the exit");
+    }
+    panic::set_hook(Box::new(move |info| {
+        let thread = thread::current();
+        let reason = format!(
+            "{} thread panicked at {}: {}",
+            thread.name().unwrap_or("Unnamed"),
+            info.location()
+                .map_or(String::from("?"), ToString::to_string),
+            payload(info.payload())
+        );
+        let backtrace = Backtrace::force_capture().to_string();
+        error!("{}\n{}", reason, backtrace);
+        history::record(Event::Panic {
+            message: reason.clone(),
+        });
+        if let Some(path) = &last_gasp {
+            let history = history::dump();
+            let gasp = LastGasp {
+                cause: Cause::Panic,
+                reason: &reason,
+                last_ping: None,
+                pets: stats::totals().pets,
+                history: &history,
+                status: None,
+                backtrace: Some(&backtrace),
+            };
+            if let Err(e) = gasp.write(path) {
+                error!("Failed to write last gasp: {:#}", e);
+            }
+        }
+        match (line, &stopper) {
+            (PanicLine::SafeLow, Some(stopper)) => match stopper.stop() {
+                Ok(()) => error!("Line set low for the supervisor to reset the card"),
+                Err(e) => error!("Failed to set the line low: {}", e),
+            },
+            _ => error!("Leaving the line as it is"),
+        }
+        process::exit(Cause::Panic as i32);
+    }));
+}
+
+/// What was passed to panic!, which is a &str without arguments to format and a String with
+fn payload(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload.downcast_ref::<String>().map_or("?", String::as_str),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload() {
+        let caught = panic::catch_unwind(|| panic!("Line stuck"));
+        assert_eq!(payload(caught.unwrap_err().as_ref()), "Line stuck");
+        let caught = panic::catch_unwind(|| panic!("Line {} stuck", 25));
+        assert_eq!(payload(caught.unwrap_err().as_ref()), "Line 25 stuck");
+        assert_eq!(payload(&42), "?");
+    }
+}
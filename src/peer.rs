@@ -0,0 +1,262 @@
+use crate::{
+    can::CanSocket,
+    clock::{Clock, Timer},
+    condition::Condition,
+    config::PeerConfig,
+    exit::Cause,
+    history::{self, Event},
+    petter::{Backend, Gpio},
+    pingee::Pingee,
+    stats,
+};
+use anyhow::{Context, Result};
+use log::{info, warn};
+use mio::{net::UdpSocket, unix::SourceFd, Interest, Registry, Token};
+use std::{io::ErrorKind, os::fd::AsRawFd, time::Duration};
+
+/// Tokens each peer takes, its UDP then its CAN heartbeats
+const TOKENS: usize = 2;
+
+/// Companion cards watched for their heartbeats, like the battery card's, each power cycled
+/// through a line of its own once they stop for its timeout. This is the watchdog for another
+/// board rather than for this one, so nothing here feeds a client or stops the petting.
+pub struct Peers {
+    peers: Vec<Peer>,
+}
+
+struct Peer {
+    name: String,
+    timeout: Duration,
+    boot: Duration,
+    pulse: Duration,
+    active_low: bool,
+    socket: Option<UdpSocket>,
+    can: Option<CanSocket>,
+    backend: Box<dyn Backend>,
+    /// Runs out on silence, or once the pulse is over while cycling
+    timer: Box<dyn Timer>,
+    /// Whether the line is asserted
+    cycling: bool,
+}
+
+impl Peers {
+    /// Registered with tokens counting up from `first`, with their timers on `clock`
+    pub fn new(
+        registry: &Registry,
+        first: Token,
+        configs: &[PeerConfig],
+        clock: &dyn Clock,
+    ) -> Result<Self> {
+        let mut peers = Vec::new();
+        for (index, config) in configs.iter().enumerate() {
+            let token = |offset| Token(first.0 + index * TOKENS + offset);
+            let gpio = Gpio::request(&config.chip, &config.label, config.line)
+                .with_context(|| format!("Failed to request the {} peer's line", config.name))
+                .context(Cause::Gpio)?;
+            let mut peer = Peer::new(config, Box::new(gpio), clock.timer()?)?;
+            if let Some(address) = config.address {
+                let mut socket = UdpSocket::bind(address)
+                    .with_context(|| format!("Failed to bind {} for heartbeats", address))?;
+                registry.register(&mut socket, token(0), Interest::READABLE)?;
+                peer.socket = Some(socket);
+            }
+            if let Some(can) = &config.can {
+                let socket = CanSocket::open(&can.interface)?;
+                socket.filter(&[can.id])?;
+                let fd = socket.as_raw_fd();
+                registry.register(&mut SourceFd(&fd), token(1), Interest::READABLE)?;
+                peer.can = Some(socket);
+            }
+            peers.push(peer);
+        }
+        Ok(Self { peers })
+    }
+}
+
+impl Peer {
+    /// Deasserted, and given its boot time
+    fn new(
+        config: &PeerConfig,
+        mut backend: Box<dyn Backend>,
+        timer: Box<dyn Timer>,
+    ) -> Result<Self> {
+        backend.set(config.active_low)?;
+        timer.set(config.boot + config.timeout)?;
+        stats::peer_watched(&config.name);
+        Ok(Self {
+            name: config.name.clone(),
+            timeout: config.timeout,
+            boot: config.boot,
+            pulse: config.pulse,
+            active_low: config.active_low,
+            socket: None,
+            can: None,
+            backend,
+            timer,
+            cycling: false,
+        })
+    }
+
+    /// Whatever arrived on either, heartbeats that come in while it's powered off aside
+    fn on_event(&mut self, index: usize) -> Result<()> {
+        let mut heard = false;
+        if let (0, Some(socket)) = (index, &self.socket) {
+            let mut buf = [0; 64];
+            loop {
+                match socket.recv_from(&mut buf) {
+                    Ok(_) => heard = true,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        warn!(
+                            "Failed to receive the {} peer's heartbeat: {}",
+                            self.name, e
+                        );
+                        break;
+                    }
+                }
+            }
+        }
+        if let (1, Some(can)) = (index, &self.can) {
+            while can.recv()?.is_some() {
+                heard = true;
+            }
+        }
+        if heard && !self.cycling {
+            stats::peer_heartbeat(&self.name);
+            self.timer.set(self.timeout)?;
+        }
+        Ok(())
+    }
+
+    /// Cuts its power once it's been silent for too long, and restores it after the pulse
+    fn on_pet(&mut self) -> Result<()> {
+        if !self.timer.remaining()?.is_zero() {
+            return Ok(());
+        }
+        match self.cycling {
+            false => {
+                warn!(
+                    "{} peer silent for {:?}, power cycling it",
+                    self.name, self.timeout
+                );
+                stats::peer_cycled(&self.name);
+                history::record(Event::PeerCycle {
+                    peer: self.name.clone(),
+                });
+                // Set first, so that a line that can't be set is retried after the pulse
+                self.timer.set(self.pulse)?;
+                self.backend.set(!self.active_low)?;
+            }
+            true => {
+                info!("{} peer power cycled, {:?} to boot", self.name, self.boot);
+                self.timer.set(self.boot + self.timeout)?;
+                self.backend.set(self.active_low)?;
+            }
+        }
+        self.cycling = !self.cycling;
+        Ok(())
+    }
+}
+
+/// Not left powered off by a watchdog going down mid-pulse
+impl Drop for Peer {
+    fn drop(&mut self) {
+        if self.cycling {
+            let _ = self.backend.set(self.active_low);
+        }
+    }
+}
+
+impl Condition for Peers {
+    fn name(&self) -> &'static str {
+        "peers"
+    }
+
+    fn tokens(&self) -> usize {
+        self.peers.len() * TOKENS
+    }
+
+    fn on_event(&mut self, index: usize, _pingee: &mut Pingee) -> Result<Option<String>> {
+        self.peers[index / TOKENS].on_event(index % TOKENS)?;
+        Ok(None)
+    }
+
+    /// A line that can't be set is only warned about, rather than taking down the petting of
+    /// this card over another one
+    fn on_pet(&mut self, _pingee: &mut Pingee) -> Result<()> {
+        for peer in &mut self.peers {
+            if let Err(e) = peer.on_pet() {
+                warn!(
+                    "{:#}",
+                    e.context(format!("Failed to cycle the {} peer", peer.name))
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::VirtualClock, config::Config, petter::Mock};
+    use mio::{Events, Poll};
+
+    #[test]
+    fn test_peer() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            [[peer]]
+            name = "battery"
+            address = "127.0.0.1:0"
+            timeout = 10
+            boot = 30
+            chip = "gpiochip0"
+            label = "BAT_PWR"
+            line = 7
+            active_low = true
+            pulse = 2
+            "#,
+        )?;
+        let clock = VirtualClock::new();
+        let mock = Mock::default();
+        let mut peer = Peer::new(&config.peers[0], Box::new(mock.clone()), clock.timer()?)?;
+        let mut poll = Poll::new()?;
+        let mut socket = UdpSocket::bind(config.peers[0].address.unwrap())?;
+        poll.registry()
+            .register(&mut socket, Token(0), Interest::READABLE)?;
+        let address = socket.local_addr()?;
+        peer.socket = Some(socket);
+        assert_eq!(mock.values(), [true]);
+
+        // Booting, then heard from
+        clock.advance(Duration::from_secs(35));
+        peer.on_pet()?;
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0")?;
+        sender.send_to(b"HEARTBEAT", address)?;
+        poll.poll(&mut Events::with_capacity(4), Some(Duration::from_secs(1)))?;
+        peer.on_event(0)?;
+        clock.advance(Duration::from_secs(9));
+        peer.on_pet()?;
+        assert_eq!(mock.values(), [true]);
+
+        clock.advance(Duration::from_secs(1));
+        peer.on_pet()?;
+        assert_eq!(mock.values(), [true, false]);
+        clock.advance(Duration::from_secs(2));
+        peer.on_pet()?;
+        assert_eq!(mock.values(), [true, false, true]);
+        // Booting again
+        clock.advance(Duration::from_secs(39));
+        peer.on_pet()?;
+        assert_eq!(mock.values().len(), 3);
+        clock.advance(Duration::from_secs(1));
+        peer.on_pet()?;
+        assert_eq!(mock.values().len(), 4);
+
+        // Back on when dropped
+        drop(peer);
+        assert_eq!(mock.values(), [true, false, true, false, true]);
+        Ok(())
+    }
+}
@@ -0,0 +1,1083 @@
+use anyhow::{bail, Result};
+#[cfg(all(target_os = "linux", feature = "sim"))]
+use gpiosim::{Bank, Sim};
+use std::os::fd::RawFd;
+#[cfg(any(test, not(target_os = "linux")))]
+use std::sync::{Arc, Mutex};
+#[cfg(target_os = "linux")]
+use {
+    crate::{
+        clock::{Clock, Monotonic, Timer},
+        config::{Config, PetConfig, PetStep, PwmConfig},
+        dictionary::Counter,
+        exit::Cause,
+        history::{self, Event},
+        stats::{self, Syscall},
+    },
+    anyhow::{ensure, Context},
+    gpiod::{Chip, LineInfo, Lines, Options, Output},
+    log::{debug, warn},
+    mio::{Registry, Token},
+    std::{
+        fs::{self, File, OpenOptions},
+        os::{
+            fd::{AsRawFd, OwnedFd},
+            unix::fs::FileExt,
+        },
+        path::{Path, PathBuf},
+        thread,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+};
+
+pub const GPIO_LABEL: &str = "PET_WDT";
+pub const GPIO_LINE: u32 = 25;
+pub const GPIO_CHIP: &str = "gpiochip2";
+const GPIO_CONSUMER: &str = "C3_Watchdog";
+#[cfg(target_os = "linux")]
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(1);
+#[cfg(target_os = "linux")]
+const SYSFS_GPIO: &str = "/sys/class/gpio";
+
+#[cfg(target_os = "linux")]
+// GPIO_V2_LINE_SET_VALUES_IOCTL, _IOWR(0xB4, 0x0F, struct gpio_v2_line_values)
+const GPIO_V2_LINE_SET_VALUES: u64 = 0xC010_B40F;
+#[cfg(target_os = "linux")]
+// GPIO_V2_LINE_GET_VALUES_IOCTL, _IOWR(0xB4, 0x0E, struct gpio_v2_line_values)
+const GPIO_V2_LINE_GET_VALUES: u64 = 0xC010_B40E;
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct LineValues {
+    bits: u64,
+    mask: u64,
+}
+
+/// Whatever the pets toggle. On the C3 that's the watchdog GPIO line, test rigs can bring their
+/// own.
+pub trait Backend: Send {
+    fn set(&mut self, value: bool) -> Result<()>;
+
+    /// The level the line is at, None if it can't be read back
+    fn get(&self) -> Result<Option<bool>> {
+        Ok(None)
+    }
+
+    /// What a successor needs to keep petting, see the handover
+    fn fd(&self) -> Result<RawFd> {
+        bail!("Nothing to hand over")
+    }
+
+    /// Stops feeding the supervisor, so that it bites
+    fn stop(&mut self) -> Result<()> {
+        self.set(false)
+    }
+
+    /// Stops it without the backend, for the panic hook, None if there's no way to
+    #[cfg(target_os = "linux")]
+    fn stopper(&self) -> Option<Stopper> {
+        None
+    }
+}
+
+/// What the panic hook stops feeding the supervisor with, as it can't get at the backend
+#[cfg(target_os = "linux")]
+pub enum Stopper {
+    /// A GPIO uAPI line request, set low
+    Line(RawFd),
+    /// A sysfs file written "0", a GPIO's value or a PWM channel's enable
+    Sysfs(File),
+}
+
+#[cfg(target_os = "linux")]
+impl Stopper {
+    pub fn stop(&self) -> std::io::Result<()> {
+        match self {
+            Stopper::Line(fd) => {
+                let values = LineValues { bits: 0, mask: 1 };
+                // SAFETY: values outlives the call and matches the ioctl's argument type
+                let ret =
+                    unsafe { libc::ioctl(*fd, GPIO_V2_LINE_SET_VALUES as libc::Ioctl, &values) };
+                match ret < 0 {
+                    true => Err(std::io::Error::last_os_error()),
+                    false => Ok(()),
+                }
+            }
+            Stopper::Sysfs(file) => file.write_all_at(b"0", 0),
+        }
+    }
+}
+
+/// The requested GPIO line. gpiod can't build Lines from an fd, so a line handed over by a
+/// previous instance is driven through the GPIO uAPI directly.
+#[cfg(target_os = "linux")]
+pub enum Gpio {
+    Requested(Lines<Output>),
+    Inherited(OwnedFd),
+}
+
+/// The line is already requested, by whoever the kernel names as its consumer
+#[derive(Debug)]
+pub struct Busy {
+    pub label: String,
+    pub consumer: String,
+}
+
+impl Busy {
+    /// Held by another instance of the watchdog, which might hand it over
+    pub fn ours(&self) -> bool {
+        self.consumer == GPIO_CONSUMER
+    }
+}
+
+impl std::fmt::Display for Busy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.consumer.as_str() {
+            "" => write!(f, "GPIO line {} is already in use", self.label),
+            consumer => write!(
+                f,
+                "GPIO line {} is already held by {:?}",
+                self.label, consumer
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Busy {}
+
+#[cfg(target_os = "linux")]
+impl Gpio {
+    /// Fails with a `Busy` if something else holds the line
+    pub fn request(gpio_chip: &str, gpio_label: &str, gpio_line: u32) -> Result<Self> {
+        let chip = Chip::new(gpio_chip).context("Failed to get GPIO chip")?;
+
+        let info = named(&chip, gpio_label, gpio_line)?;
+        let busy = |consumer| Busy {
+            label: gpio_label.to_string(),
+            consumer,
+        };
+        if info.used {
+            bail!(busy(info.consumer));
+        }
+
+        let opts = Options::output([gpio_line])
+            .values([false])
+            .consumer(GPIO_CONSUMER);
+        match chip.request_lines(opts) {
+            Ok(line) => Ok(Gpio::Requested(line)),
+            // Taken since the line info was read
+            Err(e) if e.raw_os_error() == Some(libc::EBUSY) => {
+                let consumer = chip.line_info(gpio_line).map(|i| i.consumer);
+                bail!(busy(consumer.unwrap_or_default()))
+            }
+            Err(e) => Err(e).context("Failed to get GPIO pin"),
+        }
+    }
+}
+
+/// The line's info, once it's known to be the one named `gpio_label`
+#[cfg(target_os = "linux")]
+fn named(chip: &Chip, gpio_label: &str, gpio_line: u32) -> Result<LineInfo> {
+    let info = chip.line_info(gpio_line)?;
+    ensure!(
+        info.name == gpio_label,
+        "Invalid GPIO LINE label: expected {:?}, found {:?}",
+        gpio_label,
+        info.name
+    );
+    Ok(info)
+}
+
+/// Checks that `gpio_line` on `gpio_chip` is the one named `gpio_label` and that nothing but a
+/// watchdog holds it, without requesting it
+#[cfg(target_os = "linux")]
+pub fn resolve(gpio_chip: &str, gpio_label: &str, gpio_line: u32) -> Result<()> {
+    let chip = Chip::new(gpio_chip).with_context(|| format!("No GPIO chip {}", gpio_chip))?;
+    let info = named(&chip, gpio_label, gpio_line)?;
+    let busy = Busy {
+        label: gpio_label.to_string(),
+        consumer: info.consumer,
+    };
+    if info.used && !busy.ours() {
+        bail!(busy);
+    }
+    Ok(())
+}
+
+/// Resolves every line the config drives or requests, with the watchdog line on `gpio_chip`
+/// unless it's petted some other way, collecting what's wrong with each
+#[cfg(target_os = "linux")]
+pub fn check_lines(config: &Config, gpio_chip: &str) -> Vec<anyhow::Error> {
+    let mut problems = Vec::new();
+    let mut check = |what: String, resolved: Result<()>| {
+        if let Err(e) = resolved {
+            problems.push(e.context(what));
+        }
+    };
+    if let Some(pwm) = &config.pet.pwm {
+        let exists = pwm.chip.exists().then_some(());
+        check(
+            String::from("PWM channel"),
+            exists.with_context(|| format!("No PWM chip {:?}", pwm.chip)),
+        );
+    } else if config.pet.sysfs_gpio.is_some() {
+        let exists = Path::new(SYSFS_GPIO).exists().then_some(());
+        check(
+            String::from("Watchdog line"),
+            exists.context("No sysfs GPIO interface"),
+        );
+    } else {
+        let resolved = resolve(gpio_chip, GPIO_LABEL, GPIO_LINE);
+        check(String::from("Watchdog line"), resolved);
+    }
+    for line in &config.lines {
+        let resolved = resolve(&line.chip, &line.label, line.line);
+        check(format!("{} line", line.name), resolved);
+    }
+    if let Some(reset) = &config.reset_request {
+        let resolved = resolve(&reset.chip, &reset.label, reset.line);
+        check(String::from("Reset request line"), resolved);
+    }
+    if let Some(supervisor) = &config.supervisor {
+        let resolved = resolve(&supervisor.chip, &supervisor.label, supervisor.line);
+        check(String::from("Supervisor line"), resolved);
+    }
+    for peer in &config.peers {
+        let resolved = resolve(&peer.chip, &peer.label, peer.line);
+        check(format!("{} peer line", peer.name), resolved);
+    }
+    problems
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn check_lines(_config: &crate::config::Config, gpio_chip: &str) -> Vec<anyhow::Error> {
+    vec![anyhow::anyhow!(
+        "Can't resolve the lines on {} off Linux",
+        gpio_chip
+    )]
+}
+
+#[cfg(target_os = "linux")]
+impl Backend for Gpio {
+    fn set(&mut self, value: bool) -> Result<()> {
+        match self {
+            Gpio::Requested(lines) => {
+                stats::timed(Syscall::SetValues, || lines.set_values([value]))
+                    .context(Cause::Gpio)?
+            }
+            Gpio::Inherited(fd) => {
+                let values = LineValues {
+                    bits: value as u64,
+                    mask: 1,
+                };
+                // SAFETY: values outlives the call and matches the ioctl's argument type
+                let ret = stats::timed(Syscall::SetValues, || unsafe {
+                    libc::ioctl(
+                        fd.as_raw_fd(),
+                        GPIO_V2_LINE_SET_VALUES as libc::Ioctl,
+                        &values,
+                    )
+                });
+                if ret < 0 {
+                    return Err(std::io::Error::last_os_error()).context(Cause::Gpio);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<Option<bool>> {
+        let value = match self {
+            Gpio::Requested(lines) => lines.get_values(0u8)? & 1 == 1,
+            Gpio::Inherited(fd) => {
+                let mut values = LineValues { bits: 0, mask: 1 };
+                // SAFETY: values outlives the call and matches the ioctl's argument type
+                let ret = unsafe {
+                    libc::ioctl(
+                        fd.as_raw_fd(),
+                        GPIO_V2_LINE_GET_VALUES as libc::Ioctl,
+                        &mut values,
+                    )
+                };
+                if ret < 0 {
+                    return Err(std::io::Error::last_os_error()).context(Cause::Gpio);
+                }
+                values.bits & 1 == 1
+            }
+        };
+        Ok(Some(value))
+    }
+
+    fn fd(&self) -> Result<RawFd> {
+        match self {
+            Gpio::Inherited(fd) => Ok(fd.as_raw_fd()),
+            // gpiod doesn't expose the fd of a request, but it's the only line this process holds
+            Gpio::Requested(_) => {
+                for entry in fs::read_dir("/proc/self/fd")? {
+                    let entry = entry?;
+                    if fs::read_link(entry.path())?.as_os_str() == "anon_inode:gpio-line" {
+                        return Ok(entry.file_name().to_string_lossy().parse()?);
+                    }
+                }
+                bail!("GPIO line fd not found")
+            }
+        }
+    }
+
+    fn stopper(&self) -> Option<Stopper> {
+        self.fd().ok().map(Stopper::Line)
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub struct Petter {
+    backend: Box<dyn Backend>,
+    timer: Box<dyn Timer>,
+    pattern: Vec<PetStep>,
+    /// Switched to once the current cycle is through
+    pending: Option<Vec<PetStep>>,
+    /// The step the next pet goes to
+    next: usize,
+    /// Handed over to a successor, so the line isn't pulled low on drop
+    released: bool,
+    pets: u64,
+    /// When the timer is due to fire next, the schedule the pets after it are kept on
+    due: Instant,
+    /// The next step starts the schedule over from now rather than from `due`
+    fresh: bool,
+    /// How late the last expiration was, until the pet it called for went through
+    owed: Option<Duration>,
+    /// The offset the cycles are aligned to on the wall clock, if they are
+    align: Option<Duration>,
+    /// How far the current cycle started past its boundary, in nanoseconds, negative if early
+    phase: i64,
+}
+
+#[cfg(target_os = "linux")]
+impl Petter {
+    pub fn new(gpio_chip: &str, gpio_label: &str, gpio_line: u32) -> Result<Self> {
+        let gpio = Gpio::request(gpio_chip, gpio_label, gpio_line)?;
+        Self::with_backend(Box::new(gpio))
+    }
+
+    /// Takes over a line requested by a previous instance
+    pub fn inherit(line: OwnedFd) -> Result<Self> {
+        Self::with_backend(Box::new(Gpio::Inherited(line)))
+    }
+
+    pub fn with_backend(backend: Box<dyn Backend>) -> Result<Self> {
+        Ok(Petter {
+            backend,
+            timer: Monotonic.timer()?,
+            pattern: PetConfig::default().pattern,
+            pending: None,
+            next: 0,
+            released: false,
+            pets: 0,
+            due: Instant::now(),
+            fresh: true,
+            owed: None,
+            align: None,
+            phase: 0,
+        })
+    }
+
+    /// Moves the pet timer onto `clock`, before the first pet
+    pub fn set_clock(&mut self, clock: &dyn Clock) -> Result<()> {
+        self.timer = clock.timer()?;
+        Ok(())
+    }
+
+    /// Takes effect from a fresh cycle, a validated one from the config
+    pub fn set_pattern(&mut self, pattern: &[PetStep]) {
+        self.pattern = pattern.to_vec();
+        self.pending = None;
+        self.next = 0;
+    }
+
+    /// Starts the cycles `offset` past a multiple of the period on the wall clock, from the next
+    /// one on. Each cycle's last step makes up for how far off it started, so that the wall
+    /// clock's drift and steps are caught up with gradually.
+    pub fn set_align(&mut self, offset: Option<Duration>) {
+        self.align = offset;
+    }
+
+    /// Takes over from the next cycle, so that the current one still feeds the supervisor the
+    /// way it expects
+    pub fn switch_pattern(&mut self, pattern: &[PetStep]) {
+        self.pending = Some(pattern.to_vec());
+    }
+
+    /// Petted through from the next cycle on
+    pub fn pattern(&self) -> &[PetStep] {
+        self.pending.as_deref().unwrap_or(&self.pattern)
+    }
+
+    /// One whole cycle of the pattern
+    pub fn period(&self) -> Duration {
+        self.pattern.iter().map(|step| step.duration).sum()
+    }
+
+    /// The timer becomes readable when the next pet is due
+    pub fn register(&self, registry: &Registry, token: Token) -> Result<()> {
+        self.timer.register(registry, token)
+    }
+
+    /// Until the pet timer fires, zero if it's overdue or stopped
+    pub fn next_pet(&self) -> Result<Duration> {
+        self.timer.remaining()
+    }
+
+    /// How many cycles of the pattern were started
+    pub fn pets(&self) -> u64 {
+        self.pets
+    }
+
+    pub fn fd(&self) -> Result<RawFd> {
+        self.backend.fd()
+    }
+
+    pub fn stopper(&self) -> Option<Stopper> {
+        self.backend.stopper()
+    }
+
+    /// Pets right away, with the next one a step from now. A failed pet can be retried, the phase
+    /// only advances once it went through.
+    pub fn pet(&mut self) -> Result<()> {
+        self.fresh = true;
+        self.step()
+    }
+
+    fn step(&mut self) -> Result<()> {
+        if self.next == 0 {
+            if let Some(pattern) = self.pending.take() {
+                self.pattern = pattern;
+            }
+        }
+        let step = self.pattern[self.next];
+        let value = step.high;
+        #[cfg(feature = "inject")]
+        crate::inject::gpio_write().context(Cause::Gpio)?;
+        let asked = Instant::now();
+        let wall = SystemTime::now();
+        self.backend.set(value)?;
+        stats::wrote(value, asked);
+        if let (Some(offset), 0) = (self.align, self.next) {
+            let wall = wall.duration_since(UNIX_EPOCH).unwrap_or_default();
+            self.phase = phase_error(wall, offset, self.period());
+            stats::phase_error(self.phase);
+        }
+        // On the grid of the pets before, however late this one ran
+        let from = match self.fresh {
+            true => Instant::now(),
+            false => self.due,
+        };
+        let mut duration = step.duration;
+        // By a tenth of the step at most, so that the supervisor barely notices
+        if self.align.is_some() && self.next + 1 == self.pattern.len() {
+            let most = (duration / 10).as_nanos() as i64;
+            let correction = self.phase.clamp(-most, most);
+            let by = Duration::from_nanos(correction.unsigned_abs());
+            duration = match correction >= 0 {
+                true => duration - by,
+                false => duration + by,
+            };
+        }
+        self.timer.set_at(from + duration)?;
+        self.pets += (self.next == 0) as u64;
+        self.next = (self.next + 1) % self.pattern.len();
+        self.due = from + duration;
+        self.fresh = false;
+        history::record(Event::Pet { value });
+        #[cfg(debug_assertions)]
+        debug!(
+            "PETTED at {} ms with value {}",
+            crate::timestamp_millis(),
+            value
+        );
+        Ok(())
+    }
+
+    /// Returns how late the timer fired. Retrying after a failed pet only pets again, the
+    /// expiration was already read.
+    pub fn on_pet(&mut self) -> Result<Duration> {
+        #[cfg(feature = "inject")]
+        if self.owed.is_none() {
+            thread::sleep(crate::inject::pet_delay());
+        }
+        let late = match self.owed {
+            Some(late) => late,
+            None => self.expired()?,
+        };
+        self.owed = Some(late);
+        self.step()?;
+        self.owed = None;
+        Ok(late)
+    }
+
+    fn expired(&mut self) -> Result<Duration> {
+        let expirations = self.timer.expirations()?;
+        let late = self.due.elapsed();
+        stats::pet(late);
+        // One-shot timers expire once, but the loop can stall through whole cycles
+        if expirations != 1 || late >= self.period() {
+            warn!(
+                "Pet timer overran by {:?} ({} expirations), starting a fresh cycle",
+                late, expirations
+            );
+            stats::count(Counter::Overruns);
+            // Rather than toggling into a phase that's long gone
+            self.next = 0;
+            self.fresh = true;
+        }
+        Ok(late)
+    }
+
+    /// Drives the line through one whole cycle of the pattern, checking that each level reads
+    /// back. Returns whether it could be read back at all. The next pet starts a fresh cycle.
+    pub fn self_test(&mut self) -> Result<bool> {
+        let mut read_back = true;
+        for &PetStep {
+            high: value,
+            duration,
+        } in &self.pattern
+        {
+            self.backend.set(value)?;
+            match self.backend.get()? {
+                Some(level) => ensure!(
+                    level == value,
+                    "GPIO line reads {} after being set {}",
+                    level as u8,
+                    value as u8
+                ),
+                None => read_back = false,
+            }
+            thread::sleep(duration);
+        }
+        self.next = 0;
+        Ok(read_back)
+    }
+
+    /// Stops petting, holding the line low. Petting again starts by raising it.
+    pub fn stop(&mut self) -> Result<()> {
+        self.timer.unset()?;
+        self.backend.stop()?;
+        self.next = 0;
+        self.owed = None;
+        Ok(())
+    }
+
+    /// Stops petting, holding the line at `level`
+    pub fn hold(&mut self, level: bool) -> Result<()> {
+        self.timer.unset()?;
+        self.backend.set(level)?;
+        self.next = 0;
+        self.owed = None;
+        Ok(())
+    }
+
+    /// Stops petting, leaving the line as it is for whoever pets next
+    pub fn pause(&mut self) -> Result<()> {
+        self.timer.unset()?;
+        self.owed = None;
+        Ok(())
+    }
+
+    /// Gives the line up to a successor, it's left as it is on drop
+    pub fn release(&mut self) {
+        self.released = true;
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Petter {
+    fn drop(&mut self) {
+        if !self.released {
+            let _ = self.backend.stop();
+        }
+    }
+}
+
+/// How far `wall`, in Unix time, is past the nearest boundary `offset` past a multiple of
+/// `period`, in nanoseconds, negative if it's before it
+#[cfg(target_os = "linux")]
+fn phase_error(wall: Duration, offset: Duration, period: Duration) -> i64 {
+    let period = period.as_nanos() as i64;
+    let phase = (wall.as_nanos() as i64 - offset.as_nanos() as i64).rem_euclid(period);
+    match phase * 2 <= period {
+        true => phase,
+        false => phase - period,
+    }
+}
+
+/// A gpio-sim chip with the watchdog line, for running without the C3. It goes away on drop.
+#[cfg(all(target_os = "linux", feature = "sim"))]
+pub fn simulate_gpio(line_offset: u32, line_label: &str) -> Sim {
+    debug!("Simulating the GPIO.");
+
+    gpiosim::builder()
+        .with_name("watchdog_sim")
+        .with_bank(Bank::new(32, "sim_bank").name(line_offset, line_label))
+        .live()
+        .expect("Failed to simulate GPIO")
+}
+
+/// The watchdog line on a simulated chip, which lives as long as the backend
+#[cfg(all(target_os = "linux", feature = "sim"))]
+pub struct Simulated {
+    gpio: Gpio,
+    sim: Sim,
+}
+
+#[cfg(all(target_os = "linux", feature = "sim"))]
+impl Simulated {
+    pub fn new() -> Result<Self> {
+        let sim = simulate_gpio(GPIO_LINE, GPIO_LABEL);
+        let gpio = Gpio::request(&sim.chips()[0].chip_name, GPIO_LABEL, GPIO_LINE)?;
+        Ok(Self { gpio, sim })
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "sim"))]
+impl Backend for Simulated {
+    fn set(&mut self, value: bool) -> Result<()> {
+        self.gpio.set(value)
+    }
+
+    /// The simulated chip's level rather than the request's, like a probe on the wire
+    fn get(&self) -> Result<Option<bool>> {
+        let level = self.sim.chips()[0]
+            .get_level(GPIO_LINE)
+            .map_err(|e| anyhow::anyhow!("Failed to read simulated level: {}", e))?;
+        Ok(Some(level == gpiosim::Level::High))
+    }
+
+    fn fd(&self) -> Result<RawFd> {
+        self.gpio.fd()
+    }
+
+    fn stopper(&self) -> Option<Stopper> {
+        self.gpio.stopper()
+    }
+}
+
+/// The pattern generated by a PWM channel, configured once, so that neither jitter nor a short
+/// stall of the daemon shows on the line. Pets only make sure it's enabled, and it's disabled
+/// to stop feeding the supervisor. A daemon killed outright leaves it running, so the clients'
+/// pings are all that can still stop it then.
+#[cfg(target_os = "linux")]
+pub struct Pwm {
+    dir: PathBuf,
+    enabled: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl Pwm {
+    /// Exports the channel if need be and sets it up for `pattern`, one high and one low step,
+    /// leaving it disabled
+    pub fn open(config: &PwmConfig, pattern: &[PetStep]) -> Result<Self> {
+        let dir = config.chip.join(format!("pwm{}", config.channel));
+        export(&config.chip, config.channel, &dir.join("enable"))?;
+        let pwm = Self {
+            dir,
+            enabled: false,
+        };
+        let period: Duration = pattern.iter().map(|step| step.duration).sum();
+        let duty = pattern
+            .iter()
+            .filter(|step| step.high)
+            .map(|step| step.duration);
+        let duty: Duration = duty.sum();
+        pwm.write("enable", 0)?;
+        // The duty cycle can never be longer than the period, whichever is set first
+        pwm.write("duty_cycle", 0)?;
+        pwm.write("period", period.as_nanos())?;
+        pwm.write("duty_cycle", duty.as_nanos())?;
+        Ok(pwm)
+    }
+
+    fn write(&self, attribute: &str, value: impl ToString) -> Result<()> {
+        let path = self.dir.join(attribute);
+        fs::write(&path, value.to_string())
+            .with_context(|| format!("Failed to write {:?}", path))
+            .context(Cause::Gpio)
+    }
+
+    fn enable(&mut self, enabled: bool) -> Result<()> {
+        if enabled != self.enabled {
+            self.write("enable", enabled as u8)?;
+            self.enabled = enabled;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Backend for Pwm {
+    /// Going low is part of the waveform, only the first pet after stopping does anything
+    fn set(&mut self, value: bool) -> Result<()> {
+        match value {
+            true => self.enable(true),
+            false => Ok(()),
+        }
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.enable(false)
+    }
+
+    fn stopper(&self) -> Option<Stopper> {
+        let enable = OpenOptions::new().write(true).open(self.dir.join("enable"));
+        enable.ok().map(Stopper::Sysfs)
+    }
+}
+
+/// Exports `id` through the sysfs `class` directory, unless `attribute` of it is already there
+#[cfg(target_os = "linux")]
+fn export(class: &Path, id: u32, attribute: &Path) -> Result<()> {
+    if attribute.exists() {
+        return Ok(());
+    }
+    fs::write(class.join("export"), id.to_string())
+        .with_context(|| format!("Failed to export {} of {:?}", id, class))?;
+    // Appears asynchronously, with udev fixing up its permissions
+    let deadline = Instant::now() + EXPORT_TIMEOUT;
+    while !attribute.exists() {
+        ensure!(Instant::now() < deadline, "{:?} didn't appear", attribute);
+        thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+/// The watchdog line through the deprecated sysfs GPIO interface, for kernels without the
+/// character device. Like the PWM channel it can't be handed over.
+#[cfg(target_os = "linux")]
+pub struct SysfsGpio {
+    value: File,
+}
+
+#[cfg(target_os = "linux")]
+impl SysfsGpio {
+    /// Exports GPIO `number`, the global one rather than an offset on a chip, as an output that
+    /// starts low
+    pub fn open(number: u32) -> Result<Self> {
+        Self::open_in(Path::new(SYSFS_GPIO), number)
+    }
+
+    fn open_in(class: &Path, number: u32) -> Result<Self> {
+        let dir = class.join(format!("gpio{}", number));
+        let value = dir.join("value");
+        export(class, number, &value)?;
+        let direction = dir.join("direction");
+        fs::write(&direction, "low")
+            .with_context(|| format!("Failed to write {:?}", direction))
+            .context(Cause::Gpio)?;
+        let value = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&value)
+            .with_context(|| format!("Failed to open {:?}", value))
+            .context(Cause::Gpio)?;
+        Ok(Self { value })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Backend for SysfsGpio {
+    fn set(&mut self, value: bool) -> Result<()> {
+        let digit = if value { b"1" } else { b"0" };
+        stats::timed(Syscall::SetValues, || self.value.write_all_at(digit, 0)).context(Cause::Gpio)
+    }
+
+    fn get(&self) -> Result<Option<bool>> {
+        let mut digit = [0];
+        self.value
+            .read_exact_at(&mut digit, 0)
+            .context(Cause::Gpio)?;
+        Ok(Some(digit[0] == b'1'))
+    }
+
+    fn stopper(&self) -> Option<Stopper> {
+        self.value.try_clone().ok().map(Stopper::Sysfs)
+    }
+}
+
+/// Only logs what the line would be set to, for running the whole daemon on a workstation or a
+/// C3 that someone else pets. Reads back what was last set, so that the self-test passes.
+#[cfg(all(target_os = "linux", not(feature = "flight")))]
+#[derive(Default)]
+pub struct DryRun(Option<bool>);
+
+#[cfg(all(target_os = "linux", not(feature = "flight")))]
+impl Backend for DryRun {
+    fn set(&mut self, value: bool) -> Result<()> {
+        debug!("Dry run, not setting the watchdog line {}", value);
+        self.0 = Some(value);
+        Ok(())
+    }
+
+    fn get(&self) -> Result<Option<bool>> {
+        Ok(self.0)
+    }
+}
+
+/// Records what the line would be set to, for developing and testing off the C3. Clones share
+/// the record.
+#[cfg(any(test, not(target_os = "linux")))]
+#[derive(Clone, Default)]
+pub struct Mock(Arc<Mutex<Vec<bool>>>);
+
+#[cfg(any(test, not(target_os = "linux")))]
+impl Mock {
+    pub fn values(&self) -> Vec<bool> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+#[cfg(any(test, not(target_os = "linux")))]
+impl Backend for Mock {
+    fn set(&mut self, value: bool) -> Result<()> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).push(value);
+        Ok(())
+    }
+
+    fn get(&self) -> Result<Option<bool>> {
+        Ok(self.values().last().copied())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backend() -> Result<()> {
+        let mock = Mock::default();
+        let mut petter = Petter::with_backend(Box::new(mock.clone()))?;
+        petter.pet()?;
+        petter.pet()?;
+        petter.pet()?;
+        assert_eq!(petter.pets(), 2);
+        assert!(petter.fd().is_err());
+        drop(petter);
+        assert_eq!(mock.values(), [true, false, true, false]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_phase_error() {
+        let ms = Duration::from_millis;
+        let second = Duration::from_secs(1);
+        let at = |secs: u64, millis| Duration::from_secs(secs) + ms(millis);
+        assert_eq!(phase_error(at(1_760_000_000, 3), ms(0), second), 3_000_000);
+        assert_eq!(
+            phase_error(at(1_760_000_000, 998), ms(0), second),
+            -2_000_000
+        );
+        assert_eq!(
+            phase_error(at(1_760_000_000, 240), ms(250), second),
+            -10_000_000
+        );
+        // Halfway counts as late
+        assert_eq!(
+            phase_error(at(1_760_000_001, 0), ms(0), ms(2000)),
+            1_000_000_000
+        );
+        assert_eq!(
+            phase_error(at(1_760_000_000, 120), ms(0), ms(500)),
+            120_000_000
+        );
+    }
+
+    #[test]
+    fn test_overrun() -> Result<()> {
+        let mock = Mock::default();
+        let mut petter = Petter::with_backend(Box::new(mock.clone()))?;
+        petter.pet()?;
+        // Not due yet
+        assert!(petter.on_pet().is_err());
+
+        // Stalled through the high phase and then some, the cycle starts over rather than
+        // going low
+        petter.due = Instant::now() - 2 * petter.period();
+        for _ in 0..2 {
+            petter.timer.set(Duration::from_nanos(1))?;
+            std::thread::sleep(Duration::from_millis(1));
+            petter.on_pet()?;
+        }
+        assert_eq!(mock.values(), [true, true, false]);
+        let mut status = crate::status::StatusReport::new();
+        stats::report(&mut status);
+        assert_eq!(status.get("pet.overruns"), Some("1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_schedule() -> Result<()> {
+        let mut petter = Petter::with_backend(Box::new(Mock::default()))?;
+        petter.pet()?;
+        let due = petter.due;
+        // Handled late, the next pet is still a step after the one before rather than after now
+        petter.timer.set(Duration::from_nanos(1))?;
+        std::thread::sleep(Duration::from_millis(50));
+        petter.on_pet()?;
+        let step = petter.pattern[1].duration;
+        assert_eq!(petter.due, due + step);
+        assert!(petter.next_pet()? > step);
+        Ok(())
+    }
+
+    #[test]
+    fn test_busy() {
+        let busy = |consumer: &str| Busy {
+            label: GPIO_LABEL.to_string(),
+            consumer: consumer.to_string(),
+        };
+        let error = anyhow::Error::from(busy("gpioset")).context(Cause::Gpio);
+        assert_eq!(
+            error
+                .downcast_ref::<Busy>()
+                .map(ToString::to_string)
+                .as_deref(),
+            Some("GPIO line PET_WDT is already held by \"gpioset\"")
+        );
+        assert!(!busy("gpioset").ours());
+        assert!(busy(GPIO_CONSUMER).ours());
+        assert_eq!(busy("").to_string(), "GPIO line PET_WDT is already in use");
+    }
+
+    #[test]
+    fn test_pattern() -> Result<()> {
+        let config = crate::Config::parse(
+            r#"
+            [pet]
+            pattern = [
+                { high = true, duration = 0.05 },
+                { high = false, duration = 0.05 },
+                { high = true, duration = 0.05 },
+                { high = false, duration = 0.7 },
+            ]
+            "#,
+        )?;
+        let mock = Mock::default();
+        let mut petter = Petter::with_backend(Box::new(mock.clone()))?;
+        petter.set_pattern(&config.pet.pattern);
+        assert_eq!(petter.period(), Duration::from_millis(850));
+        for _ in 0..5 {
+            petter.pet()?;
+        }
+        assert_eq!(mock.values(), [true, false, true, false, true]);
+        assert_eq!(petter.pets(), 2);
+        assert!(petter.next_pet()? <= Duration::from_millis(50));
+        Ok(())
+    }
+
+    /// Fails the next `fails` sets
+    struct Flaky(Mock, u32);
+
+    impl Backend for Flaky {
+        fn set(&mut self, value: bool) -> Result<()> {
+            if self.1 > 0 {
+                self.1 -= 1;
+                bail!("Line busy");
+            }
+            self.0.set(value)
+        }
+    }
+
+    #[test]
+    fn test_retried_pet() -> Result<()> {
+        let mock = Mock::default();
+        let mut petter = Petter::with_backend(Box::new(Flaky(mock.clone(), 1)))?;
+        assert!(petter.pet().is_err());
+        petter.pet()?;
+
+        petter.timer.set(Duration::from_nanos(1))?;
+        std::thread::sleep(Duration::from_millis(1));
+        petter.backend = Box::new(Flaky(mock.clone(), 1));
+        assert!(petter.on_pet().is_err());
+        // The expiration was read already, the retry only pets
+        petter.on_pet()?;
+        assert_eq!(mock.values(), [true, false]);
+        assert_eq!(petter.pets(), 1);
+        Ok(())
+    }
+
+    #[cfg(feature = "sim")]
+    #[test]
+    fn test_pwm() -> Result<()> {
+        let chip = std::env::temp_dir().join(format!("watchdog-pwm-{}", std::process::id()));
+        let dir = chip.join("pwm1");
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("enable"), "0")?;
+        let config = PwmConfig {
+            chip: chip.clone(),
+            channel: 1,
+        };
+        let read = |attribute: &str| fs::read_to_string(dir.join(attribute));
+        let mut petter =
+            Petter::with_backend(Box::new(Pwm::open(&config, &PetConfig::default().pattern)?))?;
+        assert_eq!(read("period")?, "1000000000");
+        assert_eq!(read("duty_cycle")?, "100000000");
+        assert_eq!(read("enable")?, "0");
+        petter.pet()?;
+        assert_eq!(read("enable")?, "1");
+        petter.pet()?;
+        assert_eq!(read("enable")?, "1");
+        petter.stop()?;
+        assert_eq!(read("enable")?, "0");
+        petter.pet()?;
+        drop(petter);
+        assert_eq!(read("enable")?, "0");
+
+        // Not exported, and nothing exports it
+        let config = PwmConfig { chip, channel: 2 };
+        assert!(Pwm::open(&config, &PetConfig::default().pattern).is_err());
+        fs::remove_dir_all(&config.chip)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_sysfs_gpio() -> Result<()> {
+        let class = std::env::temp_dir().join(format!("watchdog-gpio-{}", std::process::id()));
+        let dir = class.join("gpio505");
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("value"), "0\n")?;
+        let mut petter = Petter::with_backend(Box::new(SysfsGpio::open_in(&class, 505)?))?;
+        assert_eq!(fs::read_to_string(dir.join("direction"))?, "low");
+        petter.pet()?;
+        assert_eq!(petter.backend.get()?, Some(true));
+        petter.pet()?;
+        assert_eq!(petter.backend.get()?, Some(false));
+        assert!(petter.fd().is_err());
+        fs::remove_dir_all(&class)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "sim")]
+    #[test]
+    fn test_simulated_pet() -> Result<()> {
+        // Test whether the simulated watchdog GPIO output changes on each pet
+
+        use gpiosim::Level;
+
+        let sim = simulate_gpio(GPIO_LINE, GPIO_LABEL);
+        let chip = &sim.chips()[0];
+        let gpio_chip = chip.chip_name.clone();
+
+        let mut petter = Petter::new(&gpio_chip, GPIO_LABEL, GPIO_LINE)?;
+
+        let line_level = chip.get_level(GPIO_LINE).unwrap();
+        assert_eq!(line_level, Level::Low);
+
+        petter.pet()?;
+        let line_level = chip.get_level(GPIO_LINE).unwrap();
+        assert_eq!(line_level, Level::High);
+
+        petter.pet()?;
+        let line_level = chip.get_level(GPIO_LINE).unwrap();
+        assert_eq!(line_level, Level::Low);
+
+        Ok(())
+    }
+}
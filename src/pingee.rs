@@ -0,0 +1,3059 @@
+use crate::{
+    audit::AuditLog,
+    auth::Authenticator,
+    budget::Budget,
+    capture::{Capture, Verdict},
+    clock::{self, Clock, Timer},
+    config::{
+        Config, Criticality, ExtensionConfig, InhibitConfig, LegacyConfig, MulticastConfig,
+        RegistrationConfig, StrikeConfig, MAX_CLIENTS,
+    },
+    dictionary::Counter,
+    exit::Cause,
+    framing::{self, Frame, Kind, Rejected},
+    health::{Grade, Health},
+    history::{self, Event},
+    logbook,
+    net::{self, Subnet},
+    policy::Policy,
+    process::ProcessMonitor,
+    protocol::{self, Command, Fault},
+    rule::{self, Rule, Value},
+    state::{State, StateMachine},
+    stats::{self, Syscall},
+    status::StatusReport,
+    traffic::Traffic,
+};
+use anyhow::{bail, ensure, Context, Result};
+use log::{debug, info, warn};
+use mio::{event::Source, net::UdpSocket, Interest, Registry, Token};
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    fs,
+    io::{self, ErrorKind},
+    iter,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    os::fd::{AsRawFd, OwnedFd, RawFd},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+pub const INHIBIT: Duration = Duration::from_secs(120);
+pub const PING_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Big enough for any command, longer packets are truncated
+const MAX_PACKET: usize = 512;
+
+/// Named inhibits active at once. Past that new names are denied.
+const MAX_INHIBITS: usize = 16;
+
+/// Replies to commands with an id kept for resends, the oldest make way past that
+const MAX_REPLIED: usize = 32;
+/// How long a resend gets the first reply rather than being run again
+const REPLIED_FOR: Duration = Duration::from_secs(30);
+
+/// A client pinging faster than this share of its timeout, by its own word, only re-arms its
+/// timer once the deadline fell that far behind
+const COALESCE: u32 = 20;
+
+/// A source of pings that has to be heard from within its timeout
+struct Client {
+    /// None is the anonymous client, used when no clients are configured, that is fed by any packet
+    name: Option<String>,
+    timeout: Duration,
+    timer: Box<dyn Timer>,
+    clock: Arc<dyn Clock>,
+    /// Added with REGISTER rather than from the config
+    registered: bool,
+    /// Deadline granted by EXTEND, until which pings don't move the timer
+    extended_until: Option<Instant>,
+    /// Windows missed since the last ping
+    missed_in_a_row: u32,
+    /// When windows were missed, within the strike window
+    misses: VecDeque<Instant>,
+    /// Out of strikes, which the policy could do without, until it pings again
+    struck_out: bool,
+    /// Unix seconds of the last ping, for the status
+    last_ping: Option<i64>,
+    /// Acknowledges every ping
+    ack: bool,
+    /// How often it said it pings
+    every: Option<Duration>,
+    criticality: Criticality,
+    /// When it was added, so that the windows before don't count against its health
+    added: Instant,
+}
+
+impl Client {
+    fn new(
+        name: Option<String>,
+        timeout: Duration,
+        first: Duration,
+        clock: &Arc<dyn Clock>,
+    ) -> Result<Self> {
+        let timer = clock.timer()?;
+        timer.set(first)?;
+        Ok(Self {
+            name,
+            timeout,
+            timer,
+            clock: clock.clone(),
+            registered: false,
+            extended_until: None,
+            missed_in_a_row: 0,
+            misses: VecDeque::new(),
+            struck_out: false,
+            last_ping: None,
+            ack: false,
+            every: None,
+            criticality: Criticality::Critical,
+            added: clock.now(),
+        })
+    }
+
+    fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or("anonymous")
+    }
+
+    /// Pushes the timeout back, returning what was left of it
+    fn feed(&mut self) -> Result<Duration> {
+        let margin = self.remaining()?;
+        self.missed_in_a_row = 0;
+        self.struck_out = false;
+        self.last_ping = Some(clock::wall_now());
+        match self.extended_until {
+            Some(until) if self.clock.now() < until => return Ok(margin),
+            Some(_) => self.extended_until = None,
+            None => (),
+        }
+        // Close enough to the full timeout already, a fast client's timer isn't re-armed, and
+        // nothing is recorded, on every ping
+        if self.timeout.saturating_sub(margin) < self.slack() {
+            return Ok(margin);
+        }
+        // An expired timer, on death row, starts over too
+        if self.timer.hold(self.timeout)? {
+            history::record(Event::TimerReset {
+                client: self.label().to_string(),
+                timeout: self.timeout,
+            });
+        }
+        Ok(margin)
+    }
+
+    /// How far behind the deadline may fall, which is how much shorter the timeout can get
+    fn slack(&self) -> Duration {
+        let slack = self.timeout / COALESCE;
+        match self.every {
+            Some(every) if every < slack => slack,
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Counts a missed window, returning the strikes against the client. Zero `window` counts
+    /// them in a row.
+    fn strike(&mut self, window: Duration) -> u32 {
+        let now = self.clock.now();
+        self.missed_in_a_row += 1;
+        if window.is_zero() {
+            return self.missed_in_a_row;
+        }
+        self.misses.push_back(now);
+        while self.misses.front().is_some_and(|&at| now - at >= window) {
+            self.misses.pop_front();
+        }
+        self.misses.len() as u32
+    }
+
+    /// Zero once the timeout expired
+    fn remaining(&self) -> Result<Duration> {
+        self.timer.remaining()
+    }
+
+    /// Moves the deadline out to `duration` from now, unless it already is further out. Returns
+    /// whether it moved.
+    fn hold(&self, duration: Duration) -> Result<bool> {
+        let moved = self.timer.hold(duration)?;
+        if moved {
+            history::record(Event::TimerReset {
+                client: self.label().to_string(),
+                timeout: duration,
+            });
+        }
+        Ok(moved)
+    }
+
+    /// Like hold, but pings also can't shorten it
+    fn extend(&mut self, duration: Duration) -> Result<()> {
+        if self.hold(duration)? {
+            self.extended_until = Some(self.clock.now() + duration);
+        }
+        Ok(())
+    }
+}
+
+/// A commanded inhibit, which stacks with the others of other names
+struct Inhibit {
+    /// "anonymous" for an INHIBIT without one
+    name: String,
+    /// Where it came from, for the status
+    requester: String,
+    until: Instant,
+}
+
+/// The reply to a command with an id, for a resend of the same packet from the same source
+struct Replied {
+    at: Instant,
+    source: SocketAddr,
+    packet: Vec<u8>,
+    reply: String,
+}
+
+/// Where the pings come in and the replies go out. That's a UDP socket, unless whoever embeds the
+/// watchdog brings something else that mio can poll.
+pub trait Transport: Source + AsRawFd + Send {
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+    fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize>;
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Packets the kernel dropped for lack of buffer space, if it says
+    fn drops(&self) -> Option<u32> {
+        None
+    }
+
+    /// Whether replies to `target` go out this way rather than the main socket, when it's the
+    /// second transport
+    fn reaches(&self, _target: SocketAddr) -> bool {
+        false
+    }
+}
+
+impl Transport for UdpSocket {
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+
+    fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, target)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    fn drops(&self) -> Option<u32> {
+        net::drops(self)
+    }
+}
+
+/// An extra listening port where any packet feeds its channel, for subsystems that can only be
+/// configured with a port number
+struct Port {
+    socket: UdpSocket,
+    /// Index of the channel's client
+    client: usize,
+}
+
+/// Requests for the main loop, for things outside of pinging
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Stop petting, a commanded reset
+    Reset,
+    /// Stop draining and exit
+    Takeover,
+    /// Execute the installed binary in place
+    Reexec,
+    /// Enter or leave the low power mode
+    Power(bool),
+    /// Switch to the profile with this index
+    Profile(usize),
+    /// Into maintenance for this long, zero ends it
+    Maintenance(Duration),
+}
+
+fn bind(address: SocketAddr, multicast_groups: &[MulticastConfig]) -> Result<UdpSocket> {
+    let socket = UdpSocket::bind(address).with_context(|| format!("Failed to bind {}", address))?;
+    join(&socket, multicast_groups)?;
+    Ok(socket)
+}
+
+/// Zero leaves the kernel's default
+fn size(socket: &UdpSocket, recv_buffer: usize) -> Result<()> {
+    if recv_buffer == 0 {
+        return Ok(());
+    }
+    let got = net::set_recv_buffer(socket, recv_buffer)?;
+    if got < recv_buffer {
+        warn!(
+            "Got a {} byte receive buffer rather than {}, net.core.rmem_max caps it",
+            got, recv_buffer
+        );
+    }
+    Ok(())
+}
+
+fn join(socket: &UdpSocket, multicast_groups: &[MulticastConfig]) -> Result<()> {
+    for multicast in multicast_groups {
+        net::join_multicast(socket, multicast.group, multicast.interface.as_deref())
+            .with_context(|| format!("Failed to join multicast group {}", multicast.group))?;
+    }
+    Ok(())
+}
+
+/// How the main socket was bound, so that it can be bound again once it breaks, e.g. when the
+/// network namespace is reconfigured on the flatsat
+struct Rebind {
+    address: SocketAddr,
+    multicast_groups: Vec<MulticastConfig>,
+    recv_buffer: usize,
+    /// Binding failed, tried again on every pet until it works
+    broken: bool,
+}
+
+pub struct Pingee {
+    socket: Box<dyn Transport>,
+    /// Token the socket was registered with
+    token: Option<Token>,
+    /// None for a transport that was handed in
+    rebind: Option<Rebind>,
+    /// Read along with the main socket under its token, neither rebound nor handed over
+    second: Option<Box<dyn Transport>>,
+    /// Packets read per wakeup, from the main socket and the second transport together
+    drain_budget: usize,
+    /// The main socket's kernel drop count when last looked at
+    drops: u32,
+    /// Each slot has its own timer token, freed slots are reused by later registrations
+    clients: Vec<Option<Client>>,
+    ports: Vec<Port>,
+    allowed_sources: Vec<Subnet>,
+    timers: Token,
+    first_port: Token,
+    registration: RegistrationConfig,
+    legacy: LegacyConfig,
+    extension: ExtensionConfig,
+    inhibit: InhibitConfig,
+    inhibit_rule: Option<Rule>,
+    strikes: StrikeConfig,
+    policy: Option<Policy>,
+    /// End of the startup inhibit
+    startup_until: Instant,
+    /// The commanded inhibits, run out or not, the latest end being the effective one
+    inhibits: Vec<Inhibit>,
+    /// Commanded inhibits granted so far
+    granted: u32,
+    budget: Budget,
+    auth: Authenticator,
+    audit: AuditLog,
+    capture: Capture,
+    traffic: Traffic,
+    /// Whether missing pings are fatal
+    armed: bool,
+    /// The update flag file while it keeps this disarmed, and until when
+    interlock: Option<(PathBuf, Instant)>,
+    /// The startup gates still waited for while they keep this disarmed, comma separated
+    gated: Option<String>,
+    state: StateMachine,
+    clock: Arc<dyn Clock>,
+    missed_while_disarmed: u64,
+    /// Exiting after a SIGTERM, only TAKEOVER is accepted then
+    draining: bool,
+    /// When and from where the last ping packet was received
+    last_ping: Option<(Instant, SocketAddr)>,
+    /// For the daemon's uptime
+    started: Instant,
+    /// Extra status keys that don't change after startup
+    annotations: StatusReport,
+    /// Whether the low power mode is on, None without one
+    low_power: Option<bool>,
+    /// The longest MAINTENANCE, None without a maintenance mode
+    maintenance: Option<Duration>,
+    /// When the running maintenance is over
+    maintenance_until: Option<Instant>,
+    /// The profile names, with the active one's index
+    profiles: (Vec<String>, usize),
+    health: Health,
+    /// Oldest first
+    replied: VecDeque<Replied>,
+}
+
+impl Pingee {
+    /// Client timeout timers are registered with tokens counting up from `timers`, and the extra
+    /// port sockets from `first_port`.
+    pub fn new(config: &Config, timers: Token, first_port: Token) -> Result<Self> {
+        Self::inherit(config, timers, first_port, Vec::new())
+    }
+
+    /// Like new, but with the main and port sockets handed over by a previous instance, in that
+    /// order, rather than bound
+    pub fn inherit(
+        config: &Config,
+        timers: Token,
+        first_port: Token,
+        sockets: Vec<OwnedFd>,
+    ) -> Result<Self> {
+        ensure!(
+            sockets.is_empty() || sockets.len() == 1 + config.ports.len(),
+            "Handed over sockets don't match the config"
+        );
+        let mut inherited = sockets.into_iter().map(|fd| -> Result<UdpSocket> {
+            let socket = std::net::UdpSocket::from(fd);
+            socket.set_nonblocking(true)?;
+            Ok(UdpSocket::from_std(socket))
+        });
+        // Inherited sockets are already members of the multicast groups
+        let socket = match inherited.next() {
+            Some(socket) => socket?,
+            None => bind(config.address, &config.multicast_groups)?,
+        };
+        size(&socket, config.recv_buffer)?;
+        let rebind = Rebind {
+            address: socket.local_addr()?,
+            multicast_groups: config.multicast_groups.clone(),
+            recv_buffer: config.recv_buffer,
+            broken: false,
+        };
+        let clock = clock::configured(config.clock)?;
+        let mut pingee = Self::build(
+            config,
+            timers,
+            first_port,
+            Box::new(socket),
+            inherited,
+            clock,
+        )?;
+        pingee.rebind = Some(rebind);
+        Ok(pingee)
+    }
+
+    /// Like inherit, with the sockets systemd passed when socket activated. Unlike handed over
+    /// ones they have yet to join the multicast groups, and a unit that drifted from the config
+    /// is refused.
+    pub fn activated(
+        config: &Config,
+        timers: Token,
+        first_port: Token,
+        sockets: Vec<OwnedFd>,
+    ) -> Result<Self> {
+        let expected = iter::once(config.address.port()).chain(config.ports.iter().map(|p| p.port));
+        ensure!(
+            sockets.len() == 1 + config.ports.len(),
+            "Socket activated with {} sockets, the config has {}",
+            sockets.len(),
+            1 + config.ports.len()
+        );
+        for (socket, port) in sockets.iter().zip(expected) {
+            let socket = std::net::UdpSocket::from(socket.try_clone()?);
+            let address = socket.local_addr()?;
+            ensure!(
+                address.ip() == config.address.ip() && address.port() == port,
+                "Socket activated on {}, the config has port {} on {}",
+                address,
+                port,
+                config.address.ip()
+            );
+        }
+        if let Some(socket) = sockets.first() {
+            let socket = std::net::UdpSocket::from(socket.try_clone()?);
+            join(&UdpSocket::from_std(socket), &config.multicast_groups)?;
+        }
+        Self::inherit(config, timers, first_port, sockets)
+    }
+
+    /// Like new, but pinged through `transport` instead of a socket bound to the configured
+    /// address. The extra ports are still bound.
+    pub fn with_transport(
+        config: &Config,
+        timers: Token,
+        first_port: Token,
+        transport: Box<dyn Transport>,
+    ) -> Result<Self> {
+        let clock = clock::configured(config.clock)?;
+        Self::with_clock(config, timers, first_port, transport, clock)
+    }
+
+    /// Like with_transport, with the client deadlines on `clock`. Timers without an fd, like the
+    /// virtual ones, are never polled, so their timeouts only show in `starving`.
+    pub fn with_clock(
+        config: &Config,
+        timers: Token,
+        first_port: Token,
+        transport: Box<dyn Transport>,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self> {
+        Self::build(config, timers, first_port, transport, iter::empty(), clock)
+    }
+
+    fn build(
+        config: &Config,
+        timers: Token,
+        first_port: Token,
+        socket: Box<dyn Transport>,
+        mut inherited: impl Iterator<Item = Result<UdpSocket>>,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self> {
+        let startup = config.inhibit.startup;
+        let mut clients = if config.clients.is_empty() {
+            vec![Some(Client::new(None, PING_TIMEOUT, startup, &clock)?)]
+        } else {
+            config
+                .clients
+                .iter()
+                .map(|c| {
+                    let mut client = Client::new(Some(c.name.clone()), c.timeout, startup, &clock)?;
+                    client.ack = c.ack;
+                    client.criticality = c.criticality;
+                    Ok(Some(client))
+                })
+                .collect::<Result<_>>()?
+        };
+        let mut ports = Vec::new();
+        for port in &config.ports {
+            // Keeps any IPv6 scope of the main address
+            let mut address = config.address;
+            address.set_port(port.port);
+            let socket = match inherited.next() {
+                Some(socket) => socket?,
+                None => UdpSocket::bind(address)
+                    .with_context(|| format!("Failed to bind port {}", port.port))?,
+            };
+            size(&socket, config.recv_buffer)?;
+            clients.push(Some(Client::new(
+                Some(port.name.clone()),
+                port.timeout,
+                startup,
+                &clock,
+            )?));
+            ports.push(Port {
+                socket,
+                client: clients.len() - 1,
+            });
+        }
+        let interlock = config
+            .interlock
+            .as_ref()
+            .filter(|i| config.armed && i.path.exists())
+            .map(|i| (i.path.clone(), clock.now() + i.window));
+        let gated = config.gates.iter().map(|g| g.name()).collect::<Vec<_>>();
+        let gated = (config.armed && !gated.is_empty()).then(|| gated.join(","));
+        let armed = config.armed && interlock.is_none() && gated.is_none();
+        if let Some((path, _)) = &interlock {
+            warn!(
+                "DISARMED: {:?} is there, missing pings are not fatal until ARM",
+                path
+            );
+        } else if let Some(gates) = &gated {
+            warn!("DISARMED: waiting for the startup gates {}", gates);
+        } else if !armed {
+            warn!("DISARMED: missing pings are not fatal");
+        }
+        let state = match (armed, startup.is_zero()) {
+            (false, _) => State::Disarmed,
+            (true, false) => State::StartupInhibit,
+            (true, true) => State::Armed,
+        };
+        Ok(Self {
+            socket,
+            token: None,
+            rebind: None,
+            second: None,
+            clients,
+            ports,
+            allowed_sources: config.allowed_sources.clone(),
+            drain_budget: config.drain_budget,
+            drops: 0,
+            timers,
+            first_port,
+            registration: config.registration.clone(),
+            legacy: config.legacy.clone(),
+            extension: config.extension.clone(),
+            inhibit: config.inhibit.clone(),
+            inhibit_rule: config.rules.inhibit.clone(),
+            strikes: config.strikes.clone(),
+            policy: config.policy.clone(),
+            startup_until: clock.now() + startup,
+            inhibits: Vec::new(),
+            granted: 0,
+            budget: Budget::new(&config.budget, clock.clone()),
+            auth: Authenticator::new(&config.auth)?,
+            audit: AuditLog::open(config.audit_log.as_deref()),
+            capture: Capture::open(config.capture.as_ref()),
+            traffic: Traffic::new(clock.clone()),
+            armed,
+            interlock,
+            gated,
+            state: StateMachine::new(state, "Started"),
+            clock,
+            missed_while_disarmed: 0,
+            draining: false,
+            last_ping: None,
+            started: Instant::now(),
+            annotations: StatusReport::new(),
+            low_power: config.low_power.as_ref().map(|_| false),
+            maintenance: config.maintenance.as_ref().map(|m| m.max),
+            maintenance_until: None,
+            profiles: (config.profiles.iter().map(|p| p.name.clone()).collect(), 0),
+            health: Health::new(config.health_window),
+            replied: VecDeque::new(),
+        })
+    }
+
+    /// Moves the client deadlines onto `clock`, which has to count the same time as the one they
+    /// were built on, before `register`
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) -> Result<()> {
+        for client in self.clients.iter_mut().flatten() {
+            let timer = clock.timer()?;
+            // Zero would unset it, an expired one is due right away instead
+            timer.set(client.timer.remaining()?.max(Duration::from_nanos(1)))?;
+            client.timer = timer;
+            client.clock = clock.clone();
+        }
+        self.clock = clock;
+        Ok(())
+    }
+
+    /// Adds a transport pinged through next to the main socket, like vsock into the VM a test
+    /// rig runs the image in, before `register`
+    pub fn add_transport(&mut self, transport: Box<dyn Transport>) {
+        self.second = Some(transport);
+    }
+
+    pub fn register(&mut self, registry: &Registry, token: Token) -> Result<()> {
+        registry.register(&mut *self.socket, token, Interest::READABLE)?;
+        if let Some(second) = &mut self.second {
+            registry.register(&mut **second, token, Interest::READABLE)?;
+        }
+        self.token = Some(token);
+        for (index, client) in self.clients.iter().enumerate() {
+            if let Some(client) = client {
+                client
+                    .timer
+                    .register(registry, Token(self.timers.0 + index))?;
+            }
+        }
+        for (index, port) in self.ports.iter_mut().enumerate() {
+            registry.register(
+                &mut port.socket,
+                Token(self.first_port.0 + index),
+                Interest::READABLE,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn pinged(&mut self, client: String, source: SocketAddr) {
+        stats::ping(&client);
+        history::record(Event::Ping { client, source });
+        self.last_ping = Some((Instant::now(), source));
+    }
+
+    /// Time since the last ping packet, and its source
+    pub fn last_ping(&self) -> Option<(Duration, SocketAddr)> {
+        self.last_ping.map(|(at, source)| (at.elapsed(), source))
+    }
+
+    /// The main socket followed by the port sockets, for handing over
+    pub fn sockets(&self) -> Vec<RawFd> {
+        let ports = self.ports.iter().map(|p| p.socket.as_raw_fd());
+        std::iter::once(self.socket.as_raw_fd())
+            .chain(ports)
+            .collect()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Client> {
+        self.clients.iter().flatten()
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.clients
+            .iter()
+            .position(|c| matches!(c, Some(c) if c.name.as_deref() == Some(name)))
+    }
+
+    /// Whether the anonymous client, fed by any packet, is in use
+    fn anonymous(&self) -> bool {
+        self.iter().any(|c| c.name.is_none())
+    }
+
+    pub fn on_ping(
+        &mut self,
+        registry: &Registry,
+        processes: &mut ProcessMonitor,
+    ) -> Result<Option<Action>> {
+        self.update_state();
+        let mut buf = [0; MAX_PACKET];
+        let mut action = None;
+        let mut rebound = false;
+        let mut left = self.drain_budget;
+        // Read until there's no more packets, otherwise mio won't see the socket as readable again,
+        // or until the budget is spent
+        loop {
+            if self.rebind.as_ref().is_some_and(|r| r.broken) {
+                break;
+            }
+            if left == 0 {
+                self.cut_short(registry)?;
+                return Ok(action);
+            }
+            match stats::timed(Syscall::RecvFrom, || self.socket.recv_from(&mut buf)) {
+                Ok((len, source)) => {
+                    left -= 1;
+                    let acted = self.on_datagram(&buf[..len], source, registry, processes)?;
+                    action = action.or(acted);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                // Once, a fresh socket that fails right away won't be fixed by another
+                Err(e) if self.rebind.is_some() && !rebound => {
+                    warn!("Ping socket read failed, rebinding: {}", e);
+                    rebound = true;
+                    self.rebind(registry)?;
+                }
+                Err(e) => {
+                    return Err(e)
+                        .context("Ping socket read failed")
+                        .context(Cause::Socket)
+                }
+            }
+        }
+        while let Some(second) = &self.second {
+            if left == 0 {
+                self.cut_short(registry)?;
+                break;
+            }
+            match stats::timed(Syscall::RecvFrom, || second.recv_from(&mut buf)) {
+                Ok((len, source)) => {
+                    left -= 1;
+                    let acted = self.on_datagram(&buf[..len], source, registry, processes)?;
+                    action = action.or(acted);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    return Err(e)
+                        .context("Second transport read failed")
+                        .context(Cause::Socket)
+                }
+            }
+        }
+        Ok(action)
+    }
+
+    /// The budget ran out with packets possibly left. Registering the sockets again has epoll
+    /// look at them afresh, so that they're readable on the next poll rather than never again.
+    fn cut_short(&mut self, registry: &Registry) -> Result<()> {
+        stats::count(Counter::DrainCuts);
+        if let Some(token) = self.token {
+            registry.reregister(&mut *self.socket, token, Interest::READABLE)?;
+            if let Some(second) = &mut self.second {
+                registry.reregister(&mut **second, token, Interest::READABLE)?;
+            }
+        }
+        self.check_drops();
+        Ok(())
+    }
+
+    /// Warns once the kernel dropped packets since the last look
+    fn check_drops(&mut self) {
+        let Some(drops) = self.socket.drops() else {
+            return;
+        };
+        if drops > self.drops {
+            let dropped = drops - self.drops;
+            warn!(
+                "Kernel dropped {} packets, the receive buffer overflowed",
+                dropped
+            );
+        }
+        self.drops = drops;
+    }
+
+    fn on_datagram(
+        &mut self,
+        packet: &[u8],
+        source: SocketAddr,
+        registry: &Registry,
+        processes: &mut ProcessMonitor,
+    ) -> Result<Option<Action>> {
+        if !self.allowed(source) {
+            debug!("Dropped packet from {}", source);
+            self.capture.record(source, packet, Verdict::Blocked);
+            self.traffic.record(source.ip(), Verdict::Blocked);
+            return Ok(None);
+        }
+        let (verdict, action) = self.on_packet(packet, source, registry, processes)?;
+        self.capture.record(source, packet, verdict);
+        self.traffic.record(source.ip(), verdict);
+        Ok(action)
+    }
+
+    /// Tries binding the main socket again if that failed before. The client timers keep running
+    /// in the meantime, so the silence still times out.
+    pub fn recover(&mut self, registry: &Registry) -> Result<()> {
+        if self.rebind.as_ref().is_some_and(|r| r.broken) {
+            self.rebind(registry)?;
+        }
+        Ok(())
+    }
+
+    fn rebind(&mut self, registry: &Registry) -> Result<()> {
+        let Some(rebind) = &mut self.rebind else {
+            return Ok(());
+        };
+        if !rebind.broken {
+            let _ = registry.deregister(&mut *self.socket);
+            // Closes the old socket, so that its address is free again
+            let loopback: IpAddr = match rebind.address {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            };
+            self.socket = Box::new(UdpSocket::bind(SocketAddr::new(loopback, 0))?);
+        }
+        match bind(rebind.address, &rebind.multicast_groups)
+            .and_then(|socket| size(&socket, rebind.recv_buffer).map(|()| socket))
+        {
+            Ok(socket) => {
+                self.socket = Box::new(socket);
+                if let Some(token) = self.token {
+                    registry.register(&mut *self.socket, token, Interest::READABLE)?;
+                }
+                rebind.broken = false;
+                stats::count(Counter::Rebinds);
+                info!("Rebound {}", rebind.address);
+            }
+            Err(e) if !rebind.broken => {
+                warn!("{:#}, retrying", e);
+                rebind.broken = true;
+            }
+            Err(_) => (),
+        }
+        Ok(())
+    }
+
+    fn allowed(&self, source: SocketAddr) -> bool {
+        self.allowed_sources.is_empty()
+            || self.allowed_sources.iter().any(|s| s.contains(source.ip()))
+    }
+
+    fn on_packet(
+        &mut self,
+        packet: &[u8],
+        source: SocketAddr,
+        registry: &Registry,
+        processes: &mut ProcessMonitor,
+    ) -> Result<(Verdict, Option<Action>)> {
+        if self.draining {
+            return Ok(self.on_draining(packet, source));
+        }
+        let anonymous = self.anonymous();
+        if anonymous {
+            // Without configured clients the contents don't matter
+            self.feed(None)?;
+            self.pinged(String::from("anonymous"), source);
+        }
+        let unless_fed = |verdict| (if anonymous { Verdict::Fed } else { verdict }, None);
+        if self.legacy.enabled && packet.trim_ascii().is_empty() {
+            stats::count(Counter::LegacyPings);
+            let Some(client) = self.legacy.client.clone() else {
+                return Ok(unless_fed(Verdict::Ignored));
+            };
+            self.feed(Some(&client))?;
+            self.pinged(client, source);
+            return Ok((Verdict::Fed, None));
+        }
+        let Some((packet, framed)) = unframe(packet, source) else {
+            return Ok(unless_fed(Verdict::Malformed));
+        };
+        let raw = packet;
+        let packet = match protocol::parse(packet) {
+            Ok(packet) => packet,
+            Err(e) if !anonymous => {
+                warn!("Bad packet from {}: {:#}", source, e);
+                return Ok((Verdict::Malformed, None));
+            }
+            Err(_) => return Ok((Verdict::Fed, None)),
+        };
+        let id = packet.id;
+        if let (Some(id), false) = (id, matches!(packet.command, Command::Ping { .. })) {
+            let now = self.clock.now();
+            while self
+                .replied
+                .front()
+                .is_some_and(|r| now.saturating_duration_since(r.at) >= REPLIED_FOR)
+            {
+                self.replied.pop_front();
+            }
+            let replied = self
+                .replied
+                .iter()
+                .find(|r| r.source == source && r.packet == raw);
+            if let Some(replied) = replied {
+                debug!("Resent the reply to request {} from {}", id, source);
+                self.reply(source, Some(id), &replied.reply, framed);
+                return Ok((Verdict::Command, None));
+            }
+        }
+        if let Err(e) = self.auth.authorize(&packet.command, packet.auth.as_ref()) {
+            warn!("Command from {} denied: {:#}", source, e);
+            let reply = format!("ERR {:#}", e);
+            self.audit
+                .denied(source, &packet.command, packet.auth.as_ref(), &reply);
+            // Pings never get a reply
+            if !matches!(packet.command, Command::Ping { .. }) {
+                self.reply(source, id, &reply, framed);
+            }
+            return Ok((Verdict::Denied, None));
+        }
+        let mut action = None;
+        let reply = match packet.command {
+            Command::Ping { client, ack, every } => {
+                #[cfg(feature = "inject")]
+                if crate::inject::drop_ping() {
+                    debug!("Dropped a ping from {:?}", client);
+                    return Ok(unless_fed(Verdict::Ignored));
+                }
+                if let Some(index) = self.find(client) {
+                    if let (Some(every), Some(c)) = (every, &mut self.clients[index]) {
+                        c.every = (!every.is_zero()).then_some(every);
+                    }
+                    self.feed(Some(client))?;
+                    self.pinged(client.to_string(), source);
+                    if ack.is_some() || self.clients[index].as_ref().is_some_and(|c| c.ack) {
+                        self.acknowledge(source, id, ack, framed)?;
+                    }
+                    return Ok((Verdict::Fed, None));
+                }
+                if !anonymous {
+                    warn!("Ping from unknown client {:?} ({})", client, source);
+                }
+                return Ok(unless_fed(Verdict::Unknown));
+            }
+            Command::Register {
+                client,
+                timeout,
+                pid,
+            } => match pid {
+                // A watched process exiting resets the card, so handing one over takes a key
+                Some(_) => self.auth.verify(packet.auth.as_ref()),
+                None => Ok(()),
+            }
+            .and_then(|()| self.on_register(client, timeout, pid, registry, processes)),
+            Command::Deregister { client } => self.on_deregister(client, registry, processes),
+            Command::Extend { client, duration } => self.on_extend(client, duration),
+            Command::Inhibit { duration, name } => match self.inhibit.auth {
+                true => self.auth.verify(packet.auth.as_ref()),
+                false => Ok(()),
+            }
+            .and_then(|()| self.on_inhibit(duration, name, &source.to_string())),
+            Command::Uninhibit { name } => match self.inhibit.auth {
+                true => self.auth.verify(packet.auth.as_ref()),
+                false => Ok(()),
+            }
+            .and_then(|()| self.on_uninhibit(name)),
+            Command::Status => self.status().map(|status| status.to_lines()),
+            Command::Logbook { from } => logbook::page(from.unwrap_or(0)),
+            Command::Arm | Command::Disarm => self
+                .auth
+                .verify(packet.auth.as_ref())
+                .and_then(|()| self.set_armed(packet.command == Command::Arm)),
+            Command::Takeover => Err(anyhow::anyhow!("Not draining")),
+            Command::Reset => self.auth.verify(packet.auth.as_ref()).map(|()| {
+                warn!("Reset commanded by {}", source);
+                action = Some(Action::Reset);
+                String::from("OK")
+            }),
+            Command::Reexec => self.auth.verify(packet.auth.as_ref()).map(|()| {
+                warn!("Re-exec commanded by {}", source);
+                action = Some(Action::Reexec);
+                String::from("OK")
+            }),
+            Command::Inject { fault } => self
+                .auth
+                .verify(packet.auth.as_ref())
+                .and_then(|()| inject(fault)),
+            Command::Power { low } => self.auth.verify(packet.auth.as_ref()).and_then(|()| {
+                ensure!(self.low_power.is_some(), "No low power mode");
+                action = Some(Action::Power(low));
+                Ok(String::from("OK"))
+            }),
+            Command::Criticality { client, critical } => self
+                .auth
+                .verify(packet.auth.as_ref())
+                .and_then(|()| self.set_criticality(client, critical)),
+            Command::Profile { name } => self.auth.verify(packet.auth.as_ref()).and_then(|()| {
+                let index = self.profiles.0.iter().position(|p| p == name);
+                let index = index.with_context(|| format!("No profile {:?}", name))?;
+                action = Some(Action::Profile(index));
+                Ok(String::from("OK"))
+            }),
+            Command::Maintenance { duration } => self
+                .auth
+                .verify(packet.auth.as_ref())
+                .and_then(|()| self.on_maintenance(duration))
+                .inspect(|_| action = Some(Action::Maintenance(duration))),
+        };
+        let reply = reply.unwrap_or_else(|e| {
+            warn!("Command from {} failed: {:#}", source, e);
+            format!("ERR {:#}", e)
+        });
+        self.audit
+            .record(source, &packet.command, packet.auth.as_ref(), &reply);
+        self.reply(source, id, &reply, framed);
+        if id.is_some() {
+            if self.replied.len() == MAX_REPLIED {
+                self.replied.pop_front();
+            }
+            self.replied.push_back(Replied {
+                at: self.clock.now(),
+                source,
+                packet: raw.to_vec(),
+                reply,
+            });
+        }
+        Ok((Verdict::Command, action))
+    }
+
+    /// Stops handling anything but TAKEOVER, the watchdog is on its way out
+    pub fn drain(&mut self) {
+        self.draining = true;
+    }
+
+    fn on_draining(&mut self, packet: &[u8], source: SocketAddr) -> (Verdict, Option<Action>) {
+        let Some((packet, framed)) = unframe(packet, source) else {
+            return (Verdict::Malformed, None);
+        };
+        match protocol::parse(packet) {
+            Ok(packet) if packet.command == Command::Takeover => {
+                // Ending the drain stops the petting, so it takes a key, or coming from this host
+                // without one
+                let allowed = match self.auth.keyed() {
+                    true => self.auth.verify(packet.auth.as_ref()),
+                    false if source.ip().is_loopback() => Ok(()),
+                    false => Err(anyhow::anyhow!("Not from this host")),
+                };
+                let reply = match &allowed {
+                    Ok(()) => String::from("OK"),
+                    Err(e) => format!("ERR {:#}", e),
+                };
+                self.audit
+                    .record(source, &packet.command, packet.auth.as_ref(), &reply);
+                self.reply(source, packet.id, &reply, framed);
+                match allowed {
+                    Ok(()) => {
+                        info!("Takeover by {}", source);
+                        (Verdict::Command, Some(Action::Takeover))
+                    }
+                    Err(e) => {
+                        warn!("Takeover from {} refused, still draining: {:#}", source, e);
+                        (Verdict::Ignored, None)
+                    }
+                }
+            }
+            // Pings never get a reply
+            Ok(packet) if !matches!(packet.command, Command::Ping { .. }) => {
+                let reply = "ERR Draining";
+                self.audit
+                    .record(source, &packet.command, packet.auth.as_ref(), reply);
+                self.reply(source, packet.id, reply, framed);
+                (Verdict::Ignored, None)
+            }
+            _ => (Verdict::Ignored, None),
+        }
+    }
+
+    /// "ACK <seq> <margin ms>", so that a client can tell its pings aren't arriving before the
+    /// watchdog bites
+    fn acknowledge(
+        &self,
+        dest: SocketAddr,
+        id: Option<u64>,
+        seq: Option<u64>,
+        framed: bool,
+    ) -> Result<()> {
+        let seq = seq.map_or(String::from("-"), |seq| seq.to_string());
+        let margin = match self.deadline()? {
+            Some(margin) => margin.as_millis().to_string(),
+            None => String::from("-"),
+        };
+        self.reply(dest, id, &format!("ACK {} {}", seq, margin), framed);
+        Ok(())
+    }
+
+    /// Framed if the command was, and starting with its id if it had one
+    fn reply(&self, dest: SocketAddr, id: Option<u64>, reply: &str, framed: bool) {
+        let reply = match id {
+            Some(id) => format!("ID {} {}", id, reply),
+            None => reply.to_string(),
+        };
+        let packet = match framed {
+            true => framing::encode(Kind::Reply, reply.as_bytes()),
+            false => Ok(reply.as_bytes().to_vec()),
+        };
+        // Best effort, the requester can always ask again
+        let socket = match &self.second {
+            Some(second) if second.reaches(dest) => second,
+            _ => &self.socket,
+        };
+        let sent = packet.and_then(|packet| Ok(socket.send_to(&packet, dest)?));
+        if let Err(e) = sent {
+            warn!("Failed to reply to {}: {:#}", dest, e);
+        }
+    }
+
+    fn on_register(
+        &mut self,
+        name: &str,
+        timeout: Duration,
+        pid: Option<i32>,
+        registry: &Registry,
+        processes: &mut ProcessMonitor,
+    ) -> Result<String> {
+        ensure!(self.registration.enabled, "Registration is disabled");
+        // Zero would disarm its timer, so it could never time out
+        ensure!(!timeout.is_zero(), "The timeout must be positive");
+        let timeout = timeout.min(self.registration.max_timeout);
+        let slot = match self.find(name) {
+            Some(index) => {
+                ensure!(
+                    self.clients[index].as_ref().is_some_and(|c| c.registered),
+                    "Client {} is configured",
+                    name
+                );
+                // Re-registering replaces the old registration, e.g. after a client restart
+                self.remove(index, registry, processes)?;
+                index
+            }
+            None => match self.clients.iter().position(Option::is_none) {
+                Some(index) => index,
+                None if self.clients.len() < MAX_CLIENTS => {
+                    self.clients.push(None);
+                    self.clients.len() - 1
+                }
+                None => bail!("Too many clients"),
+            },
+        };
+
+        // Registering during an inhibit doesn't cut it short
+        let first = timeout.max(self.inhibit_remaining());
+        let mut client = Client::new(Some(name.to_string()), timeout, first, &self.clock)?;
+        client.registered = true;
+        client
+            .timer
+            .register(registry, Token(self.timers.0 + slot))?;
+        self.clients[slot] = Some(client);
+        if let Some(pid) = pid {
+            if let Err(e) = processes.watch(registry, name, pid) {
+                self.remove(slot, registry, processes)?;
+                return Err(e);
+            }
+        }
+        info!("Registered client {} with a {:?} timeout", name, timeout);
+        Ok(format!("OK {}", timeout.as_secs_f64()))
+    }
+
+    fn on_deregister(
+        &mut self,
+        name: &str,
+        registry: &Registry,
+        processes: &mut ProcessMonitor,
+    ) -> Result<String> {
+        let index = self
+            .find(name)
+            .with_context(|| format!("Unknown client {}", name))?;
+        ensure!(
+            self.clients[index].as_ref().is_some_and(|c| c.registered),
+            "Client {} is configured",
+            name
+        );
+        self.remove(index, registry, processes)?;
+        info!("Deregistered client {}", name);
+        Ok(String::from("OK"))
+    }
+
+    fn on_extend(&mut self, name: &str, duration: Duration) -> Result<String> {
+        ensure!(self.extension.enabled, "Extension is disabled");
+        ensure!(
+            duration <= self.extension.max,
+            "Extension of {:?} exceeds the maximum of {:?}",
+            duration,
+            self.extension.max
+        );
+        // Without configured clients anyone may extend the anonymous client
+        let index = match self.find(name) {
+            Some(index) => index,
+            None => self
+                .clients
+                .iter()
+                .position(|c| matches!(c, Some(c) if c.name.is_none()))
+                .with_context(|| format!("Unknown client {}", name))?,
+        };
+        // Only the delay actually added is spent and granted, a deadline further out stays
+        let added = match &self.clients[index] {
+            Some(client) => duration.saturating_sub(client.remaining()?),
+            None => Duration::ZERO,
+        };
+        if added.is_zero() {
+            return Ok(String::from("OK 0.0"));
+        }
+        self.spend(added)?;
+        if let Some(client) = &mut self.clients[index] {
+            client.extend(duration)?;
+            info!("Extended {} client by {:?}", client.label(), added);
+        }
+        Ok(format!("OK {:.1}", added.as_secs_f64()))
+    }
+
+    fn on_inhibit(
+        &mut self,
+        duration: Duration,
+        name: Option<&str>,
+        requester: &str,
+    ) -> Result<String> {
+        ensure!(self.inhibit.enabled, "Inhibit is disabled");
+        ensure!(
+            duration <= self.inhibit.max,
+            "Inhibit of {:?} exceeds the maximum of {:?}",
+            duration,
+            self.inhibit.max
+        );
+        if let Some(max_count) = self.inhibit.max_count {
+            ensure!(
+                self.granted < max_count,
+                "All {} inhibits have been used",
+                max_count
+            );
+        }
+        let name = name.unwrap_or("anonymous");
+        if let Some(rule) = &self.inhibit_rule {
+            let variables = |variable: &str| match variable {
+                "duration" => Some(Value::Number(duration.as_secs_f64())),
+                "name" => Some(Value::Text(name.to_string())),
+                "requester" => Some(Value::Text(requester.to_string())),
+                "hour" => Some(rule::hour()),
+                variable => self.variable(variable),
+            };
+            // Like no rule if it can't be evaluated, the limits above still apply
+            match rule.holds(&variables) {
+                Ok(holds) => ensure!(holds, "Inhibit denied by the inhibit rule"),
+                Err(e) => warn!("Failed to evaluate the inhibit rule: {:#}", e),
+            }
+        }
+        let now = self.clock.now();
+        let others = self.active().filter(|i| i.name != name).count();
+        ensure!(others < MAX_INHIBITS, "Too many inhibits");
+        self.spend(duration)?;
+        self.granted += 1;
+        // The same name replaces its own, which may end it sooner
+        let replaced = self.active().any(|i| i.name == name);
+        self.inhibits.retain(|i| i.until > now && i.name != name);
+        self.inhibits.push(Inhibit {
+            name: name.to_string(),
+            requester: requester.to_string(),
+            until: now + duration,
+        });
+        // Same as the startup inhibit: every deadline is pushed out to at least the end of it.
+        // Afterwards each client has to have pinged within its timeout again.
+        for client in self.iter() {
+            client.hold(duration)?;
+        }
+        if replaced {
+            self.shorten()?;
+        }
+        info!("Inhibited for {:?} ({})", duration, name);
+        history::record(Event::Inhibit {
+            duration,
+            name: name.to_string(),
+        });
+        let command = Command::Inhibit {
+            duration,
+            name: (name != "anonymous").then_some(name),
+        };
+        self.enter(&command.to_string());
+        Ok(format!("OK {}", duration.as_secs_f64()))
+    }
+
+    /// A client made critical gets a full window from now, rather than being fatal right away for
+    /// the silence it was allowed
+    fn set_criticality(&mut self, name: &str, critical: bool) -> Result<String> {
+        let index = self
+            .find(name)
+            .with_context(|| format!("Unknown client {}", name))?;
+        let criticality = match critical {
+            true => Criticality::Critical,
+            false => Criticality::Advisory,
+        };
+        if let Some(client) = &mut self.clients[index] {
+            if client.criticality != criticality {
+                client.criticality = criticality;
+                client.missed_in_a_row = 0;
+                client.misses.clear();
+                client.struck_out = false;
+                if critical {
+                    client.timer.set(client.timeout)?;
+                }
+                warn!("{} client made {}", name, criticality.name());
+                history::record(Event::Criticality {
+                    client: name.to_string(),
+                    criticality,
+                });
+            }
+        }
+        Ok(String::from("OK"))
+    }
+
+    fn on_uninhibit(&mut self, name: &str) -> Result<String> {
+        ensure!(self.inhibit.enabled, "Inhibit is disabled");
+        let now = self.clock.now();
+        let index = self
+            .inhibits
+            .iter()
+            .position(|i| i.until > now && i.name == name);
+        let Some(index) = index else {
+            bail!("No inhibit {:?}", name);
+        };
+        self.inhibits.remove(index);
+        self.shorten()?;
+        info!("Revoked inhibit {}", name);
+        history::record(Event::Uninhibit {
+            name: name.to_string(),
+        });
+        self.enter(&format!("UNINHIBIT {}", name));
+        Ok(String::from("OK"))
+    }
+
+    /// The commanded inhibits that haven't run out
+    fn active(&self) -> impl Iterator<Item = &Inhibit> {
+        let now = self.clock.now();
+        self.inhibits.iter().filter(move |i| i.until > now)
+    }
+
+    /// Brings in the deadlines an inhibit that was cut short pushed out, to what's left of the
+    /// others or a full timeout. Extensions stay.
+    fn shorten(&self) -> Result<()> {
+        let now = self.clock.now();
+        let left = self.inhibit_remaining();
+        for client in self.iter() {
+            let until = left.max(client.timeout);
+            let extended = client.extended_until.is_some_and(|until| now < until);
+            if !extended && client.remaining()? > until {
+                client.timer.set(until)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes a requested delay out of the budget, the whole of it even if it moves a deadline by
+    /// less
+    fn spend(&mut self, delay: Duration) -> Result<()> {
+        self.budget
+            .take(delay)
+            .inspect_err(|_| stats::count(Counter::BudgetDenials))
+    }
+
+    /// Until the startup and every commanded inhibit are over
+    pub fn inhibit_remaining(&self) -> Duration {
+        let until = self.active().map(|i| i.until).max();
+        until
+            .map_or(self.startup_until, |until| until.max(self.startup_until))
+            .saturating_duration_since(self.clock.now())
+    }
+
+    fn set_armed(&mut self, armed: bool) -> Result<String> {
+        ensure!(
+            self.maintenance_until.is_none(),
+            "In maintenance, end it with MAINTENANCE 0"
+        );
+        self.switch_armed(armed, if armed { "ARM" } else { "DISARM" })?;
+        Ok(String::from("OK"))
+    }
+
+    fn switch_armed(&mut self, armed: bool, cause: &str) -> Result<()> {
+        self.release();
+        if armed && !self.armed {
+            // Everyone gets a full window from now rather than whatever was left of the last one
+            for client in self.iter() {
+                client.timer.set(client.timeout)?;
+            }
+            warn!("ARMED: missing pings are fatal again");
+        } else if !armed {
+            warn!("DISARMED: missing pings are no longer fatal");
+        }
+        self.armed = armed;
+        self.enter(cause);
+        Ok(())
+    }
+
+    /// Drops the startup gates and the interlock, which arming or disarming by hand supersedes
+    fn release(&mut self) {
+        self.gated = None;
+        // Either way, the update is done with
+        if let Some((path, _)) = self.interlock.take() {
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    warn!("Failed to remove {:?}: {}", path, e)
+                }
+                _ => info!("Removed {:?}", path),
+            }
+        }
+    }
+
+    /// Disarms until `duration` from now, or ends the maintenance for zero. Entering it drops the
+    /// startup gates and the interlock like DISARM, it's armed again either way once it's over.
+    fn on_maintenance(&mut self, duration: Duration) -> Result<String> {
+        let max = self.maintenance.context("No maintenance mode")?;
+        if duration.is_zero() {
+            ensure!(self.maintenance_until.is_some(), "Not in maintenance");
+            self.end_maintenance("MAINTENANCE 0")?;
+            return Ok(String::from("OK"));
+        }
+        ensure!(
+            duration <= max,
+            "Maintenance is at most {} s",
+            max.as_secs_f64()
+        );
+        if self.maintenance_until.is_none() {
+            warn!(
+                "MAINTENANCE: missing pings aren't fatal for {} s",
+                duration.as_secs_f64()
+            );
+        }
+        self.release();
+        self.maintenance_until = Some(self.clock.now() + duration);
+        self.armed = false;
+        self.enter(&format!("MAINTENANCE {}", duration.as_secs_f64()));
+        Ok(String::from("OK"))
+    }
+
+    /// Arms again, giving every client a full window, unless it isn't in maintenance
+    pub fn end_maintenance(&mut self, cause: &str) -> Result<()> {
+        if self.maintenance_until.take().is_some() {
+            warn!("Maintenance is over: {}", cause);
+            self.switch_armed(true, cause)?;
+        }
+        Ok(())
+    }
+
+    /// Until the maintenance is over, None outside of one
+    pub fn maintenance_remaining(&self) -> Option<Duration> {
+        let until = self.maintenance_until?;
+        Some(until.saturating_duration_since(self.clock.now()))
+    }
+
+    pub fn armed(&self) -> bool {
+        self.armed
+    }
+
+    /// The state both kinds of rule can look at
+    pub fn variable(&self, name: &str) -> Option<Value> {
+        match name {
+            "armed" => Some(Value::Bool(self.armed)),
+            "inhibited" => Some(Value::Number(self.inhibit_remaining().as_secs_f64())),
+            "inhibits" => Some(Value::Number(f64::from(self.granted))),
+            _ => None,
+        }
+    }
+
+    /// Whether the startup gates still keep this disarmed
+    pub fn gated(&self) -> bool {
+        self.gated.is_some()
+    }
+
+    /// The startup gates still waited for changed
+    pub fn gating(&mut self, waiting: String) {
+        if let Some(gated) = &mut self.gated {
+            *gated = waiting;
+        }
+    }
+
+    /// Arms once the startup gates are done with, unless the interlock still keeps it disarmed
+    pub fn ungate(&mut self) -> Result<()> {
+        if self.gated.take().is_some() && self.interlock.is_none() {
+            warn!("Startup gates are done");
+            self.set_armed(true)?;
+        }
+        Ok(())
+    }
+
+    /// Arms once the interlock window is over without an ARM, on every pet
+    pub fn check_interlock(&mut self) -> Result<()> {
+        if self
+            .interlock
+            .as_ref()
+            .is_some_and(|(_, until)| self.clock.now() >= *until)
+        {
+            warn!("Interlock window is over");
+            self.set_armed(true)?;
+        }
+        Ok(())
+    }
+
+    /// Rereads the command authentication keys, keeping the old ones if that fails
+    pub fn reload_keys(&mut self) -> Result<()> {
+        self.auth.reload()
+    }
+
+    /// Like ARM and DISARM, audited as from `source`
+    pub fn arm(&mut self, armed: bool, source: &str) -> Result<()> {
+        let result = self.set_armed(armed);
+        let command = match armed {
+            true => Command::Arm,
+            false => Command::Disarm,
+        };
+        self.audit_result(source, &command, &result);
+        result.map(drop)
+    }
+
+    /// Like an INHIBIT without a name, but never authenticated
+    pub fn inhibit(&mut self, duration: Duration, source: &str) -> Result<()> {
+        let result = self.on_inhibit(duration, None, source);
+        let command = Command::Inhibit {
+            duration,
+            name: None,
+        };
+        self.audit_result(source, &command, &result);
+        result.map(drop)
+    }
+
+    fn audit_result(&mut self, source: &str, command: &Command, result: &Result<String>) {
+        let outcome = match result {
+            Ok(reply) => reply.clone(),
+            Err(e) => format!("ERR {:#}", e),
+        };
+        self.audit.record(source, command, None, &outcome);
+    }
+
+    /// Commanded inhibits granted so far
+    pub fn inhibits(&self) -> u32 {
+        self.granted
+    }
+
+    /// Client slots, the configured clients first. Registered ones can leave theirs free.
+    pub fn slots(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// None for a free slot
+    pub fn timeout(&self, slot: usize) -> Option<Duration> {
+        self.clients.get(slot)?.as_ref().map(|c| c.timeout)
+    }
+
+    /// Takes effect from the next ping, or right away if the deadline is further out and no
+    /// inhibit holds it there. Returns false for a free slot.
+    pub fn set_timeout(&mut self, slot: usize, timeout: Duration) -> Result<bool> {
+        let until = timeout.max(self.inhibit_remaining());
+        let Some(Some(client)) = self.clients.get_mut(slot) else {
+            return Ok(false);
+        };
+        client.timeout = timeout;
+        if client.extended_until.is_none() && client.remaining()? > until {
+            client.timer.set(until)?;
+        }
+        info!("{} client timeout set to {:?}", client.label(), timeout);
+        Ok(true)
+    }
+
+    /// For the status, once the mode actually changed
+    pub fn set_low_power(&mut self, low: bool) {
+        self.low_power = Some(low);
+    }
+
+    /// For the status, once switched
+    pub fn set_profile(&mut self, index: usize) {
+        self.profiles.1 = index;
+    }
+
+    /// Adds a key to every status report
+    pub fn annotate(&mut self, key: &str, value: impl Display) {
+        self.annotations.push(key, value);
+    }
+
+    /// As of the last transition
+    pub fn state(&self) -> State {
+        self.state.state()
+    }
+
+    /// Disarmed, inhibited, or armed, going by the flags and the inhibit alone
+    fn derived(&self) -> State {
+        if self.maintenance_until.is_some() {
+            State::Maintenance
+        } else if !self.armed {
+            State::Disarmed
+        } else if self.inhibit_remaining().is_zero() {
+            State::Armed
+        } else if self.active().next().is_none() {
+            State::StartupInhibit
+        } else {
+            State::Inhibited
+        }
+    }
+
+    /// Enters the derived state after a command. Only disarming, maintenance included, gets off
+    /// death row that way, anything else has to be pardoned.
+    fn enter(&mut self, cause: &str) {
+        let state = self.derived();
+        let disarmed = matches!(state, State::Disarmed | State::Maintenance);
+        if self.state() != State::DeathRow || disarmed {
+            self.state.enter(state, cause);
+        }
+    }
+
+    /// Notices an inhibit that ran out
+    fn update_state(&mut self) {
+        let cause = match self.state() {
+            State::StartupInhibit => "Startup inhibit over",
+            State::Inhibited => "Inhibit over",
+            _ => return,
+        };
+        self.state.enter(self.derived(), cause);
+    }
+
+    /// Catches up with time and with death row, `sentence` being the reason it's pending for
+    pub fn sync_state(&mut self, sentence: Option<&str>) {
+        match sentence {
+            Some(reason) => {
+                self.state.enter(State::DeathRow, reason);
+            }
+            None if self.state() == State::DeathRow => {
+                self.state.enter(self.derived(), "Reprieved");
+            }
+            None => self.update_state(),
+        }
+    }
+
+    /// For good, the petting is about to stop
+    pub fn die(&mut self, reason: &str) {
+        self.state.enter(State::Dying, reason);
+    }
+
+    /// Until the first client times out, None if missing pings aren't fatal now
+    pub fn deadline(&self) -> Result<Option<Duration>> {
+        if self.derived() != State::Armed {
+            return Ok(None);
+        }
+        let mut deadline = None;
+        for client in self.iter() {
+            let remaining = client.remaining()?;
+            deadline = Some(deadline.map_or(remaining, |d: Duration| d.min(remaining)));
+        }
+        Ok(deadline)
+    }
+
+    /// The client furthest into its timeout, with what's left of it and the share gone by, None
+    /// if missing pings aren't fatal now
+    pub fn countdown(&self) -> Result<Option<(&str, Duration, f64)>> {
+        if self.derived() != State::Armed {
+            return Ok(None);
+        }
+        let mut furthest = None;
+        let critical = self
+            .iter()
+            .filter(|c| c.criticality == Criticality::Critical);
+        for client in critical {
+            let remaining = client.remaining()?;
+            let gone = 1.0 - remaining.as_secs_f64() / client.timeout.as_secs_f64();
+            if furthest.is_none_or(|(_, _, most)| gone > most) {
+                furthest = Some((client.label(), remaining, gone.max(0.0)));
+            }
+        }
+        Ok(furthest)
+    }
+
+    /// How well the clients kept up over the health window
+    pub fn health(&self) -> Grade {
+        let now = self.clock.now();
+        let window = self.health.window();
+        let expected = self
+            .iter()
+            .map(|client| {
+                let since = now.saturating_duration_since(client.added).min(window);
+                since.as_secs_f64() / client.timeout.as_secs_f64()
+            })
+            .sum();
+        self.health.grade(now, expected)
+    }
+
+    pub fn status(&self) -> Result<StatusReport> {
+        let mut status = StatusReport::new();
+        let last = self.state.last();
+        status.push("state", last.to);
+        status.push("state.cause", &last.cause);
+        status.push("state.entered", last.at);
+        let seconds = |ago: Duration| format!("{:.1}", ago.as_secs_f64());
+        status.push("uptime", seconds(self.started.elapsed()));
+        let since_last_ping = self.last_ping().map(|(ago, _)| seconds(ago));
+        status.push("since_last_ping", since_last_ping.as_deref().unwrap_or("-"));
+        let inhibit = self.inhibit_remaining();
+        status.push("inhibit_remaining", format!("{:.1}", inhibit.as_secs_f64()));
+        for inhibit in self.active() {
+            let left = inhibit.until.saturating_duration_since(self.clock.now());
+            let expires = clock::wall_now() + left.as_secs_f64().ceil() as i64;
+            status.push(
+                format!("inhibit.{}.requester", inhibit.name),
+                &inhibit.requester,
+            );
+            status.push(format!("inhibit.{}.expires", inhibit.name), expires);
+        }
+        if let Some((_, until)) = &self.interlock {
+            let left = until.saturating_duration_since(self.clock.now());
+            status.push("interlock_remaining", seconds(left));
+        }
+        if let Some(gates) = &self.gated {
+            status.push("gates_waiting", gates);
+        }
+        if let Some(left) = self.maintenance_remaining() {
+            status.push("maintenance_remaining", seconds(left));
+        }
+        status.push("missed_while_disarmed", self.missed_while_disarmed);
+        let health = self.health();
+        status.push("health", format!("{:.3}", health.grade));
+        let worst = health
+            .worst_margin
+            .map(|margin| format!("{:.3}", margin.as_secs_f64()));
+        status.push("health.worst_margin", worst.as_deref().unwrap_or("-"));
+        if self.budget.enabled() {
+            let left = self.budget.left().as_secs_f64();
+            status.push("budget.remaining", format!("{:.1}", left));
+        }
+        if let Some(policy) = &self.policy {
+            status.push("policy", policy);
+            status.push("policy.holds", self.policy_holds());
+        }
+        if let Some(low) = self.low_power {
+            status.push("power", if low { "low" } else { "normal" });
+        }
+        if let Some(profile) = self.profiles.0.get(self.profiles.1) {
+            status.push("profile", profile);
+        }
+        for (key, value) in self.annotations.iter() {
+            status.push(key, value);
+        }
+        for client in self.iter() {
+            let label = client.label();
+            let last_ping = client.last_ping.map(|at| at.to_string());
+            status.push(
+                format!("client.{}.last_ping", label),
+                last_ping.as_deref().unwrap_or("-"),
+            );
+            status.push(format!("client.{}.timeout", label), seconds(client.timeout));
+            status.push(
+                format!("client.{}.remaining", label),
+                seconds(client.remaining()?),
+            );
+            if let Some(every) = client.every {
+                status.push(format!("client.{}.every", label), seconds(every));
+            }
+            status.push(
+                format!("client.{}.criticality", label),
+                client.criticality.name(),
+            );
+            if self.strikes.max > 1 {
+                let strikes = match self.strikes.window.is_zero() {
+                    true => client.missed_in_a_row as usize,
+                    false => client.misses.len(),
+                };
+                status.push(format!("client.{}.strikes", label), strikes);
+            }
+        }
+        if let Some(drops) = self.socket.drops() {
+            status.push("socket.drops", drops);
+        }
+        self.traffic.report(&mut status);
+        stats::report(&mut status);
+        Ok(status)
+    }
+
+    fn remove(
+        &mut self,
+        index: usize,
+        registry: &Registry,
+        processes: &mut ProcessMonitor,
+    ) -> Result<()> {
+        if let Some(client) = self.clients[index].take() {
+            client.timer.deregister(registry)?;
+            if let Some(name) = &client.name {
+                processes.unwatch(registry, name)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Pushes back the timeout of the named client, or the anonymous client if None.
+    pub fn feed(&mut self, name: Option<&str>) -> Result<()> {
+        for client in self.clients.iter_mut().flatten() {
+            if client.name.as_deref() == name {
+                let margin = client.feed()?;
+                self.health.pinged(self.clock.now(), margin);
+                #[cfg(debug_assertions)]
+                debug!(
+                    "PINGED by {} at {} ms",
+                    client.label(),
+                    crate::timestamp_millis()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the token is one of the client timers
+    pub fn handles(&self, token: Token) -> bool {
+        (self.timers.0..self.timers.0 + self.clients.len()).contains(&token.0)
+    }
+
+    pub fn handles_port(&self, token: Token) -> bool {
+        (self.first_port.0..self.first_port.0 + self.ports.len()).contains(&token.0)
+    }
+
+    /// Any packet on a port feeds its channel, the contents are discarded. Read within the same
+    /// budget as the main socket.
+    pub fn on_port(&mut self, registry: &Registry, token: Token) -> Result<()> {
+        let index = token.0 - self.first_port.0;
+        let mut buf = [0; 1];
+        let mut pinged = None;
+        let mut left = self.drain_budget;
+        loop {
+            if left == 0 {
+                stats::count(Counter::DrainCuts);
+                let socket = &mut self.ports[index].socket;
+                registry.reregister(socket, token, Interest::READABLE)?;
+                break;
+            }
+            left -= 1;
+            match self.ports[index].socket.recv_from(&mut buf) {
+                Ok((_, source)) if !self.allowed(source) => {
+                    debug!("Dropped packet from {}", source)
+                }
+                Ok((_, source)) => pinged = Some(source),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    return Err(e)
+                        .context("Port socket read failed")
+                        .context(Cause::Socket)
+                }
+            }
+        }
+        if let Some(source) = pinged {
+            if let Some(client) = &mut self.clients[self.ports[index].client] {
+                let margin = client.feed()?;
+                self.health.pinged(self.clock.now(), margin);
+                let label = client.label().to_string();
+                self.pinged(label, source);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether an armed client's timeout expired without it having pinged since
+    pub fn starving(&self) -> Result<bool> {
+        Ok(self.starved()?.is_some())
+    }
+
+    /// The first armed client whose timeout expired without it having pinged since, or that's
+    /// out of strikes if the policy doesn't hold without it
+    pub fn starved(&self) -> Result<Option<&str>> {
+        if !self.armed {
+            return Ok(None);
+        }
+        let holds = self.policy_holds();
+        for client in self.iter() {
+            if client.criticality == Criticality::Advisory {
+                continue;
+            }
+            let starved = match self.covered(client) {
+                true => !holds && client.struck_out,
+                false => client.remaining()?.is_zero(),
+            };
+            if starved {
+                return Ok(Some(client.label()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether the policy decides over the client, rather than it being required
+    fn covered(&self, client: &Client) -> bool {
+        let policy = self.policy.as_ref();
+        client
+            .name
+            .as_deref()
+            .is_some_and(|name| policy.is_some_and(|p| p.clients().contains(&name)))
+    }
+
+    /// With the clients that are out of strikes dead, true without a policy
+    fn policy_holds(&self) -> bool {
+        let alive = |name: &str| {
+            self.find(name)
+                .and_then(|index| self.clients[index].as_ref())
+                .is_some_and(|client| !client.struck_out)
+        };
+        self.policy.as_ref().is_none_or(|p| p.holds(&alive))
+    }
+
+    /// Called when a client's timeout timer expired, returns why that's fatal if it is
+    pub fn on_timeout(&mut self, token: Token) -> Result<Option<String>> {
+        let slot = token.0 - self.timers.0;
+        if self.clients[slot].is_some() {
+            self.health.missed(self.clock.now());
+        }
+        let reason = match &mut self.clients[slot] {
+            Some(client) if !self.armed => {
+                // Start the next window, so this keeps nagging for as long as it's disarmed
+                client.timer.set(client.timeout)?;
+                self.missed_while_disarmed += 1;
+                warn!(
+                    "DISARMED: ignoring ping timeout for {} client",
+                    client.label()
+                );
+                return Ok(None);
+            }
+            Some(client) if client.criticality == Criticality::Advisory => {
+                client.timer.set(client.timeout)?;
+                client.missed_in_a_row += 1;
+                let timeout = format!("Ping timeout for {} advisory client", client.label());
+                warn!("{}", stats::annotate(timeout, client.timeout));
+                if client.missed_in_a_row == 1 {
+                    history::record(Event::Missed {
+                        client: client.label().to_string(),
+                    });
+                }
+                return Ok(None);
+            }
+            Some(client) => {
+                let strikes = client.strike(self.strikes.window);
+                if strikes < self.strikes.max {
+                    // Another window to recover in
+                    client.timer.set(client.timeout)?;
+                    stats::count(Counter::Strikes);
+                    let timeout = format!("Ping timeout for {} client", client.label());
+                    warn!(
+                        "{}, strike {} of {}",
+                        stats::annotate(timeout, client.timeout),
+                        strikes,
+                        self.strikes.max
+                    );
+                    history::record(Event::Strike {
+                        client: client.label().to_string(),
+                        strikes,
+                        max: self.strikes.max,
+                    });
+                    return Ok(None);
+                }
+                client.struck_out = true;
+                let reason = format!("{} client stopped pinging", client.label());
+                stats::annotate(reason, client.timeout)
+            }
+            // Stale event for a client that deregistered in the same poll
+            None => return Ok(None),
+        };
+        let Some(client) = &self.clients[slot] else {
+            return Ok(None);
+        };
+        if !self.covered(client) || !self.policy_holds() {
+            return Ok(Some(reason));
+        }
+        // Keeps nagging every window, and the policy is checked again each time
+        client.timer.set(client.timeout)?;
+        if let Some(policy) = &self.policy {
+            warn!("{}, but the policy {} still holds", reason, policy);
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "inject")]
+fn inject(fault: Fault) -> Result<String> {
+    crate::inject::set(fault);
+    Ok(String::from("OK"))
+}
+
+#[cfg(not(feature = "inject"))]
+fn inject(_fault: Fault) -> Result<String> {
+    bail!("Built without failure injection")
+}
+
+/// The command in `packet` and whether it was framed, None if the frame was rejected
+fn unframe(packet: &[u8], source: SocketAddr) -> Option<(&[u8], bool)> {
+    if !framing::is_framed(packet) {
+        return Some((packet, false));
+    }
+    match framing::decode(packet) {
+        Ok(Frame {
+            kind: Kind::Command,
+            payload,
+        }) => Some((payload, true)),
+        Ok(_) => {
+            warn!("Unexpected reply frame from {}", source);
+            None
+        }
+        Err(e) => {
+            if let Rejected::Version(_) = e {
+                stats::count(Counter::UnknownVersions);
+            }
+            warn!("Bad frame from {}: {}", source, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{auth::sign, clock::VirtualClock};
+    use mio::{Events, Poll};
+    use std::{net::UdpSocket as StdSocket, time::Duration};
+
+    fn send(pingee: &Pingee, packet: impl AsRef<[u8]>) -> StdSocket {
+        let client = StdSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        client
+            .send_to(packet.as_ref(), pingee.socket.local_addr().unwrap())
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        client
+    }
+
+    fn command(
+        pingee: &mut Pingee,
+        poll: &Poll,
+        processes: &mut ProcessMonitor,
+        text: &str,
+    ) -> String {
+        let client = send(pingee, text);
+        assert_eq!(pingee.on_ping(poll.registry(), processes).unwrap(), None);
+        // Replies, unlike commands, can be longer than a packet
+        let mut buf = [0; 4096];
+        let len = client.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_registration() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3-main"
+            [registration]
+            enabled = true
+            max_timeout = 60
+            [extension]
+            enabled = true
+            max = 120
+            [inhibit]
+            enabled = true
+            max = 600
+            auth = false
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        pingee.register(poll.registry(), Token(0))?;
+
+        macro_rules! cmd {
+            ($text:expr) => {
+                command(&mut pingee, &poll, &mut processes, $text)
+            };
+        }
+        assert_eq!(cmd!("REGISTER edl 90"), "OK 60");
+        assert_eq!(cmd!("REGISTER edl 20"), "OK 20");
+        assert!(cmd!("REGISTER edl 0").starts_with("ERR"));
+        assert!(cmd!("REGISTER c3-main 20").starts_with("ERR"));
+        assert!(cmd!("DEREGISTER c3-main").starts_with("ERR"));
+        assert_eq!(cmd!("DEREGISTER edl"), "OK");
+        assert!(cmd!("DEREGISTER edl").starts_with("ERR"));
+        pingee.auth = Authenticator::with_key(b"0123456789abcdef");
+        let pid = format!("REGISTER edl 20 {}", std::process::id());
+        assert_eq!(cmd!(&pid), "ERR Not authenticated");
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(cmd!(&sign(b"0123456789abcdef", &pid, 1)), "OK 20");
+            assert_eq!(cmd!("DEREGISTER edl"), "OK");
+        }
+
+        // Still the startup inhibit's two minutes out
+        assert_eq!(cmd!("EXTEND c3-main 90"), "OK 0.0");
+        assert!(cmd!("EXTEND c3-main 121").starts_with("ERR"));
+        assert!(cmd!("EXTEND edl 10").starts_with("ERR"));
+
+        pingee.annotate("boots", 3);
+        let status = cmd!("STATUS");
+        assert!(status.starts_with("state=startup_inhibit\nstate.cause=Started\n"));
+        assert!(status.contains("\ninhibit_remaining=1"));
+        assert!(status.contains("\nboots=3\n"));
+        assert!(status.contains("\nsince_last_ping=-\n"));
+        assert!(status.contains("\nclient.c3-main.last_ping=-\nclient.c3-main.timeout=30.0\n"));
+        send(&pingee, "PING c3-main");
+        pingee.on_ping(poll.registry(), &mut processes)?;
+        assert_eq!(cmd!("INHIBIT 500"), "OK 500");
+        assert!(cmd!("INHIBIT 601").starts_with("ERR"));
+        let status = cmd!("STATUS");
+        let value = |key: &str| -> f64 {
+            let line = status.lines().find(|l| l.starts_with(key)).unwrap();
+            line[key.len() + 1..].parse().unwrap()
+        };
+        assert!(value("inhibit_remaining") > 499.0);
+        assert!(value("client.c3-main.remaining") > 499.0);
+        assert!(value("since_last_ping") < value("uptime") + 0.1);
+        assert!(value("client.c3-main.last_ping") > 1.7e9);
+        assert!(status.starts_with("state=inhibited\nstate.cause=INHIBIT 500\n"));
+
+        assert!(pingee.handles(Token(11)));
+        assert_eq!(pingee.on_timeout(Token(11))?, None);
+        assert!(pingee.on_timeout(Token(10))?.is_some());
+
+        // Without a key nothing can be authenticated
+        assert!(cmd!("DISARM").starts_with("ERR"));
+        pingee.auth = Authenticator::with_key(b"0123456789abcdef");
+        assert!(cmd!("DISARM").starts_with("ERR"));
+        assert_eq!(cmd!(&sign(b"0123456789abcdef", "DISARM", 1)), "OK");
+        assert!(cmd!("STATUS").starts_with("state=disarmed"));
+        assert_eq!(pingee.on_timeout(Token(10))?, None);
+        assert_eq!(pingee.missed_while_disarmed, 1);
+        assert!(cmd!("RESET").starts_with("ERR"));
+        send(&pingee, sign(b"0123456789abcdef", "RESET", 2));
+        let action = pingee.on_ping(poll.registry(), &mut processes)?;
+        assert_eq!(action, Some(Action::Reset));
+        Ok(())
+    }
+
+    #[test]
+    fn test_extend_budget() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            timeout = 30
+            [extension]
+            enabled = true
+            max = 120
+            [inhibit]
+            startup = 0.001
+            enabled = true
+            auth = false
+            [budget]
+            max = 100
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        pingee.register(poll.registry(), Token(0))?;
+        pingee.feed(Some("c3"))?;
+        let mut cmd = |text: &str| command(&mut pingee, &poll, &mut processes, text);
+
+        assert_eq!(cmd("EXTEND c3 90"), "OK 60.0");
+        // Repeats don't move the deadline, so they don't spend anything either
+        for _ in 0..5 {
+            assert_eq!(cmd("EXTEND c3 90"), "OK 0.0");
+        }
+        assert_eq!(cmd("INHIBIT 30"), "OK 30");
+        assert!(cmd("EXTEND c3 120").starts_with("ERR"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_inhibit() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [inhibit]
+            startup = 10
+            enabled = true
+            max_count = 1
+            auth = true
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        pingee.register(poll.registry(), Token(0))?;
+        pingee.auth = Authenticator::with_key(b"0123456789abcdef");
+        assert!(pingee.inhibit_remaining() <= Duration::from_secs(10));
+
+        let mut cmd = |text: &str| command(&mut pingee, &poll, &mut processes, text);
+        assert!(cmd("INHIBIT 60").starts_with("ERR"));
+        assert_eq!(cmd(&sign(b"0123456789abcdef", "INHIBIT 60", 1)), "OK 60");
+        let again = cmd(&sign(b"0123456789abcdef", "INHIBIT 60", 2));
+        assert!(again.starts_with("ERR All 1 inhibits"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_inhibit_rule() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [inhibit]
+            startup = 10
+            enabled = true
+            max_count = 1
+            auth = true
+            [rules]
+            inhibit = "duration <= 120 OR NOT armed"
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        pingee.register(poll.registry(), Token(0))?;
+        pingee.auth = Authenticator::with_key(b"0123456789abcdef");
+
+        let mut cmd = |text: &str| command(&mut pingee, &poll, &mut processes, text);
+        // Refused by the rule without using up the one allowed
+        let long = cmd(&sign(b"0123456789abcdef", "INHIBIT 300", 1));
+        assert_eq!(long, "ERR Inhibit denied by the inhibit rule");
+        assert_eq!(cmd(&sign(b"0123456789abcdef", "INHIBIT 120", 2)), "OK 120");
+        let again = cmd(&sign(b"0123456789abcdef", "INHIBIT 60", 3));
+        assert!(again.starts_with("ERR All 1 inhibits"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_maintenance() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            timeout = 30
+            [inhibit]
+            startup = 0.001
+            [maintenance]
+            max = 600
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        pingee.register(poll.registry(), Token(0))?;
+        pingee.auth = Authenticator::with_key(b"0123456789abcdef");
+        std::thread::sleep(Duration::from_millis(5));
+        pingee.sync_state(None);
+
+        let signed = |text: &str, seq| sign(b"0123456789abcdef", text, seq);
+        let cmd = |pingee: &mut Pingee, processes: &mut _, text: &str, seq| {
+            command(pingee, &poll, processes, &signed(text, seq))
+        };
+        let reply = cmd(&mut pingee, &mut processes, "MAINTENANCE 0", 1);
+        assert_eq!(reply, "ERR Not in maintenance");
+        let reply = cmd(&mut pingee, &mut processes, "MAINTENANCE 601", 2);
+        assert_eq!(reply, "ERR Maintenance is at most 600 s");
+        send(&pingee, signed("MAINTENANCE 300", 3));
+        let action = pingee.on_ping(poll.registry(), &mut processes)?;
+        assert_eq!(action, Some(Action::Maintenance(Duration::from_secs(300))));
+        assert!(!pingee.armed());
+        assert_eq!(pingee.state(), State::Maintenance);
+        assert_eq!(pingee.on_timeout(Token(10))?, None);
+        let status = pingee.status()?;
+        assert_eq!(status.get("state.cause"), Some("MAINTENANCE 300"));
+        assert!(status.get("maintenance_remaining").is_some());
+        // Only ended by MAINTENANCE 0 or running out
+        let reply = cmd(&mut pingee, &mut processes, "ARM", 4);
+        assert!(reply.starts_with("ERR In maintenance"));
+        pingee.end_maintenance("Maintenance ran out")?;
+        assert!(pingee.armed());
+        assert_eq!(pingee.state(), State::Armed);
+        assert_eq!(pingee.status()?.get("maintenance_remaining"), None);
+        assert!(pingee.deadline()?.unwrap() > Duration::from_secs(29));
+
+        let mut pingee = Pingee::new(
+            &Config::parse("address = \"127.0.0.1:0\"")?,
+            Token(10),
+            Token(90),
+        )?;
+        assert!(pingee.on_maintenance(Duration::from_secs(60)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_ids() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            [inhibit]
+            enabled = true
+            max = 600
+            auth = false
+            "#,
+        )?;
+        let clock = VirtualClock::new();
+        let transport = Box::new(UdpSocket::bind(config.address)?);
+        let mut pingee = Pingee::with_clock(
+            &config,
+            Token(10),
+            Token(90),
+            transport,
+            Arc::new(clock.clone()),
+        )?;
+        let client = StdSocket::bind("127.0.0.1:0")?;
+        client.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let mut request = |pingee: &mut Pingee, text: &str| -> Result<String> {
+            client.send_to(text.as_bytes(), pingee.socket.local_addr()?)?;
+            std::thread::sleep(Duration::from_millis(10));
+            assert_eq!(pingee.on_ping(poll.registry(), &mut processes)?, None);
+            let mut buf = [0; 4096];
+            let len = client.recv(&mut buf)?;
+            Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+        };
+
+        assert_eq!(request(&mut pingee, "ID 7 INHIBIT 300")?, "ID 7 OK 300");
+        assert_eq!(request(&mut pingee, "INHIBIT 300")?, "OK 300");
+        // A resend isn't run again, a new id is
+        assert_eq!(request(&mut pingee, "ID 7 INHIBIT 300")?, "ID 7 OK 300");
+        assert_eq!(pingee.inhibits(), 2);
+        assert_eq!(request(&mut pingee, "ID 8 INHIBIT 300")?, "ID 8 OK 300");
+        assert_eq!(pingee.inhibits(), 3);
+        // Until it's been too long
+        clock.advance(REPLIED_FOR);
+        assert_eq!(request(&mut pingee, "ID 7 INHIBIT 300")?, "ID 7 OK 300");
+        assert_eq!(pingee.inhibits(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_named_inhibits() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            timeout = 10
+            [inhibit]
+            startup = 10
+            enabled = true
+            max = 600
+            auth = false
+            "#,
+        )?;
+        let clock = VirtualClock::new();
+        let transport = Box::new(UdpSocket::bind(config.address)?);
+        let mut pingee = Pingee::with_clock(
+            &config,
+            Token(10),
+            Token(90),
+            transport,
+            Arc::new(clock.clone()),
+        )?;
+        pingee.register(poll.registry(), Token(0))?;
+        clock.advance(Duration::from_secs(10));
+        let mut cmd =
+            |pingee: &mut Pingee, text: &str| command(pingee, &poll, &mut processes, text);
+
+        // Both count, the longer one going
+        assert_eq!(cmd(&mut pingee, "INHIBIT 100 deploy"), "OK 100");
+        assert_eq!(cmd(&mut pingee, "INHIBIT 30 update"), "OK 30");
+        assert_eq!(pingee.inhibit_remaining(), Duration::from_secs(100));
+        let status = pingee.status()?;
+        assert!(status
+            .get("inhibit.deploy.requester")
+            .unwrap()
+            .starts_with("127.0.0.1:"));
+        assert!(status.get("inhibit.update.expires").is_some());
+        assert_eq!(status.get("state"), Some("inhibited"));
+
+        assert_eq!(cmd(&mut pingee, "UNINHIBIT deploy"), "OK");
+        assert!(cmd(&mut pingee, "UNINHIBIT deploy").starts_with("ERR No inhibit"));
+        assert_eq!(pingee.inhibit_remaining(), Duration::from_secs(30));
+        assert_eq!(
+            pingee.iter().next().unwrap().remaining()?,
+            Duration::from_secs(30)
+        );
+        assert!(pingee.status()?.get("inhibit.deploy.requester").is_none());
+
+        // The same name cuts its own short, down to a full timeout
+        assert_eq!(cmd(&mut pingee, "INHIBIT 5 update"), "OK 5");
+        assert_eq!(
+            pingee.iter().next().unwrap().remaining()?,
+            Duration::from_secs(10)
+        );
+        clock.advance(Duration::from_secs(5));
+        pingee.sync_state(None);
+        assert_eq!(pingee.state(), State::Armed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_permissions() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3-main"
+            timeout = 30
+            [auth.permissions]
+            c3-main = ["PING"]
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        pingee.register(poll.registry(), Token(0))?;
+
+        let mut cmd = |text: &str| command(&mut pingee, &poll, &mut processes, text);
+        assert_eq!(cmd("EXTEND c3-main 10"), "ERR c3-main may not EXTEND");
+        assert!(cmd("STATUS").starts_with("state="));
+        assert!(Config::parse("[auth.permissions]\nops = [\"REBOOT\"]").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebind() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let config = Config::parse("address = \"127.0.0.1:0\"\n[[client]]\nname = \"c3-main\"")?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        pingee.register(poll.registry(), Token(0))?;
+        let address = pingee.socket.local_addr()?;
+
+        pingee.rebind(poll.registry())?;
+        assert_eq!(pingee.socket.local_addr()?, address);
+        let status = command(&mut pingee, &poll, &mut processes, "STATUS");
+        assert!(status.contains("socket.rebinds="));
+
+        // An address that's taken is retried until it's free
+        let blocker = StdSocket::bind("127.0.0.1:0")?;
+        let taken = blocker.local_addr()?;
+        pingee.rebind.as_mut().unwrap().address = taken;
+        pingee.rebind(poll.registry())?;
+        assert!(pingee.on_ping(poll.registry(), &mut processes)?.is_none());
+        pingee.recover(poll.registry())?;
+        assert_ne!(pingee.socket.local_addr()?, taken);
+        drop(blocker);
+        pingee.recover(poll.registry())?;
+        assert_eq!(pingee.socket.local_addr()?, taken);
+        assert!(command(&mut pingee, &poll, &mut processes, "STATUS").contains("state="));
+        Ok(())
+    }
+
+    #[test]
+    fn test_drain_budget() -> Result<()> {
+        let mut poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            recv_buffer = 65536
+            drain_budget = 2
+            [[client]]
+            name = "c3"
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        pingee.register(poll.registry(), Token(0))?;
+        let client = StdSocket::bind("127.0.0.1:0")?;
+        for _ in 0..3 {
+            client.send_to(b"PING c3", pingee.socket.local_addr()?)?;
+        }
+        let mut events = Events::with_capacity(8);
+        let mut readable = |poll: &mut Poll, timeout| -> Result<bool> {
+            poll.poll(&mut events, Some(timeout))?;
+            Ok(events.iter().any(|e| e.token() == Token(0)))
+        };
+        assert!(readable(&mut poll, Duration::from_secs(1))?);
+        pingee.on_ping(poll.registry(), &mut processes)?;
+        // Still readable for the one left over
+        assert!(readable(&mut poll, Duration::from_secs(1))?);
+        pingee.on_ping(poll.registry(), &mut processes)?;
+        assert!(!readable(&mut poll, Duration::from_millis(10))?);
+
+        let status = pingee.status()?;
+        // Only Linux reports the kernel's drops
+        #[cfg(target_os = "linux")]
+        assert_eq!(status.get("socket.drops"), Some("0"));
+        assert!(status.get("socket.drain_cuts").is_some_and(|n| n != "0"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_activated() -> Result<()> {
+        let main = StdSocket::bind("127.0.0.1:0")?;
+        let port = StdSocket::bind("127.0.0.1:0")?;
+        let config = Config::parse(&format!(
+            "address = \"{}\"\n[[port]]\nname = \"camera\"\nport = {}",
+            main.local_addr()?,
+            port.local_addr()?.port()
+        ))?;
+        let sockets = || -> Result<Vec<OwnedFd>> {
+            Ok(vec![main.try_clone()?.into(), port.try_clone()?.into()])
+        };
+        let pingee = Pingee::activated(&config, Token(10), Token(90), sockets()?)?;
+        assert_eq!(pingee.socket.local_addr()?, main.local_addr()?);
+
+        // A unit that drifted from the config
+        let mut swapped = sockets()?;
+        swapped.reverse();
+        assert!(Pingee::activated(&config, Token(10), Token(90), swapped).is_err());
+        let missing = sockets()?.into_iter().take(1).collect();
+        assert!(Pingee::activated(&config, Token(10), Token(90), missing).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_degraded() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let path = std::env::temp_dir().join(format!("watchdog-degraded-{}", std::process::id()));
+        let key = path.with_extension("key");
+        std::fs::write(&key, "0123456789abcdef\n")?;
+        let text = format!("armed = \"yes\"\n[auth]\nkey_file = {:?}\n", key);
+        std::fs::write(&path, text)?;
+        let mut config = Config::load_or_safe(&path, true);
+        assert!(config.degraded.is_some());
+        config.address = "127.0.0.1:0".parse()?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(&key)?;
+        pingee.register(poll.registry(), Token(0))?;
+        assert!(!pingee.armed());
+
+        let arm = sign(b"0123456789abcdef", "ARM", 1);
+        assert_eq!(command(&mut pingee, &poll, &mut processes, &arm), "OK");
+        assert!(pingee.armed());
+        Ok(())
+    }
+
+    #[test]
+    fn test_drain() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let config = Config::parse("address = \"127.0.0.1:0\"")?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        pingee.register(poll.registry(), Token(0))?;
+
+        assert!(command(&mut pingee, &poll, &mut processes, "TAKEOVER").starts_with("ERR"));
+        pingee.drain();
+        assert_eq!(
+            command(&mut pingee, &poll, &mut processes, "STATUS"),
+            "ERR Draining"
+        );
+        let client = send(&pingee, "TAKEOVER");
+        let action = pingee.on_ping(poll.registry(), &mut processes)?;
+        assert_eq!(action, Some(Action::Takeover));
+        let mut buf = [0; 64];
+        let len = client.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"OK");
+
+        // With a key, even a local one has to be signed
+        pingee.auth = Authenticator::with_key(b"0123456789abcdef");
+        let client = send(&pingee, "TAKEOVER");
+        assert_eq!(pingee.on_ping(poll.registry(), &mut processes)?, None);
+        let len = client.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"ERR Not authenticated");
+        send(&pingee, sign(b"0123456789abcdef", "TAKEOVER", 1));
+        let action = pingee.on_ping(poll.registry(), &mut processes)?;
+        assert_eq!(action, Some(Action::Takeover));
+        Ok(())
+    }
+
+    #[test]
+    fn test_framed() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        pingee.register(poll.registry(), Token(0))?;
+
+        let client = send(&pingee, framing::encode(Kind::Command, b"STATUS")?);
+        assert_eq!(pingee.on_ping(poll.registry(), &mut processes)?, None);
+        let mut buf = [0; 4096];
+        let len = client.recv(&mut buf)?;
+        let reply = framing::decode(&buf[..len])?;
+        assert_eq!(reply.kind, Kind::Reply);
+        assert!(reply.payload.starts_with(b"state=startup_inhibit\n"));
+
+        // Dropped without a reply, and counted
+        let mut newer = framing::encode(Kind::Command, b"STATUS")?;
+        newer[0] = framing::VERSION + 1;
+        let client = send(&pingee, newer);
+        assert_eq!(pingee.on_ping(poll.registry(), &mut processes)?, None);
+        client.set_nonblocking(true)?;
+        assert!(client.recv(&mut buf).is_err());
+        let status = pingee.status()?;
+        assert!(status
+            .get("protocol.unknown_versions")
+            .is_some_and(|n| n != "0"));
+
+        // Plain text still works
+        let reply = command(&mut pingee, &poll, &mut processes, "STATUS");
+        assert!(reply.starts_with("state=startup_inhibit\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_second_transport() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        let second = UdpSocket::bind(config.address)?;
+        let address = second.local_addr()?;
+        pingee.add_transport(Box::new(second));
+        pingee.register(poll.registry(), Token(0))?;
+
+        let client = StdSocket::bind("127.0.0.1:0")?;
+        client.send_to(b"PING c3", address)?;
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(pingee.on_ping(poll.registry(), &mut processes)?, None);
+        assert_eq!(
+            pingee.last_ping().map(|(_, s)| s),
+            Some(client.local_addr()?)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_interlock() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("watchdog-interlock-{}", std::process::id()));
+        let config = Config::parse(&format!(
+            r#"
+            address = "127.0.0.1:0"
+            [interlock]
+            path = {:?}
+            window = 0.05
+            [inhibit]
+            startup = 0.001
+            "#,
+            path
+        ))?;
+        assert!(Pingee::new(&config, Token(10), Token(90))?.armed());
+
+        std::fs::write(&path, "")?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        assert!(!pingee.armed());
+        assert!(pingee.status()?.get("interlock_remaining").is_some());
+        pingee.check_interlock()?;
+        assert!(!pingee.armed());
+        std::thread::sleep(Duration::from_millis(60));
+        pingee.check_interlock()?;
+        assert!(pingee.armed());
+        assert!(!path.exists());
+
+        // An ARM ends it early
+        std::fs::write(&path, "")?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        pingee.arm(true, "test")?;
+        assert!(pingee.armed());
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ack() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            timeout = 10
+            [[client]]
+            name = "edl"
+            ack = true
+            [inhibit]
+            startup = 0.001
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        pingee.register(poll.registry(), Token(0))?;
+        std::thread::sleep(Duration::from_millis(10));
+        pingee.feed(Some("edl"))?;
+
+        let reply = command(&mut pingee, &poll, &mut processes, "PING c3 ACK 42");
+        let (seq, margin) = reply.strip_prefix("ACK ").unwrap().split_once(' ').unwrap();
+        assert_eq!(seq, "42");
+        assert!((1..=10_000).contains(&margin.parse::<u64>()?));
+        let reply = command(&mut pingee, &poll, &mut processes, "PING edl");
+        assert!(reply.starts_with("ACK - "));
+        // Only when asked for
+        let client = send(&pingee, "PING c3");
+        assert_eq!(pingee.on_ping(poll.registry(), &mut processes)?, None);
+        client.set_read_timeout(Some(Duration::from_millis(50)))?;
+        assert!(client.recv(&mut [0; 64]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            [legacy]
+            enabled = true
+            client = "c3"
+            [inhibit]
+            startup = 0.001
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        pingee.register(poll.registry(), Token(0))?;
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(pingee.starving()?);
+
+        send(&pingee, b"");
+        assert_eq!(pingee.on_ping(poll.registry(), &mut processes)?, None);
+        assert!(!pingee.starving()?);
+        let status = pingee.status()?;
+        assert!(status
+            .get("protocol.legacy_pings")
+            .is_some_and(|n| n != "0"));
+        assert!(command(&mut pingee, &poll, &mut processes, "STATUS").starts_with("state=armed"));
+        pingee.sync_state(Some("c3 client stopped pinging"));
+        assert_eq!(pingee.state(), State::DeathRow);
+        pingee.sync_state(None);
+        assert_eq!(pingee.state(), State::Armed);
+        assert_eq!(pingee.status()?.get("state.cause"), Some("Reprieved"));
+
+        assert!(Config::parse("[legacy]\nenabled = true\nclient = \"c3\"").is_err());
+        let unfed = "[[client]]\nname = \"c3\"\n[legacy]\nenabled = true";
+        assert!(Config::parse(unfed).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_capture() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let path = std::env::temp_dir().join(format!("watchdog-pings-{}", std::process::id()));
+        let config = Config::parse(&format!(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            [capture]
+            path = {:?}
+            snaplen = 6
+            "#,
+            path
+        ))?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        for packet in ["PING c3", "PING edl", "\x00garbage"] {
+            send(&pingee, packet);
+            pingee.on_ping(poll.registry(), &mut processes)?;
+        }
+        command(&mut pingee, &poll, &mut processes, "STATUS");
+        let records = crate::capture::read(&path)?;
+        let verdicts: Vec<_> = records.iter().map(|r| r.verdict).collect();
+        assert_eq!(
+            verdicts,
+            [
+                Verdict::Fed,
+                Verdict::Unknown,
+                Verdict::Malformed,
+                Verdict::Command
+            ]
+        );
+        assert_eq!((records[1].len, &records[1].data[..]), (8, &b"PING e"[..]));
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_virtual_clock() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3-main"
+            [extension]
+            enabled = true
+            max = 120
+            "#,
+        )?;
+        let clock = VirtualClock::new();
+        let socket = UdpSocket::bind(config.address)?;
+        let transport = Box::new(socket);
+        let mut pingee = Pingee::with_clock(
+            &config,
+            Token(10),
+            Token(90),
+            transport,
+            Arc::new(clock.clone()),
+        )?;
+        let step = Duration::from_millis(1);
+
+        // Nothing has to ping during the startup inhibit
+        clock.advance(INHIBIT - step);
+        assert!(!pingee.starving()?);
+        clock.advance(step);
+        assert!(pingee.starving()?);
+
+        // Then exactly the timeout after each ping
+        pingee.feed(Some("c3-main"))?;
+        assert!(!pingee.starving()?);
+        clock.advance(PING_TIMEOUT - step);
+        pingee.feed(Some("c3-main"))?;
+        clock.advance(PING_TIMEOUT - step);
+        assert!(!pingee.starving()?);
+        clock.advance(step);
+        assert!(pingee.starving()?);
+
+        // Pings don't cut an extension short, but count again once it's over
+        pingee.on_extend("c3-main", Duration::from_secs(90))?;
+        clock.advance(Duration::from_secs(80));
+        pingee.feed(Some("c3-main"))?;
+        clock.advance(Duration::from_secs(10) - step);
+        assert!(!pingee.starving()?);
+        clock.advance(step);
+        assert!(pingee.starving()?);
+        pingee.feed(Some("c3-main"))?;
+        clock.advance(PING_TIMEOUT);
+        assert!(pingee.starving()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_strikes() -> Result<()> {
+        let strikes = |strikes: &str| -> Result<(Pingee, VirtualClock)> {
+            let config = Config::parse(&format!(
+                "address = \"127.0.0.1:0\"\n[[client]]\nname = \"c3-main\"\n[strikes]\n{}",
+                strikes
+            ))?;
+            let clock = VirtualClock::new();
+            let transport = Box::new(UdpSocket::bind(config.address)?);
+            let pingee = Pingee::with_clock(
+                &config,
+                Token(10),
+                Token(90),
+                transport,
+                Arc::new(clock.clone()),
+            )?;
+            clock.advance(INHIBIT);
+            Ok((pingee, clock))
+        };
+
+        // In a row, a ping in between starts the count over
+        let (mut pingee, clock) = strikes("max = 2")?;
+        assert_eq!(pingee.on_timeout(Token(10))?, None);
+        assert!(!pingee.starving()?);
+        pingee.feed(Some("c3-main"))?;
+        clock.advance(PING_TIMEOUT);
+        assert_eq!(pingee.on_timeout(Token(10))?, None);
+        let status = pingee.status()?;
+        assert_eq!(status.get("client.c3-main.strikes"), Some("1"));
+        clock.advance(PING_TIMEOUT);
+        assert!(pingee.on_timeout(Token(10))?.is_some());
+
+        // Within the window, pings don't
+        let (mut pingee, clock) = strikes("max = 2\nwindow = 100")?;
+        assert_eq!(pingee.on_timeout(Token(10))?, None);
+        pingee.feed(Some("c3-main"))?;
+        clock.advance(PING_TIMEOUT);
+        assert!(pingee.on_timeout(Token(10))?.is_some());
+        // Unless the earlier miss slid out of it
+        let (mut pingee, clock) = strikes("max = 2\nwindow = 10")?;
+        assert_eq!(pingee.on_timeout(Token(10))?, None);
+        clock.advance(PING_TIMEOUT);
+        assert_eq!(pingee.on_timeout(Token(10))?, None);
+
+        // The default is strict
+        let (mut pingee, _) = strikes("")?;
+        assert!(pingee.on_timeout(Token(10))?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_health() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            health_window = 100
+            [[client]]
+            name = "c3"
+            timeout = 10
+            [inhibit]
+            startup = 10
+            [strikes]
+            max = 5
+            "#,
+        )?;
+        let clock = VirtualClock::new();
+        let transport = Box::new(UdpSocket::bind(config.address)?);
+        let mut pingee = Pingee::with_clock(
+            &config,
+            Token(10),
+            Token(90),
+            transport,
+            Arc::new(clock.clone()),
+        )?;
+        assert_eq!(pingee.health().grade, 1.0);
+
+        // One of five windows missed
+        clock.advance(Duration::from_secs(50));
+        assert_eq!(pingee.on_timeout(Token(10))?, None);
+        clock.advance(Duration::from_secs(4));
+        pingee.feed(Some("c3"))?;
+        let health = pingee.health();
+        assert!((health.grade - 0.815).abs() < 0.001);
+        assert_eq!(health.worst_margin, Some(Duration::from_secs(6)));
+        assert_eq!(pingee.status()?.get("health.worst_margin"), Some("6.000"));
+
+        // Both slid out of the window
+        clock.advance(Duration::from_secs(200));
+        let status = pingee.status()?;
+        assert_eq!(status.get("health"), Some("1.000"));
+        assert_eq!(status.get("health.worst_margin"), Some("-"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cadence() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            timeout = 10
+            [inhibit]
+            startup = 10
+            "#,
+        )?;
+        let clock = VirtualClock::new();
+        let transport = Box::new(UdpSocket::bind(config.address)?);
+        let mut pingee = Pingee::with_clock(
+            &config,
+            Token(10),
+            Token(90),
+            transport,
+            Arc::new(clock.clone()),
+        )?;
+        pingee.register(poll.registry(), Token(0))?;
+        clock.advance(Duration::from_secs(10));
+        send(&pingee, "PING c3 EVERY 0.1");
+        assert_eq!(pingee.on_ping(poll.registry(), &mut processes)?, None);
+        assert_eq!(pingee.status()?.get("client.c3.every"), Some("0.1"));
+        let remaining = |pingee: &Pingee| pingee.clients[0].as_ref().unwrap().remaining();
+
+        // Within a twentieth of the timeout the timer is left alone
+        clock.advance(Duration::from_millis(400));
+        pingee.feed(Some("c3"))?;
+        assert_eq!(remaining(&pingee)?, Duration::from_millis(9600));
+        clock.advance(Duration::from_millis(200));
+        pingee.feed(Some("c3"))?;
+        assert_eq!(remaining(&pingee)?, Duration::from_secs(10));
+
+        send(&pingee, "PING c3 EVERY 0");
+        assert_eq!(pingee.on_ping(poll.registry(), &mut processes)?, None);
+        assert_eq!(pingee.status()?.get("client.c3.every"), None);
+        clock.advance(Duration::from_millis(100));
+        pingee.feed(Some("c3"))?;
+        assert_eq!(remaining(&pingee)?, Duration::from_secs(10));
+        Ok(())
+    }
+
+    #[test]
+    fn test_policy() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            policy = "c3 OR maintenance"
+            [[client]]
+            name = "c3"
+            [[client]]
+            name = "maintenance"
+            [[client]]
+            name = "edl"
+            "#,
+        )?;
+        let clock = VirtualClock::new();
+        let transport = Box::new(UdpSocket::bind(config.address)?);
+        let mut pingee = Pingee::with_clock(
+            &config,
+            Token(10),
+            Token(90),
+            transport,
+            Arc::new(clock.clone()),
+        )?;
+        clock.advance(INHIBIT);
+
+        pingee.feed(Some("edl"))?;
+
+        // Either will do
+        assert_eq!(pingee.on_timeout(Token(10))?, None);
+        assert!(!pingee.starving()?);
+        assert_eq!(pingee.status()?.get("policy.holds"), Some("true"));
+        assert!(pingee.on_timeout(Token(11))?.is_some());
+        assert_eq!(pingee.starved()?, Some("c3"));
+        // Until one pings again
+        pingee.feed(Some("maintenance"))?;
+        assert_eq!(pingee.starved()?, None);
+        // Not named by the policy, so still required
+        clock.advance(PING_TIMEOUT);
+        assert_eq!(pingee.starved()?, Some("edl"));
+        assert!(pingee.on_timeout(Token(12))?.is_some());
+        assert!(Config::parse("policy = \"c3\"\n[[client]]\nname = \"edl\"").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_criticality() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            [[client]]
+            name = "camera"
+            criticality = "advisory"
+            "#,
+        )?;
+        let clock = VirtualClock::new();
+        let transport = Box::new(UdpSocket::bind(config.address)?);
+        let mut pingee = Pingee::with_clock(
+            &config,
+            Token(10),
+            Token(90),
+            transport,
+            Arc::new(clock.clone()),
+        )?;
+        clock.advance(INHIBIT);
+        pingee.feed(Some("c3"))?;
+
+        // Only counted against the health
+        assert_eq!(pingee.on_timeout(Token(11))?, None);
+        assert_eq!(pingee.starved()?, None);
+        assert_eq!(pingee.countdown()?.unwrap().0, "c3");
+        assert!(pingee.health().grade < 1.0);
+        let status = pingee.status()?;
+        assert_eq!(status.get("client.camera.criticality"), Some("advisory"));
+
+        // Made critical, with a full window
+        pingee.set_criticality("camera", true)?;
+        assert_eq!(pingee.starved()?, None);
+        clock.advance(PING_TIMEOUT);
+        assert_eq!(pingee.starved()?, Some("c3"));
+        pingee.set_criticality("c3", false)?;
+        assert_eq!(pingee.starved()?, Some("camera"));
+        assert!(pingee.on_timeout(Token(11))?.is_some());
+        assert!(pingee.set_criticality("edl", true).is_err());
+        Ok(())
+    }
+}
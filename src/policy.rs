@@ -0,0 +1,229 @@
+use anyhow::{bail, ensure, Context, Result};
+use std::{fmt, iter::Peekable, str::FromStr};
+
+/// Which clients have to be alive for the card to be. Without one every client has to be, a
+/// policy can instead express e.g. `(c3 AND can-heartbeat) OR maintenance`:
+///
+/// ```text
+/// policy := any
+/// any    := all ("OR" all)*
+/// all    := term ("AND" term)*
+/// term   := <client> | "(" any ")" | <n> "OF" "(" any ("," any)* ")"
+/// ```
+///
+/// Keywords are case insensitive. Any client or port channel can be named.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Policy {
+    Client(String),
+    All(Vec<Policy>),
+    Any(Vec<Policy>),
+    AtLeast(usize, Vec<Policy>),
+}
+
+impl Policy {
+    /// Whether it's satisfied with the clients `alive` says are
+    pub fn holds(&self, alive: &impl Fn(&str) -> bool) -> bool {
+        match self {
+            Policy::Client(name) => alive(name),
+            Policy::All(terms) => terms.iter().all(|t| t.holds(alive)),
+            Policy::Any(terms) => terms.iter().any(|t| t.holds(alive)),
+            Policy::AtLeast(n, terms) => terms.iter().filter(|t| t.holds(alive)).count() >= *n,
+        }
+    }
+
+    /// Every client it names
+    pub fn clients(&self) -> Vec<&str> {
+        match self {
+            Policy::Client(name) => vec![name.as_str()],
+            Policy::All(terms) | Policy::Any(terms) | Policy::AtLeast(_, terms) => {
+                terms.iter().flat_map(Policy::clients).collect()
+            }
+        }
+    }
+}
+
+impl FromStr for Policy {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let mut tokens = tokenize(text).peekable();
+        let policy = any(&mut tokens).with_context(|| format!("Invalid policy {:?}", text))?;
+        if let Some(token) = tokens.next() {
+            bail!("Invalid policy {:?}: unexpected {:?}", text, token);
+        }
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter<'_>, terms: &[Policy], separator: &str| {
+            for (i, term) in terms.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(separator)?;
+                }
+                match term {
+                    Policy::All(_) | Policy::Any(_) => write!(f, "({})", term)?,
+                    _ => write!(f, "{}", term)?,
+                }
+            }
+            Ok(())
+        };
+        match self {
+            Policy::Client(name) => f.write_str(name),
+            Policy::All(terms) => join(f, terms, " AND "),
+            Policy::Any(terms) => join(f, terms, " OR "),
+            Policy::AtLeast(n, terms) => {
+                write!(f, "{} OF (", n)?;
+                join(f, terms, ", ")?;
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+type Tokens<'a> = Peekable<Box<dyn Iterator<Item = &'a str> + 'a>>;
+
+/// Words, and the parentheses and commas between them
+fn tokenize(text: &str) -> Box<dyn Iterator<Item = &str> + '_> {
+    Box::new(text.split_whitespace().flat_map(|word| {
+        let mut tokens = Vec::new();
+        let mut start = 0;
+        for (i, c) in word.char_indices() {
+            if matches!(c, '(' | ')' | ',') {
+                tokens.extend((start < i).then(|| &word[start..i]));
+                tokens.push(&word[i..i + 1]);
+                start = i + 1;
+            }
+        }
+        tokens.extend((start < word.len()).then(|| &word[start..]));
+        tokens
+    }))
+}
+
+fn keyword(tokens: &mut Tokens, keyword: &str) -> bool {
+    let matched = tokens
+        .peek()
+        .is_some_and(|t| t.eq_ignore_ascii_case(keyword));
+    if matched {
+        tokens.next();
+    }
+    matched
+}
+
+fn any(tokens: &mut Tokens) -> Result<Policy> {
+    let mut terms = vec![all(tokens)?];
+    while keyword(tokens, "OR") {
+        terms.push(all(tokens)?);
+    }
+    Ok(match terms.len() {
+        1 => terms.remove(0),
+        _ => Policy::Any(terms),
+    })
+}
+
+fn all(tokens: &mut Tokens) -> Result<Policy> {
+    let mut terms = vec![term(tokens)?];
+    while keyword(tokens, "AND") {
+        terms.push(term(tokens)?);
+    }
+    Ok(match terms.len() {
+        1 => terms.remove(0),
+        _ => Policy::All(terms),
+    })
+}
+
+fn term(tokens: &mut Tokens) -> Result<Policy> {
+    let token = tokens.next().context("Missing a client")?;
+    if token == "(" {
+        let policy = any(tokens)?;
+        ensure!(keyword(tokens, ")"), "Missing )");
+        return Ok(policy);
+    }
+    ensure!(
+        !matches!(token, ")" | ",")
+            && !["AND", "OR", "OF"]
+                .iter()
+                .any(|k| token.eq_ignore_ascii_case(k)),
+        "Unexpected {:?}",
+        token
+    );
+    if !keyword(tokens, "OF") {
+        return Ok(Policy::Client(token.to_string()));
+    }
+    let n: usize = token
+        .parse()
+        .with_context(|| format!("Invalid count {:?}", token))?;
+    ensure!(keyword(tokens, "("), "Missing ( after OF");
+    let mut terms = vec![any(tokens)?];
+    while keyword(tokens, ",") {
+        terms.push(any(tokens)?);
+    }
+    ensure!(keyword(tokens, ")"), "Missing )");
+    ensure!(
+        (1..=terms.len()).contains(&n),
+        "{} OF {} can never hold",
+        n,
+        terms.len()
+    );
+    Ok(Policy::AtLeast(n, terms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(name: &str) -> Policy {
+        Policy::Client(name.to_string())
+    }
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let policy: Policy = "(c3 AND can-heartbeat) or maintenance".parse()?;
+        assert_eq!(
+            policy,
+            Policy::Any(vec![
+                Policy::All(vec![client("c3"), client("can-heartbeat")]),
+                client("maintenance"),
+            ])
+        );
+        assert_eq!(policy.to_string(), "(c3 AND can-heartbeat) OR maintenance");
+        assert_eq!(policy.clients(), ["c3", "can-heartbeat", "maintenance"]);
+
+        // AND binds tighter
+        let policy: Policy = "a OR b AND c".parse()?;
+        assert_eq!(
+            policy,
+            Policy::Any(vec![
+                client("a"),
+                Policy::All(vec![client("b"), client("c")])
+            ])
+        );
+        let policy: Policy = "2 OF(a, b,c) AND d".parse()?;
+        assert_eq!(policy.to_string(), "2 OF (a, b, c) AND d");
+
+        for invalid in [
+            "",
+            "a AND",
+            "(a OR b",
+            "a b",
+            "4 OF (a, b)",
+            "x OF (a)",
+            "OR a",
+        ] {
+            assert!(invalid.parse::<Policy>().is_err(), "{:?}", invalid);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_holds() -> Result<()> {
+        let policy: Policy = "(c3 AND can-heartbeat) OR 2 OF (a, b, c)".parse()?;
+        let holds = |alive: &[&str]| policy.holds(&|name| alive.contains(&name));
+        assert!(holds(&["c3", "can-heartbeat"]));
+        assert!(!holds(&["c3", "a"]));
+        assert!(holds(&["b", "c"]));
+        assert!(!holds(&[]));
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+use crate::{
+    can::{CanSocket, Frame},
+    config::{LowPowerConfig, PetStep},
+    petter::Petter,
+    pingee::Pingee,
+};
+use anyhow::Result;
+use log::info;
+use mio::{unix::SourceFd, Interest, Registry, Token};
+use std::{os::fd::AsRawFd, time::Duration};
+
+/// Pets through a slower waveform and gives the clients longer while the EPS reports critical
+/// power, so that the card wakes up less. The EPS reports it over CAN, the ground can also switch
+/// with POWER. Either one saying power is back to normal reverts both.
+pub struct LowPower {
+    socket: Option<CanSocket>,
+    mode: Mode,
+}
+
+impl LowPower {
+    /// Starts out normal, petting through `normal`
+    pub fn new(config: &LowPowerConfig, normal: &[PetStep]) -> Result<Self> {
+        let socket = match &config.can {
+            Some(can) => {
+                let socket = CanSocket::open(&can.interface)?;
+                socket.filter(&[can.id])?;
+                Some(socket)
+            }
+            None => None,
+        };
+        Ok(Self {
+            socket,
+            mode: Mode::new(config, normal),
+        })
+    }
+
+    pub fn register(&self, registry: &Registry, token: Token) -> Result<()> {
+        if let Some(socket) = &self.socket {
+            let fd = socket.as_raw_fd();
+            registry.register(&mut SourceFd(&fd), token, Interest::READABLE)?;
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, low: bool, petter: &mut Petter, pingee: &mut Pingee) -> Result<()> {
+        self.mode.set(low, petter, pingee)
+    }
+
+    /// Changes what's reverted to, the pattern and the timeouts by slot, switching to it right
+    /// away unless power is critical
+    pub fn set_normal(
+        &mut self,
+        pattern: &[PetStep],
+        timeouts: &[(usize, Duration)],
+        petter: &mut Petter,
+        pingee: &mut Pingee,
+    ) -> Result<()> {
+        self.mode.set_normal(pattern, timeouts, petter, pingee)
+    }
+
+    pub fn on_readable(&mut self, petter: &mut Petter, pingee: &mut Pingee) -> Result<()> {
+        let Some(socket) = &self.socket else {
+            return Ok(());
+        };
+        while let Some(frame) = socket.recv()? {
+            self.mode.on_frame(&frame, petter, pingee)?;
+        }
+        Ok(())
+    }
+}
+
+/// The switching, apart from the socket
+struct Mode {
+    pattern: Vec<PetStep>,
+    normal: Vec<PetStep>,
+    timeout_scale: f64,
+    low: bool,
+    /// Each client slot's timeout from before stretching
+    saved: Vec<Option<Duration>>,
+}
+
+impl Mode {
+    fn new(config: &LowPowerConfig, normal: &[PetStep]) -> Self {
+        Self {
+            pattern: config.pattern.clone(),
+            normal: normal.to_vec(),
+            timeout_scale: config.timeout_scale,
+            low: false,
+            saved: Vec::new(),
+        }
+    }
+
+    /// Frames without a payload say nothing
+    fn on_frame(&mut self, frame: &Frame, petter: &mut Petter, pingee: &mut Pingee) -> Result<()> {
+        match frame.payload() {
+            &[mode, ..] => self.set(mode != 0, petter, pingee),
+            [] => Ok(()),
+        }
+    }
+
+    fn set(&mut self, low: bool, petter: &mut Petter, pingee: &mut Pingee) -> Result<()> {
+        if low == self.low {
+            return Ok(());
+        }
+        let scale = self.timeout_scale;
+        if low {
+            info!("Power is critical, switching to the low power pet pattern");
+            self.saved = (0..pingee.slots())
+                .map(|slot| pingee.timeout(slot))
+                .collect();
+            for (slot, timeout) in self.saved.iter().enumerate() {
+                if let Some(timeout) = timeout {
+                    pingee.set_timeout(slot, timeout.mul_f64(scale))?;
+                }
+            }
+            petter.switch_pattern(&self.pattern);
+        } else {
+            info!("Power recovered, switching back to the pet pattern");
+            // Unless it was changed since, or the slot is someone else's now
+            for (slot, saved) in self.saved.drain(..).enumerate() {
+                if let Some(saved) = saved {
+                    if pingee.timeout(slot) == Some(saved.mul_f64(scale)) {
+                        pingee.set_timeout(slot, saved)?;
+                    }
+                }
+            }
+            petter.switch_pattern(&self.normal);
+        }
+        self.low = low;
+        pingee.set_low_power(low);
+        Ok(())
+    }
+
+    fn set_normal(
+        &mut self,
+        pattern: &[PetStep],
+        timeouts: &[(usize, Duration)],
+        petter: &mut Petter,
+        pingee: &mut Pingee,
+    ) -> Result<()> {
+        self.normal = pattern.to_vec();
+        if !self.low {
+            petter.switch_pattern(pattern);
+            for &(slot, timeout) in timeouts {
+                pingee.set_timeout(slot, timeout)?;
+            }
+            return Ok(());
+        }
+        // Stretched like the ones it replaces, and restored once power recovers
+        for &(slot, timeout) in timeouts {
+            if let Some(saved @ Some(_)) = self.saved.get_mut(slot) {
+                *saved = Some(timeout);
+                pingee.set_timeout(slot, timeout.mul_f64(self.timeout_scale))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, petter::Mock};
+
+    #[test]
+    fn test_mode() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            timeout = 30
+            [low_power]
+            pattern = [{ high = true, duration = 0.1 }, { high = false, duration = 2.9 }]
+            timeout_scale = 3
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        let mock = Mock::default();
+        let mut petter = Petter::with_backend(Box::new(mock.clone()))?;
+        let mut mode = Mode::new(config.low_power.as_ref().unwrap(), &config.pet.pattern);
+
+        // Mid-cycle, which is finished the usual way first
+        petter.pet()?;
+        mode.on_frame(&Frame::new(0x1a0, &[0x01])?, &mut petter, &mut pingee)?;
+        assert_eq!(pingee.timeout(0), Some(Duration::from_secs(90)));
+        assert_eq!(petter.period(), Duration::from_secs(1));
+        petter.pet()?;
+        petter.pet()?;
+        assert_eq!(petter.period(), Duration::from_secs(3));
+        assert_eq!(mock.values(), [true, false, true]);
+        assert_eq!(pingee.status()?.get("power"), Some("low"));
+
+        // Still critical, and one that says nothing
+        mode.on_frame(&Frame::new(0x1a0, &[0x02])?, &mut petter, &mut pingee)?;
+        mode.on_frame(&Frame::new(0x1a0, &[])?, &mut petter, &mut pingee)?;
+        assert!(mode.low);
+        mode.on_frame(&Frame::new(0x1a0, &[0x00])?, &mut petter, &mut pingee)?;
+        assert_eq!(pingee.timeout(0), Some(Duration::from_secs(30)));
+        petter.pet()?;
+        petter.pet()?;
+        assert_eq!(petter.period(), Duration::from_secs(1));
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+use crate::config::PrivilegesConfig;
+use anyhow::{Context, Result};
+use log::info;
+use nix::unistd::{self, Group, Uid, User};
+
+/// Switches to the configured user and group for good. Everything that needs root, the GPIO chip,
+/// the sockets, and the scheduling, has to be set up before, the open fds are kept.
+pub fn drop(config: &PrivilegesConfig) -> Result<()> {
+    let Some(name) = &config.user else {
+        return Ok(());
+    };
+    let user = User::from_name(name)?.with_context(|| format!("No user {:?}", name))?;
+    let gid = match &config.group {
+        Some(name) => {
+            Group::from_name(name)?
+                .with_context(|| format!("No group {:?}", name))?
+                .gid
+        }
+        None => user.gid,
+    };
+    // The supplementary groups have to go first, it takes root to change them
+    unistd::setgroups(&[gid]).context("Failed to set groups")?;
+    unistd::setgid(gid).context("Failed to set group")?;
+    unistd::setuid(user.uid).context("Failed to set user")?;
+    anyhow::ensure!(
+        user.uid.is_root() || unistd::setuid(Uid::from_raw(0)).is_err(),
+        "Still able to regain root"
+    );
+    info!("Running as {} ({}:{})", name, user.uid, gid);
+    Ok(())
+}
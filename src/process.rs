@@ -0,0 +1,160 @@
+use crate::config::ProcessConfig;
+use anyhow::{bail, Context, Result};
+use log::info;
+use mio::{unix::SourceFd, Interest, Registry, Token};
+use std::{
+    fs,
+    os::fd::{AsRawFd, OwnedFd},
+};
+#[cfg(target_os = "linux")]
+use std::{io::Error, os::fd::FromRawFd};
+
+struct Process {
+    name: String,
+    pid: i32,
+    pidfd: OwnedFd,
+}
+
+// Enough for every client to hand over a process, plus the configured ones
+const MAX_PROCESSES: usize = 2 * crate::config::MAX_CLIENTS;
+
+/// Watches processes through pidfds, which become readable when the process exits.
+///
+/// Each process gets its own token, counting up from `first`.
+pub struct ProcessMonitor {
+    /// Slots are indexed by token, freed slots are reused by later watches
+    processes: Vec<Option<Process>>,
+    first: Token,
+}
+
+impl ProcessMonitor {
+    pub fn new(registry: &Registry, first: Token, configs: &[ProcessConfig]) -> Result<Self> {
+        let mut monitor = Self {
+            processes: Vec::new(),
+            first,
+        };
+        for config in configs {
+            let pid = match (config.pid, &config.pid_file) {
+                (Some(pid), None) => pid,
+                (None, Some(path)) => fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read pid file {:?}", path))?
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid pid file {:?}", path))?,
+                _ => bail!(
+                    "Process {} needs exactly one of pid or pid_file",
+                    config.name
+                ),
+            };
+            monitor.watch(registry, &config.name, pid)?;
+        }
+        Ok(monitor)
+    }
+
+    pub fn watch(&mut self, registry: &Registry, name: &str, pid: i32) -> Result<()> {
+        let pidfd = pidfd_open(pid).with_context(|| format!("Failed to watch process {}", pid))?;
+        let slot = match self.processes.iter().position(Option::is_none) {
+            Some(slot) => slot,
+            None if self.processes.len() < MAX_PROCESSES => {
+                self.processes.push(None);
+                self.processes.len() - 1
+            }
+            None => bail!("Too many watched processes"),
+        };
+        let token = Token(self.first.0 + slot);
+        registry.register(&mut SourceFd(&pidfd.as_raw_fd()), token, Interest::READABLE)?;
+        info!("Watching process {} ({})", name, pid);
+        self.processes[slot] = Some(Process {
+            name: name.to_string(),
+            pid,
+            pidfd,
+        });
+        Ok(())
+    }
+
+    /// Stops watching all processes with the given name
+    pub fn unwatch(&mut self, registry: &Registry, name: &str) -> Result<()> {
+        for slot in &mut self.processes {
+            if slot.as_ref().is_some_and(|p| p.name == name) {
+                if let Some(process) = slot.take() {
+                    registry.deregister(&mut SourceFd(&process.pidfd.as_raw_fd()))?;
+                    info!("Stopped watching process {} ({})", name, process.pid);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn handles(&self, token: Token) -> bool {
+        (self.first.0..self.first.0 + self.processes.len()).contains(&token.0)
+    }
+
+    /// Called when a process's pidfd became readable, meaning it has exited. Stops watching it
+    /// and returns why that's a loss of liveness, for the loop to take like a ping timeout.
+    pub fn on_exit(&mut self, registry: &Registry, token: Token) -> Result<Option<String>> {
+        // None for a stale event, the process was unwatched in the same poll
+        let Some(process) = self.processes[token.0 - self.first.0].take() else {
+            return Ok(None);
+        };
+        registry.deregister(&mut SourceFd(&process.pidfd.as_raw_fd()))?;
+        Ok(Some(format!(
+            "Process {} ({}) exited",
+            process.name, process.pid
+        )))
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn pidfd_open(pid: i32) -> Result<OwnedFd> {
+    // SAFETY: pidfd_open has no memory arguments and returns a new fd or -1
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if fd < 0 {
+        return Err(Error::last_os_error().into());
+    }
+    // SAFETY: fd was just returned by the kernel and isn't owned by anything else
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pidfd_open(_pid: i32) -> Result<OwnedFd> {
+    bail!("Watching processes is only supported on Linux")
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use mio::{Events, Poll};
+    use std::{process::Command, time::Duration};
+
+    #[test]
+    fn test_process_exit() -> Result<()> {
+        let mut poll = Poll::new()?;
+        let mut events = Events::with_capacity(8);
+        let first = Token(10);
+        let mut monitor = ProcessMonitor::new(poll.registry(), first, &[])?;
+
+        let mut child = Command::new("sleep").arg("0.2").spawn()?;
+        monitor.watch(poll.registry(), "sleep", child.id() as i32)?;
+        assert!(monitor.handles(first));
+        assert!(!monitor.handles(Token(11)));
+
+        poll.poll(&mut events, Some(Duration::from_millis(10)))?;
+        assert!(events.is_empty());
+
+        child.wait()?;
+        poll.poll(&mut events, Some(Duration::from_secs(1)))?;
+        let event = events.iter().next().expect("process exit not seen");
+        assert_eq!(event.token(), first);
+        let reason = monitor.on_exit(poll.registry(), first)?;
+        assert_eq!(
+            reason,
+            Some(format!("Process sleep ({}) exited", child.id()))
+        );
+        // No longer watched, the slot is free again
+        assert_eq!(monitor.on_exit(poll.registry(), first)?, None);
+        monitor.watch(poll.registry(), "self", std::process::id() as i32)?;
+        monitor.unwatch(poll.registry(), "self")?;
+        assert_eq!(monitor.on_exit(poll.registry(), first)?, None);
+        Ok(())
+    }
+}
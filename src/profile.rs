@@ -0,0 +1,205 @@
+use crate::{
+    can::CanSocket,
+    config::{Config, PetStep, ProfileConfig},
+    exit::Cause,
+    petter::Petter,
+    pingee::Pingee,
+    power::LowPower,
+};
+use anyhow::Result;
+use log::{debug, info};
+use mio::{unix::SourceFd, Interest, Registry, Token};
+use std::{os::fd::AsRawFd, time::Duration};
+
+/// The mission mode profiles, e.g. ground-test, launch, and safe-mode, each with its own pet
+/// pattern, client timeouts, and whether the uptime and schedule resets go ahead. The first one is
+/// active from the start, PROFILE or the mission state over CAN switch to another.
+pub struct Profiles {
+    socket: Option<CanSocket>,
+    profiles: Vec<ProfileConfig>,
+    /// The pet pattern and the configured clients' timeouts, for profiles that leave them
+    pattern: Vec<PetStep>,
+    timeouts: Vec<Duration>,
+    active: usize,
+    /// A scheduled reset held back by the active profile, until one that allows them
+    held: Option<(String, Cause)>,
+}
+
+impl Profiles {
+    pub fn new(config: &Config) -> Result<Self> {
+        let socket = match &config.profile_can {
+            Some(can) => {
+                let socket = CanSocket::open(&can.interface)?;
+                socket.filter(&[can.id])?;
+                Some(socket)
+            }
+            None => None,
+        };
+        Ok(Self {
+            socket,
+            profiles: config.profiles.clone(),
+            pattern: config.pet.pattern.clone(),
+            timeouts: config.clients.iter().map(|c| c.timeout).collect(),
+            active: 0,
+            held: None,
+        })
+    }
+
+    pub fn register(&self, registry: &Registry, token: Token) -> Result<()> {
+        if let Some(socket) = &self.socket {
+            let fd = socket.as_raw_fd();
+            registry.register(&mut SourceFd(&fd), token, Interest::READABLE)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the first profile, before the low power mode can be entered
+    pub fn start(&mut self, petter: &mut Petter, pingee: &mut Pingee) -> Result<()> {
+        self.apply(0, petter, pingee, None)?;
+        Ok(())
+    }
+
+    /// Returns the reset the profile it switched from held back, if the new one allows it
+    pub fn switch(
+        &mut self,
+        index: usize,
+        petter: &mut Petter,
+        pingee: &mut Pingee,
+        low_power: Option<&mut LowPower>,
+    ) -> Result<Option<(String, Cause)>> {
+        if index == self.active {
+            return Ok(None);
+        }
+        self.apply(index, petter, pingee, low_power)
+    }
+
+    /// Frames for a mission state without a profile, or without a payload, say nothing
+    pub fn on_readable(
+        &mut self,
+        petter: &mut Petter,
+        pingee: &mut Pingee,
+        mut low_power: Option<&mut LowPower>,
+    ) -> Result<Option<(String, Cause)>> {
+        let mut released = None;
+        loop {
+            let frame = match &self.socket {
+                Some(socket) => socket.recv()?,
+                None => None,
+            };
+            let Some(frame) = frame else {
+                break;
+            };
+            let Some(&state) = frame.payload().first() else {
+                continue;
+            };
+            match self.profiles.iter().position(|p| p.state == Some(state)) {
+                Some(index) => {
+                    let low_power = low_power.as_deref_mut();
+                    let reset = self.switch(index, petter, pingee, low_power)?;
+                    released = released.or(reset);
+                }
+                None => debug!("No profile for mission state {}", state),
+            }
+        }
+        Ok(released)
+    }
+
+    /// A scheduled reset, passed on unless the active profile holds it back
+    pub fn allow(&mut self, reset: Option<(String, Cause)>) -> Option<(String, Cause)> {
+        let profile = &self.profiles[self.active];
+        match reset {
+            Some((reason, cause)) if !profile.scheduled_resets => {
+                info!("{}, held back in the {} profile", reason, profile.name);
+                self.held = Some((reason, cause));
+                None
+            }
+            reset => reset,
+        }
+    }
+
+    fn apply(
+        &mut self,
+        index: usize,
+        petter: &mut Petter,
+        pingee: &mut Pingee,
+        low_power: Option<&mut LowPower>,
+    ) -> Result<Option<(String, Cause)>> {
+        let profile = &self.profiles[index];
+        info!("Switching to the {} profile", profile.name);
+        let pattern = match profile.pattern.is_empty() {
+            true => &self.pattern,
+            false => &profile.pattern,
+        };
+        let timeouts: Vec<_> = self
+            .timeouts
+            .iter()
+            .map(|&timeout| match profile.timeout.is_zero() {
+                true => timeout,
+                false => profile.timeout,
+            })
+            .enumerate()
+            .collect();
+        match low_power {
+            Some(low_power) => low_power.set_normal(pattern, &timeouts, petter, pingee)?,
+            None => {
+                petter.switch_pattern(pattern);
+                for (slot, timeout) in timeouts {
+                    pingee.set_timeout(slot, timeout)?;
+                }
+            }
+        }
+        self.active = index;
+        pingee.set_profile(index);
+        Ok(match profile.scheduled_resets {
+            true => self.held.take(),
+            false => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::petter::Mock;
+
+    #[test]
+    fn test_profiles() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            timeout = 30
+            [inhibit]
+            startup = 0.001
+            [[profile]]
+            name = "launch"
+            pattern = [{ high = true, duration = 0.1 }, { high = false, duration = 1.9 }]
+            timeout = 120
+            scheduled_resets = false
+            [[profile]]
+            name = "nominal"
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        let mock = Mock::default();
+        let mut petter = Petter::with_backend(Box::new(mock.clone()))?;
+        let mut profiles = Profiles::new(&config)?;
+        profiles.start(&mut petter, &mut pingee)?;
+        assert_eq!(pingee.timeout(0), Some(Duration::from_secs(120)));
+        petter.pet()?;
+        petter.pet()?;
+        petter.pet()?;
+        assert_eq!(petter.period(), Duration::from_secs(2));
+        assert_eq!(pingee.status()?.get("profile"), Some("launch"));
+
+        // Held back until a profile allows it
+        let reset = Some((String::from("Up for too long"), Cause::Uptime));
+        assert_eq!(profiles.allow(reset.clone()), None);
+        assert_eq!(profiles.switch(1, &mut petter, &mut pingee, None)?, reset);
+        assert_eq!(pingee.timeout(0), Some(Duration::from_secs(30)));
+        assert_eq!(pingee.status()?.get("profile"), Some("nominal"));
+        assert_eq!(profiles.allow(reset.clone()), reset);
+        Ok(())
+    }
+}
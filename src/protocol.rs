@@ -0,0 +1,798 @@
+use anyhow::{bail, ensure, Context, Result};
+use std::{fmt, time::Duration};
+
+// Packets are a single line of ASCII: a command keyword followed by space separated arguments.
+// Durations are in (possibly fractional) seconds.
+//
+//     PING <client> [ACK <seq>] [EVERY <seconds>]
+//     REGISTER <client> <timeout> [pid]
+//     DEREGISTER <client>
+//     EXTEND <client> <duration>
+//     INHIBIT <duration> [name]
+//     UNINHIBIT <name>
+//     STATUS
+//     LOGBOOK [from]
+//     ARM
+//     DISARM
+//     RESET
+//     TAKEOVER
+//     REEXEC
+//     INJECT DROP <percent> | DELAY <duration> | FAIL <writes> | CLEAR
+//     POWER LOW | NORMAL
+//     PROFILE <name>
+//     CRITICALITY <client> CRITICAL | ADVISORY
+//     MAINTENANCE <duration>
+//
+// Commands other than PING get a reply of either "OK ..." or "ERR <reason>". A PING gets one only
+// if it asks with ACK, or its client is configured with ack: "ACK <seq> <margin ms>", with "-" for
+// no sequence number or no margin.
+//
+// Any command can start with "ID <id> ", which its reply then starts with too, so that a reply that
+// arrives late over a lossy link isn't taken for the reply to the next command. A command resent
+// with the same id from the same source is answered with the first reply rather than run again.
+//
+// Any command can be authenticated by appending "AUTH [key id] <nonce> <mac>", where the MAC is
+// the hex HMAC-SHA256 of everything before the space preceding it, and the nonce has to be greater
+// than the last accepted one (a timestamp works well). Some commands require it. Without a key id
+// the key file's key is used.
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command<'a> {
+    Ping {
+        client: &'a str,
+        /// The sequence number to acknowledge it with, if it asks for that
+        ack: Option<u64>,
+        /// How often it says it pings, zero for not saying anymore
+        every: Option<Duration>,
+    },
+    Register {
+        client: &'a str,
+        timeout: Duration,
+        pid: Option<i32>,
+    },
+    Deregister {
+        client: &'a str,
+    },
+    /// Asks for a one-off longer ping window, e.g. before a long flash erase
+    Extend {
+        client: &'a str,
+        duration: Duration,
+    },
+    /// Suspends the ping requirement entirely, e.g. during a firmware update. Inhibits of
+    /// different names stack, the same name replaces its own.
+    Inhibit {
+        duration: Duration,
+        name: Option<&'a str>,
+    },
+    /// Revokes a named inhibit before it runs out
+    Uninhibit {
+        name: &'a str,
+    },
+    Status,
+    /// Reads a page of the logbook from this entry on, the oldest kept if not given
+    Logbook {
+        from: Option<u64>,
+    },
+    /// Makes missing pings fatal again
+    Arm,
+    /// Keeps petting regardless of pings, for ground testing
+    Disarm,
+    /// Stops petting for good so that the supervisor power cycles the card
+    Reset,
+    /// Ends the SIGTERM drain early, once the replacement instance is ready
+    Takeover,
+    /// Executes the installed binary in place, which resumes petting with the same line and
+    /// sockets, for upgrades
+    Reexec,
+    /// Starts or stops injecting a fault, only in builds with the inject feature
+    Inject {
+        fault: Fault,
+    },
+    /// Switches the low power mode on or off, for when the EPS can't say so over CAN
+    Power {
+        low: bool,
+    },
+    /// Switches to another mission mode profile
+    Profile {
+        name: &'a str,
+    },
+    /// Makes a client's silence fatal again, or only reported, for contingency operations
+    Criticality {
+        client: &'a str,
+        critical: bool,
+    },
+    /// Enters maintenance for at most this long, after which it's armed again, or ends it early
+    /// with zero
+    Maintenance {
+        duration: Duration,
+    },
+}
+
+/// What INJECT can break, for exercising the failure handling without breaking anything for real
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Drops this percentage of pings, spread evenly
+    DropPings(u8),
+    /// Delays every pet by this much
+    DelayPets(Duration),
+    /// Fails this many GPIO writes from now on
+    FailGpio(u32),
+    /// Stops injecting anything
+    Clear,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::DropPings(percent) => write!(f, "DROP {}", percent),
+            Fault::DelayPets(delay) => write!(f, "DELAY {}", delay.as_secs_f64()),
+            Fault::FailGpio(writes) => write!(f, "FAIL {}", writes),
+            Fault::Clear => f.write_str("CLEAR"),
+        }
+    }
+}
+
+/// Every command's keyword
+pub const KEYWORDS: [&str; 18] = [
+    "PING",
+    "REGISTER",
+    "DEREGISTER",
+    "EXTEND",
+    "INHIBIT",
+    "UNINHIBIT",
+    "STATUS",
+    "LOGBOOK",
+    "ARM",
+    "DISARM",
+    "RESET",
+    "TAKEOVER",
+    "REEXEC",
+    "INJECT",
+    "POWER",
+    "PROFILE",
+    "CRITICALITY",
+    "MAINTENANCE",
+];
+
+impl<'a> Command<'a> {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Command::Ping { .. } => "PING",
+            Command::Register { .. } => "REGISTER",
+            Command::Deregister { .. } => "DEREGISTER",
+            Command::Extend { .. } => "EXTEND",
+            Command::Inhibit { .. } => "INHIBIT",
+            Command::Uninhibit { .. } => "UNINHIBIT",
+            Command::Status => "STATUS",
+            Command::Logbook { .. } => "LOGBOOK",
+            Command::Arm => "ARM",
+            Command::Disarm => "DISARM",
+            Command::Reset => "RESET",
+            Command::Takeover => "TAKEOVER",
+            Command::Reexec => "REEXEC",
+            Command::Inject { .. } => "INJECT",
+            Command::Power { .. } => "POWER",
+            Command::Profile { .. } => "PROFILE",
+            Command::Criticality { .. } => "CRITICALITY",
+            Command::Maintenance { .. } => "MAINTENANCE",
+        }
+    }
+
+    /// The client it's about, if any
+    pub fn client(&self) -> Option<&'a str> {
+        match *self {
+            Command::Ping { client, .. }
+            | Command::Register { client, .. }
+            | Command::Deregister { client }
+            | Command::Extend { client, .. } => Some(client),
+            _ => None,
+        }
+    }
+}
+
+/// As sent, which parses back to the same command
+impl fmt::Display for Command<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.keyword())?;
+        match self {
+            Command::Ping { client, ack, every } => {
+                write!(f, " {}", client)?;
+                if let Some(seq) = ack {
+                    write!(f, " ACK {}", seq)?;
+                }
+                match every {
+                    Some(every) => write!(f, " EVERY {}", every.as_secs_f64()),
+                    None => Ok(()),
+                }
+            }
+            Command::Deregister { client } => write!(f, " {}", client),
+            Command::Register {
+                client,
+                timeout,
+                pid,
+            } => {
+                write!(f, " {} {}", client, timeout.as_secs_f64())?;
+                match pid {
+                    Some(pid) => write!(f, " {}", pid),
+                    None => Ok(()),
+                }
+            }
+            Command::Extend { client, duration } => {
+                write!(f, " {} {}", client, duration.as_secs_f64())
+            }
+            Command::Inhibit { duration, name } => {
+                write!(f, " {}", duration.as_secs_f64())?;
+                match name {
+                    Some(name) => write!(f, " {}", name),
+                    None => Ok(()),
+                }
+            }
+            Command::Uninhibit { name } | Command::Profile { name } => write!(f, " {}", name),
+            Command::Logbook { from: Some(from) } => write!(f, " {}", from),
+            Command::Inject { fault } => write!(f, " {}", fault),
+            Command::Power { low: true } => f.write_str(" LOW"),
+            Command::Power { low: false } => f.write_str(" NORMAL"),
+            Command::Criticality { client, critical } => {
+                let criticality = if *critical { "CRITICAL" } else { "ADVISORY" };
+                write!(f, " {} {}", client, criticality)
+            }
+            Command::Maintenance { duration } => write!(f, " {}", duration.as_secs_f64()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Auth<'a> {
+    /// Which key signed it, None for the key file's
+    pub key_id: Option<&'a str>,
+    pub nonce: u64,
+    pub mac: &'a str,
+    /// The part of the packet covered by the MAC
+    pub signed: &'a str,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Packet<'a> {
+    /// Echoed in the reply
+    pub id: Option<u64>,
+    pub command: Command<'a>,
+    pub auth: Option<Auth<'a>>,
+}
+
+/// The command with its id and AUTH trailer, if any. The MAC is copied, not computed.
+impl fmt::Display for Packet<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(id) = self.id {
+            write!(f, "ID {} ", id)?;
+        }
+        write!(f, "{}", self.command)?;
+        if let Some(auth) = &self.auth {
+            f.write_str(" AUTH")?;
+            if let Some(key_id) = auth.key_id {
+                write!(f, " {}", key_id)?;
+            }
+            write!(f, " {} {}", auth.nonce, auth.mac)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn seconds(arg: &str) -> Result<Duration> {
+    let secs: f64 = arg
+        .parse()
+        .with_context(|| format!("Invalid duration {:?}", arg))?;
+    Duration::try_from_secs_f64(secs).with_context(|| format!("Invalid duration {:?}", arg))
+}
+
+pub fn parse(packet: &[u8]) -> Result<Packet<'_>> {
+    let text = std::str::from_utf8(packet).context("Packet is not text")?;
+    let text = text.trim_end_matches(['\r', '\n']);
+    let (text, auth) = match text.split_once(" AUTH ") {
+        Some((command, trailer)) => {
+            let (head, mac) = trailer.rsplit_once(' ').context("Missing MAC")?;
+            ensure!(!mac.is_empty(), "Invalid MAC");
+            let (key_id, nonce) = match head.split_once(' ') {
+                Some((key_id, nonce)) => (Some(key_id), nonce),
+                None => (None, head),
+            };
+            ensure!(key_id.is_none_or(|id| !id.is_empty()), "Invalid key id");
+            let auth = Auth {
+                key_id,
+                nonce: nonce.parse().context("Invalid nonce")?,
+                mac,
+                signed: &text[..text.len() - mac.len() - 1],
+            };
+            (command, Some(auth))
+        }
+        None => (text, None),
+    };
+    let (id, text) = match text.strip_prefix("ID ") {
+        Some(rest) => {
+            let (id, command) = rest.split_once(' ').context("Missing command")?;
+            (Some(id.parse().context("Invalid request id")?), command)
+        }
+        None => (None, text),
+    };
+    Ok(Packet {
+        id,
+        command: parse_command(text)?,
+        auth,
+    })
+}
+
+/// The reply with the id it starts with, if any, taken off
+pub fn reply_id(reply: &str) -> (Option<u64>, &str) {
+    let id = reply
+        .strip_prefix("ID ")
+        .and_then(|rest| rest.split_once(' '));
+    match id.and_then(|(id, rest)| Some((id.parse().ok()?, rest))) {
+        Some((id, rest)) => (Some(id), rest),
+        None => (None, reply),
+    }
+}
+
+fn parse_command(text: &str) -> Result<Command<'_>> {
+    let mut args = text.split(' ');
+    let mut next = |what: &str| match args.next() {
+        Some(arg) if !arg.is_empty() => Ok(arg),
+        _ => Err(anyhow::anyhow!("Missing {}", what)),
+    };
+
+    let command = match next("command")? {
+        "PING" => {
+            let client = next("client")?;
+            let mut option = args.next();
+            let mut ack = None;
+            if option == Some("ACK") {
+                let seq = args.next().context("Missing sequence number")?;
+                ack = Some(seq.parse().context("Invalid sequence number")?);
+                option = args.next();
+            }
+            let every = match option {
+                Some("EVERY") => Some(seconds(args.next().context("Missing cadence")?)?),
+                Some(other) => bail!("Unexpected {:?}", other),
+                None => None,
+            };
+            Command::Ping { client, ack, every }
+        }
+        "REGISTER" => Command::Register {
+            client: next("client")?,
+            timeout: seconds(next("timeout")?)?,
+            pid: match args.next() {
+                Some(pid) => Some(pid.parse().context("Invalid pid")?),
+                None => None,
+            },
+        },
+        "DEREGISTER" => Command::Deregister {
+            client: next("client")?,
+        },
+        "EXTEND" => Command::Extend {
+            client: next("client")?,
+            duration: seconds(next("duration")?)?,
+        },
+        "INHIBIT" => Command::Inhibit {
+            duration: seconds(next("duration")?)?,
+            name: args.next(),
+        },
+        "UNINHIBIT" => Command::Uninhibit {
+            name: next("name")?,
+        },
+        "STATUS" => Command::Status,
+        "LOGBOOK" => Command::Logbook {
+            from: match args.next() {
+                Some(from) => Some(from.parse().context("Invalid entry")?),
+                None => None,
+            },
+        },
+        "ARM" => Command::Arm,
+        "DISARM" => Command::Disarm,
+        "RESET" => Command::Reset,
+        "TAKEOVER" => Command::Takeover,
+        "REEXEC" => Command::Reexec,
+        "INJECT" => Command::Inject {
+            fault: match next("fault")? {
+                "DROP" => {
+                    let percent = next("percentage")?;
+                    let percent = percent
+                        .parse()
+                        .ok()
+                        .filter(|&p| p <= 100)
+                        .with_context(|| format!("Invalid percentage {:?}", percent))?;
+                    Fault::DropPings(percent)
+                }
+                "DELAY" => Fault::DelayPets(seconds(next("duration")?)?),
+                "FAIL" => Fault::FailGpio(next("count")?.parse().context("Invalid count")?),
+                "CLEAR" => Fault::Clear,
+                other => bail!("Unknown fault {:?}", other),
+            },
+        },
+        "POWER" => Command::Power {
+            low: match next("power mode")? {
+                "LOW" => true,
+                "NORMAL" => false,
+                other => bail!("Unknown power mode {:?}", other),
+            },
+        },
+        "PROFILE" => Command::Profile {
+            name: next("profile")?,
+        },
+        "CRITICALITY" => Command::Criticality {
+            client: next("client")?,
+            critical: match next("criticality")? {
+                "CRITICAL" => true,
+                "ADVISORY" => false,
+                other => bail!("Unknown criticality {:?}", other),
+            },
+        },
+        "MAINTENANCE" => Command::Maintenance {
+            duration: seconds(next("duration")?)?,
+        },
+        other => bail!("Unknown command {:?}", other),
+    };
+    if args.next().is_some() {
+        bail!("Too many arguments");
+    }
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::{collection::vec, option, prelude::*, sample::Index};
+
+    const NAME: &str = "[a-zA-Z0-9_.:-]{1,23}";
+
+    /// Whole milliseconds, which survive being written as seconds
+    fn duration() -> impl Strategy<Value = Duration> {
+        (0..1_000_000_000u64).prop_map(Duration::from_millis)
+    }
+
+    /// Everything in a command but the names, which it borrows: the variant, a number, a duration,
+    /// an optional duration, and a flag
+    type Parts = (u8, Option<u64>, Duration, Option<Duration>, bool);
+
+    fn parts() -> impl Strategy<Value = Parts> {
+        (
+            0..21u8,
+            option::of(any::<u64>()),
+            duration(),
+            option::of(duration()),
+            any::<bool>(),
+        )
+    }
+
+    fn command(client: &str, (variant, number, duration, every, flag): Parts) -> Command<'_> {
+        match variant {
+            0 => Command::Ping {
+                client,
+                ack: number,
+                every,
+            },
+            1 => Command::Register {
+                client,
+                timeout: duration,
+                pid: number.map(|pid| pid as i32),
+            },
+            2 => Command::Deregister { client },
+            3 => Command::Extend { client, duration },
+            4 => Command::Inhibit {
+                duration,
+                name: flag.then_some(client),
+            },
+            5 => Command::Status,
+            6 => Command::Arm,
+            7 => Command::Disarm,
+            8 => Command::Reset,
+            9 => Command::Takeover,
+            10 => Command::Power { low: flag },
+            11 => Command::Reexec,
+            12 => Command::Uninhibit { name: client },
+            13 => Command::Profile { name: client },
+            14 => Command::Logbook { from: number },
+            15 => Command::Criticality {
+                client,
+                critical: flag,
+            },
+            16 => Command::Maintenance { duration },
+            _ => Command::Inject {
+                fault: match variant {
+                    17 => Fault::DropPings((number.unwrap_or(0) % 101) as u8),
+                    18 => Fault::DelayPets(duration),
+                    19 => Fault::FailGpio(number.unwrap_or(0) as u32),
+                    _ => Fault::Clear,
+                },
+            },
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(10_000))]
+
+        #[test]
+        fn test_round_trip(
+            client in NAME,
+            parts in parts(),
+            id in option::of(any::<u64>()),
+            key_id in option::of(NAME),
+            nonce in any::<u64>(),
+            mac in "[0-9a-f]{64}",
+        ) {
+            let command = command(&client, parts);
+            let text = command.to_string();
+            prop_assert_eq!(&parse(text.as_bytes()).unwrap().command, &command, "{}", text);
+
+            let text = match id {
+                Some(id) => format!("ID {} {}", id, text),
+                None => text,
+            };
+            let signed = match &key_id {
+                Some(key_id) => format!("{} AUTH {} {}", text, key_id, nonce),
+                None => format!("{} AUTH {}", text, nonce),
+            };
+            let packet = Packet {
+                id,
+                command,
+                auth: Some(Auth {
+                    key_id: key_id.as_deref(),
+                    nonce,
+                    mac: &mac,
+                    signed: &signed,
+                }),
+            };
+            let text = packet.to_string();
+            prop_assert_eq!(parse(text.as_bytes()).unwrap(), packet, "{}", text);
+        }
+
+        #[test]
+        fn test_arbitrary_bytes(bytes in vec(any::<u8>(), ..64)) {
+            let _ = parse(&bytes);
+        }
+
+        /// Valid packets with a byte or two mangled get further into the parser
+        #[test]
+        fn test_mangled_bytes(
+            client in NAME,
+            parts in parts(),
+            mangles in vec((any::<Index>(), any::<u8>()), 1..3),
+        ) {
+            let mut bytes = command(&client, parts).to_string().into_bytes();
+            for (i, byte) in mangles {
+                let i = i.index(bytes.len());
+                bytes[i] = byte;
+            }
+            let _ = parse(&bytes);
+        }
+    }
+
+    #[test]
+    fn test_keywords() {
+        for keyword in KEYWORDS {
+            let text = match keyword {
+                "PING" | "DEREGISTER" | "UNINHIBIT" | "PROFILE" => format!("{} c3", keyword),
+                "REGISTER" | "EXTEND" => format!("{} c3 10", keyword),
+                "INHIBIT" | "MAINTENANCE" => format!("{} 10", keyword),
+                "INJECT" => format!("{} CLEAR", keyword),
+                "POWER" => format!("{} LOW", keyword),
+                "CRITICALITY" => format!("{} c3 ADVISORY", keyword),
+                _ => keyword.to_string(),
+            };
+            let command = parse(text.as_bytes()).unwrap().command;
+            assert_eq!(command.keyword(), keyword);
+        }
+        assert_eq!(parse(b"EXTEND c3 10").unwrap().command.client(), Some("c3"));
+        assert_eq!(parse(b"RESET").unwrap().command.client(), None);
+    }
+
+    #[test]
+    fn test_parse_ping() {
+        assert_eq!(
+            parse(b"PING c3-main").unwrap().command,
+            Command::Ping {
+                client: "c3-main",
+                ack: None,
+                every: None
+            }
+        );
+        assert_eq!(
+            parse(b"PING edl\n").unwrap().command,
+            Command::Ping {
+                client: "edl",
+                ack: None,
+                every: None
+            }
+        );
+        assert_eq!(
+            parse(b"PING c3 ACK 42").unwrap().command,
+            Command::Ping {
+                client: "c3",
+                ack: Some(42),
+                every: None
+            }
+        );
+        assert!(parse(b"PING c3 ACK").is_err());
+        assert!(parse(b"PING c3 ACK -1").is_err());
+        assert!(parse(b"PING c3 ACK 1 2").is_err());
+        assert_eq!(
+            parse(b"PING c3 ACK 7 EVERY 0.1").unwrap().command,
+            Command::Ping {
+                client: "c3",
+                ack: Some(7),
+                every: Some(Duration::from_millis(100))
+            }
+        );
+        assert!(parse(b"PING c3 EVERY").is_err());
+        assert!(parse(b"PING c3 EVERY 1 ACK 2").is_err());
+        assert!(parse(b"").is_err());
+        assert!(parse(b"PING").is_err());
+        assert!(parse(b"PING  c3").is_err());
+        assert!(parse(b"PING c3 extra").is_err());
+        assert!(parse(b"PONG c3").is_err());
+        assert!(parse(&[0xff, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn test_parse_register() {
+        assert_eq!(
+            parse(b"REGISTER edl 12.5 4242").unwrap().command,
+            Command::Register {
+                client: "edl",
+                timeout: Duration::from_millis(12500),
+                pid: Some(4242),
+            }
+        );
+        assert_eq!(
+            parse(b"REGISTER edl 10").unwrap().command,
+            Command::Register {
+                client: "edl",
+                timeout: Duration::from_secs(10),
+                pid: None,
+            }
+        );
+        assert_eq!(
+            parse(b"DEREGISTER edl").unwrap().command,
+            Command::Deregister { client: "edl" }
+        );
+        assert!(parse(b"REGISTER edl").is_err());
+        assert!(parse(b"REGISTER edl -3").is_err());
+        assert!(parse(b"REGISTER edl 10 pid").is_err());
+        assert!(parse(b"DEREGISTER").is_err());
+    }
+
+    #[test]
+    fn test_parse_inject() {
+        let fault = |text: &str| match parse(text.as_bytes()).unwrap().command {
+            Command::Inject { fault } => fault,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(fault("INJECT DROP 25"), Fault::DropPings(25));
+        assert_eq!(
+            fault("INJECT DELAY 0.2"),
+            Fault::DelayPets(Duration::from_millis(200))
+        );
+        assert_eq!(fault("INJECT FAIL 3"), Fault::FailGpio(3));
+        assert_eq!(fault("INJECT CLEAR").to_string(), "CLEAR");
+        assert_eq!(fault("INJECT DELAY 1.5").to_string(), "DELAY 1.5");
+        assert!(parse(b"INJECT").is_err());
+        assert!(parse(b"INJECT DROP 101").is_err());
+        assert!(parse(b"INJECT FAIL -1").is_err());
+        assert!(parse(b"INJECT FLOOD 5").is_err());
+        assert!(parse(b"INJECT CLEAR 5").is_err());
+    }
+
+    #[test]
+    fn test_parse_power() {
+        assert_eq!(
+            parse(b"POWER LOW").unwrap().command,
+            Command::Power { low: true }
+        );
+        assert_eq!(
+            parse(b"POWER NORMAL").unwrap().command.to_string(),
+            "POWER NORMAL"
+        );
+        assert!(parse(b"POWER").is_err());
+        assert!(parse(b"POWER OFF").is_err());
+        assert_eq!(
+            parse(b"PROFILE safe-mode").unwrap().command,
+            Command::Profile { name: "safe-mode" }
+        );
+        assert!(parse(b"PROFILE").is_err());
+    }
+
+    #[test]
+    fn test_parse_extend() {
+        assert_eq!(
+            parse(b"EXTEND c3-main 90").unwrap().command,
+            Command::Extend {
+                client: "c3-main",
+                duration: Duration::from_secs(90),
+            }
+        );
+        assert!(parse(b"EXTEND c3-main").is_err());
+        assert!(parse(b"EXTEND c3-main forever").is_err());
+    }
+
+    #[test]
+    fn test_parse_inhibit_status() {
+        assert_eq!(
+            parse(b"INHIBIT 300").unwrap().command,
+            Command::Inhibit {
+                duration: Duration::from_secs(300),
+                name: None,
+            }
+        );
+        assert_eq!(
+            parse(b"INHIBIT 300 deploy").unwrap().command,
+            Command::Inhibit {
+                duration: Duration::from_secs(300),
+                name: Some("deploy"),
+            }
+        );
+        assert!(parse(b"INHIBIT").is_err());
+        assert!(parse(b"INHIBIT 300 deploy now").is_err());
+        assert_eq!(
+            parse(b"UNINHIBIT deploy").unwrap().command,
+            Command::Uninhibit { name: "deploy" }
+        );
+        assert!(parse(b"UNINHIBIT").is_err());
+        assert_eq!(parse(b"STATUS\n").unwrap().command, Command::Status);
+        assert!(parse(b"STATUS now").is_err());
+        assert_eq!(parse(b"TAKEOVER").unwrap().command, Command::Takeover);
+        assert_eq!(parse(b"REEXEC").unwrap().command, Command::Reexec);
+    }
+
+    #[test]
+    fn test_parse_auth() {
+        let packet = parse(b"ARM AUTH 1700000000 c0ffee\n").unwrap();
+        assert_eq!(packet.command, Command::Arm);
+        assert_eq!(
+            packet.auth,
+            Some(Auth {
+                key_id: None,
+                nonce: 1700000000,
+                mac: "c0ffee",
+                signed: "ARM AUTH 1700000000",
+            })
+        );
+        assert_eq!(parse(b"DISARM").unwrap().auth, None);
+        assert_eq!(parse(b"RESET AUTH 2 00").unwrap().command, Command::Reset);
+        assert!(parse(b"ARM AUTH 17").is_err());
+        assert!(parse(b"ARM AUTH x c0ffee").is_err());
+        assert!(parse(b"ARM AUTH 1 c0ffee extra").is_err());
+        assert!(parse(b"ARM AUTH 1 2 3 c0ffee").is_err());
+        assert!(parse(b"ARM AUTH  1 c0ffee").is_err());
+
+        let packet = parse(b"DISARM AUTH 2026b 1700000000 c0ffee").unwrap();
+        assert_eq!(
+            packet.auth,
+            Some(Auth {
+                key_id: Some("2026b"),
+                nonce: 1700000000,
+                mac: "c0ffee",
+                signed: "DISARM AUTH 2026b 1700000000",
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_id() {
+        let packet = parse(b"ID 42 INHIBIT 300 AUTH 1700000000 c0ffee").unwrap();
+        assert_eq!(packet.id, Some(42));
+        assert_eq!(
+            packet.auth.unwrap().signed,
+            "ID 42 INHIBIT 300 AUTH 1700000000"
+        );
+        assert_eq!(parse(b"PING c3").unwrap().id, None);
+        assert!(parse(b"ID 42").is_err());
+        assert!(parse(b"ID x STATUS").is_err());
+        assert!(parse(b"STATUS ID 42").is_err());
+
+        assert_eq!(reply_id("ID 42 OK 300"), (Some(42), "OK 300"));
+        assert_eq!(
+            reply_id("ID 7 state=armed\nuptime=5\n").1,
+            "state=armed\nuptime=5\n"
+        );
+        assert_eq!(reply_id("OK 300"), (None, "OK 300"));
+        assert_eq!(reply_id("ID x OK"), (None, "ID x OK"));
+    }
+}
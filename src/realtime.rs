@@ -0,0 +1,54 @@
+use crate::config::RealtimeConfig;
+use log::{info, warn};
+use std::io::Error;
+
+/// Makes the petting less likely to be starved by the rest of the system. Called once everything
+/// is set up. None of this is required, so failures, e.g. without CAP_SYS_NICE or CAP_IPC_LOCK,
+/// only warn.
+pub fn apply(config: &RealtimeConfig) {
+    if let Some(priority) = config.priority {
+        match set_fifo(priority) {
+            Ok(()) => info!("Running as SCHED_FIFO with priority {}", priority),
+            Err(e) => warn!("Failed to set SCHED_FIFO priority {}: {}", priority, e),
+        }
+    }
+    if !config.cpus.is_empty() {
+        match set_affinity(&config.cpus) {
+            Ok(()) => info!("Pinned to CPUs {:?}", config.cpus),
+            Err(e) => warn!("Failed to pin to CPUs {:?}: {}", config.cpus, e),
+        }
+    }
+    if config.lock_memory {
+        // SAFETY: no memory arguments
+        if unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) } < 0 {
+            warn!("Failed to lock memory: {}", Error::last_os_error());
+        } else {
+            info!("Locked memory");
+        }
+    }
+}
+
+fn set_fifo(priority: i32) -> Result<(), Error> {
+    let param = libc::sched_param {
+        sched_priority: priority,
+    };
+    // SAFETY: param outlives the call, pid 0 is this process
+    if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn set_affinity(cpus: &[usize]) -> Result<(), Error> {
+    // SAFETY: cpu_set_t is a plain bitmask, and the config only has CPUs below CPU_SETSIZE
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) < 0 {
+            return Err(Error::last_os_error());
+        }
+    }
+    Ok(())
+}
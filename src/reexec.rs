@@ -0,0 +1,182 @@
+use anyhow::{bail, ensure, Context, Result};
+use std::{
+    env,
+    ffi::OsStr,
+    io::Error,
+    os::{
+        fd::{FromRawFd, OwnedFd, RawFd},
+        unix::{ffi::OsStrExt, process::CommandExt},
+    },
+    path::PathBuf,
+    process::{self, Command},
+};
+
+// A re-exec replaces the process with the installed binary, which resumes petting with the line
+// and sockets it was left, rather than handing them over to a second process:
+//
+//  1. The old image clears close-on-exec on the fds and execs with VAR describing them
+//  2. The new image finds VAR, takes the fds, and pets again without a self-test
+
+const VAR: &str = "WATCHDOG_REEXEC";
+
+/// What the previous image of this process kept open across the exec
+#[derive(Debug, PartialEq)]
+pub struct State {
+    pub line: RawFd,
+    /// Only if that image held it
+    pub lock: Option<RawFd>,
+    /// The main socket followed by the extra ports, in config order
+    pub sockets: Vec<RawFd>,
+    pub armed: bool,
+}
+
+/// Like `State`, owned by the new image
+pub struct Resumed {
+    pub line: OwnedFd,
+    pub lock: Option<OwnedFd>,
+    pub sockets: Vec<OwnedFd>,
+    pub armed: bool,
+}
+
+/// Whether this is the new image, which the daemonizing has to know before `take`
+pub fn resuming() -> bool {
+    env::var_os(VAR).is_some()
+}
+
+/// The state the previous image left, None if there was none. Taken only once, and kept from any
+/// children, like the hook.
+pub fn take() -> Result<Option<Resumed>> {
+    let Some(text) = env::var_os(VAR) else {
+        return Ok(None);
+    };
+    env::remove_var(VAR);
+    let (pid, state) = decode(&text.to_string_lossy())?;
+    // Left by an image of another process, that then started this one
+    if pid != process::id() {
+        return Ok(None);
+    }
+    let take = |fd: RawFd| -> Result<OwnedFd> {
+        cloexec(fd, true)?;
+        // SAFETY: the previous image left it open for this one, and nothing else owns it
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    };
+    Ok(Some(Resumed {
+        line: take(state.line)?,
+        lock: state.lock.map(take).transpose()?,
+        sockets: state.sockets.into_iter().map(take).collect::<Result<_>>()?,
+        armed: state.armed,
+    }))
+}
+
+/// Replaces this process with the installed binary and the same arguments, leaving it `state`.
+/// Only returns if that failed, with the fds as they were.
+pub fn exec(state: &State) -> anyhow::Error {
+    let fds = || {
+        let fds = [state.line].into_iter().chain(state.lock);
+        fds.chain(state.sockets.iter().copied())
+    };
+    let exec = || -> Result<Error> {
+        let program = executable()?;
+        for fd in fds() {
+            cloexec(fd, false)?;
+        }
+        Ok(Command::new(&program)
+            .args(env::args_os().skip(1))
+            .env(VAR, encode(process::id(), state))
+            .exec())
+    };
+    let e = match exec() {
+        Ok(e) => anyhow::Error::new(e).context("Failed to exec"),
+        Err(e) => e,
+    };
+    for fd in fds() {
+        let _ = cloexec(fd, true);
+    }
+    e
+}
+
+/// The installed binary, which /proc/self/exe still names once a package upgrade replaced it
+fn executable() -> Result<PathBuf> {
+    let exe = env::current_exe().context("Failed to find this executable")?;
+    match exe.as_os_str().as_bytes().strip_suffix(b" (deleted)") {
+        Some(path) => Ok(PathBuf::from(OsStr::from_bytes(path))),
+        None => Ok(exe),
+    }
+}
+
+fn cloexec(fd: RawFd, on: bool) -> Result<()> {
+    let flags = if on { libc::FD_CLOEXEC } else { 0 };
+    // SAFETY: no pointers, a bad fd only fails
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, flags) } < 0 {
+        return Err(Error::last_os_error()).with_context(|| format!("Bad fd {}", fd));
+    }
+    Ok(())
+}
+
+/// "pid=1 line=3 lock=4 sockets=5,6 armed=1", the lock left out unless held
+fn encode(pid: u32, state: &State) -> String {
+    let sockets: Vec<_> = state.sockets.iter().map(RawFd::to_string).collect();
+    let lock = state
+        .lock
+        .map_or(String::new(), |lock| format!(" lock={}", lock));
+    format!(
+        "pid={} line={}{} sockets={} armed={}",
+        pid,
+        state.line,
+        lock,
+        sockets.join(","),
+        state.armed as u8
+    )
+}
+
+fn decode(text: &str) -> Result<(u32, State)> {
+    let (mut pid, mut line, mut lock, mut sockets, mut armed) = (None, None, None, None, None);
+    for field in text.split(' ') {
+        let (key, value) = field.split_once('=').context("Bad re-exec state")?;
+        match key {
+            "pid" => pid = Some(value.parse()?),
+            "line" => line = Some(value.parse()?),
+            "lock" => lock = Some(value.parse()?),
+            "sockets" => {
+                let fds = value.split(',').map(str::parse);
+                sockets = Some(fds.collect::<Result<Vec<RawFd>, _>>()?)
+            }
+            "armed" => armed = Some(value == "1"),
+            other => bail!("Unknown re-exec state {:?}", other),
+        }
+    }
+    let (Some(pid), Some(line), Some(sockets), Some(armed)) = (pid, line, sockets, armed) else {
+        bail!("Incomplete re-exec state {:?}", text);
+    };
+    ensure!(!sockets.is_empty(), "Re-exec state without sockets");
+    let state = State {
+        line,
+        lock,
+        sockets,
+        armed,
+    };
+    Ok((pid, state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state() -> Result<()> {
+        let state = State {
+            line: 3,
+            lock: Some(4),
+            sockets: vec![5, 6],
+            armed: true,
+        };
+        let text = encode(42, &state);
+        assert_eq!(text, "pid=42 line=3 lock=4 sockets=5,6 armed=1");
+        assert_eq!(decode(&text)?, (42, state));
+        let (_, unlocked) = decode("pid=42 line=3 sockets=5 armed=0")?;
+        assert_eq!((unlocked.lock, unlocked.armed), (None, false));
+        assert!(decode("pid=42 line=3 armed=0").is_err());
+        assert!(decode("pid=42 line=3 sockets=5 armed=0 future=1").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use crate::config::{ReasonGpioConfig, ResetReasonConfig};
+use anyhow::{Context, Result};
+use gpiod::{Chip, Options};
+use log::{info, warn};
+use std::{fs, path::Path};
+
+// WDIOF_* flags from linux/watchdog.h, as reported in a watchdog's sysfs bootstatus
+const BOOTSTATUS_FLAGS: [(u32, &str); 5] = [
+    (0x0001, "overheat"),
+    (0x0002, "fan-fault"),
+    (0x0010, "brown-out"),
+    (0x0020, "watchdog"),
+    (0x0040, "over-voltage"),
+];
+
+/// Works out why the card last reset from the first configured source that can tell, in order:
+/// a supervisor status GPIO, a sysfs node holding the reason as text, or a watchdog bootstatus.
+pub fn detect(config: &ResetReasonConfig) -> String {
+    let sources = [
+        config.gpio.as_ref().map(|gpio| ("GPIO", read_gpio(gpio))),
+        config.file.as_deref().map(|path| ("file", read_file(path))),
+        config
+            .bootstatus
+            .as_deref()
+            .map(|path| ("bootstatus", read_bootstatus(path))),
+    ];
+    for (source, reason) in sources.into_iter().flatten() {
+        match reason {
+            Ok(reason) => {
+                info!("Last reset reason: {} (from {})", reason, source);
+                return reason;
+            }
+            Err(e) => warn!("Failed to read reset reason {}: {:#}", source, e),
+        }
+    }
+    String::from("unknown")
+}
+
+fn read_gpio(config: &ReasonGpioConfig) -> Result<String> {
+    let chip = Chip::new(&config.chip).context("Failed to get GPIO chip")?;
+    let lines = chip
+        .request_lines(Options::input([config.line]).consumer("C3_Watchdog_Reason"))
+        .context("Failed to get GPIO pin")?;
+    let [high] = lines.get_values([false])?;
+    Ok(if high { &config.high } else { &config.low }.clone())
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    let reason = fs::read_to_string(path)?;
+    let reason = reason.trim();
+    anyhow::ensure!(!reason.is_empty(), "{:?} is empty", path);
+    Ok(reason.to_string())
+}
+
+fn read_bootstatus(path: &Path) -> Result<String> {
+    let text = fs::read_to_string(path)?;
+    let status: u32 = text.trim().parse().context("Invalid bootstatus")?;
+    Ok(decode_bootstatus(status))
+}
+
+/// Nothing set means a plain power-on
+fn decode_bootstatus(status: u32) -> String {
+    let reasons: Vec<_> = BOOTSTATUS_FLAGS
+        .iter()
+        .filter(|(flag, _)| status & flag != 0)
+        .map(|(_, reason)| *reason)
+        .collect();
+    if reasons.is_empty() {
+        String::from("power-on")
+    } else {
+        reasons.join("+")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reset_reason() -> Result<()> {
+        assert_eq!(decode_bootstatus(0), "power-on");
+        assert_eq!(decode_bootstatus(0x20), "watchdog");
+        assert_eq!(decode_bootstatus(0x30), "brown-out+watchdog");
+
+        let dir = std::env::temp_dir().join(format!("watchdog-reason-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("bootstatus"), "32\n")?;
+        fs::write(dir.join("reason"), "brown-out\n")?;
+        let mut config = ResetReasonConfig {
+            file: Some(dir.join("missing")),
+            bootstatus: Some(dir.join("bootstatus")),
+            gpio: None,
+        };
+        assert_eq!(detect(&config), "watchdog");
+        config.file = Some(dir.join("reason"));
+        assert_eq!(detect(&config), "brown-out");
+        assert_eq!(detect(&ResetReasonConfig::default()), "unknown");
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
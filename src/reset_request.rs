@@ -0,0 +1,67 @@
+use crate::{
+    config::ResetRequestConfig,
+    exit::Cause,
+    petter::{Backend, Gpio},
+};
+use anyhow::{Context, Result};
+use log::warn;
+use std::{thread, time::Duration};
+
+/// The RESET_REQ line to the supervisor, asserted for a commanded reset so that it reboots the
+/// card in an orderly way rather than waiting for the watchdog to bite. The petting stops either
+/// way, in case the request goes unheard. Otherwise only a bite with the pulse action touches it.
+pub struct ResetRequest {
+    backend: Box<dyn Backend>,
+    active_low: bool,
+}
+
+impl ResetRequest {
+    pub fn new(config: &ResetRequestConfig) -> Result<Self> {
+        let gpio = Gpio::request(&config.chip, &config.label, config.line)
+            .context("Failed to request the reset request line")
+            .context(Cause::Gpio)?;
+        Self::with_backend(Box::new(gpio), config.active_low)
+    }
+
+    /// Starts out deasserted
+    pub fn with_backend(mut backend: Box<dyn Backend>, active_low: bool) -> Result<Self> {
+        backend.set(active_low)?;
+        Ok(Self {
+            backend,
+            active_low,
+        })
+    }
+
+    /// Held until the supervisor resets the card
+    pub fn assert(&mut self) -> Result<()> {
+        warn!("Requesting a reset from the supervisor");
+        self.backend.set(!self.active_low)
+    }
+
+    /// Asserted for `duration`, then released
+    pub fn pulse(&mut self, duration: Duration) -> Result<()> {
+        warn!("Pulsing the reset request line");
+        self.backend.set(!self.active_low)?;
+        thread::sleep(duration);
+        self.backend.set(self.active_low)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::petter::Mock;
+
+    #[test]
+    fn test_reset_request() -> Result<()> {
+        let mock = Mock::default();
+        let mut request = ResetRequest::with_backend(Box::new(mock.clone()), true)?;
+        request.assert()?;
+        assert_eq!(mock.values(), [true, false]);
+        let mock = Mock::default();
+        let mut request = ResetRequest::with_backend(Box::new(mock.clone()), false)?;
+        request.pulse(Duration::from_millis(1))?;
+        assert_eq!(mock.values(), [false, true, false]);
+        Ok(())
+    }
+}
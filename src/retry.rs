@@ -0,0 +1,108 @@
+use crate::config::RetryConfig;
+use anyhow::Result;
+use log::warn;
+use nix::errno::Errno;
+use std::{io, thread, time::Duration};
+
+/// Errnos worth trying again, rather than resetting the card over them
+const TRANSIENT: [Errno; 5] = [
+    Errno::EINTR,
+    Errno::EIO,
+    Errno::ENOBUFS,
+    Errno::ENOMEM,
+    Errno::EBUSY,
+];
+
+/// Retries the event loop's handlers on transient errors, backing off in between. Only once
+/// `threshold` of them failed in a row is the error returned, a success starts the count over.
+pub struct Retry {
+    threshold: u32,
+    backoff: Duration,
+    failures: u32,
+}
+
+impl Retry {
+    pub fn new(config: &RetryConfig) -> Self {
+        Self {
+            threshold: config.threshold,
+            backoff: config.backoff,
+            failures: 0,
+        }
+    }
+
+    /// Runs `handler` until it succeeds, fails for good, or the threshold is reached. It has to
+    /// be safe to call again after failing, like the handlers that read until would block.
+    pub fn run<T>(&mut self, mut handler: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut backoff = self.backoff;
+        loop {
+            match handler() {
+                Ok(value) => {
+                    self.failures = 0;
+                    return Ok(value);
+                }
+                Err(e) if transient(&e) && self.failures + 1 < self.threshold => {
+                    self.failures += 1;
+                    warn!(
+                        "Transient error, retrying in {:?} ({}/{}): {:#}",
+                        backoff, self.failures, self.threshold, e
+                    );
+                    thread::sleep(backoff);
+                    // Short enough that the retries can't make the pet late by themselves
+                    backoff = (backoff * 2).min(Duration::from_millis(100));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn transient(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        let errno = match cause.downcast_ref::<io::Error>() {
+            Some(e) => e.raw_os_error().map(Errno::from_i32),
+            None => cause.downcast_ref::<Errno>().copied(),
+        };
+        errno.is_some_and(|errno| TRANSIENT.contains(&errno))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, exit::Cause};
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn test_retry() -> Result<()> {
+        let config = Config::parse("[retry]\nthreshold = 3\nbackoff = 0.001")?;
+        let mut retry = Retry::new(&config.retry);
+
+        let mut calls = 0;
+        let value = retry.run(|| {
+            calls += 1;
+            match calls {
+                1 => Err(Errno::EINTR).context("Failed to pet"),
+                _ => Ok(calls),
+            }
+        })?;
+        assert_eq!(value, 2);
+
+        // Given up on after the threshold, with the cause intact
+        let mut calls = 0;
+        let result: Result<()> = retry.run(|| {
+            calls += 1;
+            Err(io::Error::from_raw_os_error(libc::EIO)).context(Cause::Socket)
+        });
+        assert_eq!(calls, 3);
+        assert_eq!(Cause::of(&result.unwrap_err()), Cause::Socket);
+
+        let mut calls = 0;
+        let result: Result<()> = retry.run(|| {
+            calls += 1;
+            Err(anyhow!("Ping timeout"))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,23 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Where the `index`th newest rotated file of `path` is, counting from 1
+pub fn rotated(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+/// Moves `path` to .1, .1 to .2 and so on, keeping `files` of them. Only renames, each of which
+/// is atomic, so that every file is always either whole or not there. The caller reopens `path`.
+pub fn shift(path: &Path, files: usize) -> io::Result<()> {
+    for index in (1..files).rev() {
+        let from = rotated(path, index);
+        if from.exists() {
+            fs::rename(&from, rotated(path, index + 1))?;
+        }
+    }
+    fs::rename(path, rotated(path, 1))
+}
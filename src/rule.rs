@@ -0,0 +1,521 @@
+use crate::clock::wall_now;
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Deserializer};
+use std::{fmt, iter::Peekable, str::FromStr};
+#[cfg(target_os = "linux")]
+use {
+    crate::{
+        clock::{Clock, Timer},
+        config::RulesConfig,
+        exit::Cause,
+        pingee::Pingee,
+    },
+    log::{info, warn},
+    mio::{Registry, Token},
+    nix::time::{clock_gettime, ClockId},
+    std::time::Duration,
+};
+
+/// What a reset rule can look at
+pub const RESET: &[&str] = &["cause", "hour", "uptime", "armed", "inhibited", "inhibits"];
+/// What an inhibit rule can look at
+pub const INHIBIT: &[&str] = &[
+    "duration",
+    "name",
+    "requester",
+    "hour",
+    "armed",
+    "inhibited",
+    "inhibits",
+];
+
+/// Nesting past this is refused, so that an uplinked rule can't overflow the stack
+const MAX_DEPTH: usize = 32;
+
+/// A mission policy for a decision the watchdog makes, uplinked in the config instead of a new
+/// binary. There are only expressions over read-only variables, no loops, calls, or assignments,
+/// so evaluating one is bounded by its length and can't touch anything:
+///
+/// ```text
+/// rule       := any
+/// any        := all ("OR" all)*
+/// all        := not ("AND" not)*
+/// not        := "NOT" not | comparison
+/// comparison := sum (("==" | "!=" | "<" | "<=" | ">" | ">=") sum)?
+/// sum        := product (("+" | "-") product)*
+/// product    := unary (("*" | "/") unary)*
+/// unary      := "-" unary | <number> | "<text>" | "TRUE" | "FALSE" | <variable> | "(" any ")"
+/// ```
+///
+/// Keywords are case insensitive, times are in seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    Value(Value),
+    Variable(String),
+    Not(Box<Rule>),
+    Negate(Box<Rule>),
+    Binary(Operator, Box<Rule>, Box<Rule>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Bool(bool),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Rule {
+    /// Whether it holds with the variables `variables` gives, an error if it isn't a condition
+    pub fn holds(&self, variables: &impl Fn(&str) -> Option<Value>) -> Result<bool> {
+        match self.eval(variables)? {
+            Value::Bool(holds) => Ok(holds),
+            value => bail!("{} is {}, not true or false", self, value),
+        }
+    }
+
+    /// Every variable it names
+    pub fn variables(&self) -> Vec<&str> {
+        match self {
+            Rule::Value(_) => Vec::new(),
+            Rule::Variable(name) => vec![name.as_str()],
+            Rule::Not(rule) | Rule::Negate(rule) => rule.variables(),
+            Rule::Binary(_, left, right) => {
+                let mut names = left.variables();
+                names.extend(right.variables());
+                names
+            }
+        }
+    }
+
+    fn eval(&self, variables: &impl Fn(&str) -> Option<Value>) -> Result<Value> {
+        match self {
+            Rule::Value(value) => Ok(value.clone()),
+            Rule::Variable(name) => variables(name).with_context(|| format!("Unknown {}", name)),
+            Rule::Not(rule) => Ok(Value::Bool(!rule.holds(variables)?)),
+            Rule::Negate(rule) => Ok(Value::Number(-rule.eval(variables)?.number()?)),
+            // Short circuited, so that the right side can rely on the left
+            Rule::Binary(Operator::Or, left, right) => Ok(Value::Bool(
+                left.holds(variables)? || right.holds(variables)?,
+            )),
+            Rule::Binary(Operator::And, left, right) => Ok(Value::Bool(
+                left.holds(variables)? && right.holds(variables)?,
+            )),
+            Rule::Binary(operator, left, right) => {
+                operator.apply(left.eval(variables)?, right.eval(variables)?)
+            }
+        }
+    }
+}
+
+impl Value {
+    fn number(&self) -> Result<f64> {
+        match self {
+            Value::Number(number) => Ok(*number),
+            value => bail!("{} is not a number", value),
+        }
+    }
+}
+
+impl Operator {
+    fn apply(self, left: Value, right: Value) -> Result<Value> {
+        use Operator::*;
+        Ok(match self {
+            Equal => Value::Bool(left == right),
+            NotEqual => Value::Bool(left != right),
+            Less => Value::Bool(left.number()? < right.number()?),
+            LessOrEqual => Value::Bool(left.number()? <= right.number()?),
+            Greater => Value::Bool(left.number()? > right.number()?),
+            GreaterOrEqual => Value::Bool(left.number()? >= right.number()?),
+            Add => Value::Number(left.number()? + right.number()?),
+            Subtract => Value::Number(left.number()? - right.number()?),
+            Multiply => Value::Number(left.number()? * right.number()?),
+            Divide => {
+                let divisor = right.number()?;
+                ensure!(divisor != 0.0, "Division by zero");
+                Value::Number(left.number()? / divisor)
+            }
+            Or | And => unreachable!(),
+        })
+    }
+
+    fn symbol(self) -> &'static str {
+        use Operator::*;
+        match self {
+            Or => "OR",
+            And => "AND",
+            Equal => "==",
+            NotEqual => "!=",
+            Less => "<",
+            LessOrEqual => "<=",
+            Greater => ">",
+            GreaterOrEqual => ">=",
+            Add => "+",
+            Subtract => "-",
+            Multiply => "*",
+            Divide => "/",
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let context = || format!("Invalid rule {:?}", text);
+        let mut tokens = tokenize(text).with_context(context)?.into_iter().peekable();
+        let rule = any(&mut tokens, 0).with_context(context)?;
+        if let Some(token) = tokens.next() {
+            bail!("Invalid rule {:?}: unexpected {}", text, token);
+        }
+        Ok(rule)
+    }
+}
+
+impl<'de> Deserialize<'de> for Rule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Fully parenthesized, so that it reads back the same
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::Value(value) => write!(f, "{}", value),
+            Rule::Variable(name) => f.write_str(name),
+            Rule::Not(rule) => write!(f, "NOT {}", rule),
+            Rule::Negate(rule) => write!(f, "-{}", rule),
+            Rule::Binary(operator, left, right) => {
+                write!(f, "({} {} {})", left, operator.symbol(), right)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{}", number),
+            Value::Bool(true) => f.write_str("TRUE"),
+            Value::Bool(false) => f.write_str("FALSE"),
+            Value::Text(text) => write!(f, "\"{}\"", text),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Lexeme {
+    Number(f64),
+    Text(String),
+    Word(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Lexeme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lexeme::Number(number) => write!(f, "{}", number),
+            Lexeme::Text(text) => write!(f, "\"{}\"", text),
+            Lexeme::Word(word) => f.write_str(word),
+            Lexeme::Symbol(symbol) => f.write_str(symbol),
+        }
+    }
+}
+
+type Lexemes = Peekable<std::vec::IntoIter<Lexeme>>;
+
+const SYMBOLS: [&str; 12] = [
+    "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "(", ")",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Lexeme>> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c == '"' {
+            let end = rest[1..].find('"').context("Unterminated text")?;
+            tokens.push(Lexeme::Text(rest[1..end + 1].to_string()));
+            end + 2
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            tokens.push(Lexeme::Symbol(symbol));
+            symbol.len()
+        } else if c.is_ascii_digit() || c == '.' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            let number = rest[..len]
+                .parse()
+                .with_context(|| format!("Invalid number {:?}", &rest[..len]))?;
+            tokens.push(Lexeme::Number(number));
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Lexeme::Word(rest[..len].to_string()));
+            len
+        } else {
+            bail!("Unexpected {:?}", c);
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+fn keyword(tokens: &mut Lexemes, keyword: &str) -> bool {
+    let matched = matches!(tokens.peek(), Some(Lexeme::Word(w)) if w.eq_ignore_ascii_case(keyword));
+    if matched {
+        tokens.next();
+    }
+    matched
+}
+
+fn symbol(tokens: &mut Lexemes, symbols: &[(&str, Operator)]) -> Option<Operator> {
+    let Some(Lexeme::Symbol(symbol)) = tokens.peek() else {
+        return None;
+    };
+    let (_, operator) = symbols.iter().find(|(s, _)| s == symbol)?;
+    tokens.next();
+    Some(*operator)
+}
+
+fn binary(operator: Operator, left: Rule, right: Rule) -> Rule {
+    Rule::Binary(operator, Box::new(left), Box::new(right))
+}
+
+fn any(tokens: &mut Lexemes, depth: usize) -> Result<Rule> {
+    ensure!(depth < MAX_DEPTH, "Nested deeper than {}", MAX_DEPTH);
+    let mut rule = all(tokens, depth)?;
+    while keyword(tokens, "OR") {
+        rule = binary(Operator::Or, rule, all(tokens, depth)?);
+    }
+    Ok(rule)
+}
+
+fn all(tokens: &mut Lexemes, depth: usize) -> Result<Rule> {
+    let mut rule = not(tokens, depth)?;
+    while keyword(tokens, "AND") {
+        rule = binary(Operator::And, rule, not(tokens, depth)?);
+    }
+    Ok(rule)
+}
+
+fn not(tokens: &mut Lexemes, depth: usize) -> Result<Rule> {
+    if keyword(tokens, "NOT") {
+        ensure!(depth < MAX_DEPTH, "Nested deeper than {}", MAX_DEPTH);
+        return Ok(Rule::Not(Box::new(not(tokens, depth + 1)?)));
+    }
+    comparison(tokens, depth)
+}
+
+fn comparison(tokens: &mut Lexemes, depth: usize) -> Result<Rule> {
+    use Operator::*;
+    let symbols = [
+        ("==", Equal),
+        ("!=", NotEqual),
+        ("<", Less),
+        ("<=", LessOrEqual),
+        (">", Greater),
+        (">=", GreaterOrEqual),
+    ];
+    let left = sum(tokens, depth)?;
+    match symbol(tokens, &symbols) {
+        Some(operator) => Ok(binary(operator, left, sum(tokens, depth)?)),
+        None => Ok(left),
+    }
+}
+
+fn sum(tokens: &mut Lexemes, depth: usize) -> Result<Rule> {
+    let symbols = [("+", Operator::Add), ("-", Operator::Subtract)];
+    let mut rule = product(tokens, depth)?;
+    while let Some(operator) = symbol(tokens, &symbols) {
+        rule = binary(operator, rule, product(tokens, depth)?);
+    }
+    Ok(rule)
+}
+
+fn product(tokens: &mut Lexemes, depth: usize) -> Result<Rule> {
+    let symbols = [("*", Operator::Multiply), ("/", Operator::Divide)];
+    let mut rule = unary(tokens, depth)?;
+    while let Some(operator) = symbol(tokens, &symbols) {
+        rule = binary(operator, rule, unary(tokens, depth)?);
+    }
+    Ok(rule)
+}
+
+fn unary(tokens: &mut Lexemes, depth: usize) -> Result<Rule> {
+    ensure!(depth < MAX_DEPTH, "Nested deeper than {}", MAX_DEPTH);
+    match tokens.next().context("Missing a value")? {
+        Lexeme::Symbol("-") => Ok(Rule::Negate(Box::new(unary(tokens, depth + 1)?))),
+        Lexeme::Symbol("(") => {
+            let rule = any(tokens, depth + 1)?;
+            ensure!(tokens.next() == Some(Lexeme::Symbol(")")), "Missing )");
+            Ok(rule)
+        }
+        Lexeme::Number(number) => Ok(Rule::Value(Value::Number(number))),
+        Lexeme::Text(text) => Ok(Rule::Value(Value::Text(text))),
+        Lexeme::Word(word) if word.eq_ignore_ascii_case("TRUE") => {
+            Ok(Rule::Value(Value::Bool(true)))
+        }
+        Lexeme::Word(word) if word.eq_ignore_ascii_case("FALSE") => {
+            Ok(Rule::Value(Value::Bool(false)))
+        }
+        Lexeme::Word(word)
+            if ["AND", "OR", "NOT"]
+                .iter()
+                .any(|k| word.eq_ignore_ascii_case(k)) =>
+        {
+            bail!("Unexpected {}", word)
+        }
+        Lexeme::Word(word) => Ok(Rule::Variable(word)),
+        token => bail!("Unexpected {}", token),
+    }
+}
+
+/// The hour of the day in UTC, for rules keeping to a time of day
+pub fn hour() -> Value {
+    Value::Number((wall_now().rem_euclid(86400) / 3600) as f64)
+}
+
+/// Holds deliberate resets back for as long as the reset rule doesn't hold, checking it again
+/// each `interval`. A rule that can't be evaluated doesn't hold anything back, like no rule.
+#[cfg(target_os = "linux")]
+pub struct Rules {
+    reset: Option<Rule>,
+    interval: Duration,
+    timer: Box<dyn Timer>,
+    /// A reset waiting for the rule to hold
+    deferred: Option<(String, Cause)>,
+}
+
+#[cfg(target_os = "linux")]
+impl Rules {
+    pub fn new(config: &RulesConfig, clock: &dyn Clock) -> Result<Self> {
+        Ok(Self {
+            reset: config.reset.clone(),
+            interval: config.interval,
+            timer: clock.timer()?,
+            deferred: None,
+        })
+    }
+
+    pub fn register(&self, registry: &Registry, token: Token) -> Result<()> {
+        self.timer.register(registry, token)
+    }
+
+    /// A deliberate reset, passed on if the rule allows it now. Otherwise it's returned by
+    /// `on_timer` once it does.
+    pub fn allow(
+        &mut self,
+        reset: Option<(String, Cause)>,
+        pingee: &Pingee,
+    ) -> Result<Option<(String, Cause)>> {
+        let Some((reason, cause)) = reset else {
+            return Ok(None);
+        };
+        if self.holds(cause, pingee) {
+            return Ok(Some((reason, cause)));
+        }
+        info!("{}, held back by the reset rule", reason);
+        self.deferred = Some((reason, cause));
+        self.timer.set(self.interval)?;
+        Ok(None)
+    }
+
+    /// The reset due, if the rule holds by now
+    pub fn on_timer(&mut self, pingee: &Pingee) -> Result<Option<(String, Cause)>> {
+        let Some((_, cause)) = &self.deferred else {
+            return Ok(None);
+        };
+        if !self.holds(*cause, pingee) {
+            self.timer.set(self.interval)?;
+            return Ok(None);
+        }
+        Ok(self.deferred.take())
+    }
+
+    fn holds(&self, cause: Cause, pingee: &Pingee) -> bool {
+        let Some(rule) = &self.reset else {
+            return true;
+        };
+        let variables = |name: &str| match name {
+            "cause" => Some(Value::Text(cause.name().to_string())),
+            "hour" => Some(hour()),
+            "uptime" => clock_gettime(ClockId::CLOCK_BOOTTIME)
+                .ok()
+                .map(|t| Value::Number(Duration::from(t).as_secs_f64())),
+            name => pingee.variable(name),
+        };
+        rule.holds(&variables).unwrap_or_else(|e| {
+            warn!("Failed to evaluate the reset rule: {:#}", e);
+            true
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rule() -> Result<()> {
+        let variables = |name: &str| match name {
+            "cause" => Some(Value::Text(String::from("max_uptime"))),
+            "hour" => Some(Value::Number(14.0)),
+            "armed" => Some(Value::Bool(true)),
+            "duration" => Some(Value::Number(600.0)),
+            _ => None,
+        };
+        let holds = |text: &str| text.parse::<Rule>()?.holds(&variables);
+        assert!(holds(r#"cause != "max_uptime" OR hour < 6 OR hour >= 12"#)?);
+        assert!(!holds("not armed")?);
+        assert!(holds("duration <= 5 * 60 + 300 and -hour == 0 - 14")?);
+        assert!(holds("(hour - 2) / 3 == 4")?);
+        // Short circuited past what can't be evaluated
+        assert!(holds("TRUE or missing")?);
+        assert!(holds("missing").is_err());
+        assert!(holds("hour + 1").is_err());
+        assert!(holds("armed < 1").is_err());
+        assert!(holds("hour / 0 == 1").is_err());
+
+        let rule: Rule = "NOT armed AND hour < 6 OR cause == \"scheduled_cycle\"".parse()?;
+        assert_eq!(
+            rule.to_string(),
+            "((NOT armed AND (hour < 6)) OR (cause == \"scheduled_cycle\"))"
+        );
+        assert_eq!(rule.to_string().parse::<Rule>()?, rule);
+        assert_eq!(rule.variables(), ["armed", "hour", "cause"]);
+
+        for invalid in [
+            "",
+            "hour <",
+            "(armed",
+            "armed)",
+            "\"open",
+            "hour # 2",
+            "AND armed",
+        ] {
+            assert!(invalid.parse::<Rule>().is_err(), "{}", invalid);
+        }
+        assert!("(".repeat(40).parse::<Rule>().is_err());
+        assert!("-".repeat(40).parse::<Rule>().is_err());
+        Ok(())
+    }
+}
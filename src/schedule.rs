@@ -0,0 +1,355 @@
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Deserializer};
+use std::str::FromStr;
+#[cfg(target_os = "linux")]
+use {
+    crate::{
+        clock::{wall_now, WallEvent, WallTimer},
+        config::ScheduleConfig,
+        exit::Cause,
+    },
+    log::{info, warn},
+    mio::{unix::SourceFd, Interest, Registry, Token},
+    std::time::Duration,
+};
+
+/// Far enough ahead for any date that exists at all, like February 29 on a Monday
+const SEARCH_DAYS: i64 = 366 * 28;
+
+/// A cron expression, "minute hour day month weekday", in UTC. Fields take `*`, numbers, ranges,
+/// lists, and steps. Like cron, a day matches either field if both are restricted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    /// Whether the minute starting at or containing `at`, in Unix seconds, matches
+    pub fn matches(&self, at: i64) -> bool {
+        let (day, minute) = (at.div_euclid(86400), at.rem_euclid(86400) / 60);
+        self.matches_day(day) && self.matches_time(minute / 60, minute % 60)
+    }
+
+    /// The first matching minute strictly after `after`, in Unix seconds
+    pub fn next(&self, after: i64) -> Option<i64> {
+        let start = (after.div_euclid(60) + 1) * 60;
+        let first = start.div_euclid(86400);
+        for day in (first..first + SEARCH_DAYS).filter(|&day| self.matches_day(day)) {
+            for hour in 0..24 {
+                for minute in 0..60 {
+                    let at = day * 86400 + hour * 3600 + minute * 60;
+                    if at >= start && self.matches_time(hour, minute) {
+                        return Some(at);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, day: i64) -> bool {
+        let (_, month, date) = civil(day);
+        // January 1 1970 was a Thursday
+        let weekday = (day + 4).rem_euclid(7);
+        let date = bit(self.days, date);
+        let weekday = bit(self.weekdays, weekday);
+        let day = match (self.any_day, self.any_weekday) {
+            (false, false) => date || weekday,
+            _ => date && weekday,
+        };
+        day && bit(self.months, month)
+    }
+
+    fn matches_time(&self, hour: i64, minute: i64) -> bool {
+        bit(self.hours, hour) && bit(self.minutes, minute)
+    }
+}
+
+fn bit(mask: u64, index: i64) -> bool {
+    mask & 1 << index != 0
+}
+
+/// The year, month, and day of the month of the `day`th day since the epoch
+fn civil(day: i64) -> (i64, i64, i64) {
+    // Hinnant's days_from_civil, backwards, with eras of 400 years starting on March 1
+    let day = day + 719468;
+    let era = day.div_euclid(146097);
+    let of_era = day.rem_euclid(146097);
+    let year_of_era = (of_era - of_era / 1460 + of_era / 36524 - of_era / 146096) / 365;
+    let of_year = of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * of_year + 2) / 153;
+    let date = of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, date)
+}
+
+/// `at`, in Unix seconds, for the log
+pub fn format_utc(at: i64) -> String {
+    let (year, month, date) = civil(at.div_euclid(86400));
+    let minute = at.rem_euclid(86400) / 60;
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        date,
+        minute / 60,
+        minute % 60
+    )
+}
+
+fn field(text: &str, min: i64, max: i64) -> Result<u64> {
+    let mut mask = 0;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse()?),
+            None => (part, 1),
+        };
+        ensure!(step > 0, "Zero step in {:?}", text);
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (first.parse()?, last.parse()?),
+            // A single value with a step runs to the end, like cron
+            None if part.contains('/') => (range.parse()?, max),
+            None => (range.parse()?, range.parse()?),
+        };
+        ensure!(
+            min <= first && first <= last && last <= max,
+            "{:?} is outside of {}-{}",
+            part,
+            min,
+            max
+        );
+        for value in (first..=last).step_by(step) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+impl FromStr for Cron {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let fields: Vec<_> = text.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            bail!("Expected 5 fields in {:?}", text);
+        };
+        let context = || format!("Invalid cron expression {:?}", text);
+        let mut weekday_mask = field(weekdays, 0, 7).with_context(context)?;
+        // Sunday is either 0 or 7
+        if bit(weekday_mask, 7) {
+            weekday_mask |= 1;
+        }
+        Ok(Self {
+            minutes: field(minutes, 0, 59).with_context(context)?,
+            hours: field(hours, 0, 23).with_context(context)?,
+            days: field(days, 1, 31).with_context(context)?,
+            months: field(months, 1, 12).with_context(context)?,
+            weekdays: weekday_mask,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Cron {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// The windows in which deliberate resets may happen, e.g. outside of ground station passes.
+/// Resets outside of them are deferred to the next one opening. Timed on the wall clock, so the
+/// timer is armed for the next opening again whenever the time is set. Setting it back never
+/// resets, as an old window could look open again.
+#[cfg(target_os = "linux")]
+pub struct Schedule {
+    windows: Vec<Cron>,
+    duration: Duration,
+    required: bool,
+    timer: WallTimer,
+    /// A reset waiting for the next window
+    deferred: Option<(String, Cause)>,
+}
+
+#[cfg(target_os = "linux")]
+impl Schedule {
+    pub fn new(config: &ScheduleConfig) -> Result<Self> {
+        let mut schedule = Self {
+            windows: config.windows.clone(),
+            duration: config.duration,
+            required: config.required,
+            timer: WallTimer::new()?,
+            deferred: None,
+        };
+        schedule.arm(wall_now())?;
+        Ok(schedule)
+    }
+
+    pub fn register(&self, registry: &Registry, token: Token) -> Result<()> {
+        let fd = self.timer.fd();
+        registry.register(&mut SourceFd(&fd), token, Interest::READABLE)?;
+        Ok(())
+    }
+
+    /// A deliberate reset, returned if it can happen now. Otherwise it's returned by `on_timer`
+    /// once a window opens.
+    pub fn request(&mut self, reason: String, cause: Cause) -> Result<Option<(String, Cause)>> {
+        let now = wall_now();
+        if self.windows.is_empty() || self.open(now) {
+            return Ok(Some((reason, cause)));
+        }
+        match self.next(now) {
+            Some(next) => {
+                info!("{}, deferred to {}", reason, format_utc(next));
+                self.deferred = Some((reason, cause));
+                self.arm(now)?;
+                Ok(None)
+            }
+            None => {
+                warn!("No reset window ever opens");
+                Ok(Some((reason, cause)))
+            }
+        }
+    }
+
+    /// The reset due, if a window opened
+    pub fn on_timer(&mut self) -> Result<Option<(String, Cause)>> {
+        let event = self
+            .timer
+            .on_event()
+            .context("Failed to read the schedule timer")?;
+        let now = wall_now();
+        let due = match event {
+            WallEvent::Pending => return Ok(None),
+            WallEvent::Expired => match self.deferred.take() {
+                Some(deferred) => Some(deferred),
+                None if self.required => Some((
+                    format!("Reset window opened at {}", format_utc(now)),
+                    Cause::Scheduled,
+                )),
+                None => None,
+            },
+            WallEvent::Stepped(step) => {
+                warn!("The wall clock was set by {}s, rescheduling", step);
+                // Jumping into a window only counts going forward, a required one still waits
+                // for the next opening
+                match step > 0 && self.open(now) {
+                    true => self.deferred.take(),
+                    false => None,
+                }
+            }
+        };
+        self.arm(now)?;
+        Ok(due)
+    }
+
+    fn open(&self, at: i64) -> bool {
+        let opened = at - self.duration.as_secs() as i64;
+        let mut minutes = (opened.div_euclid(60)..=at.div_euclid(60)).map(|minute| minute * 60);
+        minutes.any(|minute| {
+            minute + self.duration.as_secs() as i64 > at
+                && self.windows.iter().any(|window| window.matches(minute))
+        })
+    }
+
+    fn next(&self, after: i64) -> Option<i64> {
+        self.windows.iter().filter_map(|w| w.next(after)).min()
+    }
+
+    /// For the next opening, if anything waits for it
+    fn arm(&mut self, now: i64) -> Result<()> {
+        match self.next(now) {
+            Some(next) if self.required || self.deferred.is_some() => self.timer.set(next),
+            _ => self.timer.unset(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tuesday 2024-01-02 03:04 UTC
+    const TUESDAY: i64 = 1704164640;
+
+    #[test]
+    fn test_cron() -> Result<()> {
+        assert_eq!(format_utc(TUESDAY), "2024-01-02 03:04 UTC");
+        assert_eq!(format_utc(951782400), "2000-02-29 00:00 UTC");
+
+        let nightly: Cron = "30 2 * * *".parse()?;
+        assert_eq!(
+            format_utc(nightly.next(TUESDAY).unwrap()),
+            "2024-01-03 02:30 UTC"
+        );
+        assert!(nightly.matches(nightly.next(TUESDAY).unwrap() + 59));
+
+        let weekdays: Cron = "*/15 8-17 * * 1-5".parse()?;
+        assert_eq!(
+            format_utc(weekdays.next(TUESDAY).unwrap()),
+            "2024-01-02 08:00 UTC"
+        );
+        let sunday = "0 0 * * 7".parse::<Cron>()?.next(TUESDAY).unwrap();
+        assert_eq!(format_utc(sunday), "2024-01-07 00:00 UTC");
+        // Either day field, when both are restricted
+        let either = "0 0 5 * 3".parse::<Cron>()?.next(TUESDAY).unwrap();
+        assert_eq!(format_utc(either), "2024-01-03 00:00 UTC");
+        let leap = "0 0 29 2 *".parse::<Cron>()?.next(TUESDAY).unwrap();
+        assert_eq!(format_utc(leap), "2024-02-29 00:00 UTC");
+        assert_eq!("0 0 30 2 *".parse::<Cron>()?.next(TUESDAY), None);
+
+        assert!("* * * *".parse::<Cron>().is_err());
+        assert!("60 * * * *".parse::<Cron>().is_err());
+        assert!("*/0 * * * *".parse::<Cron>().is_err());
+        assert!("5-1 * * * *".parse::<Cron>().is_err());
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_schedule() -> Result<()> {
+        use crate::config::Config;
+
+        let config = Config::parse("[schedule]\nwindows = [\"0 3 * * *\"]\nduration = 600")?;
+        let mut schedule = Schedule::new(&config.schedule)?;
+        assert!(schedule.open(TUESDAY));
+        assert!(!schedule.open(TUESDAY + 600));
+        assert_eq!(
+            format_utc(schedule.next(TUESDAY).unwrap()),
+            "2024-01-03 03:00 UTC"
+        );
+        // Not waiting for anything, so not armed
+        assert!(!schedule.timer.is_set()?);
+
+        let always = Config::parse("[schedule]\nwindows = [\"* * * * *\"]")?;
+        let mut always = Schedule::new(&always.schedule)?;
+        let reset = always.request(String::from("Up for too long"), Cause::Uptime)?;
+        assert_eq!(
+            reset,
+            Some((String::from("Up for too long"), Cause::Uptime))
+        );
+
+        let never = Config::parse("[schedule]\nwindows = [\"0 0 1 1 *\"]\nduration = 60")?;
+        schedule = Schedule::new(&never.schedule)?;
+        if !schedule.open(wall_now()) {
+            let reset = schedule.request(String::from("Up for too long"), Cause::Uptime)?;
+            assert_eq!(reset, None);
+            assert!(schedule.timer.is_set()?);
+            assert_eq!(schedule.on_timer()?, None);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{
+    can::{CanSocket, Frame},
+    config::CanopenConfig,
+    dictionary::{self, Counter},
+    exit::Cause,
+    pingee::Pingee,
+    stats,
+    uptime::MaxUptime,
+};
+use anyhow::Result;
+use log::warn;
+use mio::{unix::SourceFd, Interest, Registry, Token};
+use std::{os::fd::AsRawFd, time::Duration};
+
+/// Function codes of SDO requests and replies, and of EMCY, added to the node id
+const RX: u32 = 0x600;
+const TX: u32 = 0x580;
+const EMCY: u32 = 0x080;
+
+/// Client command specifiers, the top 3 bits of the first byte
+const DOWNLOAD: u8 = 1;
+const UPLOAD: u8 = 2;
+const ABORT: u8 = 4;
+
+const UNSUPPORTED: u32 = 0x0504_0001;
+const READ_ONLY: u32 = 0x0601_0002;
+const NO_OBJECT: u32 = 0x0602_0000;
+const LENGTH: u32 = 0x0607_0010;
+const NO_SUBINDEX: u32 = 0x0609_0011;
+const RANGE: u32 = 0x0609_0030;
+const REFUSED: u32 = 0x0800_0020;
+const NO_DATA: u32 = 0x0800_0024;
+
+/// What the object dictionary reads and writes
+pub struct Objects<'a> {
+    pub pingee: &'a mut Pingee,
+    pub uptime: &'a mut MaxUptime,
+    /// Of the pet pattern, the shortest a client timeout can be set to
+    pub period: Duration,
+}
+
+/// A minimal CANopen SDO server, expedited transfers only, so that the ground can read and set
+/// the watchdog's parameters and counters like any other node's. The objects are:
+///
+/// - 0x2000: armed, u8
+/// - 0x2001: client timeouts in ms, u32, one subindex per client slot
+/// - 0x2002: inhibit remaining in ms, u32, written to inhibit, and inhibits granted, u32
+/// - 0x2003: maximum uptime in s, u32, zero for none
+/// - 0x2010: the counters, u32, read only, one subindex each as `Counter` numbers them
+pub struct Sdo {
+    socket: CanSocket,
+    node_id: u8,
+    writable: bool,
+}
+
+impl Sdo {
+    pub fn new(config: &CanopenConfig) -> Result<Self> {
+        let socket = CanSocket::open(&config.interface)?;
+        socket.filter(&[RX + u32::from(config.node_id)])?;
+        Ok(Self {
+            socket,
+            node_id: config.node_id,
+            writable: config.writable,
+        })
+    }
+
+    pub fn register(&self, registry: &Registry, token: Token) -> Result<()> {
+        let fd = self.socket.as_raw_fd();
+        registry.register(&mut SourceFd(&fd), token, Interest::READABLE)?;
+        Ok(())
+    }
+
+    /// Announces going down: the cause's EMCY code, the generic error bit, then the exit code as
+    /// the manufacturer specific part. Best effort, the card is about to reset either way.
+    pub fn emergency(&self, cause: Cause) {
+        let [low, high] = cause.emcy().to_le_bytes();
+        let payload = [low, high, 0x01, cause as u8, 0, 0, 0, 0];
+        let frame = Frame::new(EMCY + u32::from(self.node_id), &payload);
+        if let Err(e) = frame.and_then(|f| self.socket.send(&f)) {
+            warn!("Failed to send EMCY: {:#}", e);
+        }
+    }
+
+    pub fn on_readable(&self, objects: &mut Objects) -> Result<()> {
+        while let Some(frame) = self.socket.recv()? {
+            if let Some(reply) = handle(frame.payload(), self.writable, objects) {
+                let reply = Frame::new(TX + u32::from(self.node_id), &reply)?;
+                self.socket.send(&reply)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The reply to an SDO request, if any
+fn handle(request: &[u8], writable: bool, objects: &mut Objects) -> Option<[u8; 8]> {
+    let &[command, index_low, index_high, sub, ref data @ ..] = request else {
+        return None;
+    };
+    let index = u16::from_le_bytes([index_low, index_high]);
+    let mut reply = [0, index_low, index_high, sub, 0, 0, 0, 0];
+    let result = match command >> 5 {
+        UPLOAD => read(objects, index, sub).map(|(value, size)| {
+            reply[0] = 0x43 | ((4 - size as u8) << 2);
+            reply[4..].copy_from_slice(&value.to_le_bytes());
+        }),
+        // Only expedited, nothing here is longer than 4 bytes
+        DOWNLOAD if command & 0x02 == 0 => Err(UNSUPPORTED),
+        DOWNLOAD if !writable => Err(READ_ONLY),
+        DOWNLOAD => {
+            let size = (command & 0x01 != 0).then(|| 4 - usize::from((command >> 2) & 0x03));
+            let mut value = [0; 4];
+            let len = data.len().min(size.unwrap_or(4));
+            value[..len].copy_from_slice(&data[..len]);
+            write(objects, index, sub, u32::from_le_bytes(value), size).map(|()| reply[0] = 0x60)
+        }
+        ABORT => return None,
+        _ => Err(UNSUPPORTED),
+    };
+    if let Err(code) = result {
+        reply[0] = 0x80;
+        reply[4..].copy_from_slice(&code.to_le_bytes());
+    }
+    Some(reply)
+}
+
+/// The value and its size in bytes, or the abort code
+fn read(objects: &Objects, index: u16, sub: u8) -> Result<(u32, usize), u32> {
+    let millis = |duration: Duration| duration.as_millis().try_into().unwrap_or(u32::MAX);
+    let pingee = &objects.pingee;
+    match (index, sub) {
+        (0x1000, 0) => Ok((0, 4)),
+        (0x2000, 0) => Ok((pingee.armed().into(), 1)),
+        (0x2001, 0) => Ok((pingee.slots() as u32, 1)),
+        (0x2001, slot) if usize::from(slot) <= pingee.slots() => {
+            let timeout = pingee.timeout(usize::from(slot) - 1).ok_or(NO_DATA)?;
+            Ok((millis(timeout), 4))
+        }
+        (0x2002, 0) => Ok((2, 1)),
+        (0x2002, 1) => Ok((millis(pingee.inhibit_remaining()), 4)),
+        (0x2002, 2) => Ok((pingee.inhibits(), 4)),
+        (0x2003, 0) => Ok((objects.uptime.max().as_secs() as u32, 4)),
+        (dictionary::INDEX, 0) => Ok((Counter::ALL.len() as u32, 1)),
+        (dictionary::INDEX, sub) => {
+            let counter = Counter::at(sub).ok_or(NO_SUBINDEX)?;
+            Ok((stats::counter(counter).try_into().unwrap_or(u32::MAX), 4))
+        }
+        (0x1000 | 0x2000..=0x2003, _) => Err(NO_SUBINDEX),
+        _ => Err(NO_OBJECT),
+    }
+}
+
+/// `size` is the one given by the client, if any
+fn write(
+    objects: &mut Objects,
+    index: u16,
+    sub: u8,
+    value: u32,
+    size: Option<usize>,
+) -> Result<(), u32> {
+    let (_, expected) = read(objects, index, sub)?;
+    if size.is_some_and(|size| size != expected) {
+        return Err(LENGTH);
+    }
+    let refused = |e: anyhow::Error| {
+        warn!("SDO write to {:#06x}:{} failed: {:#}", index, sub, e);
+        REFUSED
+    };
+    let millis = Duration::from_millis(value.into());
+    match (index, sub) {
+        (0x2000, 0) if value > 1 => Err(RANGE),
+        (0x2000, 0) => objects.pingee.arm(value == 1, "SDO").map_err(refused),
+        (0x2001, 1..) if millis < objects.period => Err(RANGE),
+        (0x2001, slot @ 1..) => {
+            let set = objects.pingee.set_timeout(usize::from(slot) - 1, millis);
+            match set.map_err(refused)? {
+                true => Ok(()),
+                false => Err(NO_DATA),
+            }
+        }
+        (0x2002, 1) => objects.pingee.inhibit(millis, "SDO").map_err(refused),
+        (0x2003, 0) => {
+            let max = Duration::from_secs(value.into());
+            objects.uptime.set(max).map_err(refused)
+        }
+        _ => Err(READ_ONLY),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use mio::Token;
+
+    #[test]
+    fn test_sdo() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            timeout = 10
+            [inhibit]
+            enabled = true
+            auth = false
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        let mut uptime = MaxUptime::new(Duration::ZERO)?;
+        let mut objects = Objects {
+            pingee: &mut pingee,
+            uptime: &mut uptime,
+            period: config.pet.period(),
+        };
+        let mut request = |writable, request: [u8; 8]| handle(&request, writable, &mut objects);
+
+        // The c3 client's timeout, in ms
+        let reply = request(false, [0x40, 0x01, 0x20, 1, 0, 0, 0, 0]);
+        assert_eq!(reply, Some([0x43, 0x01, 0x20, 1, 0x10, 0x27, 0, 0]));
+        let armed = request(false, [0x40, 0x00, 0x20, 0, 0, 0, 0, 0]);
+        assert_eq!(armed, Some([0x4f, 0x00, 0x20, 0, 1, 0, 0, 0]));
+
+        // Disarming, only if writable
+        let denied = request(false, [0x2f, 0x00, 0x20, 0, 0, 0, 0, 0]);
+        assert_eq!(denied, Some([0x80, 0x00, 0x20, 0, 0x02, 0x00, 0x01, 0x06]));
+        let done = request(true, [0x2f, 0x00, 0x20, 0, 0, 0, 0, 0]);
+        assert_eq!(done, Some([0x60, 0x00, 0x20, 0, 0, 0, 0, 0]));
+        let armed = request(false, [0x40, 0x00, 0x20, 0, 0, 0, 0, 0]);
+        assert_eq!(armed, Some([0x4f, 0x00, 0x20, 0, 0, 0, 0, 0]));
+
+        // A 20 s timeout, then one shorter than the pet period
+        let done = request(true, [0x23, 0x01, 0x20, 1, 0x20, 0x4e, 0, 0]);
+        assert_eq!(done, Some([0x60, 0x01, 0x20, 1, 0, 0, 0, 0]));
+        let short = request(true, [0x23, 0x01, 0x20, 1, 0x64, 0, 0, 0]);
+        assert_eq!(short, Some([0x80, 0x01, 0x20, 1, 0x30, 0x00, 0x09, 0x06]));
+        // The wrong size, and a subindex past the clients
+        let size = request(true, [0x2b, 0x01, 0x20, 1, 0x10, 0x27, 0, 0]);
+        assert_eq!(size, Some([0x80, 0x01, 0x20, 1, 0x10, 0x00, 0x07, 0x06]));
+        let past = request(false, [0x40, 0x01, 0x20, 2, 0, 0, 0, 0]);
+        assert_eq!(past, Some([0x80, 0x01, 0x20, 2, 0x11, 0x00, 0x09, 0x06]));
+
+        // Inhibiting for a minute, counted
+        let done = request(true, [0x23, 0x02, 0x20, 1, 0x60, 0xea, 0, 0]);
+        assert_eq!(done, Some([0x60, 0x02, 0x20, 1, 0, 0, 0, 0]));
+        let count = request(false, [0x40, 0x02, 0x20, 2, 0, 0, 0, 0]);
+        assert_eq!(count, Some([0x43, 0x02, 0x20, 2, 1, 0, 0, 0]));
+
+        let missing = request(false, [0x40, 0x00, 0x30, 0, 0, 0, 0, 0]);
+        assert_eq!(missing, Some([0x80, 0x00, 0x30, 0, 0x00, 0x00, 0x02, 0x06]));
+        let counters = request(true, [0x23, 0x10, 0x20, 1, 0, 0, 0, 0]);
+        assert_eq!(
+            counters,
+            Some([0x80, 0x10, 0x20, 1, 0x02, 0x00, 0x01, 0x06])
+        );
+        assert_eq!(request(false, [0x80, 0, 0, 0, 0, 0, 0, 0]), None);
+        assert_eq!(pingee.timeout(0), Some(Duration::from_secs(20)));
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+use anyhow::{bail, Result};
+use libc::{c_long, sock_filter, sock_fprog};
+use std::{io::Error, os::fd::RawFd};
+
+// From linux/audit.h, which libc doesn't have
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xC000_003E;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xC000_00B7;
+#[cfg(target_arch = "arm")]
+const AUDIT_ARCH: u32 = 0x4000_0028;
+
+// Offsets into seccomp_data. The args are 64 bits, but fds only use the low half, which comes
+// first on all the supported (little endian) targets.
+const NR: u32 = 0;
+const ARCH: u32 = 4;
+const ARG0: u32 = 16;
+
+/// Everything the event loop does once set up, on every target
+const ALLOWED: &[c_long] = &[
+    // Polling, reading, and writing the timers, sockets, and log
+    libc::SYS_epoll_pwait,
+    libc::SYS_epoll_ctl,
+    libc::SYS_ppoll,
+    libc::SYS_read,
+    libc::SYS_readv,
+    libc::SYS_write,
+    libc::SYS_writev,
+    libc::SYS_recvfrom,
+    libc::SYS_recvmsg,
+    libc::SYS_sendto,
+    libc::SYS_sendmsg,
+    libc::SYS_close,
+    libc::SYS_fcntl,
+    libc::SYS_timerfd_create,
+    libc::SYS_timerfd_settime,
+    libc::SYS_timerfd_gettime,
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_nanosleep,
+    // Whether the clock is synchronized yet, for the startup gates
+    libc::SYS_adjtimex,
+    libc::SYS_clock_adjtime,
+    // Handing over to a successor, including finding the GPIO fd in /proc/self/fd, HTTP probes, and
+    // the ping sockets' buffer size and drop count
+    libc::SYS_accept4,
+    libc::SYS_getpeername,
+    libc::SYS_setsockopt,
+    libc::SYS_getsockopt,
+    // Taking the instance lock once a predecessor that handed over has gone
+    libc::SYS_bind,
+    libc::SYS_getdents64,
+    libc::SYS_readlinkat,
+    // Watching processes that register
+    libc::SYS_pidfd_open,
+    // Writing the last gasp and boot counters
+    libc::SYS_openat,
+    libc::SYS_mkdirat,
+    libc::SYS_renameat2,
+    libc::SYS_unlinkat,
+    libc::SYS_statx,
+    libc::SYS_fstat,
+    libc::SYS_lseek,
+    libc::SYS_fsync,
+    libc::SYS_fdatasync,
+    // Memory, locks, and the rest of the runtime
+    libc::SYS_brk,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_madvise,
+    libc::SYS_futex,
+    libc::SYS_sched_yield,
+    libc::SYS_getrandom,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_restart_syscall,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+];
+
+/// The same on targets that still have the older variants, which glibc prefers there
+#[cfg(target_arch = "x86_64")]
+const ARCH_ALLOWED: &[c_long] = &[
+    libc::SYS_epoll_wait,
+    libc::SYS_poll,
+    libc::SYS_readlink,
+    libc::SYS_mkdir,
+    libc::SYS_rename,
+    libc::SYS_renameat,
+    libc::SYS_unlink,
+    libc::SYS_newfstatat,
+    libc::SYS_mmap,
+];
+#[cfg(target_arch = "aarch64")]
+const ARCH_ALLOWED: &[c_long] = &[libc::SYS_newfstatat, libc::SYS_mmap];
+#[cfg(target_arch = "arm")]
+const ARCH_ALLOWED: &[c_long] = &[
+    libc::SYS_epoll_wait,
+    libc::SYS_poll,
+    libc::SYS_readlink,
+    libc::SYS_mkdir,
+    libc::SYS_rename,
+    libc::SYS_renameat,
+    libc::SYS_unlink,
+    libc::SYS_fstat64,
+    libc::SYS_fstatat64,
+    libc::SYS__llseek,
+    libc::SYS_fcntl64,
+    libc::SYS_mmap2,
+    libc::SYS_sigreturn,
+    // The 64-bit time variants that glibc tries first, which libc doesn't have yet
+    403, // clock_gettime64
+    405, // clock_adjtime64
+    407, // clock_nanosleep_time64
+    410, // timerfd_gettime64
+    411, // timerfd_settime64
+    414, // ppoll_time64
+    422, // futex_time64
+];
+
+/// Restricts this thread, and the threads and processes it starts from now on, to the syscalls
+/// the event loop needs, with ioctl only on the GPIO line and sockets only for IP. Anything else
+/// fails with EPERM, which surfaces as an error instead of the call going through. Threads started
+/// before, like the D-Bus ones, aren't affected.
+pub fn install(gpio: RawFd) -> Result<()> {
+    let filter = filter(gpio);
+    let prog = sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr() as *mut sock_filter,
+    };
+    // SAFETY: prog points to the filter, which outlives the calls, the kernel copies it
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) < 0 {
+            bail!("Failed to set no_new_privs: {}", Error::last_os_error());
+        }
+        if libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER, &prog) < 0 {
+            bail!(
+                "Failed to install seccomp filter: {}",
+                Error::last_os_error()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn filter(gpio: RawFd) -> Vec<sock_filter> {
+    let load = |offset| stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset);
+    let allow = stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW);
+    let deny = stmt(
+        libc::BPF_RET | libc::BPF_K,
+        libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+    );
+
+    // Syscall numbers are only meaningful for the architecture they were made for
+    let mut filter = vec![
+        load(ARCH),
+        jump(AUDIT_ARCH, 1, 0),
+        stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        load(NR),
+    ];
+    for &nr in ALLOWED.iter().chain(ARCH_ALLOWED) {
+        filter.extend([jump(nr as u32, 0, 1), allow]);
+    }
+    // Rebinding a broken ping socket, only IP ones
+    filter.extend([
+        jump(libc::SYS_socket as u32, 0, 5),
+        load(ARG0),
+        jump(libc::AF_INET as u32, 1, 0),
+        jump(libc::AF_INET6 as u32, 0, 1),
+        allow,
+        deny,
+    ]);
+    filter.extend([
+        jump(libc::SYS_ioctl as u32, 0, 3),
+        load(ARG0),
+        jump(gpio as u32, 0, 1),
+        allow,
+        deny,
+    ]);
+    filter
+}
+
+fn stmt(code: u32, k: u32) -> sock_filter {
+    sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+/// Compares the accumulator to `k`, skipping `jt` instructions if equal, otherwise `jf`
+fn jump(k: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter {
+        code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::UdpSocket, os::fd::AsRawFd, thread};
+
+    #[test]
+    fn test_seccomp() -> Result<()> {
+        let gpio = UdpSocket::bind("127.0.0.1:0")?;
+        let other = UdpSocket::bind("127.0.0.1:0")?;
+        let (gpio, other) = (gpio.as_raw_fd(), other.as_raw_fd());
+        // Only the thread installing it is filtered, not the rest of the tests
+        thread::spawn(move || -> Result<()> {
+            install(gpio)?;
+            let mut pending: libc::c_int = 0;
+            // SAFETY: pending outlives the calls, socket has no memory arguments
+            unsafe {
+                assert_eq!(libc::ioctl(gpio, libc::FIONREAD, &mut pending), 0);
+                assert_eq!(libc::ioctl(other, libc::FIONREAD, &mut pending), -1);
+                assert_eq!(Error::last_os_error().raw_os_error(), Some(libc::EPERM));
+                assert_eq!(libc::socket(libc::AF_UNIX, libc::SOCK_DGRAM, 0), -1);
+                assert_eq!(Error::last_os_error().raw_os_error(), Some(libc::EPERM));
+                assert!(libc::getpid() > 0);
+            }
+            // Rebinding the ping socket still works
+            UdpSocket::bind("127.0.0.1:0")?;
+            Ok(())
+        })
+        .join()
+        .unwrap()
+    }
+}
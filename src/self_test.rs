@@ -0,0 +1,74 @@
+use crate::{config::Config, exit::Cause, notify, petter::Petter};
+use anyhow::{ensure, Context, Result};
+use log::{info, warn};
+
+/// Checks that the timings make sense against the pet period and that the line follows a whole
+/// waveform, before anything relies on it. The result goes to the log and to systemd's STATUS.
+pub fn run(config: &Config, petter: &mut Petter) -> Result<()> {
+    let result = timings(config).and_then(|()| {
+        petter
+            .self_test()
+            .context("Pet path failed")
+            .context(Cause::Gpio)
+    });
+    let status = match &result {
+        Ok(true) => String::from("Self-test: PASS"),
+        Ok(false) => String::from("Self-test: PASS (no readback)"),
+        Err(e) => format!("Self-test: FAIL {:#}", e),
+    };
+    match &result {
+        Ok(_) => info!("{}", status),
+        Err(_) => warn!("{}", status),
+    }
+    if let Err(e) = notify::notify(&format!("STATUS={}", status)) {
+        warn!("{:#}", e);
+    }
+    result.map(|_| ())
+}
+
+fn timings(config: &Config) -> Result<()> {
+    let clients = config.clients.iter().map(|c| (&c.name, c.timeout));
+    let ports = config.ports.iter().map(|p| (&p.name, p.timeout));
+    let lines = config.lines.iter().flat_map(|l| &l.clients);
+    let lines = lines.map(|c| (&c.name, c.timeout));
+    let period = config.pet.period();
+    for (name, timeout) in clients.chain(ports).chain(lines) {
+        // Otherwise a single late pet would be the whole window
+        ensure!(
+            timeout >= period,
+            "The {} timeout of {:?} is shorter than the pet period",
+            name,
+            timeout
+        );
+    }
+    ensure!(
+        config.late_pet.threshold < period,
+        "The late pet threshold of {:?} is past the pet period, those are overruns",
+        config.late_pet.threshold
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::petter::Mock;
+
+    #[test]
+    fn test_self_test() -> Result<()> {
+        let mock = Mock::default();
+        let mut petter = Petter::with_backend(Box::new(mock.clone()))?;
+        run(&Config::default(), &mut petter)?;
+        assert_eq!(mock.values(), [true, false]);
+        // The first pet afterwards raises the line again
+        petter.pet()?;
+        assert_eq!(mock.values(), [true, false, true]);
+
+        let short = Config::parse("[[client]]\nname = \"c3\"\ntimeout = 0.5")?;
+        assert!(timings(&short).is_err());
+        let late = Config::parse("[late_pet]\nthreshold = 1")?;
+        assert!(timings(&late).is_err());
+        assert!(timings(&Config::default()).is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{condition::Condition, config::ShmConfig, pingee::Pingee, stats};
+use anyhow::{Context, Result};
+use nix::time::{clock_gettime, ClockId};
+use std::{
+    fs::{File, OpenOptions},
+    io::Error,
+    os::{
+        fd::AsRawFd,
+        unix::fs::{OpenOptionsExt, PermissionsExt},
+    },
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+// A native endian u64 counter, then the CLOCK_MONOTONIC nanoseconds the client last wrote it at
+const SIZE: usize = 16;
+
+/// A small file the client keeps writing a counter and a timestamp to, mapped here and checked on
+/// every pet. It costs the client no syscall per ping and the watchdog no packet, for the fastest
+/// local clients on the slow SoC.
+struct Region {
+    config: ShmConfig,
+    map: NonNull<AtomicU64>,
+    /// The counter as last seen
+    last: u64,
+}
+
+// SAFETY: the mapping is only read through atomics, and it's unmapped only on drop
+unsafe impl Send for Region {}
+
+impl Region {
+    /// Creates the file if needed, readable and writable by anyone so that the client can map it
+    /// whoever it runs as
+    fn map(config: &ShmConfig) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            // What a client already wrote stays
+            .truncate(false)
+            .mode(0o666)
+            .open(&config.path)
+            .with_context(|| format!("Failed to open {:?}", config.path))?;
+        if file.metadata()?.len() < SIZE as u64 {
+            file.set_len(SIZE as u64)?;
+            // Past the umask
+            file.set_permissions(PermissionsExt::from_mode(0o666))?;
+        }
+        let map = mmap(&file).with_context(|| format!("Failed to map {:?}", config.path))?;
+        let mut region = Self {
+            config: config.clone(),
+            map,
+            last: 0,
+        };
+        // Only what's written from now on counts
+        region.last = region.load(0);
+        Ok(region)
+    }
+
+    fn load(&self, index: usize) -> u64 {
+        // SAFETY: the mapping holds SIZE bytes, page aligned, for as long as self
+        unsafe { (*self.map.as_ptr().add(index)).load(Ordering::Acquire) }
+    }
+
+    /// Whether the counter moved since the last check, with a timestamp at most `max_age` old
+    fn fresh(&mut self, now: Duration) -> bool {
+        let counter = self.load(0);
+        let timestamp = Duration::from_nanos(self.load(1));
+        let moved = counter != self.last;
+        self.last = counter;
+        moved && now.saturating_sub(timestamp) <= self.config.max_age
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        // SAFETY: mapped in `map` with this size, and nothing borrows it past self
+        unsafe { libc::munmap(self.map.as_ptr().cast(), SIZE) };
+    }
+}
+
+fn mmap(file: &File) -> Result<NonNull<AtomicU64>> {
+    // SAFETY: a new shared read-only mapping of an fd that's open, checked for failure below
+    let map = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            SIZE,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        )
+    };
+    if map == libc::MAP_FAILED {
+        return Err(Error::last_os_error().into());
+    }
+    Ok(NonNull::new(map.cast()).unwrap())
+}
+
+/// The shared memory regions, each feeding its client on the pets it's fresh for
+pub struct SharedRegions {
+    regions: Vec<Region>,
+}
+
+impl SharedRegions {
+    pub fn new(configs: &[ShmConfig]) -> Result<Self> {
+        let regions = configs.iter().map(Region::map).collect::<Result<_>>()?;
+        Ok(Self { regions })
+    }
+}
+
+impl Condition for SharedRegions {
+    fn name(&self) -> &'static str {
+        "shared memory regions"
+    }
+
+    /// Nothing to poll
+    fn tokens(&self) -> usize {
+        0
+    }
+
+    fn on_event(&mut self, _index: usize, _pingee: &mut Pingee) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn on_pet(&mut self, pingee: &mut Pingee) -> Result<()> {
+        let now = clock_gettime(ClockId::CLOCK_MONOTONIC)?.into();
+        for region in &mut self.regions {
+            if region.fresh(now) {
+                pingee.feed(Some(&region.config.client))?;
+                stats::ping(&region.config.client);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use mio::Token;
+    use std::{fs, os::unix::fs::FileExt};
+
+    fn write(file: &File, counter: u64, timestamp: Duration) -> Result<()> {
+        file.write_at(&(timestamp.as_nanos() as u64).to_ne_bytes(), 8)?;
+        file.write_at(&counter.to_ne_bytes(), 0)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_regions() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("watchdog-shm-{}", std::process::id()));
+        let config = Config::parse(&format!(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "fast"
+            [[shm]]
+            path = {:?}
+            client = "fast"
+            max_age = 1
+            "#,
+            path
+        ))?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        let mut regions = SharedRegions::new(&config.shm)?;
+        assert_eq!(fs::metadata(&path)?.len(), SIZE as u64);
+        let now: Duration = clock_gettime(ClockId::CLOCK_MONOTONIC)?.into();
+        let region = &mut regions.regions[0];
+        assert!(!region.fresh(now));
+
+        let file = OpenOptions::new().write(true).open(&path)?;
+        write(&file, 1, now)?;
+        assert!(region.fresh(now));
+        // Not moved since
+        assert!(!region.fresh(now));
+        write(&file, 2, now - Duration::from_secs(5))?;
+        assert!(!region.fresh(now));
+
+        write(&file, 3, clock_gettime(ClockId::CLOCK_MONOTONIC)?.into())?;
+        regions.on_pet(&mut pingee)?;
+        assert!(stats::last_pings()
+            .iter()
+            .any(|(client, _)| client == "fast"));
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
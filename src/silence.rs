@@ -0,0 +1,83 @@
+use crate::{
+    can::CanSocket,
+    clock::{Clock, Timer},
+    config::CanSilenceConfig,
+};
+use anyhow::Result;
+use log::{info, warn};
+use mio::{unix::SourceFd, Interest, Registry, Token};
+use std::{os::fd::AsRawFd, sync::Arc, time::Duration};
+
+/// Takes the ping timeout path when nothing at all is heard on a CAN bus for a while, as a hung
+/// CAN stack can leave the main loop pinging just fine. Frames sent from this host, like the
+/// death row's warnings, don't count.
+pub struct CanSilence {
+    interface: String,
+    socket: CanSocket,
+    timeout: Duration,
+    timer: Box<dyn Timer>,
+    silent: bool,
+}
+
+impl CanSilence {
+    /// The first frame is waited for for at least `startup`, like the pings
+    pub fn new(
+        config: &CanSilenceConfig,
+        clock: Arc<dyn Clock>,
+        startup: Duration,
+    ) -> Result<Self> {
+        let socket = CanSocket::open(&config.interface)?;
+        let timer = clock.timer()?;
+        timer.set(config.timeout.max(startup))?;
+        Ok(Self {
+            interface: config.interface.clone(),
+            socket,
+            timeout: config.timeout,
+            timer,
+            silent: false,
+        })
+    }
+
+    pub fn register(&self, registry: &Registry, socket: Token, timer: Token) -> Result<()> {
+        let fd = self.socket.as_raw_fd();
+        registry.register(&mut SourceFd(&fd), socket, Interest::READABLE)?;
+        self.timer.register(registry, timer)
+    }
+
+    /// Whether the bus went quiet and hasn't been heard from since
+    pub fn silent(&self) -> bool {
+        self.silent
+    }
+
+    pub fn on_readable(&mut self) -> Result<()> {
+        let mut heard = false;
+        while let Some((_, local)) = self.socket.recv_with_origin()? {
+            heard |= !local;
+        }
+        if heard {
+            self.timer.set(self.timeout)?;
+            if self.silent {
+                info!("CAN traffic on {} again", self.interface);
+                self.silent = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns why that's fatal, unless `fatal` says it isn't right now, e.g. when disarmed. The
+    /// bus then gets another window.
+    pub fn on_timer(&mut self, fatal: bool) -> Result<Option<String>> {
+        // Heard from in the same poll
+        if !self.timer.remaining()?.is_zero() {
+            return Ok(None);
+        }
+        let reason = format!("No CAN frames on {} for {:?}", self.interface, self.timeout);
+        if !fatal {
+            warn!("{}, not fatal now", reason);
+            self.timer.set(self.timeout)?;
+            return Ok(None);
+        }
+        self.silent = true;
+        Ok(Some(reason))
+    }
+}
@@ -0,0 +1,252 @@
+#[cfg(target_os = "linux")]
+use crate::petter::Stopper;
+use crate::{config::HardwareConfig, petter::Backend};
+use anyhow::Result;
+use log::error;
+use std::{
+    fmt,
+    os::fd::RawFd,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// What a process the simulated supervisor reset exits with
+pub const EXIT_CODE: i32 = 10;
+
+// How often starvation is checked for
+const TICK: Duration = Duration::from_millis(5);
+
+/// Why the simulated supervisor bit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bite {
+    /// No rising edge for this long, past the maximum feed interval
+    Starved(Duration),
+    /// Rising edges only this far apart, under the minimum feed interval
+    TooFast(Duration),
+    /// A level only held this long, under the minimum pulse width
+    ShortPulse(Duration),
+}
+
+impl fmt::Display for Bite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bite::Starved(since) => write!(f, "not fed for {:?}", since),
+            Bite::TooFast(feed) => write!(f, "fed again after only {:?}", feed),
+            Bite::ShortPulse(pulse) => write!(f, "{:?} pulse", pulse),
+        }
+    }
+}
+
+/// Called once when it bites, from whichever thread noticed
+pub type Hook = Box<dyn Fn(Bite) + Send + Sync>;
+
+/// The supervisor's side of the line, judged against its datasheet limits
+struct Model {
+    limits: HardwareConfig,
+    high: bool,
+    last_edge: Instant,
+    /// Or when it came up, for the first feed
+    last_feed: Instant,
+    fed: bool,
+    bitten: bool,
+}
+
+impl Model {
+    /// Starts out low, like the line is requested
+    fn new(limits: &HardwareConfig, now: Instant) -> Self {
+        Self {
+            limits: limits.clone(),
+            high: false,
+            last_edge: now,
+            last_feed: now,
+            fed: false,
+            bitten: false,
+        }
+    }
+
+    fn set(&mut self, high: bool, now: Instant) -> Option<Bite> {
+        if self.bitten || high == self.high {
+            return None;
+        }
+        let pulse = now - self.last_edge;
+        self.high = high;
+        self.last_edge = now;
+        // The line sits low for however long before the first pet
+        let bite = if pulse < self.limits.min_pulse && self.fed {
+            Some(Bite::ShortPulse(pulse))
+        } else if high && self.fed && now - self.last_feed < self.limits.min_feed {
+            Some(Bite::TooFast(now - self.last_feed))
+        } else {
+            None
+        };
+        if high {
+            self.last_feed = now;
+            self.fed = true;
+        }
+        self.bite(bite)
+    }
+
+    fn check(&mut self, now: Instant) -> Option<Bite> {
+        let since = now - self.last_feed;
+        let bite = (!self.bitten && since > self.limits.max_feed).then_some(Bite::Starved(since));
+        self.bite(bite)
+    }
+
+    fn bite(&mut self, bite: Option<Bite>) -> Option<Bite> {
+        self.bitten |= bite.is_some();
+        bite
+    }
+}
+
+/// Stands in for the C3's supervisor in sim mode, which would otherwise never bite. It watches
+/// the edges the petter drives and calls the hook the moment the configured hardware limits would
+/// have reset the card, so that a test can tell the pet cadence actually satisfies them.
+pub struct SimulatedSupervisor {
+    backend: Box<dyn Backend>,
+    model: Arc<Mutex<Model>>,
+    hook: Arc<Hook>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl SimulatedSupervisor {
+    /// Counts from now, like the supervisor does from power on
+    pub fn new(backend: Box<dyn Backend>, limits: &HardwareConfig, hook: Hook) -> Self {
+        let model = Arc::new(Mutex::new(Model::new(limits, Instant::now())));
+        let hook = Arc::new(hook);
+        let stopped = Arc::new(AtomicBool::new(false));
+        let (watched, bitten, stopping) = (model.clone(), hook.clone(), stopped.clone());
+        thread::spawn(move || {
+            while !stopping.load(Ordering::Relaxed) {
+                thread::sleep(TICK);
+                let bite = lock(&watched).check(Instant::now());
+                if let Some(bite) = bite {
+                    bitten(bite);
+                    break;
+                }
+            }
+        });
+        Self {
+            backend,
+            model,
+            hook,
+            stopped,
+        }
+    }
+
+    /// The default hook, ending the process like the reset would
+    pub fn reset(bite: Bite) {
+        error!("Simulated supervisor reset the card: {}", bite);
+        std::process::exit(EXIT_CODE);
+    }
+}
+
+impl Backend for SimulatedSupervisor {
+    fn set(&mut self, value: bool) -> Result<()> {
+        self.backend.set(value)?;
+        let bite = lock(&self.model).set(value, Instant::now());
+        if let Some(bite) = bite {
+            (self.hook)(bite);
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<Option<bool>> {
+        self.backend.get()
+    }
+
+    fn fd(&self) -> Result<RawFd> {
+        self.backend.fd()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn stopper(&self) -> Option<Stopper> {
+        self.backend.stopper()
+    }
+}
+
+impl Drop for SimulatedSupervisor {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+fn lock(model: &Mutex<Model>) -> std::sync::MutexGuard<'_, Model> {
+    model.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::petter::Mock;
+
+    fn limits() -> HardwareConfig {
+        HardwareConfig {
+            min_feed: Duration::from_millis(200),
+            max_feed: Duration::from_millis(1600),
+            min_pulse: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn test_model() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut model = Model::new(&limits(), start);
+        // The default pattern, starting right away
+        assert_eq!(model.set(true, at(0)), None);
+        assert_eq!(model.set(false, at(100)), None);
+        assert_eq!(model.check(at(900)), None);
+        assert_eq!(model.set(true, at(1000)), None);
+        // Not an edge
+        assert_eq!(model.set(true, at(1002)), None);
+        assert_eq!(
+            model.set(false, at(1005)),
+            Some(Bite::ShortPulse(Duration::from_millis(5)))
+        );
+        // Only bites once
+        assert_eq!(model.check(at(5000)), None);
+
+        let mut model = Model::new(&limits(), start);
+        assert_eq!(model.set(true, at(0)), None);
+        assert_eq!(model.set(false, at(50)), None);
+        assert_eq!(
+            model.set(true, at(100)),
+            Some(Bite::TooFast(Duration::from_millis(100)))
+        );
+
+        let mut model = Model::new(&limits(), start);
+        assert_eq!(model.check(at(1600)), None);
+        assert_eq!(
+            model.check(at(1700)),
+            Some(Bite::Starved(Duration::from_millis(1700)))
+        );
+    }
+
+    #[test]
+    fn test_simulated_supervisor() -> Result<()> {
+        let bites = Arc::new(Mutex::new(Vec::new()));
+        let recorded = bites.clone();
+        let limits = HardwareConfig {
+            min_feed: Duration::ZERO,
+            max_feed: Duration::from_millis(50),
+            min_pulse: Duration::ZERO,
+        };
+        let mock = Mock::default();
+        let mut supervisor = SimulatedSupervisor::new(
+            Box::new(mock.clone()),
+            &limits,
+            Box::new(move |bite| recorded.lock().unwrap().push(bite)),
+        );
+        supervisor.set(true)?;
+        supervisor.set(false)?;
+        assert_eq!(mock.values(), [true, false]);
+        thread::sleep(Duration::from_millis(200));
+        let bites = bites.lock().unwrap();
+        assert!(matches!(bites[..], [Bite::Starved(since)] if since > limits.max_feed));
+        Ok(())
+    }
+}
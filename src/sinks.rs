@@ -0,0 +1,131 @@
+use crate::{history::Event, logbook::Logbook, stats};
+use anyhow::Result;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Items a sink can fall behind by before the oldest are dropped
+pub const CAPACITY: usize = 64;
+
+static EVENTS: Mutex<Option<Arc<Queue<Event>>>> = Mutex::new(None);
+
+/// A bounded queue in front of a reporting sink. Pushing never blocks: once it's full the oldest
+/// item makes way, counted in the status as `sinks.<name>.dropped`, so that a wedged sink costs the
+/// loop a lock and a wakeup at most and never holds up the petting.
+pub struct Queue<T> {
+    name: &'static str,
+    items: Mutex<VecDeque<T>>,
+    ready: Condvar,
+}
+
+impl<T> Queue<T> {
+    pub fn new(name: &'static str) -> Arc<Self> {
+        stats::sink_added(name);
+        Arc::new(Self {
+            name,
+            items: Mutex::new(VecDeque::with_capacity(CAPACITY)),
+            ready: Condvar::new(),
+        })
+    }
+
+    pub fn push(&self, item: T) {
+        let mut items = self.items.lock().unwrap_or_else(|e| e.into_inner());
+        if items.len() == CAPACITY {
+            items.pop_front();
+            stats::sink_dropped(self.name);
+        }
+        items.push_back(item);
+        self.ready.notify_one();
+    }
+
+    /// Waits for the next item
+    pub fn pop(&self) -> T {
+        let mut items = self.items.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(item) = items.pop_front() {
+                return item;
+            }
+            items = self.ready.wait(items).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Waits up to `timeout` for the next item, None if there wasn't one
+    #[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+    pub fn pop_timeout(&self, timeout: Duration) -> Option<T> {
+        let until = Instant::now() + timeout;
+        let mut items = self.items.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(item) = items.pop_front() {
+                return Some(item);
+            }
+            let left = until.checked_duration_since(Instant::now())?;
+            items = self
+                .ready
+                .wait_timeout(items, left)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+}
+
+/// Hands the recorded events to the telemetry, D-Bus, and MQTT sinks and the logbook from a thread
+/// of its own from now on, rather than from the loop that records them. Started before the seccomp
+/// filter, which doesn't allow new threads.
+pub fn start(mut logbook: Option<Logbook>) -> Result<()> {
+    let events = Queue::new("events");
+    let forwarded = events.clone();
+    thread::Builder::new()
+        .name(String::from("sinks"))
+        .spawn(move || loop {
+            let event = forwarded.pop();
+            crate::telemetry::forward(&event);
+            crate::bus::forward(&event);
+            #[cfg(feature = "mqtt")]
+            crate::mqtt::forward(&event);
+            if let Some(logbook) = &mut logbook {
+                logbook.forward(&event);
+            }
+        })?;
+    *EVENTS.lock().unwrap_or_else(|e| e.into_inner()) = Some(events);
+    Ok(())
+}
+
+/// Called for every recorded event, dropped unless started
+pub fn forward(event: &Event) {
+    let events = EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(events) = &*events {
+        events.push(event.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::StatusReport;
+
+    #[test]
+    fn test_queue() {
+        let queue = Queue::new("test");
+        for i in 0..CAPACITY + 3 {
+            queue.push(i);
+        }
+        assert_eq!(queue.pop(), 3);
+        assert_eq!(queue.pop_timeout(Duration::ZERO), Some(4));
+        let mut status = StatusReport::new();
+        stats::report(&mut status);
+        assert_eq!(status.get("sinks.test.dropped"), Some("3"));
+
+        while queue.pop_timeout(Duration::ZERO).is_some() {}
+        let pushed = queue.clone();
+        let pusher = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            pushed.push(100);
+        });
+        assert_eq!(queue.pop_timeout(Duration::from_secs(5)), Some(100));
+        assert_eq!(queue.pop_timeout(Duration::from_millis(10)), None);
+        pusher.join().unwrap();
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{death_row::DeathRow, pingee::Pingee, retry::Retry};
+use anyhow::{ensure, Result};
+use mio::{Registry, Token};
+
+/// The most tokens all sources together can take
+pub const TOKENS: usize = 40;
+
+/// What a ready source gets to look at and act on
+pub struct Ready<'a> {
+    pub registry: &'a Registry,
+    pub pingee: &'a mut Pingee,
+    pub death_row: &'a mut DeathRow,
+    pub retry: &'a mut Retry,
+}
+
+/// Something the event loop polls that handles its own readiness, like the reports, the
+/// anomaly watchers and the HTTP listener, so that a new one doesn't need its own arm in the loop.
+/// Whatever feeds clients is a `Condition` instead, and whatever the loop itself depends on, like
+/// the CAN silence holding back a pardon, keeps a token of its own.
+pub trait EventSource: Send {
+    /// How many consecutive tokens it needs
+    fn tokens(&self) -> usize {
+        1
+    }
+
+    /// Registers everything it polls, with the tokens from `token` on
+    fn register(&mut self, registry: &Registry, token: Token) -> Result<()>;
+
+    /// One of its tokens became ready, `index` counting from its first. An error is fatal.
+    fn handle_ready(&mut self, index: usize, ready: &mut Ready) -> Result<()>;
+}
+
+/// The event sources, each with its range of tokens
+pub struct Sources {
+    first: Token,
+    sources: Vec<(Token, Box<dyn EventSource>)>,
+}
+
+impl Sources {
+    /// Sources get tokens counting up from `first`
+    pub fn new(first: Token) -> Self {
+        Self {
+            first,
+            sources: Vec::new(),
+        }
+    }
+
+    /// Registers `source` with the next free tokens
+    pub fn add(
+        &mut self,
+        registry: &Registry,
+        mut source: impl EventSource + 'static,
+    ) -> Result<()> {
+        let token = self
+            .sources
+            .last()
+            .map_or(self.first, |(token, s)| Token(token.0 + s.tokens()));
+        ensure!(
+            token.0 + source.tokens() <= self.first.0 + TOKENS,
+            "Too many event sources"
+        );
+        source.register(registry, token)?;
+        self.sources.push((token, Box::new(source)));
+        Ok(())
+    }
+
+    /// Like `add`, if there is one
+    pub fn add_some(
+        &mut self,
+        registry: &Registry,
+        source: Option<impl EventSource + 'static>,
+    ) -> Result<()> {
+        match source {
+            Some(source) => self.add(registry, source),
+            None => Ok(()),
+        }
+    }
+
+    pub fn handles(&self, token: Token) -> bool {
+        self.find(token).is_some()
+    }
+
+    pub fn on_ready(&mut self, token: Token, ready: &mut Ready) -> Result<()> {
+        match self.find(token) {
+            Some(index) => {
+                let (first, source) = &mut self.sources[index];
+                source.handle_ready(token.0 - first.0, ready)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn find(&self, token: Token) -> Option<usize> {
+        self.sources
+            .iter()
+            .position(|(first, s)| (first.0..first.0 + s.tokens()).contains(&token.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mio::Poll;
+
+    struct Counted(usize);
+
+    impl EventSource for Counted {
+        fn tokens(&self) -> usize {
+            self.0
+        }
+
+        fn register(&mut self, _registry: &Registry, _token: Token) -> Result<()> {
+            Ok(())
+        }
+
+        fn handle_ready(&mut self, _index: usize, _ready: &mut Ready) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tokens() -> Result<()> {
+        let poll = Poll::new()?;
+        let mut sources = Sources::new(Token(100));
+        sources.add(poll.registry(), Counted(1))?;
+        sources.add_some(poll.registry(), None::<Counted>)?;
+        sources.add(poll.registry(), Counted(3))?;
+        let handled: Vec<_> = (99..106).map(|t| sources.handles(Token(t))).collect();
+        assert_eq!(handled, [false, true, true, true, true, false, false]);
+        assert_eq!(sources.find(Token(103)), Some(1));
+        assert!(sources.add(poll.registry(), Counted(TOKENS)).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{exit::Cause, history};
+use anyhow::Result;
+use log::{error, info};
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+#[derive(Default)]
+struct Shared {
+    /// Counted at the top of every iteration of the main loop
+    iterations: AtomicU64,
+    stopped: AtomicBool,
+}
+
+/// Watches from a thread of its own that the main loop keeps going round. A deadlock or a handler
+/// stuck in a syscall would otherwise only show as the supervisor resetting the card, with nothing
+/// in the logs. Past the bound the thread logs the recent events and exits with `loop_stall`.
+pub struct StallDetector {
+    shared: Arc<Shared>,
+}
+
+impl StallDetector {
+    /// Started before the seccomp filter, which doesn't allow new threads
+    pub fn start(bound: Duration) -> Result<Self> {
+        let shared = Arc::new(Shared::default());
+        let watched = shared.clone();
+        thread::Builder::new()
+            .name(String::from("stall"))
+            .spawn(move || {
+                if let Some(stalled) = watch(&watched, bound) {
+                    error!("Main loop stalled for {:?}, exiting", stalled);
+                    info!("Recent events:");
+                    for line in history::dump() {
+                        info!("  {}", line);
+                    }
+                    process::exit(Cause::Stall as i32);
+                }
+            })?;
+        Ok(Self { shared })
+    }
+
+    pub fn tick(&self) {
+        self.shared.iterations.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for StallDetector {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+/// How long the loop went without an iteration once that's past `bound`, or None once stopped
+fn watch(shared: &Shared, bound: Duration) -> Option<Duration> {
+    let period = bound / 4;
+    let mut seen = shared.iterations.load(Ordering::Relaxed);
+    let mut since = Instant::now();
+    let mut woke = since;
+    loop {
+        thread::sleep(period);
+        if shared.stopped.load(Ordering::Relaxed) {
+            return None;
+        }
+        let now = Instant::now();
+        let iterations = shared.iterations.load(Ordering::Relaxed);
+        // Held up itself, by a SIGSTOP or a suspend, the loop gets as long again
+        if iterations != seen || now - woke > 2 * period {
+            seen = iterations;
+            since = now;
+        } else if now - since > bound {
+            return Some(now - since);
+        }
+        woke = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch() {
+        let bound = Duration::from_millis(40);
+        let shared = Arc::new(Shared::default());
+        assert!(watch(&shared, bound).unwrap() > bound);
+
+        let ticking = shared.clone();
+        let ticker = thread::spawn(move || {
+            for _ in 0..20 {
+                ticking.iterations.fetch_add(1, Ordering::Relaxed);
+                thread::sleep(Duration::from_millis(5));
+            }
+            ticking.stopped.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(watch(&shared, bound), None);
+        ticker.join().unwrap();
+    }
+}
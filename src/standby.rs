@@ -0,0 +1,123 @@
+use crate::{config::StandbyConfig, instance::InstanceLock};
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::{
+    io::ErrorKind,
+    net::UdpSocket,
+    time::{Duration, Instant},
+};
+
+/// Longest wait on the socket, so that the silence and the lock are looked at often enough
+const POLL: Duration = Duration::from_millis(100);
+
+/// Another instance, on this card or the redundant processor, listening to the primary's
+/// heartbeats instead of petting. It takes over once they've stopped for the silence, or the
+/// primary announced it's dying, and the instance lock on the line is free, so that a primary
+/// crash looping under systemd doesn't end in a hardware reset. A primary that's still holding the
+/// lock, hung or not, keeps it.
+pub struct Standby {
+    socket: UdpSocket,
+    silence: Duration,
+    last: Instant,
+    dying: bool,
+}
+
+impl Standby {
+    pub fn new(config: &StandbyConfig) -> Result<Self> {
+        let socket = UdpSocket::bind(config.listen)
+            .with_context(|| format!("Failed to bind {} for the standby", config.listen))?;
+        Ok(Self {
+            socket,
+            silence: config.silence,
+            last: Instant::now(),
+            dying: false,
+        })
+    }
+
+    /// Waits until the primary is gone and the lock on the line `name` is taken
+    pub fn wait(mut self, name: &str) -> Result<InstanceLock> {
+        info!("In standby on {}", self.socket.local_addr()?);
+        let mut locked = false;
+        loop {
+            self.listen(POLL)?;
+            if !self.silent() {
+                continue;
+            }
+            let lock = InstanceLock::acquire(name)?;
+            if lock.held() {
+                warn!("The primary went silent, taking over {}", name);
+                return Ok(lock);
+            }
+            if !locked {
+                warn!("The primary went silent but still holds {}", name);
+                locked = true;
+            }
+        }
+    }
+
+    /// Takes in the heartbeats that arrive within `timeout`
+    fn listen(&mut self, timeout: Duration) -> Result<()> {
+        self.socket.set_read_timeout(Some(timeout))?;
+        let mut buf = [0; 64];
+        loop {
+            let len = match self.socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(())
+                }
+                Err(e) => return Err(e).context("Failed to receive a heartbeat"),
+            };
+            let text = String::from_utf8_lossy(&buf[..len]);
+            let mut words = text.split_whitespace();
+            if words.next() != Some("HEARTBEAT") {
+                continue;
+            }
+            self.last = Instant::now();
+            self.dying = words.next() == Some("dying");
+            self.socket
+                .set_read_timeout(Some(Duration::from_millis(1)))?;
+        }
+    }
+
+    fn silent(&self) -> bool {
+        self.dying || self.last.elapsed() >= self.silence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standby() -> Result<()> {
+        let config = StandbyConfig {
+            listen: "127.0.0.1:0".parse()?,
+            silence: Duration::from_millis(200),
+        };
+        let mut standby = Standby::new(&config)?;
+        let primary = UdpSocket::bind("127.0.0.1:0")?;
+        primary.connect(standby.socket.local_addr()?)?;
+
+        for _ in 0..3 {
+            primary.send(b"HEARTBEAT armed 28.5")?;
+            standby.listen(Duration::from_millis(100))?;
+            assert!(!standby.silent());
+        }
+        // Anything else doesn't count
+        primary.send(b"MARGIN c3 28.5")?;
+        standby.listen(Duration::from_millis(250))?;
+        assert!(standby.silent());
+        primary.send(b"HEARTBEAT armed 28.5")?;
+        standby.listen(Duration::from_millis(10))?;
+        assert!(!standby.silent());
+        primary.send(b"HEARTBEAT dying -")?;
+        standby.listen(Duration::from_millis(10))?;
+        assert!(standby.silent());
+
+        let name = format!("standby-{}", std::process::id());
+        let primary = InstanceLock::acquire(&name)?;
+        drop(primary);
+        assert!(standby.wait(&name)?.held());
+        Ok(())
+    }
+}
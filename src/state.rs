@@ -0,0 +1,135 @@
+use crate::{
+    clock,
+    history::{self, Event},
+};
+use log::{info, warn};
+use std::fmt;
+
+/// What the daemon is doing about missing pings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Nothing has to ping yet since the start
+    StartupInhibit,
+    /// Missing pings are fatal
+    Armed,
+    /// A commanded inhibit is running
+    Inhibited,
+    /// Something was fatal, the petting stops unless it's pardoned first
+    DeathRow,
+    /// About to stop petting, nothing gets out of this one
+    Dying,
+    /// Missing pings are only counted
+    Disarmed,
+    /// Disarmed by MAINTENANCE, which re-arms once its time runs out
+    Maintenance,
+}
+
+impl State {
+    /// As in the status and heartbeats
+    pub fn name(self) -> &'static str {
+        match self {
+            State::StartupInhibit => "startup_inhibit",
+            State::Armed => "armed",
+            State::Inhibited => "inhibited",
+            State::DeathRow => "death_row",
+            State::Dying => "dying",
+            State::Disarmed => "disarmed",
+            State::Maintenance => "maintenance",
+        }
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The last transition, or the initial state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    pub from: Option<State>,
+    pub to: State,
+    pub cause: String,
+    /// Unix seconds
+    pub at: i64,
+}
+
+/// The current state and how it got there. The inhibits and timers still decide when things
+/// happen, this only names the result, so every change gets logged and shows in the status.
+pub struct StateMachine {
+    last: Transition,
+}
+
+impl StateMachine {
+    pub fn new(state: State, cause: &str) -> Self {
+        info!("State {}: {}", state, cause);
+        Self {
+            last: Transition {
+                from: None,
+                to: state,
+                cause: cause.to_string(),
+                at: clock::wall_now(),
+            },
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.last.to
+    }
+
+    pub fn last(&self) -> &Transition {
+        &self.last
+    }
+
+    /// Logs and records the change, if it is one. Returns whether it was.
+    pub fn enter(&mut self, state: State, cause: &str) -> bool {
+        let from = self.state();
+        if state == from || from == State::Dying {
+            return false;
+        }
+        let at = clock::wall_now();
+        match state {
+            State::DeathRow | State::Dying | State::Disarmed | State::Maintenance => {
+                warn!("State {} -> {} at {}: {}", from, state, at, cause)
+            }
+            _ => info!("State {} -> {} at {}: {}", from, state, at, cause),
+        }
+        history::record(Event::State {
+            from,
+            to: state,
+            cause: cause.to_string(),
+        });
+        self.last = Transition {
+            from: Some(from),
+            to: state,
+            cause: cause.to_string(),
+            at,
+        };
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_machine() {
+        let mut machine = StateMachine::new(State::StartupInhibit, "Started");
+        assert_eq!(machine.last().from, None);
+        assert!(!machine.enter(State::StartupInhibit, "Again"));
+        assert!(machine.enter(State::Armed, "Startup inhibit over"));
+        assert!(machine.enter(State::DeathRow, "c3 client stopped pinging"));
+        let last = machine.last();
+        assert_eq!(last.from, Some(State::Armed));
+        assert_eq!(last.cause, "c3 client stopped pinging");
+        assert!(machine.enter(State::Dying, "c3 client stopped pinging"));
+        // There's no way back from dying
+        assert!(!machine.enter(State::Armed, "Pinged"));
+        assert_eq!(machine.state(), State::Dying);
+        assert!(history::dump()
+            .iter()
+            .any(|l| l.ends_with(" state armed -> death_row: c3 client stopped pinging")));
+    }
+}
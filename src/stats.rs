@@ -0,0 +1,713 @@
+use crate::{config::MAX_CADENCE_SAMPLES, dictionary::Counter, status::StatusReport};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    ops::Add,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+const BUCKETS: usize = 8;
+/// Upper bounds of the pet lateness buckets, in microseconds
+const PET_BUCKETS: [u64; BUCKETS] = [1000, 2000, 5000, 10_000, 20_000, 50_000, 100_000, 500_000];
+/// Upper bounds of the ping interval buckets, in microseconds
+const PING_BUCKETS: [u64; BUCKETS] = [
+    100_000, 500_000, 1_000_000, 2_000_000, 5_000_000, 10_000_000, 20_000_000, 30_000_000,
+];
+/// Upper bounds of the timer wakeup latency buckets, in microseconds
+const WAKEUP_BUCKETS: [u64; BUCKETS] = [50, 100, 200, 500, 1000, 2000, 5000, 20_000];
+/// Of each syscall, for its percentiles
+const LATENCY_SAMPLES: usize = 1024;
+
+/// Whether the syscalls are timed
+static INSTRUMENTED: AtomicBool = AtomicBool::new(false);
+
+static STATS: Mutex<Stats> = Mutex::new(Stats {
+    pet: Series::new(&PET_BUCKETS),
+    counts: [0; Counter::ALL.len()],
+    wakeup: Series::new(&WAKEUP_BUCKETS),
+    wakeup_threshold: Duration::MAX,
+    wakeup_max_at: None,
+    late_alarm: false,
+    last_pet: None,
+    last_feed: None,
+    wrote: None,
+    max_feed: None,
+    supervisor: None,
+    mirror: None,
+    degraded_config: None,
+    phase_error: None,
+    sinks: BTreeMap::new(),
+    peers: BTreeMap::new(),
+    clients: BTreeMap::new(),
+    syscalls: [const { Latency::new() }; 3],
+    last_anomaly: None,
+    before: None,
+});
+
+/// The syscalls in the pet and ping paths that a kernel stall would show up in
+#[derive(Debug, Clone, Copy)]
+pub enum Syscall {
+    /// Setting the watchdog line
+    SetValues,
+    TimerfdSettime,
+    RecvFrom,
+}
+
+impl Syscall {
+    const ALL: [Syscall; 3] = [
+        Syscall::SetValues,
+        Syscall::TimerfdSettime,
+        Syscall::RecvFrom,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Syscall::SetValues => "set_values",
+            Syscall::TimerfdSettime => "timerfd_settime",
+            Syscall::RecvFrom => "recv_from",
+        }
+    }
+}
+
+struct Stats {
+    /// How late each pet was against its schedule
+    pet: Series,
+    /// In the order of Counter::ALL
+    counts: [u64; Counter::ALL.len()],
+    /// How long after their expiry the timerfds were read
+    wakeup: Series,
+    wakeup_threshold: Duration,
+    /// When the latest wakeup was as late as they've been
+    wakeup_max_at: Option<Instant>,
+    /// Whether the pets are trending dangerously late
+    late_alarm: bool,
+    last_pet: Option<Instant>,
+    /// The last rising edge, which is what feeds the supervisor
+    last_feed: Option<Instant>,
+    /// The last write to the line, and when it was asked for
+    wrote: Option<(bool, Instant)>,
+    /// How long the supervisor goes without a feed before it bites, with a hardware profile
+    max_feed: Option<Duration>,
+    /// The supervisor's reset line, if it's watched
+    supervisor: Option<Supervisor>,
+    /// The edges on the watchdog line, if a mirror input watches them
+    mirror: Option<Mirror>,
+    /// Why the config couldn't be loaded, if it couldn't
+    degraded_config: Option<String>,
+    /// How far the last aligned cycle started off its boundary, in nanoseconds, negative if early
+    phase_error: Option<i64>,
+    /// Items each reporting sink's queue dropped for falling behind
+    sinks: BTreeMap<&'static str, u64>,
+    /// The companion cards watched, by name
+    peers: BTreeMap<String, Peer>,
+    clients: BTreeMap<String, Pings>,
+    /// In the order of Syscall::ALL
+    syscalls: [Latency; 3],
+    last_anomaly: Option<(Instant, String)>,
+    /// The totals of the previous runs, if they're kept
+    before: Option<Totals>,
+}
+
+/// Counts kept across restarts, see `counters::BootCounter`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Totals {
+    pub pets: u64,
+    pub pings: u64,
+    pub strikes: u64,
+    pub resets: u64,
+}
+
+impl Totals {
+    pub fn named(&self) -> [(&'static str, u64); 4] {
+        [
+            ("pets", self.pets),
+            ("pings", self.pings),
+            ("strikes", self.strikes),
+            ("resets", self.resets),
+        ]
+    }
+}
+
+impl Add for Totals {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            pets: self.pets + other.pets,
+            pings: self.pings + other.pings,
+            strikes: self.strikes + other.strikes,
+            resets: self.resets + other.resets,
+        }
+    }
+}
+
+/// How long a syscall took, all time and recently
+struct Latency {
+    count: u64,
+    max: Duration,
+    recent: VecDeque<Duration>,
+}
+
+impl Latency {
+    const fn new() -> Self {
+        Self {
+            count: 0,
+            max: Duration::ZERO,
+            recent: VecDeque::new(),
+        }
+    }
+
+    fn extend(&mut self, samples: impl IntoIterator<Item = Duration>) {
+        for sample in samples {
+            self.add(sample);
+        }
+    }
+
+    fn add(&mut self, sample: Duration) {
+        self.count += 1;
+        self.max = self.max.max(sample);
+        if self.recent.len() == LATENCY_SAMPLES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(sample);
+    }
+
+    /// Under `prefix`, in seconds, with the percentiles of the recent samples
+    fn report(&self, prefix: &str, status: &mut StatusReport) {
+        let mut sorted: Vec<_> = self.recent.iter().copied().collect();
+        sorted.sort_unstable();
+        status.push(format!("{}.count", prefix), self.count);
+        let seconds = |latency: Duration| format!("{:.6}", latency.as_secs_f64());
+        status.push(format!("{}.max", prefix), seconds(self.max));
+        for (name, quantile) in [("p50", 0.5), ("p99", 0.99), ("p999", 0.999)] {
+            let rank = (quantile * sorted.len() as f64).ceil() as usize;
+            let latency = match sorted.len() {
+                0 => Duration::ZERO,
+                len => sorted[rank.clamp(1, len) - 1],
+            };
+            status.push(format!("{}.{}", prefix, name), seconds(latency));
+        }
+    }
+}
+
+struct Pings {
+    last: Instant,
+    /// Intervals between pings
+    intervals: Series,
+    /// The latest intervals, for the cadence
+    recent: VecDeque<Duration>,
+    recommended: Option<Duration>,
+}
+
+#[derive(Default)]
+struct Peer {
+    heartbeats: u64,
+    last_heartbeat: Option<Instant>,
+    /// Times it was power cycled
+    cycles: u64,
+}
+
+struct Mirror {
+    edges: u64,
+    last_edge: Option<Instant>,
+    /// From each write to the edge it made
+    lag: Latency,
+}
+
+struct Supervisor {
+    /// How long an assertion counts as recent
+    recent: Duration,
+    edges: u64,
+    last_reset: Option<Instant>,
+}
+
+/// A histogram, plus the count, mean, and max of the samples
+struct Series {
+    buckets: &'static [u64; BUCKETS],
+    /// One per bucket, plus one for everything past the last
+    counts: [u64; BUCKETS + 1],
+    total: Duration,
+    max: Duration,
+}
+
+impl Series {
+    const fn new(buckets: &'static [u64; BUCKETS]) -> Self {
+        Self {
+            buckets,
+            counts: [0; BUCKETS + 1],
+            total: Duration::ZERO,
+            max: Duration::ZERO,
+        }
+    }
+
+    fn add(&mut self, sample: Duration) {
+        let micros = sample.as_micros();
+        let bucket = self.buckets.iter().position(|&b| micros <= b.into());
+        self.counts[bucket.unwrap_or(BUCKETS)] += 1;
+        self.total += sample;
+        self.max = self.max.max(sample);
+    }
+
+    fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Under `prefix`, to the microsecond if the buckets go under a millisecond
+    fn report(&self, prefix: &str, status: &mut StatusReport) {
+        let count = self.count();
+        let mean = match count {
+            0 => 0.0,
+            count => self.total.as_secs_f64() / count as f64,
+        };
+        let bound = |micros: u64| match micros % 1000 {
+            0 => format!("{}ms", micros / 1000),
+            _ => format!("{}us", micros),
+        };
+        let mut histogram: Vec<_> = self
+            .buckets
+            .iter()
+            .zip(&self.counts)
+            .map(|(&micros, count)| format!("le{}:{}", bound(micros), count))
+            .collect();
+        histogram.push(format!("inf:{}", self.counts[BUCKETS]));
+        let digits = match self.buckets[0] < 1000 {
+            true => 6,
+            false => 3,
+        };
+        status.push(format!("{}.count", prefix), count);
+        status.push(format!("{}.mean", prefix), format!("{:.*}", digits, mean));
+        let max = format!("{:.*}", digits, self.max.as_secs_f64());
+        status.push(format!("{}.max", prefix), max);
+        status.push(format!("{}.histogram", prefix), histogram.join(","));
+    }
+}
+
+fn stats() -> std::sync::MutexGuard<'static, Stats> {
+    STATS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Starts or stops timing the syscalls
+pub fn instrument(on: bool) {
+    INSTRUMENTED.store(on, Ordering::Relaxed);
+}
+
+/// Runs `call`, timing it as `syscall` if instrumented
+pub fn timed<T>(syscall: Syscall, call: impl FnOnce() -> T) -> T {
+    if !INSTRUMENTED.load(Ordering::Relaxed) {
+        return call();
+    }
+    let start = Instant::now();
+    let result = call();
+    let took = start.elapsed();
+    stats().syscalls[syscall as usize].add(took);
+    result
+}
+
+/// A pet happened this much later than scheduled
+pub fn pet(late: Duration) {
+    let mut stats = stats();
+    stats.pet.add(late);
+    stats.counts[Counter::Pets as usize] += 1;
+    stats.last_pet = Some(Instant::now());
+}
+
+/// Wakeups later than `threshold` are counted, and logged by the timers
+pub fn set_wakeup_threshold(threshold: Duration) {
+    stats().wakeup_threshold = threshold;
+}
+
+/// A timerfd was read `late` after it expired. Returns whether that's over the threshold.
+pub fn wakeup(late: Duration) -> bool {
+    let mut stats = stats();
+    if late >= stats.wakeup.max {
+        stats.wakeup_max_at = Some(Instant::now());
+    }
+    stats.wakeup.add(late);
+    let over = late > stats.wakeup_threshold;
+    stats.counts[Counter::LateWakeups as usize] += over as u64;
+    over
+}
+
+/// The line was set to `value`, asked for at `at`
+pub fn wrote(value: bool, at: Instant) {
+    let mut stats = stats();
+    stats.wrote = Some((value, at));
+    if value {
+        stats.last_feed = Some(at);
+    }
+}
+
+/// A reporting sink got a queue, shown from now on even before it drops anything
+pub fn sink_added(name: &'static str) {
+    stats().sinks.entry(name).or_default();
+}
+
+pub fn sink_dropped(name: &'static str) {
+    *stats().sinks.entry(name).or_default() += 1;
+}
+
+/// A companion card is watched, shown from now on even before it's heard from
+pub fn peer_watched(name: &str) {
+    stats().peers.entry(name.to_string()).or_default();
+}
+
+pub fn peer_heartbeat(name: &str) {
+    let mut stats = stats();
+    let peer = stats.peers.entry(name.to_string()).or_default();
+    peer.heartbeats += 1;
+    peer.last_heartbeat = Some(Instant::now());
+}
+
+pub fn peer_cycled(name: &str) {
+    stats().peers.entry(name.to_string()).or_default().cycles += 1;
+}
+
+/// The watchdog line's edges are watched through a mirror input
+pub fn mirror_watched() {
+    stats().mirror = Some(Mirror {
+        edges: 0,
+        last_edge: None,
+        lag: Latency::new(),
+    });
+}
+
+/// The line went to `value` at `at`, by the kernel's timestamp, which is when the supervisor was
+/// really fed. Returns how long after the write that asked for it, if it did.
+pub fn mirror_edge(value: bool, at: Instant) -> Option<Duration> {
+    let mut stats = stats();
+    let lag = match stats.wrote {
+        Some((wrote, asked)) if wrote == value => Some(at.saturating_duration_since(asked)),
+        _ => None,
+    };
+    if value {
+        stats.last_feed = Some(at);
+    }
+    if let Some(mirror) = &mut stats.mirror {
+        mirror.edges += 1;
+        mirror.last_edge = Some(at);
+        mirror.lag.extend(lag);
+    }
+    lag
+}
+
+/// The safe defaults run, the config couldn't be loaded for `error`
+pub fn degraded_config(error: &str) {
+    stats().degraded_config = Some(error.to_string());
+}
+
+/// An aligned cycle started `error` nanoseconds past its boundary
+pub fn phase_error(error: i64) {
+    stats().phase_error = Some(error);
+}
+
+/// The supervisor's `max_feed` is known, so that the status can tell how long it would take to
+/// bite were the petting to stop now
+pub fn set_max_feed(max_feed: Duration) {
+    stats().max_feed = Some(max_feed);
+}
+
+pub fn count(counter: Counter) {
+    stats().counts[counter as usize] += 1;
+}
+
+/// So far in this run
+pub fn counter(counter: Counter) -> u64 {
+    stats().counts[counter as usize]
+}
+
+pub fn set_late_alarm(alarm: bool) {
+    stats().late_alarm = alarm;
+}
+
+/// The supervisor's reset line is watched, assertions within `recent` are reported as such
+pub fn supervisor_watched(recent: Duration) {
+    stats().supervisor = Some(Supervisor {
+        recent,
+        edges: 0,
+        last_reset: None,
+    });
+}
+
+/// The supervisor's reset line changed, to asserted or not
+pub fn supervisor_edge(asserted: bool) {
+    if let Some(supervisor) = &mut stats().supervisor {
+        supervisor.edges += 1;
+        if asserted {
+            supervisor.last_reset = Some(Instant::now());
+        }
+    }
+}
+
+/// The counts of this run
+pub fn totals() -> Totals {
+    let counts = stats().counts;
+    Totals {
+        pets: counts[Counter::Pets as usize],
+        pings: counts[Counter::Pings as usize],
+        strikes: counts[Counter::Strikes as usize],
+        resets: counts[Counter::Resets as usize],
+    }
+}
+
+/// The totals of the previous runs, which the status adds this one's to
+pub fn set_before(before: Totals) {
+    stats().before = Some(before);
+}
+
+/// A ping was received from the client
+pub fn ping(client: &str) {
+    let now = Instant::now();
+    let mut stats = stats();
+    stats.counts[Counter::Pings as usize] += 1;
+    match stats.clients.get_mut(client) {
+        Some(pings) => {
+            let interval = now - pings.last;
+            pings.intervals.add(interval);
+            if pings.recent.len() == MAX_CADENCE_SAMPLES {
+                pings.recent.pop_front();
+            }
+            pings.recent.push_back(interval);
+            pings.last = now;
+        }
+        None => {
+            let pings = Pings {
+                last: now,
+                intervals: Series::new(&PING_BUCKETS),
+                recent: VecDeque::new(),
+                recommended: None,
+            };
+            stats.clients.insert(client.to_string(), pings);
+        }
+    }
+}
+
+/// The client's latest intervals between pings, oldest first
+pub fn recent_intervals(client: &str) -> Vec<Duration> {
+    let stats = stats();
+    let recent = stats.clients.get(client).map(|pings| pings.recent.iter());
+    recent.into_iter().flatten().copied().collect()
+}
+
+/// The timeout the cadence recommends for the client, for the status
+pub fn recommend(client: &str, timeout: Duration) {
+    if let Some(pings) = stats().clients.get_mut(client) {
+        pings.recommended = Some(timeout);
+    }
+}
+
+/// The wall clock was stepped or the card suspended, as `what` says
+pub fn anomaly(what: String) {
+    let mut stats = stats();
+    stats.counts[Counter::TimeAnomalies as usize] += 1;
+    stats.last_anomaly = Some((Instant::now(), what));
+}
+
+/// Notes in a timeout's `reason` if the last time anomaly was within its `window`, as the
+/// client may well not be the one at fault then
+pub fn annotate(reason: String, window: Duration) -> String {
+    annotated(reason, stats().last_anomaly.as_ref(), window)
+}
+
+fn annotated(reason: String, last: Option<&(Instant, String)>, window: Duration) -> String {
+    match last {
+        Some((at, what)) if at.elapsed() <= window => {
+            format!(
+                "{} (a time anomaly happened in this window: {})",
+                reason, what
+            )
+        }
+        _ => reason,
+    }
+}
+
+/// How long ago each client last pinged
+pub fn last_pings() -> Vec<(String, Duration)> {
+    let stats = stats();
+    let now = Instant::now();
+    let pings = stats.clients.iter();
+    pings
+        .map(|(client, pings)| (client.clone(), now - pings.last))
+        .collect()
+}
+
+/// All statistics as STATUS lines. Durations are in seconds, the maxima are the longest
+/// observed gaps.
+pub fn report(status: &mut StatusReport) {
+    let run = totals();
+    let stats = stats();
+    for counter in Counter::ALL {
+        status.push(counter.key(), stats.counts[counter as usize]);
+    }
+    if let Some(before) = stats.before {
+        for (name, count) in (before + run).named() {
+            status.push(format!("count.{}_total", name), count);
+        }
+    }
+    status.push("degraded_config", stats.degraded_config.is_some() as u8);
+    if let Some(error) = &stats.degraded_config {
+        status.push("degraded_config.error", error);
+    }
+    stats.pet.report("pet.late", status);
+    status.push("pet.late_alarm", stats.late_alarm as u8);
+    let last_pet = stats
+        .last_pet
+        .map(|at| format!("{:.3}", at.elapsed().as_secs_f64()));
+    status.push("pet.last", last_pet.as_deref().unwrap_or("-"));
+    if let Some(error) = stats.phase_error {
+        status.push("pet.phase_error", format!("{:.6}", error as f64 / 1e9));
+    }
+    stats.wakeup.report("wakeup.latency", status);
+    let max_at = stats
+        .wakeup_max_at
+        .map(|at| format!("{:.1}", at.elapsed().as_secs_f64()));
+    status.push("wakeup.max_ago", max_at.as_deref().unwrap_or("-"));
+    if let Some(max_feed) = stats.max_feed {
+        let left = stats.last_feed.map(|at| {
+            let left = max_feed.saturating_sub(at.elapsed());
+            left.as_millis().to_string()
+        });
+        status.push("hardware.remaining_ms", left.as_deref().unwrap_or("-"));
+    }
+    for (name, dropped) in &stats.sinks {
+        status.push(format!("sinks.{}.dropped", name), dropped);
+    }
+    for (name, peer) in &stats.peers {
+        status.push(format!("peer.{}.heartbeats", name), peer.heartbeats);
+        let last = peer
+            .last_heartbeat
+            .map(|at| format!("{:.1}", at.elapsed().as_secs_f64()));
+        status.push(
+            format!("peer.{}.last_heartbeat", name),
+            last.as_deref().unwrap_or("-"),
+        );
+        status.push(format!("peer.{}.cycles", name), peer.cycles);
+    }
+    if let Some(mirror) = &stats.mirror {
+        status.push("pet.edges", mirror.edges);
+        let last_edge = mirror
+            .last_edge
+            .map(|at| format!("{:.3}", at.elapsed().as_secs_f64()));
+        status.push("pet.last_edge", last_edge.as_deref().unwrap_or("-"));
+        mirror.lag.report("pet.edge_lag", status);
+    }
+    if let Some((at, what)) = &stats.last_anomaly {
+        status.push(
+            "time.last_anomaly",
+            format!("{:.1}", at.elapsed().as_secs_f64()),
+        );
+        status.push("time.last_anomaly_was", what);
+    }
+    #[cfg(feature = "inject")]
+    crate::inject::report(status);
+    if INSTRUMENTED.load(Ordering::Relaxed) {
+        for syscall in Syscall::ALL {
+            let prefix = format!("syscall.{}", syscall.name());
+            stats.syscalls[syscall as usize].report(&prefix, status);
+        }
+    }
+    if let Some(supervisor) = &stats.supervisor {
+        let ago = supervisor.last_reset.map(|at| at.elapsed());
+        status.push("supervisor.edges", supervisor.edges);
+        let last = ago.map_or(String::from("-"), |ago| format!("{:.1}", ago.as_secs_f64()));
+        status.push("supervisor.last_reset", last);
+        let recently = ago.is_some_and(|ago| ago <= supervisor.recent);
+        status.push("supervisor.reset_recently", recently as u8);
+    }
+    for (client, pings) in &stats.clients {
+        pings
+            .intervals
+            .report(&format!("client.{}.interval", client), status);
+        if let Some(recommended) = pings.recommended {
+            let recommended = format!("{:.1}", recommended.as_secs_f64());
+            status.push(
+                format!("client.{}.recommended_timeout", client),
+                recommended,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_series() {
+        let mut series = Series::new(&PET_BUCKETS);
+        series.add(Duration::from_micros(500));
+        series.add(Duration::from_millis(7));
+        series.add(Duration::from_secs(2));
+        let mut status = StatusReport::new();
+        series.report("pet.late", &mut status);
+        assert_eq!(
+            status.to_lines(),
+            "pet.late.count=3\npet.late.mean=0.669\npet.late.max=2.000\npet.late.histogram=\
+             le1ms:1,le2ms:0,le5ms:0,le10ms:1,le20ms:0,le50ms:0,le100ms:0,le500ms:0,inf:1\n"
+        );
+
+        ping("stats-test");
+        ping("stats-test");
+        let mut status = StatusReport::new();
+        report(&mut status);
+        assert_eq!(status.get("client.stats-test.interval.count"), Some("1"));
+
+        set_max_feed(Duration::from_secs(60));
+        wrote(true, Instant::now());
+        let mut status = StatusReport::new();
+        report(&mut status);
+        let left: u64 = status
+            .get("hardware.remaining_ms")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((59_000..=60_000).contains(&left));
+    }
+
+    #[test]
+    fn test_wakeup_series() {
+        let mut series = Series::new(&WAKEUP_BUCKETS);
+        series.add(Duration::from_micros(30));
+        series.add(Duration::from_micros(1500));
+        let mut status = StatusReport::new();
+        series.report("wakeup.latency", &mut status);
+        assert_eq!(status.get("wakeup.latency.mean"), Some("0.000765"));
+        assert_eq!(status.get("wakeup.latency.max"), Some("0.001500"));
+        assert_eq!(
+            status.get("wakeup.latency.histogram"),
+            Some("le50us:1,le100us:0,le200us:0,le500us:0,le1ms:0,le2ms:1,le5ms:0,le20ms:0,inf:0")
+        );
+    }
+
+    #[test]
+    fn test_latency() {
+        let mut latency = Latency::new();
+        for micros in 1..=2000 {
+            latency.add(Duration::from_micros(micros));
+        }
+        let mut status = StatusReport::new();
+        latency.report("syscall.recv_from", &mut status);
+        assert_eq!(status.get("syscall.recv_from.count"), Some("2000"));
+        assert_eq!(status.get("syscall.recv_from.max"), Some("0.002000"));
+        // Of the last 1024 only
+        assert_eq!(status.get("syscall.recv_from.p50"), Some("0.001488"));
+        assert_eq!(status.get("syscall.recv_from.p999"), Some("0.001999"));
+    }
+
+    #[test]
+    fn test_annotated() {
+        let reason = || String::from("c3 client stopped pinging");
+        let window = Duration::from_secs(30);
+        assert_eq!(annotated(reason(), None, window), reason());
+        let step = (Instant::now(), String::from("wall clock stepped by 3600s"));
+        assert_eq!(
+            annotated(reason(), Some(&step), window),
+            "c3 client stopped pinging \
+             (a time anomaly happened in this window: wall clock stepped by 3600s)"
+        );
+        let long_ago = (Instant::now() - Duration::from_secs(60), step.1);
+        assert_eq!(annotated(reason(), Some(&long_ago), window), reason());
+    }
+}
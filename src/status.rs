@@ -0,0 +1,162 @@
+use crate::clock::wall_now;
+use anyhow::{bail, Context, Result};
+use std::fmt::{self, Display, Write as _};
+
+/// The watchdog's state and counters as keys and values, in the order they were added. The one
+/// schema behind the STATUS reply, `status`, /status, the status socket, SIGUSR1 dumps, and the
+/// last gasp, so that a key added here shows up on all of them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatusReport(Vec<(String, String)>);
+
+impl StatusReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, key: impl Into<String>, value: impl Display) {
+        self.0.push((key.into(), value.to_string()));
+    }
+
+    /// Reads a STATUS reply back
+    pub fn parse(reply: &str) -> Result<Self> {
+        if let Some(reason) = reply.strip_prefix("ERR ") {
+            bail!("Watchdog refused: {}", reason);
+        }
+        let lines = reply.lines().map(|line| {
+            let (key, value) = line.split_once('=').context("Invalid status line")?;
+            Ok((key.to_string(), value.to_string()))
+        });
+        Ok(Self(lines.collect::<Result<_>>()?))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// As sent in reply to STATUS, a key=value line each
+    pub fn to_lines(&self) -> String {
+        let mut lines = String::new();
+        for (key, value) in &self.0 {
+            writeln!(lines, "{}={}", key, value).unwrap();
+        }
+        lines
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// A flat object of the keys, with numbers as numbers
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{");
+        for (i, (key, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write!(json, "{}:", quote(key)).unwrap();
+            match value.parse::<f64>() {
+                Ok(number) if number.is_finite() => json.push_str(value),
+                _ => json.push_str(&quote(value)),
+            }
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// The state and client deadlines first, then everything else as it came
+impl fmt::Display for StatusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "State:     {}", self.get("state").unwrap_or("unknown"))?;
+        if let Some(inhibit) = self.get("inhibit_remaining") {
+            writeln!(f, "Inhibited: {} s", inhibit)?;
+        }
+        let clients = self.0.iter().filter_map(|(key, value)| {
+            let client = key.strip_prefix("client.")?.strip_suffix(".remaining")?;
+            Some((client, value))
+        });
+        writeln!(f, "Clients:")?;
+        for (client, remaining) in clients {
+            write!(f, "  {:<20} {} s remaining", client, remaining)?;
+            let key = |suffix: &str| format!("client.{}.{}", client, suffix);
+            if let Some(timeout) = self.get(&key("timeout")) {
+                write!(f, " of {} s", timeout)?;
+            }
+            match self.get(&key("last_ping")).map(str::parse::<i64>) {
+                Some(Ok(at)) => write!(f, ", pinged {} s ago", (wall_now() - at).max(0))?,
+                Some(Err(_)) => write!(f, ", never pinged")?,
+                None => (),
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "Counters:")?;
+        let shown = |key: &str| {
+            let client = key.strip_prefix("client.").unwrap_or_default();
+            key == "state"
+                || key == "inhibit_remaining"
+                || [".remaining", ".timeout", ".last_ping"]
+                    .iter()
+                    .any(|suffix| client.ends_with(suffix))
+        };
+        for (key, value) in self.0.iter().filter(|(key, _)| !shown(key)) {
+            writeln!(f, "  {:<32} {}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// As a JSON string
+pub(crate) fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => write!(quoted, "\\{}", c).unwrap(),
+            c if c.is_control() => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status() -> Result<()> {
+        let status = StatusReport::parse(
+            "state=armed\ninhibit_remaining=0.0\nboots=3\nclient.c3-main.remaining=12.5\n\
+             pet.late.histogram=le1ms:1,inf:0\n",
+        )?;
+        assert_eq!(status.get("boots"), Some("3"));
+        assert_eq!(StatusReport::parse(&status.to_lines())?, status);
+        assert_eq!(
+            status.to_json(),
+            "{\"state\":\"armed\",\"inhibit_remaining\":0.0,\"boots\":3,\
+             \"client.c3-main.remaining\":12.5,\"pet.late.histogram\":\"le1ms:1,inf:0\"}"
+        );
+        let pretty = status.to_string();
+        assert!(pretty.starts_with("State:     armed\n"));
+        assert!(pretty.contains("  c3-main              12.5 s remaining\n"));
+        assert!(pretty.contains("  boots"));
+        assert!(!pretty.contains("client.c3-main"));
+
+        let status = StatusReport::parse(&format!(
+            "client.edl.last_ping=-\nclient.edl.timeout=60.0\nclient.edl.remaining=60.0\n\
+             client.c3.last_ping={}\nclient.c3.timeout=30.0\nclient.c3.remaining=25.0\n",
+            wall_now() - 5
+        ))?;
+        let pretty = status.to_string();
+        assert!(
+            pretty.contains("  edl                  60.0 s remaining of 60.0 s, never pinged\n")
+        );
+        assert!(pretty.contains("  c3                   25.0 s remaining of 30.0 s, pinged "));
+        assert!(!pretty.contains("client."));
+        assert!(StatusReport::parse("ERR Draining").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{
+    clock::{self, Clock, Timer},
+    pingee::Pingee,
+    source::{EventSource, Ready},
+};
+use anyhow::{Context, Result};
+use log::{info, warn};
+use mio::{Registry, Token};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The STATUS keys written, always all of them and in this order, "-" for the ones it doesn't have
+const KEYS: [&str; 16] = [
+    "state",
+    "state.cause",
+    "state.entered",
+    "uptime",
+    "since_last_ping",
+    "inhibit_remaining",
+    "hardware.remaining_ms",
+    "health",
+    "health.worst_margin",
+    "count.pets",
+    "count.pings",
+    "count.strikes",
+    "count.resets",
+    "pet.late_pets",
+    "pet.overruns",
+    "degraded_config",
+];
+
+/// A few lines of the status in a file rewritten every interval, for someone on the debug UART to
+/// `cat` when every socket and bus is down. Meant for a tmpfs: it's replaced atomically but never
+/// synced. The first line is when it was written, so a stale file shows the watchdog stopped.
+pub struct StatusFile {
+    path: PathBuf,
+    interval: Duration,
+    timer: Box<dyn Timer>,
+    /// Whether the last write failed, so that a broken file is warned about once
+    failing: bool,
+}
+
+impl StatusFile {
+    pub fn new(path: &Path, interval: Duration, clock: &dyn Clock) -> Result<Self> {
+        let timer = clock.timer()?;
+        timer.set(interval)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            interval,
+            timer,
+            failing: false,
+        })
+    }
+
+    /// Rewrites the file and waits for the next time. A failed write is tried again then.
+    pub fn on_tick(&mut self, pingee: &Pingee) -> Result<()> {
+        self.timer.set(self.interval)?;
+        let written = self.write(pingee);
+        match &written {
+            Ok(()) if self.failing => info!("Writing the status file {:?} again", self.path),
+            Err(e) if !self.failing => warn!("{:#}", e),
+            _ => (),
+        }
+        self.failing = written.is_err();
+        Ok(())
+    }
+
+    fn write(&self, pingee: &Pingee) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp = self.path.with_extension("new");
+        fs::write(&temp, contents(pingee)?)
+            .with_context(|| format!("Failed to write {:?}", temp))?;
+        fs::rename(&temp, &self.path)
+            .with_context(|| format!("Failed to replace {:?}", self.path))?;
+        Ok(())
+    }
+}
+
+impl EventSource for StatusFile {
+    fn register(&mut self, registry: &Registry, token: Token) -> Result<()> {
+        self.timer.register(registry, token)
+    }
+
+    fn handle_ready(&mut self, _index: usize, ready: &mut Ready) -> Result<()> {
+        self.on_tick(ready.pingee)
+    }
+}
+
+/// `key=value` lines, with the client closest to timing out and what it has left last
+fn contents(pingee: &Pingee) -> Result<String> {
+    let status = pingee.status()?;
+    let mut text = format!("written={}\n", clock::wall_now());
+    for key in KEYS {
+        text += &format!("{}={}\n", key, status.get(key).unwrap_or("-"));
+    }
+    let (client, left) = match pingee.countdown()? {
+        Some((client, left, _)) => (client, format!("{:.1}", left.as_secs_f64())),
+        None => ("-", String::from("-")),
+    };
+    text += &format!("margin.client={}\nmargin.remaining={}\n", client, left);
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::Monotonic, config::Config};
+
+    #[test]
+    fn test_status_file() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            timeout = 30
+            [inhibit]
+            startup = 0.001
+            "#,
+        )?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        std::thread::sleep(Duration::from_millis(5));
+        pingee.feed(Some("c3"))?;
+        let dir = std::env::temp_dir().join(format!("watchdog-status-{}", std::process::id()));
+        let path = dir.join("status");
+        let mut file = StatusFile::new(&path, Duration::from_secs(5), &Monotonic)?;
+        file.on_tick(&pingee)?;
+        let text = fs::read_to_string(&path)?;
+        fs::remove_dir_all(&dir)?;
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), KEYS.len() + 3);
+        assert!(lines[0].starts_with("written="));
+        assert!(lines[1].starts_with("state="));
+        assert!(lines.contains(&"degraded_config=0"));
+        assert_eq!(lines[lines.len() - 2], "margin.client=c3");
+        assert!(!file.failing);
+        Ok(())
+    }
+}
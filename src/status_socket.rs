@@ -0,0 +1,91 @@
+use crate::source::{EventSource, Ready};
+use anyhow::{Context, Result};
+use log::debug;
+use mio::{unix::SourceFd, Interest, Registry, Token};
+use std::{
+    fs::{self, Permissions},
+    io::{ErrorKind, Write},
+    os::{
+        fd::AsRawFd,
+        unix::{fs::PermissionsExt, net::UnixListener},
+    },
+    path::Path,
+};
+
+/// A Unix stream socket the C3 software pulls the status from, to mirror into its object
+/// dictionary and beacon without speaking the command protocol. Each connection gets the STATUS
+/// reply's key=value lines, then is closed. Like STATUS on the ping socket anyone local may read
+/// it.
+pub struct StatusSocket {
+    listener: UnixListener,
+}
+
+impl StatusSocket {
+    pub fn bind(path: &Path) -> Result<Self> {
+        // Whatever is left there belongs to a previous instance that's gone or on its way out
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("Failed to remove {:?}", path))
+            }
+            _ => (),
+        }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to bind status socket {:?}", path))?;
+        fs::set_permissions(path, Permissions::from_mode(0o666))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener })
+    }
+
+    pub fn on_accept(&self, status: impl Fn() -> Result<String>) -> Result<()> {
+        loop {
+            let mut stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e).context("Status socket accept failed"),
+            };
+            // Small enough for the socket buffer, a reader that can't take it gets nothing
+            stream.set_nonblocking(true)?;
+            if let Err(e) = stream.write_all(status()?.as_bytes()) {
+                debug!("Failed to send the status: {}", e);
+            }
+        }
+    }
+}
+
+impl EventSource for StatusSocket {
+    fn register(&mut self, registry: &Registry, token: Token) -> Result<()> {
+        let fd = self.listener.as_raw_fd();
+        registry.register(&mut SourceFd(&fd), token, Interest::READABLE)?;
+        Ok(())
+    }
+
+    fn handle_ready(&mut self, _index: usize, ready: &mut Ready) -> Result<()> {
+        self.on_accept(|| Ok(ready.pingee.status()?.to_lines()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::StatusReport;
+    use std::{io::Read, os::unix::net::UnixStream};
+
+    #[test]
+    fn test_status_socket() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("watchdog-status-{}", std::process::id()));
+        let socket = StatusSocket::bind(&path)?;
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o666);
+        let mut client = UnixStream::connect(&path)?;
+        socket.on_accept(|| Ok(String::from("state=armed\nuptime=1.0\n")))?;
+        let mut reply = String::new();
+        client.read_to_string(&mut reply)?;
+        assert_eq!(StatusReport::parse(&reply)?.get("state"), Some("armed"));
+        // Nothing waiting
+        socket.on_accept(|| unreachable!())?;
+        // A stale socket from a previous run is replaced
+        drop(socket);
+        StatusSocket::bind(&path)?;
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
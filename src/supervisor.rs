@@ -0,0 +1,242 @@
+use crate::{
+    config::{Bias, SupervisorConfig},
+    source::{EventSource, Ready},
+    stats,
+};
+use anyhow::{ensure, Context, Result};
+use gpiod::Chip;
+use log::{info, warn};
+use mio::{unix::SourceFd, Interest, Registry, Token};
+use nix::{errno::Errno, unistd};
+use std::{
+    fs::File,
+    io, mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::PathBuf,
+    time::Duration,
+};
+
+// GPIO_V2_GET_LINE_IOCTL, _IOWR(0xB4, 0x07, struct gpio_v2_line_request). gpiod doesn't expose
+// the fd of a request, which has to be polled here.
+const GPIO_V2_GET_LINE: u64 = 0xC250_B407;
+const FLAG_ACTIVE_LOW: u64 = 1 << 1;
+const FLAG_INPUT: u64 = 1 << 2;
+const FLAG_EDGE_RISING: u64 = 1 << 4;
+const FLAG_EDGE_FALLING: u64 = 1 << 5;
+const FLAG_BIAS_PULL_UP: u64 = 1 << 8;
+const FLAG_BIAS_PULL_DOWN: u64 = 1 << 9;
+const FLAG_BIAS_DISABLED: u64 = 1 << 10;
+const ATTR_DEBOUNCE: u32 = 3;
+/// gpio_v2_line_event ids, rising being the asserting one with the active low flag applied
+const EVENT_RISING: u32 = 1;
+pub const EVENT_SIZE: usize = 48;
+
+const CONSUMER: &[u8] = b"C3_Watchdog";
+
+#[repr(C)]
+struct LineAttribute {
+    id: u32,
+    padding: u32,
+    value: u64,
+}
+
+#[repr(C)]
+struct LineConfigAttribute {
+    attr: LineAttribute,
+    mask: u64,
+}
+
+#[repr(C)]
+struct LineConfig {
+    flags: u64,
+    num_attrs: u32,
+    padding: [u32; 5],
+    attrs: [LineConfigAttribute; 10],
+}
+
+#[repr(C)]
+struct LineRequest {
+    offsets: [u32; 64],
+    consumer: [u8; 32],
+    config: LineConfig,
+    num_lines: u32,
+    event_buffer_size: u32,
+    padding: [u32; 5],
+    fd: i32,
+}
+
+const _: () = assert!(mem::size_of::<LineRequest>() == 592);
+
+/// The supervisor's WDT_STATUS/RESET_OUT pin, watched for edges. Each is logged and counted, and
+/// a reset asserted within `recent` shows in the status, so that a supervisor biting spuriously,
+/// e.g. while the pets are on time, stands out.
+pub struct SupervisorLine {
+    fd: OwnedFd,
+}
+
+impl SupervisorLine {
+    pub fn new(config: &SupervisorConfig) -> Result<Self> {
+        let fd = request_edges(
+            "supervisor",
+            &config.chip,
+            &config.label,
+            config.line,
+            config.active_low,
+            config.bias,
+            config.debounce,
+        )?;
+        stats::supervisor_watched(config.recent);
+        Ok(Self { fd })
+    }
+
+    pub fn on_readable(&self) -> Result<()> {
+        let mut buf = [0; EVENT_SIZE * 16];
+        loop {
+            match unistd::read(self.fd.as_raw_fd(), &mut buf) {
+                Ok(len) => {
+                    for (_, asserted) in edges(&buf[..len]) {
+                        on_edge(asserted);
+                    }
+                }
+                Err(Errno::EAGAIN) => return Ok(()),
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(e).context("Supervisor line read failed"),
+            }
+        }
+    }
+}
+
+/// Requests an input line on `chip` for its edges, checking its label like the main line's. The
+/// fd reads gpio_v2_line_events, and doesn't block. `what` it is goes in the errors.
+pub fn request_edges(
+    what: &str,
+    chip: &str,
+    label: &str,
+    line: u32,
+    active_low: bool,
+    bias: Option<Bias>,
+    debounce: Duration,
+) -> Result<OwnedFd> {
+    let path = match chip.contains('/') {
+        true => PathBuf::from(chip),
+        false => PathBuf::from("/dev").join(chip),
+    };
+    let chip = Chip::new(chip).with_context(|| format!("Failed to get {} GPIO chip", what))?;
+    let found = chip.line_info(line)?.name;
+    ensure!(
+        found == label,
+        "Invalid {} GPIO line label: expected {:?}, found {:?}",
+        what,
+        label,
+        found
+    );
+    let chip = File::open(&path).with_context(|| format!("Failed to open {:?}", path))?;
+
+    // SAFETY: all integers, for which zero is valid
+    let mut request: LineRequest = unsafe { mem::zeroed() };
+    request.offsets[0] = line;
+    request.consumer[..CONSUMER.len()].copy_from_slice(CONSUMER);
+    request.config.flags = FLAG_INPUT | FLAG_EDGE_RISING | FLAG_EDGE_FALLING;
+    if active_low {
+        request.config.flags |= FLAG_ACTIVE_LOW;
+    }
+    request.config.flags |= match bias {
+        Some(Bias::PullUp) => FLAG_BIAS_PULL_UP,
+        Some(Bias::PullDown) => FLAG_BIAS_PULL_DOWN,
+        Some(Bias::Disabled) => FLAG_BIAS_DISABLED,
+        None => 0,
+    };
+    if !debounce.is_zero() {
+        // Validated to fit
+        let micros = debounce.as_micros() as u32;
+        // The attribute's value is a union, the period is its first u32
+        let mut value = [0; 8];
+        value[..4].copy_from_slice(&micros.to_ne_bytes());
+        request.config.attrs[0] = LineConfigAttribute {
+            attr: LineAttribute {
+                id: ATTR_DEBOUNCE,
+                padding: 0,
+                value: u64::from_ne_bytes(value),
+            },
+            mask: 1,
+        };
+        request.config.num_attrs = 1;
+    }
+    request.num_lines = 1;
+    // SAFETY: request outlives the call and matches the ioctl's argument type
+    let ret = unsafe {
+        libc::ioctl(
+            chip.as_raw_fd(),
+            GPIO_V2_GET_LINE as libc::Ioctl,
+            &mut request,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("Failed to request {} line", what));
+    }
+    // SAFETY: the kernel just handed over this fd, nothing else owns it
+    let fd = unsafe { OwnedFd::from_raw_fd(request.fd) };
+    // SAFETY: plain fcntl on an fd that's open
+    let ret = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error()).context("Failed to set O_NONBLOCK");
+    }
+    Ok(fd)
+}
+
+impl EventSource for SupervisorLine {
+    fn register(&mut self, registry: &Registry, token: Token) -> Result<()> {
+        let fd = self.fd.as_raw_fd();
+        registry.register(&mut SourceFd(&fd), token, Interest::READABLE)?;
+        Ok(())
+    }
+
+    fn handle_ready(&mut self, _index: usize, ready: &mut Ready) -> Result<()> {
+        ready.retry.run(|| self.on_readable())
+    }
+}
+
+/// The CLOCK_MONOTONIC timestamp of each gpio_v2_line_event, and whether it asserts the line
+pub fn edges(events: &[u8]) -> impl Iterator<Item = (Duration, bool)> + '_ {
+    events.chunks_exact(EVENT_SIZE).map(|event| {
+        let timestamp = u64::from_ne_bytes(event[..8].try_into().unwrap());
+        let id = u32::from_ne_bytes(event[8..12].try_into().unwrap());
+        (Duration::from_nanos(timestamp), id == EVENT_RISING)
+    })
+}
+
+fn on_edge(asserted: bool) {
+    stats::supervisor_edge(asserted);
+    match asserted {
+        true => warn!("Supervisor asserted reset"),
+        false => info!("Supervisor released reset"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::StatusReport;
+
+    #[test]
+    fn test_edges() {
+        let mut events = [0; EVENT_SIZE * 2];
+        events[..8].copy_from_slice(&1_500_000_000u64.to_ne_bytes());
+        events[8..12].copy_from_slice(&EVENT_RISING.to_ne_bytes());
+        events[EVENT_SIZE + 8..EVENT_SIZE + 12].copy_from_slice(&2u32.to_ne_bytes());
+        let edges: Vec<_> = edges(&events).collect();
+        assert_eq!(
+            edges,
+            [(Duration::from_millis(1500), true), (Duration::ZERO, false)]
+        );
+
+        stats::supervisor_watched(Duration::from_secs(60));
+        on_edge(true);
+        on_edge(false);
+        let mut status = StatusReport::new();
+        stats::report(&mut status);
+        assert_eq!(status.get("supervisor.reset_recently"), Some("1"));
+        assert!(status.get("supervisor.edges").is_some_and(|n| n != "0"));
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{config::TelemetryConfig, history::Event, net};
+use anyhow::Result;
+use log::warn;
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+static SINK: Mutex<Option<Sink>> = Mutex::new(None);
+
+/// Sends the significant events as EVENT packets from now on, e.g. to the ground software
+/// simulator on the flatsat, so that pass rehearsals include what the watchdog did
+pub fn start(config: &TelemetryConfig) -> Result<()> {
+    let sink = Sink::new(config)?;
+    *SINK.lock().unwrap_or_else(|e| e.into_inner()) = Some(sink);
+    Ok(())
+}
+
+/// Called for every recorded event, only the significant ones go out
+pub fn forward(event: &Event) {
+    let mut sink = SINK.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(sink) = &mut *sink {
+        sink.send(event);
+    }
+}
+
+struct Sink {
+    targets: Vec<(UdpSocket, SocketAddr)>,
+    /// Of the last event sent, so that the receiver can tell it missed some
+    seq: u32,
+}
+
+impl Sink {
+    fn new(config: &TelemetryConfig) -> Result<Self> {
+        Ok(Self {
+            targets: net::senders(&config.targets)?,
+            seq: 0,
+        })
+    }
+
+    /// Best effort, like the heartbeats
+    fn send(&mut self, event: &Event) {
+        let Some(fields) = fields(event) else {
+            return;
+        };
+        self.seq = self.seq.wrapping_add(1);
+        let packet = format!("EVENT {} {:.3} {}", self.seq, timestamp(), fields);
+        for (socket, target) in &self.targets {
+            if let Err(e) = socket.send_to(packet.as_bytes(), target) {
+                warn!("Failed to send event to {}: {}", target, e);
+            }
+        }
+    }
+}
+
+/// What's sent of an event, None for the ones that happen all the time
+pub fn fields(event: &Event) -> Option<String> {
+    let fields = match event {
+        Event::State { from, to, cause } => format!("state {} {} {}", from, to, cause),
+        Event::Strike {
+            client,
+            strikes,
+            max,
+        } => format!("strike {} {} {}", client, strikes, max),
+        Event::Inhibit { duration, name } => {
+            format!("inhibit {} {}", duration.as_secs_f64(), name)
+        }
+        Event::Uninhibit { name } => format!("uninhibit {}", name),
+        Event::DeathWarning { left, reason } => {
+            format!("warning {:.1} {}", left.as_secs_f64(), reason)
+        }
+        Event::Anomaly { what } => format!("anomaly {}", what),
+        Event::Rung { rung, client, left } => {
+            format!("rung {} {} {:.1}", rung, client, left.as_secs_f64())
+        }
+        Event::Missed { client } => format!("missed {}", client),
+        Event::Criticality {
+            client,
+            criticality,
+        } => format!("criticality {} {}", client, criticality.name()),
+        Event::PeerCycle { peer } => format!("peer_cycle {}", peer),
+        Event::DegradedConfig { error } => format!("degraded_config {}", error),
+        Event::Panic { message } => format!("panic {}", message),
+        Event::Pet { .. }
+        | Event::Edge { .. }
+        | Event::LateWakeup { .. }
+        | Event::Ping { .. }
+        | Event::TimerReset { .. } => return None,
+    };
+    Some(fields)
+}
+
+/// Unix seconds
+pub fn timestamp() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, state::State};
+    use std::time::Duration;
+
+    #[test]
+    fn test_sink() -> Result<()> {
+        let listener = UdpSocket::bind("127.0.0.1:0")?;
+        listener.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let config = Config::parse(&format!(
+            "[telemetry]\ntargets = [\"{}\"]",
+            listener.local_addr()?
+        ))?;
+        let mut sink = Sink::new(config.telemetry.as_ref().unwrap())?;
+        sink.send(&Event::Pet { value: true });
+        sink.send(&Event::State {
+            from: State::Armed,
+            to: State::DeathRow,
+            cause: String::from("c3 client stopped pinging"),
+        });
+        sink.send(&Event::Inhibit {
+            duration: Duration::from_secs(300),
+            name: String::from("deploy"),
+        });
+        let mut buf = [0; 512];
+        let len = listener.recv(&mut buf)?;
+        let packet = std::str::from_utf8(&buf[..len])?;
+        assert!(packet.starts_with("EVENT 1 "));
+        assert!(packet.ends_with(" state armed death_row c3 client stopped pinging"));
+        let len = listener.recv(&mut buf)?;
+        let packet = std::str::from_utf8(&buf[..len])?;
+        assert!(packet.starts_with("EVENT 2 ") && packet.ends_with(" inhibit 300 deploy"));
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{
+    clock::{Clock, Timer},
+    condition::Condition,
+    config::TouchConfig,
+    pingee::Pingee,
+    stats,
+};
+use anyhow::Result;
+use log::warn;
+use mio::{unix::SourceFd, Interest, Registry, Token};
+use nix::{
+    errno::Errno,
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor},
+};
+use std::{
+    ffi::OsString,
+    fs,
+    os::fd::{AsFd, AsRawFd},
+    path::Path,
+    time::Duration,
+};
+
+struct Touch {
+    config: TouchConfig,
+    /// Its directory's watch, None if it has to be polled instead
+    watch: Option<WatchDescriptor>,
+}
+
+/// Files that feed a client whenever they're touched, for scripts that can't send UDP. A file
+/// counts if its mtime is at most `max_age` old, checked on inotify events on its directory so
+/// that it can be replaced or created later. Where inotify doesn't work, e.g. on network file
+/// systems, the file is stat'ed every half `max_age` instead.
+pub struct TouchFiles {
+    inotify: Inotify,
+    touches: Vec<Touch>,
+    /// For the polled ones, if any
+    timer: Box<dyn Timer>,
+    interval: Option<Duration>,
+}
+
+impl TouchFiles {
+    /// Registered with `first` for the inotify events and the one after it for the polling
+    pub fn new(
+        registry: &Registry,
+        first: Token,
+        configs: &[TouchConfig],
+        clock: &dyn Clock,
+    ) -> Result<Self> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        let flags = AddWatchFlags::IN_ATTRIB
+            | AddWatchFlags::IN_CLOSE_WRITE
+            | AddWatchFlags::IN_MOVED_TO
+            | AddWatchFlags::IN_CREATE;
+        let mut touches = Vec::new();
+        for config in configs {
+            let dir = config.path.parent().unwrap_or(Path::new("/"));
+            let watch = inotify
+                .add_watch(dir, flags)
+                .inspect_err(|e| warn!("Polling {:?}, it can't be watched: {}", config.path, e))
+                .ok();
+            touches.push(Touch {
+                config: config.clone(),
+                watch,
+            });
+        }
+        let interval = touches
+            .iter()
+            .filter(|t| t.watch.is_none())
+            .map(|t| t.config.max_age / 2)
+            .min();
+        let timer = clock.timer()?;
+        if let Some(interval) = interval {
+            timer.set(interval)?;
+        }
+        let fd = inotify.as_fd().as_raw_fd();
+        registry.register(&mut SourceFd(&fd), first, Interest::READABLE)?;
+        timer.register(registry, Token(first.0 + 1))?;
+        Ok(Self {
+            inotify,
+            touches,
+            timer,
+            interval,
+        })
+    }
+
+    fn on_changes(&mut self, pingee: &mut Pingee) -> Result<()> {
+        let mut changed: Vec<(WatchDescriptor, OsString)> = Vec::new();
+        loop {
+            match self.inotify.read_events() {
+                Ok(events) => {
+                    let named = events.into_iter().filter_map(|e| Some((e.wd, e.name?)));
+                    changed.extend(named);
+                }
+                Err(Errno::EAGAIN) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        for touch in &self.touches {
+            let name = touch.config.path.file_name();
+            let touched = changed
+                .iter()
+                .any(|(wd, changed)| touch.watch == Some(*wd) && name == Some(changed.as_os_str()));
+            if touched {
+                feed(touch, pingee)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_timer(&mut self, pingee: &mut Pingee) -> Result<()> {
+        let Some(interval) = self.interval else {
+            return Ok(());
+        };
+        self.timer.set(interval)?;
+        for touch in self.touches.iter().filter(|t| t.watch.is_none()) {
+            feed(touch, pingee)?;
+        }
+        Ok(())
+    }
+}
+
+impl Condition for TouchFiles {
+    fn name(&self) -> &'static str {
+        "touch files"
+    }
+
+    fn tokens(&self) -> usize {
+        2
+    }
+
+    fn on_event(&mut self, index: usize, pingee: &mut Pingee) -> Result<Option<String>> {
+        match index {
+            0 => self.on_changes(pingee)?,
+            _ => self.on_timer(pingee)?,
+        }
+        Ok(None)
+    }
+}
+
+fn feed(touch: &Touch, pingee: &mut Pingee) -> Result<()> {
+    if fresh(&touch.config.path, touch.config.max_age) {
+        pingee.feed(Some(&touch.config.client))?;
+        stats::ping(&touch.config.client);
+    }
+    Ok(())
+}
+
+/// A file that's missing or can't be stat'ed simply isn't
+fn fresh(path: &Path, max_age: Duration) -> bool {
+    match fs::metadata(path).and_then(|m| m.modified()) {
+        // In the future counts, the clock may have been set back
+        Ok(modified) => modified.elapsed().map_or(true, |age| age <= max_age),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::Monotonic, config::Config};
+    use mio::{Events, Poll};
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_fresh() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("watchdog-touch-{}", std::process::id()));
+        let max_age = Duration::from_secs(10);
+        assert!(!fresh(&path, max_age));
+        let file = fs::File::create(&path)?;
+        assert!(fresh(&path, max_age));
+        file.set_modified(SystemTime::now() - Duration::from_secs(60))?;
+        assert!(!fresh(&path, max_age));
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_touch_files() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("watchdog-touches-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let config = Config::parse(&format!(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "script"
+            timeout = 30
+            [[touch]]
+            path = {:?}
+            client = "script"
+            "#,
+            dir.join("alive")
+        ))?;
+        let mut pingee = Pingee::new(&config, Token(10), Token(90))?;
+        let mut poll = Poll::new()?;
+        let mut events = Events::with_capacity(4);
+        let mut touches = TouchFiles::new(poll.registry(), Token(0), &config.touches, &Monotonic)?;
+        assert!(touches.touches[0].watch.is_some());
+
+        // Created after the watch started, among other changes in the directory
+        fs::write(dir.join("other"), "")?;
+        fs::write(dir.join("alive"), "")?;
+        poll.poll(&mut events, Some(Duration::from_secs(1)))?;
+        let tokens: Vec<_> = events.iter().map(|e| e.token()).collect();
+        assert_eq!(tokens, [Token(0)]);
+        touches.on_event(0, &mut pingee)?;
+        assert!(stats::last_pings()
+            .iter()
+            .any(|(client, _)| client == "script"));
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
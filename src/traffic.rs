@@ -0,0 +1,185 @@
+use crate::{capture::Verdict, clock::Clock, status::StatusReport};
+use log::{info, warn};
+use std::{collections::HashMap, net::IpAddr, sync::Arc, time::Duration, time::Instant};
+
+/// Sources kept track of at once. Past that the one heard from longest ago makes way.
+const SOURCES: usize = 64;
+/// Junk is judged over windows this long
+const WINDOW: Duration = Duration::from_secs(60);
+/// Junk packets in a window, outnumbering the good ones, that make a source anomalous
+const JUNK: u32 = 10;
+/// Offenders shown in the status
+const TOP: usize = 3;
+
+#[derive(Default)]
+struct Counts {
+    pings: u64,
+    commands: u64,
+    malformed: u64,
+    denied: u64,
+    unknown: u64,
+    blocked: u64,
+}
+
+impl Counts {
+    fn junk(&self) -> u64 {
+        self.malformed + self.denied + self.unknown + self.blocked
+    }
+}
+
+struct Source {
+    counts: Counts,
+    last_seen: Instant,
+    /// When the current window started, with the junk and the good packets in it
+    window: (Instant, u32, u32),
+    anomalous: bool,
+}
+
+impl Source {
+    fn qualifies(&self) -> bool {
+        let (_, junk, good) = self.window;
+        junk >= JUNK && junk > good
+    }
+}
+
+/// What each source address sent, by verdict, so that e.g. a misconfigured test client flooding
+/// the port during integration stands out. A source whose junk outnumbers its pings and commands,
+/// at least `JUNK` in a window, is flagged as anomalous until a window without.
+pub struct Traffic {
+    sources: HashMap<IpAddr, Source>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Traffic {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            sources: HashMap::new(),
+            clock,
+        }
+    }
+
+    pub fn record(&mut self, source: IpAddr, verdict: Verdict) {
+        let now = self.clock.now();
+        if !self.sources.contains_key(&source) && self.sources.len() == SOURCES {
+            let oldest = self.sources.iter().min_by_key(|(_, s)| s.last_seen);
+            let oldest = *oldest.unwrap().0;
+            self.sources.remove(&oldest);
+        }
+        let s = self.sources.entry(source).or_insert_with(|| Source {
+            counts: Counts::default(),
+            last_seen: now,
+            window: (now, 0, 0),
+            anomalous: false,
+        });
+        s.last_seen = now;
+        if now.saturating_duration_since(s.window.0) >= WINDOW {
+            if s.anomalous && !s.qualifies() {
+                info!("{} is well-behaved again", source);
+                s.anomalous = false;
+            }
+            s.window = (now, 0, 0);
+        }
+        let counts = &mut s.counts;
+        let (count, junk) = match verdict {
+            Verdict::Fed => (&mut counts.pings, false),
+            Verdict::Command => (&mut counts.commands, false),
+            Verdict::Malformed => (&mut counts.malformed, true),
+            Verdict::Denied => (&mut counts.denied, true),
+            Verdict::Unknown => (&mut counts.unknown, true),
+            Verdict::Blocked => (&mut counts.blocked, true),
+            Verdict::Ignored => return,
+        };
+        *count += 1;
+        match junk {
+            true => s.window.1 += 1,
+            false => s.window.2 += 1,
+        }
+        if !s.anomalous && s.qualifies() {
+            warn!(
+                "{} turned anomalous, {} junk packets to {} good in {:?}",
+                source, s.window.1, s.window.2, WINDOW
+            );
+            s.anomalous = true;
+        }
+    }
+
+    /// A source that went quiet stops counting as anomalous a window after its last one
+    fn anomalous(&self, source: &Source, now: Instant) -> bool {
+        source.anomalous && now.saturating_duration_since(source.window.0) < 2 * WINDOW
+    }
+
+    /// The counts and flags of the sources with the most junk
+    pub fn report(&self, status: &mut StatusReport) {
+        let now = self.clock.now();
+        let anomalous = self.sources.values().filter(|s| self.anomalous(s, now));
+        status.push("sources.tracked", self.sources.len());
+        status.push("sources.anomalous", anomalous.count());
+        let mut offenders: Vec<_> = self
+            .sources
+            .iter()
+            .filter(|(_, s)| s.counts.junk() > 0)
+            .collect();
+        offenders.sort_by_key(|(ip, s)| (std::cmp::Reverse(s.counts.junk()), **ip));
+        for (ip, s) in offenders.into_iter().take(TOP) {
+            let counts = &s.counts;
+            status.push(format!("source.{}.pings", ip), counts.pings);
+            status.push(format!("source.{}.commands", ip), counts.commands);
+            status.push(format!("source.{}.malformed", ip), counts.malformed);
+            status.push(format!("source.{}.denied", ip), counts.denied);
+            status.push(format!("source.{}.unknown", ip), counts.unknown);
+            status.push(format!("source.{}.blocked", ip), counts.blocked);
+            let flag = self.anomalous(s, now) as u8;
+            status.push(format!("source.{}.anomalous", ip), flag);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+
+    #[test]
+    fn test_traffic() {
+        let clock = VirtualClock::new();
+        let mut traffic = Traffic::new(Arc::new(clock.clone()));
+        let good: IpAddr = "192.168.10.4".parse().unwrap();
+        let flood: IpAddr = "192.168.10.9".parse().unwrap();
+        for _ in 0..20 {
+            traffic.record(good, Verdict::Fed);
+            traffic.record(flood, Verdict::Fed);
+        }
+        traffic.record(good, Verdict::Malformed);
+        for _ in 0..25 {
+            traffic.record(flood, Verdict::Malformed);
+            traffic.record(flood, Verdict::Denied);
+        }
+        let mut status = StatusReport::new();
+        traffic.report(&mut status);
+        assert_eq!(status.get("sources.tracked"), Some("2"));
+        assert_eq!(status.get("sources.anomalous"), Some("1"));
+        assert_eq!(status.get("source.192.168.10.9.denied"), Some("25"));
+        assert_eq!(status.get("source.192.168.10.9.anomalous"), Some("1"));
+        assert_eq!(status.get("source.192.168.10.4.malformed"), Some("1"));
+        assert_eq!(status.get("source.192.168.10.4.anomalous"), Some("0"));
+
+        // Back to pinging alone, for a whole window
+        clock.advance(WINDOW);
+        traffic.record(flood, Verdict::Fed);
+        let mut status = StatusReport::new();
+        traffic.report(&mut status);
+        assert_eq!(status.get("sources.anomalous"), Some("1"));
+        clock.advance(WINDOW);
+        traffic.record(flood, Verdict::Fed);
+        let mut status = StatusReport::new();
+        traffic.report(&mut status);
+        assert_eq!(status.get("sources.anomalous"), Some("0"));
+        assert_eq!(status.get("source.192.168.10.9.malformed"), Some("25"));
+
+        for i in 0..SOURCES as u8 {
+            traffic.record(IpAddr::from([10, 0, 0, i]), Verdict::Unknown);
+        }
+        assert_eq!(traffic.sources.len(), SOURCES);
+        assert!(!traffic.sources.contains_key(&good));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{condition::Condition, config::UnitConfig, pingee::Pingee};
+use anyhow::{Context, Result};
+use log::{info, warn};
+use mio::{Registry, Token, Waker};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver},
+        Arc,
+    },
+    thread,
+};
+use zbus::{
+    blocking::{Connection, Proxy},
+    zvariant::OwnedObjectPath,
+};
+
+const SYSTEMD: &str = "org.freedesktop.systemd1";
+const MANAGER_PATH: &str = "/org/freedesktop/systemd1";
+const MANAGER: &str = "org.freedesktop.systemd1.Manager";
+const UNIT: &str = "org.freedesktop.systemd1.Unit";
+
+struct Unit {
+    config: UnitConfig,
+    state: String,
+}
+
+/// Watches the ActiveState of systemd units over D-Bus.
+///
+/// zbus only offers a blocking (or async) interface, so each unit gets a thread that forwards
+/// PropertiesChanged updates over a channel and wakes up the poll loop.
+pub struct UnitMonitor {
+    units: Vec<Unit>,
+    updates: Receiver<(usize, String)>,
+}
+
+impl UnitMonitor {
+    pub fn new(registry: &Registry, token: Token, configs: &[UnitConfig]) -> Result<Self> {
+        let (tx, updates) = channel();
+        let mut units = Vec::new();
+        if !configs.is_empty() {
+            let waker = Arc::new(Waker::new(registry, token)?);
+            let conn = Connection::system().context("Failed to connect to the system D-Bus")?;
+            for (index, config) in configs.iter().enumerate() {
+                let path = unit_path(&conn, &config.name)?;
+                let (tx, waker) = (tx.clone(), waker.clone());
+                let send = move |state| tx.send((index, state)).is_ok() && waker.wake().is_ok();
+                spawn_watcher(conn.clone(), path, format!("unit-{}", index), send)?;
+                units.push(Unit {
+                    config: config.clone(),
+                    state: String::from("unknown"),
+                });
+            }
+        }
+        Ok(Self { units, updates })
+    }
+
+    /// Applies the pending unit state updates, returning why if a unit with a fail policy has
+    /// failed
+    fn on_update(&mut self) -> Option<String> {
+        let mut failed = None;
+        while let Ok((index, state)) = self.updates.try_recv() {
+            let unit = &mut self.units[index];
+            info!("Unit {} is {}", unit.config.name, state);
+            unit.state = state;
+            if unit.config.policy.fails() && unit.state == "failed" {
+                failed.get_or_insert_with(|| format!("Unit {} failed", unit.config.name));
+            }
+        }
+        failed
+    }
+
+    /// The clients fed by currently active units with a ping policy, None being the anonymous
+    /// client.
+    fn feeding(&self) -> impl Iterator<Item = Option<&str>> {
+        self.units
+            .iter()
+            .filter(|unit| unit.config.policy.pings() && unit.state == "active")
+            .map(|unit| unit.config.client.as_deref())
+    }
+}
+
+impl Condition for UnitMonitor {
+    fn name(&self) -> &'static str {
+        "systemd units"
+    }
+
+    fn on_event(&mut self, _index: usize, _pingee: &mut Pingee) -> Result<Option<String>> {
+        Ok(self.on_update())
+    }
+
+    /// Active units feed their clients for as long as they stay active
+    fn on_pet(&mut self, pingee: &mut Pingee) -> Result<()> {
+        for client in self.feeding() {
+            pingee.feed(client)?;
+        }
+        Ok(())
+    }
+}
+
+fn unit_path(conn: &Connection, name: &str) -> Result<OwnedObjectPath> {
+    let manager = Proxy::new(conn, SYSTEMD, MANAGER_PATH, MANAGER)?;
+    manager
+        .call("LoadUnit", &(name,))
+        .with_context(|| format!("Failed to load unit {}", name))
+}
+
+/// Follows whether `name` is active from a thread of its own, for the startup gates
+pub fn watch_active(name: &str, active: Arc<AtomicBool>) -> Result<()> {
+    let conn = Connection::system().context("Failed to connect to the system D-Bus")?;
+    let path = unit_path(&conn, name)?;
+    let send = move |state: String| {
+        active.store(state == "active", Ordering::Relaxed);
+        true
+    };
+    spawn_watcher(conn, path, format!("gate-{}", name), send)
+}
+
+/// Hands `send` the unit's ActiveState and every change of it, until it returns false
+fn spawn_watcher(
+    conn: Connection,
+    path: OwnedObjectPath,
+    thread: String,
+    send: impl Fn(String) -> bool + Send + 'static,
+) -> Result<()> {
+    let proxy = Proxy::new(&conn, SYSTEMD, path, UNIT)?;
+    thread::Builder::new().name(thread).spawn(move || {
+        // The initial state, later updates only come when it changes
+        let state = proxy
+            .get_property::<String>("ActiveState")
+            .unwrap_or_else(|e| {
+                warn!("Failed to get unit state: {}", e);
+                String::from("unknown")
+            });
+        if !send(state) {
+            return;
+        }
+        for change in proxy.receive_property_changed::<String>("ActiveState") {
+            let state = change.get().unwrap_or_else(|e| {
+                warn!("Failed to get unit state: {}", e);
+                String::from("unknown")
+            });
+            if !send(state) {
+                return;
+            }
+        }
+        warn!("Unit state updates stopped");
+        send(String::from("unknown"));
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UnitPolicy;
+    use std::sync::mpsc::Sender;
+
+    fn monitor(policy: UnitPolicy) -> (UnitMonitor, Sender<(usize, String)>) {
+        let (tx, updates) = channel();
+        let unit = Unit {
+            config: UnitConfig {
+                name: String::from("test.service"),
+                policy,
+                client: None,
+            },
+            state: String::from("unknown"),
+        };
+        (
+            UnitMonitor {
+                units: vec![unit],
+                updates,
+            },
+            tx,
+        )
+    }
+
+    #[test]
+    fn test_unit_policies() -> Result<()> {
+        let (mut ping, tx) = monitor(UnitPolicy::Ping);
+        assert_eq!(ping.feeding().count(), 0);
+        tx.send((0, String::from("active")))?;
+        assert_eq!(ping.on_update(), None);
+        assert_eq!(ping.feeding().collect::<Vec<_>>(), [None]);
+        tx.send((0, String::from("failed")))?;
+        assert_eq!(ping.on_update(), None);
+        assert_eq!(ping.feeding().count(), 0);
+
+        let (mut fail, tx) = monitor(UnitPolicy::Fail);
+        tx.send((0, String::from("active")))?;
+        assert_eq!(fail.on_update(), None);
+        assert_eq!(fail.feeding().count(), 0);
+        tx.send((0, String::from("failed")))?;
+        assert_eq!(
+            fail.on_update().as_deref(),
+            Some("Unit test.service failed")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use crate::clock::{Boottime, Clock, Timer};
+use anyhow::Result;
+use mio::{Registry, Token};
+use nix::time::{clock_gettime, ClockId};
+use std::time::Duration;
+
+/// Resets the card once it has been up for `max_uptime`, counted from boot on CLOCK_BOOTTIME so
+/// that neither restarts of the watchdog nor suspends push it back
+pub struct MaxUptime {
+    max: Duration,
+    timer: Box<dyn Timer>,
+}
+
+impl MaxUptime {
+    /// Zero never resets. Already being past it fires right away.
+    pub fn new(max: Duration) -> Result<Self> {
+        let timer = Boottime::new(false)?.timer()?;
+        let mut uptime = Self {
+            max: Duration::ZERO,
+            timer,
+        };
+        uptime.set(max)?;
+        Ok(uptime)
+    }
+
+    pub fn register(&self, registry: &Registry, token: Token) -> Result<()> {
+        self.timer.register(registry, token)
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Moves the reset to `max` after boot, zero never resets
+    pub fn set(&mut self, max: Duration) -> Result<()> {
+        self.max = max;
+        if max.is_zero() {
+            self.timer.unset()?;
+            return Ok(());
+        }
+        // A zero expiration would disarm it instead
+        let left = max.saturating_sub(uptime()?).max(Duration::from_nanos(1));
+        self.timer.set(left)
+    }
+
+    /// The reason to reset, once the timer fired
+    pub fn on_expired(&self) -> Result<String> {
+        Ok(format!(
+            "Up for {}s, past the maximum uptime of {}s",
+            uptime()?.as_secs(),
+            self.max.as_secs()
+        ))
+    }
+}
+
+fn uptime() -> Result<Duration> {
+    Ok(clock_gettime(ClockId::CLOCK_BOOTTIME)?.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mio::{Events, Poll};
+
+    #[test]
+    fn test_max_uptime() -> Result<()> {
+        let mut poll = Poll::new()?;
+        let mut events = Events::with_capacity(4);
+        let never = MaxUptime::new(Duration::ZERO)?;
+        never.register(poll.registry(), Token(0))?;
+
+        // The test machine has been up for longer than a second
+        let past = MaxUptime::new(Duration::from_secs(1))?;
+        past.register(poll.registry(), Token(1))?;
+        poll.poll(&mut events, Some(Duration::from_secs(1)))?;
+        let tokens: Vec<_> = events.iter().map(|e| e.token()).collect();
+        assert_eq!(tokens, [Token(1)]);
+        assert!(past.on_expired()?.ends_with("maximum uptime of 1s"));
+        Ok(())
+    }
+}
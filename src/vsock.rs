@@ -0,0 +1,239 @@
+use crate::pingee::Transport;
+use anyhow::{Context, Result};
+use log::debug;
+use mio::{event::Source, unix::SourceFd, Interest, Registry, Token};
+use nix::{
+    errno::Errno,
+    sys::socket::{
+        accept4, bind, getpeername, listen, recv, send, socket, AddressFamily, MsgFlags, SockFlag,
+        SockType, VsockAddr,
+    },
+};
+use std::{
+    cell::RefCell,
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, SocketAddr},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+};
+
+/// Connections open at once. Past that new ones are refused.
+const CONNECTIONS: usize = 16;
+
+/// Peers show as addresses in 0.0.0.0/8, which no UDP packet comes from, with their CID as the
+/// address and their connection's slot as the port
+fn address(cid: u32, slot: usize) -> Option<SocketAddr> {
+    (cid <= 0x00ff_ffff).then(|| SocketAddr::from((Ipv4Addr::from(cid), slot as u16)))
+}
+
+struct Connection {
+    fd: OwnedFd,
+    cid: u32,
+}
+
+/// An AF_VSOCK listener, for a host pinging and commanding the watchdog running in a VM, like the
+/// hardware-in-the-loop rig's harness. Each SOCK_SEQPACKET message is one packet, and replies go
+/// back over the connection it came in on.
+pub struct VsockSocket {
+    port: u32,
+    listener: OwnedFd,
+    connections: RefCell<Vec<Option<Connection>>>,
+    /// Where the connections get registered too, once the listener is
+    registry: Option<(Registry, Token)>,
+}
+
+impl VsockSocket {
+    /// Listens on `port` for any CID
+    pub fn bind(port: u32) -> Result<Self> {
+        let listener = socket(
+            AddressFamily::Vsock,
+            SockType::SeqPacket,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            None,
+        )
+        .context("Failed to create a vsock socket")?;
+        bind(
+            listener.as_raw_fd(),
+            &VsockAddr::new(libc::VMADDR_CID_ANY, port),
+        )
+        .with_context(|| format!("Failed to bind vsock port {}", port))?;
+        listen(&listener, CONNECTIONS)?;
+        Ok(Self {
+            port,
+            listener,
+            connections: RefCell::new((0..CONNECTIONS).map(|_| None).collect()),
+            registry: None,
+        })
+    }
+
+    fn on_accept(&self) -> io::Result<()> {
+        loop {
+            let fd = match accept4(
+                self.listener.as_raw_fd(),
+                SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            ) {
+                Ok(fd) => fd,
+                Err(Errno::EAGAIN) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            // SAFETY: just accepted, nothing else owns it
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            let cid = getpeername::<VsockAddr>(fd.as_raw_fd())?.cid();
+            let mut connections = self.connections.borrow_mut();
+            let Some(slot) = connections.iter().position(Option::is_none) else {
+                debug!("Refused vsock connection from CID {}", cid);
+                continue;
+            };
+            if address(cid, slot).is_none() {
+                debug!("Refused vsock connection from CID {}", cid);
+                continue;
+            }
+            if let Some((registry, token)) = &self.registry {
+                let raw = fd.as_raw_fd();
+                registry.register(&mut SourceFd(&raw), *token, Interest::READABLE)?;
+            }
+            connections[slot] = Some(Connection { fd, cid });
+        }
+    }
+
+    fn close(&self, slot: usize) {
+        if let Some(connection) = self.connections.borrow_mut()[slot].take() {
+            if let Some((registry, _)) = &self.registry {
+                let _ = registry.deregister(&mut SourceFd(&connection.fd.as_raw_fd()));
+            }
+        }
+    }
+}
+
+impl Transport for VsockSocket {
+    /// Accepts whoever is waiting, then takes the next message off any connection
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.on_accept()?;
+        for slot in 0..CONNECTIONS {
+            let Some((fd, cid)) = self.connections.borrow()[slot]
+                .as_ref()
+                .map(|c| (c.fd.as_raw_fd(), c.cid))
+            else {
+                continue;
+            };
+            match recv(fd, buf, MsgFlags::MSG_DONTWAIT) {
+                Ok(0) => self.close(slot),
+                Ok(len) => return Ok((len, address(cid, slot).unwrap())),
+                Err(Errno::EAGAIN) => (),
+                Err(e) => {
+                    debug!("Vsock connection from CID {} failed: {}", cid, e);
+                    self.close(slot);
+                }
+            }
+        }
+        Err(ErrorKind::WouldBlock.into())
+    }
+
+    fn send_to(&self, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let connections = self.connections.borrow();
+        let connection = connections
+            .get(target.port() as usize)
+            .and_then(Option::as_ref)
+            .filter(|c| address(c.cid, target.port() as usize) == Some(target))
+            .ok_or(ErrorKind::NotConnected)?;
+        let flags = MsgFlags::MSG_DONTWAIT | MsgFlags::MSG_NOSIGNAL;
+        Ok(send(connection.fd.as_raw_fd(), buf, flags)?)
+    }
+
+    /// Its port doesn't fit a SocketAddr
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            format!("vsock port {}", self.port),
+        ))
+    }
+
+    fn reaches(&self, target: SocketAddr) -> bool {
+        matches!(target, SocketAddr::V4(v4) if v4.ip().octets()[0] == 0)
+    }
+}
+
+impl Source for VsockSocket {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.listener.as_raw_fd()).register(registry, token, interests)?;
+        for connection in self.connections.borrow().iter().flatten() {
+            SourceFd(&connection.fd.as_raw_fd()).register(registry, token, interests)?;
+        }
+        self.registry = Some((registry.try_clone()?, token));
+        Ok(())
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        SourceFd(&self.listener.as_raw_fd()).reregister(registry, token, interests)?;
+        for connection in self.connections.borrow().iter().flatten() {
+            SourceFd(&connection.fd.as_raw_fd()).reregister(registry, token, interests)?;
+        }
+        self.registry = Some((registry.try_clone()?, token));
+        Ok(())
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        SourceFd(&self.listener.as_raw_fd()).deregister(registry)?;
+        for connection in self.connections.borrow().iter().flatten() {
+            SourceFd(&connection.fd.as_raw_fd()).deregister(registry)?;
+        }
+        self.registry = None;
+        Ok(())
+    }
+}
+
+impl AsRawFd for VsockSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::socket::connect;
+
+    #[test]
+    fn test_address() {
+        assert_eq!(address(2, 3), Some("0.0.0.2:3".parse().unwrap()));
+        assert_eq!(address(u32::MAX, 0), None);
+    }
+
+    #[test]
+    #[ignore = "needs the vsock_loopback module"]
+    fn test_loopback() -> Result<()> {
+        let port = 20000 + std::process::id() % 10000;
+        let vsock = VsockSocket::bind(port)?;
+        let client = socket(
+            AddressFamily::Vsock,
+            SockType::SeqPacket,
+            SockFlag::empty(),
+            None,
+        )?;
+        connect(
+            client.as_raw_fd(),
+            &VsockAddr::new(libc::VMADDR_CID_LOCAL, port),
+        )?;
+        send(client.as_raw_fd(), b"PING", MsgFlags::empty())?;
+        let mut buf = [0; 16];
+        let (len, source) = vsock.recv_from(&mut buf)?;
+        assert_eq!(&buf[..len], b"PING");
+        assert!(vsock.reaches(source));
+        vsock.send_to(b"OK", source)?;
+        assert_eq!(recv(client.as_raw_fd(), &mut buf, MsgFlags::empty())?, 2);
+        assert_eq!(
+            vsock.recv_from(&mut buf).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,2058 @@
+#[cfg(feature = "mqtt")]
+use crate::mqtt::Mqtt;
+#[cfg(not(feature = "flight"))]
+use crate::petter::DryRun;
+use crate::{
+    anomaly::TimeAnomalies,
+    battery::Battery,
+    bus,
+    cadence::Cadence,
+    clock::{self, Clock, Monotonic, Wheel},
+    condition::{self, Conditions},
+    config::{self, BiteAction, BusyPolicy, Config, TimerClock},
+    counters::BootCounter,
+    death_row::DeathRow,
+    dictionary::Counter,
+    exit::Cause,
+    gate::StartupGate,
+    handover::{self, Handover, Inherited},
+    heartbeat::Heartbeat,
+    history,
+    hook::Hook,
+    http::Http,
+    instance::InstanceLock,
+    kernel_watchdog::KernelWatchdog,
+    ladder::Ladder,
+    last_gasp::{self, LastGasp},
+    late_pet::LatePets,
+    line::{self, Line},
+    logbook::Logbook,
+    maintenance::Maintenance,
+    margin::MarginReport,
+    mirror::PetMirror,
+    nmt::Nmt,
+    notify::{self, Keepalive},
+    panic,
+    petter::{Backend, Busy, Petter, Pwm, SysfsGpio, GPIO_LABEL, GPIO_LINE},
+    pingee::{Action, Pingee, Transport},
+    power::LowPower,
+    privileges,
+    process::ProcessMonitor,
+    profile::Profiles,
+    realtime, reexec, reset_reason,
+    reset_request::ResetRequest,
+    retry::Retry,
+    rule::Rules,
+    schedule::Schedule,
+    sdo::{Objects, Sdo},
+    seccomp, self_test,
+    silence::CanSilence,
+    sinks,
+    source::{Ready, Sources},
+    stall::StallDetector,
+    standby::Standby,
+    stats,
+    status_file::StatusFile,
+    status_socket::StatusSocket,
+    supervisor::SupervisorLine,
+    telemetry,
+    uptime::MaxUptime,
+    vsock::VsockSocket,
+};
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use mio::{
+    unix::{pipe, SourceFd},
+    Events, Interest, Poll, Registry, Token,
+};
+use nix::sys::{
+    signal::{Signal, SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGUSR1, SIGUSR2},
+    signalfd::{SfdFlags, SigSet, SignalFd},
+};
+#[cfg(feature = "async")]
+use std::os::fd::RawFd;
+use std::{
+    future::Future,
+    io::{ErrorKind, Read, Write},
+    os::fd::{AsRawFd, OwnedFd},
+    path::Path,
+    pin::pin,
+    sync::Arc,
+    task::{self, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+#[cfg(feature = "async")]
+use tokio::{
+    io::unix::AsyncFd,
+    signal::unix::{self as tokio_signal, SignalKind},
+    time,
+};
+
+const PING: Token = Token(0);
+const PET: Token = Token(1);
+const SIGNAL: Token = Token(3);
+const DEATH_ROW: Token = Token(5);
+const HOOK: Token = Token(6);
+const HANDOVER: Token = Token(7);
+const SHUTDOWN: Token = Token(8);
+const UPTIME: Token = Token(9);
+const SCHEDULE: Token = Token(10);
+const BATTERY: Token = Token(11);
+const SDO: Token = Token(12);
+const NMT: Token = Token(13);
+const CAN_SILENCE: Token = Token(14);
+const CAN_SILENCE_TIMER: Token = Token(15);
+const WHEEL: Token = Token(16);
+const POWER: Token = Token(17);
+const PROFILE: Token = Token(18);
+const RULES: Token = Token(19);
+const MAINTENANCE: Token = Token(20);
+// Up to source::TOKENS
+const SOURCES: Token = Token(24);
+// Each ping client, extra port, and watched process gets its own token from here on up
+const TIMEOUTS: Token = Token(64);
+const PORTS: Token = Token(TIMEOUTS.0 + config::MAX_CLIENTS);
+const PROCESSES: Token = Token(PORTS.0 + config::MAX_PORTS);
+// Past the processes, up to condition::TOKENS
+const CONDITIONS: Token = Token(512);
+// Past the conditions, each line gets line::TOKENS from here on up
+const LINES: Token = Token(CONDITIONS.0 + condition::TOKENS);
+
+// Between tries for a busy watchdog line
+const BUSY_RETRY: Duration = Duration::from_millis(500);
+
+const SIGNALS: [Signal; 6] = [SIGTERM, SIGHUP, SIGINT, SIGQUIT, SIGUSR1, SIGUSR2];
+
+/// Why the main loop stopped, other than an error
+enum Stop {
+    Signal(Signal),
+    Reset,
+    /// A successor took over the line, which must be left alone
+    HandedOver,
+    /// Asked to through a shutdown handle
+    Shutdown,
+}
+
+/// How the loop waits for its events
+enum Waiter {
+    /// In poll itself, blocking the thread
+    Blocking,
+    /// On the host's tokio runtime, for the epoll fd behind poll to become readable, or for a
+    /// signal to pass on
+    #[cfg(feature = "async")]
+    Tokio(AsyncFd<EpollFd>, Option<Forwarder>),
+}
+
+impl Waiter {
+    async fn wait(
+        &mut self,
+        poll: &mut Poll,
+        events: &mut Events,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        match self {
+            // Stopping and continuing the process interrupts the wait
+            Waiter::Blocking => loop {
+                match poll.poll(events, timeout) {
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    result => break result?,
+                }
+            },
+            #[cfg(feature = "async")]
+            Waiter::Tokio(epoll, forwarder) => {
+                let deadline = timeout.map(|t| time::Instant::now() + t);
+                loop {
+                    poll.poll(events, Some(Duration::ZERO))?;
+                    if !events.is_empty() {
+                        break;
+                    }
+                    let timer = async {
+                        match deadline {
+                            Some(deadline) => time::sleep_until(deadline).await,
+                            None => std::future::pending().await,
+                        }
+                    };
+                    let signal = async {
+                        match forwarder {
+                            Some(forwarder) => forwarder.forward().await,
+                            None => std::future::pending().await,
+                        }
+                    };
+                    tokio::select! {
+                        readable = epoll.readable() => readable?.clear_ready(),
+                        // For the next poll to pick up
+                        forwarded = signal => forwarded?,
+                        // Timed out, with no events
+                        () = timer => break,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The fd of a Poll, which it keeps owning
+#[cfg(feature = "async")]
+struct EpollFd(RawFd);
+
+#[cfg(feature = "async")]
+impl AsRawFd for EpollFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+/// Where the loop reads its signals from
+enum Signals {
+    Fd(SignalFd),
+    /// Passed on by a Forwarder
+    #[cfg(feature = "async")]
+    Pipe(pipe::Receiver),
+}
+
+/// Passes the signals from tokio's streams down a pipe, which the loop reads like the signalfd. A
+/// signalfd only gets the signals blocked in every thread, and the runtime's threads don't block
+/// them.
+#[cfg(feature = "async")]
+struct Forwarder {
+    streams: Vec<(Signal, tokio_signal::Signal)>,
+    sender: pipe::Sender,
+}
+
+#[cfg(feature = "async")]
+impl Forwarder {
+    fn new(sender: pipe::Sender) -> Result<Self> {
+        let streams = SIGNALS.iter().map(|&signal| {
+            let stream = tokio_signal::signal(SignalKind::from_raw(signal as i32))?;
+            Ok((signal, stream))
+        });
+        Ok(Self {
+            streams: streams.collect::<Result<_>>()?,
+            sender,
+        })
+    }
+
+    async fn forward(&mut self) -> Result<()> {
+        let signal = std::future::poll_fn(|cx| {
+            for (signal, stream) in &mut self.streams {
+                if let task::Poll::Ready(Some(())) = stream.poll_recv(cx) {
+                    return task::Poll::Ready(*signal);
+                }
+            }
+            task::Poll::Pending
+        })
+        .await;
+        self.sender.write_all(&[signal as u8])?;
+        Ok(())
+    }
+}
+
+/// Stops a running watchdog from any thread, like SIGINT would
+#[derive(Clone)]
+pub struct ShutdownHandle(Arc<pipe::Sender>);
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) -> Result<()> {
+        match (&*self.0).write(&[0]) {
+            // Full means a shutdown is pending already, broken that it's stopped already
+            Err(e) if !matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::BrokenPipe) => {
+                Err(e.into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How this instance came to hold the line
+enum Start {
+    Fresh,
+    /// By a previous instance, confirmed once petting
+    HandedOver(Inherited),
+    /// Across a re-exec of this process
+    Reexecuted,
+}
+
+/// The pet and ping loop, with everything it watches
+pub struct Watchdog {
+    config: Config,
+    poll: Poll,
+    /// Owned so that sources can still be (de)registered while polling
+    registry: Registry,
+    events: Events,
+    pingee: Pingee,
+    petter: Petter,
+    start: Start,
+    /// Only taken for the card's own line
+    lock: Option<InstanceLock>,
+    boot: Option<BootCounter>,
+    death_row: DeathRow,
+    uptime: MaxUptime,
+    schedule: Schedule,
+    battery: Battery,
+    rules: Rules,
+    maintenance: Maintenance,
+    sdo: Option<Sdo>,
+    nmt: Option<Nmt>,
+    can_silence: Option<CanSilence>,
+    reset_request: Option<ResetRequest>,
+    sources: Sources,
+    hook: Hook,
+    ladder: Option<Ladder>,
+    gate: Option<StartupGate>,
+    late_pets: LatePets,
+    kernel_watchdog: Option<KernelWatchdog>,
+    /// Only the binary handles signals, a host embedding the watchdog keeps its own
+    sfd: Option<Signals>,
+    shutdown: (ShutdownHandle, pipe::Receiver),
+    processes: ProcessMonitor,
+    conditions: Conditions,
+    lines: Vec<Line>,
+    wheel: Wheel,
+    low_power: Option<LowPower>,
+    profiles: Option<Profiles>,
+}
+
+impl Watchdog {
+    /// Acquires everything, the watchdog line on `gpio_chip` included, without petting yet. A
+    /// previous instance handing over takes precedence over the chip, and the line and sockets kept
+    /// across a re-exec over both. SIGTERM, SIGHUP, SIGINT, SIGQUIT, SIGUSR1, and SIGUSR2 are
+    /// blocked in this thread and handled by `run` instead, so this has to be called before any
+    /// other thread is started. A line someone else holds is waited for as the busy policy says. A
+    /// PWM channel for the pet pattern, or the line's sysfs GPIO, takes the line's place. Another
+    /// instance petting the same line is fatal, unless it's handing over. A standby waits for the
+    /// primary's heartbeats to stop first.
+    pub fn new(mut config: Config, gpio_chip: &str) -> Result<Self> {
+        let name = lock_name(&config, gpio_chip);
+        if let Some(resumed) = reexec::take().context(Cause::Gpio)? {
+            // Still interlocked if the flag is still there
+            let interlocked = config.interlock.as_ref().is_some_and(|i| i.path.exists());
+            config.armed = resumed.armed || interlocked;
+            let pingee = Pingee::inherit(&config, TIMEOUTS, PORTS, resumed.sockets)
+                .context(Cause::Socket)?;
+            let petter = Petter::inherit(resumed.line).context(Cause::Gpio)?;
+            let mut watchdog = Self::with(config, pingee, petter, Start::Reexecuted, true)?;
+            watchdog.lock = Some(match resumed.lock {
+                Some(socket) => InstanceLock::inherit(&name, socket),
+                None => InstanceLock::acquire(&name).context(Cause::Gpio)?,
+            });
+            info!("Resumed petting {} after a re-exec", name);
+            return Ok(watchdog);
+        }
+        let lock = match &config.standby {
+            Some(standby) => Standby::new(standby)?.wait(&name),
+            None => InstanceLock::acquire(&name),
+        }
+        .context(Cause::Gpio)?;
+        if !lock.held() && config.handover.is_none() {
+            let e = anyhow!("Another watchdog instance is already petting {}", name);
+            return Err(e.context(Cause::Gpio));
+        }
+        let mut watchdog = Self::acquire(config, gpio_chip)?;
+        watchdog.lock = Some(lock);
+        Ok(watchdog)
+    }
+
+    fn acquire(config: Config, gpio_chip: &str) -> Result<Self> {
+        if let Some(pwm) = &config.pet.pwm {
+            let pwm = Pwm::open(pwm, &config.pet.pattern)?;
+            let pingee = listening(&config)?;
+            let petter = Petter::with_backend(Box::new(pwm))?;
+            return Self::with(config, pingee, petter, Start::Fresh, true);
+        }
+        if let Some(number) = config.pet.sysfs_gpio {
+            let gpio = SysfsGpio::open(number)?;
+            let pingee = listening(&config)?;
+            let petter = Petter::with_backend(Box::new(gpio))?;
+            return Self::with(config, pingee, petter, Start::Fresh, true);
+        }
+        let deadline = Instant::now() + config.pet.busy_timeout;
+        let mut waiting = false;
+        loop {
+            let inherited = match &config.handover {
+                Some(path) => handover::receive(path)?,
+                None => None,
+            };
+            if let Some(inherited) = inherited {
+                let sockets = inherited.sockets.iter().map(OwnedFd::try_clone);
+                let sockets = sockets.collect::<Result<_, _>>()?;
+                let pingee =
+                    Pingee::inherit(&config, TIMEOUTS, PORTS, sockets).context(Cause::Socket)?;
+                let petter = Petter::inherit(inherited.line.try_clone()?).context(Cause::Gpio)?;
+                return Self::with(config, pingee, petter, Start::HandedOver(inherited), true);
+            }
+            match Petter::new(gpio_chip, GPIO_LABEL, GPIO_LINE) {
+                Ok(petter) => {
+                    let pingee = listening(&config)?;
+                    return Self::with(config, pingee, petter, Start::Fresh, true);
+                }
+                Err(e) => {
+                    let retry =
+                        e.downcast_ref::<Busy>()
+                            .is_some_and(|busy| match config.pet.busy {
+                                BusyPolicy::Fail => false,
+                                BusyPolicy::Wait => true,
+                                BusyPolicy::Takeover => busy.ours(),
+                            });
+                    if !retry || Instant::now() >= deadline {
+                        return Err(e.context(Cause::Gpio));
+                    }
+                    if !waiting {
+                        warn!("{:#}, waiting up to {:?}", e, config.pet.busy_timeout);
+                        waiting = true;
+                    }
+                    thread::sleep(BUSY_RETRY);
+                }
+            }
+        }
+    }
+
+    /// Like new, but petting `backend` instead of the GPIO line, e.g. a simulated one. There's
+    /// no handover then.
+    pub fn with_backend(config: Config, backend: Box<dyn Backend>) -> Result<Self> {
+        let pingee = Pingee::new(&config, TIMEOUTS, PORTS).context(Cause::Socket)?;
+        let petter = Petter::with_backend(backend)?;
+        Self::with(config, pingee, petter, Start::Fresh, true)
+    }
+
+    /// Like new, but petting nothing and leaving out whatever else drives or requests the card's
+    /// lines or writes its state, so that it can run next to the real watchdog. The pings, the
+    /// protocol and the state machine all work as usual. Not in flight builds.
+    #[cfg(not(feature = "flight"))]
+    pub fn dry_run(mut config: Config) -> Result<Self> {
+        info!("Dry run, leaving the GPIO lines, the hook and the state files alone");
+        config.handover = None;
+        config.status_socket = None;
+        config.status_file = None;
+        config.vsock = None;
+        config.eventfds.clear();
+        config.last_gasp = None;
+        config.counters = None;
+        config.logbook = None;
+        config.reset_request = None;
+        // Rebooting the host would be anything but dry
+        config.bite = config::BiteConfig::default();
+        config.supervisor = None;
+        config.pet.mirror = None;
+        config.kernel_watchdog = None;
+        config.lines.clear();
+        config.peers.clear();
+        config.hook.command.clear();
+        // The filter is built around the line's fd
+        config.seccomp = false;
+        Self::with_backend(config, Box::new(DryRun::default()))
+    }
+
+    /// Like new, but with the pings and pets going through whatever the host brings, e.g. a test
+    /// rig without the C3's GPIO line. Signals are left alone, see `shutdown_handle`.
+    pub fn with_parts(
+        config: Config,
+        transport: Box<dyn Transport>,
+        backend: Box<dyn Backend>,
+    ) -> Result<Self> {
+        let pingee = Pingee::with_transport(&config, TIMEOUTS, PORTS, transport)?;
+        let petter = Petter::with_backend(backend)?;
+        Self::with(config, pingee, petter, Start::Fresh, false)
+    }
+
+    /// For stopping `run` from elsewhere, e.g. when it's running on another thread
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.0.clone()
+    }
+
+    /// Has a panic on any thread end the watchdog, setting the line as configured. It's for the
+    /// binary: the hook is the whole process's.
+    pub fn install_panic_hook(&self) {
+        let last_gasp = self.config.last_gasp.clone();
+        panic::install(self.config.panic_line, self.petter.stopper(), last_gasp);
+    }
+
+    /// Only the startup self-test, which `run` does first anyway unless the line was handed over
+    pub fn self_test(&mut self) -> Result<()> {
+        self_test::run(&self.config, &mut self.petter)
+    }
+
+    fn with(
+        config: Config,
+        mut pingee: Pingee,
+        mut petter: Petter,
+        start: Start,
+        signals: bool,
+    ) -> Result<Self> {
+        petter.set_pattern(&config.pet.pattern);
+        petter.set_align(config.pet.align.as_ref().map(|align| align.offset));
+        let poll = Poll::new()?;
+        let registry = poll.registry().try_clone()?;
+
+        // Not being able to count is no reason to stop petting
+        let boot = config.counters.as_deref().and_then(|path| {
+            BootCounter::start(
+                path,
+                !matches!(start, Start::Fresh),
+                config.counters_interval,
+            )
+            .inspect_err(|e| warn!("Failed to update boot counters: {:#}", e))
+            .ok()
+        });
+        pingee.annotate("reset_reason", reset_reason::detect(&config.reset_reason));
+        match config
+            .last_gasp
+            .as_deref()
+            .map(last_gasp::replay)
+            .transpose()
+        {
+            Ok(Some(Some(previous))) => {
+                info!("Previous run went down with {}", previous);
+                for (key, value) in previous.to_status().iter() {
+                    pingee.annotate(key, value);
+                }
+            }
+            Ok(_) => (),
+            Err(e) => warn!("{:#}", e.context("Failed to replay the last gasp")),
+        }
+        if let Some(boot) = &boot {
+            pingee.annotate("boots", boot.counters.boots);
+            pingee.annotate("unclean_shutdowns", boot.counters.unclean_shutdowns);
+        }
+        // The frequent timers all go on one timerfd, the client deadlines only if that's their
+        // clock
+        let wheel = Wheel::new(Arc::new(Monotonic))?;
+        petter.set_clock(&wheel)?;
+        let clock = match config.clock {
+            TimerClock::Monotonic => {
+                let clock: Arc<dyn Clock> = Arc::new(wheel.clone());
+                pingee.set_clock(clock.clone())?;
+                clock
+            }
+            other => clock::configured(other)?,
+        };
+        let death_row = DeathRow::new(&config.death_row, clock.clone())?;
+        let uptime = MaxUptime::new(config.max_uptime)?;
+        let schedule = Schedule::new(&config.schedule)?;
+        let battery = Battery::new(&config.battery, &wheel)?;
+        let rules = Rules::new(&config.rules, &wheel)?;
+        let maintenance = Maintenance::new(config.maintenance.as_ref(), &wheel)?;
+        let sdo = config.canopen.as_ref().map(Sdo::new).transpose()?;
+        let nmt = match &config.canopen {
+            Some(canopen) => Nmt::new(canopen)?,
+            None => None,
+        };
+        let can_silence = match &config.can_silence {
+            Some(silence) => Some(CanSilence::new(silence, clock, config.inhibit.startup)?),
+            None => None,
+        };
+        let heartbeat = config.heartbeat.as_ref();
+        let heartbeat = heartbeat.map(|c| Heartbeat::new(c, &wheel)).transpose()?;
+        if let Some(telemetry) = &config.telemetry {
+            telemetry::start(telemetry)?;
+        }
+        if config.dbus_signals {
+            bus::start()?;
+        }
+        stats::instrument(config.instrument);
+        if let Some(hardware) = &config.hardware {
+            stats::set_max_feed(hardware.max_feed);
+        }
+        #[cfg(feature = "mqtt")]
+        let mqtt = config.mqtt.as_ref();
+        #[cfg(feature = "mqtt")]
+        let mqtt = mqtt.map(|c| Mqtt::new(c, &wheel)).transpose()?;
+        // Not worth failing over, the ground just has less to page through
+        let logbook = config.logbook.as_ref().and_then(|config| {
+            Logbook::open(config)
+                .map_err(|e| warn!("{:#}", e.context("Failed to open the logbook")))
+                .ok()
+        });
+        let forwarding = config.telemetry.is_some() || config.dbus_signals || logbook.is_some();
+        #[cfg(feature = "mqtt")]
+        let forwarding = forwarding || mqtt.is_some();
+        if forwarding {
+            sinks::start(logbook)?;
+        }
+        if let Some(error) = &config.degraded {
+            stats::degraded_config(error);
+            history::record(history::Event::DegradedConfig {
+                error: error.clone(),
+            });
+        }
+        let margin = config.margin.as_ref();
+        let margin = margin.map(|c| MarginReport::new(c, &wheel)).transpose()?;
+        let status_file = config.status_file.as_deref();
+        let status_file = status_file
+            .map(|path| StatusFile::new(path, config.status_file_interval, &wheel))
+            .transpose()?;
+        let cadence = config.cadence.as_ref();
+        let cadence = cadence
+            .map(|c| Cadence::new(&config, c, &wheel))
+            .transpose()?;
+        let time_anomalies = config.time_anomalies.as_ref();
+        let time_anomalies = time_anomalies
+            .map(|c| TimeAnomalies::new(c, &wheel))
+            .transpose()?;
+        let reset_request = config
+            .reset_request
+            .as_ref()
+            .map(ResetRequest::new)
+            .transpose()?;
+        let supervisor = config
+            .supervisor
+            .as_ref()
+            .map(SupervisorLine::new)
+            .transpose()?;
+        let mirror = config.pet.mirror.as_ref().map(PetMirror::new).transpose()?;
+        let gates = &config.gates;
+        let gate = (!gates.is_empty())
+            .then(|| StartupGate::new(gates))
+            .transpose()?;
+        let http = config.http.map(Http::new).transpose()?;
+        let status_socket = config.status_socket.as_deref();
+        let status_socket = status_socket.map(StatusSocket::bind).transpose()?;
+        let low_power = config.low_power.as_ref();
+        let low_power = low_power.map(|c| LowPower::new(c, &config.pet.pattern));
+        let low_power = low_power.transpose()?;
+        let profiles = (!config.profiles.is_empty()).then(|| Profiles::new(&config));
+        let mut profiles = profiles.transpose()?;
+        if let Some(profiles) = &mut profiles {
+            profiles.start(&mut petter, &mut pingee)?;
+        }
+        let hook = Hook::new(&config.hook);
+        let ladder = config.ladder.as_ref();
+        let ladder = ladder
+            .map(|l| Ladder::new(l, &config.hook, HOOK))
+            .transpose()?;
+        let late_pets = LatePets::new(&config.late_pet)?;
+        stats::set_wakeup_threshold(config.late_pet.wakeup);
+        // A predecessor handing over lets go of it once it has confirmed
+        let handed_over = matches!(start, Start::HandedOver(_));
+        let kernel_watchdog = config.kernel_watchdog.as_ref();
+        let kernel_watchdog = kernel_watchdog
+            .map(|c| KernelWatchdog::open(c, handed_over))
+            .transpose()?;
+        // Before the D-Bus threads are started, which inherit the mask
+        let sfd = if signals {
+            let mask = SigSet::from_iter(SIGNALS);
+            mask.thread_block()?;
+            Some(Signals::Fd(SignalFd::with_flags(
+                &mask,
+                SfdFlags::SFD_NONBLOCK,
+            )?))
+        } else {
+            None
+        };
+        let (sender, mut receiver) = pipe::new()?;
+
+        if let Some(port) = config.vsock {
+            let vsock = VsockSocket::bind(port).context(Cause::Socket)?;
+            pingee.add_transport(Box::new(vsock));
+        }
+        pingee.register(&registry, PING)?;
+        petter.register(&registry, PET)?;
+        if let Some(Signals::Fd(sfd)) = &sfd {
+            registry.register(&mut SourceFd(&sfd.as_raw_fd()), SIGNAL, Interest::READABLE)?;
+        }
+        registry.register(&mut receiver, SHUTDOWN, Interest::READABLE)?;
+        wheel.register(&registry, WHEEL)?;
+        death_row.register(&registry, DEATH_ROW)?;
+        uptime.register(&registry, UPTIME)?;
+        schedule.register(&registry, SCHEDULE)?;
+        battery.register(&registry, BATTERY)?;
+        rules.register(&registry, RULES)?;
+        maintenance.register(&registry, MAINTENANCE)?;
+        if let Some(sdo) = &sdo {
+            sdo.register(&registry, SDO)?;
+        }
+        if let Some(nmt) = &nmt {
+            nmt.register(&registry, NMT)?;
+        }
+        if let Some(can_silence) = &can_silence {
+            can_silence.register(&registry, CAN_SILENCE, CAN_SILENCE_TIMER)?;
+        }
+        let mut sources = Sources::new(SOURCES);
+        sources.add_some(&registry, heartbeat)?;
+        sources.add_some(&registry, margin)?;
+        sources.add_some(&registry, status_file)?;
+        sources.add_some(&registry, cadence)?;
+        sources.add_some(&registry, time_anomalies)?;
+        #[cfg(feature = "mqtt")]
+        sources.add_some(&registry, mqtt)?;
+        sources.add_some(&registry, supervisor)?;
+        sources.add_some(&registry, mirror)?;
+        sources.add_some(&registry, http)?;
+        sources.add_some(&registry, status_socket)?;
+        if let Some(low_power) = &low_power {
+            low_power.register(&registry, POWER)?;
+        }
+        if let Some(profiles) = &profiles {
+            profiles.register(&registry, PROFILE)?;
+        }
+        let processes = ProcessMonitor::new(&registry, PROCESSES, &config.processes)?;
+        let conditions = Conditions::new(&config, &registry, CONDITIONS, &wheel)?;
+        let mut lines = Vec::new();
+        for (index, line) in config.lines.iter().enumerate() {
+            let mut line = Line::new(line, &config, Token(LINES.0 + index * line::TOKENS))?;
+            line.register(&registry)?;
+            lines.push(line);
+        }
+
+        Ok(Self {
+            config,
+            poll,
+            registry,
+            events: Events::with_capacity(128),
+            pingee,
+            petter,
+            start,
+            lock: None,
+            boot,
+            death_row,
+            uptime,
+            schedule,
+            battery,
+            rules,
+            maintenance,
+            sdo,
+            nmt,
+            can_silence,
+            reset_request,
+            sources,
+            hook,
+            ladder,
+            gate,
+            late_pets,
+            kernel_watchdog,
+            sfd,
+            shutdown: (ShutdownHandle(Arc::new(sender)), receiver),
+            processes,
+            conditions,
+            lines,
+            wheel,
+            low_power,
+            profiles,
+        })
+    }
+
+    /// Pets until stopped by a signal, a shutdown handle, or an error, returning why. Can be run
+    /// on another thread, which then gets the real-time scheduling and syscall filter, if any.
+    pub fn run(self) -> Result<Cause> {
+        // Blocking waits are never pending, so a single poll runs it to the end
+        let mut run = pin!(self.run_with(Waiter::Blocking));
+        match run
+            .as_mut()
+            .poll(&mut task::Context::from_waker(Waker::noop()))
+        {
+            task::Poll::Ready(result) => result,
+            task::Poll::Pending => unreachable!(),
+        }
+    }
+
+    /// Like run, but as a task of the host's tokio runtime, instead of taking up a thread. The
+    /// epoll fd behind poll is waited on through an AsyncFd, the timeouts on tokio's timers, and
+    /// the signals, if the watchdog takes them, come from tokio's streams. Awaited inside the
+    /// runtime, with its io and time drivers enabled.
+    ///
+    /// Whichever executor thread polls it would get the scheduling, the dropped privileges, and
+    /// the seccomp filter, which the rest of the host's tasks then run under, and a work-stealing
+    /// executor can move it off that thread again, so a config with any of those is refused.
+    #[cfg(feature = "async")]
+    pub async fn run_async(mut self) -> Result<Cause> {
+        if let Some(setting) = async_refusal(&self.config) {
+            anyhow::bail!("Can't run on an executor with {} configured", setting);
+        }
+        let mut forwarder = None;
+        if let Some(Signals::Fd(sfd)) = &self.sfd {
+            self.registry.deregister(&mut SourceFd(&sfd.as_raw_fd()))?;
+            let (sender, mut receiver) = pipe::new()?;
+            self.registry
+                .register(&mut receiver, SIGNAL, Interest::READABLE)?;
+            forwarder = Some(Forwarder::new(sender)?);
+            // Delivered to tokio's handlers from here on, including any already pending
+            SigSet::from_iter(SIGNALS).thread_unblock()?;
+            self.sfd = Some(Signals::Pipe(receiver));
+        }
+        let fd = EpollFd(self.poll.as_raw_fd());
+        let waiter = Waiter::Tokio(
+            AsyncFd::with_interest(fd, tokio::io::Interest::READABLE)?,
+            forwarder,
+        );
+        self.run_with(waiter).await
+    }
+
+    async fn run_with(self, mut waiter: Waiter) -> Result<Cause> {
+        let Watchdog {
+            config,
+            mut poll,
+            registry,
+            mut events,
+            mut pingee,
+            mut petter,
+            start,
+            mut lock,
+            mut boot,
+            mut death_row,
+            mut uptime,
+            mut schedule,
+            mut battery,
+            mut rules,
+            mut maintenance,
+            sdo,
+            mut nmt,
+            mut can_silence,
+            mut reset_request,
+            mut sources,
+            hook,
+            mut ladder,
+            mut gate,
+            mut late_pets,
+            mut kernel_watchdog,
+            mut sfd,
+            shutdown: (_, mut shutdown),
+            mut processes,
+            mut conditions,
+            mut lines,
+            wheel,
+            mut low_power,
+            mut profiles,
+        } = self;
+
+        // The scheduling only affects this thread, not the D-Bus threads started before
+        realtime::apply(&config.realtime);
+        let mut keepalive = Keepalive::from_env()?;
+        let mut retry = Retry::new(&config.retry);
+        // A handed over or kept line is mid-cycle, and a predecessor is waiting on the confirmation
+        if let Start::Fresh = start {
+            self_test::run(&config, &mut petter)?;
+        }
+        retry.run(|| petter.pet())?;
+        for line in &mut lines {
+            retry.run(|| line.start())?;
+        }
+        if let Start::HandedOver(inherited) = start {
+            inherited.confirm()?;
+        }
+        let mut successors = match &config.handover {
+            Some(path) => Some(Handover::listen(path)?),
+            None => None,
+        };
+        if let Some(successors) = &mut successors {
+            successors.register(&registry, HANDOVER)?;
+        }
+        privileges::drop(&config.privileges).context("Failed to drop privileges")?;
+        let stall = match config.stall.is_zero() {
+            true => None,
+            false => Some(StallDetector::start(config.stall)?),
+        };
+        if config.seccomp {
+            seccomp::install(petter.fd()?)?;
+            info!("Installed seccomp filter");
+        }
+
+        let watch = async {
+            // Pings coming back don't pardon a watched process, cgroup, or unit that was lost
+            let mut lost = false;
+            loop {
+                if let Some(stall) = &stall {
+                    stall.tick();
+                }
+                waiter.wait(&mut poll, &mut events, None).await?;
+                for token in wheel.tokens(&events, WHEEL)? {
+                    match token {
+                        SIGNAL => {
+                            while let Some(signal) = next_signal(&mut sfd, || {
+                                dump(&pingee, &petter, &death_row, nmt.as_ref())
+                            })? {
+                                if signal == SIGUSR2 {
+                                    let signals = matches!(sfd, Some(Signals::Fd(_)));
+                                    let (lock, boot) = (lock.as_ref(), boot.as_mut());
+                                    reexec(
+                                        &config, &pingee, &petter, lock, boot, &death_row, signals,
+                                    );
+                                    continue;
+                                }
+                                // Rotates the command keys, a bad key file keeps the old ones
+                                if signal != SIGHUP {
+                                    return Ok(Stop::Signal(signal));
+                                }
+                                let mut reloaded = vec![pingee.reload_keys()];
+                                reloaded.extend(lines.iter_mut().map(Line::reload_keys));
+                                for e in reloaded.into_iter().filter_map(Result::err) {
+                                    warn!("Failed to reload keys: {:#}", e);
+                                }
+                            }
+                        }
+                        SHUTDOWN => {
+                            // Drained, so that a handle can't be left readable for the next run
+                            let _ = shutdown.read(&mut [0; 16]);
+                            return Ok(Stop::Shutdown);
+                        }
+                        PING => match retry.run(|| pingee.on_ping(&registry, &mut processes))? {
+                            Some(Action::Reset) => return Ok(Stop::Reset),
+                            // Only accepted with a low power mode configured
+                            Some(Action::Power(low)) => {
+                                let low_power = low_power.as_mut().unwrap();
+                                low_power.set(low, &mut petter, &mut pingee)?;
+                            }
+                            // Only accepted for a configured profile
+                            Some(Action::Profile(index)) => {
+                                let profiles = profiles.as_mut().unwrap();
+                                let low_power = low_power.as_mut();
+                                let held =
+                                    profiles.switch(index, &mut petter, &mut pingee, low_power)?;
+                                let reset =
+                                    held.map(|(r, c)| schedule.request(r, c)).transpose()?;
+                                deliberate(
+                                    reset.flatten(),
+                                    &mut maintenance,
+                                    &mut rules,
+                                    &pingee,
+                                    nmt.as_mut(),
+                                    &mut battery,
+                                    &mut death_row,
+                                )?;
+                            }
+                            // Only accepted with a maintenance mode configured
+                            Some(Action::Maintenance(duration)) => {
+                                let reset = maintenance.on_command(duration, &mut petter)?;
+                                deliberate(
+                                    reset,
+                                    &mut maintenance,
+                                    &mut rules,
+                                    &pingee,
+                                    nmt.as_mut(),
+                                    &mut battery,
+                                    &mut death_row,
+                                )?;
+                            }
+                            Some(Action::Reexec) => {
+                                let signals = matches!(sfd, Some(Signals::Fd(_)));
+                                let (lock, boot) = (lock.as_ref(), boot.as_mut());
+                                reexec(&config, &pingee, &petter, lock, boot, &death_row, signals);
+                            }
+                            _ => (),
+                        },
+                        POWER => {
+                            let low_power = low_power.as_mut().unwrap();
+                            retry.run(|| low_power.on_readable(&mut petter, &mut pingee))?
+                        }
+                        PROFILE => {
+                            let profiles = profiles.as_mut().unwrap();
+                            let held = retry.run(|| {
+                                profiles.on_readable(&mut petter, &mut pingee, low_power.as_mut())
+                            })?;
+                            let reset = held.map(|(r, c)| schedule.request(r, c)).transpose()?;
+                            deliberate(
+                                reset.flatten(),
+                                &mut maintenance,
+                                &mut rules,
+                                &pingee,
+                                nmt.as_mut(),
+                                &mut battery,
+                                &mut death_row,
+                            )?;
+                        }
+                        PET => {
+                            late_pets.on_pet(retry.run(|| petter.on_pet())?);
+                            if let Some(lock) = &mut lock {
+                                lock.on_pet()?;
+                            }
+                            if let Some(keepalive) = &mut keepalive {
+                                keepalive.on_pet();
+                            }
+                            if let Some(kernel) = &mut kernel_watchdog {
+                                retry.run(|| kernel.on_pet())?;
+                            }
+                            if let Some(boot) = &mut boot {
+                                if let Err(e) = boot.on_pet() {
+                                    warn!("Failed to save the counters: {:#}", e);
+                                }
+                            }
+                            retry.run(|| pingee.recover(&registry))?;
+                            retry.run(|| pingee.check_interlock())?;
+                            if let Some(gate) = &mut gate {
+                                let reset = retry.run(|| gate.on_pet(&mut pingee))?;
+                                deliberate(
+                                    reset,
+                                    &mut maintenance,
+                                    &mut rules,
+                                    &pingee,
+                                    nmt.as_mut(),
+                                    &mut battery,
+                                    &mut death_row,
+                                )?;
+                            }
+                            retry.run(|| conditions.on_pet(&mut pingee))?;
+                            if let Some(ladder) = &mut ladder {
+                                retry.run(|| ladder.on_pet(&pingee, &registry))?;
+                            }
+                        }
+                        HOOK => ladder.as_mut().unwrap().on_hook(&registry)?,
+                        HANDOVER => {
+                            let Some(successors) = &successors else {
+                                continue;
+                            };
+                            let mut fds = vec![petter.fd()?];
+                            fds.extend(pingee.sockets());
+                            while let Some(successor) = successors.accept(&fds)? {
+                                // Neither instance pets until the successor confirms, or gives up
+                                petter.pause()?;
+                                if handover::confirmed(successor) {
+                                    return Ok(Stop::HandedOver);
+                                }
+                                petter.pet()?;
+                            }
+                        }
+                        DEATH_ROW => retry.run(|| death_row.on_tick())?,
+                        UPTIME => {
+                            let reset = schedule.request(uptime.on_expired()?, Cause::Uptime)?;
+                            let reset = allow(profiles.as_mut(), reset);
+                            deliberate(
+                                reset,
+                                &mut maintenance,
+                                &mut rules,
+                                &pingee,
+                                nmt.as_mut(),
+                                &mut battery,
+                                &mut death_row,
+                            )?;
+                        }
+                        SCHEDULE => {
+                            let reset = retry.run(|| schedule.on_timer())?;
+                            let reset = allow(profiles.as_mut(), reset);
+                            deliberate(
+                                reset,
+                                &mut maintenance,
+                                &mut rules,
+                                &pingee,
+                                nmt.as_mut(),
+                                &mut battery,
+                                &mut death_row,
+                            )?;
+                        }
+                        NMT => {
+                            let nmt = nmt.as_mut().unwrap();
+                            let reset = retry.run(|| nmt.on_readable(&mut pingee))?;
+                            past_nmt(reset, &mut battery, &mut death_row)?;
+                        }
+                        SDO => {
+                            let sdo = sdo.as_ref().unwrap();
+                            retry.run(|| {
+                                sdo.on_readable(&mut Objects {
+                                    pingee: &mut pingee,
+                                    uptime: &mut uptime,
+                                    period: config.pet.period(),
+                                })
+                            })?
+                        }
+                        CAN_SILENCE => {
+                            let can_silence = can_silence.as_mut().unwrap();
+                            retry.run(|| can_silence.on_readable())?
+                        }
+                        CAN_SILENCE_TIMER => {
+                            let can_silence = can_silence.as_mut().unwrap();
+                            // Like a ping timeout, not while disarmed or inhibited
+                            let fatal = pingee.armed() && pingee.inhibit_remaining().is_zero();
+                            if let Some(reason) = retry.run(|| can_silence.on_timer(fatal))? {
+                                death_row.sentence(reason, Cause::PingTimeout)?;
+                            }
+                        }
+                        RULES => {
+                            let reset = rules.on_timer(&pingee)?;
+                            past_rules(reset, nmt.as_mut(), &mut battery, &mut death_row)?;
+                        }
+                        MAINTENANCE => {
+                            let reset =
+                                retry.run(|| maintenance.on_timer(&mut petter, &mut pingee))?;
+                            deliberate(
+                                reset,
+                                &mut maintenance,
+                                &mut rules,
+                                &pingee,
+                                nmt.as_mut(),
+                                &mut battery,
+                                &mut death_row,
+                            )?;
+                        }
+                        BATTERY => {
+                            if let Some((reason, cause)) = battery.on_timer()? {
+                                death_row.sentence(reason, cause)?;
+                            }
+                        }
+                        token if pingee.handles(token) => {
+                            if let Some(reason) = retry.run(|| pingee.on_timeout(token))? {
+                                death_row.sentence(reason, Cause::PingTimeout)?;
+                            }
+                        }
+                        token if conditions.handles(token) => {
+                            let loss = retry.run(|| conditions.on_event(token, &mut pingee))?;
+                            if let Some(reason) = loss {
+                                lost |= liveness_lost(reason, &pingee, &mut death_row)?;
+                            }
+                        }
+                        token if sources.handles(token) => {
+                            let mut ready = Ready {
+                                registry: &registry,
+                                pingee: &mut pingee,
+                                death_row: &mut death_row,
+                                retry: &mut retry,
+                            };
+                            sources.on_ready(token, &mut ready)?
+                        }
+                        token if pingee.handles_port(token) => {
+                            retry.run(|| pingee.on_port(&registry, token))?
+                        }
+                        token if processes.handles(token) => {
+                            let exited = retry.run(|| processes.on_exit(&registry, token))?;
+                            if let Some(reason) = exited {
+                                lost |= liveness_lost(reason, &pingee, &mut death_row)?;
+                            }
+                        }
+                        token if token.0 >= LINES.0 => {
+                            let line = &mut lines[(token.0 - LINES.0) / line::TOKENS];
+                            retry.run(|| line.on_event(token, &registry, &mut processes))?
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                let silent = can_silence.as_ref().is_some_and(CanSilence::silent);
+                if death_row.pending() && !silent && !lost && !retry.run(|| pingee.starving())? {
+                    retry.run(|| death_row.pardon())?;
+                }
+                pingee.sync_state(death_row.left().map(|(reason, _)| reason));
+            }
+        };
+        let stop: Result<Stop> = watch.await;
+
+        let (cause, reason) = match &stop {
+            // Has the supervisor reset the card, like a RESET
+            Ok(Stop::Signal(SIGQUIT)) => (Cause::Reset, SIGQUIT.to_string()),
+            Ok(Stop::Signal(signal)) => (Cause::Signal, signal.to_string()),
+            Ok(Stop::HandedOver) => (Cause::Signal, String::from("Handed over")),
+            Ok(Stop::Shutdown) => (Cause::Signal, String::from("Shut down")),
+            Ok(Stop::Reset) => (Cause::Reset, Cause::Reset.to_string()),
+            Err(e) => (Cause::of(e), format!("{:#}", e)),
+        };
+        if cause != Cause::Signal {
+            pingee.die(&reason);
+        }
+        match cause {
+            Cause::Signal => info!("Going down, {}: {}", cause.name(), reason),
+            _ => warn!("Going down, {}: {}", cause.name(), reason),
+        }
+        if let (Some(sdo), false) = (&sdo, cause == Cause::Signal) {
+            sdo.emergency(cause);
+        }
+        let history = history::dump();
+        if cause != Cause::Signal {
+            info!("Recent events:");
+            for line in &history {
+                info!("  {}", line);
+            }
+        }
+        if let Some(path) = &config.last_gasp {
+            let status = pingee.status().ok();
+            let gasp = LastGasp {
+                cause,
+                reason: &reason,
+                last_ping: pingee.last_ping(),
+                pets: petter.pets(),
+                history: &history,
+                status: status.as_ref(),
+                backtrace: None,
+            };
+            if let Err(e) = gasp.write(path) {
+                warn!("Failed to write last gasp: {:#}", e);
+            }
+        }
+
+        // Anything but a signal other than SIGQUIT, or a shutdown, counts as unclean on the next
+        // start. After a handover the file is the successor's.
+        if cause != Cause::Signal {
+            stats::count(Counter::Resets);
+        }
+        if let (false, Some(boot)) = (matches!(stop, Ok(Stop::HandedOver)), boot) {
+            let clean = cause == Cause::Signal;
+            if let Err(e) = boot.stop(clean) {
+                warn!("Failed to update boot counters: {:#}", e);
+            }
+        }
+
+        match &stop {
+            Ok(Stop::Signal(SIGTERM)) if !config.drain.is_zero() => {
+                // Keeps petting so that a service restart doesn't reset the card, until the drain
+                // time is up, the replacement takes over, or another signal
+                info!("Draining for {:?}", config.drain);
+                pingee.drain();
+                let until = Instant::now() + config.drain;
+                loop {
+                    let left = until.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return Ok(Cause::Signal);
+                    }
+                    if let Some(stall) = &stall {
+                        stall.tick();
+                    }
+                    waiter.wait(&mut poll, &mut events, Some(left)).await?;
+                    for token in wheel.tokens(&events, WHEEL)? {
+                        match token {
+                            PET => {
+                                retry.run(|| petter.on_pet())?;
+                                if let Some(keepalive) = &mut keepalive {
+                                    keepalive.on_pet();
+                                }
+                                if let Some(kernel) = &mut kernel_watchdog {
+                                    retry.run(|| kernel.on_pet())?;
+                                }
+                            }
+                            SIGNAL => {
+                                let dump = || dump(&pingee, &petter, &death_row, nmt.as_ref());
+                                if next_signal(&mut sfd, dump)?.is_some() {
+                                    return Ok(Cause::Signal);
+                                }
+                            }
+                            SHUTDOWN => return Ok(Cause::Signal),
+                            PING => {
+                                let action = pingee.on_ping(&registry, &mut processes)?;
+                                if action == Some(Action::Takeover) {
+                                    return Ok(Cause::Signal);
+                                }
+                            }
+                            token if token.0 >= LINES.0 => {
+                                let line = &mut lines[(token.0 - LINES.0) / line::TOKENS];
+                                retry.run(|| line.on_event(token, &registry, &mut processes))?
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+            }
+            Ok(Stop::Signal(SIGQUIT)) => {
+                // The line stays as it is, so the supervisor bites once it's due
+                warn!("Exiting without touching the line");
+                petter.release();
+                return Ok(Cause::Reset);
+            }
+            Ok(Stop::Signal(_) | Stop::Shutdown) => return Ok(Cause::Signal),
+            Ok(Stop::HandedOver) => {
+                info!("Handed over, exiting");
+                petter.release();
+                return Ok(Cause::Signal);
+            }
+            Ok(Stop::Reset) | Err(_) => (),
+        }
+        // Still running if the ladder started it, which it isn't again
+        let running = match ladder.as_mut().and_then(Ladder::take_hook) {
+            Some(running) => running,
+            None => hook.start(&registry, HOOK, cause, &reason),
+        };
+        if let Some(mut running) = running {
+            // Keeps petting until the hook is done, a signal cuts it short
+            'hook: loop {
+                if let Some(stall) = &stall {
+                    stall.tick();
+                }
+                waiter.wait(&mut poll, &mut events, None).await?;
+                for token in wheel.tokens(&events, WHEEL)? {
+                    match token {
+                        PET => {
+                            retry.run(|| petter.on_pet())?;
+                            if let Some(keepalive) = &mut keepalive {
+                                keepalive.on_pet();
+                            }
+                            if let Some(kernel) = &mut kernel_watchdog {
+                                retry.run(|| kernel.on_pet())?;
+                            }
+                        }
+                        SIGNAL => {
+                            let dump = || dump(&pingee, &petter, &death_row, nmt.as_ref());
+                            if next_signal(&mut sfd, dump)?.is_some() {
+                                break 'hook;
+                            }
+                        }
+                        SHUTDOWN => break 'hook,
+                        HOOK if running.on_event()? => break 'hook,
+                        token if token.0 >= LINES.0 => {
+                            let line = &mut lines[(token.0 - LINES.0) / line::TOKENS];
+                            retry.run(|| line.on_event(token, &registry, &mut processes))?
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            running.finish(&registry)?;
+        }
+        let commanded = matches!(stop, Ok(Stop::Reset));
+        // Only a ping timeout bites as configured, other failures exit right away
+        let bite = match stop {
+            Err(_) if cause == Cause::PingTimeout && config.bite.action != BiteAction::Stop => {
+                config.bite.action
+            }
+            stop => {
+                stop?;
+                BiteAction::Stop
+            }
+        };
+        if let (true, Some(reset_request)) = (commanded, &mut reset_request) {
+            // Stopping the petting below still gets the card reset if this fails
+            if let Err(e) = reset_request.assert() {
+                warn!("Failed to request a reset: {:#}", e);
+            }
+        }
+        if bite == BiteAction::Reboot {
+            match bus::reboot() {
+                Ok(()) => {
+                    // Keeps petting so that the system can go down cleanly, whose SIGTERM ends it
+                    let timeout = config.bite.reboot_timeout;
+                    warn!("Requested a reboot, petting for up to {:?}", timeout);
+                    let until = Instant::now() + timeout;
+                    loop {
+                        let left = until.saturating_duration_since(Instant::now());
+                        if left.is_zero() {
+                            warn!("Still up {:?} after requesting a reboot", timeout);
+                            break;
+                        }
+                        if let Some(stall) = &stall {
+                            stall.tick();
+                        }
+                        waiter.wait(&mut poll, &mut events, Some(left)).await?;
+                        for token in wheel.tokens(&events, WHEEL)? {
+                            match token {
+                                PET => {
+                                    retry.run(|| petter.on_pet())?;
+                                    if let Some(keepalive) = &mut keepalive {
+                                        keepalive.on_pet();
+                                    }
+                                    if let Some(kernel) = &mut kernel_watchdog {
+                                        retry.run(|| kernel.on_pet())?;
+                                    }
+                                }
+                                SIGNAL => {
+                                    let dump = || dump(&pingee, &petter, &death_row, nmt.as_ref());
+                                    if next_signal(&mut sfd, dump)?.is_some() {
+                                        return Ok(cause);
+                                    }
+                                }
+                                SHUTDOWN => return Ok(cause),
+                                _ => (),
+                            }
+                        }
+                    }
+                }
+                Err(e) => warn!("{:#}", e),
+            }
+        }
+
+        // After a commanded reset or a bite nothing but a signal or shutdown is handled, so that
+        // nothing can get the petting going again before the supervisor power cycles the card
+        match bite {
+            BiteAction::Hold => {
+                let level = if config.bite.level { "high" } else { "low" };
+                warn!("Holding the line {}", level);
+                petter.hold(config.bite.level)?;
+            }
+            _ => petter.stop()?,
+        }
+        if let (BiteAction::Pulse, Some(reset_request)) = (bite, &mut reset_request) {
+            if let Err(e) = reset_request.pulse(config.bite.pulse) {
+                warn!("Failed to pulse the reset request line: {:#}", e);
+            }
+        }
+        // Nothing wakes the loop up from now on
+        drop(stall);
+        warn!("Stopped petting, waiting for the supervisor to reset");
+        loop {
+            waiter.wait(&mut poll, &mut events, None).await?;
+            for token in wheel.tokens(&events, WHEEL)? {
+                match token {
+                    SIGNAL => {
+                        let dump = || dump(&pingee, &petter, &death_row, nmt.as_ref());
+                        if next_signal(&mut sfd, dump)?.is_some() {
+                            return Ok(cause);
+                        }
+                    }
+                    SHUTDOWN => return Ok(cause),
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+/// Sentences a watched process exiting, or a condition's loss of liveness, like a ping timeout,
+/// unless disarmed or inhibited. Returns whether it was.
+fn liveness_lost(reason: String, pingee: &Pingee, death_row: &mut DeathRow) -> Result<bool> {
+    if !pingee.armed() || !pingee.inhibit_remaining().is_zero() {
+        warn!("{}, not fatal now", reason);
+        return Ok(false);
+    }
+    death_row.sentence(reason, Cause::PingTimeout)?;
+    Ok(true)
+}
+
+/// The line the instance lock is named after, however it's driven
+fn lock_name(config: &Config, gpio_chip: &str) -> String {
+    let name = |path: &Path| {
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        name.to_string_lossy().into_owned()
+    };
+    if let Some(pwm) = &config.pet.pwm {
+        format!("{}/pwm{}", name(&pwm.chip), pwm.channel)
+    } else if let Some(number) = config.pet.sysfs_gpio {
+        format!("gpio{}", number)
+    } else {
+        format!("{}/{}", name(Path::new(gpio_chip)), GPIO_LINE)
+    }
+}
+
+/// Replaces this process with the installed binary, which picks up the line, the ping sockets, and
+/// the instance lock where this one left them. Only returns if it couldn't, which isn't fatal.
+fn reexec(
+    config: &Config,
+    pingee: &Pingee,
+    petter: &Petter,
+    lock: Option<&InstanceLock>,
+    boot: Option<&mut BootCounter>,
+    death_row: &DeathRow,
+    signals: bool,
+) {
+    if let Some(refusal) = reexec_refusal(config, pingee, death_row.pending(), signals) {
+        warn!("Not re-executing, {}", refusal);
+        return;
+    }
+    // The new image starts from what's in the file
+    if let Some(boot) = boot {
+        if let Err(e) = boot.save_totals() {
+            warn!("Failed to save the counters: {:#}", e);
+        }
+    }
+    let line = match petter.fd() {
+        Ok(line) => line,
+        Err(e) => {
+            warn!("Not re-executing: {:#}", e);
+            return;
+        }
+    };
+    let state = reexec::State {
+        line,
+        lock: lock.and_then(InstanceLock::fd),
+        sockets: pingee.sockets(),
+        armed: pingee.armed(),
+    };
+    warn!("Re-executing in place");
+    let e = reexec::exec(&state);
+    warn!("Failed to re-exec, carrying on: {:#}", e);
+}
+
+/// The setting that would apply to whichever executor thread polls `run_async`, if any
+#[cfg(feature = "async")]
+fn async_refusal(config: &Config) -> Option<&'static str> {
+    if config.realtime.priority.is_some() || !config.realtime.cpus.is_empty() {
+        Some("a realtime priority or CPU affinity")
+    } else if config.privileges.user.is_some() || config.privileges.group.is_some() {
+        Some("privileges to drop")
+    } else if config.seccomp {
+        Some("seccomp")
+    } else {
+        None
+    }
+}
+
+/// Why the state can't be carried across an exec, if it can't
+fn reexec_refusal(
+    config: &Config,
+    pingee: &Pingee,
+    death_row: bool,
+    signals: bool,
+) -> Option<&'static str> {
+    if config.seccomp {
+        Some("the seccomp filter doesn't allow it")
+    } else if config.privileges.user.is_some() {
+        Some("the privileges to reopen everything were dropped")
+    } else if config.pet.pwm.is_some() || config.pet.sysfs_gpio.is_some() {
+        Some("only a line requested from the GPIO chip can be kept")
+    } else if !signals {
+        Some("the host owns this process")
+    } else if death_row {
+        Some("the death row sentence would be lost")
+    } else if pingee.maintenance_remaining().is_some() {
+        // The new image would come up disarmed, with nothing to ever arm it again
+        Some("the maintenance deadline would be lost")
+    } else if pingee.gated() {
+        Some("the startup gates would be lost")
+    } else {
+        None
+    }
+}
+
+/// With the sockets systemd passed when socket activated, otherwise bound here
+fn listening(config: &Config) -> Result<Pingee> {
+    let sockets = notify::listen_fds().context(Cause::Socket)?;
+    match sockets.is_empty() {
+        true => Pingee::new(config, TIMEOUTS, PORTS),
+        false => Pingee::activated(config, TIMEOUTS, PORTS, sockets),
+    }
+    .context(Cause::Socket)
+}
+
+/// Reads the pending signals, dumping the state for each SIGUSR1, until another one
+fn next_signal(signals: &mut Option<Signals>, dump: impl Fn()) -> Result<Option<Signal>> {
+    let Some(signals) = signals else {
+        return Ok(None);
+    };
+    loop {
+        let (signal, pid) = match signals {
+            Signals::Fd(sfd) => match sfd.read_signal()? {
+                Some(info) => (Signal::try_from(info.ssi_signo as i32)?, Some(info.ssi_pid)),
+                None => return Ok(None),
+            },
+            // Without the sender, which tokio doesn't say
+            #[cfg(feature = "async")]
+            Signals::Pipe(receiver) => {
+                let mut signal = [0];
+                match receiver.read(&mut signal) {
+                    Ok(0) => return Ok(None),
+                    Ok(_) => (Signal::try_from(signal[0] as i32)?, None),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        };
+        match (signal, pid) {
+            (SIGUSR1, _) => dump(),
+            (signal, Some(pid)) => {
+                info!("Got {} from pid {}", signal, pid);
+                return Ok(Some(signal));
+            }
+            (signal, None) => {
+                info!("Got {}", signal);
+                return Ok(Some(signal));
+            }
+        }
+    }
+}
+
+/// A scheduled reset, unless the active profile holds it back
+fn allow(
+    profiles: Option<&mut Profiles>,
+    reset: Option<(String, Cause)>,
+) -> Option<(String, Cause)> {
+    match profiles {
+        Some(profiles) => profiles.allow(reset),
+        None => reset,
+    }
+}
+
+/// A deliberate reset that made it past the schedule, on to maintenance, the reset rule, the C3's
+/// NMT state, and then the battery, any of which can hold it back to release it later
+fn deliberate(
+    reset: Option<(String, Cause)>,
+    maintenance: &mut Maintenance,
+    rules: &mut Rules,
+    pingee: &Pingee,
+    nmt: Option<&mut Nmt>,
+    battery: &mut Battery,
+    death_row: &mut DeathRow,
+) -> Result<()> {
+    let reset = maintenance.allow(reset);
+    let reset = rules.allow(reset, pingee)?;
+    past_rules(reset, nmt, battery, death_row)
+}
+
+fn past_rules(
+    reset: Option<(String, Cause)>,
+    nmt: Option<&mut Nmt>,
+    battery: &mut Battery,
+    death_row: &mut DeathRow,
+) -> Result<()> {
+    let reset = match (reset, nmt) {
+        (Some((reason, cause)), Some(nmt)) => nmt.request(reason, cause),
+        (reset, _) => reset,
+    };
+    past_nmt(reset, battery, death_row)
+}
+
+fn past_nmt(
+    reset: Option<(String, Cause)>,
+    battery: &mut Battery,
+    death_row: &mut DeathRow,
+) -> Result<()> {
+    if let Some((reason, cause)) = reset {
+        if let Some((reason, cause)) = battery.request(reason, cause)? {
+            death_row.sentence(reason, cause)?;
+        }
+    }
+    Ok(())
+}
+
+/// Everything behind "why hasn't it timed out" or "why did it", logged for SIGUSR1 without
+/// changing anything
+fn dump(pingee: &Pingee, petter: &Petter, death_row: &DeathRow, nmt: Option<&Nmt>) {
+    info!("State dump:");
+    match petter.next_pet() {
+        Ok(next) => info!("  next pet in {:.3} s", next.as_secs_f64()),
+        Err(e) => warn!("  next pet unknown: {:#}", e),
+    }
+    info!("  pets={}", petter.pets());
+    match death_row.left() {
+        Some((reason, left)) => info!("  death row: {:.1} s left, {}", left.as_secs_f64(), reason),
+        None => info!("  death row: not pending"),
+    }
+    if let Some(nmt) = nmt {
+        match nmt.state() {
+            Some(state) => info!("  c3 nmt state: {:?}", state),
+            None => info!("  c3 nmt state: unknown"),
+        }
+    }
+    for (client, ago) in stats::last_pings() {
+        info!(
+            "  client.{}.last_ping={:.1} s ago",
+            client,
+            ago.as_secs_f64()
+        );
+    }
+    match pingee.status() {
+        Ok(status) => {
+            for (key, value) in status.iter() {
+                info!("  {}={}", key, value);
+            }
+        }
+        Err(e) => warn!("  status unknown: {:#}", e),
+    }
+    info!("Recent events:");
+    for line in history::dump() {
+        info!("  {}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::petter::Mock;
+    use std::{net::UdpSocket, thread, time::Duration};
+
+    #[test]
+    fn test_shutdown() -> Result<()> {
+        let config = Config::parse("address = \"127.0.0.1:0\"")?;
+        let transport = mio::net::UdpSocket::bind(config.address)?;
+        let address = transport.local_addr()?;
+        let mock = Mock::default();
+        let watchdog = Watchdog::with_parts(config, Box::new(transport), Box::new(mock.clone()))?;
+        let handle = watchdog.shutdown_handle();
+        let running = thread::spawn(move || watchdog.run());
+
+        let client = UdpSocket::bind("127.0.0.1:0")?;
+        client.set_read_timeout(Some(Duration::from_secs(1)))?;
+        client.send_to(b"STATUS", address)?;
+        let mut buf = [0; 4096];
+        let len = client.recv(&mut buf)?;
+        assert!(buf[..len].starts_with(b"state=startup_inhibit\n"));
+
+        handle.shutdown()?;
+        handle.shutdown()?;
+        assert_eq!(running.join().unwrap()?, Cause::Signal);
+        // Raised first, and left low
+        let values = mock.values();
+        assert_eq!((values.first(), values.last()), (Some(&true), Some(&false)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_bite_hold() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            timeout = 0.2
+            [inhibit]
+            startup = 0.001
+            [death_row]
+            duration = 0
+            [pet]
+            pattern = [{ high = true, duration = 0.05 }, { high = false, duration = 0.05 }]
+            [bite]
+            action = "hold"
+            level = true
+            "#,
+        )?;
+        let transport = mio::net::UdpSocket::bind(config.address)?;
+        let mock = Mock::default();
+        let watchdog = Watchdog::with_parts(config, Box::new(transport), Box::new(mock.clone()))?;
+        let handle = watchdog.shutdown_handle();
+        let running = thread::spawn(move || watchdog.run());
+
+        thread::sleep(Duration::from_secs(1));
+        let held = mock.values();
+        assert_eq!(held.last(), Some(&true));
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(mock.values(), held);
+        handle.shutdown()?;
+        assert_eq!(running.join().unwrap()?, Cause::PingTimeout);
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_exit() -> Result<()> {
+        let mut child = std::process::Command::new("sleep").arg("0.3").spawn()?;
+        let config = Config::parse(&format!(
+            r#"
+            address = "127.0.0.1:0"
+            [[process]]
+            name = "flight"
+            pid = {}
+            [inhibit]
+            startup = 0.001
+            [death_row]
+            duration = 0
+            [pet]
+            pattern = [{{ high = true, duration = 0.05 }}, {{ high = false, duration = 0.05 }}]
+            [bite]
+            action = "hold"
+            level = true
+            "#,
+            child.id()
+        ))?;
+        let transport = mio::net::UdpSocket::bind(config.address)?;
+        let mock = Mock::default();
+        let watchdog = Watchdog::with_parts(config, Box::new(transport), Box::new(mock.clone()))?;
+        let handle = watchdog.shutdown_handle();
+        let running = thread::spawn(move || watchdog.run());
+
+        child.wait()?;
+        thread::sleep(Duration::from_millis(300));
+        // Bitten like a ping timeout, rather than failing for systemd to restart it
+        let held = mock.values();
+        assert_eq!(held.last(), Some(&true));
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(mock.values(), held);
+        handle.shutdown()?;
+        assert_eq!(running.join().unwrap()?, Cause::PingTimeout);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cgroup_empty() -> Result<()> {
+        // A regular file stands in for the kernel's
+        let dir = std::env::temp_dir().join(format!("watchdog-loop-cgroup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let events = dir.join("cgroup.events");
+        std::fs::write(&events, "populated 1\n")?;
+        let config = Config::parse(&format!(
+            r#"
+            address = "127.0.0.1:0"
+            [[cgroup]]
+            name = "flight"
+            path = {:?}
+            [inhibit]
+            startup = 0.001
+            [death_row]
+            duration = 0
+            [pet]
+            pattern = [{{ high = true, duration = 0.05 }}, {{ high = false, duration = 0.05 }}]
+            [bite]
+            action = "hold"
+            level = true
+            "#,
+            dir
+        ))?;
+        let transport = mio::net::UdpSocket::bind(config.address)?;
+        let mock = Mock::default();
+        let watchdog = Watchdog::with_parts(config, Box::new(transport), Box::new(mock.clone()))?;
+        let handle = watchdog.shutdown_handle();
+        let running = thread::spawn(move || watchdog.run());
+
+        thread::sleep(Duration::from_millis(200));
+        std::fs::write(&events, "populated 0\n")?;
+        thread::sleep(Duration::from_millis(300));
+        let held = mock.values();
+        assert_eq!(held.last(), Some(&true));
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(mock.values(), held);
+        handle.shutdown()?;
+        assert_eq!(running.join().unwrap()?, Cause::PingTimeout);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset() -> Result<()> {
+        let key = std::env::temp_dir().join(format!("watchdog-reset-{}.key", std::process::id()));
+        std::fs::write(&key, "0123456789abcdef\n")?;
+        let config = Config::parse(&format!(
+            r#"
+            address = "127.0.0.1:0"
+            [[client]]
+            name = "c3"
+            timeout = 1
+            [auth]
+            key_file = {:?}
+            [pet]
+            pattern = [{{ high = true, duration = 0.05 }}, {{ high = false, duration = 0.05 }}]
+            "#,
+            key
+        ))?;
+        let transport = mio::net::UdpSocket::bind(config.address)?;
+        let address = transport.local_addr()?;
+        let mock = Mock::default();
+        let watchdog = Watchdog::with_parts(config, Box::new(transport), Box::new(mock.clone()))?;
+        std::fs::remove_file(&key)?;
+        let handle = watchdog.shutdown_handle();
+        let running = thread::spawn(move || watchdog.run());
+
+        let client = UdpSocket::bind("127.0.0.1:0")?;
+        client.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let reset = crate::auth::sign(b"0123456789abcdef", "RESET", 1);
+        client.send_to(reset.as_bytes(), address)?;
+        let mut buf = [0; 64];
+        let len = client.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"OK");
+        thread::sleep(Duration::from_millis(100));
+        let stopped = mock.values();
+        assert_eq!(stopped.last(), Some(&false));
+        // Pings don't get the petting going again before the supervisor resets the card
+        for _ in 0..5 {
+            client.send_to(b"PING c3", address)?;
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(mock.values(), stopped);
+        handle.shutdown()?;
+        assert_eq!(running.join().unwrap()?, Cause::Reset);
+        Ok(())
+    }
+
+    /// Runs the watchdog handling signals on a thread of its own, which they're sent to alone
+    /// through the returned id. A resumed start skips the self-test's sleeping through `config`'s
+    /// pattern.
+    fn run_signalled(
+        config: Config,
+        mock: &Mock,
+        start: fn() -> Start,
+    ) -> Result<(thread::JoinHandle<Result<Cause>>, libc::pthread_t)> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mock = mock.clone();
+        let running = thread::spawn(move || {
+            let transport = mio::net::UdpSocket::bind(config.address)?;
+            let pingee = Pingee::with_transport(&config, TIMEOUTS, PORTS, Box::new(transport))?;
+            let petter = Petter::with_backend(Box::new(mock))?;
+            // Blocks the signals on this thread only
+            let watchdog = Watchdog::with(config, pingee, petter, start(), true)?;
+            // SAFETY: pthread_self has no arguments and can't fail
+            let _ = tx.send(unsafe { libc::pthread_self() });
+            watchdog.run()
+        });
+        Ok((running, rx.recv()?))
+    }
+
+    fn pthread_kill(thread: libc::pthread_t, signal: Signal) -> Result<()> {
+        // SAFETY: the thread is still running, it's only joined after the signal
+        match unsafe { libc::pthread_kill(thread, signal as i32) } {
+            0 => Ok(()),
+            e => Err(std::io::Error::from_raw_os_error(e).into()),
+        }
+    }
+
+    #[test]
+    fn test_signals() -> Result<()> {
+        let long = r#"
+            address = "127.0.0.1:0"
+            [pet]
+            pattern = [{ high = true, duration = 10 }, { high = false, duration = 10 }]
+            "#;
+        // SIGQUIT leaves the line high, for the supervisor to bite once it's due
+        let mock = Mock::default();
+        let (running, thread) = run_signalled(Config::parse(long)?, &mock, || Start::Reexecuted)?;
+        thread::sleep(Duration::from_millis(100));
+        pthread_kill(thread, SIGQUIT)?;
+        assert_eq!(running.join().unwrap()?, Cause::Reset);
+        assert_eq!(mock.values(), [true]);
+
+        // As does the supervisor after SIGINT, as the line is forced low
+        let mock = Mock::default();
+        let (running, thread) = run_signalled(Config::parse(long)?, &mock, || Start::Reexecuted)?;
+        thread::sleep(Duration::from_millis(100));
+        pthread_kill(thread, SIGINT)?;
+        assert_eq!(running.join().unwrap()?, Cause::Signal);
+        assert_eq!(mock.values(), [true, false]);
+
+        // SIGTERM with a drain time keeps petting through it
+        let draining = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            drain = 0.5
+            [pet]
+            pattern = [{ high = true, duration = 0.05 }, { high = false, duration = 0.05 }]
+            "#,
+        )?;
+        let mock = Mock::default();
+        let (running, thread) = run_signalled(draining, &mock, || Start::Fresh)?;
+        thread::sleep(Duration::from_millis(200));
+        pthread_kill(thread, SIGTERM)?;
+        thread::sleep(Duration::from_millis(100));
+        let pets = mock.values().len();
+        thread::sleep(Duration::from_millis(200));
+        assert!(mock.values().len() >= pets + 2);
+        assert_eq!(running.join().unwrap()?, Cause::Signal);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "flight"))]
+    #[test]
+    fn test_dry_run() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            handover = "/run/oresat-c3-watchdog.sock"
+            counters = "/var/lib/oresat-c3-watchdog/counters"
+            [hook]
+            command = ["reboot"]
+            "#,
+        )?;
+        let watchdog = Watchdog::dry_run(config)?;
+        assert_eq!(watchdog.config.handover, None);
+        assert_eq!(watchdog.config.counters, None);
+        assert!(watchdog.config.hook.command.is_empty());
+        let handle = watchdog.shutdown_handle();
+        // The self-test reads back what it set
+        let running = thread::spawn(move || watchdog.run());
+        thread::sleep(Duration::from_millis(100));
+        handle.shutdown()?;
+        assert_eq!(running.join().unwrap()?, Cause::Signal);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_name() -> Result<()> {
+        let config = Config::parse("")?;
+        assert_eq!(
+            lock_name(&config, "/dev/gpiochip2"),
+            lock_name(&config, "gpiochip2")
+        );
+        let pwm = Config::parse(
+            r#"
+            [pet]
+            pattern = [{ high = true, duration = 0.1 }, { high = false, duration = 0.9 }]
+            pwm = { chip = "/sys/class/pwm/pwmchip0", channel = 1 }
+            "#,
+        )?;
+        assert_eq!(lock_name(&pwm, "gpiochip2"), "pwmchip0/pwm1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_reexec_refusal() -> Result<()> {
+        let key = std::env::temp_dir().join(format!("watchdog-reexec-{}.key", std::process::id()));
+        std::fs::write(&key, "0123456789abcdef\n")?;
+        let config = Config::parse(&format!(
+            "address = \"127.0.0.1:0\"\n[maintenance]\nmax = 600\n[auth]\nkey_file = {:?}\n",
+            key
+        ))?;
+        let poll = Poll::new()?;
+        let mut processes = ProcessMonitor::new(poll.registry(), Token(100), &[])?;
+        let transport = mio::net::UdpSocket::bind(config.address)?;
+        let address = transport.local_addr()?;
+        let mut pingee =
+            Pingee::with_transport(&config, Token(10), Token(90), Box::new(transport))?;
+        std::fs::remove_file(&key)?;
+        pingee.register(poll.registry(), Token(0))?;
+
+        assert_eq!(reexec_refusal(&config, &pingee, false, true), None);
+        assert!(reexec_refusal(&config, &pingee, true, true).is_some());
+        assert!(reexec_refusal(&config, &pingee, false, false).is_some());
+        let filtered = Config::parse("seccomp = true")?;
+        assert!(reexec_refusal(&filtered, &pingee, false, true).is_some());
+        let dropped = Config::parse("[privileges]\nuser = \"watchdog\"")?;
+        assert!(reexec_refusal(&dropped, &pingee, false, true).is_some());
+
+        // Neither a maintenance deadline nor the gates survive the exec
+        let client = UdpSocket::bind("127.0.0.1:0")?;
+        let maintenance = crate::auth::sign(b"0123456789abcdef", "MAINTENANCE 300", 1);
+        client.send_to(maintenance.as_bytes(), address)?;
+        thread::sleep(Duration::from_millis(20));
+        let action = pingee.on_ping(poll.registry(), &mut processes)?;
+        assert_eq!(action, Some(Action::Maintenance(Duration::from_secs(300))));
+        let refusal = reexec_refusal(&config, &pingee, false, true);
+        assert_eq!(refusal, Some("the maintenance deadline would be lost"));
+        let gated =
+            Config::parse("address = \"127.0.0.1:0\"\n[[gate]]\ntime_sync = true\ntimeout = 60")?;
+        let pingee = Pingee::new(&gated, Token(10), Token(90))?;
+        let refusal = reexec_refusal(&gated, &pingee, false, true);
+        assert_eq!(refusal, Some("the startup gates would be lost"));
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_refusal() -> Result<()> {
+        assert_eq!(async_refusal(&Config::parse("")?), None);
+        let refused = [
+            "seccomp = true",
+            "[realtime]\npriority = 50",
+            "[privileges]\nuser = \"w\"",
+        ];
+        for text in refused {
+            assert!(async_refusal(&Config::parse(text)?).is_some());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_signal() -> Result<()> {
+        // Raised signals are pending on this thread only
+        let mask = SigSet::from_iter([SIGUSR1, SIGHUP]);
+        mask.thread_block()?;
+        let mut sfd = Some(Signals::Fd(SignalFd::with_flags(
+            &mask,
+            SfdFlags::SFD_NONBLOCK,
+        )?));
+        let dumps = std::cell::Cell::new(0);
+        let dump = || dumps.set(dumps.get() + 1);
+
+        nix::sys::signal::raise(SIGUSR1)?;
+        assert_eq!(next_signal(&mut sfd, dump)?, None);
+        nix::sys::signal::raise(SIGHUP)?;
+        assert_eq!(next_signal(&mut sfd, dump)?, Some(SIGHUP));
+        assert_eq!(dumps.get(), 1);
+        mask.thread_unblock()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_run_async() -> Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let _runtime = runtime.enter();
+        let config = Config::parse("address = \"127.0.0.1:0\"")?;
+        let transport = mio::net::UdpSocket::bind(config.address)?;
+        let watchdog =
+            Watchdog::with_parts(config, Box::new(transport), Box::new(Mock::default()))?;
+        let handle = watchdog.shutdown_handle();
+        // Spawned, as it has to be Send for a multi-threaded runtime
+        let running = runtime.spawn(watchdog.run_async());
+        // Past the first pet timer, so that the loop has woken up on the runtime
+        runtime.block_on(time::sleep(Duration::from_millis(150)));
+        handle.shutdown()?;
+        assert_eq!(runtime.block_on(running)??, Cause::Signal);
+
+        // Signals taken by the watchdog come through tokio's streams
+        let config = Config::parse(
+            r#"
+            address = "127.0.0.1:0"
+            [pet]
+            pattern = [{ high = true, duration = 10 }, { high = false, duration = 10 }]
+            "#,
+        )?;
+        let mock = Mock::default();
+        let transport = mio::net::UdpSocket::bind(config.address)?;
+        let pingee = Pingee::with_transport(&config, TIMEOUTS, PORTS, Box::new(transport))?;
+        let petter = Petter::with_backend(Box::new(mock.clone()))?;
+        let watchdog = Watchdog::with(config, pingee, petter, Start::Reexecuted, true)?;
+        let running = runtime.spawn(watchdog.run_async());
+        runtime.block_on(time::sleep(Duration::from_millis(150)));
+        // To this thread, which the runtime runs on, and which run_async unblocked them on
+        nix::sys::signal::raise(SIGINT)?;
+        assert_eq!(runtime.block_on(running)??, Cause::Signal);
+        assert_eq!(mock.values(), [true, false]);
+        Ok(())
+    }
+}
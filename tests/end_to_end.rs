@@ -0,0 +1,149 @@
+#![cfg(target_os = "linux")]
+
+use anyhow::{Context, Result};
+use gpiosim::Level;
+use oresat_c3_watchdog::{
+    auth,
+    petter::{self, GPIO_LABEL, GPIO_LINE},
+    sim_supervisor,
+};
+use std::{
+    fs,
+    net::UdpSocket,
+    process::{Child, Command},
+    thread,
+    time::{Duration, Instant},
+};
+
+const KEY: &str = "0123456789abcdef";
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+struct Watchdog(Child);
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn request(socket: &UdpSocket, text: &str) -> Result<String> {
+    socket.send(text.as_bytes())?;
+    let mut buf = [0; 4096];
+    let len = socket.recv(&mut buf)?;
+    Ok(String::from_utf8(buf[..len].to_vec())?)
+}
+
+/// Runs the binary against a simulated chip: it has to pet at 1 Hz with 100 ms pulses for as long
+/// as it's pinged, and exit with the ping timeout status once the pings stop.
+#[test]
+fn test_end_to_end() -> Result<()> {
+    let sim = petter::simulate_gpio(GPIO_LINE, GPIO_LABEL);
+    let chip = &sim.chips()[0];
+    let high = || chip.get_level(GPIO_LINE).unwrap() == Level::High;
+
+    let dir = std::env::temp_dir().join(format!("watchdog-e2e-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let address = UdpSocket::bind("127.0.0.1:0")?.local_addr()?;
+    fs::write(dir.join("key"), KEY)?;
+    // Disarmed until armed below, which starts the client's window right away instead of after
+    // the two minute startup inhibit
+    let config = format!(
+        "address = \"{}\"\narmed = false\n[auth]\nkey_file = {:?}\n\
+         [[client]]\nname = \"e2e\"\ntimeout = {}\n",
+        address,
+        dir.join("key"),
+        TIMEOUT.as_secs()
+    );
+    fs::write(dir.join("config.toml"), config)?;
+    let mut watchdog = Watchdog(
+        Command::new(env!("CARGO_BIN_EXE_oresat-c3-watchdog"))
+            .arg("--config")
+            .arg(dir.join("config.toml"))
+            .args(["--gpio-chip", &chip.chip_name])
+            .spawn()?,
+    );
+
+    let socket = UdpSocket::bind("127.0.0.1:0")?;
+    socket.connect(address)?;
+    socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+    let started = Instant::now();
+    while request(&socket, "STATUS").is_err() {
+        assert!(started.elapsed() < Duration::from_secs(5), "Never answered");
+    }
+    let arm = auth::sign(KEY.as_bytes(), "ARM", 1);
+    assert_eq!(request(&socket, &arm)?, "OK");
+
+    // Sampled every 10 ms while pinging well within the timeout
+    let mut levels = Vec::new();
+    let pinging = Instant::now();
+    while pinging.elapsed() < Duration::from_secs(4) {
+        if levels.len() % 50 == 0 {
+            socket.send(b"PING e2e")?;
+        }
+        levels.push(high());
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(watchdog.0.try_wait()?.is_none(), "Exited while pinged");
+    let pulses: Vec<usize> = levels
+        .split(|high| !high)
+        .map(<[bool]>::len)
+        .filter(|&len| len > 0)
+        .collect();
+    assert!((3..=5).contains(&pulses.len()), "Pulses: {:?}", pulses);
+    // The first and last ones may be cut off by the sampling window
+    for &pulse in &pulses[1..pulses.len() - 1] {
+        assert!((5..=20).contains(&pulse), "Pulses: {:?}", pulses);
+    }
+
+    let stopped = Instant::now();
+    let status = loop {
+        if let Some(status) = watchdog.0.try_wait()? {
+            break status;
+        }
+        assert!(stopped.elapsed() < TIMEOUT * 2, "Still running");
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert!(stopped.elapsed() > TIMEOUT / 2);
+    assert_eq!(status.code().context("Killed by a signal")?, 2);
+    assert!(!high());
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+/// Runs the binary in sim mode with supervisor limits the default pattern is well within: the
+/// simulated supervisor mustn't bite for as long as it's pinged.
+#[test]
+fn test_simulated_supervisor() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("watchdog-e2e-sim-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let address = UdpSocket::bind("127.0.0.1:0")?.local_addr()?;
+    let config = format!(
+        "address = \"{}\"\n[[client]]\nname = \"e2e\"\ntimeout = {}\n\
+         [hardware]\nmin_feed = 0.8\nmax_feed = 1.2\nmin_pulse = 0.05\n",
+        address,
+        TIMEOUT.as_secs()
+    );
+    fs::write(dir.join("config.toml"), config)?;
+    let mut watchdog = Watchdog(
+        Command::new(env!("CARGO_BIN_EXE_oresat-c3-watchdog"))
+            .arg("sim")
+            .arg("--config")
+            .arg(dir.join("config.toml"))
+            .spawn()?,
+    );
+
+    let socket = UdpSocket::bind("127.0.0.1:0")?;
+    socket.connect(address)?;
+    let pinging = Instant::now();
+    while pinging.elapsed() < Duration::from_secs(5) {
+        socket.send(b"PING e2e")?;
+        if let Some(status) = watchdog.0.try_wait()? {
+            assert_ne!(status.code(), Some(sim_supervisor::EXIT_CODE), "Bitten");
+            panic!("Exited while pinged: {}", status);
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}